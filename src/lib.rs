@@ -16,8 +16,13 @@
 #![forbid(unsafe_code)]
 #![no_std]
 
+#[cfg(feature = "alloc")]
 extern crate alloc;
 
+#[cfg(feature = "std")]
+extern crate std;
+
+#[cfg_attr(not(feature = "alloc"), allow(unused_imports))]
 use {
     core::{
         convert::{Infallible, TryFrom, TryInto},
@@ -30,13 +35,43 @@ use {
     },
 };
 
+/// Emits a debug event through the `log` facade if the `log` feature is enabled.
+#[cfg(feature = "alloc")]
+macro_rules! log_debug {
+    ($($arg:tt)+) => {
+        #[cfg(feature = "log")]
+        log::debug!($($arg)+);
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = core::format_args!($($arg)+);
+        }
+    };
+}
+
+/// Emits a trace event through the `log` facade if the `log` feature is enabled.
+#[cfg(feature = "alloc")]
+macro_rules! log_trace {
+    ($($arg:tt)+) => {
+        #[cfg(feature = "log")]
+        log::trace!($($arg)+);
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = core::format_args!($($arg)+);
+        }
+    };
+}
+
 /// Package Version
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 /// Crate Prelude Module
 pub mod prelude {
+    #[cfg(feature = "alloc")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+    pub use crate::{rule::Rule, substitution::Substitution};
+
     pub use {
-        crate::{self as rd, rule::Rule, substitution::Substitution, Structure},
+        crate::{self as rd, Structure},
         exprz::{
             self, Expr, ExprRef, Expression, Group, GroupRef, GroupRefItem, GroupRefIter,
             GroupReference, Reference,
@@ -103,13 +138,281 @@ where
     }
 }
 
+/// Expression Utilities Module
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod expr {
+    use {
+        super::*,
+        crate::util::Fnv1a,
+        core::hash::{Hash, Hasher},
+    };
+
+    /// Returns `true` if the two expression references are structurally equal.
+    pub fn eq_ref<E>(lhs: &ExprRef<E>, rhs: &ExprRef<E>) -> bool
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        match (lhs, rhs) {
+            (ExprRef::Atom(lhs), ExprRef::Atom(rhs)) => lhs == rhs,
+            (ExprRef::Group(lhs), ExprRef::Group(rhs)) => {
+                let mut lhs_iter = lhs.iter();
+                let mut rhs_iter = rhs.iter();
+                loop {
+                    match (lhs_iter.next(), rhs_iter.next()) {
+                        (Some(lhs), Some(rhs)) => {
+                            if !eq_ref::<E>(&lhs.cases(), &rhs.cases()) {
+                                return false;
+                            }
+                        }
+                        (None, None) => return true,
+                        _ => return false,
+                    }
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Feeds the structure of an expression reference into the given hasher.
+    pub fn hash_ref<E, H>(expr: &ExprRef<E>, state: &mut H)
+    where
+        E: Expression,
+        E::Atom: Hash,
+        H: Hasher,
+    {
+        match expr {
+            ExprRef::Atom(atom) => {
+                state.write_u8(0);
+                atom.hash(state);
+            }
+            ExprRef::Group(group) => {
+                state.write_u8(1);
+                for item in group.iter() {
+                    hash_ref(&item.cases(), state);
+                }
+                state.write_u8(2);
+            }
+        }
+    }
+
+    /// Feeds the structure of an expression into the given hasher.
+    #[inline]
+    pub fn hash<E, H>(expr: &E, state: &mut H)
+    where
+        E: Expression,
+        E::Atom: Hash,
+        H: Hasher,
+    {
+        hash_ref(&expr.cases(), state)
+    }
+
+    /// Computes the digest of an expression reference with the [`Fnv1a`] hasher.
+    ///
+    /// The digest is deterministic, but atoms are fed to the hasher through their [`Hash`]
+    /// implementation, so it is only reproducible across builds and platforms as far as this
+    /// implementation is. Digests are meant for caching and consistency checks within a
+    /// program, use the [canonical encoding](crate::binary) to identify expressions across
+    /// builds.
+    #[inline]
+    pub fn digest_ref<E>(expr: &ExprRef<E>) -> u64
+    where
+        E: Expression,
+        E::Atom: Hash,
+    {
+        let mut state = Fnv1a::new();
+        hash_ref(expr, &mut state);
+        state.finish()
+    }
+
+    /// Computes the digest of an expression, see [`digest_ref`].
+    #[inline]
+    pub fn digest<E>(expr: &E) -> u64
+    where
+        E: Expression,
+        E::Atom: Hash,
+    {
+        digest_ref(&expr.cases())
+    }
+
+    /// Computes the digest of a group read as a multiset, so that the order of the elements of
+    /// the group does not affect the result, see [`digest_ref`].
+    #[inline]
+    pub fn multiset_digest<E>(group: &GroupRef<E>) -> u64
+    where
+        E: Expression,
+        E::Atom: Hash,
+    {
+        util::multiset_digest(group.iter().map(move |item| digest_ref(&item.cases())))
+    }
+
+    /// Computes the digest of a slice of expressions read as a multiset, see [`digest_ref`].
+    #[inline]
+    pub fn multiset_digest_of<E>(exprs: &[E]) -> u64
+    where
+        E: Expression,
+        E::Atom: Hash,
+    {
+        util::multiset_digest(exprs.iter().map(digest::<E>))
+    }
+
+    /// Diagnoses whether `expr` has the [`Rule`](rule::Rule) shape, returning the same error
+    /// as the conversion into a [rule structure](rule::Structure) would, without converting.
+    #[inline]
+    pub fn ratio_shape<E>(expr: &E) -> Result<(), rule::ShapeError>
+    where
+        E: Expression,
+    {
+        rule::matches::<E>(&expr.cases())
+    }
+
+    /// Returns `true` if `expr` has the [`Rule`](rule::Rule) shape.
+    #[inline]
+    pub fn has_ratio_shape<E>(expr: &E) -> bool
+    where
+        E: Expression,
+    {
+        ratio_shape(expr).is_ok()
+    }
+
+    /// Converts an expression reference into a [rule structure](rule::Structure), cloning the
+    /// `top` and `bot` groups only if the expression has the [`Rule`](rule::Rule) shape.
+    pub fn ratio_from_ref<E>(expr: &ExprRef<E>) -> Result<rule::Structure<E>, rule::ShapeError>
+    where
+        E: Expression,
+        E::Atom: Clone,
+        E::Group: FromIterator<E>,
+    {
+        rule::matches::<E>(expr)?;
+        match expr {
+            ExprRef::Group(group) => {
+                let mut iter = group.iter();
+                match (
+                    iter.next().and_then(exprz::Reference::group),
+                    iter.next().and_then(exprz::Reference::group),
+                ) {
+                    (Some(top), Some(bot)) => {
+                        Ok(rule::Structure::new(top.to_owned(), bot.to_owned()))
+                    }
+                    _ => Err(rule::ShapeError::BadGroupShape(group.iter().count())),
+                }
+            }
+            _ => Err(rule::ShapeError::NotGroup),
+        }
+    }
+
+    /// Simple Expression Type
+    ///
+    /// A minimal implementation of the `exprz` [`Expression`] trait whose groups are vectors,
+    /// for use without defining a custom expression type.
+    #[cfg(feature = "simple")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "simple")))]
+    #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+    pub enum SimpleExpr<A = alloc::string::String> {
+        /// Atomic expression
+        Atom(A),
+
+        /// Grouped expression
+        Group(alloc::vec::Vec<Self>),
+    }
+
+    #[cfg(feature = "simple")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "simple")))]
+    impl<A> SimpleExpr<A> {
+        /// Builds an atomic expression.
+        #[inline]
+        pub fn atom<T>(atom: T) -> Self
+        where
+            T: Into<A>,
+        {
+            Self::Atom(atom.into())
+        }
+
+        /// Builds a grouped expression from an iterator of expressions.
+        #[inline]
+        pub fn group<I>(iter: I) -> Self
+        where
+            I: IntoIterator<Item = Self>,
+        {
+            Self::Group(iter.into_iter().collect())
+        }
+    }
+
+    #[cfg(feature = "simple")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "simple")))]
+    impl<A> Expression for SimpleExpr<A> {
+        type Atom = A;
+
+        type Group = alloc::vec::Vec<Self>;
+
+        #[inline]
+        fn cases(&self) -> ExprRef<Self> {
+            match self {
+                Self::Atom(atom) => ExprRef::Atom(atom),
+                Self::Group(group) => ExprRef::Group(group.reference()),
+            }
+        }
+
+        #[inline]
+        fn from_atom(atom: Self::Atom) -> Self {
+            Self::Atom(atom)
+        }
+
+        #[inline]
+        fn from_group(group: Self::Group) -> Self {
+            Self::Group(group)
+        }
+    }
+
+    #[cfg(feature = "simple")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "simple")))]
+    impl<A> From<SimpleExpr<A>> for Expr<SimpleExpr<A>> {
+        #[inline]
+        fn from(expr: SimpleExpr<A>) -> Self {
+            match expr {
+                SimpleExpr::Atom(atom) => Self::Atom(atom),
+                SimpleExpr::Group(group) => Self::Group(group),
+            }
+        }
+    }
+
+    #[cfg(feature = "simple")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "simple")))]
+    impl<A> core::fmt::Display for SimpleExpr<A>
+    where
+        A: core::fmt::Display,
+    {
+        #[inline]
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            match self {
+                Self::Atom(atom) => atom.fmt(f),
+                Self::Group(group) => {
+                    f.write_str("(")?;
+                    for (i, expr) in group.iter().enumerate() {
+                        if i > 0 {
+                            f.write_str(" ")?;
+                        }
+                        expr.fmt(f)?;
+                    }
+                    f.write_str(")")
+                }
+            }
+        }
+    }
+}
+
 /// Atom-Expr Pairs
 ///
 /// A base structure for [`Substitutions`](Substitution) and `Composition`s.
 #[cfg(feature = "aep")]
 #[cfg_attr(docsrs, doc(cfg(feature = "aep")))]
 pub mod aep {
-    use {super::*, alloc::vec::Vec, core::mem};
+    use {
+        super::*,
+        alloc::vec::Vec,
+        core::{fmt, mem},
+    };
 
     /// AEP Pair
     pub type Pair<E> = (<E as Expression>::Atom, E);
@@ -495,6 +798,21 @@ pub mod aep {
         BadGroupShape(usize),
     }
 
+    impl fmt::Display for ShapeError {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::NotGroup => f.write_str("expression is not a group"),
+                Self::NotGroupedInPairs => f.write_str("expression is not grouped in pairs"),
+                Self::BadGroupShape(i) => write!(f, "pair {} does not start with an atom", i),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for ShapeError {}
+
     /// Checks if an atom matches the `AEP` pattern.
     #[inline]
     pub fn matches_atom<E>(atom: &E::Atom) -> Result<(), ShapeError>
@@ -556,10 +874,21 @@ pub mod aep {
 }
 
 /// Rule Module
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
 pub mod rule {
-    use super::*;
+    use {
+        super::*,
+        alloc::{boxed::Box, collections::VecDeque, string::String, vec, vec::Vec},
+        core::{
+            fmt,
+            hash::{Hash, Hasher},
+            slice,
+        },
+    };
 
-    /// Composes two rules using the ratio monoid multiplication algorithm.
+    /// Composes two rules using the ratio monoid multiplication algorithm, see
+    /// [`fixed::pair_compose_by`].
     pub fn pair_compose_by<E, T, B, Output, F>(top: T, bot: B, eq: F) -> Output
     where
         E: Expression,
@@ -571,15 +900,19 @@ pub mod rule {
     {
         let top = top.structure();
         let bot = bot.structure();
-        let (lower, upper) = util::multiset_symmetric_difference_by::<_, _, _, E::Group>(
-            top.bot,
-            bot.top.into_iter().collect(),
-            eq,
-        );
-        Output::from(Structure::new(
-            upper.chain(top.top).collect(),
-            lower.into_iter().chain(bot.bot).collect(),
-        ))
+        let parts = move |structure: Structure<E>| -> (Vec<E>, Vec<E>) {
+            (
+                structure.top.into_iter().collect(),
+                structure.bot.into_iter().collect(),
+            )
+        };
+        match fixed::pair_compose_by(parts(top), parts(bot), eq) {
+            Ok((top, bot)) => Output::from(Structure::new(
+                top.into_iter().collect(),
+                bot.into_iter().collect(),
+            )),
+            _ => unreachable!("vectors have no capacity bound"),
+        }
     }
 
     /// Composes two rules using the ratio monoid multiplication algorithm.
@@ -606,10 +939,14 @@ pub mod rule {
         I: IntoIterator<Item = R>,
         F: FnMut(&E, &E) -> bool,
     {
-        rules
+        let mut count = 0;
+        let rule = rules
             .into_iter()
-            .reduce(move |t, b| pair_compose_by(t, b, &mut eq))
-            .unwrap_or_else(R::empty)
+            .inspect(|_| count += 1)
+            .reduce(|t, b| pair_compose_by(t, b, &mut eq))
+            .unwrap_or_else(R::empty);
+        log_trace!("composed {} rules", count);
+        rule
     }
 
     /// Fold an iterator of rules using [`pair_compose`].
@@ -722,6 +1059,75 @@ pub mod rule {
             && ExprRef::<E>::eq_groups::<E>(lhs_bot, rhs_bot)
     }
 
+    /// Returns `true` if the two groups are equal as multisets.
+    #[inline]
+    pub fn multiset_eq<E>(lhs: &GroupRef<E>, rhs: &GroupRef<E>) -> bool
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        util::multiset_eq_by(
+            lhs.iter().map(move |e| e.cases()),
+            rhs.iter().map(move |e| e.cases()).collect(),
+            move |l, r| expr::eq_ref::<E>(l, r),
+        )
+    }
+
+    /// Returns `true` if `general` subsumes `specific`, that is, if there is a substitution of
+    /// the variables of `general` which makes it equal to `specific` up to a permutation of the
+    /// tops and bottoms. The variables of `specific` are treated as constants.
+    ///
+    /// The bottoms are matched under every match of the tops as it is found, stopping at the
+    /// first one which extends to the bottoms.
+    pub fn subsumes<E, G, S, F>(general: &G, specific: &S, can_substitute: F) -> bool
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        G: Rule<E>,
+        S: Rule<E>,
+        F: FnMut(&E::Atom) -> bool,
+    {
+        let (general_top, general_bot) = general.ref_pair();
+        let (specific_top, specific_bot) = specific.ref_pair();
+        let general_top = general_top
+            .iter()
+            .map(move |e| e.cases())
+            .collect::<Vec<_>>();
+        let general_bot = general_bot
+            .iter()
+            .map(move |e| e.cases())
+            .collect::<Vec<_>>();
+        let specific_top = specific_top
+            .iter()
+            .map(move |e| e.cases())
+            .collect::<Vec<_>>();
+        let specific_bot = specific_bot
+            .iter()
+            .map(move |e| e.cases())
+            .collect::<Vec<_>>();
+        if general_top.len() != specific_top.len() || general_bot.len() != specific_bot.len() {
+            return false;
+        }
+        let can_substitute = core::cell::RefCell::new(can_substitute);
+        let can_substitute = |atom: &E::Atom| (can_substitute.borrow_mut())(atom);
+        substitution::match_multiset(
+            &general_top,
+            &specific_top,
+            &mut Vec::new(),
+            can_substitute,
+            |bindings, _| {
+                substitution::match_multiset(
+                    &general_bot,
+                    &specific_bot,
+                    &mut bindings.to_vec(),
+                    can_substitute,
+                    move |_, _| true,
+                )
+            },
+        )
+    }
+
     /// Rule Trait
     // TODO: `eq_by_symmetric_cancellation`
     // TODO: `has_cancellation`
@@ -826,90 +1232,231 @@ pub mod rule {
                 substitution.apply_group_ref(&self.bot()),
             )
         }
-    }
-
-    /// [`Rule`] Reference Structure Type
-    pub struct Reference<'e, E>
-    where
-        E: 'e + Expression,
-    {
-        /// Top element of the rule
-        pub top: GroupRef<'e, E>,
-
-        /// Bottom element of the rule
-        pub bot: GroupRef<'e, E>,
-    }
 
-    impl<'e, E> Reference<'e, E>
-    where
-        E: Expression,
-    {
-        /// Builds a new [`Reference`] from references to the top and bottom of a rule.
+        /// Returns the premises of the rule, that is, the expressions which must be derivable
+        /// or present in the state before the rule can be applied.
+        ///
+        /// Premises are checked but not consumed when the rule is applied.
         #[inline]
-        pub fn new(top: GroupRef<'e, E>, bot: GroupRef<'e, E>) -> Self {
-            Self { top, bot }
+        fn premises(&self) -> &[E] {
+            &[]
         }
 
-        /// Returns the top and bottom element of the rule as a pair.
+        /// Returns `true` if every premise of the rule satisfies `holds`.
         #[inline]
-        pub fn ref_pair(self) -> RefPair<'e, E> {
-            (self.top, self.bot)
+        fn premises_hold<F>(&self, holds: F) -> bool
+        where
+            F: FnMut(&E) -> bool,
+        {
+            self.premises().iter().all(holds)
         }
 
-        /// Returns the top and bottom element of the rule as a pair.
+        /// Returns the absence patterns of the rule, that is, the patterns which must not match
+        /// any element of the state before the rule can be applied.
+        ///
+        /// The variables bound by the match of the top are instantiated before checking the
+        /// patterns and the remaining variables match any expression.
         #[inline]
-        pub fn ref_pair_by_ref(&self) -> (&GroupRef<'e, E>, &GroupRef<'e, E>) {
-            (&self.top, &self.bot)
+        fn absences(&self) -> &[E] {
+            &[]
         }
-    }
 
-    impl<'e, E> Clone for Reference<'e, E>
-    where
-        E: Expression,
-        GroupRef<'e, E>: Clone,
-    {
+        /// Returns `true` if the side conditions of the rule allow it to be applied with the
+        /// match `bindings`.
         #[inline]
-        fn clone(&self) -> Self {
-            Self::new(self.top.clone(), self.bot.clone())
+        fn allows(&self, bindings: &[substitution::Term<E>]) -> bool {
+            let _ = bindings;
+            true
         }
-    }
-
-    impl<'e, E> Copy for Reference<'e, E>
-    where
-        E: Expression,
-        GroupRef<'e, E>: Copy,
-    {
-    }
 
-    impl<'e, E> Eq for Reference<'e, E>
-    where
-        E: Expression,
-        E::Atom: PartialEq,
-    {
-    }
+        /// Returns `true` if a [`Guard`] may veto the applications of the rule, in which case
+        /// [`allows`](Self::allows) is not determined by the rule alone.
+        #[inline]
+        fn is_guarded(&self) -> bool {
+            false
+        }
 
-    impl<'e, E> PartialEq for Reference<'e, E>
-    where
-        E: Expression,
-        E::Atom: PartialEq,
-    {
+        /// Returns the weight of the rule if it has one.
+        ///
+        /// Depending on the [`Scoring`] this is either a cost or a probability.
         #[inline]
-        fn eq(&self, other: &Self) -> bool {
-            eq::<E>(&self.top, &self.bot, &other.top, &other.bot)
+        fn weight(&self) -> Option<f64> {
+            None
         }
-    }
 
-    impl<'e, E> From<&'e Structure<E>> for Reference<'e, E>
-    where
-        E: Expression,
-    {
+        /// Returns `true` if the rule has [premises](Self::premises),
+        /// [absence patterns](Self::absences) or a [guard](Self::is_guarded), which restrict its
+        /// applications beyond the match of its top.
         #[inline]
-        fn from(structure: &'e Structure<E>) -> Self {
-            Self::new(structure.top.as_ref(), structure.bot.as_ref())
+        fn has_side_conditions(&self) -> bool {
+            !self.premises().is_empty() || !self.absences().is_empty() || self.is_guarded()
         }
-    }
 
-    /// Based [`Rule`] Reference Structure Type
+        /// Instantiates the rule schema with `substitution`, keeping track of the bindings
+        /// which produced the instance.
+        #[inline]
+        fn instantiate<S>(&self, substitution: &S) -> Instance<E, Self>
+        where
+            Self: Sized,
+            E::Atom: Clone + PartialEq,
+            S: Substitution<E>,
+        {
+            Instance {
+                rule: self.substitute_ref(substitution),
+                bindings: substitution.iter().map(move |t| t.to_owned()).collect(),
+            }
+        }
+
+        /// Returns `true` if the two rules are equal up to a permutation of their tops and
+        /// bottoms.
+        #[inline]
+        fn multiset_eq<R>(&self, other: &R) -> bool
+        where
+            E::Atom: PartialEq,
+            R: Rule<E>,
+        {
+            let (lhs_top, lhs_bot) = self.ref_pair();
+            let (rhs_top, rhs_bot) = other.ref_pair();
+            multiset_eq::<E>(&lhs_top, &rhs_top) && multiset_eq::<E>(&lhs_bot, &rhs_bot)
+        }
+
+        /// Returns `true` if `self` subsumes `other`.
+        ///
+        /// See [`subsumes`](self::subsumes) for more details.
+        #[inline]
+        fn subsumes<R, F>(&self, other: &R, can_substitute: F) -> bool
+        where
+            E::Atom: Clone + PartialEq,
+            R: Rule<E>,
+            F: FnMut(&E::Atom) -> bool,
+        {
+            subsumes(self, other, can_substitute)
+        }
+
+        /// Reduces the rule by cancelling the elements which appear in both its top and its
+        /// bottom using `eq` to compare elements.
+        #[inline]
+        fn reduce_by<F>(self, eq: F) -> Self
+        where
+            Self: Sized,
+            F: FnMut(&E, &E) -> bool,
+        {
+            let (top, bot) = self.pair();
+            let (top, bot) = util::multiset_symmetric_difference_by::<_, _, _, E::Group>(
+                top,
+                bot.into_iter().collect(),
+                eq,
+            );
+            Self::new(top, bot.collect())
+        }
+
+        /// Reduces the rule by cancelling the elements which appear in both its top and its
+        /// bottom.
+        #[inline]
+        fn reduce(self) -> Self
+        where
+            Self: Sized,
+            E::Atom: PartialEq,
+        {
+            self.reduce_by(E::eq)
+        }
+
+        /// Computes a digest of the rule which does not depend on the order of the elements of
+        /// its top and bottom, see [`expr::digest_ref`] for its reproducibility.
+        #[inline]
+        fn content_hash(&self) -> u64
+        where
+            E::Atom: Hash,
+        {
+            util::sequence_digest(util::two_item_iter(
+                expr::multiset_digest::<E>(&self.top()),
+                expr::multiset_digest::<E>(&self.bot()),
+            ))
+        }
+    }
+
+    /// [`Rule`] Reference Structure Type
+    pub struct Reference<'e, E>
+    where
+        E: 'e + Expression,
+    {
+        /// Top element of the rule
+        pub top: GroupRef<'e, E>,
+
+        /// Bottom element of the rule
+        pub bot: GroupRef<'e, E>,
+    }
+
+    impl<'e, E> Reference<'e, E>
+    where
+        E: Expression,
+    {
+        /// Builds a new [`Reference`] from references to the top and bottom of a rule.
+        #[inline]
+        pub fn new(top: GroupRef<'e, E>, bot: GroupRef<'e, E>) -> Self {
+            Self { top, bot }
+        }
+
+        /// Returns the top and bottom element of the rule as a pair.
+        #[inline]
+        pub fn ref_pair(self) -> RefPair<'e, E> {
+            (self.top, self.bot)
+        }
+
+        /// Returns the top and bottom element of the rule as a pair.
+        #[inline]
+        pub fn ref_pair_by_ref(&self) -> (&GroupRef<'e, E>, &GroupRef<'e, E>) {
+            (&self.top, &self.bot)
+        }
+    }
+
+    impl<'e, E> Clone for Reference<'e, E>
+    where
+        E: Expression,
+        GroupRef<'e, E>: Clone,
+    {
+        #[inline]
+        fn clone(&self) -> Self {
+            Self::new(self.top.clone(), self.bot.clone())
+        }
+    }
+
+    impl<'e, E> Copy for Reference<'e, E>
+    where
+        E: Expression,
+        GroupRef<'e, E>: Copy,
+    {
+    }
+
+    impl<'e, E> Eq for Reference<'e, E>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+    }
+
+    impl<'e, E> PartialEq for Reference<'e, E>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        #[inline]
+        fn eq(&self, other: &Self) -> bool {
+            eq::<E>(&self.top, &self.bot, &other.top, &other.bot)
+        }
+    }
+
+    impl<'e, E> From<&'e Structure<E>> for Reference<'e, E>
+    where
+        E: Expression,
+    {
+        #[inline]
+        fn from(structure: &'e Structure<E>) -> Self {
+            Self::new(structure.top.as_ref(), structure.bot.as_ref())
+        }
+    }
+
+    /// Based [`Rule`] Reference Structure Type
     pub struct BasedReference<'e, E>
     where
         E: 'e + Expression,
@@ -1202,23 +1749,138 @@ pub mod rule {
         }
     }
 
+    /// Expression Kind
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum ExprKind {
+        /// Atomic expression
+        Atom,
+
+        /// Group expression
+        Group,
+    }
+
+    impl fmt::Display for ExprKind {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(match self {
+                Self::Atom => "atom",
+                Self::Group => "group",
+            })
+        }
+    }
+
+    impl ExprKind {
+        /// Returns the kind of the expression reference.
+        #[inline]
+        pub fn of<E>(expr: &ExprRef<E>) -> Self
+        where
+            E: Expression,
+        {
+            match expr {
+                ExprRef::Atom(_) => Self::Atom,
+                ExprRef::Group(_) => Self::Group,
+            }
+        }
+    }
+
+    /// Offending Sub-Expression
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct Offending {
+        /// Position of the sub-expression in the enclosing group
+        pub position: usize,
+
+        /// Kind of the sub-expression
+        pub kind: ExprKind,
+    }
+
+    impl Offending {
+        /// Builds a new [`Offending`] atom at the given `position`.
+        #[inline]
+        pub fn atom(position: usize) -> Self {
+            Self {
+                position,
+                kind: ExprKind::Atom,
+            }
+        }
+    }
+
     /// [`Rule`] Shape Error Type
     #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
     pub enum ShapeError {
         /// The expression is not a group.
         NotGroup,
 
-        /// The expression has the wrong group shape.
-        BadGroupShape,
+        /// The expression has the wrong group shape, with the given number of elements.
+        BadGroupShape(usize),
 
         /// The `top` element of the group is not a group.
-        MissingTopGroup,
+        MissingTopGroup(Offending),
 
         /// The `bot` element of the group is not a group.
-        MissingBotGroup,
+        MissingBotGroup(Offending),
 
         /// The `top` and `bot` element of the group are not groups.
-        MissingTopBotGroups,
+        MissingTopBotGroups(Offending, Offending),
+
+        /// The group does not start with the tag of a [tagged](RatioEncoding::Tagged) encoding.
+        MissingTag,
+
+        /// The group does not contain the divider of a [flat](RatioEncoding::Flat) encoding.
+        MissingDivider,
+    }
+
+    impl fmt::Display for ShapeError {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::NotGroup => f.write_str("expression is not a group"),
+                Self::BadGroupShape(count) => {
+                    write!(f, "expected a group of two elements, found {}", count)
+                }
+                Self::MissingTopGroup(top) => write!(
+                    f,
+                    "top element at position {} is an {}, not a group",
+                    top.position, top.kind
+                ),
+                Self::MissingBotGroup(bot) => write!(
+                    f,
+                    "bottom element at position {} is an {}, not a group",
+                    bot.position, bot.kind
+                ),
+                Self::MissingTopBotGroups(top, bot) => write!(
+                    f,
+                    "top and bottom elements at positions {} and {} are not groups",
+                    top.position, bot.position
+                ),
+                Self::MissingTag => f.write_str("group does not start with the encoding tag"),
+                Self::MissingDivider => f.write_str("group does not contain the encoding divider"),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for ShapeError {}
+
+    impl ShapeError {
+        /// Shifts the positions and element counts of the error by `offset`, for groups read
+        /// after `offset` leading elements.
+        #[inline]
+        pub fn shifted(self, offset: usize) -> Self {
+            let shift = move |o: Offending| Offending {
+                position: o.position + offset,
+                ..o
+            };
+            match self {
+                Self::BadGroupShape(count) => Self::BadGroupShape(count + offset),
+                Self::MissingTopGroup(top) => Self::MissingTopGroup(shift(top)),
+                Self::MissingBotGroup(bot) => Self::MissingBotGroup(shift(bot)),
+                Self::MissingTopBotGroups(top, bot) => {
+                    Self::MissingTopBotGroups(shift(top), shift(bot))
+                }
+                error => error,
+            }
+        }
     }
 
     /// Checks if an atom matches the [`Rule`] pattern.
@@ -1245,11 +1907,14 @@ pub mod rule {
         ) {
             (Some(top), Some(bot), None) => match (top, bot) {
                 (true, true) => Ok(()),
-                (true, false) => Err(ShapeError::MissingBotGroup),
-                (false, true) => Err(ShapeError::MissingTopGroup),
-                _ => Err(ShapeError::MissingTopBotGroups),
+                (true, false) => Err(ShapeError::MissingBotGroup(Offending::atom(1))),
+                (false, true) => Err(ShapeError::MissingTopGroup(Offending::atom(0))),
+                _ => Err(ShapeError::MissingTopBotGroups(
+                    Offending::atom(0),
+                    Offending::atom(1),
+                )),
             },
-            _ => Err(ShapeError::BadGroupShape),
+            _ => Err(ShapeError::BadGroupShape(group.iter().count())),
         }
     }
 
@@ -1296,11 +1961,17 @@ pub mod rule {
                     match (iter.next(), iter.next(), iter.next()) {
                         (Some(top), Some(bot), None) => match (top.group(), bot.group()) {
                             (Some(top), Some(bot)) => Ok(Self::new(top, bot)),
-                            (_, Some(_)) => Err(Self::Error::MissingTopGroup),
-                            (Some(_), _) => Err(Self::Error::MissingBotGroup),
-                            _ => Err(Self::Error::MissingTopBotGroups),
+                            (_, Some(_)) => Err(Self::Error::MissingTopGroup(Offending::atom(0))),
+                            (Some(_), _) => Err(Self::Error::MissingBotGroup(Offending::atom(1))),
+                            _ => Err(Self::Error::MissingTopBotGroups(
+                                Offending::atom(0),
+                                Offending::atom(1),
+                            )),
                         },
-                        _ => Err(Self::Error::BadGroupShape),
+                        (Some(_), Some(_), Some(_)) => {
+                            Err(Self::Error::BadGroupShape(3 + iter.count()))
+                        }
+                        (top, _, _) => Err(Self::Error::BadGroupShape(top.map_or(0, move |_| 1))),
                     }
                 }
                 _ => Err(Self::Error::NotGroup),
@@ -1308,2197 +1979,24006 @@ pub mod rule {
         }
     }
 
-    impl<E> Shape<E> for Structure<E>
+    /// [`Rule`] Conversion Mode
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum Conversion {
+        /// Both sides of the rule must be groups.
+        Strict,
+
+        /// Atom sides of the rule are promoted to singleton groups.
+        Lenient,
+    }
+
+    impl Default for Conversion {
+        #[inline]
+        fn default() -> Self {
+            Self::Strict
+        }
+    }
+
+    impl<E> Structure<E>
     where
         E: Expression,
-        E::Group: Container<E>,
+        E::Group: FromIterator<E> + IntoIterator<Item = E>,
     {
+        /// Converts an expression into a [`Structure`] following the `conversion` mode.
+        pub fn try_from_expr_with(
+            expr: Expr<E>,
+            conversion: Conversion,
+        ) -> Result<Self, ShapeError> {
+            if conversion == Conversion::Strict {
+                return Self::try_from(expr);
+            }
+            match expr.group() {
+                Some(group) => {
+                    let mut iter = group.into_iter();
+                    match (iter.next(), iter.next(), iter.next()) {
+                        (Some(top), Some(bot), None) => Ok(Self::new(promote(top), promote(bot))),
+                        (Some(_), Some(_), Some(_)) => {
+                            Err(ShapeError::BadGroupShape(3 + iter.count()))
+                        }
+                        (top, _, _) => Err(ShapeError::BadGroupShape(top.map_or(0, move |_| 1))),
+                    }
+                }
+                _ => Err(ShapeError::NotGroup),
+            }
+        }
+
+        /// Converts an expression into a [`Structure`], promoting atom sides to singleton
+        /// groups, so that `(a (b c))` is read as `((a) (b c))`.
+        #[inline]
+        pub fn try_from_expr_lenient(expr: Expr<E>) -> Result<Self, ShapeError> {
+            Self::try_from_expr_with(expr, Conversion::Lenient)
+        }
     }
 
-    impl<E> Rule<E> for Structure<E>
+    /// Returns the group of `expr`, or the singleton group of `expr` if it is an atom.
+    #[inline]
+    fn promote<E>(expr: E) -> E::Group
     where
         E: Expression,
-        E::Group: Container<E>,
+        E::Group: FromIterator<E>,
     {
-        #[inline]
-        fn cases(&self) -> Reference<E> {
-            self.into()
+        match Expression::into(expr) {
+            Expr::Atom(atom) => Some(E::from_atom(atom)).into_iter().collect(),
+            Expr::Group(group) => group,
         }
     }
 
-    /// [`Rule`] Reference Pair Type
-    pub type RefPair<'e, E> = (GroupRef<'e, E>, GroupRef<'e, E>);
+    /// Rule Encoding
+    ///
+    /// Ways of writing a [`Structure`] as an expression, for interoperability with other tools.
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum RatioEncoding<A> {
+        /// Pair of groups `((t ...) (b ...))`, the canonical encoding
+        Pair,
 
-    /// [`Rule`] Pair Type
-    pub type Pair<E> = (<E as Expression>::Group, <E as Expression>::Group);
-}
+        /// Pair of groups tagged by a marker atom `(marker (t ...) (b ...))`
+        Tagged(A),
 
-/// Substitution Module
-pub mod substitution {
-    use {
-        super::*,
-        alloc::vec::Vec,
-        core::{iter, mem, slice},
-    };
+        /// Flat group split by a divider atom `(t ... divider b ...)`
+        ///
+        /// The divider must not occur in the top group.
+        Flat(A),
+    }
 
-    /// Returns the corresponding expression from the substitution iterator.
-    #[inline]
-    pub fn get_expression<'s, E, I>(iter: I, atom: &E::Atom) -> Option<E>
-    where
-        E: 's + Expression,
-        E::Atom: PartialEq,
-        I: IntoIterator<Item = (&'s E::Atom, E)>,
-    {
-        iter.into_iter()
-            .find(move |(a, _)| *a == atom)
-            .map(move |(_, t)| t)
+    impl<A> Default for RatioEncoding<A> {
+        #[inline]
+        fn default() -> Self {
+            Self::Pair
+        }
     }
 
-    #[inline]
-    fn from_iter_on_atoms_inner<'s, E, I>(iter: &mut I, atom: E::Atom) -> E
+    /// Encodes `structure` as an expression following `encoding`.
+    pub fn encode<E>(structure: Structure<E>, encoding: &RatioEncoding<E::Atom>) -> Expr<E>
     where
-        E: 's + Expression,
-        E::Atom: PartialEq,
-        I: Iterator<Item = (&'s E::Atom, E)>,
+        E: Expression,
+        E::Atom: Clone,
+        E::Group: Container<E>,
     {
-        get_expression(iter, &atom).unwrap_or_else(move || E::from_atom(atom))
+        match encoding {
+            RatioEncoding::Pair => structure.into(),
+            RatioEncoding::Tagged(tag) => Expr::Group(
+                Some(E::from_atom(tag.clone()))
+                    .into_iter()
+                    .chain(util::two_item_iter(structure.top, structure.bot).map(E::from_group))
+                    .collect(),
+            ),
+            RatioEncoding::Flat(divider) => Expr::Group(
+                structure
+                    .top
+                    .into_iter()
+                    .chain(Some(E::from_atom(divider.clone())))
+                    .chain(structure.bot)
+                    .collect(),
+            ),
+        }
     }
 
-    #[inline]
-    fn from_iter_on_atoms_ref_inner<'s, E, I>(iter: &mut I, atom: &E::Atom) -> E
+    /// Decodes an expression written following `encoding` into a [`Structure`].
+    pub fn decode<E>(
+        expr: Expr<E>,
+        encoding: &RatioEncoding<E::Atom>,
+    ) -> Result<Structure<E>, ShapeError>
     where
-        E: 's + Expression,
-        E::Atom: Clone + PartialEq,
-        I: Iterator<Item = (&'s E::Atom, E)>,
-    {
-        get_expression(iter, atom).unwrap_or_else(move || E::from_atom(atom.clone()))
-    }
-
-    /// Performs a substitution using data from an iterator to an atomic expression.
-    #[inline]
-    pub fn from_iter_on_atoms<'s, E, I>(iter: I, atom: E::Atom) -> E
-    where
-        E: 's + Expression,
+        E: Expression,
         E::Atom: PartialEq,
-        I: IntoIterator<Item = (&'s E::Atom, E)>,
+        E::Group: Container<E>,
     {
-        from_iter_on_atoms_inner(&mut iter.into_iter(), atom)
+        let is_atom = move |e: &E, atom: &E::Atom| match e.cases() {
+            ExprRef::Atom(a) => a == atom,
+            _ => false,
+        };
+        match encoding {
+            RatioEncoding::Pair => Structure::try_from(expr),
+            RatioEncoding::Tagged(tag) => {
+                let mut iter = expr.group().ok_or(ShapeError::NotGroup)?.into_iter();
+                match iter.next() {
+                    Some(first) if is_atom(&first, tag) => {
+                        Structure::try_from(Expr::Group(iter.collect()))
+                            .map_err(move |error| error.shifted(1))
+                    }
+                    _ => Err(ShapeError::MissingTag),
+                }
+            }
+            RatioEncoding::Flat(divider) => {
+                let mut iter = expr.group().ok_or(ShapeError::NotGroup)?.into_iter();
+                let mut top = Vec::new();
+                loop {
+                    match iter.next() {
+                        Some(e) if is_atom(&e, divider) => break,
+                        Some(e) => top.push(e),
+                        _ => return Err(ShapeError::MissingDivider),
+                    }
+                }
+                Ok(Structure::new(top.into_iter().collect(), iter.collect()))
+            }
+        }
     }
 
-    /// Performs a substitution by reference using data from an iterator to an atomic expression.
-    #[inline]
-    pub fn from_iter_on_atoms_ref<'s, E, I>(iter: I, atom: &E::Atom) -> E
+    impl<'e, E> TryFrom<&'e Expr<E>> for Structure<E>
     where
-        E: 's + Expression,
-        E::Atom: Clone + PartialEq,
-        I: IntoIterator<Item = (&'s E::Atom, E)>,
+        E: Expression,
+        E::Atom: Clone,
+        E::Group: FromIterator<E>,
     {
-        from_iter_on_atoms_ref_inner(&mut iter.into_iter(), atom)
-    }
+        type Error = <Structure<E> as Matcher<E>>::Error;
 
-    /// Returns a function which performs substitution using data from an iterator to an atomic expression.
-    #[inline]
-    pub fn from_iter_on_atoms_fn<'s, E, I>(iter: I) -> impl FnMut(E::Atom) -> E
-    where
-        E: 's + Expression,
-        E::Atom: PartialEq,
-        I: IntoIterator<Item = (&'s E::Atom, E)>,
-    {
-        let mut iter = iter.into_iter();
-        move |atom| from_iter_on_atoms_inner(&mut iter, atom)
+        #[inline]
+        fn try_from(expr: &'e Expr<E>) -> Result<Self, Self::Error> {
+            match expr {
+                Expr::Atom(atom) => {
+                    matches_atom::<E>(atom)?;
+                    Err(Self::Error::NotGroup)
+                }
+                Expr::Group(group) => expr::ratio_from_ref(&ExprRef::Group(group.reference())),
+            }
+        }
     }
 
-    /// Returns a function which performs substitution by reference using data from an iterator to an atomic expression.
-    #[inline]
-    pub fn from_iter_on_atoms_ref_fn<'s, E, I>(iter: I) -> impl FnMut(&E::Atom) -> E
+    impl<E> Shape<E> for Structure<E>
     where
-        E: 's + Expression,
-        E::Atom: Clone + PartialEq,
-        I: IntoIterator<Item = (&'s E::Atom, E)>,
+        E: Expression,
+        E::Group: Container<E>,
     {
-        let mut iter = iter.into_iter();
-        move |atom| from_iter_on_atoms_ref_inner(&mut iter, atom)
     }
 
-    /// Performs substitution using data from an iterator.
-    #[inline]
-    pub fn from_iter<'s, E, I>(iter: I, expr: E) -> E
+    impl<E> Rule<E> for Structure<E>
     where
-        E: 's + Expression,
-        E::Atom: PartialEq,
+        E: Expression,
         E::Group: Container<E>,
-        I: IntoIterator<Item = (&'s E::Atom, E)>,
     {
-        expr.substitute(from_iter_on_atoms_fn(iter))
+        #[inline]
+        fn cases(&self) -> Reference<E> {
+            self.into()
+        }
     }
 
-    /// Performs substitution by reference using data from an iterator.
-    #[inline]
-    pub fn from_iter_ref<'s, E, I>(iter: I, expr: &E) -> E
+    /// Conditional Rule
+    ///
+    /// Wraps a rule with a list of [premises](Rule::premises) which must hold before it can be
+    /// applied. A [`Structure`] has no premises, so converting a conditional rule into its
+    /// structure keeps only the underlying rule.
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub struct Conditional<E, R = Structure<E>>
     where
-        E: 's + Expression,
-        E::Atom: Clone + PartialEq,
-        E::Group: FromIterator<E>,
-        I: IntoIterator<Item = (&'s E::Atom, E)>,
+        E: Expression,
     {
-        expr.substitute_ref(from_iter_on_atoms_ref_fn(iter))
-    }
+        /// Underlying rule
+        pub rule: R,
 
-    /// Returns a function which performs substitution using data from an iterator.
-    #[inline]
-    pub fn from_iter_fn<'s, E, I>(iter: I) -> impl FnOnce(E) -> E
-    where
-        E: 's + Expression,
-        E::Atom: PartialEq,
-        E::Group: Container<E>,
-        I: IntoIterator<Item = (&'s E::Atom, E)>,
-    {
-        move |expr| from_iter(iter, expr)
+        /// Premises of the rule
+        pub premises: Vec<E>,
     }
 
-    /// Returns a function which performs substitution by reference using data from an iterator.
-    #[inline]
-    pub fn from_iter_ref_fn<'s, E, I>(iter: I) -> impl FnOnce(&E) -> E
+    impl<E, R> Conditional<E, R>
     where
-        E: 's + Expression,
-        E::Atom: Clone + PartialEq,
-        E::Group: FromIterator<E>,
-        I: IntoIterator<Item = (&'s E::Atom, E)>,
+        E: Expression,
     {
-        move |expr| from_iter_ref(iter, expr)
+        /// Builds a new [`Conditional`] rule from a rule and its premises.
+        #[inline]
+        pub fn new(rule: R, premises: Vec<E>) -> Self {
+            Self { rule, premises }
+        }
+
+        /// Adds a premise to the rule.
+        #[inline]
+        pub fn with_premise(mut self, premise: E) -> Self {
+            self.premises.push(premise);
+            self
+        }
     }
 
-    /// Substitution Term Reference Type
-    #[derive(Clone, Copy, Debug)]
-    pub struct TermRef<'e, E>
+    impl<E, R> crate::Structure<E, Structure<E>> for Conditional<E, R>
     where
         E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
     {
-        /// Substitution variable atom
-        pub var: &'e E::Atom,
+        #[inline]
+        fn from(structure: Structure<E>) -> Self {
+            Self::new(R::from(structure), Vec::new())
+        }
 
-        /// Substitution expression
-        pub expr: &'e E,
+        #[inline]
+        fn structure(self) -> Structure<E> {
+            self.rule.structure()
+        }
     }
 
-    impl<'e, E> TermRef<'e, E>
+    impl<E, R> Rule<E> for Conditional<E, R>
     where
         E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
     {
-        /// Builds a new substitution term reference.
         #[inline]
-        pub fn new(var: &'e E::Atom, expr: &'e E) -> Self {
-            Self { var, expr }
+        fn cases(&self) -> Reference<E> {
+            self.rule.cases()
         }
 
-        /// Checks if `&self` is the identity substitution.
         #[inline]
-        pub fn is_identity(&self) -> bool
+        fn clone(&self) -> Self
         where
-            E::Atom: PartialEq,
+            Self: Sized,
+            E::Atom: Clone,
         {
-            self.expr.atom().map_or(false, move |atom| atom == self.var)
+            Self::new(
+                Rule::clone(&self.rule),
+                self.premises.iter().map(E::clone).collect(),
+            )
         }
 
-        /// Returns an owned [`Term`].
         #[inline]
-        pub fn to_owned(&self) -> Term<E>
+        fn premises(&self) -> &[E] {
+            &self.premises
+        }
+
+        #[inline]
+        fn absences(&self) -> &[E] {
+            self.rule.absences()
+        }
+
+        #[inline]
+        fn allows(&self, bindings: &[substitution::Term<E>]) -> bool {
+            self.rule.allows(bindings)
+        }
+
+        #[inline]
+        fn is_guarded(&self) -> bool {
+            self.rule.is_guarded()
+        }
+
+        #[inline]
+        fn weight(&self) -> Option<f64> {
+            self.rule.weight()
+        }
+
+        #[inline]
+        fn substitute<S>(self, substitution: &S) -> Self
         where
-            E::Atom: Clone,
-            E::Group: FromIterator<E>,
+            Self: Sized,
+            E::Atom: Clone + PartialEq,
+            S: Substitution<E>,
         {
-            Term::new(self.var.clone(), self.expr.clone())
+            Self::new(
+                self.rule.substitute(substitution),
+                self.premises
+                    .into_iter()
+                    .map(move |p| substitution.apply(p))
+                    .collect(),
+            )
         }
-    }
 
-    impl<'e, E> PartialEq for TermRef<'e, E>
-    where
-        E: Expression,
-        E::Atom: PartialEq,
-    {
         #[inline]
-        fn eq(&self, other: &Self) -> bool {
-            self.var.eq(other.var) && self.expr.eq(other.expr)
+        fn substitute_ref<S>(&self, substitution: &S) -> Self
+        where
+            Self: Sized,
+            E::Atom: Clone + PartialEq,
+            S: Substitution<E>,
+        {
+            Self::new(
+                self.rule.substitute_ref(substitution),
+                self.premises
+                    .iter()
+                    .map(move |p| substitution.apply_ref(p))
+                    .collect(),
+            )
+        }
+
+        #[inline]
+        fn reduce_by<F>(self, eq: F) -> Self
+        where
+            Self: Sized,
+            F: FnMut(&E, &E) -> bool,
+        {
+            Self::new(self.rule.reduce_by(eq), self.premises)
         }
     }
 
-    impl<'e, E> Eq for TermRef<'e, E>
+    /// Negated Rule
+    ///
+    /// Wraps a rule with a list of [absence patterns](Rule::absences) which must not match the
+    /// state before it can be applied.
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub struct Negated<E, R = Structure<E>>
     where
         E: Expression,
-        E::Atom: PartialEq,
     {
+        /// Underlying rule
+        pub rule: R,
+
+        /// Absence patterns of the rule
+        pub absent: Vec<E>,
     }
 
-    impl<'e, E> From<&'e Term<E>> for TermRef<'e, E>
+    impl<E, R> Negated<E, R>
     where
         E: Expression,
     {
+        /// Builds a new [`Negated`] rule from a rule and its absence patterns.
         #[inline]
-        fn from(term: &'e Term<E>) -> Self {
-            term.as_ref()
+        pub fn new(rule: R, absent: Vec<E>) -> Self {
+            Self { rule, absent }
+        }
+
+        /// Adds an absence pattern to the rule.
+        #[inline]
+        pub fn with_absence(mut self, pattern: E) -> Self {
+            self.absent.push(pattern);
+            self
         }
     }
 
-    /// Substitution Term Type
-    #[derive(Debug)]
-    pub struct Term<E>
+    impl<E, R> crate::Structure<E, Structure<E>> for Negated<E, R>
     where
         E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
     {
-        /// Substitution variable atom
-        pub var: E::Atom,
+        #[inline]
+        fn from(structure: Structure<E>) -> Self {
+            Self::new(R::from(structure), Vec::new())
+        }
 
-        /// Substitution expression
-        pub expr: E,
+        #[inline]
+        fn structure(self) -> Structure<E> {
+            self.rule.structure()
+        }
     }
 
-    impl<E> Term<E>
+    impl<E, R> Rule<E> for Negated<E, R>
     where
         E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
     {
-        /// Builds a new substitution term.
-        #[inline]
-        pub fn new(var: E::Atom, expr: E) -> Self {
-            Self { var, expr }
-        }
-
-        /// Returns the [`&Term`](Term) as a [`TermRef`].
         #[inline]
-        pub fn as_ref(&self) -> TermRef<'_, E> {
-            TermRef::new(&self.var, &self.expr)
+        fn cases(&self) -> Reference<E> {
+            self.rule.cases()
         }
 
-        /// Builds an identity [`Term`].
         #[inline]
-        pub fn identity(atom: E::Atom) -> Self
+        fn clone(&self) -> Self
         where
+            Self: Sized,
             E::Atom: Clone,
         {
-            Self::new(atom.clone(), E::from_atom(atom))
+            Self::new(
+                Rule::clone(&self.rule),
+                self.absent.iter().map(E::clone).collect(),
+            )
         }
 
-        /// Checks if `&self` is the identity substitution.
         #[inline]
-        pub fn is_identity(&self) -> bool
-        where
-            E::Atom: PartialEq,
-        {
-            self.as_ref().is_identity()
+        fn premises(&self) -> &[E] {
+            self.rule.premises()
         }
 
-        /// Flips the substitution if possible.
         #[inline]
-        pub fn flip(self) -> Option<Self> {
-            Some(Self::new(self.expr.atom()?, E::from_atom(self.var)))
+        fn absences(&self) -> &[E] {
+            &self.absent
         }
 
-        /// Tries the flip the substitution in place and returns `true` on success.
-        ///
-        /// # Panics
-        ///
-        /// This function could panic if the `is_atom`/`unwrap_atom` contract is faulty.
-        pub fn flip_in_place(&mut self) -> bool
-        where
-            E::Group: FromIterator<E>,
-        {
-            if self.expr.is_atom() {
-                self.expr = E::from_atom(mem::replace(
-                    &mut self.var,
-                    mem::replace(&mut self.expr, E::empty()).unwrap_atom(),
-                ));
-                true
-            } else {
-                false
-            }
+        #[inline]
+        fn allows(&self, bindings: &[substitution::Term<E>]) -> bool {
+            self.rule.allows(bindings)
         }
 
-        /// Generates a unit substitution from a substitution term.
         #[inline]
-        pub fn unit<U>(self) -> U
+        fn is_guarded(&self) -> bool {
+            self.rule.is_guarded()
+        }
+
+        #[inline]
+        fn weight(&self) -> Option<f64> {
+            self.rule.weight()
+        }
+
+        #[inline]
+        fn substitute<S>(self, substitution: &S) -> Self
         where
-            U: FromIterator<Self>,
+            Self: Sized,
+            E::Atom: Clone + PartialEq,
+            S: Substitution<E>,
         {
-            Some(self).into_iter().collect()
+            Self::new(
+                self.rule.substitute(substitution),
+                self.absent
+                    .into_iter()
+                    .map(move |p| substitution.apply(p))
+                    .collect(),
+            )
         }
 
         #[inline]
-        fn iter(self) -> impl Iterator<Item = E> {
-            util::two_item_iter(E::from_atom(self.var), self.expr)
+        fn substitute_ref<S>(&self, substitution: &S) -> Self
+        where
+            Self: Sized,
+            E::Atom: Clone + PartialEq,
+            S: Substitution<E>,
+        {
+            Self::new(
+                self.rule.substitute_ref(substitution),
+                self.absent
+                    .iter()
+                    .map(move |p| substitution.apply_ref(p))
+                    .collect(),
+            )
         }
-    }
 
-    impl<E> Clone for Term<E>
-    where
-        E: Expression,
-        E::Atom: Clone,
-        E::Group: FromIterator<E>,
-    {
         #[inline]
-        fn clone(&self) -> Self {
-            Self::new(self.var.clone(), self.expr.clone())
+        fn reduce_by<F>(self, eq: F) -> Self
+        where
+            Self: Sized,
+            F: FnMut(&E, &E) -> bool,
+        {
+            Self::new(self.rule.reduce_by(eq), self.absent)
         }
     }
 
-    impl<E> Copy for Term<E>
+    /// Rule Guard Trait
+    ///
+    /// A guard receives the bindings of a match and can veto the application of a rule.
+    pub trait Guard<E>
     where
-        E: Copy + Expression,
-        E::Atom: Copy,
-        E::Group: FromIterator<E>,
+        E: Expression,
     {
+        /// Returns `true` if the rule can be applied with the match `bindings`.
+        fn allows(&self, bindings: &[substitution::Term<E>]) -> bool;
     }
 
-    impl<E> PartialEq for Term<E>
+    impl<E, F> Guard<E> for F
     where
         E: Expression,
-        E::Atom: PartialEq,
+        F: Fn(&[substitution::Term<E>]) -> bool,
     {
         #[inline]
-        fn eq(&self, other: &Self) -> bool {
-            self.as_ref().eq(&other.as_ref())
+        fn allows(&self, bindings: &[substitution::Term<E>]) -> bool {
+            self(bindings)
         }
     }
 
-    impl<E> Eq for Term<E>
+    /// Guard Condition Evaluator Trait
+    pub trait Evaluator<E>
     where
         E: Expression,
-        E::Atom: PartialEq,
     {
+        /// Evaluates `condition` under the match `bindings`.
+        fn evaluate(&self, condition: &E, bindings: &[substitution::Term<E>]) -> bool;
     }
 
-    impl<E> TryFrom<(E, E)> for Term<E>
-    where
-        E: Expression,
-    {
-        type Error = ();
+    /// Expression Guard
+    ///
+    /// Stores the guard condition as an expression, and delegates its evaluation to a
+    /// user-provided [`Evaluator`]. With the `serde` feature, the guard serializes its condition
+    /// only, and its evaluator is rebuilt with [`Default`] when it is deserialized.
+    #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct ExprGuard<E, V> {
+        /// Guard condition
+        pub condition: E,
+
+        /// Condition evaluator
+        #[cfg_attr(feature = "serde", serde(skip))]
+        pub evaluator: V,
+    }
 
+    impl<E, V> ExprGuard<E, V> {
+        /// Builds a new [`ExprGuard`] from a condition and an evaluator.
         #[inline]
-        fn try_from(pair: (E, E)) -> Result<Self, Self::Error> {
-            Ok(Self::new(pair.0.atom().ok_or(())?, pair.1))
+        pub fn new(condition: E, evaluator: V) -> Self {
+            Self {
+                condition,
+                evaluator,
+            }
         }
     }
 
-    /// Substitution Trait
-    pub trait Substitution<E, V = Vec<Term<E>>>: super::Structure<E, Structure<E, V>>
+    impl<E, V> Guard<E> for ExprGuard<E, V>
     where
         E: Expression,
-        E::Group: Container<E>,
-        V: Container<Term<E>>,
+        V: Evaluator<E>,
     {
-        /// Iterator over elements by reference.
-        type Iter<'s>: Iterator<Item = TermRef<'s, E>>
-        where
-            E: 's;
+        #[inline]
+        fn allows(&self, bindings: &[substitution::Term<E>]) -> bool {
+            self.evaluator.evaluate(&self.condition, bindings)
+        }
+    }
 
-        /// Returns an iterator over the elements by reference.
-        fn iter(&self) -> Self::Iter<'_>;
+    /// Guarded Rule
+    ///
+    /// Wraps a rule with a [`Guard`] which is consulted before every application.
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct Guarded<R, G> {
+        /// Underlying rule
+        pub rule: R,
 
-        /// Returns an iterator over the variables by reference.
+        /// Rule guard, where `None` allows every application
+        pub guard: Option<G>,
+    }
+
+    impl<R, G> Guarded<R, G> {
+        /// Builds a new [`Guarded`] rule from a rule and its guard.
         #[inline]
-        fn vars(&self) -> VarsIter<E, Self::Iter<'_>> {
-            VarsIter(self.iter())
+        pub fn new(rule: R, guard: G) -> Self {
+            Self {
+                rule,
+                guard: Some(guard),
+            }
         }
 
-        /// Returns an iterator over the expressions by reference.
+        /// Builds a new [`Guarded`] rule without a guard.
         #[inline]
-        fn exprs(&self) -> ExprsIter<E, Self::Iter<'_>> {
-            ExprsIter(self.iter())
+        pub fn unguarded(rule: R) -> Self {
+            Self { rule, guard: None }
         }
+    }
 
-        /// Builds an empty `Substitution`.
+    impl<E, R, G> crate::Structure<E, Structure<E>> for Guarded<R, G>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
         #[inline]
-        fn empty() -> Self
-        where
-            Self: Sized,
-        {
-            Self::from(Structure::from_iter(None))
+        fn from(structure: Structure<E>) -> Self {
+            Self::unguarded(R::from(structure))
         }
 
-        /// Checks if the `Substitution` is empty.
         #[inline]
-        fn is_empty(&self) -> bool {
-            self.iter().next().is_none()
+        fn structure(self) -> Structure<E> {
+            self.rule.structure()
         }
+    }
 
-        /// Builds an identity `Substitution` over the iterator of atoms.
+    impl<E, R, G> Rule<E> for Guarded<R, G>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        G: Clone + Guard<E>,
+    {
         #[inline]
-        fn identity<I>(atoms: I) -> Self
+        fn cases(&self) -> Reference<E> {
+            self.rule.cases()
+        }
+
+        #[inline]
+        fn clone(&self) -> Self
         where
             Self: Sized,
             E::Atom: Clone,
-            I: IntoIterator<Item = E::Atom>,
         {
-            Self::from(atoms.into_iter().map(Term::identity).collect())
+            Self {
+                rule: Rule::clone(&self.rule),
+                guard: self.guard.clone(),
+            }
         }
 
-        /// Checks if all of the elements of `&self` are the identity substitution.
         #[inline]
-        fn is_identity(&self) -> bool
-        where
-            E::Atom: PartialEq,
-        {
-            self.iter().all(move |t| t.is_identity())
+        fn premises(&self) -> &[E] {
+            self.rule.premises()
         }
 
-        /// Builds a unit `Substitution`.
         #[inline]
-        fn unit(var: E::Atom, expr: E) -> Self
-        where
-            Self: Sized,
-        {
-            Self::from(Term::new(var, expr).unit())
+        fn absences(&self) -> &[E] {
+            self.rule.absences()
         }
 
-        /// Returns corresponding expression which `var` completes to under substitution.
         #[inline]
-        fn get(&self, var: &E::Atom) -> Option<&E>
-        where
-            E::Atom: PartialEq,
-        {
-            self.iter().find(move |t| t.var == var).map(move |t| t.expr)
+        fn allows(&self, bindings: &[substitution::Term<E>]) -> bool {
+            self.guard
+                .as_ref()
+                .map_or(true, move |g| g.allows(bindings))
+                && self.rule.allows(bindings)
         }
 
-        /// Returns corresponding expression which `var` completes to under substitution.
         #[inline]
-        fn get_owned(&self, var: &E::Atom) -> Option<E>
-        where
-            E::Atom: Clone + PartialEq,
-            E::Group: FromIterator<E>,
-        {
-            self.get(var).map(E::clone)
+        fn is_guarded(&self) -> bool {
+            self.guard.is_some() || self.rule.is_guarded()
         }
 
-        /// Performs substitution on an atomic expression.
         #[inline]
-        fn apply_atom(&self, atom: E::Atom) -> E
-        where
-            E::Atom: Clone + PartialEq,
-            E::Group: FromIterator<E>,
-        {
-            self.get_owned(&atom)
-                .unwrap_or_else(move || E::from_atom(atom))
+        fn weight(&self) -> Option<f64> {
+            self.rule.weight()
         }
 
-        /// Performs substitution on an atomic expression by reference.
         #[inline]
-        fn apply_atom_ref(&self, atom: &E::Atom) -> E
+        fn substitute<S>(self, substitution: &S) -> Self
         where
+            Self: Sized,
             E::Atom: Clone + PartialEq,
-            E::Group: FromIterator<E>,
+            S: Substitution<E>,
         {
-            self.get_owned(atom)
-                .unwrap_or_else(move || E::from_atom(atom.clone()))
+            Self {
+                rule: self.rule.substitute(substitution),
+                guard: self.guard,
+            }
         }
 
-        /// Performs substitution on a grouped expression.
-        fn apply_group(&self, group: E::Group) -> E::Group
+        #[inline]
+        fn substitute_ref<S>(&self, substitution: &S) -> Self
         where
+            Self: Sized,
             E::Atom: Clone + PartialEq,
-            E::Group: FromIterator<E>,
+            S: Substitution<E>,
         {
-            group.substitute(move |atom| self.apply_atom(atom))
+            Self {
+                rule: self.rule.substitute_ref(substitution),
+                guard: self.guard.clone(),
+            }
         }
 
-        /// Performs substitution on a grouped expression by reference.
-        fn apply_group_ref(&self, group: &GroupRef<E>) -> E::Group
+        #[inline]
+        fn reduce_by<F>(self, eq: F) -> Self
         where
-            E::Atom: Clone + PartialEq,
-            E::Group: FromIterator<E>,
+            Self: Sized,
+            F: FnMut(&E, &E) -> bool,
         {
-            group.substitute_ref(move |atom| self.apply_atom_ref(atom))
+            Self {
+                rule: self.rule.reduce_by(eq),
+                guard: self.guard,
+            }
         }
+    }
 
-        /// Performs substitution on an expression.
+    /// Weight Scoring Method
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum Scoring {
+        /// Weights are costs which add up along a derivation
+        Cost,
+
+        /// Weights are probabilities which multiply along a derivation
+        Probability,
+    }
+
+    impl Scoring {
+        /// Returns the score of an empty derivation.
         #[inline]
-        fn apply(&self, expr: E) -> E
-        where
-            E::Atom: Clone + PartialEq,
-            E::Group: Container<E>,
-        {
-            expr.substitute(move |atom| self.apply_atom(atom))
+        pub fn identity(self) -> f64 {
+            match self {
+                Self::Cost => 0.0,
+                Self::Probability => 1.0,
+            }
         }
 
-        /// Performs substitution on an expression by reference.
+        /// Combines two weights.
         #[inline]
-        fn apply_ref(&self, expr: &E) -> E
-        where
-            E::Atom: Clone + PartialEq,
-            E::Group: Container<E>,
-        {
-            expr.substitute_ref(move |atom| self.apply_atom_ref(atom))
+        pub fn combine(self, lhs: f64, rhs: f64) -> f64 {
+            match self {
+                Self::Cost => lhs + rhs,
+                Self::Probability => lhs * rhs,
+            }
         }
 
-        /// Extends the given substitution by one variable-expression pair.
+        /// Returns `true` if the score `lhs` is better than the score `rhs`.
         #[inline]
-        fn push(&mut self, var: E::Atom, expr: E) -> &mut Self
-        where
-            Self: Sized,
-        {
-            self.push_term(Term::new(var, expr))
+        pub fn is_better(self, lhs: f64, rhs: f64) -> bool {
+            match self {
+                Self::Cost => lhs < rhs,
+                Self::Probability => lhs > rhs,
+            }
         }
 
-        /// Extends the given substitution by the one term.
+        /// Computes the cumulative score of a sequence of rules, treating rules without a
+        /// weight as neutral.
         #[inline]
-        fn push_term(&mut self, term: Term<E>) -> &mut Self
+        pub fn score<'r, E, R, I>(self, rules: I) -> f64
         where
-            Self: Sized,
+            E: 'r + Expression,
+            E::Group: Container<E>,
+            R: 'r + Rule<E>,
+            I: IntoIterator<Item = &'r R>,
         {
-            *self = Self::from(
-                mem::replace(self, Self::empty())
-                    .structure()
-                    .push_inner(term),
-            );
-            self
+            rules
+                .into_iter()
+                .filter_map(move |r| r.weight())
+                .fold(self.identity(), move |acc, w| self.combine(acc, w))
         }
+    }
 
-        /// Deduplicates the substitution.
+    impl Default for Scoring {
         #[inline]
-        fn dedup(&mut self) -> &mut Self
-        where
-            Self: Sized,
-            E::Atom: PartialEq,
-        {
-            *self = Self::from(mem::replace(self, Self::empty()).structure().dedup_inner());
-            self
+        fn default() -> Self {
+            Self::Cost
         }
+    }
 
-        /// Sorts the substitution by variable.
+    /// Weighted Rule
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct Weighted<R> {
+        /// Underlying rule
+        pub rule: R,
+
+        /// Rule weight
+        pub weight: Option<f64>,
+    }
+
+    impl<R> Weighted<R> {
+        /// Builds a new [`Weighted`] rule from a rule and its weight.
         #[inline]
-        fn sort(&mut self) -> &mut Self
-        where
-            Self: Sized,
-            E::Atom: Ord,
-        {
-            *self = Self::from(mem::replace(self, Self::empty()).structure().sort_inner());
-            self
+        pub fn new(rule: R, weight: f64) -> Self {
+            Self {
+                rule,
+                weight: Some(weight),
+            }
         }
 
-        /// Reduces the substitution to its canonical form.
+        /// Builds a new [`Weighted`] rule without a weight.
         #[inline]
-        fn canonicalize(&mut self) -> &mut Self
-        where
-            Self: Sized,
-            E::Atom: Ord,
-        {
-            *self = Self::from(
-                mem::replace(self, Self::empty())
-                    .structure()
-                    .canonicalize_inner(),
-            );
-            self
-        }
-
-        /// Tries to generate a substitution from two expressions.
-        // FIXME: find a way to accept `&E` or `ExprRef<E>`
-        #[inline]
-        fn generate<F>(lhs: &E, rhs: &E, can_substitute: F) -> Option<Directed<Self>>
-        where
-            Self: Sized,
-            E::Atom: Clone + PartialEq,
-            F: FnMut(&E::Atom) -> bool,
-        {
-            generate::<_, Structure<_, V>, _>(lhs, rhs, can_substitute)
-                .map(move |d| d.map(Self::from))
+        pub fn unweighted(rule: R) -> Self {
+            Self { rule, weight: None }
         }
     }
 
-    /// Iterator over Substitution Variables
-    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-    pub struct VarsIter<'s, E, I>(I)
-    where
-        E: 's + Expression,
-        I: Iterator<Item = TermRef<'s, E>>;
-
-    impl<'s, E, I> Iterator for VarsIter<'s, E, I>
+    impl<E, R> crate::Structure<E, Structure<E>> for Weighted<R>
     where
-        E: 's + Expression,
-        I: Iterator<Item = TermRef<'s, E>>,
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
     {
-        type Item = &'s E::Atom;
-
         #[inline]
-        fn next(&mut self) -> Option<Self::Item> {
-            self.0.next().map(move |t| t.var)
+        fn from(structure: Structure<E>) -> Self {
+            Self::unweighted(R::from(structure))
         }
 
         #[inline]
-        fn size_hint(&self) -> (usize, Option<usize>) {
-            self.0.size_hint()
+        fn structure(self) -> Structure<E> {
+            self.rule.structure()
         }
     }
 
-    /// Iterator over Substitution Expressions
-    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-    pub struct ExprsIter<'s, E, I>(I)
-    where
-        E: 's + Expression,
-        I: Iterator<Item = TermRef<'s, E>>;
-
-    impl<'s, E, I> Iterator for ExprsIter<'s, E, I>
+    impl<E, R> Rule<E> for Weighted<R>
     where
-        E: 's + Expression,
-        I: Iterator<Item = TermRef<'s, E>>,
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
     {
-        type Item = &'s E;
+        #[inline]
+        fn cases(&self) -> Reference<E> {
+            self.rule.cases()
+        }
 
         #[inline]
-        fn next(&mut self) -> Option<Self::Item> {
-            self.0.next().map(move |t| t.expr)
+        fn clone(&self) -> Self
+        where
+            Self: Sized,
+            E::Atom: Clone,
+        {
+            Self {
+                rule: Rule::clone(&self.rule),
+                weight: self.weight,
+            }
         }
 
         #[inline]
-        fn size_hint(&self) -> (usize, Option<usize>) {
-            self.0.size_hint()
+        fn premises(&self) -> &[E] {
+            self.rule.premises()
         }
-    }
 
-    /// Substitution Structure Type
-    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-    pub struct Structure<E, V = Vec<Term<E>>>
-    where
-        E: Expression,
-        V: Container<Term<E>>,
-    {
-        /// Substitution terms
-        pub terms: V,
+        #[inline]
+        fn absences(&self) -> &[E] {
+            self.rule.absences()
+        }
 
-        /// Phantom Marker
-        __: PhantomData<E>,
-    }
+        #[inline]
+        fn allows(&self, bindings: &[substitution::Term<E>]) -> bool {
+            self.rule.allows(bindings)
+        }
 
-    impl<E, V> Structure<E, V>
-    where
-        E: Expression,
-        V: Container<Term<E>>,
-    {
-        /// Builds a new [`Structure`] from a [`Term`] container.
         #[inline]
-        pub fn new(terms: V) -> Self {
-            Self {
-                terms,
-                __: PhantomData,
-            }
+        fn is_guarded(&self) -> bool {
+            self.rule.is_guarded()
         }
 
         #[inline]
-        fn push_inner(self, term: Term<E>) -> Self {
-            self.into_iter().chain(Some(term)).collect()
+        fn weight(&self) -> Option<f64> {
+            self.weight.or_else(|| self.rule.weight())
         }
 
         #[inline]
-        fn dedup_inner(self) -> Self
+        fn substitute<S>(self, substitution: &S) -> Self
         where
-            E::Atom: PartialEq,
+            Self: Sized,
+            E::Atom: Clone + PartialEq,
+            S: Substitution<E>,
         {
-            let mut terms = self.into_iter().collect::<Vec<_>>();
-            terms.dedup_by(move |l, r| l.var.eq(&r.var));
-            Self::from_iter(terms)
+            Self {
+                rule: self.rule.substitute(substitution),
+                weight: self.weight,
+            }
         }
 
         #[inline]
-        fn sort_inner(self) -> Self
+        fn substitute_ref<S>(&self, substitution: &S) -> Self
         where
-            E::Atom: Ord,
+            Self: Sized,
+            E::Atom: Clone + PartialEq,
+            S: Substitution<E>,
         {
-            let mut terms = self.into_iter().collect::<Vec<_>>();
-            terms.sort_by(move |l, r| l.var.cmp(&r.var));
-            Self::from_iter(terms)
+            Self {
+                rule: self.rule.substitute_ref(substitution),
+                weight: self.weight,
+            }
         }
 
         #[inline]
-        fn canonicalize_inner(self) -> Self
+        fn reduce_by<F>(self, eq: F) -> Self
         where
-            E::Atom: Ord,
+            Self: Sized,
+            F: FnMut(&E, &E) -> bool,
         {
-            self.sort_inner()
-                .dedup_inner()
-                .into_iter()
-                .filter(move |t| !t.is_identity())
-                .collect()
+            Self {
+                rule: self.rule.reduce_by(eq),
+                weight: self.weight,
+            }
         }
     }
 
-    impl<E, V> Default for Structure<E, V>
+    /// Composes two rules using [`pair_compose_by`] and combines their weights using `scoring`.
+    #[inline]
+    pub fn pair_compose_weighted_by<E, T, B, Output, F>(
+        top: T,
+        bot: B,
+        scoring: Scoring,
+        eq: F,
+    ) -> Weighted<Output>
     where
         E: Expression,
-        V: Container<Term<E>>,
+        E::Group: Container<E>,
+        T: Rule<E>,
+        B: Rule<E>,
+        Output: Rule<E>,
+        F: FnMut(&E, &E) -> bool,
     {
-        #[inline]
-        fn default() -> Self {
-            Self::new(V::from_iter(None))
+        let weight = match (top.weight(), bot.weight()) {
+            (Some(t), Some(b)) => Some(scoring.combine(t, b)),
+            (t, b) => t.or(b),
+        };
+        Weighted {
+            rule: pair_compose_by(top, bot, eq),
+            weight,
         }
     }
 
-    impl<E, V> IntoIterator for Structure<E, V>
+    /// Fold an iterator of rules using [`pair_compose_weighted_by`].
+    #[inline]
+    pub fn compose_weighted_by<E, R, I, F>(rules: I, scoring: Scoring, mut eq: F) -> Weighted<R>
     where
         E: Expression,
-        V: Container<Term<E>>,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        I: IntoIterator<Item = R>,
+        F: FnMut(&E, &E) -> bool,
     {
-        type Item = Term<E>;
-
-        type IntoIter = <V as IntoIterator>::IntoIter;
-
-        #[inline]
-        fn into_iter(self) -> Self::IntoIter {
-            self.terms.into_iter()
-        }
+        rules
+            .into_iter()
+            .map(move |r| {
+                let weight = r.weight();
+                Weighted { rule: r, weight }
+            })
+            .reduce(move |t, b| pair_compose_weighted_by(t, b, scoring, &mut eq))
+            .unwrap_or_else(move || Weighted::unweighted(R::empty()))
     }
 
-    impl<E, V> FromIterator<Term<E>> for Structure<E, V>
+    /// Object-Safe Rule Trait
+    ///
+    /// A subset of the [`Rule`] API which can be used behind trait objects, implemented for
+    /// every [`Rule`]. Heterogeneous rules can be stored as `Box<dyn DynRule<E>>`, which is
+    /// itself a [`Rule`].
+    pub trait DynRule<E>
     where
         E: Expression,
-        V: Container<Term<E>>,
     {
-        #[inline]
-        fn from_iter<I>(iter: I) -> Self
-        where
-            I: IntoIterator<Item = Term<E>>,
-        {
-            Self::new(V::from_iter(iter))
-        }
-    }
+        /// Returns a reference to the top and bottom of the rule.
+        fn dyn_cases(&self) -> Reference<E>;
 
-    impl<E, V> From<Structure<E, V>> for Expr<E>
-    where
-        E: Expression,
-        E::Group: FromIterator<E>,
-        V: Container<Term<E>>,
-    {
-        #[inline]
-        fn from(structure: Structure<E, V>) -> Self {
-            Self::Group(structure.into_iter().map(Term::iter).flatten().collect())
-        }
-    }
+        /// Returns the premises of the rule.
+        fn dyn_premises(&self) -> &[E];
 
-    /// Substitution Shape Error Type
-    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-    pub enum ShapeError {
-        /// The expression is not a group.
-        NotGroup,
+        /// Returns the absence patterns of the rule.
+        fn dyn_absences(&self) -> &[E];
 
-        /// The expression is not grouped in pairs.
-        NotGroupedInPairs,
+        /// Returns `true` if the rule can be applied with the match `bindings`.
+        fn dyn_allows(&self, bindings: &[substitution::Term<E>]) -> bool;
 
-        /// The expression does not have the right group shape.
-        BadGroupShape(usize),
-    }
+        /// Returns `true` if a guard may veto the applications of the rule.
+        fn dyn_is_guarded(&self) -> bool;
 
-    /// Checks if an atom matches the `Substitution` pattern.
-    #[inline]
-    pub fn matches_atom<E>(atom: &E::Atom) -> Result<(), ShapeError>
-    where
-        E: Expression,
-    {
-        let _ = atom;
-        Err(ShapeError::NotGroup)
+        /// Returns the weight of the rule if it has one.
+        fn dyn_weight(&self) -> Option<f64>;
+
+        /// Clones the rule into a new box.
+        fn clone_box(&self) -> Box<dyn DynRule<E>>
+        where
+            Self: 'static,
+            E: 'static;
+
+        /// Performs substitution over the rule, keeping its side conditions.
+        fn substitute_box(
+            self: Box<Self>,
+            substitution: &substitution::Structure<E>,
+        ) -> Box<dyn DynRule<E>>
+        where
+            Self: 'static,
+            E: 'static;
+
+        /// Performs substitution over the rule by reference, keeping its side conditions.
+        fn substitute_ref_box(
+            &self,
+            substitution: &substitution::Structure<E>,
+        ) -> Box<dyn DynRule<E>>
+        where
+            Self: 'static,
+            E: 'static;
+
+        /// Reduces the rule using `eq` to compare elements, keeping its side conditions.
+        fn reduce_box(self: Box<Self>, eq: &mut dyn FnMut(&E, &E) -> bool) -> Box<dyn DynRule<E>>
+        where
+            Self: 'static,
+            E: 'static;
+
+        /// Matches the top of the rule against distinct elements of `state`, returning the
+        /// bindings and the indices of the matched elements.
+        fn match_state(
+            &self,
+            state: &[E],
+            is_variable: &mut dyn FnMut(&E::Atom) -> bool,
+        ) -> Option<(Vec<substitution::Term<E>>, Vec<usize>)>;
+
+        /// Applies the rule to `state`, replacing the matched elements by the instantiation of
+        /// the bottom of the rule.
+        fn apply_to(
+            &self,
+            state: &[E],
+            is_variable: &mut dyn FnMut(&E::Atom) -> bool,
+        ) -> Option<Vec<E>>;
+
+        /// Writes a description of the rule in the [text format](text) using `atom` to write
+        /// atoms.
+        fn describe(
+            &self,
+            out: &mut dyn fmt::Write,
+            atom: &mut dyn FnMut(&E::Atom, &mut dyn fmt::Write) -> fmt::Result,
+        ) -> fmt::Result;
     }
 
-    /// Checks if a group matches the `Substitution` pattern.
-    #[inline]
-    pub fn matches_group<E>(group: &GroupRef<E>) -> Result<(), ShapeError>
+    impl<E, R> DynRule<E> for R
     where
         E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
     {
-        for (i, pair) in util::by_pairs(group.iter()).enumerate() {
-            match pair {
-                Some((var, _)) => {
-                    if !var.is_atom() {
-                        return Err(ShapeError::BadGroupShape(i));
-                    }
-                }
-                _ => return Err(ShapeError::NotGroupedInPairs),
-            }
+        #[inline]
+        fn dyn_cases(&self) -> Reference<E> {
+            self.cases()
         }
-        Ok(())
-    }
 
-    /// Checks if an expression matches the `Substitution` pattern.
-    #[inline]
-    pub fn matches<E>(expr: &ExprRef<E>) -> Result<(), ShapeError>
-    where
-        E: Expression,
-    {
-        match expr {
-            ExprRef::Atom(atom) => matches_atom::<E>(atom),
-            ExprRef::Group(group) => matches_group::<E>(group),
+        #[inline]
+        fn dyn_premises(&self) -> &[E] {
+            self.premises()
         }
-    }
 
-    impl<E, V> Matcher<E> for Structure<E, V>
-    where
-        E: Expression,
-        V: Container<Term<E>>,
-    {
-        type Error = ShapeError;
+        #[inline]
+        fn dyn_absences(&self) -> &[E] {
+            self.absences()
+        }
 
         #[inline]
-        fn matches_atom(atom: &E::Atom) -> Result<(), Self::Error> {
-            matches_atom::<E>(atom)
+        fn dyn_allows(&self, bindings: &[substitution::Term<E>]) -> bool {
+            self.allows(bindings)
         }
 
         #[inline]
-        fn matches_group(group: GroupRef<E>) -> Result<(), Self::Error> {
-            matches_group::<E>(&group)
+        fn dyn_is_guarded(&self) -> bool {
+            self.is_guarded()
         }
-    }
 
-    impl<E, V> TryFrom<Expr<E>> for Structure<E, V>
-    where
-        E: Expression,
-        E::Group: IntoIterator<Item = E>,
-        V: Container<Term<E>>,
-    {
-        type Error = <Structure<E, V> as Matcher<E>>::Error;
+        #[inline]
+        fn dyn_weight(&self) -> Option<f64> {
+            self.weight()
+        }
 
         #[inline]
-        fn try_from(expr: Expr<E>) -> Result<Self, Self::Error> {
-            util::by_pairs(expr.group().ok_or(Self::Error::NotGroup)?)
-                .enumerate()
-                .map(move |(i, pair)| match pair {
-                    Some(pair) => pair
-                        .try_into()
-                        .map_err(move |_| Self::Error::BadGroupShape(i)),
-                    _ => Err(Self::Error::NotGroupedInPairs),
-                })
-                .collect()
+        fn clone_box(&self) -> Box<dyn DynRule<E>>
+        where
+            Self: 'static,
+            E: 'static,
+        {
+            Box::new(Rule::clone(self))
         }
-    }
 
-    impl<E, V> Shape<E> for Structure<E, V>
-    where
-        E: Expression,
-        E::Group: Container<E>,
-        V: Container<Term<E>>,
-    {
-    }
+        #[inline]
+        fn substitute_box(
+            self: Box<Self>,
+            substitution: &substitution::Structure<E>,
+        ) -> Box<dyn DynRule<E>>
+        where
+            Self: 'static,
+            E: 'static,
+        {
+            Box::new((*self).substitute(substitution))
+        }
 
-    /// Structure Vector Reference Iterator
-    #[derive(Clone)]
-    pub struct StructureVecIter<'s, E>(slice::Iter<'s, Term<E>>)
-    where
-        E: Expression;
+        #[inline]
+        fn substitute_ref_box(
+            &self,
+            substitution: &substitution::Structure<E>,
+        ) -> Box<dyn DynRule<E>>
+        where
+            Self: 'static,
+            E: 'static,
+        {
+            Box::new(self.substitute_ref(substitution))
+        }
 
-    impl<E> AsRef<[Term<E>]> for StructureVecIter<'_, E>
-    where
-        E: Expression,
-    {
         #[inline]
-        fn as_ref(&self) -> &[Term<E>] {
-            self.0.as_ref()
+        fn reduce_box(self: Box<Self>, eq: &mut dyn FnMut(&E, &E) -> bool) -> Box<dyn DynRule<E>>
+        where
+            Self: 'static,
+            E: 'static,
+        {
+            Box::new((*self).reduce_by(eq))
+        }
+
+        fn match_state(
+            &self,
+            state: &[E],
+            is_variable: &mut dyn FnMut(&E::Atom) -> bool,
+        ) -> Option<(Vec<substitution::Term<E>>, Vec<usize>)> {
+            let top = self.top();
+            let patterns = top.iter().map(move |e| e.cases()).collect::<Vec<_>>();
+            let targets = state.iter().map(move |e| e.cases()).collect::<Vec<_>>();
+            let mut found = None;
+            substitution::match_multiset(
+                &patterns,
+                &targets,
+                &mut Vec::new(),
+                is_variable,
+                |bindings, chosen| {
+                    if !self.allows(bindings) {
+                        return false;
+                    }
+                    found = Some((
+                        bindings
+                            .iter()
+                            .map(move |t| t.as_ref().to_owned())
+                            .collect(),
+                        chosen.to_vec(),
+                    ));
+                    true
+                },
+            );
+            found
+        }
+
+        fn apply_to(
+            &self,
+            state: &[E],
+            is_variable: &mut dyn FnMut(&E::Atom) -> bool,
+        ) -> Option<Vec<E>> {
+            let (bindings, mut chosen) = self.match_state(state, is_variable)?;
+            let substitution = substitution::Structure::<E>::from_iter(bindings);
+            chosen.sort_unstable();
+            let mut next = state
+                .iter()
+                .enumerate()
+                .filter(move |(i, _)| chosen.binary_search(i).is_err())
+                .map(move |(_, e)| E::clone(e))
+                .collect::<Vec<_>>();
+            next.extend(substitution.apply_group_ref(&self.bot()));
+            Some(next)
+        }
+
+        fn describe(
+            &self,
+            mut out: &mut dyn fmt::Write,
+            atom: &mut dyn FnMut(&E::Atom, &mut dyn fmt::Write) -> fmt::Result,
+        ) -> fmt::Result {
+            let (top, bot) = self.ref_pair();
+            let mut write_atom =
+                move |a: &E::Atom, out: &mut &mut dyn fmt::Write| atom(a, &mut **out);
+            text::write_group_with::<E, _, _>(&top, &mut out, &mut write_atom)?;
+            out.write_str(" / ")?;
+            text::write_group_with::<E, _, _>(&bot, &mut out, &mut write_atom)
         }
     }
 
-    impl<'s, E> DoubleEndedIterator for StructureVecIter<'s, E>
+    impl<E> crate::Structure<E, Structure<E>> for Box<dyn DynRule<E>>
     where
-        E: Expression,
+        E: 'static + Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
     {
         #[inline]
-        fn next_back(&mut self) -> Option<TermRef<'s, E>> {
-            self.0.next_back().map(Term::as_ref)
+        fn from(structure: Structure<E>) -> Self {
+            Box::new(structure)
         }
-    }
-
-    impl<E> ExactSizeIterator for StructureVecIter<'_, E> where E: Expression {}
 
-    impl<E> iter::FusedIterator for StructureVecIter<'_, E> where E: Expression {}
+        #[inline]
+        fn structure(self) -> Structure<E> {
+            Structure::from(self.dyn_cases())
+        }
+    }
 
-    impl<'s, E> Iterator for StructureVecIter<'s, E>
+    impl<E> Rule<E> for Box<dyn DynRule<E>>
     where
-        E: Expression,
+        E: 'static + Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
     {
-        type Item = TermRef<'s, E>;
+        #[inline]
+        fn cases(&self) -> Reference<E> {
+            (**self).dyn_cases()
+        }
 
         #[inline]
-        fn next(&mut self) -> Option<Self::Item> {
-            self.0.next().map(Term::as_ref)
+        fn premises(&self) -> &[E] {
+            (**self).dyn_premises()
         }
 
         #[inline]
-        fn size_hint(&self) -> (usize, Option<usize>) {
-            self.0.size_hint()
+        fn absences(&self) -> &[E] {
+            (**self).dyn_absences()
         }
 
         #[inline]
-        fn count(self) -> usize {
-            self.0.count()
+        fn allows(&self, bindings: &[substitution::Term<E>]) -> bool {
+            (**self).dyn_allows(bindings)
         }
-    }
 
-    impl<E> Substitution<E> for Structure<E>
-    where
-        E: Expression,
-        E::Group: Container<E>,
-    {
-        type Iter<'s>
-        where
-            E: 's,
-        = StructureVecIter<'s, E>;
+        #[inline]
+        fn is_guarded(&self) -> bool {
+            (**self).dyn_is_guarded()
+        }
 
         #[inline]
-        fn iter(&self) -> Self::Iter<'_> {
-            StructureVecIter(self.terms.iter())
+        fn weight(&self) -> Option<f64> {
+            (**self).dyn_weight()
         }
 
         #[inline]
-        fn push_term(&mut self, term: Term<E>) -> &mut Self {
-            *self = mem::take(self).push_inner(term);
-            self
+        fn clone(&self) -> Self
+        where
+            Self: Sized,
+            E::Atom: Clone,
+        {
+            (**self).clone_box()
         }
 
         #[inline]
-        fn dedup(&mut self) -> &mut Self
+        fn substitute<S>(self, substitution: &S) -> Self
         where
-            E::Atom: PartialEq,
+            Self: Sized,
+            E::Atom: Clone + PartialEq,
+            S: Substitution<E>,
         {
-            *self = mem::take(self).dedup_inner();
-            self
+            self.substitute_box(&substitution.iter().map(move |t| t.to_owned()).collect())
         }
 
         #[inline]
-        fn sort(&mut self) -> &mut Self
+        fn substitute_ref<S>(&self, substitution: &S) -> Self
         where
-            E::Atom: Ord,
+            Self: Sized,
+            E::Atom: Clone + PartialEq,
+            S: Substitution<E>,
         {
-            *self = mem::take(self).sort_inner();
-            self
+            (**self).substitute_ref_box(&substitution.iter().map(move |t| t.to_owned()).collect())
         }
 
         #[inline]
-        fn canonicalize(&mut self) -> &mut Self
+        fn reduce_by<F>(self, mut eq: F) -> Self
         where
-            E::Atom: Ord,
+            Self: Sized,
+            F: FnMut(&E, &E) -> bool,
         {
-            *self = mem::take(self).canonicalize_inner();
-            self
+            self.reduce_box(&mut eq)
         }
     }
 
-    /// Directed Substitution
-    pub enum Directed<S> {
-        /// Forward Substitution
-        Forward(S),
+    /// [`Rule`] Reference Pair Type
+    pub type RefPair<'e, E> = (GroupRef<'e, E>, GroupRef<'e, E>);
 
-        /// Backward Substitution
-        Backward(S),
-    }
+    /// [`Rule`] Pair Type
+    pub type Pair<E> = (<E as Expression>::Group, <E as Expression>::Group);
 
-    /// Forward Substitution Boolean
-    pub type IsForward = bool;
+    /// [`RuleSet`] Identifier Type
+    pub type RuleId = usize;
 
-    impl<S> Directed<S> {
-        /// Returns `true` if the substitution is a `Forward` substitution.
+    /// [`Rule`] Metadata
+    #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+    #[derive(Clone, Debug, Default)]
+    pub struct Metadata {
+        /// Tags attached to the rule
+        #[cfg_attr(feature = "serde", serde(default))]
+        pub tags: Vec<String>,
+
+        /// Description of the rule
+        #[cfg_attr(feature = "serde", serde(default))]
+        pub description: Option<String>,
+
+        /// Identifiers of the rules whose composition produced this rule
+        #[cfg_attr(feature = "serde", serde(default))]
+        pub derived_from: Vec<RuleId>,
+
+        /// Identifier of the rule schema this rule is an instance of
+        #[cfg_attr(feature = "serde", serde(default))]
+        pub instance_of: Option<RuleId>,
+
+        /// Score of a derived rule, the sum of the weights of the rules it was derived from
+        #[cfg_attr(feature = "serde", serde(default))]
+        pub score: Option<f64>,
+    }
+
+    impl Metadata {
+        /// Builds a new [`Metadata`] object from a list of tags and a description.
         #[inline]
-        pub fn is_forward(&self) -> bool {
-            matches!(self, Self::Forward(_))
+        pub fn new(tags: Vec<String>, description: Option<String>) -> Self {
+            Self {
+                tags,
+                description,
+                derived_from: Vec::new(),
+                instance_of: None,
+                score: None,
+            }
         }
 
-        /// Returns `true` if the substitution is a `Backward` substitution.
+        /// Returns `true` if the rule was derived from other rules.
         #[inline]
-        pub fn is_backward(&self) -> bool {
-            matches!(self, Self::Backward(_))
+        pub fn is_derived(&self) -> bool {
+            !self.derived_from.is_empty()
         }
 
-        /// Returns `Some` with the inner substitution if `self` is `Forward`.
-        #[must_use]
+        /// Returns `true` if `tag` is one of the tags of the rule.
         #[inline]
-        pub fn forward(self) -> Option<S> {
-            match self {
-                Self::Forward(substitution) => Some(substitution),
-                _ => None,
-            }
+        pub fn has_tag(&self, tag: &str) -> bool {
+            self.tags.iter().any(move |t| t == tag)
         }
+    }
 
-        /// Returns `Some` with the inner substitution if `self` is `Forward`.
-        #[must_use]
+    impl PartialEq for Metadata {
         #[inline]
-        pub fn forward_ref(&self) -> Option<&S> {
-            match self {
-                Self::Forward(substitution) => Some(substitution),
-                _ => None,
-            }
+        fn eq(&self, other: &Self) -> bool {
+            self.tags == other.tags
+                && self.description == other.description
+                && self.derived_from == other.derived_from
+                && self.instance_of == other.instance_of
+                && self.score.map(f64::to_bits) == other.score.map(f64::to_bits)
         }
+    }
 
-        /// Returns `Some` with the inner substitution if `self` is `Backward`.
-        #[must_use]
+    impl Eq for Metadata {}
+
+    impl Hash for Metadata {
         #[inline]
-        pub fn backward(self) -> Option<S> {
-            match self {
-                Self::Backward(substitution) => Some(substitution),
-                _ => None,
-            }
+        fn hash<H>(&self, state: &mut H)
+        where
+            H: Hasher,
+        {
+            self.tags.hash(state);
+            self.description.hash(state);
+            self.derived_from.hash(state);
+            self.instance_of.hash(state);
+            self.score.map(f64::to_bits).hash(state);
         }
+    }
 
-        /// Returns `Some` with the inner substitution if `self` is `Backward`.
-        #[must_use]
+    /// Rule Schema Instance
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub struct Instance<E, R>
+    where
+        E: Expression,
+    {
+        /// Instantiated Rule
+        pub rule: R,
+
+        /// Bindings used to instantiate the rule schema
+        pub bindings: substitution::Structure<E>,
+    }
+
+    impl<E, R> Instance<E, R>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        /// Returns `true` if no atom of the instance satisfies `is_variable`.
         #[inline]
-        pub fn backward_ref(&self) -> Option<&S> {
-            match self {
-                Self::Backward(substitution) => Some(substitution),
-                _ => None,
+        pub fn is_ground<F>(&self, mut is_variable: F) -> bool
+        where
+            F: FnMut(&E::Atom) -> bool,
+        {
+            fn is_ground_ref<E, F>(expr: &ExprRef<E>, is_variable: &mut F) -> bool
+            where
+                E: Expression,
+                F: FnMut(&E::Atom) -> bool,
+            {
+                match expr {
+                    ExprRef::Atom(atom) => !is_variable(atom),
+                    ExprRef::Group(group) => group
+                        .iter()
+                        .all(move |e| is_ground_ref(&e.cases(), is_variable)),
+                }
             }
+            let (top, bot) = self.rule.ref_pair();
+            top.iter()
+                .chain(bot.iter())
+                .all(move |e| is_ground_ref(&e.cases(), &mut is_variable))
         }
+    }
 
-        /// Maps a `Directed<S>` to `Directed<T>` by applying a function to a contained value.
+    /// [`RuleSet`] Entry Type
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub struct Entry<R> {
+        /// Rule Identifier
+        pub id: RuleId,
+
+        /// Rule Name
+        pub name: String,
+
+        /// Rule Object
+        pub rule: R,
+
+        /// Rule Metadata
+        pub metadata: Metadata,
+    }
+
+    impl<R> Entry<R> {
+        /// Builds a new [`Entry`].
         #[inline]
-        pub fn map<T, F>(self, f: F) -> Directed<T>
-        where
-            F: FnOnce(S) -> T,
-        {
-            match self {
-                Self::Forward(substitution) => Directed::Forward(f(substitution)),
-                Self::Backward(substitution) => Directed::Backward(f(substitution)),
+        pub fn new(id: RuleId, name: String, rule: R, metadata: Metadata) -> Self {
+            Self {
+                id,
+                name,
+                rule,
+                metadata,
             }
         }
+    }
 
-        /// Returns the inner substitution.
+    /// [`RuleSet`] Entry Insertion Error
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub enum InsertError<R> {
+        /// The identifier of the entry is already in use.
+        DuplicateId(Entry<R>),
+
+        /// The identifier of the entry is the largest one, which leaves no identifier for the
+        /// next inserted rule.
+        IdOverflow(Entry<R>),
+    }
+
+    impl<R> InsertError<R> {
+        /// Returns the entry which could not be inserted.
         #[inline]
-        pub fn substitution(self) -> S {
+        pub fn into_entry(self) -> Entry<R> {
             match self {
-                Self::Forward(substitution) | Self::Backward(substitution) => substitution,
+                Self::DuplicateId(entry) | Self::IdOverflow(entry) => entry,
             }
         }
+    }
 
-        /// Packs the enumeration from a `(bool, S)` pair.
+    impl<R> fmt::Display for InsertError<R> {
         #[inline]
-        pub fn pack(is_forward: IsForward, substitution: S) -> Self {
-            if is_forward {
-                Self::Forward(substitution)
-            } else {
-                Self::Backward(substitution)
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::DuplicateId(entry) => write!(f, "rule identifier {} is in use", entry.id),
+                Self::IdOverflow(entry) => write!(f, "rule identifier {} is too large", entry.id),
             }
         }
+    }
 
-        /// Unpacks the enumeration into a `(bool, S)` pair.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl<R> std::error::Error for InsertError<R> where R: fmt::Debug {}
+
+    /// Rule Derivation Error
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum DeriveError {
+        /// The rule is not in the set.
+        Missing(RuleId),
+
+        /// The rule has premises, which composition cannot carry.
+        Conditional(RuleId),
+
+        /// The rule has a guard, which composition cannot carry.
+        Guarded(RuleId),
+
+        /// The rule has absence patterns, which composition cannot carry.
+        Negated(RuleId),
+
+        /// The set has run out of identifiers for the derived rule.
+        IdOverflow,
+    }
+
+    impl fmt::Display for DeriveError {
         #[inline]
-        pub fn unpack(self) -> (IsForward, S) {
-            (self.is_forward(), self.substitution())
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::Missing(id) => write!(f, "rule {} is not in the set", id),
+                Self::Conditional(id) => write!(f, "rule {} has premises", id),
+                Self::Guarded(id) => write!(f, "rule {} has a guard", id),
+                Self::Negated(id) => write!(f, "rule {} has absence patterns", id),
+                Self::IdOverflow => f.write_str("rule identifiers are exhausted"),
+            }
         }
     }
 
-    /// Tries to generate a substitution from two atomic expressions.
-    #[inline]
-    pub fn generate_from_atoms<E, S, F>(
-        lhs: &E::Atom,
-        rhs: &E::Atom,
-        mut can_substitute: F,
-    ) -> Option<Directed<S>>
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for DeriveError {}
+
+    /// Rule Set
+    ///
+    /// Every rule inserted into a [`RuleSet`] is given a [`RuleId`] which stays valid until the
+    /// rule is removed. Identifiers are never reused by the same set.
+    #[derive(Debug)]
+    pub struct RuleSet<E, R = Structure<E>>
     where
         E: Expression,
-        E::Atom: Clone + PartialEq,
-        E::Group: FromIterator<E>,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        /// Rule entries sorted by identifier
+        entries: Vec<Entry<R>>,
+
+        /// Next fresh identifier
+        next_id: RuleId,
+
+        /// Phantom Marker
+        __: PhantomData<E>,
+    }
+
+    impl<E, R> RuleSet<E, R>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        /// Builds a new empty [`RuleSet`].
+        #[inline]
+        pub fn new() -> Self {
+            Self {
+                entries: Vec::new(),
+                next_id: 0,
+                __: PhantomData,
+            }
+        }
+
+        /// Returns the number of rules in the set.
+        #[inline]
+        pub fn len(&self) -> usize {
+            self.entries.len()
+        }
+
+        /// Returns `true` if the set has no rules.
+        #[inline]
+        pub fn is_empty(&self) -> bool {
+            self.entries.is_empty()
+        }
+
+        #[inline]
+        fn position(&self, id: RuleId) -> Result<usize, usize> {
+            self.entries.binary_search_by_key(&id, move |e| e.id)
+        }
+
+        /// Inserts a new rule with the given `name` and returns its identifier.
+        ///
+        /// # Panics
+        ///
+        /// Panics if the set has run out of identifiers, see
+        /// [`insert_with_metadata`](Self::insert_with_metadata).
+        #[inline]
+        pub fn insert<N>(&mut self, name: N, rule: R) -> RuleId
+        where
+            N: Into<String>,
+        {
+            match self.insert_with_metadata(name, rule, Default::default()) {
+                Ok(id) => id,
+                Err(error) => panic!("{}", error),
+            }
+        }
+
+        /// Inserts a new rule with the given `name` and `metadata` and returns its identifier,
+        /// returning the entry back if the set has run out of identifiers.
+        #[inline]
+        pub fn insert_with_metadata<N>(
+            &mut self,
+            name: N,
+            rule: R,
+            metadata: Metadata,
+        ) -> Result<RuleId, InsertError<R>>
+        where
+            N: Into<String>,
+        {
+            let id = self.next_id;
+            let entry = Entry::new(id, name.into(), rule, metadata);
+            match id.checked_add(1) {
+                Some(next_id) => {
+                    self.next_id = next_id;
+                    self.entries.push(entry);
+                    Ok(id)
+                }
+                _ => Err(InsertError::IdOverflow(entry)),
+            }
+        }
+
+        /// Composes the rules with the given identifiers in order, [reduces](Rule::reduce) the
+        /// result, and inserts it as a new rule whose [metadata](Metadata::derived_from) records
+        /// the chain it was derived from. The [score](Metadata::score) of the new rule combines
+        /// the [weights](Self::weight) of the rules of the chain which have one using
+        /// `scoring`.
+        ///
+        /// Fails on the first rule of the chain which is not in the set or has
+        /// [premises](Rule::premises), a [guard](Rule::is_guarded) or
+        /// [absence patterns](Rule::absences), which the composite could not carry, or if the
+        /// set has run out of identifiers.
+        pub fn derive<N>(
+            &mut self,
+            name: N,
+            chain: &[RuleId],
+            scoring: Scoring,
+        ) -> Result<RuleId, DeriveError>
+        where
+            N: Into<String>,
+            E::Atom: PartialEq,
+        {
+            let mut rules = Vec::with_capacity(chain.len());
+            let mut score = None;
+            for id in chain {
+                let rule = self.rule(*id).ok_or(DeriveError::Missing(*id))?;
+                if !rule.premises().is_empty() {
+                    return Err(DeriveError::Conditional(*id));
+                }
+                if rule.is_guarded() {
+                    return Err(DeriveError::Guarded(*id));
+                }
+                if !rule.absences().is_empty() {
+                    return Err(DeriveError::Negated(*id));
+                }
+                rules.push(Structure::<E>::from(rule.cases()));
+                if let Some(weight) = self.weight(*id) {
+                    score = Some(score.map_or(weight, move |s| scoring.combine(s, weight)));
+                }
+            }
+            let derived = compose::<E, Structure<E>, _>(rules).reduce();
+            let metadata = Metadata {
+                derived_from: chain.to_vec(),
+                score,
+                ..Metadata::default()
+            };
+            self.insert_with_metadata(name, R::from(derived), metadata)
+                .map_err(move |_| DeriveError::IdOverflow)
+        }
+
+        /// Instantiates the rule schema `schema` with `substitution` and inserts the instance
+        /// as a new rule whose [metadata](Metadata::instance_of) links back to the schema.
+        ///
+        /// Returns the identifier of the new rule and the bindings used to instantiate it, or
+        /// `None` if `schema` is not in the set or if the set has run out of identifiers.
+        pub fn instantiate<N, S>(
+            &mut self,
+            schema: RuleId,
+            name: N,
+            substitution: &S,
+        ) -> Option<(RuleId, substitution::Structure<E>)>
+        where
+            N: Into<String>,
+            E::Atom: Clone + PartialEq,
+            S: Substitution<E>,
+        {
+            let entry = self.get(schema)?;
+            let instance = entry.rule.instantiate(substitution);
+            let metadata = Metadata {
+                tags: entry.metadata.tags.clone(),
+                instance_of: Some(schema),
+                ..Metadata::default()
+            };
+            let id = self
+                .insert_with_metadata(name, instance.rule, metadata)
+                .ok()?;
+            Some((id, instance.bindings))
+        }
+
+        /// Computes the [`Statistics`] of the rules, estimating their interaction with the
+        /// `sample` state by syntactic comparison.
+        pub fn statistics(&self, sample: &[E]) -> Statistics<E::Atom>
+        where
+            E::Atom: Clone + PartialEq,
+        {
+            let mut atoms = Vec::new();
+            let mut sizes = Vec::new();
+            let mut depths = Vec::new();
+            let mut branching = 0;
+            let mut cancellation = Vec::with_capacity(self.len());
+            let (mut consumed, mut produced) = (0, 0);
+            for entry in self.iter() {
+                let (top, bot) = entry.rule.ref_pair();
+                let mut max_depth = 0;
+                for e in top.iter().chain(bot.iter()) {
+                    let e = e.cases();
+                    count_atoms(&e, &mut atoms);
+                    max_depth = max_depth.max(ordering::depth(&e));
+                }
+                record(&mut sizes, rule_size(&entry.rule));
+                record(&mut depths, max_depth);
+                let mut used = vec![false; sample.len()];
+                let mut cancelled = 0;
+                for t in top.iter() {
+                    let t = t.cases();
+                    let found = sample
+                        .iter()
+                        .zip(used.iter_mut())
+                        .find(|(s, used)| !**used && expr::eq_ref::<E>(&s.cases(), &t));
+                    if let Some((_, used)) = found {
+                        *used = true;
+                        cancelled += 1;
+                    }
+                }
+                let top_len = top.iter().count();
+                if cancelled == top_len {
+                    branching += 1;
+                }
+                consumed += top_len;
+                produced += bot.iter().count();
+                cancellation.push((entry.id, cancelled));
+            }
+            atoms.sort_by(|l, r| r.1.cmp(&l.1));
+            Statistics {
+                atoms,
+                sizes,
+                depths,
+                branching,
+                growth: if consumed == 0 {
+                    0.0
+                } else {
+                    produced as f64 / consumed as f64
+                },
+                cancellation,
+            }
+        }
+
+        /// Builds a [`Query`] over the rules of the set.
+        #[inline]
+        pub fn query(&self) -> Query<E, R>
+        where
+            E::Atom: PartialEq,
+        {
+            Query::new(self)
+        }
+
+        /// Compiles a [`Matcher`](index::Matcher) which applies the rules of the set to states
+        /// of expressions, treating the atoms which satisfy `is_variable` as variables.
+        #[inline]
+        pub fn compile_matcher(&self, is_variable: fn(&E::Atom) -> bool) -> index::Matcher<E, R>
+        where
+            E::Atom: Clone + Hash + PartialEq,
+        {
+            index::Matcher::new(self, is_variable)
+        }
+
+        /// Inserts an entry keeping its identifier, returning the entry back if the identifier
+        /// is already in use or leaves no identifier for the next inserted rule.
+        #[inline]
+        pub fn insert_entry(&mut self, entry: Entry<R>) -> Result<(), InsertError<R>> {
+            let next_id = match entry.id.checked_add(1) {
+                Some(next_id) => next_id,
+                _ => return Err(InsertError::IdOverflow(entry)),
+            };
+            match self.position(entry.id) {
+                Ok(_) => Err(InsertError::DuplicateId(entry)),
+                Err(index) => {
+                    self.next_id = self.next_id.max(next_id);
+                    self.entries.insert(index, entry);
+                    Ok(())
+                }
+            }
+        }
+
+        /// Removes the rule with the given identifier from the set.
+        #[inline]
+        pub fn remove(&mut self, id: RuleId) -> Option<Entry<R>> {
+            let index = self.position(id).ok()?;
+            Some(self.entries.remove(index))
+        }
+
+        /// Returns `true` if there is a rule with the given identifier in the set.
+        #[inline]
+        pub fn contains(&self, id: RuleId) -> bool {
+            self.position(id).is_ok()
+        }
+
+        /// Returns the entry with the given identifier.
+        #[inline]
+        pub fn get(&self, id: RuleId) -> Option<&Entry<R>> {
+            self.position(id).ok().map(move |i| &self.entries[i])
+        }
+
+        /// Returns the entry with the given identifier by mutable reference.
+        #[inline]
+        pub fn get_mut(&mut self, id: RuleId) -> Option<&mut Entry<R>> {
+            let index = self.position(id).ok()?;
+            Some(&mut self.entries[index])
+        }
+
+        /// Returns the rule with the given identifier.
+        #[inline]
+        pub fn rule(&self, id: RuleId) -> Option<&R> {
+            self.get(id).map(move |e| &e.rule)
+        }
+
+        /// Returns the weight of the rule with the given identifier, which is its own
+        /// [weight](Rule::weight) or else the [score](Metadata::score) it was derived with.
+        #[inline]
+        pub fn weight(&self, id: RuleId) -> Option<f64> {
+            let entry = self.get(id)?;
+            entry.rule.weight().or(entry.metadata.score)
+        }
+
+        /// Returns the first entry with the given `name`.
+        #[inline]
+        pub fn find(&self, name: &str) -> Option<&Entry<R>> {
+            self.entries.iter().find(move |e| e.name == name)
+        }
+
+        /// Returns an iterator over the entries of the set in identifier order.
+        #[inline]
+        pub fn iter(&self) -> slice::Iter<Entry<R>> {
+            self.entries.iter()
+        }
+
+        /// Returns an iterator over the identifiers of the set in increasing order.
+        #[inline]
+        pub fn ids(&self) -> impl '_ + Iterator<Item = RuleId> {
+            self.entries.iter().map(move |e| e.id)
+        }
+
+        /// Returns the entries of the set as a slice.
+        #[inline]
+        pub fn entries(&self) -> &[Entry<R>] {
+            &self.entries
+        }
+
+        /// Retains only the entries specified by the predicate.
+        #[inline]
+        pub fn retain<F>(&mut self, f: F)
+        where
+            F: FnMut(&Entry<R>) -> bool,
+        {
+            self.entries.retain(f)
+        }
+
+        /// Computes a digest of the rules in the set.
+        ///
+        /// The digest only depends on the contents of the rules, computed with
+        /// [`Rule::content_hash`], and not on their names, metadata, identifiers, or the order in
+        /// which they were inserted.
+        #[inline]
+        pub fn content_hash(&self) -> u64
+        where
+            E::Atom: Hash,
+        {
+            util::multiset_digest(self.entries.iter().map(move |e| e.rule.content_hash()))
+        }
+
+        /// Removes the rules which are equal to a rule with a smaller identifier up to
+        /// [reduction](Rule::reduce) and a permutation of their tops and bottoms.
+        ///
+        /// The rules must also have the same [weight](Rule::weight), and the same
+        /// [premises](Rule::premises) and [absence patterns](Rule::absences) up to permutation.
+        /// Guards cannot be compared, so [guarded](Rule::is_guarded) rules are always kept.
+        pub fn dedup(&mut self) -> DedupReport
+        where
+            E::Atom: Clone + Hash + PartialEq,
+        {
+            let mut kept = Vec::<(u64, Structure<E>, &Entry<R>)>::new();
+            let mut report = DedupReport::default();
+            for entry in &self.entries {
+                if entry.rule.is_guarded() {
+                    continue;
+                }
+                let reduced = Structure::from(entry.rule.cases()).reduce();
+                let digest = reduced.content_hash();
+                match kept.iter().find(|(d, rule, other)| {
+                    *d == digest
+                        && rule.multiset_eq(&reduced)
+                        && same_side_conditions(&other.rule, &entry.rule)
+                }) {
+                    Some((_, _, other)) => report.merged.push((entry.id, other.id)),
+                    _ => kept.push((digest, reduced, entry)),
+                }
+            }
+            self.remove_sorted(report.removed());
+            report
+        }
+
+        /// Removes the rules whose identifiers are yielded by `ids` in increasing order.
+        #[inline]
+        fn remove_sorted<I>(&mut self, ids: I)
+        where
+            I: IntoIterator<Item = RuleId>,
+        {
+            let mut ids = ids.into_iter().peekable();
+            self.entries.retain(move |e| match ids.peek() {
+                Some(id) if *id == e.id => {
+                    ids.next();
+                    false
+                }
+                _ => true,
+            });
+        }
+
+        /// Removes the rules which are subsumed by other rules of the set and returns the number
+        /// of removed rules.
+        ///
+        /// See [`prune_subsumed_with_report`](Self::prune_subsumed_with_report) for more details.
+        #[inline]
+        pub fn prune_subsumed<F>(&mut self, modulo_renaming: bool, can_substitute: F) -> usize
+        where
+            E::Atom: Clone + PartialEq,
+            F: FnMut(&E::Atom) -> bool,
+        {
+            self.prune_subsumed_with_report(modulo_renaming, can_substitute)
+                .pruned
+                .len()
+        }
+
+        /// Removes the rules which are subsumed by other rules of the set.
+        ///
+        /// A rule is removed if some other rule [subsumes](Rule::subsumes) it but is not
+        /// subsumed by it. If `modulo_renaming` is `true`, rules which subsume each other, i.e.
+        /// which are equal up to a renaming of their variables, are also removed in favor of the
+        /// one with the smallest identifier.
+        ///
+        /// Subsumption only compares the tops and bottoms of the rules, so rules with
+        /// [side conditions](Rule::has_side_conditions) are neither removed nor used to remove
+        /// other rules.
+        pub fn prune_subsumed_with_report<F>(
+            &mut self,
+            modulo_renaming: bool,
+            mut can_substitute: F,
+        ) -> PruneReport
+        where
+            E::Atom: Clone + PartialEq,
+            F: FnMut(&E::Atom) -> bool,
+        {
+            let mut report = PruneReport::default();
+            for (i, entry) in self.entries.iter().enumerate() {
+                if entry.rule.has_side_conditions() {
+                    continue;
+                }
+                let subsumer = self.entries.iter().enumerate().find(|(j, other)| {
+                    *j != i
+                        && !other.rule.has_side_conditions()
+                        && !report.removed().any(|id| id == other.id)
+                        && other.rule.subsumes(&entry.rule, &mut can_substitute)
+                        && ((modulo_renaming && *j < i)
+                            || !entry.rule.subsumes(&other.rule, &mut can_substitute))
+                });
+                if let Some((_, other)) = subsumer {
+                    report.pruned.push((entry.id, other.id));
+                }
+            }
+            self.remove_sorted(report.removed());
+            report
+        }
+
+        /// Removes the rules which are equal, up to [reduction](Rule::reduce) and a permutation
+        /// of their tops and bottoms, to a [composition](compose) of at least two other rules of
+        /// the set.
+        ///
+        /// Rules are considered in increasing order of their identifiers and compositions are
+        /// explored by increasing length. Every composition costs one unit of `budget` and the
+        /// procedure stops early once the budget is exhausted. Each removal is justified by a
+        /// derivation which only refers to rules which were still in the set at the time of the
+        /// removal, so that the removed rules remain derivable from the final set.
+        ///
+        /// Composition does not carry [side conditions](Rule::has_side_conditions), so rules
+        /// which have some are neither removed nor used in compositions.
+        pub fn minimize(&mut self, mut budget: usize) -> MinimizeReport
+        where
+            E::Atom: PartialEq,
+            Structure<E>: Clone,
+        {
+            let mut report = MinimizeReport::default();
+            for index in 0..self.entries.len() {
+                if self.entries[index].rule.has_side_conditions() {
+                    continue;
+                }
+                let target = Structure::from(self.entries[index].rule.cases()).reduce();
+                let basis = self
+                    .entries
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, e)| {
+                        *i != index
+                            && !e.rule.has_side_conditions()
+                            && !report.removed().any(|id| id == e.id)
+                    })
+                    .map(move |(_, e)| (e.id, Structure::<E>::from(e.rule.cases())))
+                    .collect::<Vec<_>>();
+                let mut queue = basis
+                    .iter()
+                    .map(move |(id, rule)| (vec![*id], rule.clone()))
+                    .collect::<VecDeque<_>>();
+                let mut derivation = None;
+                'search: while let Some((steps, composed)) = queue.pop_front() {
+                    for (id, rule) in &basis {
+                        if budget == 0 {
+                            report.exhausted = true;
+                            break 'search;
+                        }
+                        budget -= 1;
+                        let next: Structure<E> = pair_compose(composed.clone(), rule.clone());
+                        let mut next_steps = steps.clone();
+                        next_steps.push(*id);
+                        if next.clone().reduce().multiset_eq(&target) {
+                            derivation = Some(next_steps);
+                            break 'search;
+                        }
+                        queue.push_back((next_steps, next));
+                    }
+                }
+                if let Some(steps) = derivation {
+                    report.removed.push((self.entries[index].id, steps));
+                }
+                if report.exhausted {
+                    break;
+                }
+            }
+            self.remove_sorted(report.removed());
+            report
+        }
+    }
+
+    /// Returns `true` if `lhs` and `rhs` have the same weight, and the same premises and
+    /// absence patterns up to permutation.
+    #[inline]
+    fn same_side_conditions<E, R>(lhs: &R, rhs: &R) -> bool
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        let same = move |lhs: &[E], rhs: &[E]| {
+            util::multiset_eq_by(lhs, rhs.iter().collect(), move |l: &&E, r: &&E| E::eq(l, r))
+        };
+        lhs.weight() == rhs.weight()
+            && same(lhs.premises(), rhs.premises())
+            && same(lhs.absences(), rhs.absences())
+    }
+
+    /// [`RuleSet::dedup`] Report
+    #[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct DedupReport {
+        /// Pairs `(removed, kept)` of the identifiers of the removed rules and the rules they
+        /// were merged into
+        pub merged: Vec<(RuleId, RuleId)>,
+    }
+
+    impl DedupReport {
+        /// Returns `true` if no rules were removed.
+        #[inline]
+        pub fn is_empty(&self) -> bool {
+            self.merged.is_empty()
+        }
+
+        /// Returns an iterator over the identifiers of the removed rules in increasing order.
+        #[inline]
+        pub fn removed(&self) -> impl '_ + Iterator<Item = RuleId> {
+            self.merged.iter().map(move |(removed, _)| *removed)
+        }
+    }
+
+    /// [`RuleSet::minimize`] Report
+    #[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct MinimizeReport {
+        /// Pairs `(removed, derivation)` of the identifiers of the removed rules and of the
+        /// sequences of rules whose [composition](compose) is equal to them
+        pub removed: Vec<(RuleId, Vec<RuleId>)>,
+
+        /// Set to `true` if the budget was exhausted before every rule was considered
+        pub exhausted: bool,
+    }
+
+    impl MinimizeReport {
+        /// Returns `true` if no rules were removed.
+        #[inline]
+        pub fn is_empty(&self) -> bool {
+            self.removed.is_empty()
+        }
+
+        /// Returns an iterator over the identifiers of the removed rules in increasing order.
+        #[inline]
+        pub fn removed(&self) -> impl '_ + Iterator<Item = RuleId> {
+            self.removed.iter().map(move |(removed, _)| *removed)
+        }
+    }
+
+    /// [`RuleSet::prune_subsumed_with_report`] Report
+    #[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct PruneReport {
+        /// Pairs `(removed, subsumer)` of the identifiers of the removed rules and of the rules
+        /// which subsume them
+        pub pruned: Vec<(RuleId, RuleId)>,
+    }
+
+    impl PruneReport {
+        /// Returns `true` if no rules were removed.
+        #[inline]
+        pub fn is_empty(&self) -> bool {
+            self.pruned.is_empty()
+        }
+
+        /// Returns an iterator over the identifiers of the removed rules in increasing order.
+        #[inline]
+        pub fn removed(&self) -> impl '_ + Iterator<Item = RuleId> {
+            self.pruned.iter().map(move |(removed, _)| *removed)
+        }
+    }
+
+    impl<E, R> Clone for RuleSet<E, R>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Clone + Rule<E>,
+    {
+        #[inline]
+        fn clone(&self) -> Self {
+            Self {
+                entries: self.entries.clone(),
+                next_id: self.next_id,
+                __: PhantomData,
+            }
+        }
+    }
+
+    impl<E, R> Default for RuleSet<E, R>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        #[inline]
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<E, R> IntoIterator for RuleSet<E, R>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        type Item = Entry<R>;
+
+        type IntoIter = alloc::vec::IntoIter<Entry<R>>;
+
+        #[inline]
+        fn into_iter(self) -> Self::IntoIter {
+            self.entries.into_iter()
+        }
+    }
+
+    impl<'s, E, R> IntoIterator for &'s RuleSet<E, R>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        type Item = &'s Entry<R>;
+
+        type IntoIter = slice::Iter<'s, Entry<R>>;
+
+        #[inline]
+        fn into_iter(self) -> Self::IntoIter {
+            self.iter()
+        }
+    }
+
+    impl<E, R, N> FromIterator<(N, R)> for RuleSet<E, R>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        N: Into<String>,
+    {
+        #[inline]
+        fn from_iter<I>(iter: I) -> Self
+        where
+            I: IntoIterator<Item = (N, R)>,
+        {
+            let mut rules = Self::new();
+            for (name, rule) in iter {
+                rules.insert(name, rule);
+            }
+            rules
+        }
+    }
+
+    /// Builds the [`DependencyGraph`] of `rules`, where a rule depends on another if the bottom
+    /// of the latter shares an element with the top of the former, using `eq` to compare
+    /// elements.
+    pub fn dependency_graph_by<E, R, F>(rules: &RuleSet<E, R>, mut eq: F) -> DependencyGraph
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        F: FnMut(&ExprRef<E>, &ExprRef<E>) -> bool,
+    {
+        let nodes = rules.ids().collect::<Vec<_>>();
+        let successors = rules
+            .iter()
+            .map(|source| {
+                let bot = source.rule.bot();
+                rules
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, target)| {
+                        let top = target.rule.top();
+                        bot.iter()
+                            .any(|b| top.iter().any(|t| eq(&b.cases(), &t.cases())))
+                    })
+                    .map(move |(i, _)| i)
+                    .collect()
+            })
+            .collect();
+        DependencyGraph { nodes, successors }
+    }
+
+    /// Builds the [`DependencyGraph`] of `rules`.
+    ///
+    /// See [`dependency_graph_by`] for more details.
+    #[inline]
+    pub fn dependency_graph<E, R>(rules: &RuleSet<E, R>) -> DependencyGraph
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        dependency_graph_by(rules, expr::eq_ref::<E>)
+    }
+
+    /// Returns `true` if some instantiation of the variables of `lhs` and `rhs` could make them
+    /// equal, treating every variable as a wildcard.
+    fn may_overlap<E, F>(lhs: &ExprRef<E>, rhs: &ExprRef<E>, is_variable: &mut F) -> bool
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+        F: FnMut(&E::Atom) -> bool,
+    {
+        match (lhs, rhs) {
+            (ExprRef::Atom(l), _) if is_variable(l) => true,
+            (_, ExprRef::Atom(r)) if is_variable(r) => true,
+            (ExprRef::Atom(l), ExprRef::Atom(r)) => l == r,
+            (ExprRef::Group(l), ExprRef::Group(r)) => {
+                l.iter().count() == r.iter().count()
+                    && l.iter()
+                        .zip(r.iter())
+                        .all(|(l, r)| may_overlap(&l.cases(), &r.cases(), is_variable))
+            }
+            _ => false,
+        }
+    }
+
+    /// Partitions `rules` into strata such that every rule only depends positively on rules
+    /// from the same or earlier strata and negatively on rules from earlier strata.
+    ///
+    /// A rule depends positively on every rule whose bottom may produce an element of its top
+    /// or of its [premises](Rule::premises), and negatively on every rule whose bottom may
+    /// produce an element matching one of its [absence patterns](Rule::absences). If the
+    /// rules are not stratifiable, returns the rules which lie on, or depend on, a cycle
+    /// through a negative dependency.
+    pub fn stratify<E, R, F>(
+        rules: &RuleSet<E, R>,
+        mut is_variable: F,
+    ) -> Result<Vec<Vec<RuleId>>, Vec<RuleId>>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        F: FnMut(&E::Atom) -> bool,
+    {
+        let mut edges = Vec::new();
+        for (source, producer) in rules.iter().enumerate() {
+            let bot = producer.rule.bot();
+            for (target, consumer) in rules.iter().enumerate() {
+                let mut produces = |items: &mut dyn Iterator<Item = ExprRef<E>>| {
+                    items.any(|item| {
+                        bot.iter()
+                            .any(|b| may_overlap(&b.cases(), &item, &mut is_variable))
+                    })
+                };
+                let top = consumer.rule.top();
+                let negative = produces(&mut consumer.rule.absences().iter().map(E::cases));
+                let positive = negative
+                    || produces(
+                        &mut top
+                            .iter()
+                            .map(move |e| e.cases())
+                            .chain(consumer.rule.premises().iter().map(E::cases)),
+                    );
+                if positive {
+                    edges.push((source, target, negative));
+                }
+            }
+        }
+        let len = rules.len();
+        let mut strata = vec![0; len];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (source, target, negative) in &edges {
+                let required = strata[*source] + usize::from(*negative);
+                if strata[*target] < required {
+                    strata[*target] = required;
+                    changed = true;
+                    if required > len {
+                        return Err(rules
+                            .ids()
+                            .zip(&strata)
+                            .filter(move |(_, s)| **s > len)
+                            .map(move |(id, _)| id)
+                            .collect());
+                    }
+                }
+            }
+        }
+        let mut layers = vec![Vec::new(); strata.iter().max().map_or(0, move |m| m + 1)];
+        for (id, stratum) in rules.ids().zip(strata) {
+            layers[stratum].push(id);
+        }
+        layers.retain(move |layer| !layer.is_empty());
+        Ok(layers)
+    }
+
+    /// Rule Dependency Graph
+    ///
+    /// The graph has an edge from a rule to every rule whose top can cancel against its bottom
+    /// when the two are [composed](pair_compose).
+    #[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct DependencyGraph {
+        /// Rule identifiers in increasing order
+        nodes: Vec<RuleId>,
+
+        /// Adjacency lists indexed by node position
+        successors: Vec<Vec<usize>>,
+    }
+
+    impl DependencyGraph {
+        /// Returns the number of rules in the graph.
+        #[inline]
+        pub fn len(&self) -> usize {
+            self.nodes.len()
+        }
+
+        /// Returns `true` if the graph has no rules.
+        #[inline]
+        pub fn is_empty(&self) -> bool {
+            self.nodes.is_empty()
+        }
+
+        /// Returns the rule identifiers of the graph in increasing order.
+        #[inline]
+        pub fn nodes(&self) -> &[RuleId] {
+            &self.nodes
+        }
+
+        #[inline]
+        fn position(&self, id: RuleId) -> Option<usize> {
+            self.nodes.binary_search(&id).ok()
+        }
+
+        /// Returns an iterator over the rules which can consume the output of the rule `id`.
+        #[inline]
+        pub fn successors(&self, id: RuleId) -> impl '_ + Iterator<Item = RuleId> {
+            self.position(id)
+                .into_iter()
+                .flat_map(move |i| self.successors[i].iter().map(move |j| self.nodes[*j]))
+        }
+
+        /// Returns an iterator over the rules whose output can be consumed by the rule `id`.
+        #[inline]
+        pub fn predecessors(&self, id: RuleId) -> impl '_ + Iterator<Item = RuleId> {
+            let target = self.position(id);
+            self.successors
+                .iter()
+                .enumerate()
+                .filter(move |(_, s)| target.map_or(false, |t| s.contains(&t)))
+                .map(move |(i, _)| self.nodes[i])
+        }
+
+        /// Returns an iterator over the edges `(source, target)` of the graph.
+        #[inline]
+        pub fn edges(&self) -> impl '_ + Iterator<Item = (RuleId, RuleId)> {
+            self.successors
+                .iter()
+                .enumerate()
+                .flat_map(move |(i, s)| s.iter().map(move |j| (self.nodes[i], self.nodes[*j])))
+        }
+
+        /// Returns `true` if `source` has an edge to `target`.
+        #[inline]
+        pub fn has_edge(&self, source: RuleId, target: RuleId) -> bool {
+            match (self.position(source), self.position(target)) {
+                (Some(s), Some(t)) => self.successors[s].contains(&t),
+                _ => false,
+            }
+        }
+
+        /// Returns `true` if the graph has a cycle.
+        #[inline]
+        pub fn has_cycle(&self) -> bool {
+            self.find_cycle().is_some()
+        }
+
+        /// Returns the rules along some cycle of the graph if one exists.
+        pub fn find_cycle(&self) -> Option<Vec<RuleId>> {
+            const UNVISITED: u8 = 0;
+            const ACTIVE: u8 = 1;
+            const DONE: u8 = 2;
+            let mut state = vec![UNVISITED; self.len()];
+            let mut path = Vec::new();
+            for root in 0..self.len() {
+                if state[root] != UNVISITED {
+                    continue;
+                }
+                let mut stack = vec![(root, 0)];
+                state[root] = ACTIVE;
+                path.push(root);
+                while let Some((node, next)) = stack.last_mut() {
+                    match self.successors[*node].get(*next) {
+                        Some(&successor) => {
+                            *next += 1;
+                            match state[successor] {
+                                UNVISITED => {
+                                    state[successor] = ACTIVE;
+                                    path.push(successor);
+                                    stack.push((successor, 0));
+                                }
+                                ACTIVE => {
+                                    let start = path.iter().position(|n| *n == successor)?;
+                                    return Some(
+                                        path[start..].iter().map(|n| self.nodes[*n]).collect(),
+                                    );
+                                }
+                                _ => {}
+                            }
+                        }
+                        _ => {
+                            state[*node] = DONE;
+                            path.pop();
+                            stack.pop();
+                        }
+                    }
+                }
+            }
+            None
+        }
+
+        /// Partitions the rules into layers such that every rule only depends on rules from
+        /// earlier layers.
+        ///
+        /// If the graph has cycles, returns the rules which could not be placed in any layer.
+        pub fn layers(&self) -> Result<Vec<Vec<RuleId>>, Vec<RuleId>> {
+            let mut in_degree = vec![0usize; self.len()];
+            for successors in &self.successors {
+                for j in successors {
+                    in_degree[*j] += 1;
+                }
+            }
+            let mut layers = Vec::new();
+            let mut current = (0..self.len())
+                .filter(|i| in_degree[*i] == 0)
+                .collect::<Vec<_>>();
+            let mut placed = 0;
+            while !current.is_empty() {
+                placed += current.len();
+                let mut next = Vec::new();
+                for i in &current {
+                    for j in &self.successors[*i] {
+                        in_degree[*j] -= 1;
+                        if in_degree[*j] == 0 {
+                            next.push(*j);
+                        }
+                    }
+                }
+                next.sort_unstable();
+                layers.push(current.into_iter().map(|i| self.nodes[i]).collect());
+                current = next;
+            }
+            if placed == self.len() {
+                Ok(layers)
+            } else {
+                Err((0..self.len())
+                    .filter(|i| in_degree[*i] != 0)
+                    .map(|i| self.nodes[i])
+                    .collect())
+            }
+        }
+    }
+
+    /// Builds the [`Hypergraph`] of `rules`, using `eq` to identify equal items.
+    pub fn hypergraph_by<E, R, F>(rules: &RuleSet<E, R>, mut eq: F) -> Hypergraph<E>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        F: FnMut(&ExprRef<E>, &ExprRef<E>) -> bool,
+    {
+        let mut items = Vec::<E>::new();
+        let mut edges = Vec::with_capacity(rules.len());
+        for entry in rules.iter() {
+            let (top, bot) = entry.rule.ref_pair();
+            let mut side = |group: &GroupRef<E>| {
+                let mut counts = Vec::<(usize, usize)>::new();
+                for item in group.iter() {
+                    let item = item.cases();
+                    let index = match items.iter().position(|i| eq(&i.cases(), &item)) {
+                        Some(index) => index,
+                        _ => {
+                            items.push(item.to_owned());
+                            items.len() - 1
+                        }
+                    };
+                    match counts.iter_mut().find(|(i, _)| *i == index) {
+                        Some((_, count)) => *count += 1,
+                        _ => counts.push((index, 1)),
+                    }
+                }
+                counts.sort_unstable();
+                counts
+            };
+            let sources = side(&top);
+            let targets = side(&bot);
+            edges.push(HyperEdge {
+                rule: entry.id,
+                sources,
+                targets,
+            });
+        }
+        Hypergraph { items, edges }
+    }
+
+    /// Builds the [`Hypergraph`] of `rules`.
+    ///
+    /// See [`hypergraph_by`] for more details.
+    #[inline]
+    pub fn hypergraph<E, R>(rules: &RuleSet<E, R>) -> Hypergraph<E>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        hypergraph_by(rules, expr::eq_ref::<E>)
+    }
+
+    /// Rule Hyperedge
+    ///
+    /// Items are referred to by their index in the [`Hypergraph`] and paired with their
+    /// multiplicity.
+    #[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct HyperEdge {
+        /// Rule Identifier
+        pub rule: RuleId,
+
+        /// Items consumed by the rule in increasing order
+        pub sources: Vec<(usize, usize)>,
+
+        /// Items produced by the rule in increasing order
+        pub targets: Vec<(usize, usize)>,
+    }
+
+    /// Rule Hypergraph
+    ///
+    /// The distinct elements of the rules are the nodes of the hypergraph and every rule is a
+    /// hyperedge from its top to its bottom. Elements are compared syntactically, so variables
+    /// are treated like any other atom.
+    #[derive(Clone, Debug)]
+    pub struct Hypergraph<E>
+    where
+        E: Expression,
+    {
+        /// Distinct items in order of first appearance
+        items: Vec<E>,
+
+        /// Hyperedges in increasing order of rule identifier
+        edges: Vec<HyperEdge>,
+    }
+
+    impl<E> Hypergraph<E>
+    where
+        E: Expression,
+    {
+        /// Returns the items of the hypergraph.
+        #[inline]
+        pub fn items(&self) -> &[E] {
+            &self.items
+        }
+
+        /// Returns the hyperedges of the hypergraph.
+        #[inline]
+        pub fn edges(&self) -> &[HyperEdge] {
+            &self.edges
+        }
+
+        /// Returns the index of `item` in the hypergraph.
+        #[inline]
+        pub fn find(&self, item: &ExprRef<E>) -> Option<usize>
+        where
+            E::Atom: PartialEq,
+        {
+            self.items
+                .iter()
+                .position(move |i| expr::eq_ref::<E>(&i.cases(), item))
+        }
+
+        /// Returns `true` if every source of `edge` is marked in `reached`.
+        #[inline]
+        fn enabled(edge: &HyperEdge, reached: &[bool]) -> bool {
+            edge.sources.iter().all(move |(i, _)| reached[*i])
+        }
+
+        /// Returns the items which can appear in some state reachable from the `initial` items.
+        ///
+        /// Multiplicities and consumption are ignored, so the result over-approximates the
+        /// items of the reachable states.
+        pub fn reachable<I>(&self, initial: I) -> Vec<usize>
+        where
+            I: IntoIterator<Item = usize>,
+        {
+            let mut reached = vec![false; self.items.len()];
+            for i in initial {
+                if let Some(r) = reached.get_mut(i) {
+                    *r = true;
+                }
+            }
+            let mut fired = vec![false; self.edges.len()];
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for (edge, fired) in self.edges.iter().zip(fired.iter_mut()) {
+                    if *fired || !Self::enabled(edge, &reached) {
+                        continue;
+                    }
+                    *fired = true;
+                    changed = true;
+                    for (i, _) in &edge.targets {
+                        reached[*i] = true;
+                    }
+                }
+            }
+            (0..reached.len()).filter(|i| reached[*i]).collect()
+        }
+
+        /// Returns the rules which can never fire from the `initial` items.
+        ///
+        /// See [`reachable`](Self::reachable) for the approximation used.
+        pub fn dead_rules<I>(&self, initial: I) -> Vec<RuleId>
+        where
+            I: IntoIterator<Item = usize>,
+        {
+            let mut reached = vec![false; self.items.len()];
+            for i in self.reachable(initial) {
+                reached[i] = true;
+            }
+            self.edges
+                .iter()
+                .filter(|edge| !Self::enabled(edge, &reached))
+                .map(|edge| edge.rule)
+                .collect()
+        }
+
+        /// Returns the strongly connected components of the item graph, which has an edge from
+        /// every source to every target of each hyperedge.
+        ///
+        /// Components are returned in reverse topological order.
+        pub fn strongly_connected_components(&self) -> Vec<Vec<usize>> {
+            let len = self.items.len();
+            let mut successors = vec![Vec::new(); len];
+            for edge in &self.edges {
+                for (s, _) in &edge.sources {
+                    successors[*s].extend(edge.targets.iter().map(|(t, _)| *t));
+                }
+            }
+            for s in &mut successors {
+                s.sort_unstable();
+                s.dedup();
+            }
+            let mut index = vec![usize::MAX; len];
+            let mut low = vec![0; len];
+            let mut on_stack = vec![false; len];
+            let mut stack = Vec::new();
+            let mut components = Vec::new();
+            let mut counter = 0;
+            for root in 0..len {
+                if index[root] != usize::MAX {
+                    continue;
+                }
+                let mut work = vec![(root, 0)];
+                index[root] = counter;
+                low[root] = counter;
+                counter += 1;
+                stack.push(root);
+                on_stack[root] = true;
+                while let Some((node, next)) = work.last_mut() {
+                    let node = *node;
+                    match successors[node].get(*next) {
+                        Some(&successor) => {
+                            *next += 1;
+                            if index[successor] == usize::MAX {
+                                index[successor] = counter;
+                                low[successor] = counter;
+                                counter += 1;
+                                stack.push(successor);
+                                on_stack[successor] = true;
+                                work.push((successor, 0));
+                            } else if on_stack[successor] {
+                                low[node] = low[node].min(index[successor]);
+                            }
+                        }
+                        _ => {
+                            work.pop();
+                            if let Some((parent, _)) = work.last() {
+                                low[*parent] = low[*parent].min(low[node]);
+                            }
+                            if low[node] == index[node] {
+                                let mut component = Vec::new();
+                                while let Some(item) = stack.pop() {
+                                    on_stack[item] = false;
+                                    component.push(item);
+                                    if item == node {
+                                        break;
+                                    }
+                                }
+                                component.sort_unstable();
+                                components.push(component);
+                            }
+                        }
+                    }
+                }
+            }
+            components
+        }
+    }
+
+    /// Returns `true` if `target` is equal, after [reduction](Rule::reduce), to the
+    /// [composition](compose) of a sequence of at most `depth` rules of `basis`.
+    fn derivable_within<E>(target: &Structure<E>, basis: &[Structure<E>], depth: usize) -> bool
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+        E::Group: Container<E>,
+        Structure<E>: Clone,
+    {
+        let mut layer = basis.to_vec();
+        for _ in 0..depth {
+            if layer
+                .iter()
+                .any(|rule| rule.clone().reduce().multiset_eq(target))
+            {
+                return true;
+            }
+            layer = layer
+                .iter()
+                .flat_map(|composed| {
+                    basis
+                        .iter()
+                        .map(move |rule| pair_compose(composed.clone(), rule.clone()))
+                })
+                .collect();
+        }
+        false
+    }
+
+    /// Checks whether every rule of `lhs` is derivable from `rhs` and every rule of `rhs` is
+    /// derivable from `lhs` by composing at most `depth` rules, returning the rules which are
+    /// not.
+    pub fn equivalent_up_to<E, L, R>(
+        lhs: &RuleSet<E, L>,
+        rhs: &RuleSet<E, R>,
+        depth: usize,
+    ) -> Equivalence
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+        E::Group: Container<E>,
+        L: Rule<E>,
+        R: Rule<E>,
+        Structure<E>: Clone,
+    {
+        let lhs_basis = lhs
+            .iter()
+            .map(|entry| Structure::<E>::from(entry.rule.cases()))
+            .collect::<Vec<_>>();
+        let rhs_basis = rhs
+            .iter()
+            .map(|entry| Structure::<E>::from(entry.rule.cases()))
+            .collect::<Vec<_>>();
+        let underivable = |ids: &[RuleId], rules: &[Structure<E>], basis: &[Structure<E>]| {
+            ids.iter()
+                .zip(rules)
+                .filter(|(_, rule)| !derivable_within(&rule.clone().reduce(), basis, depth))
+                .map(|(id, _)| *id)
+                .collect()
+        };
+        Equivalence {
+            lhs_underivable: underivable(&lhs.ids().collect::<Vec<_>>(), &lhs_basis, &rhs_basis),
+            rhs_underivable: underivable(&rhs.ids().collect::<Vec<_>>(), &rhs_basis, &lhs_basis),
+        }
+    }
+
+    /// [`equivalent_up_to`] Report
+    #[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct Equivalence {
+        /// Rules of the left set which are not derivable from the right set
+        pub lhs_underivable: Vec<RuleId>,
+
+        /// Rules of the right set which are not derivable from the left set
+        pub rhs_underivable: Vec<RuleId>,
+    }
+
+    impl Equivalence {
+        /// Returns `true` if every rule of each set is derivable from the other.
+        #[inline]
+        pub fn is_equivalent(&self) -> bool {
+            self.lhs_underivable.is_empty() && self.rhs_underivable.is_empty()
+        }
+    }
+
+    /// Rule Provider Trait
+    ///
+    /// A source of candidate rules for a state, implemented by [`RuleSet`] but also by lazy
+    /// sources which generate rules on demand.
+    pub trait RuleProvider<E>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+    {
+        /// Provided Rule Type
+        type Rule: Rule<E>;
+
+        /// Calls `f` on the candidate rules which may apply to `state`, in a deterministic
+        /// order, until `f` returns `true`. Returns `true` if `f` stopped the enumeration.
+        ///
+        /// Providers may over-approximate the candidates but must not omit any rule which
+        /// applies to `state`.
+        fn for_each_candidate<F>(&self, state: &[E], f: F) -> bool
+        where
+            F: FnMut(RuleId, &Self::Rule) -> bool;
+
+        /// Returns the rule with the given identifier if the provider knows it.
+        #[inline]
+        fn get_rule(&self, id: RuleId) -> Option<&Self::Rule> {
+            let _ = id;
+            None
+        }
+    }
+
+    impl<E, R> RuleProvider<E> for RuleSet<E, R>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        type Rule = R;
+
+        #[inline]
+        fn for_each_candidate<F>(&self, state: &[E], mut f: F) -> bool
+        where
+            F: FnMut(RuleId, &Self::Rule) -> bool,
+        {
+            let _ = state;
+            self.iter().any(move |entry| f(entry.id, &entry.rule))
+        }
+
+        #[inline]
+        fn get_rule(&self, id: RuleId) -> Option<&Self::Rule> {
+            self.rule(id)
+        }
+    }
+
+    impl<E, P> RuleProvider<E> for &P
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        P: RuleProvider<E> + ?Sized,
+    {
+        type Rule = P::Rule;
+
+        #[inline]
+        fn for_each_candidate<F>(&self, state: &[E], f: F) -> bool
+        where
+            F: FnMut(RuleId, &Self::Rule) -> bool,
+        {
+            (**self).for_each_candidate(state, f)
+        }
+
+        #[inline]
+        fn get_rule(&self, id: RuleId) -> Option<&Self::Rule> {
+            (**self).get_rule(id)
+        }
+    }
+
+    /// Rule Lint Kind
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub enum LintKind<A> {
+        /// The top and bottom of the rule are equal up to permutation.
+        Identity,
+
+        /// The rule is the exact reverse of the rule with the given identifier.
+        ReverseOf(RuleId),
+
+        /// The top of the rule is empty.
+        EmptyTop,
+
+        /// The bottom of the rule is empty.
+        EmptyBottom,
+
+        /// Some element appears in both the top and the bottom of the rule.
+        Unreduced,
+
+        /// The variable only appears in the top of the rule.
+        TopOnlyVariable(A),
+
+        /// The variable only appears in the bottom of the rule.
+        BottomOnlyVariable(A),
+    }
+
+    /// Rule Lint Diagnostic
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub struct Diagnostic<A> {
+        /// Identifier of the offending rule
+        pub rule: RuleId,
+
+        /// Kind of problem
+        pub kind: LintKind<A>,
+    }
+
+    /// Detects suspicious rules in `rules`, treating the atoms which satisfy `is_variable` as
+    /// variables.
+    pub fn lint<E, R, F>(rules: &RuleSet<E, R>, is_variable: F) -> Vec<Diagnostic<E::Atom>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        F: Fn(&E::Atom) -> bool,
+    {
+        let mut diagnostics = Vec::new();
+        for entry in rules {
+            let mut push = |kind| {
+                diagnostics.push(Diagnostic {
+                    rule: entry.id,
+                    kind,
+                })
+            };
+            let (top, bot) = entry.rule.ref_pair();
+            let top_empty = top.iter().next().is_none();
+            let bot_empty = bot.iter().next().is_none();
+            if top_empty {
+                push(LintKind::EmptyTop);
+            }
+            if bot_empty {
+                push(LintKind::EmptyBottom);
+            }
+            if !top_empty && multiset_eq(&top, &bot) {
+                push(LintKind::Identity);
+            } else if top.iter().any(|t| {
+                bot.iter()
+                    .any(|b| expr::eq_ref::<E>(&t.cases(), &b.cases()))
+            }) {
+                push(LintKind::Unreduced);
+            }
+            for other in rules.iter().take_while(|other| other.id < entry.id) {
+                let (other_top, other_bot) = other.rule.ref_pair();
+                if multiset_eq(&top, &other_bot) && multiset_eq(&bot, &other_top) {
+                    push(LintKind::ReverseOf(other.id));
+                }
+            }
+            let mut top_vars = Vec::new();
+            let mut bot_vars = Vec::new();
+            for e in top.iter() {
+                ordering::variables(&e.cases(), &mut top_vars, &is_variable);
+            }
+            for e in bot.iter() {
+                ordering::variables(&e.cases(), &mut bot_vars, &is_variable);
+            }
+            let mut reported = Vec::<&E::Atom>::new();
+            for var in &top_vars {
+                if !bot_vars.contains(var) && !reported.contains(&var) {
+                    reported.push(var);
+                    push(LintKind::TopOnlyVariable(var.clone()));
+                }
+            }
+            for var in &bot_vars {
+                if !top_vars.contains(var) && !reported.contains(&var) {
+                    reported.push(var);
+                    push(LintKind::BottomOnlyVariable(var.clone()));
+                }
+            }
+        }
+        diagnostics
+    }
+
+    /// Rule Change
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct Change {
+        /// Identifier of the changed rule
+        pub id: RuleId,
+
+        /// Set to `true` if the content of the rule changed
+        pub content: bool,
+
+        /// Set to `true` if the name of the rule changed
+        pub name: bool,
+
+        /// Set to `true` if the metadata of the rule changed
+        pub metadata: bool,
+    }
+
+    /// [`RuleSet`] Difference
+    #[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct Diff {
+        /// Identifiers, in the new set, of the added rules
+        pub added: Vec<RuleId>,
+
+        /// Identifiers, in the old set, of the removed rules
+        pub removed: Vec<RuleId>,
+
+        /// Rules present in both sets under the same identifier which changed
+        pub changed: Vec<Change>,
+
+        /// Pairs `(old, new)` of identifiers of rules with the same content which appear under
+        /// different identifiers
+        pub renumbered: Vec<(RuleId, RuleId)>,
+    }
+
+    impl Diff {
+        /// Returns `true` if the two rule sets are the same.
+        #[inline]
+        pub fn is_empty(&self) -> bool {
+            self.added.is_empty()
+                && self.removed.is_empty()
+                && self.changed.is_empty()
+                && self.renumbered.is_empty()
+        }
+    }
+
+    /// Computes the difference between `old` and `new`.
+    ///
+    /// Rules are first matched by identifier, and the remaining rules are matched by content,
+    /// comparing rules up to a permutation of their tops and bottoms.
+    pub fn diff<E, R1, R2>(old: &RuleSet<E, R1>, new: &RuleSet<E, R2>) -> Diff
+    where
+        E: Expression,
+        E::Atom: Hash + PartialEq,
+        E::Group: Container<E>,
+        R1: Rule<E>,
+        R2: Rule<E>,
+    {
+        let mut diff = Diff::default();
+        let mut unmatched_old = Vec::new();
+        for entry in old {
+            match new.get(entry.id) {
+                Some(other) => {
+                    let change = Change {
+                        id: entry.id,
+                        content: !entry.rule.multiset_eq(&other.rule),
+                        name: entry.name != other.name,
+                        metadata: entry.metadata != other.metadata,
+                    };
+                    if change.content || change.name || change.metadata {
+                        diff.changed.push(change);
+                    }
+                }
+                _ => unmatched_old.push((entry.id, entry.rule.content_hash())),
+            }
+        }
+        for entry in new.iter().filter(|e| !old.contains(e.id)) {
+            let hash = entry.rule.content_hash();
+            let matched = unmatched_old.iter().position(|(id, h)| {
+                *h == hash
+                    && old
+                        .rule(*id)
+                        .map_or(false, |rule| rule.multiset_eq(&entry.rule))
+            });
+            match matched {
+                Some(index) => {
+                    let (id, _) = unmatched_old.remove(index);
+                    diff.renumbered.push((id, entry.id));
+                }
+                _ => diff.added.push(entry.id),
+            }
+        }
+        diff.removed = unmatched_old.into_iter().map(|(id, _)| id).collect();
+        diff
+    }
+
+    /// [`RuleSet`] Query Builder
+    ///
+    /// Built by [`RuleSet::query`], a query filters the rules of a set without copying them.
+    pub struct Query<'r, E, R>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        /// Queried rule set
+        rules: &'r RuleSet<E, R>,
+
+        /// Glob pattern over rule names
+        name: Option<String>,
+
+        /// Required tags
+        tags: Vec<String>,
+
+        /// Maximum rule size
+        max_size: Option<usize>,
+
+        /// Atoms which must be mentioned by the rules
+        mentions: Vec<E::Atom>,
+
+        /// Custom predicates
+        predicates: Vec<Box<dyn 'r + Fn(&Entry<R>) -> bool>>,
+    }
+
+    impl<'r, E, R> Query<'r, E, R>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        /// Builds a new [`Query`] over `rules` which accepts every rule.
+        #[inline]
+        pub fn new(rules: &'r RuleSet<E, R>) -> Self {
+            Self {
+                rules,
+                name: None,
+                tags: Vec::new(),
+                max_size: None,
+                mentions: Vec::new(),
+                predicates: Vec::new(),
+            }
+        }
+
+        /// Only accepts the rules whose name matches the glob `pattern`, where `*` matches any
+        /// sequence of characters and `?` matches any single character.
+        #[inline]
+        pub fn name<S>(mut self, pattern: S) -> Self
+        where
+            S: Into<String>,
+        {
+            self.name = Some(pattern.into());
+            self
+        }
+
+        /// Only accepts the rules which have the given tag.
+        #[inline]
+        pub fn tag<S>(mut self, tag: S) -> Self
+        where
+            S: Into<String>,
+        {
+            self.tags.push(tag.into());
+            self
+        }
+
+        /// Only accepts the rules whose [size](rule_size) is at most `max_size`.
+        #[inline]
+        pub fn max_size(mut self, max_size: usize) -> Self {
+            self.max_size = Some(max_size);
+            self
+        }
+
+        /// Only accepts the rules which mention `atom`.
+        #[inline]
+        pub fn mentions(mut self, atom: E::Atom) -> Self {
+            self.mentions.push(atom);
+            self
+        }
+
+        /// Only accepts the rules whose metadata satisfies `predicate`.
+        #[inline]
+        pub fn metadata<F>(self, predicate: F) -> Self
+        where
+            F: 'r + Fn(&Metadata) -> bool,
+        {
+            self.filter(move |entry| predicate(&entry.metadata))
+        }
+
+        /// Only accepts the entries which satisfy `predicate`.
+        #[inline]
+        pub fn filter<F>(mut self, predicate: F) -> Self
+        where
+            F: 'r + Fn(&Entry<R>) -> bool,
+        {
+            self.predicates.push(Box::new(predicate));
+            self
+        }
+
+        /// Returns `true` if `entry` is accepted by the query.
+        pub fn accepts(&self, entry: &Entry<R>) -> bool {
+            if let Some(pattern) = &self.name {
+                if !util::glob_match(pattern, &entry.name) {
+                    return false;
+                }
+            }
+            if !self.tags.iter().all(|tag| entry.metadata.has_tag(tag)) {
+                return false;
+            }
+            if let Some(max_size) = self.max_size {
+                if rule_size(&entry.rule) > max_size {
+                    return false;
+                }
+            }
+            if !self.mentions.is_empty() {
+                let (top, bot) = entry.rule.ref_pair();
+                let mentions = |atom: &E::Atom| {
+                    top.iter()
+                        .chain(bot.iter())
+                        .any(|e| substitution::occurs::<E>(atom, &e.cases()))
+                };
+                if !self.mentions.iter().all(mentions) {
+                    return false;
+                }
+            }
+            self.predicates.iter().all(|predicate| predicate(entry))
+        }
+
+        /// Returns an iterator over the accepted entries.
+        #[inline]
+        pub fn iter<'q>(&'q self) -> impl 'q + Iterator<Item = &'r Entry<R>> {
+            self.rules.iter().filter(move |entry| self.accepts(entry))
+        }
+
+        /// Returns the identifiers of the accepted rules.
+        #[inline]
+        pub fn ids(&self) -> Vec<RuleId> {
+            self.iter().map(move |entry| entry.id).collect()
+        }
+
+        /// Counts the accepted rules.
+        #[inline]
+        pub fn count(&self) -> usize {
+            self.iter().count()
+        }
+    }
+
+    /// Returns the number of atoms and groups in the top and bottom of `rule`.
+    #[inline]
+    pub fn rule_size<E, R>(rule: &R) -> usize
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        let (top, bot) = rule.ref_pair();
+        top.iter()
+            .chain(bot.iter())
+            .map(move |e| ordering::size(&e.cases()))
+            .sum()
+    }
+
+    /// Increments the bucket of `value` in `histogram`.
+    #[inline]
+    fn record(histogram: &mut Vec<usize>, value: usize) {
+        if histogram.len() <= value {
+            histogram.resize(value + 1, 0);
+        }
+        histogram[value] += 1;
+    }
+
+    /// Adds the atoms of `expr` to the frequency table `atoms`.
+    fn count_atoms<E>(expr: &ExprRef<E>, atoms: &mut Vec<(E::Atom, usize)>)
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+    {
+        match expr {
+            ExprRef::Atom(atom) => match atoms.iter_mut().find(|(a, _)| a == *atom) {
+                Some((_, count)) => *count += 1,
+                _ => atoms.push(((*atom).clone(), 1)),
+            },
+            ExprRef::Group(group) => {
+                for e in group.iter() {
+                    count_atoms(&e.cases(), atoms);
+                }
+            }
+        }
+    }
+
+    /// [`RuleSet::statistics`] Report
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Statistics<A> {
+        /// Number of occurrences of each atom in decreasing order of frequency
+        pub atoms: Vec<(A, usize)>,
+
+        /// Number of rules of each [size](rule_size), indexed by size
+        pub sizes: Vec<usize>,
+
+        /// Number of rules of each maximal element [depth](ordering::depth), indexed by depth
+        pub depths: Vec<usize>,
+
+        /// Number of rules whose top is syntactically contained in the sample state
+        pub branching: usize,
+
+        /// Average number of elements produced per element consumed over all rules
+        pub growth: f64,
+
+        /// Pairs `(rule, count)` of the number of top elements of each rule which cancel against
+        /// the sample state
+        pub cancellation: Vec<(RuleId, usize)>,
+    }
+
+    impl<A> Statistics<A> {
+        /// Returns the most frequent atom if there is one.
+        #[inline]
+        pub fn most_frequent(&self) -> Option<&A> {
+            self.atoms.first().map(move |(a, _)| a)
+        }
+    }
+
+    /// Rule Index Module
+    pub mod index {
+        use {
+            super::*,
+            core::{hash::Hasher, mem},
+        };
+
+        /// Discrimination Tree Key
+        #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+        pub enum Key<A> {
+            /// Wildcard Key, produced by variables
+            Any,
+
+            /// Atom Key
+            Atom(A),
+
+            /// Group Key with the number of elements of the group
+            Group(usize),
+        }
+
+        impl<A> Key<A> {
+            /// Returns `true` if the key is a wildcard.
+            #[inline]
+            pub fn is_any(&self) -> bool {
+                matches!(self, Self::Any)
+            }
+        }
+
+        /// Appends the preorder key sequence of `expr` to `keys`, mapping the atoms which
+        /// satisfy `is_variable` to [`Key::Any`].
+        pub fn keys_of<E, F>(expr: &ExprRef<E>, keys: &mut Vec<Key<E::Atom>>, is_variable: &mut F)
+        where
+            E: Expression,
+            E::Atom: Clone,
+            F: FnMut(&E::Atom) -> bool,
+        {
+            match expr {
+                ExprRef::Atom(atom) if is_variable(*atom) => keys.push(Key::Any),
+                ExprRef::Atom(atom) => keys.push(Key::Atom((*atom).clone())),
+                ExprRef::Group(group) => {
+                    let mark = keys.len();
+                    keys.push(Key::Group(0));
+                    let mut len = 0;
+                    for e in group.iter() {
+                        keys_of(&e.cases(), keys, is_variable);
+                        len += 1;
+                    }
+                    keys[mark] = Key::Group(len);
+                }
+            }
+        }
+
+        /// Discrimination Tree Node
+        #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+        struct Node<A> {
+            /// Outgoing edges of the node
+            children: Vec<(Key<A>, usize)>,
+
+            /// Rule tops which end at this node as `(rule, top_index)` pairs
+            leaves: Vec<(RuleId, usize)>,
+        }
+
+        impl<A> Default for Node<A> {
+            #[inline]
+            fn default() -> Self {
+                Self {
+                    children: Vec::new(),
+                    leaves: Vec::new(),
+                }
+            }
+        }
+
+        /// Discrimination Tree over Rule Tops
+        ///
+        /// Every element of the top of every indexed rule is stored as a path of [`Key`]s so
+        /// that the rules which can possibly match an expression are found without scanning
+        /// the whole rule set. Queries over-approximate: non-linear variables are not checked.
+        #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+        pub struct DiscriminationTree<A> {
+            /// Tree nodes, the root being the first node
+            nodes: Vec<Node<A>>,
+
+            /// Pairs `(rule, top_length)` of the indexed rules sorted by identifier
+            rules: Vec<(RuleId, usize)>,
+        }
+
+        impl<A> DiscriminationTree<A>
+        where
+            A: Clone + PartialEq,
+        {
+            /// Builds a new empty [`DiscriminationTree`].
+            #[inline]
+            pub fn new() -> Self {
+                Self {
+                    nodes: vec![Node::default()],
+                    rules: Vec::new(),
+                }
+            }
+
+            /// Builds a new [`DiscriminationTree`] indexing every rule of `rules`.
+            #[inline]
+            pub fn from_rule_set<E, R, F>(rules: &RuleSet<E, R>, mut is_variable: F) -> Self
+            where
+                E: Expression<Atom = A>,
+                E::Group: Container<E>,
+                R: Rule<E>,
+                F: FnMut(&A) -> bool,
+            {
+                let mut tree = Self::new();
+                for entry in rules {
+                    tree.insert(entry.id, &entry.rule, &mut is_variable);
+                }
+                tree
+            }
+
+            /// Returns the number of indexed rules.
+            #[inline]
+            pub fn len(&self) -> usize {
+                self.rules.len()
+            }
+
+            /// Returns `true` if no rules are indexed.
+            #[inline]
+            pub fn is_empty(&self) -> bool {
+                self.rules.is_empty()
+            }
+
+            /// Returns `true` if the rule `id` is indexed.
+            #[inline]
+            pub fn contains(&self, id: RuleId) -> bool {
+                self.rules
+                    .binary_search_by_key(&id, move |(i, _)| *i)
+                    .is_ok()
+            }
+
+            /// Indexes the top of `rule` under the identifier `id`, replacing any rule which was
+            /// previously indexed under the same identifier.
+            pub fn insert<E, R, F>(&mut self, id: RuleId, rule: &R, mut is_variable: F)
+            where
+                E: Expression<Atom = A>,
+                E::Group: Container<E>,
+                R: Rule<E>,
+                F: FnMut(&A) -> bool,
+            {
+                self.remove(id);
+                let top = rule.top();
+                let mut keys = Vec::new();
+                let mut length = 0;
+                for (index, e) in top.iter().enumerate() {
+                    keys.clear();
+                    keys_of(&e.cases(), &mut keys, &mut is_variable);
+                    let node = self.path(&keys);
+                    self.nodes[node].leaves.push((id, index));
+                    length += 1;
+                }
+                let position = self
+                    .rules
+                    .binary_search_by_key(&id, move |(i, _)| *i)
+                    .unwrap_or_else(move |p| p);
+                self.rules.insert(position, (id, length));
+            }
+
+            /// Returns the node at the end of the path `keys`, creating it if necessary.
+            fn path(&mut self, keys: &[Key<A>]) -> usize {
+                let mut node = 0;
+                for key in keys {
+                    node = match self.nodes[node].children.iter().find(|(k, _)| k == key) {
+                        Some((_, child)) => *child,
+                        _ => {
+                            let child = self.nodes.len();
+                            self.nodes.push(Node::default());
+                            self.nodes[node].children.push((key.clone(), child));
+                            child
+                        }
+                    };
+                }
+                node
+            }
+
+            /// Removes the rule `id` from the index, returning `true` if it was indexed.
+            pub fn remove(&mut self, id: RuleId) -> bool {
+                match self.rules.binary_search_by_key(&id, move |(i, _)| *i) {
+                    Ok(position) => {
+                        self.rules.remove(position);
+                        for node in &mut self.nodes {
+                            node.leaves.retain(move |(i, _)| *i != id);
+                        }
+                        true
+                    }
+                    _ => false,
+                }
+            }
+
+            /// Returns the `(rule, top_index)` pairs of the top elements which can possibly
+            /// match `expr`.
+            pub fn matches_ref<E>(&self, expr: &ExprRef<E>) -> Vec<(RuleId, usize)>
+            where
+                E: Expression<Atom = A>,
+            {
+                let mut keys = Vec::new();
+                keys_of(expr, &mut keys, &mut move |_: &A| false);
+                let mut skips = vec![0; keys.len()];
+                for i in (0..keys.len()).rev() {
+                    skips[i] = match keys[i] {
+                        Key::Group(len) => {
+                            let mut next = i + 1;
+                            for _ in 0..len {
+                                next = skips[next];
+                            }
+                            next
+                        }
+                        _ => i + 1,
+                    };
+                }
+                let mut matches = Vec::new();
+                let mut stack = vec![(0, 0)];
+                while let Some((node, position)) = stack.pop() {
+                    if position == keys.len() {
+                        matches.extend_from_slice(&self.nodes[node].leaves);
+                        continue;
+                    }
+                    for (key, child) in &self.nodes[node].children {
+                        if key.is_any() {
+                            stack.push((*child, skips[position]));
+                        } else if *key == keys[position] {
+                            stack.push((*child, position + 1));
+                        }
+                    }
+                }
+                matches.sort_unstable();
+                matches
+            }
+
+            /// Returns the `(rule, top_index)` pairs of the top elements which can possibly
+            /// match `expr`.
+            #[inline]
+            pub fn matches<E>(&self, expr: &E) -> Vec<(RuleId, usize)>
+            where
+                E: Expression<Atom = A>,
+            {
+                self.matches_ref(&expr.cases())
+            }
+
+            /// Returns the identifiers, in increasing order, of the rules such that every
+            /// element of their top can possibly match some element of `state`.
+            pub fn candidates<E>(&self, state: &[E]) -> Vec<RuleId>
+            where
+                E: Expression<Atom = A>,
+            {
+                let mut hits = state
+                    .iter()
+                    .flat_map(move |e| self.matches(e))
+                    .collect::<Vec<_>>();
+                hits.sort_unstable();
+                hits.dedup();
+                self.rules
+                    .iter()
+                    .filter(move |(id, length)| {
+                        let start = hits.partition_point(move |(i, _)| i < id);
+                        let end = hits.partition_point(move |(i, _)| i <= id);
+                        end - start == *length
+                    })
+                    .map(move |(id, _)| *id)
+                    .collect()
+            }
+        }
+
+        impl<A> Default for DiscriminationTree<A>
+        where
+            A: Clone + PartialEq,
+        {
+            #[inline]
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        /// Atom Fingerprint Type
+        ///
+        /// A set of atoms hashed into the bits of a word. If the fingerprint of a pattern is not
+        /// contained in the fingerprint of an expression, the pattern cannot match it.
+        pub type Fingerprint = u64;
+
+        /// Computes the [`Fingerprint`] of the atoms of `expr` which do not satisfy
+        /// `is_variable`.
+        pub fn fingerprint_ref<E, F>(expr: &ExprRef<E>, is_variable: &mut F) -> Fingerprint
+        where
+            E: Expression,
+            E::Atom: Hash,
+            F: FnMut(&E::Atom) -> bool,
+        {
+            match expr {
+                ExprRef::Atom(atom) if is_variable(*atom) => 0,
+                ExprRef::Atom(atom) => {
+                    let mut hasher = util::Fnv1a::new();
+                    atom.hash(&mut hasher);
+                    1 << (hasher.finish() % Fingerprint::BITS as u64)
+                }
+                ExprRef::Group(group) => group.iter().fold(0, move |acc, e| {
+                    acc | fingerprint_ref(&e.cases(), is_variable)
+                }),
+            }
+        }
+
+        /// Computes the [`Fingerprint`] of every element of `state`.
+        #[inline]
+        pub fn state_fingerprint<E>(state: &[E]) -> Fingerprint
+        where
+            E: Expression,
+            E::Atom: Hash,
+        {
+            state.iter().fold(0, move |acc, e| {
+                acc | fingerprint_ref(&e.cases(), &mut move |_: &E::Atom| false)
+            })
+        }
+
+        /// Compiled Rule Matcher
+        ///
+        /// Built by [`RuleSet::compile_matcher`], the matcher precomputes the fingerprint of
+        /// every rule and orders the elements of each top from the most to the least specific
+        /// so that matching fails as early as possible. Candidate rules are found with a
+        /// [`DiscriminationTree`].
+        pub struct Matcher<'r, E, R>
+        where
+            E: Expression,
+            E::Group: Container<E>,
+            R: Rule<E>,
+        {
+            /// Compiled rule set
+            rules: &'r RuleSet<E, R>,
+
+            /// Index over the rule tops
+            index: DiscriminationTree<E::Atom>,
+
+            /// Fingerprints of the rule tops in the order of the rule set
+            fingerprints: Vec<Fingerprint>,
+
+            /// Matching order of the elements of each rule top
+            orders: Vec<Vec<usize>>,
+
+            /// Variable predicate
+            is_variable: fn(&E::Atom) -> bool,
+        }
+
+        impl<'r, E, R> Matcher<'r, E, R>
+        where
+            E: Expression,
+            E::Atom: Clone + Hash + PartialEq,
+            E::Group: Container<E>,
+            R: Rule<E>,
+        {
+            /// Compiles a [`Matcher`] for `rules`.
+            pub fn new(rules: &'r RuleSet<E, R>, is_variable: fn(&E::Atom) -> bool) -> Self {
+                let mut fingerprints = Vec::with_capacity(rules.len());
+                let mut orders = Vec::with_capacity(rules.len());
+                let mut keys = Vec::new();
+                for entry in rules {
+                    let mut fingerprint = 0;
+                    let mut specificity = Vec::new();
+                    for (i, e) in entry.rule.top().iter().enumerate() {
+                        let cases = e.cases();
+                        fingerprint |= fingerprint_ref(&cases, &mut { is_variable });
+                        keys.clear();
+                        keys_of(&cases, &mut keys, &mut { is_variable });
+                        specificity.push((keys.iter().filter(|k| !k.is_any()).count(), i));
+                    }
+                    specificity.sort_unstable_by(move |l, r| r.cmp(l));
+                    fingerprints.push(fingerprint);
+                    orders.push(specificity.into_iter().map(move |(_, i)| i).collect());
+                }
+                Self {
+                    index: DiscriminationTree::from_rule_set(rules, is_variable),
+                    rules,
+                    fingerprints,
+                    orders,
+                    is_variable,
+                }
+            }
+
+            /// Returns the entries of the rules which can possibly be applied to `state`.
+            pub fn candidates(&self, state: &[E]) -> impl '_ + Iterator<Item = &'r Entry<R>> {
+                let fingerprint = state_fingerprint(state);
+                let rules = self.rules;
+                let fingerprints = &self.fingerprints;
+                self.index
+                    .candidates(state)
+                    .into_iter()
+                    .filter_map(move |id| {
+                        let position = rules.entries.binary_search_by_key(&id, |e| e.id).ok()?;
+                        (fingerprints[position] & !fingerprint == 0)
+                            .then(|| &rules.entries[position])
+                    })
+            }
+
+            /// Tries to apply the rule at `position` in the rule set to `state`.
+            fn apply_entry(&self, position: usize, state: &[E]) -> Option<Vec<E>> {
+                let rule = &self.rules.entries[position].rule;
+                let top = rule.top();
+                let mut top = top.iter().map(move |e| Some(e.cases())).collect::<Vec<_>>();
+                let patterns = self.orders[position]
+                    .iter()
+                    .filter_map(|i| top[*i].take())
+                    .collect::<Vec<_>>();
+                let targets = state.iter().map(move |e| e.cases()).collect::<Vec<_>>();
+                let mut found = None;
+                substitution::match_multiset(
+                    &patterns,
+                    &targets,
+                    &mut Vec::new(),
+                    self.is_variable,
+                    |bindings, chosen| {
+                        if !rule.allows(bindings) {
+                            return false;
+                        }
+                        let substitution = bindings
+                            .iter()
+                            .map(move |t| t.as_ref().to_owned())
+                            .collect::<substitution::Structure<E>>();
+                        let premises_hold = rule.premises_hold(|premise| {
+                            let premise = substitution.apply_ref(premise);
+                            state
+                                .iter()
+                                .any(|e| expr::eq_ref::<E>(&premise.cases(), &e.cases()))
+                        });
+                        if premises_hold {
+                            found = Some((substitution, chosen.to_vec()));
+                        }
+                        premises_hold
+                    },
+                );
+                let (substitution, mut chosen) = found?;
+                chosen.sort_unstable();
+                let mut next = state
+                    .iter()
+                    .enumerate()
+                    .filter(move |(i, _)| chosen.binary_search(i).is_err())
+                    .map(move |(_, e)| E::clone(e))
+                    .collect::<Vec<_>>();
+                next.extend(substitution.apply_group_ref(&rule.bot()));
+                Some(next)
+            }
+
+            /// Applies the first candidate rule which matches `state`, returning its identifier
+            /// and the next state.
+            pub fn apply(&self, state: &[E]) -> Option<(RuleId, Vec<E>)> {
+                self.candidates(state).find_map(move |entry| {
+                    let position = self.rules.position(entry.id).ok()?;
+                    Some((entry.id, self.apply_entry(position, state)?))
+                })
+            }
+
+            /// Applies every candidate rule which matches `state`, returning the identifiers of
+            /// the rules and the next states.
+            pub fn apply_all<'s>(
+                &'s self,
+                state: &'s [E],
+            ) -> impl 's + Iterator<Item = (RuleId, Vec<E>)> {
+                self.candidates(state).filter_map(move |entry| {
+                    let position = self.rules.position(entry.id).ok()?;
+                    Some((entry.id, self.apply_entry(position, state)?))
+                })
+            }
+        }
+
+        impl<'r, E, R> RuleProvider<E> for Matcher<'r, E, R>
+        where
+            E: Expression,
+            E::Atom: Clone + Hash + PartialEq,
+            E::Group: Container<E>,
+            R: Rule<E>,
+        {
+            type Rule = R;
+
+            #[inline]
+            fn for_each_candidate<F>(&self, state: &[E], mut f: F) -> bool
+            where
+                F: FnMut(RuleId, &Self::Rule) -> bool,
+            {
+                self.candidates(state)
+                    .any(move |entry| f(entry.id, &entry.rule))
+            }
+
+            #[inline]
+            fn get_rule(&self, id: RuleId) -> Option<&Self::Rule> {
+                self.rules.rule(id)
+            }
+        }
+
+        /// Indexed Rule Set
+        ///
+        /// Keeps a [`RuleSet`] and its [`DiscriminationTree`] in sync by routing every mutation
+        /// through the index.
+        pub struct IndexedRuleSet<E, R = Structure<E>>
+        where
+            E: Expression,
+            E::Group: Container<E>,
+            R: Rule<E>,
+        {
+            /// Underlying rule set
+            rules: RuleSet<E, R>,
+
+            /// Index over the rule tops
+            index: DiscriminationTree<E::Atom>,
+
+            /// Variable predicate used to build the index keys
+            is_variable: fn(&E::Atom) -> bool,
+        }
+
+        impl<E, R> IndexedRuleSet<E, R>
+        where
+            E: Expression,
+            E::Atom: Clone + PartialEq,
+            E::Group: Container<E>,
+            R: Rule<E>,
+        {
+            /// Builds a new [`IndexedRuleSet`] indexing every rule of `rules`.
+            #[inline]
+            pub fn new(rules: RuleSet<E, R>, is_variable: fn(&E::Atom) -> bool) -> Self {
+                Self {
+                    index: DiscriminationTree::from_rule_set(&rules, is_variable),
+                    rules,
+                    is_variable,
+                }
+            }
+
+            /// Returns a reference to the underlying rule set.
+            #[inline]
+            pub fn rules(&self) -> &RuleSet<E, R> {
+                &self.rules
+            }
+
+            /// Returns a reference to the index.
+            #[inline]
+            pub fn index(&self) -> &DiscriminationTree<E::Atom> {
+                &self.index
+            }
+
+            /// Returns the variable predicate used to build the index.
+            #[inline]
+            pub fn is_variable(&self) -> fn(&E::Atom) -> bool {
+                self.is_variable
+            }
+
+            /// Inserts a new rule into the set and the index.
+            ///
+            /// # Panics
+            ///
+            /// Panics if the set has run out of identifiers, see
+            /// [`RuleSet::insert_with_metadata`].
+            #[inline]
+            pub fn insert<N>(&mut self, name: N, rule: R) -> RuleId
+            where
+                N: Into<String>,
+            {
+                match self.insert_with_metadata(name, rule, Metadata::default()) {
+                    Ok(id) => id,
+                    Err(error) => panic!("{}", error),
+                }
+            }
+
+            /// Inserts a new rule with the given metadata into the set and the index,
+            /// returning the entry back if the set has run out of identifiers.
+            #[inline]
+            pub fn insert_with_metadata<N>(
+                &mut self,
+                name: N,
+                rule: R,
+                metadata: Metadata,
+            ) -> Result<RuleId, InsertError<R>>
+            where
+                N: Into<String>,
+            {
+                let id = self.rules.insert_with_metadata(name, rule, metadata)?;
+                if let Some(rule) = self.rules.rule(id) {
+                    self.index.insert(id, rule, self.is_variable);
+                }
+                Ok(id)
+            }
+
+            /// Removes a rule from the set and the index.
+            #[inline]
+            pub fn remove(&mut self, id: RuleId) -> Option<Entry<R>> {
+                self.index.remove(id);
+                self.rules.remove(id)
+            }
+
+            /// Replaces the rule `id`, returning the old rule if it exists.
+            #[inline]
+            pub fn replace(&mut self, id: RuleId, rule: R) -> Option<R> {
+                let entry = self.rules.get_mut(id)?;
+                let old = mem::replace(&mut entry.rule, rule);
+                self.index.insert(id, &entry.rule, self.is_variable);
+                Some(old)
+            }
+
+            /// Retains only the rules specified by the predicate.
+            #[inline]
+            pub fn retain<F>(&mut self, f: F)
+            where
+                F: FnMut(&Entry<R>) -> bool,
+            {
+                self.rules.retain(f);
+                let rules = &self.rules;
+                let removed = self
+                    .index
+                    .rules
+                    .iter()
+                    .map(move |(id, _)| *id)
+                    .filter(move |id| !rules.contains(*id))
+                    .collect::<Vec<_>>();
+                for id in removed {
+                    self.index.remove(id);
+                }
+            }
+
+            /// Returns the rules which can possibly be applied to `state`.
+            #[inline]
+            pub fn candidates<'s>(
+                &'s self,
+                state: &[E],
+            ) -> impl 's + Iterator<Item = &'s Entry<R>> {
+                self.index
+                    .candidates(state)
+                    .into_iter()
+                    .filter_map(move |id| self.rules.get(id))
+            }
+
+            /// Extracts the underlying rule set.
+            #[inline]
+            pub fn into_rule_set(self) -> RuleSet<E, R> {
+                self.rules
+            }
+        }
+
+        impl<E, R> RuleProvider<E> for IndexedRuleSet<E, R>
+        where
+            E: Expression,
+            E::Atom: Clone + PartialEq,
+            E::Group: Container<E>,
+            R: Rule<E>,
+        {
+            type Rule = R;
+
+            #[inline]
+            fn for_each_candidate<F>(&self, state: &[E], mut f: F) -> bool
+            where
+                F: FnMut(RuleId, &Self::Rule) -> bool,
+            {
+                self.candidates(state)
+                    .any(move |entry| f(entry.id, &entry.rule))
+            }
+
+            #[inline]
+            fn get_rule(&self, id: RuleId) -> Option<&Self::Rule> {
+                self.rules.rule(id)
+            }
+        }
+    }
+
+    /// Rule Database Format
+    #[cfg(feature = "serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+    pub mod database {
+        use {
+            super::*,
+            serde::{
+                de::Error as _, ser::Error as _, Deserialize, Deserializer, Serialize, Serializer,
+            },
+        };
+
+        /// Current Database Format Version
+        pub const FORMAT_VERSION: u32 = 1;
+
+        /// Expression Record Type
+        ///
+        /// Encodes an [`Expression`] independently of how (or whether) the expression type itself
+        /// implements serialization.
+        #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+        pub enum ExprRecord<A> {
+            /// Atomic Expression
+            Atom(A),
+
+            /// Grouped Expression
+            Group(Vec<ExprRecord<A>>),
+        }
+
+        impl<A> ExprRecord<A> {
+            /// Builds a new [`ExprRecord`] from an expression reference.
+            pub fn from_ref<E>(expr: &ExprRef<E>) -> Self
+            where
+                E: Expression<Atom = A>,
+                A: Clone,
+            {
+                match expr {
+                    ExprRef::Atom(atom) => Self::Atom((*atom).clone()),
+                    ExprRef::Group(group) => Self::Group(
+                        group
+                            .iter()
+                            .map(move |e| Self::from_ref(&e.cases()))
+                            .collect(),
+                    ),
+                }
+            }
+
+            /// Builds a new [`ExprRecord`] from an expression.
+            #[inline]
+            pub fn from_expr<E>(expr: &E) -> Self
+            where
+                E: Expression<Atom = A>,
+                A: Clone,
+            {
+                Self::from_ref(&expr.cases())
+            }
+
+            /// Converts the record back into an expression.
+            pub fn into_expr<E>(self) -> E
+            where
+                E: Expression<Atom = A>,
+                E::Group: FromIterator<E>,
+            {
+                match self {
+                    Self::Atom(atom) => E::from_atom(atom),
+                    Self::Group(group) => {
+                        E::from_group(group.into_iter().map(Self::into_expr::<E>).collect())
+                    }
+                }
+            }
+        }
+
+        /// Converts a group reference into a list of expression records.
+        #[inline]
+        pub fn to_records<E>(group: &GroupRef<E>) -> Vec<ExprRecord<E::Atom>>
+        where
+            E: Expression,
+            E::Atom: Clone,
+        {
+            group
+                .iter()
+                .map(move |e| ExprRecord::from_ref(&e.cases()))
+                .collect()
+        }
+
+        /// Converts a list of expression records into a group.
+        #[inline]
+        pub fn from_records<E>(records: Vec<ExprRecord<E::Atom>>) -> E::Group
+        where
+            E: Expression,
+            E::Group: FromIterator<E>,
+        {
+            records
+                .into_iter()
+                .map(ExprRecord::into_expr::<E>)
+                .collect()
+        }
+
+        /// Rule Record Type
+        #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+        pub struct RuleRecord<A> {
+            /// Rule Identifier
+            #[serde(default)]
+            pub id: RuleId,
+
+            /// Rule Name
+            pub name: String,
+
+            /// Top element of the rule
+            pub top: Vec<ExprRecord<A>>,
+
+            /// Bottom element of the rule
+            pub bot: Vec<ExprRecord<A>>,
+
+            /// Premises of the rule
+            #[serde(default, skip_serializing_if = "Vec::is_empty")]
+            pub premises: Vec<ExprRecord<A>>,
+
+            /// Rule Metadata
+            #[serde(default)]
+            pub metadata: Metadata,
+        }
+
+        /// Database Error Type
+        #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+        pub enum DatabaseError {
+            /// The format version is newer than [`FORMAT_VERSION`].
+            UnsupportedVersion(u32),
+
+            /// A migration did not advance the format version.
+            StalledMigration(u32),
+
+            /// Two records share the same identifier.
+            DuplicateId(RuleId),
+
+            /// A record with premises was loaded into rules which cannot carry them.
+            Conditional(RuleId),
+
+            /// A record has the largest identifier, which leaves no identifier for new rules.
+            IdOverflow(RuleId),
+
+            /// A rule has side conditions or a weight, which the format cannot store.
+            Unsupported(RuleId),
+        }
+
+        impl fmt::Display for DatabaseError {
+            #[inline]
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self {
+                    Self::UnsupportedVersion(version) | Self::StalledMigration(version) => {
+                        write!(f, "{} ({})", self.description(), version)
+                    }
+                    Self::DuplicateId(id)
+                    | Self::Conditional(id)
+                    | Self::IdOverflow(id)
+                    | Self::Unsupported(id) => {
+                        write!(f, "{} ({})", self.description(), id)
+                    }
+                }
+            }
+        }
+
+        #[cfg(feature = "std")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+        impl std::error::Error for DatabaseError {}
+
+        impl DatabaseError {
+            #[inline]
+            fn description(&self) -> &'static str {
+                match self {
+                    Self::UnsupportedVersion(_) => "unsupported database format version",
+                    Self::StalledMigration(_) => "database migration did not advance the version",
+                    Self::DuplicateId(_) => "duplicate rule identifier in database",
+                    Self::Conditional(_) => "rule with premises in database",
+                    Self::IdOverflow(_) => "rule identifier too large in database",
+                    Self::Unsupported(_) => {
+                        "rule with absences, a guard or a weight cannot be stored"
+                    }
+                }
+            }
+        }
+
+        /// Rule Database Type
+        ///
+        /// Serialization data model for a [`RuleSet`]. The [`version`](Self::version) field
+        /// records the format the database was written with so that older databases can be
+        /// upgraded with [`migrate`](Self::migrate) before they are loaded.
+        ///
+        /// A database stores the sides, the [premises](Rule::premises) and the metadata of the
+        /// rules. Rules with [absences](Rule::absences), a [guard](Rule::is_guarded) or a
+        /// [weight](Rule::weight) cannot be stored.
+        #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+        pub struct Database<A> {
+            /// Format Version
+            pub version: u32,
+
+            /// Rule Records
+            #[serde(default)]
+            pub rules: Vec<RuleRecord<A>>,
+        }
+
+        impl<A> Database<A> {
+            /// Builds a new empty [`Database`] at the current [`FORMAT_VERSION`].
+            #[inline]
+            pub fn new() -> Self {
+                Self {
+                    version: FORMAT_VERSION,
+                    rules: Vec::new(),
+                }
+            }
+
+            /// Builds a new [`Database`] from a [`RuleSet`], failing on the first rule which
+            /// cannot be stored.
+            pub fn from_rule_set<E, R>(rules: &RuleSet<E, R>) -> Result<Self, DatabaseError>
+            where
+                E: Expression<Atom = A>,
+                E::Group: Container<E>,
+                R: Rule<E>,
+                A: Clone,
+            {
+                Ok(Self {
+                    version: FORMAT_VERSION,
+                    rules: rules
+                        .iter()
+                        .map(move |entry| {
+                            let rule = &entry.rule;
+                            if !rule.absences().is_empty()
+                                || rule.is_guarded()
+                                || rule.weight().is_some()
+                            {
+                                return Err(DatabaseError::Unsupported(entry.id));
+                            }
+                            Ok(RuleRecord {
+                                id: entry.id,
+                                name: entry.name.clone(),
+                                top: to_records::<E>(&rule.top()),
+                                bot: to_records::<E>(&rule.bot()),
+                                premises: rule
+                                    .premises()
+                                    .iter()
+                                    .map(ExprRecord::from_expr)
+                                    .collect(),
+                                metadata: entry.metadata.clone(),
+                            })
+                        })
+                        .collect::<Result<_, _>>()?,
+                })
+            }
+
+            /// Upgrades the database to [`FORMAT_VERSION`] by repeatedly calling `hook` on older
+            /// versions. Each call must return a database with a strictly larger version.
+            pub fn migrate<F>(mut self, mut hook: F) -> Result<Self, DatabaseError>
+            where
+                F: FnMut(Self) -> Result<Self, DatabaseError>,
+            {
+                while self.version < FORMAT_VERSION {
+                    let version = self.version;
+                    self = hook(self)?;
+                    if self.version <= version {
+                        return Err(DatabaseError::StalledMigration(version));
+                    }
+                }
+                if self.version > FORMAT_VERSION {
+                    Err(DatabaseError::UnsupportedVersion(self.version))
+                } else {
+                    Ok(self)
+                }
+            }
+
+            /// Upgrades the database to [`FORMAT_VERSION`] using the built-in migrations.
+            #[inline]
+            pub fn upgrade(self) -> Result<Self, DatabaseError> {
+                self.migrate(migrate_builtin)
+            }
+
+            /// Upgrades the database and converts it into a [`RuleSet`], building every rule
+            /// with `build` from its record.
+            fn load<E, R, F>(self, mut build: F) -> Result<RuleSet<E, R>, DatabaseError>
+            where
+                E: Expression<Atom = A>,
+                E::Group: Container<E>,
+                R: Rule<E>,
+                F: FnMut(RuleRecord<A>) -> Result<R, DatabaseError>,
+            {
+                let mut rules = RuleSet::new();
+                for record in self.upgrade()?.rules {
+                    let id = record.id;
+                    let name = record.name.clone();
+                    let metadata = record.metadata.clone();
+                    rules
+                        .insert_entry(Entry::new(id, name, build(record)?, metadata))
+                        .map_err(move |error| match error {
+                            InsertError::DuplicateId(_) => DatabaseError::DuplicateId(id),
+                            InsertError::IdOverflow(_) => DatabaseError::IdOverflow(id),
+                        })?;
+                }
+                Ok(rules)
+            }
+
+            /// Upgrades the database and converts it into a [`RuleSet`], failing on records with
+            /// premises, see [`into_conditional_rule_set`](Self::into_conditional_rule_set).
+            pub fn into_rule_set<E, R>(self) -> Result<RuleSet<E, R>, DatabaseError>
+            where
+                E: Expression<Atom = A>,
+                E::Group: Container<E>,
+                R: Rule<E>,
+            {
+                self.load(move |record| {
+                    if !record.premises.is_empty() {
+                        return Err(DatabaseError::Conditional(record.id));
+                    }
+                    Ok(R::new(
+                        from_records::<E>(record.top),
+                        from_records::<E>(record.bot),
+                    ))
+                })
+            }
+
+            /// Upgrades the database and converts it into a [`RuleSet`] of [`Conditional`] rules
+            /// carrying the premises of the records.
+            pub fn into_conditional_rule_set<E, R>(
+                self,
+            ) -> Result<RuleSet<E, Conditional<E, R>>, DatabaseError>
+            where
+                E: Expression<Atom = A>,
+                E::Group: Container<E>,
+                R: Rule<E>,
+            {
+                self.load(move |record| {
+                    Ok(Conditional::new(
+                        R::new(from_records::<E>(record.top), from_records::<E>(record.bot)),
+                        record
+                            .premises
+                            .into_iter()
+                            .map(ExprRecord::into_expr)
+                            .collect(),
+                    ))
+                })
+            }
+        }
+
+        impl<A> Default for Database<A> {
+            #[inline]
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        /// Applies the built-in migration for the version of `database`.
+        ///
+        /// Version `0` databases predate stable rule identifiers, so identifiers are assigned in
+        /// record order.
+        pub fn migrate_builtin<A>(mut database: Database<A>) -> Result<Database<A>, DatabaseError> {
+            match database.version {
+                0 => {
+                    for (id, record) in database.rules.iter_mut().enumerate() {
+                        record.id = id;
+                    }
+                    database.version = 1;
+                    Ok(database)
+                }
+                version => Err(DatabaseError::UnsupportedVersion(version)),
+            }
+        }
+
+        impl<E, R> Serialize for RuleSet<E, R>
+        where
+            E: Expression,
+            E::Atom: Clone + Serialize,
+            E::Group: Container<E>,
+            R: Rule<E>,
+        {
+            #[inline]
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                Database::from_rule_set(self)
+                    .map_err(move |err| S::Error::custom(err.description()))?
+                    .serialize(serializer)
+            }
+        }
+
+        impl<'de, E, R> Deserialize<'de> for RuleSet<E, R>
+        where
+            E: Expression,
+            E::Atom: Deserialize<'de>,
+            E::Group: Container<E>,
+            R: Rule<E>,
+        {
+            #[inline]
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                Database::deserialize(deserializer)?
+                    .into_rule_set()
+                    .map_err(move |err| D::Error::custom(err.description()))
+            }
+        }
+    }
+
+    /// Plain-Text Rule Format
+    ///
+    /// Every rule is written on its own line as `name: (top items) / (bot items)` where each
+    /// item is either an atom or a parenthesized group of items. Atoms are maximal runs of
+    /// characters other than whitespace and parentheses. Empty lines and lines starting with `#`
+    /// are ignored.
+    pub mod text {
+        use {
+            super::*,
+            core::fmt::{self, Display, Write},
+        };
+
+        #[cfg(feature = "parse")]
+        use core::str::FromStr;
+
+        /// Parse Error Kind
+        #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+        pub enum ParseErrorKind {
+            /// The rule has an empty name.
+            MissingName,
+
+            /// The name of the rule is not followed by `:`.
+            MissingColon,
+
+            /// A group was expected.
+            ExpectedGroup,
+
+            /// A group was not closed.
+            UnclosedGroup,
+
+            /// A closing parenthesis does not match any opening parenthesis.
+            UnexpectedClose,
+
+            /// The top and bottom groups are not separated by `/`.
+            MissingDivider,
+
+            /// There is extra input after the end of the rule or expression.
+            TrailingInput,
+        }
+
+        impl fmt::Display for ParseErrorKind {
+            #[inline]
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(match self {
+                    Self::MissingName => "rule has an empty name",
+                    Self::MissingColon => "rule name is not followed by `:`",
+                    Self::ExpectedGroup => "expected a group",
+                    Self::UnclosedGroup => "unclosed group",
+                    Self::UnexpectedClose => "unexpected closing parenthesis",
+                    Self::MissingDivider => "top and bottom groups are not separated by `/`",
+                    Self::TrailingInput => "unexpected input after the end",
+                })
+            }
+        }
+
+        /// Parse Error Type
+        #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+        pub struct ParseError {
+            /// Line of the error (starting at `1`)
+            pub line: usize,
+
+            /// Byte column of the error (starting at `1`)
+            pub column: usize,
+
+            /// Error Kind
+            pub kind: ParseErrorKind,
+        }
+
+        impl fmt::Display for ParseError {
+            #[inline]
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}:{}: {}", self.line, self.column, self.kind)
+            }
+        }
+
+        #[cfg(feature = "std")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+        impl std::error::Error for ParseError {}
+
+        impl ParseError {
+            /// Builds a new [`ParseError`].
+            #[inline]
+            pub fn new(line: usize, column: usize, kind: ParseErrorKind) -> Self {
+                Self { line, column, kind }
+            }
+
+            /// Builds a new [`ParseError`] from a byte offset into `source`.
+            #[inline]
+            pub fn at(source: &str, offset: usize, kind: ParseErrorKind) -> Self {
+                let (line, column) = location(source, offset);
+                Self::new(line, column, kind)
+            }
+        }
+
+        /// Returns the line and byte column (starting at `1`) of a byte offset into `source`.
+        #[inline]
+        fn location(source: &str, offset: usize) -> (usize, usize) {
+            let before = &source[..offset];
+            let line_start = before.rfind('\n').map_or(0, move |i| i + 1);
+            (before.matches('\n').count() + 1, offset - line_start + 1)
+        }
+
+        /// Parsing Cursor
+        struct Cursor<'s> {
+            /// Source Text
+            source: &'s str,
+
+            /// Byte offset into the source text
+            position: usize,
+        }
+
+        impl<'s> Cursor<'s> {
+            #[inline]
+            fn new(source: &'s str, position: usize) -> Self {
+                Self { source, position }
+            }
+
+            #[inline]
+            fn rest(&self) -> &'s str {
+                &self.source[self.position..]
+            }
+
+            #[inline]
+            fn peek(&self) -> Option<char> {
+                self.rest().chars().next()
+            }
+
+            #[inline]
+            fn bump(&mut self) {
+                if let Some(c) = self.peek() {
+                    self.position += c.len_utf8();
+                }
+            }
+
+            #[inline]
+            fn skip_whitespace(&mut self) {
+                let rest = self.rest();
+                self.position += rest.len() - rest.trim_start().len();
+            }
+
+            #[inline]
+            fn error(&self, kind: ParseErrorKind) -> (usize, ParseErrorKind) {
+                (self.position, kind)
+            }
+        }
+
+        /// Returns `true` if `c` terminates an atom.
+        #[inline]
+        fn is_delimiter(c: char) -> bool {
+            c.is_whitespace() || c == '(' || c == ')'
+        }
+
+        fn parse_atom<'s>(cursor: &mut Cursor<'s>) -> &'s str {
+            let rest = cursor.rest();
+            let end = rest.find(is_delimiter).unwrap_or(rest.len());
+            cursor.position += end;
+            &rest[..end]
+        }
+
+        fn parse_group<E, F>(
+            cursor: &mut Cursor,
+            atom: &mut F,
+        ) -> Result<E::Group, (usize, ParseErrorKind)>
+        where
+            E: Expression,
+            E::Group: FromIterator<E>,
+            F: FnMut(&str) -> E::Atom,
+        {
+            cursor.skip_whitespace();
+            if cursor.peek() != Some('(') {
+                return Err(cursor.error(ParseErrorKind::ExpectedGroup));
+            }
+            cursor.bump();
+            let mut items = Vec::new();
+            loop {
+                cursor.skip_whitespace();
+                match cursor.peek() {
+                    Some(')') => {
+                        cursor.bump();
+                        return Ok(items.into_iter().collect());
+                    }
+                    Some('(') => items.push(E::from_group(parse_group::<E, F>(cursor, atom)?)),
+                    Some(_) => items.push(E::from_atom(atom(parse_atom(cursor)))),
+                    _ => return Err(cursor.error(ParseErrorKind::UnclosedGroup)),
+                }
+            }
+        }
+
+        fn parse_expr_inner<E, F>(
+            cursor: &mut Cursor,
+            atom: &mut F,
+        ) -> Result<E, (usize, ParseErrorKind)>
+        where
+            E: Expression,
+            E::Group: FromIterator<E>,
+            F: FnMut(&str) -> E::Atom,
+        {
+            cursor.skip_whitespace();
+            match cursor.peek() {
+                Some('(') => Ok(E::from_group(parse_group::<E, F>(cursor, atom)?)),
+                Some(')') => Err(cursor.error(ParseErrorKind::UnexpectedClose)),
+                Some(_) => Ok(E::from_atom(atom(parse_atom(cursor)))),
+                _ => Err(cursor.error(ParseErrorKind::ExpectedGroup)),
+            }
+        }
+
+        fn parse_line<'s, E, R, F>(
+            line: &'s str,
+            atom: &mut F,
+        ) -> Result<(&'s str, R), (usize, ParseErrorKind)>
+        where
+            E: Expression,
+            E::Group: Container<E>,
+            R: Rule<E>,
+            F: FnMut(&str) -> E::Atom,
+        {
+            let head_end = line.find('(').unwrap_or_else(|| line.len());
+            let colon = line[..head_end]
+                .find(':')
+                .ok_or((head_end, ParseErrorKind::MissingColon))?;
+            let name = line[..colon].trim();
+            if name.is_empty() {
+                return Err((colon, ParseErrorKind::MissingName));
+            }
+            let mut cursor = Cursor::new(line, colon + 1);
+            let top = parse_group::<E, F>(&mut cursor, atom)?;
+            cursor.skip_whitespace();
+            if cursor.peek() != Some('/') {
+                return Err(cursor.error(ParseErrorKind::MissingDivider));
+            }
+            cursor.bump();
+            let bot = parse_group::<E, F>(&mut cursor, atom)?;
+            cursor.skip_whitespace();
+            match cursor.peek() {
+                None | Some('#') => Ok((name, R::new(top, bot))),
+                Some(')') => Err(cursor.error(ParseErrorKind::UnexpectedClose)),
+                _ => Err(cursor.error(ParseErrorKind::TrailingInput)),
+            }
+        }
+
+        /// Parses a single expression using `atom` to build atoms from their text.
+        pub fn parse_expr_with<E, F>(source: &str, mut atom: F) -> Result<E, ParseError>
+        where
+            E: Expression,
+            E::Group: FromIterator<E>,
+            F: FnMut(&str) -> E::Atom,
+        {
+            let mut cursor = Cursor::new(source, 0);
+            let expr = parse_expr_inner::<E, F>(&mut cursor, &mut atom)
+                .map_err(move |(offset, kind)| ParseError::at(source, offset, kind))?;
+            cursor.skip_whitespace();
+            match cursor.peek() {
+                None => Ok(expr),
+                Some(')') => Err(ParseError::at(
+                    source,
+                    cursor.position,
+                    ParseErrorKind::UnexpectedClose,
+                )),
+                _ => Err(ParseError::at(
+                    source,
+                    cursor.position,
+                    ParseErrorKind::TrailingInput,
+                )),
+            }
+        }
+
+        /// Parses a single expression.
+        #[inline]
+        pub fn parse_expr<E>(source: &str) -> Result<E, ParseError>
+        where
+            E: Expression,
+            E::Group: FromIterator<E>,
+            for<'a> E::Atom: From<&'a str>,
+        {
+            parse_expr_with(source, move |s| E::Atom::from(s))
+        }
+
+        /// Parses a rule file using `atom` to build atoms from their text.
+        pub fn parse_with<E, R, F>(source: &str, mut atom: F) -> Result<RuleSet<E, R>, ParseError>
+        where
+            E: Expression,
+            E::Group: Container<E>,
+            R: Rule<E>,
+            F: FnMut(&str) -> E::Atom,
+        {
+            let mut rules = RuleSet::new();
+            for (index, line) in source.lines().enumerate() {
+                let trimmed = line.trim_start();
+                if trimmed.is_empty() || trimmed.starts_with('#') {
+                    continue;
+                }
+                let (name, rule) = parse_line::<E, R, F>(line, &mut atom)
+                    .map_err(move |(offset, kind)| ParseError::new(index + 1, offset + 1, kind))?;
+                rules.insert(name, rule);
+            }
+            Ok(rules)
+        }
+
+        /// Parses a rule file.
+        #[inline]
+        pub fn parse<E, R>(source: &str) -> Result<RuleSet<E, R>, ParseError>
+        where
+            E: Expression,
+            E::Group: Container<E>,
+            R: Rule<E>,
+            for<'a> E::Atom: From<&'a str>,
+        {
+            parse_with(source, move |s| E::Atom::from(s))
+        }
+
+        /// Ratio Parse Error Type
+        #[cfg(feature = "parse")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "parse")))]
+        #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+        pub enum RatioParseError {
+            /// The text is not a valid expression.
+            Syntax(ParseError),
+
+            /// The expression does not have the [`Rule`] shape.
+            Shape {
+                /// Line of the offending expression (starting at `1`)
+                line: usize,
+
+                /// Byte column of the offending expression (starting at `1`)
+                column: usize,
+
+                /// Shape Error
+                error: ShapeError,
+            },
+        }
+
+        #[cfg(feature = "parse")]
+        impl fmt::Display for RatioParseError {
+            #[inline]
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self {
+                    Self::Syntax(error) => fmt::Display::fmt(error, f),
+                    Self::Shape {
+                        line,
+                        column,
+                        error,
+                    } => write!(f, "{}:{}: {}", line, column, error),
+                }
+            }
+        }
+
+        #[cfg(all(feature = "parse", feature = "std"))]
+        #[cfg_attr(docsrs, doc(cfg(all(feature = "parse", feature = "std"))))]
+        impl std::error::Error for RatioParseError {}
+
+        /// Moves `cursor` past the expression it points to.
+        #[cfg(feature = "parse")]
+        fn skip_expr(cursor: &mut Cursor) -> Option<()> {
+            match cursor.peek()? {
+                '(' => {
+                    let mut depth = 0usize;
+                    loop {
+                        match cursor.peek()? {
+                            '(' => depth += 1,
+                            ')' => {
+                                depth -= 1;
+                                if depth == 0 {
+                                    cursor.bump();
+                                    return Some(());
+                                }
+                            }
+                            _ => {}
+                        }
+                        cursor.bump();
+                    }
+                }
+                ')' => None,
+                _ => {
+                    parse_atom(cursor);
+                    Some(())
+                }
+            }
+        }
+
+        /// Returns the byte offset of the element `index` of the group starting `source`.
+        #[cfg(feature = "parse")]
+        fn item_offset(source: &str, index: usize) -> Option<usize> {
+            let mut cursor = Cursor::new(source, 0);
+            cursor.skip_whitespace();
+            if cursor.peek() != Some('(') {
+                return None;
+            }
+            cursor.bump();
+            for _ in 0..index {
+                cursor.skip_whitespace();
+                skip_expr(&mut cursor)?;
+            }
+            cursor.skip_whitespace();
+            Some(cursor.position)
+        }
+
+        /// Parses a rule written as the s-expression `((top items) (bot items))` using `atom`
+        /// to build atoms from their text.
+        ///
+        /// Shape errors are located at the offending element of the rule, if any.
+        #[cfg(feature = "parse")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "parse")))]
+        pub fn parse_ratio_with<E, F>(
+            source: &str,
+            atom: F,
+        ) -> Result<Structure<E>, RatioParseError>
+        where
+            E: Expression,
+            E::Group: Container<E>,
+            F: FnMut(&str) -> E::Atom,
+        {
+            let expr = parse_expr_with::<E, F>(source, atom).map_err(RatioParseError::Syntax)?;
+            Structure::try_from(Expression::into(expr)).map_err(move |error| {
+                let position = match error {
+                    ShapeError::MissingTopGroup(offending)
+                    | ShapeError::MissingBotGroup(offending)
+                    | ShapeError::MissingTopBotGroups(offending, _) => Some(offending.position),
+                    _ => None,
+                };
+                let offset = position
+                    .and_then(move |position| item_offset(source, position))
+                    .unwrap_or_else(move || source.len() - source.trim_start().len());
+                let (line, column) = location(source, offset);
+                RatioParseError::Shape {
+                    line,
+                    column,
+                    error,
+                }
+            })
+        }
+
+        /// Parses a rule written as the s-expression `((top items) (bot items))`.
+        #[cfg(feature = "parse")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "parse")))]
+        #[inline]
+        pub fn parse_ratio<E>(source: &str) -> Result<Structure<E>, RatioParseError>
+        where
+            E: Expression,
+            E::Group: Container<E>,
+            for<'a> E::Atom: From<&'a str>,
+        {
+            parse_ratio_with(source, move |s| E::Atom::from(s))
+        }
+
+        #[cfg(feature = "parse")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "parse")))]
+        impl<E> FromStr for Structure<E>
+        where
+            E: Expression,
+            E::Group: Container<E>,
+            for<'a> E::Atom: From<&'a str>,
+        {
+            type Err = RatioParseError;
+
+            #[inline]
+            fn from_str(source: &str) -> Result<Self, Self::Err> {
+                parse_ratio(source)
+            }
+        }
+
+        /// Writes an expression using `atom` to write atoms.
+        pub fn write_expr_with<E, W, F>(expr: &ExprRef<E>, out: &mut W, atom: &mut F) -> fmt::Result
+        where
+            E: Expression,
+            W: Write,
+            F: FnMut(&E::Atom, &mut W) -> fmt::Result,
+        {
+            match expr {
+                ExprRef::Atom(a) => atom(*a, out),
+                ExprRef::Group(group) => write_group_with::<E, W, F>(group, out, atom),
+            }
+        }
+
+        /// Writes a group using `atom` to write atoms.
+        pub fn write_group_with<E, W, F>(
+            group: &GroupRef<E>,
+            out: &mut W,
+            atom: &mut F,
+        ) -> fmt::Result
+        where
+            E: Expression,
+            W: Write,
+            F: FnMut(&E::Atom, &mut W) -> fmt::Result,
+        {
+            out.write_char('(')?;
+            for (i, item) in group.iter().enumerate() {
+                if i > 0 {
+                    out.write_char(' ')?;
+                }
+                write_expr_with::<E, W, F>(&item.cases(), out, atom)?;
+            }
+            out.write_char(')')
+        }
+
+        /// Writes a rule set in the plain-text format using `atom` to write atoms.
+        ///
+        /// The output can be read back with [`parse_with`] as long as rule names do not contain
+        /// `:` or `(` and the text of every atom is a valid atom.
+        pub fn write_with<E, R, W, F>(
+            rules: &RuleSet<E, R>,
+            out: &mut W,
+            mut atom: F,
+        ) -> fmt::Result
+        where
+            E: Expression,
+            E::Group: Container<E>,
+            R: Rule<E>,
+            W: Write,
+            F: FnMut(&E::Atom, &mut W) -> fmt::Result,
+        {
+            for entry in rules {
+                write!(out, "{}: ", entry.name)?;
+                write_group_with::<E, W, F>(&entry.rule.top(), out, &mut atom)?;
+                out.write_str(" / ")?;
+                write_group_with::<E, W, F>(&entry.rule.bot(), out, &mut atom)?;
+                out.write_char('\n')?;
+            }
+            Ok(())
+        }
+
+        /// Writes a rule set in the plain-text format.
+        #[inline]
+        pub fn write<E, R, W>(rules: &RuleSet<E, R>, out: &mut W) -> fmt::Result
+        where
+            E: Expression,
+            E::Atom: Display,
+            E::Group: Container<E>,
+            R: Rule<E>,
+            W: Write,
+        {
+            write_with(rules, out, move |a, out| write!(out, "{}", a))
+        }
+
+        #[cfg(all(test, feature = "simple"))]
+        mod tests {
+            use {super::*, crate::expr::SimpleExpr, alloc::string::String};
+
+            type E = SimpleExpr;
+
+            fn atom(atom: &str) -> E {
+                E::atom(atom)
+            }
+
+            fn group<const N: usize>(items: [E; N]) -> E {
+                E::group(IntoIterator::into_iter(items))
+            }
+
+            fn written(rules: &RuleSet<E>) -> String {
+                let mut out = String::new();
+                write(rules, &mut out).unwrap();
+                out
+            }
+
+            fn rule_error(source: &str) -> ParseError {
+                parse::<E, Structure<E>>(source).unwrap_err()
+            }
+
+            #[test]
+            fn round_trip() {
+                let source =
+                    "# rules\n\nfirst: (a (f ?x)) / (b)\n  second :( )/((g ?x) c) # note\n";
+                let rules = parse::<E, Structure<E>>(source).unwrap();
+                assert_eq!(rules.len(), 2);
+                assert_eq!(rules.iter().next().unwrap().name, "first");
+                assert_eq!(
+                    rules.iter().nth(1).unwrap().rule,
+                    Structure::new(vec![], vec![group([atom("g"), atom("?x")]), atom("c")])
+                );
+                let text = written(&rules);
+                assert_eq!(text, "first: (a (f ?x)) / (b)\nsecond: () / ((g ?x) c)\n");
+                let reparsed = parse::<E, Structure<E>>(&text).unwrap();
+                assert!(rules.iter().eq(reparsed.iter()));
+                assert_eq!(written(&reparsed), text);
+            }
+
+            #[test]
+            fn expression_round_trip() {
+                let expr = parse_expr::<E>(" (a (b c) () d) ").unwrap();
+                assert_eq!(
+                    expr,
+                    group([
+                        atom("a"),
+                        group([atom("b"), atom("c")]),
+                        group([]),
+                        atom("d")
+                    ])
+                );
+                let mut out = String::new();
+                write_expr_with::<E, _, _>(&expr.cases(), &mut out, &mut |a, out| out.write_str(a))
+                    .unwrap();
+                assert_eq!(out, "(a (b c) () d)");
+                assert_eq!(parse_expr::<E>(&out).unwrap(), expr);
+            }
+
+            #[test]
+            fn rule_error_positions() {
+                assert_eq!(
+                    rule_error("ok: (a) / (b)\nbad (a) / (b)"),
+                    ParseError::new(2, 5, ParseErrorKind::MissingColon)
+                );
+                assert_eq!(
+                    rule_error(": (a) / (b)"),
+                    ParseError::new(1, 1, ParseErrorKind::MissingName)
+                );
+                assert_eq!(
+                    rule_error("r: a / (b)"),
+                    ParseError::new(1, 4, ParseErrorKind::ExpectedGroup)
+                );
+                assert_eq!(
+                    rule_error("r: (a) (b)"),
+                    ParseError::new(1, 8, ParseErrorKind::MissingDivider)
+                );
+                assert_eq!(
+                    rule_error("r: (a (b) / (c)"),
+                    ParseError::new(1, 16, ParseErrorKind::UnclosedGroup)
+                );
+                assert_eq!(
+                    rule_error("r: (a) / (b))"),
+                    ParseError::new(1, 13, ParseErrorKind::UnexpectedClose)
+                );
+                assert_eq!(
+                    rule_error("# header\nr: (a) / (b) c"),
+                    ParseError::new(2, 14, ParseErrorKind::TrailingInput)
+                );
+            }
+
+            #[test]
+            fn expression_error_positions() {
+                assert_eq!(
+                    parse_expr::<E>("(a b) c").unwrap_err(),
+                    ParseError::new(1, 7, ParseErrorKind::TrailingInput)
+                );
+                assert_eq!(
+                    parse_expr::<E>("(a\n  (b c)").unwrap_err(),
+                    ParseError::new(2, 8, ParseErrorKind::UnclosedGroup)
+                );
+                assert_eq!(
+                    parse_expr::<E>("  )").unwrap_err(),
+                    ParseError::new(1, 3, ParseErrorKind::UnexpectedClose)
+                );
+                assert_eq!(
+                    parse_expr::<E>("").unwrap_err(),
+                    ParseError::new(1, 1, ParseErrorKind::ExpectedGroup)
+                );
+            }
+        }
+    }
+}
+
+/// Substitution Module
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod substitution {
+    use {
+        super::*,
+        alloc::{vec, vec::Vec},
+        bitvec::vec::BitVec,
+        core::{fmt, iter, mem, slice},
+    };
+
+    /// Returns the corresponding expression from the substitution iterator.
+    #[inline]
+    pub fn get_expression<'s, E, I>(iter: I, atom: &E::Atom) -> Option<E>
+    where
+        E: 's + Expression,
+        E::Atom: PartialEq,
+        I: IntoIterator<Item = (&'s E::Atom, E)>,
+    {
+        iter.into_iter()
+            .find(move |(a, _)| *a == atom)
+            .map(move |(_, t)| t)
+    }
+
+    #[inline]
+    fn from_iter_on_atoms_inner<'s, E, I>(iter: &mut I, atom: E::Atom) -> E
+    where
+        E: 's + Expression,
+        E::Atom: PartialEq,
+        I: Iterator<Item = (&'s E::Atom, E)>,
+    {
+        get_expression(iter, &atom).unwrap_or_else(move || E::from_atom(atom))
+    }
+
+    #[inline]
+    fn from_iter_on_atoms_ref_inner<'s, E, I>(iter: &mut I, atom: &E::Atom) -> E
+    where
+        E: 's + Expression,
+        E::Atom: Clone + PartialEq,
+        I: Iterator<Item = (&'s E::Atom, E)>,
+    {
+        get_expression(iter, atom).unwrap_or_else(move || E::from_atom(atom.clone()))
+    }
+
+    /// Performs a substitution using data from an iterator to an atomic expression.
+    #[inline]
+    pub fn from_iter_on_atoms<'s, E, I>(iter: I, atom: E::Atom) -> E
+    where
+        E: 's + Expression,
+        E::Atom: PartialEq,
+        I: IntoIterator<Item = (&'s E::Atom, E)>,
+    {
+        from_iter_on_atoms_inner(&mut iter.into_iter(), atom)
+    }
+
+    /// Performs a substitution by reference using data from an iterator to an atomic expression.
+    #[inline]
+    pub fn from_iter_on_atoms_ref<'s, E, I>(iter: I, atom: &E::Atom) -> E
+    where
+        E: 's + Expression,
+        E::Atom: Clone + PartialEq,
+        I: IntoIterator<Item = (&'s E::Atom, E)>,
+    {
+        from_iter_on_atoms_ref_inner(&mut iter.into_iter(), atom)
+    }
+
+    /// Returns a function which performs substitution using data from an iterator to an atomic expression.
+    #[inline]
+    pub fn from_iter_on_atoms_fn<'s, E, I>(iter: I) -> impl FnMut(E::Atom) -> E
+    where
+        E: 's + Expression,
+        E::Atom: PartialEq,
+        I: IntoIterator<Item = (&'s E::Atom, E)>,
+    {
+        let mut iter = iter.into_iter();
+        move |atom| from_iter_on_atoms_inner(&mut iter, atom)
+    }
+
+    /// Returns a function which performs substitution by reference using data from an iterator to an atomic expression.
+    #[inline]
+    pub fn from_iter_on_atoms_ref_fn<'s, E, I>(iter: I) -> impl FnMut(&E::Atom) -> E
+    where
+        E: 's + Expression,
+        E::Atom: Clone + PartialEq,
+        I: IntoIterator<Item = (&'s E::Atom, E)>,
+    {
+        let mut iter = iter.into_iter();
+        move |atom| from_iter_on_atoms_ref_inner(&mut iter, atom)
+    }
+
+    /// Performs substitution using data from an iterator.
+    #[inline]
+    pub fn from_iter<'s, E, I>(iter: I, expr: E) -> E
+    where
+        E: 's + Expression,
+        E::Atom: PartialEq,
+        E::Group: Container<E>,
+        I: IntoIterator<Item = (&'s E::Atom, E)>,
+    {
+        expr.substitute(from_iter_on_atoms_fn(iter))
+    }
+
+    /// Performs substitution by reference using data from an iterator.
+    #[inline]
+    pub fn from_iter_ref<'s, E, I>(iter: I, expr: &E) -> E
+    where
+        E: 's + Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E>,
+        I: IntoIterator<Item = (&'s E::Atom, E)>,
+    {
+        expr.substitute_ref(from_iter_on_atoms_ref_fn(iter))
+    }
+
+    /// Returns a function which performs substitution using data from an iterator.
+    #[inline]
+    pub fn from_iter_fn<'s, E, I>(iter: I) -> impl FnOnce(E) -> E
+    where
+        E: 's + Expression,
+        E::Atom: PartialEq,
+        E::Group: Container<E>,
+        I: IntoIterator<Item = (&'s E::Atom, E)>,
+    {
+        move |expr| from_iter(iter, expr)
+    }
+
+    /// Returns a function which performs substitution by reference using data from an iterator.
+    #[inline]
+    pub fn from_iter_ref_fn<'s, E, I>(iter: I) -> impl FnOnce(&E) -> E
+    where
+        E: 's + Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E>,
+        I: IntoIterator<Item = (&'s E::Atom, E)>,
+    {
+        move |expr| from_iter_ref(iter, expr)
+    }
+
+    /// Substitution Term Reference Type
+    #[derive(Clone, Copy, Debug)]
+    pub struct TermRef<'e, E>
+    where
+        E: Expression,
+    {
+        /// Substitution variable atom
+        pub var: &'e E::Atom,
+
+        /// Substitution expression
+        pub expr: &'e E,
+    }
+
+    impl<'e, E> TermRef<'e, E>
+    where
+        E: Expression,
+    {
+        /// Builds a new substitution term reference.
+        #[inline]
+        pub fn new(var: &'e E::Atom, expr: &'e E) -> Self {
+            Self { var, expr }
+        }
+
+        /// Checks if `&self` is the identity substitution.
+        #[inline]
+        pub fn is_identity(&self) -> bool
+        where
+            E::Atom: PartialEq,
+        {
+            self.expr.atom().map_or(false, move |atom| atom == self.var)
+        }
+
+        /// Returns an owned [`Term`].
+        #[inline]
+        pub fn to_owned(&self) -> Term<E>
+        where
+            E::Atom: Clone,
+            E::Group: FromIterator<E>,
+        {
+            Term::new(self.var.clone(), self.expr.clone())
+        }
+    }
+
+    impl<'e, E> PartialEq for TermRef<'e, E>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        #[inline]
+        fn eq(&self, other: &Self) -> bool {
+            self.var.eq(other.var) && self.expr.eq(other.expr)
+        }
+    }
+
+    impl<'e, E> Eq for TermRef<'e, E>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+    }
+
+    impl<'e, E> From<&'e Term<E>> for TermRef<'e, E>
+    where
+        E: Expression,
+    {
+        #[inline]
+        fn from(term: &'e Term<E>) -> Self {
+            term.as_ref()
+        }
+    }
+
+    /// Substitution Term Type
+    #[derive(Debug)]
+    pub struct Term<E>
+    where
+        E: Expression,
+    {
+        /// Substitution variable atom
+        pub var: E::Atom,
+
+        /// Substitution expression
+        pub expr: E,
+    }
+
+    impl<E> Term<E>
+    where
+        E: Expression,
+    {
+        /// Builds a new substitution term.
+        #[inline]
+        pub fn new(var: E::Atom, expr: E) -> Self {
+            Self { var, expr }
+        }
+
+        /// Returns the [`&Term`](Term) as a [`TermRef`].
+        #[inline]
+        pub fn as_ref(&self) -> TermRef<'_, E> {
+            TermRef::new(&self.var, &self.expr)
+        }
+
+        /// Builds an identity [`Term`].
+        #[inline]
+        pub fn identity(atom: E::Atom) -> Self
+        where
+            E::Atom: Clone,
+        {
+            Self::new(atom.clone(), E::from_atom(atom))
+        }
+
+        /// Checks if `&self` is the identity substitution.
+        #[inline]
+        pub fn is_identity(&self) -> bool
+        where
+            E::Atom: PartialEq,
+        {
+            self.as_ref().is_identity()
+        }
+
+        /// Flips the substitution if possible.
+        #[inline]
+        pub fn flip(self) -> Option<Self> {
+            Some(Self::new(self.expr.atom()?, E::from_atom(self.var)))
+        }
+
+        /// Tries the flip the substitution in place and returns `true` on success.
+        ///
+        /// # Panics
+        ///
+        /// This function could panic if the `is_atom`/`unwrap_atom` contract is faulty.
+        pub fn flip_in_place(&mut self) -> bool
+        where
+            E::Group: FromIterator<E>,
+        {
+            if self.expr.is_atom() {
+                self.expr = E::from_atom(mem::replace(
+                    &mut self.var,
+                    mem::replace(&mut self.expr, E::empty()).unwrap_atom(),
+                ));
+                true
+            } else {
+                false
+            }
+        }
+
+        /// Generates a unit substitution from a substitution term.
+        #[inline]
+        pub fn unit<U>(self) -> U
+        where
+            U: FromIterator<Self>,
+        {
+            Some(self).into_iter().collect()
+        }
+
+        #[inline]
+        fn iter(self) -> impl Iterator<Item = E> {
+            util::two_item_iter(E::from_atom(self.var), self.expr)
+        }
+    }
+
+    impl<E> Clone for Term<E>
+    where
+        E: Expression,
+        E::Atom: Clone,
+        E::Group: FromIterator<E>,
+    {
+        #[inline]
+        fn clone(&self) -> Self {
+            Self::new(self.var.clone(), self.expr.clone())
+        }
+    }
+
+    impl<E> Copy for Term<E>
+    where
+        E: Copy + Expression,
+        E::Atom: Copy,
+        E::Group: FromIterator<E>,
+    {
+    }
+
+    impl<E> PartialEq for Term<E>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        #[inline]
+        fn eq(&self, other: &Self) -> bool {
+            self.as_ref().eq(&other.as_ref())
+        }
+    }
+
+    impl<E> Eq for Term<E>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+    }
+
+    impl<E> TryFrom<(E, E)> for Term<E>
+    where
+        E: Expression,
+    {
+        type Error = ();
+
+        #[inline]
+        fn try_from(pair: (E, E)) -> Result<Self, Self::Error> {
+            Ok(Self::new(pair.0.atom().ok_or(())?, pair.1))
+        }
+    }
+
+    /// Substitution Trait
+    pub trait Substitution<E, V = Vec<Term<E>>>: super::Structure<E, Structure<E, V>>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        V: Container<Term<E>>,
+    {
+        /// Iterator over elements by reference.
+        type Iter<'s>: Iterator<Item = TermRef<'s, E>>
+        where
+            E: 's;
+
+        /// Returns an iterator over the elements by reference.
+        fn iter(&self) -> Self::Iter<'_>;
+
+        /// Returns an iterator over the variables by reference.
+        #[inline]
+        fn vars(&self) -> VarsIter<E, Self::Iter<'_>> {
+            VarsIter(self.iter())
+        }
+
+        /// Returns an iterator over the expressions by reference.
+        #[inline]
+        fn exprs(&self) -> ExprsIter<E, Self::Iter<'_>> {
+            ExprsIter(self.iter())
+        }
+
+        /// Builds an empty `Substitution`.
+        #[inline]
+        fn empty() -> Self
+        where
+            Self: Sized,
+        {
+            Self::from(Structure::from_iter(None))
+        }
+
+        /// Checks if the `Substitution` is empty.
+        #[inline]
+        fn is_empty(&self) -> bool {
+            self.iter().next().is_none()
+        }
+
+        /// Builds an identity `Substitution` over the iterator of atoms.
+        #[inline]
+        fn identity<I>(atoms: I) -> Self
+        where
+            Self: Sized,
+            E::Atom: Clone,
+            I: IntoIterator<Item = E::Atom>,
+        {
+            Self::from(atoms.into_iter().map(Term::identity).collect())
+        }
+
+        /// Checks if all of the elements of `&self` are the identity substitution.
+        #[inline]
+        fn is_identity(&self) -> bool
+        where
+            E::Atom: PartialEq,
+        {
+            self.iter().all(move |t| t.is_identity())
+        }
+
+        /// Builds a unit `Substitution`.
+        #[inline]
+        fn unit(var: E::Atom, expr: E) -> Self
+        where
+            Self: Sized,
+        {
+            Self::from(Term::new(var, expr).unit())
+        }
+
+        /// Returns corresponding expression which `var` completes to under substitution.
+        #[inline]
+        fn get(&self, var: &E::Atom) -> Option<&E>
+        where
+            E::Atom: PartialEq,
+        {
+            self.iter().find(move |t| t.var == var).map(move |t| t.expr)
+        }
+
+        /// Returns corresponding expression which `var` completes to under substitution.
+        #[inline]
+        fn get_owned(&self, var: &E::Atom) -> Option<E>
+        where
+            E::Atom: Clone + PartialEq,
+            E::Group: FromIterator<E>,
+        {
+            self.get(var).map(E::clone)
+        }
+
+        /// Performs substitution on an atomic expression.
+        #[inline]
+        fn apply_atom(&self, atom: E::Atom) -> E
+        where
+            E::Atom: Clone + PartialEq,
+            E::Group: FromIterator<E>,
+        {
+            self.get_owned(&atom)
+                .unwrap_or_else(move || E::from_atom(atom))
+        }
+
+        /// Performs substitution on an atomic expression by reference.
+        #[inline]
+        fn apply_atom_ref(&self, atom: &E::Atom) -> E
+        where
+            E::Atom: Clone + PartialEq,
+            E::Group: FromIterator<E>,
+        {
+            self.get_owned(atom)
+                .unwrap_or_else(move || E::from_atom(atom.clone()))
+        }
+
+        /// Performs substitution on a grouped expression.
+        fn apply_group(&self, group: E::Group) -> E::Group
+        where
+            E::Atom: Clone + PartialEq,
+            E::Group: FromIterator<E>,
+        {
+            group.substitute(move |atom| self.apply_atom(atom))
+        }
+
+        /// Performs substitution on a grouped expression by reference.
+        fn apply_group_ref(&self, group: &GroupRef<E>) -> E::Group
+        where
+            E::Atom: Clone + PartialEq,
+            E::Group: FromIterator<E>,
+        {
+            group.substitute_ref(move |atom| self.apply_atom_ref(atom))
+        }
+
+        /// Performs substitution on an expression.
+        #[inline]
+        fn apply(&self, expr: E) -> E
+        where
+            E::Atom: Clone + PartialEq,
+            E::Group: Container<E>,
+        {
+            expr.substitute(move |atom| self.apply_atom(atom))
+        }
+
+        /// Performs substitution on an expression by reference.
+        #[inline]
+        fn apply_ref(&self, expr: &E) -> E
+        where
+            E::Atom: Clone + PartialEq,
+            E::Group: Container<E>,
+        {
+            expr.substitute_ref(move |atom| self.apply_atom_ref(atom))
+        }
+
+        /// Extends the given substitution by one variable-expression pair.
+        #[inline]
+        fn push(&mut self, var: E::Atom, expr: E) -> &mut Self
+        where
+            Self: Sized,
+        {
+            self.push_term(Term::new(var, expr))
+        }
+
+        /// Extends the given substitution by the one term.
+        #[inline]
+        fn push_term(&mut self, term: Term<E>) -> &mut Self
+        where
+            Self: Sized,
+        {
+            *self = Self::from(
+                mem::replace(self, Self::empty())
+                    .structure()
+                    .push_inner(term),
+            );
+            self
+        }
+
+        /// Deduplicates the substitution.
+        #[inline]
+        fn dedup(&mut self) -> &mut Self
+        where
+            Self: Sized,
+            E::Atom: PartialEq,
+        {
+            *self = Self::from(mem::replace(self, Self::empty()).structure().dedup_inner());
+            self
+        }
+
+        /// Sorts the substitution by variable.
+        #[inline]
+        fn sort(&mut self) -> &mut Self
+        where
+            Self: Sized,
+            E::Atom: Ord,
+        {
+            *self = Self::from(mem::replace(self, Self::empty()).structure().sort_inner());
+            self
+        }
+
+        /// Reduces the substitution to its canonical form.
+        #[inline]
+        fn canonicalize(&mut self) -> &mut Self
+        where
+            Self: Sized,
+            E::Atom: Ord,
+        {
+            *self = Self::from(
+                mem::replace(self, Self::empty())
+                    .structure()
+                    .canonicalize_inner(),
+            );
+            self
+        }
+
+        /// Tries to generate a substitution from two expressions.
+        // FIXME: find a way to accept `&E` or `ExprRef<E>`
+        #[inline]
+        fn generate<F>(lhs: &E, rhs: &E, can_substitute: F) -> Option<Directed<Self>>
+        where
+            Self: Sized,
+            E::Atom: Clone + PartialEq,
+            F: FnMut(&E::Atom) -> bool,
+        {
+            generate::<_, Structure<_, V>, _>(lhs, rhs, can_substitute)
+                .map(move |d| d.map(Self::from))
+        }
+    }
+
+    /// Iterator over Substitution Variables
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct VarsIter<'s, E, I>(I)
+    where
+        E: 's + Expression,
+        I: Iterator<Item = TermRef<'s, E>>;
+
+    impl<'s, E, I> Iterator for VarsIter<'s, E, I>
+    where
+        E: 's + Expression,
+        I: Iterator<Item = TermRef<'s, E>>,
+    {
+        type Item = &'s E::Atom;
+
+        #[inline]
+        fn next(&mut self) -> Option<Self::Item> {
+            self.0.next().map(move |t| t.var)
+        }
+
+        #[inline]
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            self.0.size_hint()
+        }
+    }
+
+    /// Iterator over Substitution Expressions
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct ExprsIter<'s, E, I>(I)
+    where
+        E: 's + Expression,
+        I: Iterator<Item = TermRef<'s, E>>;
+
+    impl<'s, E, I> Iterator for ExprsIter<'s, E, I>
+    where
+        E: 's + Expression,
+        I: Iterator<Item = TermRef<'s, E>>,
+    {
+        type Item = &'s E;
+
+        #[inline]
+        fn next(&mut self) -> Option<Self::Item> {
+            self.0.next().map(move |t| t.expr)
+        }
+
+        #[inline]
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            self.0.size_hint()
+        }
+    }
+
+    /// Substitution Structure Type
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct Structure<E, V = Vec<Term<E>>>
+    where
+        E: Expression,
+        V: Container<Term<E>>,
+    {
+        /// Substitution terms
+        pub terms: V,
+
+        /// Phantom Marker
+        __: PhantomData<E>,
+    }
+
+    impl<E, V> Structure<E, V>
+    where
+        E: Expression,
+        V: Container<Term<E>>,
+    {
+        /// Builds a new [`Structure`] from a [`Term`] container.
+        #[inline]
+        pub fn new(terms: V) -> Self {
+            Self {
+                terms,
+                __: PhantomData,
+            }
+        }
+
+        #[inline]
+        fn push_inner(self, term: Term<E>) -> Self {
+            self.into_iter().chain(Some(term)).collect()
+        }
+
+        #[inline]
+        fn dedup_inner(self) -> Self
+        where
+            E::Atom: PartialEq,
+        {
+            let mut terms = self.into_iter().collect::<Vec<_>>();
+            terms.dedup_by(move |l, r| l.var.eq(&r.var));
+            Self::from_iter(terms)
+        }
+
+        #[inline]
+        fn sort_inner(self) -> Self
+        where
+            E::Atom: Ord,
+        {
+            let mut terms = self.into_iter().collect::<Vec<_>>();
+            terms.sort_by(move |l, r| l.var.cmp(&r.var));
+            Self::from_iter(terms)
+        }
+
+        #[inline]
+        fn canonicalize_inner(self) -> Self
+        where
+            E::Atom: Ord,
+        {
+            self.sort_inner()
+                .dedup_inner()
+                .into_iter()
+                .filter(move |t| !t.is_identity())
+                .collect()
+        }
+    }
+
+    impl<E, V> Default for Structure<E, V>
+    where
+        E: Expression,
+        V: Container<Term<E>>,
+    {
+        #[inline]
+        fn default() -> Self {
+            Self::new(V::from_iter(None))
+        }
+    }
+
+    impl<E, V> IntoIterator for Structure<E, V>
+    where
+        E: Expression,
+        V: Container<Term<E>>,
+    {
+        type Item = Term<E>;
+
+        type IntoIter = <V as IntoIterator>::IntoIter;
+
+        #[inline]
+        fn into_iter(self) -> Self::IntoIter {
+            self.terms.into_iter()
+        }
+    }
+
+    impl<E, V> FromIterator<Term<E>> for Structure<E, V>
+    where
+        E: Expression,
+        V: Container<Term<E>>,
+    {
+        #[inline]
+        fn from_iter<I>(iter: I) -> Self
+        where
+            I: IntoIterator<Item = Term<E>>,
+        {
+            Self::new(V::from_iter(iter))
+        }
+    }
+
+    impl<E, V> From<Structure<E, V>> for Expr<E>
+    where
+        E: Expression,
+        E::Group: FromIterator<E>,
+        V: Container<Term<E>>,
+    {
+        #[inline]
+        fn from(structure: Structure<E, V>) -> Self {
+            Self::Group(structure.into_iter().map(Term::iter).flatten().collect())
+        }
+    }
+
+    /// Substitution Shape Error Type
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum ShapeError {
+        /// The expression is not a group.
+        NotGroup,
+
+        /// The expression is not grouped in pairs.
+        NotGroupedInPairs,
+
+        /// The expression does not have the right group shape.
+        BadGroupShape(usize),
+    }
+
+    impl fmt::Display for ShapeError {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::NotGroup => f.write_str("expression is not a group"),
+                Self::NotGroupedInPairs => f.write_str("expression is not grouped in pairs"),
+                Self::BadGroupShape(i) => write!(f, "pair {} does not start with an atom", i),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for ShapeError {}
+
+    /// Checks if an atom matches the `Substitution` pattern.
+    #[inline]
+    pub fn matches_atom<E>(atom: &E::Atom) -> Result<(), ShapeError>
+    where
+        E: Expression,
+    {
+        let _ = atom;
+        Err(ShapeError::NotGroup)
+    }
+
+    /// Checks if a group matches the `Substitution` pattern.
+    #[inline]
+    pub fn matches_group<E>(group: &GroupRef<E>) -> Result<(), ShapeError>
+    where
+        E: Expression,
+    {
+        for (i, pair) in util::by_pairs(group.iter()).enumerate() {
+            match pair {
+                Some((var, _)) => {
+                    if !var.is_atom() {
+                        return Err(ShapeError::BadGroupShape(i));
+                    }
+                }
+                _ => return Err(ShapeError::NotGroupedInPairs),
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks if an expression matches the `Substitution` pattern.
+    #[inline]
+    pub fn matches<E>(expr: &ExprRef<E>) -> Result<(), ShapeError>
+    where
+        E: Expression,
+    {
+        match expr {
+            ExprRef::Atom(atom) => matches_atom::<E>(atom),
+            ExprRef::Group(group) => matches_group::<E>(group),
+        }
+    }
+
+    impl<E, V> Matcher<E> for Structure<E, V>
+    where
+        E: Expression,
+        V: Container<Term<E>>,
+    {
+        type Error = ShapeError;
+
+        #[inline]
+        fn matches_atom(atom: &E::Atom) -> Result<(), Self::Error> {
+            matches_atom::<E>(atom)
+        }
+
+        #[inline]
+        fn matches_group(group: GroupRef<E>) -> Result<(), Self::Error> {
+            matches_group::<E>(&group)
+        }
+    }
+
+    impl<E, V> TryFrom<Expr<E>> for Structure<E, V>
+    where
+        E: Expression,
+        E::Group: IntoIterator<Item = E>,
+        V: Container<Term<E>>,
+    {
+        type Error = <Structure<E, V> as Matcher<E>>::Error;
+
+        #[inline]
+        fn try_from(expr: Expr<E>) -> Result<Self, Self::Error> {
+            util::by_pairs(expr.group().ok_or(Self::Error::NotGroup)?)
+                .enumerate()
+                .map(move |(i, pair)| match pair {
+                    Some(pair) => pair
+                        .try_into()
+                        .map_err(move |_| Self::Error::BadGroupShape(i)),
+                    _ => Err(Self::Error::NotGroupedInPairs),
+                })
+                .collect()
+        }
+    }
+
+    impl<E, V> Shape<E> for Structure<E, V>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        V: Container<Term<E>>,
+    {
+    }
+
+    /// Structure Vector Reference Iterator
+    #[derive(Clone)]
+    pub struct StructureVecIter<'s, E>(slice::Iter<'s, Term<E>>)
+    where
+        E: Expression;
+
+    impl<E> AsRef<[Term<E>]> for StructureVecIter<'_, E>
+    where
+        E: Expression,
+    {
+        #[inline]
+        fn as_ref(&self) -> &[Term<E>] {
+            self.0.as_ref()
+        }
+    }
+
+    impl<'s, E> DoubleEndedIterator for StructureVecIter<'s, E>
+    where
+        E: Expression,
+    {
+        #[inline]
+        fn next_back(&mut self) -> Option<TermRef<'s, E>> {
+            self.0.next_back().map(Term::as_ref)
+        }
+    }
+
+    impl<E> ExactSizeIterator for StructureVecIter<'_, E> where E: Expression {}
+
+    impl<E> iter::FusedIterator for StructureVecIter<'_, E> where E: Expression {}
+
+    impl<'s, E> Iterator for StructureVecIter<'s, E>
+    where
+        E: Expression,
+    {
+        type Item = TermRef<'s, E>;
+
+        #[inline]
+        fn next(&mut self) -> Option<Self::Item> {
+            self.0.next().map(Term::as_ref)
+        }
+
+        #[inline]
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            self.0.size_hint()
+        }
+
+        #[inline]
+        fn count(self) -> usize {
+            self.0.count()
+        }
+    }
+
+    impl<E> Substitution<E> for Structure<E>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+    {
+        type Iter<'s>
+        where
+            E: 's,
+        = StructureVecIter<'s, E>;
+
+        #[inline]
+        fn iter(&self) -> Self::Iter<'_> {
+            StructureVecIter(self.terms.iter())
+        }
+
+        #[inline]
+        fn push_term(&mut self, term: Term<E>) -> &mut Self {
+            *self = mem::take(self).push_inner(term);
+            self
+        }
+
+        #[inline]
+        fn dedup(&mut self) -> &mut Self
+        where
+            E::Atom: PartialEq,
+        {
+            *self = mem::take(self).dedup_inner();
+            self
+        }
+
+        #[inline]
+        fn sort(&mut self) -> &mut Self
+        where
+            E::Atom: Ord,
+        {
+            *self = mem::take(self).sort_inner();
+            self
+        }
+
+        #[inline]
+        fn canonicalize(&mut self) -> &mut Self
+        where
+            E::Atom: Ord,
+        {
+            *self = mem::take(self).canonicalize_inner();
+            self
+        }
+    }
+
+    /// Directed Substitution
+    pub enum Directed<S> {
+        /// Forward Substitution
+        Forward(S),
+
+        /// Backward Substitution
+        Backward(S),
+    }
+
+    /// Forward Substitution Boolean
+    pub type IsForward = bool;
+
+    impl<S> Directed<S> {
+        /// Returns `true` if the substitution is a `Forward` substitution.
+        #[inline]
+        pub fn is_forward(&self) -> bool {
+            matches!(self, Self::Forward(_))
+        }
+
+        /// Returns `true` if the substitution is a `Backward` substitution.
+        #[inline]
+        pub fn is_backward(&self) -> bool {
+            matches!(self, Self::Backward(_))
+        }
+
+        /// Returns `Some` with the inner substitution if `self` is `Forward`.
+        #[must_use]
+        #[inline]
+        pub fn forward(self) -> Option<S> {
+            match self {
+                Self::Forward(substitution) => Some(substitution),
+                _ => None,
+            }
+        }
+
+        /// Returns `Some` with the inner substitution if `self` is `Forward`.
+        #[must_use]
+        #[inline]
+        pub fn forward_ref(&self) -> Option<&S> {
+            match self {
+                Self::Forward(substitution) => Some(substitution),
+                _ => None,
+            }
+        }
+
+        /// Returns `Some` with the inner substitution if `self` is `Backward`.
+        #[must_use]
+        #[inline]
+        pub fn backward(self) -> Option<S> {
+            match self {
+                Self::Backward(substitution) => Some(substitution),
+                _ => None,
+            }
+        }
+
+        /// Returns `Some` with the inner substitution if `self` is `Backward`.
+        #[must_use]
+        #[inline]
+        pub fn backward_ref(&self) -> Option<&S> {
+            match self {
+                Self::Backward(substitution) => Some(substitution),
+                _ => None,
+            }
+        }
+
+        /// Maps a `Directed<S>` to `Directed<T>` by applying a function to a contained value.
+        #[inline]
+        pub fn map<T, F>(self, f: F) -> Directed<T>
+        where
+            F: FnOnce(S) -> T,
+        {
+            match self {
+                Self::Forward(substitution) => Directed::Forward(f(substitution)),
+                Self::Backward(substitution) => Directed::Backward(f(substitution)),
+            }
+        }
+
+        /// Returns the inner substitution.
+        #[inline]
+        pub fn substitution(self) -> S {
+            match self {
+                Self::Forward(substitution) | Self::Backward(substitution) => substitution,
+            }
+        }
+
+        /// Packs the enumeration from a `(bool, S)` pair.
+        #[inline]
+        pub fn pack(is_forward: IsForward, substitution: S) -> Self {
+            if is_forward {
+                Self::Forward(substitution)
+            } else {
+                Self::Backward(substitution)
+            }
+        }
+
+        /// Unpacks the enumeration into a `(bool, S)` pair.
+        #[inline]
+        pub fn unpack(self) -> (IsForward, S) {
+            (self.is_forward(), self.substitution())
+        }
+    }
+
+    /// Tries to generate a substitution from two atomic expressions.
+    #[inline]
+    pub fn generate_from_atoms<E, S, F>(
+        lhs: &E::Atom,
+        rhs: &E::Atom,
+        mut can_substitute: F,
+    ) -> Option<Directed<S>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E>,
+        S: FromIterator<Term<E>> + IntoIterator<Item = Term<E>>,
+        F: FnMut(&E::Atom) -> bool,
+    {
+        generate_from_atoms_inner(lhs, rhs, &mut can_substitute)
+    }
+
+    /// Tries to generate a substitution from two grouped expressions.
+    // FIXME: find a way to accept `&E::Group` or `GroupRef<E>`
+    #[inline]
+    pub fn generate_from_groups<E, S, F>(
+        lhs: &GroupRef<E>,
+        rhs: &GroupRef<E>,
+        mut can_substitute: F,
+    ) -> Option<Directed<S>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E>,
+        S: FromIterator<Term<E>> + IntoIterator<Item = Term<E>>,
+        F: FnMut(&E::Atom) -> bool,
+    {
+        generate_from_groups_inner(lhs, rhs, &mut can_substitute)
+    }
+
+    /// Tries to generate a substitution from two expressions.
+    // FIXME: find a way to accept `&E` or `ExprRef<E>`
+    #[inline]
+    pub fn generate<E, S, F>(lhs: &E, rhs: &E, mut can_substitute: F) -> Option<Directed<S>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E>,
+        S: FromIterator<Term<E>> + IntoIterator<Item = Term<E>>,
+        F: FnMut(&E::Atom) -> bool,
+    {
+        generate_inner(lhs.cases(), rhs.cases(), &mut can_substitute)
+    }
+
+    fn generate_from_atoms_inner<E, S, F>(
+        lhs: &E::Atom,
+        rhs: &E::Atom,
+        can_substitute: &mut F,
+    ) -> Option<Directed<S>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E>,
+        S: FromIterator<Term<E>> + IntoIterator<Item = Term<E>>,
+        F: FnMut(&E::Atom) -> bool,
+    {
+        if lhs == rhs {
+            Some(Directed::Forward(S::from_iter(None)))
+        } else if can_substitute(lhs) {
+            Some(Directed::Forward(
+                Term::new(lhs.clone(), E::from_atom(rhs.clone())).unit(),
+            ))
+        } else if can_substitute(rhs) {
+            Some(Directed::Backward(
+                Term::new(rhs.clone(), E::from_atom(lhs.clone())).unit(),
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn generate_from_groups_inner<E, S, F>(
+        lhs: &GroupRef<E>,
+        rhs: &GroupRef<E>,
+        can_substitute: &mut F,
+    ) -> Option<Directed<S>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E>,
+        S: FromIterator<Term<E>> + IntoIterator<Item = Term<E>>,
+        F: FnMut(&E::Atom) -> bool,
+    {
+        // FIXME: use `S as FromIterator` to construct the final substitution
+        // instead of relying on an intermediate `Vec`
+        let mut is_flipped = false;
+        let mut substitution = Vec::<Term<_>>::new();
+        let mut lhs_iter = lhs.iter();
+        let mut rhs_iter = rhs.iter();
+        for (lhs, rhs) in lhs_iter.by_ref().zip(rhs_iter.by_ref()) {
+            match generate_inner::<_, S, _>(lhs.cases(), rhs.cases(), can_substitute) {
+                Some(generated) => {
+                    let (is_forward, generated) = generated.unpack();
+                    if !is_forward {
+                        if is_flipped
+                            || !substitution
+                                .iter_mut()
+                                .all(|t| t.flip_in_place() && can_substitute(&t.var))
+                        {
+                            return None;
+                        }
+                        is_flipped = true;
+                        substitution.extend(generated);
+                    } else if is_flipped {
+                        for term in generated {
+                            let flipped = term.flip()?;
+                            if !can_substitute(&flipped.var) {
+                                return None;
+                            }
+                            substitution.push(flipped);
+                        }
+                    } else {
+                        substitution.extend(generated);
+                    }
+                }
+                _ => return None,
+            }
+        }
+        if lhs_iter.next().is_some() || rhs_iter.next().is_some() {
+            None
+        } else {
+            // FIXME: we can probably safely remove the `is_identity` checks
+            Some(Directed::pack(
+                !is_flipped,
+                substitution
+                    .into_iter()
+                    .filter(move |s| !s.is_identity())
+                    .collect(),
+            ))
+        }
+    }
+
+    fn generate_inner<E, S, F>(
+        lhs: ExprRef<'_, E>,
+        rhs: ExprRef<'_, E>,
+        can_substitute: &mut F,
+    ) -> Option<Directed<S>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E>,
         S: FromIterator<Term<E>> + IntoIterator<Item = Term<E>>,
         F: FnMut(&E::Atom) -> bool,
     {
-        generate_from_atoms_inner(lhs, rhs, &mut can_substitute)
+        match (&lhs, &rhs) {
+            (ExprRef::Atom(lhs_atom), ExprRef::Atom(rhs_atom)) => {
+                generate_from_atoms(*lhs_atom, *rhs_atom, can_substitute)
+            }
+            (ExprRef::Atom(lhs), _) if can_substitute(lhs) => Some(Directed::Forward(
+                Term::new((*lhs).clone(), rhs.to_owned()).unit(),
+            )),
+            (_, ExprRef::Atom(rhs)) if can_substitute(rhs) => Some(Directed::Backward(
+                Term::new((*rhs).clone(), lhs.to_owned()).unit(),
+            )),
+            (ExprRef::Group(lhs), ExprRef::Group(rhs)) => {
+                generate_from_groups_inner(lhs, rhs, can_substitute)
+            }
+            _ => None,
+        }
+    }
+
+    /// Generalizes a set of expressions to a single expression and substitutions.
+    #[inline]
+    pub fn generalize<E, F, G, S, C>(
+        exprs: &[E],
+        mut can_substitute: F,
+        mut atom_generator: G,
+    ) -> (E, C)
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E> + IntoIterator<Item = E>,
+        F: FnMut(&E::Atom) -> bool,
+        G: FnMut() -> E::Atom,
+        S: Substitution<E>,
+        C: FromIterator<S>,
+    {
+        generalize_inner(exprs, &mut can_substitute, &mut atom_generator)
+    }
+
+    #[inline]
+    fn generalize_to_atom<E, S, C>(target: &E::Atom, exprs: &[E]) -> C
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E> + IntoIterator<Item = E>,
+        S: Substitution<E>,
+        C: FromIterator<S>,
+    {
+        exprs
+            .iter()
+            .map(move |expr| match expr.atom_ref() {
+                Some(atom) if target == atom => S::empty(),
+                _ => S::unit(target.clone(), (*expr).to_owned()),
+            })
+            .collect()
+    }
+
+    fn transpose_substitutions<E, S>(
+        expr_count: usize,
+        mut substitutions: Vec<Vec<S>>,
+    ) -> impl Iterator<Item = S>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+        E::Group: FromIterator<E> + IntoIterator<Item = E>,
+        S: Substitution<E>,
+    {
+        let mut result = Vec::with_capacity(expr_count);
+        for _ in 0..expr_count {
+            result.push(S::from(
+                substitutions
+                    .iter_mut()
+                    .map(move |s| {
+                        let mut s = s.pop().unwrap().structure();
+                        s.dedup(); // FIXME: is this correct?
+                        s
+                    })
+                    .flatten()
+                    .collect(),
+            ));
+        }
+        result.into_iter().rev()
+    }
+
+    fn generalize_inner<E, F, G, S, C>(
+        exprs: &[E],
+        can_substitute: &mut F,
+        atom_generator: &mut G,
+    ) -> (E, C)
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E> + IntoIterator<Item = E>,
+        F: FnMut(&E::Atom) -> bool,
+        G: FnMut() -> E::Atom,
+        S: Substitution<E>,
+        C: FromIterator<S>,
+    {
+        match exprs.len() {
+            0 => (E::empty(), C::from_iter(None)),
+            1 => (exprs[0].to_owned(), C::from_iter(Some(S::empty()))),
+            expr_count => {
+                match exprs.iter().find_map(move |e| e.atom()) {
+                    Some(atom) if can_substitute(atom) => {
+                        return (E::from_atom(atom.clone()), generalize_to_atom(atom, exprs));
+                    }
+                    None => {
+                        if let Some(group_size) = (&exprs[0]).unwrap_group().len() {
+                            let groups = exprs.iter().map(move |e| e.unwrap_group());
+                            if groups.clone().all(move |g| g.len() == Some(group_size)) {
+                                let (expr_base, inner_substitutions) = (0..group_size)
+                                    .into_iter()
+                                    .map(move |i| {
+                                        // FIXME: remove the call to `.to_owned` if possible
+                                        generalize_inner::<_, _, _, _, Vec<S>>(
+                                            &groups
+                                                .clone()
+                                                .map(move |g| g.get(i).unwrap().to_owned())
+                                                .collect::<Vec<_>>(),
+                                            can_substitute,
+                                            atom_generator,
+                                        )
+                                    })
+                                    .unzip::<_, _, Vec<_>, Vec<_>>();
+                                return (
+                                    E::from_group(expr_base.into_iter().collect()),
+                                    transpose_substitutions(expr_count, inner_substitutions)
+                                        .collect(),
+                                );
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+                let target = atom_generator();
+                let substitutions = generalize_to_atom(&target, exprs);
+                (E::from_atom(target), substitutions)
+            }
+        }
+    }
+
+    /// Returns `true` if the variable `var` occurs in `expr`.
+    pub fn occurs<E>(var: &E::Atom, expr: &ExprRef<E>) -> bool
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        match expr {
+            ExprRef::Atom(atom) => *atom == var,
+            ExprRef::Group(group) => group.iter().any(move |e| occurs(var, &e.cases())),
+        }
+    }
+
+    /// Computes a most general unifier of `lhs` and `rhs`, treating the atoms of both sides for
+    /// which `is_variable` returns `true` as variables.
+    ///
+    /// The returned substitution is idempotent, so applying it once to `lhs` and `rhs` makes
+    /// them equal. Returns `None` if the expressions do not unify.
+    pub fn unify<E, F>(lhs: &E, rhs: &E, mut is_variable: F) -> Option<Structure<E>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        F: FnMut(&E::Atom) -> bool,
+    {
+        let mut terms = Vec::<Term<E>>::new();
+        let mut pending = vec![(lhs.clone(), rhs.clone())];
+        while let Some((lhs, rhs)) = pending.pop() {
+            let term = match (lhs.cases(), rhs.cases()) {
+                (ExprRef::Atom(l), ExprRef::Atom(r)) if l == r => continue,
+                (ExprRef::Atom(l), _) if is_variable(l) => Term::new(l.clone(), rhs.clone()),
+                (_, ExprRef::Atom(r)) if is_variable(r) => Term::new(r.clone(), lhs.clone()),
+                (ExprRef::Group(l), ExprRef::Group(r)) => {
+                    if l.len() != r.len() {
+                        return None;
+                    }
+                    let mut l_iter = l.iter();
+                    let mut r_iter = r.iter();
+                    loop {
+                        match (l_iter.next(), r_iter.next()) {
+                            (Some(l), Some(r)) => {
+                                pending.push((l.cases().to_owned(), r.cases().to_owned()))
+                            }
+                            (None, None) => break,
+                            _ => return None,
+                        }
+                    }
+                    continue;
+                }
+                _ => return None,
+            };
+            if occurs(&term.var, &term.expr.cases()) {
+                return None;
+            }
+            let single = Structure::<E>::from_iter(Some(term.as_ref().to_owned()));
+            for t in &mut terms {
+                t.expr = single.apply_ref(&t.expr);
+            }
+            for (l, r) in &mut pending {
+                *l = single.apply_ref(l);
+                *r = single.apply_ref(r);
+            }
+            terms.push(term);
+        }
+        Some(Structure::from_iter(terms))
+    }
+
+    /// Tries to extend `bindings` so that `pattern` matches `target`.
+    ///
+    /// Only the atoms of `pattern` for which `can_substitute` returns `true` are treated as
+    /// variables, and a variable which is already bound must be bound to an expression equal to
+    /// the corresponding part of `target`. On failure, `bindings` is left unchanged.
+    #[inline]
+    pub fn match_ref<E, F>(
+        pattern: &ExprRef<E>,
+        target: &ExprRef<E>,
+        bindings: &mut Vec<Term<E>>,
+        mut can_substitute: F,
+    ) -> bool
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E>,
+        F: FnMut(&E::Atom) -> bool,
+    {
+        match_ref_inner(pattern, target, bindings, &mut can_substitute)
+    }
+
+    /// Tries to extend `bindings` so that `pattern` matches `target`.
+    ///
+    /// See [`match_ref`] for more details.
+    #[inline]
+    pub fn match_expr<E, F>(
+        pattern: &E,
+        target: &E,
+        bindings: &mut Vec<Term<E>>,
+        can_substitute: F,
+    ) -> bool
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E>,
+        F: FnMut(&E::Atom) -> bool,
+    {
+        match_ref(&pattern.cases(), &target.cases(), bindings, can_substitute)
+    }
+
+    fn match_ref_inner<E, F>(
+        pattern: &ExprRef<E>,
+        target: &ExprRef<E>,
+        bindings: &mut Vec<Term<E>>,
+        can_substitute: &mut F,
+    ) -> bool
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E>,
+        F: FnMut(&E::Atom) -> bool,
+    {
+        let mark = bindings.len();
+        let is_match = match pattern {
+            ExprRef::Atom(atom) if can_substitute(*atom) => {
+                match bindings.iter().find(move |t| &t.var == *atom) {
+                    Some(term) => expr::eq_ref::<E>(&term.expr.cases(), target),
+                    _ => {
+                        bindings.push(Term::new((*atom).clone(), target.to_owned()));
+                        true
+                    }
+                }
+            }
+            ExprRef::Atom(atom) => matches!(target, ExprRef::Atom(other) if other == atom),
+            ExprRef::Group(pattern) => match target {
+                ExprRef::Group(target) => {
+                    let mut pattern_iter = pattern.iter();
+                    let mut target_iter = target.iter();
+                    loop {
+                        match (pattern_iter.next(), target_iter.next()) {
+                            (Some(p), Some(t)) => {
+                                if !match_ref_inner(
+                                    &p.cases(),
+                                    &t.cases(),
+                                    bindings,
+                                    can_substitute,
+                                ) {
+                                    break false;
+                                }
+                            }
+                            (None, None) => break true,
+                            _ => break false,
+                        }
+                    }
+                }
+                _ => false,
+            },
+        };
+        if !is_match {
+            bindings.truncate(mark);
+        }
+        is_match
+    }
+
+    /// Enumerates the ways of matching every pattern to a distinct target.
+    ///
+    /// For every complete match, `found` is called with the extended bindings and the indices of
+    /// the targets chosen for each pattern. The enumeration stops as soon as `found` returns
+    /// `true`, in which case this function also returns `true`. The `bindings` are restored to
+    /// their original state before returning.
+    #[inline]
+    pub fn match_multiset<E, F, G>(
+        patterns: &[ExprRef<E>],
+        targets: &[ExprRef<E>],
+        bindings: &mut Vec<Term<E>>,
+        mut can_substitute: F,
+        mut found: G,
+    ) -> bool
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E>,
+        F: FnMut(&E::Atom) -> bool,
+        G: FnMut(&[Term<E>], &[usize]) -> bool,
+    {
+        match_multiset_by(
+            patterns,
+            targets,
+            bindings,
+            can_substitute,
+            move |_, _| true,
+            found,
+        )
+    }
+
+    /// Enumerates the ways of matching every pattern to a distinct target, only matching the
+    /// pattern at index `i` to the target at index `j` if `allowed(i, j)` returns `true`.
+    ///
+    /// See [`match_multiset`] for more details.
+    #[inline]
+    pub fn match_multiset_by<E, F, H, G>(
+        patterns: &[ExprRef<E>],
+        targets: &[ExprRef<E>],
+        bindings: &mut Vec<Term<E>>,
+        mut can_substitute: F,
+        mut allowed: H,
+        mut found: G,
+    ) -> bool
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E>,
+        F: FnMut(&E::Atom) -> bool,
+        H: FnMut(usize, usize) -> bool,
+        G: FnMut(&[Term<E>], &[usize]) -> bool,
+    {
+        if patterns.len() > targets.len() {
+            return false;
+        }
+        match_multiset_inner(
+            patterns,
+            targets,
+            &mut util::zeroed_bit_vector(targets.len()),
+            &mut Vec::with_capacity(patterns.len()),
+            bindings,
+            &mut can_substitute,
+            &mut allowed,
+            &mut found,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn match_multiset_inner<E, F, H, G>(
+        patterns: &[ExprRef<E>],
+        targets: &[ExprRef<E>],
+        used: &mut BitVec,
+        chosen: &mut Vec<usize>,
+        bindings: &mut Vec<Term<E>>,
+        can_substitute: &mut F,
+        allowed: &mut H,
+        found: &mut G,
+    ) -> bool
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E>,
+        F: FnMut(&E::Atom) -> bool,
+        H: FnMut(usize, usize) -> bool,
+        G: FnMut(&[Term<E>], &[usize]) -> bool,
+    {
+        let (pattern, rest) = match patterns.split_first() {
+            Some(split) => split,
+            _ => return found(bindings, chosen),
+        };
+        for (i, target) in targets.iter().enumerate() {
+            if used[i] || !allowed(chosen.len(), i) {
+                continue;
+            }
+            let mark = bindings.len();
+            if match_ref_inner(pattern, target, bindings, can_substitute) {
+                used.set(i, true);
+                chosen.push(i);
+                let stop = match_multiset_inner(
+                    rest,
+                    targets,
+                    used,
+                    chosen,
+                    bindings,
+                    can_substitute,
+                    allowed,
+                    found,
+                );
+                chosen.pop();
+                used.set(i, false);
+                bindings.truncate(mark);
+                if stop {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Composition Module
+#[cfg(feature = "composition")]
+#[cfg_attr(docsrs, doc(cfg(feature = "composition")))]
+pub mod composition {
+    use {
+        super::*,
+        alloc::vec::Vec,
+        core::{fmt, iter, slice},
+    };
+
+    /// Evaluation Shape
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum EvaluationShape<R, C> {
+        /// Rule Shape
+        Rule(R),
+
+        /// Composition Shape
+        Composition(C),
+    }
+
+    /// Stored Rule Reference
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum StoredRuleRef<'e, R, K> {
+        /// Rule
+        Rule(&'e R),
+
+        /// Key
+        Key(&'e K),
+    }
+
+    impl<'e, R, K> StoredRuleRef<'e, R, K> {
+        /// Returns an owned [`StoredRule`] value.
+        #[inline]
+        pub fn to_owned(&self) -> StoredRule<R, K>
+        where
+            R: Clone,
+            K: Clone,
+        {
+            match self {
+                Self::Rule(rule) => StoredRule::Rule(Clone::clone(rule)),
+                Self::Key(key) => StoredRule::Key(Clone::clone(key)),
+            }
+        }
+    }
+
+    impl<'e, R, K> From<&'e StoredRule<R, K>> for StoredRuleRef<'e, R, K> {
+        #[inline]
+        fn from(stored: &'e StoredRule<R, K>) -> Self {
+            stored.as_ref()
+        }
+    }
+
+    /// Stored Rule
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum StoredRule<R, K> {
+        /// Rule
+        Rule(R),
+
+        /// Key
+        Key(K),
+    }
+
+    impl<R, K> StoredRule<R, K> {
+        /// Returns the [`&StoredRule`](StoredRule) as a [`StoredRuleRef`].
+        #[inline]
+        pub fn as_ref(&self) -> StoredRuleRef<'_, R, K> {
+            match self {
+                Self::Rule(rule) => StoredRuleRef::Rule(rule),
+                Self::Key(key) => StoredRuleRef::Key(key),
+            }
+        }
+    }
+
+    impl<E, R, K> From<StoredRule<R, K>> for Expr<E>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        K: Into<E::Atom>,
+    {
+        #[inline]
+        fn from(rule: StoredRule<R, K>) -> Self {
+            match rule {
+                StoredRule::Rule(rule) => rule.into().into(),
+                StoredRule::Key(key) => Self::Atom(key.into()),
+            }
+        }
+    }
+
+    impl<E, R, K> TryFrom<Expr<E>> for StoredRule<R, K>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        K: TryFrom<E::Atom>,
+    {
+        type Error = ();
+
+        #[inline]
+        fn try_from(expr: Expr<E>) -> Result<Self, Self::Error> {
+            if expr.is_atom() {
+                match expr.unwrap_atom().try_into() {
+                    Ok(key) => Ok(Self::Key(key)),
+                    _ => Err(()),
+                }
+            } else {
+                match R::try_from(E::from_expr(expr)) {
+                    Ok(rule) => Ok(Self::Rule(rule)),
+                    _ => Err(()),
+                }
+            }
+        }
+    }
+
+    /// Composition Term Reference Type
+    #[derive(Debug, Eq, PartialEq)]
+    pub struct TermRef<'e, R, S, K> {
+        /// [`StoredRule`] Reference
+        pub rule: StoredRuleRef<'e, R, K>,
+
+        /// [`Substitution`] Reference
+        pub subst: &'e S,
+    }
+
+    impl<'e, R, S, K> TermRef<'e, R, S, K> {
+        /// Builds a new composition term reference.
+        #[inline]
+        pub fn new(rule: StoredRuleRef<'e, R, K>, subst: &'e S) -> Self {
+            Self { rule, subst }
+        }
+
+        /// Returns an owned [`Term`].
+        #[inline]
+        pub fn to_owned(&self) -> Term<R, S, K>
+        where
+            R: Clone,
+            S: Clone,
+            K: Clone,
+        {
+            Term::new(self.rule.to_owned(), self.subst.clone())
+        }
+    }
+
+    impl<'e, R, S, K> From<&'e Term<R, S, K>> for TermRef<'e, R, S, K> {
+        #[inline]
+        fn from(term: &'e Term<R, S, K>) -> Self {
+            term.as_ref()
+        }
+    }
+
+    /// Composition Term Type
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct Term<R, S, K> {
+        /// [`StoredRule`] Object
+        pub rule: StoredRule<R, K>,
+
+        /// [`Substitution`] Object
+        pub subst: S,
+    }
+
+    impl<R, S, K> Term<R, S, K> {
+        /// Builds a new composition term.
+        #[inline]
+        pub fn new(rule: StoredRule<R, K>, subst: S) -> Self {
+            Self { rule, subst }
+        }
+
+        /// Returns the [`&Term`](Term) as a [`TermRef`].
+        #[inline]
+        pub fn as_ref(&self) -> TermRef<'_, R, S, K> {
+            TermRef::new(self.rule.as_ref(), &self.subst)
+        }
+
+        #[inline]
+        fn iter<E>(self) -> impl Iterator<Item = E>
+        where
+            E: Expression,
+            E::Group: Container<E>,
+            R: Rule<E>,
+            S: Substitution<E>,
+            K: Into<E::Atom>,
+        {
+            util::two_item_iter(E::from_expr(Into::into(self.rule)), self.subst.into())
+        }
+    }
+
+    impl<E, R, S, K> TryFrom<(E, E)> for Term<R, S, K> {
+        type Error = ();
+
+        #[inline]
+        fn try_from((rule, subst): (E, E)) -> Result<Self, Self::Error> {
+            // TODO: Ok(Self::new(eval.try_into()?, subst.try_into()?))
+            let _ = (rule, subst);
+            todo!()
+        }
+    }
+
+    /// Composition Trait
+    pub trait Composition<E, R, S, K = <E as Expression>::Atom, V = Vec<Term<R, S, K>>>:
+        super::Structure<E, Structure<E, R, S, K, V>>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        S: Substitution<E>,
+        K: Into<E::Atom>,
+        V: Container<Term<R, S, K>>,
+    {
+        /// Iterator over elements by reference.
+        type Iter<'e>: Iterator<Item = TermRef<'e, R, S, K>>
+        where
+            R: 'e,
+            S: 'e,
+            K: 'e;
+
+        /// Returns an iterator over the elements by reference.
+        fn iter(&self) -> Self::Iter<'_>;
+
+        /// Builds an empty `Composition`.
+        #[inline]
+        fn empty() -> Self
+        where
+            Self: Sized,
+        {
+            Self::from(Structure::from_iter(None))
+        }
+
+        /// Checks if the `Composition` is empty.
+        #[inline]
+        fn is_empty(&self) -> bool {
+            self.iter().next().is_none()
+        }
+
+        /// Evaluates the composition.
+        #[inline]
+        fn flat_resolve<F>(self, resolver: F) -> Result<R, E::Atom>
+        where
+            Self: Sized,
+            F: FnMut(&K) -> Option<R>,
+        {
+            let _ = resolver;
+            todo!()
+        }
+
+        /// Evaluates the composition.
+        #[inline]
+        fn resolve<F>(self, resolver: F) -> Result<R, E::Atom>
+        where
+            Self: Sized,
+            F: FnMut(&K) -> Option<EvaluationShape<R, Self>>,
+        {
+            let _ = resolver;
+            todo!()
+        }
+    }
+
+    /// Composition Structure Type
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct Structure<E, R, S, K = <E as Expression>::Atom, V = Vec<Term<R, S, K>>>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        S: Substitution<E>,
+        V: Container<Term<R, S, K>>,
+    {
+        /// Composition terms
+        pub terms: V,
+
+        /// Phantom Marker
+        __: PhantomData<(E, R, S, K)>,
+    }
+
+    impl<E, R, S, K, V> Structure<E, R, S, K, V>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        S: Substitution<E>,
+        V: Container<Term<R, S, K>>,
+    {
+        /// Builds a new [`Structure`] from a [`Term`] container.
+        #[inline]
+        pub fn new(terms: V) -> Self {
+            Self {
+                terms,
+                __: PhantomData,
+            }
+        }
+    }
+
+    impl<E, R, S, K, V> Default for Structure<E, R, S, K, V>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        S: Substitution<E>,
+        V: Container<Term<R, S, K>>,
+    {
+        #[inline]
+        fn default() -> Self {
+            Self::new(V::from_iter(None))
+        }
+    }
+
+    impl<E, R, S, K, V> IntoIterator for Structure<E, R, S, K, V>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        S: Substitution<E>,
+        V: Container<Term<R, S, K>>,
+    {
+        type Item = Term<R, S, K>;
+
+        type IntoIter = <V as IntoIterator>::IntoIter;
+
+        #[inline]
+        fn into_iter(self) -> Self::IntoIter {
+            self.terms.into_iter()
+        }
+    }
+
+    impl<E, R, S, K, V> FromIterator<Term<R, S, K>> for Structure<E, R, S, K, V>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        S: Substitution<E>,
+        V: Container<Term<R, S, K>>,
+    {
+        #[inline]
+        fn from_iter<I>(iter: I) -> Self
+        where
+            I: IntoIterator<Item = Term<R, S, K>>,
+        {
+            Self::new(V::from_iter(iter))
+        }
+    }
+
+    impl<E, R, S, K, V> From<Structure<E, R, S, K, V>> for Expr<E>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        S: Substitution<E>,
+        K: Into<E::Atom>,
+        V: Container<Term<R, S, K>>,
+    {
+        #[inline]
+        fn from(structure: Structure<E, R, S, K, V>) -> Self {
+            Self::Group(structure.into_iter().map(Term::iter).flatten().collect())
+        }
+    }
+
+    /// Composition Shape Error Type
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum ShapeError {}
+
+    impl fmt::Display for ShapeError {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match *self {}
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for ShapeError {}
+
+    /// Checks if an atom matches the `Composition` pattern.
+    #[inline]
+    pub fn matches_atom<E>(atom: &E::Atom) -> Result<(), ShapeError>
+    where
+        E: Expression,
+    {
+        // FIXME: implement
+        let _ = atom;
+        todo!()
+    }
+
+    /// Checks if a group matches the `Composition` pattern.
+    #[inline]
+    pub fn matches_group<E>(group: &GroupRef<E>) -> Result<(), ShapeError>
+    where
+        E: Expression,
+    {
+        // FIXME: implement
+        let _ = group;
+        todo!()
+    }
+
+    /// Checks if an expression matches the `Composition` pattern.
+    #[inline]
+    pub fn matches<E>(expr: &ExprRef<E>) -> Result<(), ShapeError>
+    where
+        E: Expression,
+    {
+        match expr {
+            ExprRef::Atom(atom) => matches_atom::<E>(atom),
+            ExprRef::Group(group) => matches_group::<E>(group),
+        }
+    }
+
+    impl<E, R, S, K, V> Matcher<E> for Structure<E, R, S, K, V>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        S: Substitution<E>,
+        V: Container<Term<R, S, K>>,
+    {
+        type Error = ShapeError;
+
+        #[inline]
+        fn matches_atom(atom: &E::Atom) -> Result<(), Self::Error> {
+            matches_atom::<E>(atom)
+        }
+
+        #[inline]
+        fn matches_group(group: GroupRef<E>) -> Result<(), Self::Error> {
+            matches_group::<E>(&group)
+        }
+    }
+
+    impl<E, R, S, K, V> TryFrom<Expr<E>> for Structure<E, R, S, K, V>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        S: Substitution<E>,
+        V: Container<Term<R, S, K>>,
+    {
+        type Error = <Structure<E, R, S, K, V> as Matcher<E>>::Error;
+
+        #[inline]
+        fn try_from(expr: Expr<E>) -> Result<Self, Self::Error> {
+            // FIXME: implement
+            let _ = expr;
+            todo!()
+        }
+    }
+
+    impl<E, R, S, K, V> Shape<E> for Structure<E, R, S, K, V>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        S: Substitution<E>,
+        K: Into<E::Atom>,
+        V: Container<Term<R, S, K>>,
+    {
+    }
+
+    /// Structure Vector Reference Iterator
+    #[derive(Clone)]
+    pub struct StructureVecIter<'s, R, S, K>(slice::Iter<'s, Term<R, S, K>>);
+
+    impl<R, S, K> AsRef<[Term<R, S, K>]> for StructureVecIter<'_, R, S, K> {
+        #[inline]
+        fn as_ref(&self) -> &[Term<R, S, K>] {
+            self.0.as_ref()
+        }
+    }
+
+    impl<'s, R, S, K> DoubleEndedIterator for StructureVecIter<'s, R, S, K> {
+        #[inline]
+        fn next_back(&mut self) -> Option<TermRef<'s, R, S, K>> {
+            self.0.next_back().map(Term::as_ref)
+        }
+    }
+
+    impl<R, S, K> ExactSizeIterator for StructureVecIter<'_, R, S, K> {}
+
+    impl<R, S, K> iter::FusedIterator for StructureVecIter<'_, R, S, K> {}
+
+    impl<'s, R, S, K> Iterator for StructureVecIter<'s, R, S, K> {
+        type Item = TermRef<'s, R, S, K>;
+
+        #[inline]
+        fn next(&mut self) -> Option<Self::Item> {
+            self.0.next().map(Term::as_ref)
+        }
+
+        #[inline]
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            self.0.size_hint()
+        }
+
+        #[inline]
+        fn count(self) -> usize {
+            self.0.count()
+        }
+    }
+
+    impl<E, R, S, K> Composition<E, R, S, K> for Structure<E, R, S, K>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        S: Substitution<E>,
+        K: Into<E::Atom>,
+    {
+        type Iter<'e>
+        where
+            R: 'e,
+            S: 'e,
+            K: 'e,
+        = StructureVecIter<'e, R, S, K>;
+
+        #[inline]
+        fn iter(&self) -> Self::Iter<'_> {
+            StructureVecIter(self.terms.iter())
+        }
+    }
+}
+
+/// Stored Objects Module
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod stored {
+    use {super::*, core::fmt};
+
+    /// Resolvable Trait
+    pub trait Resolvable<K> {
+        /// Resolved Part Type
+        type Part;
+
+        /// Resolved Output Type
+        type Output;
+
+        /// Key Container Type
+        type UnresolvedKeys: IntoIterator<Item = K>;
+
+        /// Resolves all keys in the given object and returns the output or the unresolved keys.
+        fn resolve<F>(self, resolver: F) -> Result<Self::Output, Self::UnresolvedKeys>
+        where
+            F: FnMut(&K) -> Option<Self::Part>;
+    }
+
+    /// Stored Object
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum Stored<K, V> {
+        /// Key
+        Key(K),
+
+        /// Value
+        Value(V),
+    }
+
+    impl<K, V> Stored<K, V> {
+        /// Returns the [`&Stored`](Stored) as a [`StoredRef`].
+        #[inline]
+        pub fn as_ref(&self) -> StoredRef<'_, K, V> {
+            match self {
+                Self::Key(key) => StoredRef::Key(key),
+                Self::Value(value) => StoredRef::Value(value),
+            }
+        }
+    }
+
+    impl<K, V> Resolvable<K> for Stored<K, V> {
+        type Part = V;
+
+        type Output = V;
+
+        type UnresolvedKeys = Result<K, Infallible>;
+
+        #[inline]
+        fn resolve<F>(self, mut resolver: F) -> Result<Self::Output, Self::UnresolvedKeys>
+        where
+            F: FnMut(&K) -> Option<Self::Part>,
+        {
+            match self {
+                Self::Key(key) => resolver(&key).ok_or(Ok(key)),
+                Self::Value(value) => Ok(value),
+            }
+        }
+    }
+
+    /// Stored Reference
+    pub type StoredRef<'r, K, V> = Stored<&'r K, &'r V>;
+
+    impl<'r, K, V> StoredRef<'r, K, V> {
+        /// Returns an owned [`Stored`] value.
+        #[inline]
+        pub fn to_owned(&self) -> Stored<K, V>
+        where
+            K: Clone,
+            V: Clone,
+        {
+            match self {
+                Self::Key(key) => Stored::Key(Clone::clone(key)),
+                Self::Value(value) => Stored::Value(Clone::clone(value)),
+            }
+        }
+    }
+
+    impl<'r, K, V> From<&'r Stored<K, V>> for StoredRef<'r, K, V> {
+        #[inline]
+        fn from(stored: &'r Stored<K, V>) -> Self {
+            stored.as_ref()
+        }
+    }
+
+    /// Stored Shape Type
+    #[derive(Debug, Eq, Hash, PartialEq)]
+    pub enum StoredShape<E, S, K = <E as Expression>::Atom>
+    where
+        E: Expression,
+    {
+        /// Key
+        Key(K),
+
+        /// Expression Shape
+        Shape(S),
+
+        #[doc(hidden)]
+        __(util::Nothing<E>),
+    }
+
+    impl<E, S, K> StoredShape<E, S, K>
+    where
+        E: Expression,
+    {
+        /// Checks if the `StoredShape` is a key.
+        #[must_use]
+        #[inline]
+        pub fn is_key(&self) -> bool {
+            matches!(self, Self::Key(_))
+        }
+
+        /// Checks if the `StoredShape` is a shaped expression.
+        #[must_use]
+        #[inline]
+        pub fn is_shape(&self) -> bool {
+            matches!(self, Self::Shape(_))
+        }
+
+        /// Converts from an `StoredShape<E, S, K>` to an `Option<K>`.
+        #[must_use]
+        #[inline]
+        pub fn key(self) -> Option<K> {
+            match self {
+                Self::Key(key) => Some(key),
+                _ => None,
+            }
+        }
+
+        /// Converts from an `&StoredShape<E, S, K>` to an `Option<&K>`.
+        #[must_use]
+        #[inline]
+        pub fn key_ref(&self) -> Option<&K> {
+            match self {
+                Self::Key(key) => Some(key),
+                _ => None,
+            }
+        }
+
+        /// Converts from a `StoredShape<E, S, K>` to an `Option<S>`.
+        #[must_use]
+        #[inline]
+        pub fn shape(self) -> Option<S> {
+            match self {
+                Self::Shape(shape) => Some(shape),
+                _ => None,
+            }
+        }
+
+        /// Converts from a `&StoredShape<E, S, K>` to an `Option<&S>`.
+        #[must_use]
+        #[inline]
+        pub fn shape_ref(&self) -> Option<&S> {
+            match self {
+                Self::Shape(shape) => Some(shape),
+                _ => None,
+            }
+        }
+
+        /// Returns the contained `K` key, consuming the `self` value.
+        ///
+        /// # Panics
+        ///
+        /// Panics if the contained value was a `Shape`.
+        #[inline]
+        #[track_caller]
+        pub fn unwrap_key(self) -> K {
+            match self {
+                Self::Key(key) => key,
+                _ => panic!(),
+            }
+        }
+
+        /// Returns the contained `Shape`, consuming the `self` value.
+        ///
+        /// # Panics
+        ///
+        /// Panics if the contained value was a `K`.
+        #[inline]
+        #[track_caller]
+        pub fn unwrap_shape(self) -> S {
+            match self {
+                Self::Shape(shape) => shape,
+                _ => panic!(),
+            }
+        }
+    }
+
+    impl<E, S, K> Clone for StoredShape<E, S, K>
+    where
+        E: Expression,
+        S: Clone,
+        K: Clone,
+    {
+        #[inline]
+        fn clone(&self) -> Self {
+            match self {
+                Self::Key(key) => Self::Key(key.clone()),
+                Self::Shape(shape) => Self::Shape(shape.clone()),
+            }
+        }
+    }
+
+    impl<E, S, K> From<StoredShape<E, S, K>> for Expr<E>
+    where
+        E: Expression,
+        S: Into<Expr<E>>,
+        K: Into<E::Atom>,
+    {
+        #[inline]
+        fn from(expr: StoredShape<E, S, K>) -> Self {
+            match expr {
+                StoredShape::Key(key) => Self::Atom(key.into()),
+                StoredShape::Shape(shape) => shape.into(),
+            }
+        }
+    }
+
+    /// [`StoredShape`] Matcher Error
+    pub enum StoredShapeMatcherError<E, S, K = <E as Expression>::Atom>
+    where
+        E: Expression,
+        S: Matcher<E>,
+        K: TryFrom<E::Atom>,
+    {
+        /// Base Shape Error
+        Base(<S as Matcher<E>>::Error),
+
+        /// Invalid Key Error
+        InvalidKey(K::Error),
+    }
+
+    impl<E, S, K> fmt::Display for StoredShapeMatcherError<E, S, K>
+    where
+        E: Expression,
+        S: Matcher<E>,
+        S::Error: fmt::Display,
+        K: TryFrom<E::Atom>,
+        K::Error: fmt::Display,
+    {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::Base(error) => write!(f, "invalid shape: {}", error),
+                Self::InvalidKey(error) => write!(f, "invalid key: {}", error),
+            }
+        }
+    }
+
+    impl<E, S, K> TryFrom<Expr<E>> for StoredShape<E, S, K>
+    where
+        E: Expression,
+        S: Shape<E>,
+        K: TryFrom<E::Atom>,
+    {
+        type Error = StoredShapeMatcherError<E, S, K>;
+
+        #[inline]
+        fn try_from(expr: Expr<E>) -> Result<Self, Self::Error> {
+            match expr {
+                Expr::Atom(key) => key
+                    .try_into()
+                    .map(StoredShape::Key)
+                    .map_err(StoredShapeMatcherError::InvalidKey),
+                _ => expr
+                    .try_into()
+                    .map(StoredShape::Shape)
+                    .map_err(StoredShapeMatcherError::Base),
+            }
+        }
+    }
+
+    impl<E, S> Matcher<E> for StoredShape<E, S>
+    where
+        E: Expression,
+        S: Matcher<E>,
+    {
+        type Error = StoredShapeMatcherError<E, S>;
+
+        #[inline]
+        fn matches_atom(atom: &E::Atom) -> Result<(), Self::Error> {
+            let _ = atom;
+            Ok(())
+        }
+
+        #[inline]
+        fn matches_group(group: GroupRef<E>) -> Result<(), Self::Error> {
+            S::matches_group(group).map_err(StoredShapeMatcherError::Base)
+        }
+    }
+
+    impl<E, S> Shape<E> for StoredShape<E, S>
+    where
+        E: Expression,
+        S: Shape<E>,
+    {
+    }
+
+    impl<E, S, K> Resolvable<K> for StoredShape<E, S, K>
+    where
+        E: Expression,
+    {
+        type Part = S;
+
+        type Output = S;
+
+        type UnresolvedKeys = Result<K, Infallible>;
+
+        #[inline]
+        fn resolve<F>(self, mut resolver: F) -> Result<Self::Output, Self::UnresolvedKeys>
+        where
+            F: FnMut(&K) -> Option<Self::Part>,
+        {
+            match self {
+                Self::Key(key) => resolver(&key).ok_or(Ok(key)),
+                Self::Shape(shape) => Ok(shape),
+            }
+        }
+    }
+}
+
+/// Rule Bytecode Module
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod bytecode {
+    use {
+        super::*,
+        crate::rule::{RuleId, RuleSet},
+        alloc::{vec, vec::Vec},
+        bitvec::vec::BitVec,
+        core::ops::Range,
+    };
+
+    /// Bytecode Instruction
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum Instruction {
+        /// Matches an atom equal to the constant with the given index.
+        MatchAtom(usize),
+
+        /// Matches a group with the given number of elements and descends into it.
+        MatchGroup(usize),
+
+        /// Binds the variable with the given index to the current expression.
+        Bind(usize),
+
+        /// Checks that the current expression is equal to the binding of the given variable.
+        Check(usize),
+
+        /// Pushes the constant atom with the given index.
+        PushAtom(usize),
+
+        /// Pushes a copy of the binding of the variable with the given index.
+        PushVar(usize),
+
+        /// Pops the given number of expressions and pushes a group containing them.
+        MakeGroup(usize),
+    }
+
+    /// Compiled Rule
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub struct RuleCode {
+        /// Identifier of the compiled rule
+        pub id: RuleId,
+
+        /// Number of variables of the rule
+        pub variables: usize,
+
+        /// Range of the blocks which match the top of the rule
+        pub top: Range<usize>,
+
+        /// Range of the blocks which build the bottom of the rule
+        pub bot: Range<usize>,
+    }
+
+    /// Bytecode Program
+    ///
+    /// A [`RuleSet`] compiled into flat instruction blocks, one for each element of the top and
+    /// bottom of every rule. Applying a rule consumes a match of its top from the state and
+    /// appends the instantiation of its bottom. Only the top and bottom of each rule are
+    /// compiled, so [premises](Rule::premises) and [guards](Rule::allows) are ignored.
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub struct Program<A> {
+        /// Constant atoms
+        pub atoms: Vec<A>,
+
+        /// Instructions
+        pub code: Vec<Instruction>,
+
+        /// Instruction ranges of the blocks
+        pub blocks: Vec<Range<usize>>,
+
+        /// Compiled rules
+        pub rules: Vec<RuleCode>,
+    }
+
+    impl<A> Program<A>
+    where
+        A: Clone + PartialEq,
+    {
+        /// Compiles every rule of `rules`, treating the atoms which satisfy `is_variable` as
+        /// variables.
+        pub fn compile<E, R, F>(rules: &RuleSet<E, R>, mut is_variable: F) -> Self
+        where
+            E: Expression<Atom = A>,
+            E::Group: Container<E>,
+            R: Rule<E>,
+            F: FnMut(&A) -> bool,
+        {
+            let mut program = Self {
+                atoms: Vec::new(),
+                code: Vec::new(),
+                blocks: Vec::new(),
+                rules: Vec::with_capacity(rules.len()),
+            };
+            for entry in rules {
+                let mut variables = Vec::new();
+                let (top, bot) = entry.rule.ref_pair();
+                let top_start = program.blocks.len();
+                for e in top.iter() {
+                    let start = program.code.len();
+                    program.compile_pattern(&e.cases(), &mut variables, &mut is_variable);
+                    program.blocks.push(start..program.code.len());
+                }
+                let bot_start = program.blocks.len();
+                for e in bot.iter() {
+                    let start = program.code.len();
+                    program.compile_template(&e.cases(), &mut variables, &mut is_variable);
+                    program.blocks.push(start..program.code.len());
+                }
+                program.rules.push(RuleCode {
+                    id: entry.id,
+                    variables: variables.len(),
+                    top: top_start..bot_start,
+                    bot: bot_start..program.blocks.len(),
+                });
+            }
+            program
+        }
+
+        /// Returns the index of `atom` in the constant table, adding it if necessary.
+        fn constant(&mut self, atom: &A) -> usize {
+            match self.atoms.iter().position(move |a| a == atom) {
+                Some(index) => index,
+                _ => {
+                    self.atoms.push(atom.clone());
+                    self.atoms.len() - 1
+                }
+            }
+        }
+
+        fn compile_pattern<E, F>(
+            &mut self,
+            expr: &ExprRef<E>,
+            variables: &mut Vec<A>,
+            is_variable: &mut F,
+        ) where
+            E: Expression<Atom = A>,
+            F: FnMut(&A) -> bool,
+        {
+            match expr {
+                ExprRef::Atom(atom) if is_variable(*atom) => {
+                    match variables.iter().position(|v| v == *atom) {
+                        Some(index) => self.code.push(Instruction::Check(index)),
+                        _ => {
+                            variables.push((*atom).clone());
+                            self.code.push(Instruction::Bind(variables.len() - 1));
+                        }
+                    }
+                }
+                ExprRef::Atom(atom) => {
+                    let index = self.constant(*atom);
+                    self.code.push(Instruction::MatchAtom(index));
+                }
+                ExprRef::Group(group) => {
+                    let mark = self.code.len();
+                    self.code.push(Instruction::MatchGroup(0));
+                    let mut len = 0;
+                    for e in group.iter() {
+                        self.compile_pattern(&e.cases(), variables, is_variable);
+                        len += 1;
+                    }
+                    self.code[mark] = Instruction::MatchGroup(len);
+                }
+            }
+        }
+
+        fn compile_template<E, F>(
+            &mut self,
+            expr: &ExprRef<E>,
+            variables: &mut Vec<A>,
+            is_variable: &mut F,
+        ) where
+            E: Expression<Atom = A>,
+            F: FnMut(&A) -> bool,
+        {
+            match expr {
+                ExprRef::Atom(atom) if is_variable(*atom) => {
+                    match variables.iter().position(|v| v == *atom) {
+                        Some(index) => self.code.push(Instruction::PushVar(index)),
+                        _ => {
+                            let index = self.constant(*atom);
+                            self.code.push(Instruction::PushAtom(index));
+                        }
+                    }
+                }
+                ExprRef::Atom(atom) => {
+                    let index = self.constant(*atom);
+                    self.code.push(Instruction::PushAtom(index));
+                }
+                ExprRef::Group(group) => {
+                    let mut len = 0;
+                    for e in group.iter() {
+                        self.compile_template(&e.cases(), variables, is_variable);
+                        len += 1;
+                    }
+                    self.code.push(Instruction::MakeGroup(len));
+                }
+            }
+        }
+
+        /// Returns the compiled rule with the given identifier.
+        #[inline]
+        pub fn rule(&self, id: RuleId) -> Option<&RuleCode> {
+            self.rules
+                .binary_search_by_key(&id, move |r| r.id)
+                .ok()
+                .map(move |i| &self.rules[i])
+        }
+
+        /// Applies the rule `id` to `state`, returning the next state if the rule matches.
+        #[inline]
+        pub fn apply<E>(&self, id: RuleId, state: &[E]) -> Option<Vec<E>>
+        where
+            E: Expression<Atom = A>,
+            E::Group: FromIterator<E>,
+        {
+            self.apply_code(self.rule(id)?, state)
+        }
+
+        /// Applies the first rule which matches `state`, returning its identifier and the next
+        /// state.
+        #[inline]
+        pub fn step<E>(&self, state: &[E]) -> Option<(RuleId, Vec<E>)>
+        where
+            E: Expression<Atom = A>,
+            E::Group: FromIterator<E>,
+        {
+            self.rules
+                .iter()
+                .find_map(move |r| Some((r.id, self.apply_code(r, state)?)))
+        }
+
+        /// Applies the compiled rule `rule` to `state`.
+        pub fn apply_code<E>(&self, rule: &RuleCode, state: &[E]) -> Option<Vec<E>>
+        where
+            E: Expression<Atom = A>,
+            E::Group: FromIterator<E>,
+        {
+            let mut machine = Machine {
+                program: self,
+                bindings: (0..rule.variables).map(move |_| None).collect(),
+                trail: Vec::new(),
+            };
+            let mut used = util::zeroed_bit_vector(state.len());
+            if !machine.match_blocks(&self.blocks[rule.top.clone()], state, &mut used) {
+                return None;
+            }
+            let mut next = state
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| !used[*i])
+                .map(move |(_, e)| E::clone(e))
+                .collect::<Vec<_>>();
+            for block in &self.blocks[rule.bot.clone()] {
+                next.push(machine.build(&self.code[block.clone()]));
+            }
+            Some(next)
+        }
+    }
+
+    /// Bytecode Interpreter State
+    struct Machine<'p, A, E> {
+        /// Program being executed
+        program: &'p Program<A>,
+
+        /// Variable bindings
+        bindings: Vec<Option<E>>,
+
+        /// Variables bound so far in binding order
+        trail: Vec<usize>,
+    }
+
+    impl<'p, A, E> Machine<'p, A, E>
+    where
+        A: Clone + PartialEq,
+        E: Expression<Atom = A>,
+        E::Group: FromIterator<E>,
+    {
+        /// Matches every block to a distinct unused element of `state`, backtracking over the
+        /// choices of elements.
+        fn match_blocks(
+            &mut self,
+            blocks: &[Range<usize>],
+            state: &[E],
+            used: &mut BitVec,
+        ) -> bool {
+            let (block, rest) = match blocks.split_first() {
+                Some(split) => split,
+                _ => return true,
+            };
+            let code = &self.program.code[block.clone()];
+            for (i, e) in state.iter().enumerate() {
+                if used[i] {
+                    continue;
+                }
+                let mark = self.trail.len();
+                let mut pc = 0;
+                if self.execute(code, &mut pc, &e.cases()) {
+                    used.set(i, true);
+                    if self.match_blocks(rest, state, used) {
+                        return true;
+                    }
+                    used.set(i, false);
+                }
+                self.unwind(mark);
+            }
+            false
+        }
+
+        /// Resets the bindings made after the trail position `mark`.
+        fn unwind(&mut self, mark: usize) {
+            for variable in self.trail.drain(mark..) {
+                self.bindings[variable] = None;
+            }
+        }
+
+        /// Executes the match instructions of a single expression starting at `pc`.
+        fn execute(&mut self, code: &[Instruction], pc: &mut usize, expr: &ExprRef<E>) -> bool {
+            let instruction = code[*pc];
+            *pc += 1;
+            match instruction {
+                Instruction::MatchAtom(index) => {
+                    matches!(expr, ExprRef::Atom(atom) if *atom == &self.program.atoms[index])
+                }
+                Instruction::MatchGroup(len) => match expr {
+                    ExprRef::Group(group) => {
+                        let mut count = 0;
+                        for e in group.iter() {
+                            if count == len || !self.execute(code, pc, &e.cases()) {
+                                return false;
+                            }
+                            count += 1;
+                        }
+                        count == len
+                    }
+                    _ => false,
+                },
+                Instruction::Bind(variable) => {
+                    self.bindings[variable] = Some(expr.to_owned());
+                    self.trail.push(variable);
+                    true
+                }
+                Instruction::Check(variable) => match &self.bindings[variable] {
+                    Some(bound) => expr::eq_ref::<E>(&bound.cases(), expr),
+                    _ => false,
+                },
+                _ => false,
+            }
+        }
+
+        /// Executes the build instructions of a single expression.
+        fn build(&self, code: &[Instruction]) -> E {
+            let mut stack = Vec::<E>::new();
+            for instruction in code {
+                match *instruction {
+                    Instruction::PushAtom(index) => {
+                        stack.push(E::from_atom(self.program.atoms[index].clone()))
+                    }
+                    Instruction::PushVar(variable) => stack.push(
+                        self.bindings[variable]
+                            .as_ref()
+                            .map(E::clone)
+                            .unwrap_or_else(E::empty),
+                    ),
+                    Instruction::MakeGroup(len) => {
+                        let start = stack.len() - len;
+                        let group = stack.drain(start..).collect();
+                        stack.push(E::from_group(group));
+                    }
+                    _ => {}
+                }
+            }
+            stack.pop().unwrap_or_else(E::empty)
+        }
+    }
+
+    #[cfg(all(test, feature = "simple"))]
+    mod tests {
+        use {
+            super::*,
+            crate::{
+                engine::{self, Config},
+                expr::SimpleExpr,
+            },
+            alloc::string::String,
+        };
+
+        type E = SimpleExpr;
+
+        fn is_variable(atom: &String) -> bool {
+            atom.starts_with('?')
+        }
+
+        fn atom(atom: &str) -> E {
+            E::atom(atom)
+        }
+
+        fn group<const N: usize>(items: [E; N]) -> E {
+            E::group(IntoIterator::into_iter(items))
+        }
+
+        fn sorted(mut state: Vec<E>) -> Vec<E> {
+            state.sort();
+            state
+        }
+
+        fn rules() -> RuleSet<E> {
+            let mut rules = RuleSet::new();
+            rules.insert(
+                "constants",
+                rule::Structure::new(vec![atom("a"), atom("b")], vec![atom("c")]),
+            );
+            rules.insert(
+                "variable",
+                rule::Structure::new(
+                    vec![group([atom("f"), atom("?x")])],
+                    vec![group([atom("g"), atom("?x")])],
+                ),
+            );
+            rules.insert(
+                "nested",
+                rule::Structure::new(
+                    vec![group([
+                        atom("p"),
+                        group([atom("q"), atom("?x")]),
+                        atom("?y"),
+                    ])],
+                    vec![group([atom("r"), atom("?y"), atom("?x")])],
+                ),
+            );
+            rules.insert(
+                "repeated",
+                rule::Structure::new(
+                    vec![group([atom("eq"), atom("?x"), atom("?x")])],
+                    vec![group([atom("same"), atom("?x")])],
+                ),
+            );
+            rules.insert(
+                "shared",
+                rule::Structure::new(
+                    vec![
+                        group([atom("edge"), atom("?x"), atom("?y")]),
+                        group([atom("edge"), atom("?y"), atom("?z")]),
+                    ],
+                    vec![group([atom("path"), atom("?x"), atom("?z")])],
+                ),
+            );
+            rules
+        }
+
+        /// Checks that every compiled rule applies to `state` exactly when the reference
+        /// matcher finds a match of the rule, and that it then produces one of the reference
+        /// successors.
+        fn assert_agrees(state: Vec<E>) {
+            let rules = rules();
+            let program = Program::compile(&rules, is_variable);
+            let reference = engine::matches(&state, &rules, &Config::new(is_variable));
+            let successors = |id| {
+                reference
+                    .iter()
+                    .filter(move |m| m.rule == id)
+                    .map(|m| sorted(engine::successor(&state, m)))
+                    .collect::<Vec<_>>()
+            };
+            for entry in &rules {
+                let expected = successors(entry.id);
+                match program.apply(entry.id, &state) {
+                    Some(next) => assert!(expected.contains(&sorted(next)), "rule {}", entry.name),
+                    _ => assert!(expected.is_empty(), "rule {}", entry.name),
+                }
+            }
+            match program.step(&state) {
+                Some((id, next)) => assert!(successors(id).contains(&sorted(next))),
+                _ => assert!(reference.is_empty()),
+            }
+        }
+
+        #[test]
+        fn constants() {
+            assert_agrees(vec![atom("b"), atom("d"), atom("a")]);
+            assert_agrees(vec![atom("a"), atom("a")]);
+        }
+
+        #[test]
+        fn variables() {
+            assert_agrees(vec![
+                group([atom("f"), group([atom("h"), atom("1")])]),
+                group([atom("f"), atom("2"), atom("3")]),
+            ]);
+        }
+
+        #[test]
+        fn nested_groups() {
+            assert_agrees(vec![
+                group([atom("p"), group([atom("z"), atom("1")]), atom("2")]),
+                group([
+                    atom("p"),
+                    group([atom("q"), group([atom("k"), atom("1")])]),
+                    group([atom("k"), atom("2")]),
+                ]),
+            ]);
+            assert_agrees(vec![group([atom("p"), group([atom("q")]), atom("2")])]);
+        }
+
+        #[test]
+        fn repeated_variables() {
+            assert_agrees(vec![group([atom("eq"), atom("1"), atom("2")])]);
+            assert_agrees(vec![
+                group([atom("eq"), atom("1"), atom("2")]),
+                group([
+                    atom("eq"),
+                    group([atom("h"), atom("1")]),
+                    group([atom("h"), atom("1")]),
+                ]),
+            ]);
+        }
+
+        #[test]
+        fn shared_variables() {
+            assert_agrees(vec![
+                group([atom("edge"), atom("1"), atom("2")]),
+                group([atom("edge"), atom("3"), atom("4")]),
+                group([atom("edge"), atom("2"), atom("3")]),
+            ]);
+            assert_agrees(vec![
+                group([atom("edge"), atom("1"), atom("2")]),
+                group([atom("edge"), atom("3"), atom("4")]),
+            ]);
+        }
+    }
+}
+
+/// Rewriting Module
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod rewrite {
+    use {
+        super::*,
+        crate::{
+            fuel::{Exhausted, Fuel},
+            rule::{RuleId, RuleProvider},
+        },
+        alloc::{vec, vec::Vec},
+    };
+
+    /// Position of a subexpression as the path of group indices leading to it
+    pub type Position = Vec<usize>;
+
+    /// Rule Reading
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum Reading {
+        /// A rule with a single top and a single bottom element rewrites any subexpression
+        /// matching its top into the instantiation of its bottom.
+        Term,
+
+        /// A rule rewrites the elements of a group matching its top, as a multiset, into the
+        /// instantiation of its bottom elements.
+        Multiset,
+    }
+
+    /// Rewriting Strategy
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum Strategy {
+        /// Tries the positions in leftmost-outermost order.
+        Outermost,
+
+        /// Tries the positions in leftmost-innermost order.
+        Innermost,
+    }
+
+    /// Rewriting Configuration
+    pub struct Config<E>
+    where
+        E: Expression,
+    {
+        /// Rule reading
+        pub reading: Reading,
+
+        /// Position strategy
+        pub strategy: Strategy,
+
+        /// Variable predicate for rule atoms
+        pub is_variable: fn(&E::Atom) -> bool,
+    }
+
+    impl<E> Config<E>
+    where
+        E: Expression,
+    {
+        /// Builds a new [`Config`] using the [`Term`](Reading::Term) reading and the
+        /// [`Outermost`](Strategy::Outermost) strategy.
+        #[inline]
+        pub fn new(is_variable: fn(&E::Atom) -> bool) -> Self {
+            Self {
+                reading: Reading::Term,
+                strategy: Strategy::Outermost,
+                is_variable,
+            }
+        }
+
+        /// Sets the rule reading.
+        #[inline]
+        pub fn reading(mut self, reading: Reading) -> Self {
+            self.reading = reading;
+            self
+        }
+
+        /// Sets the position strategy.
+        #[inline]
+        pub fn strategy(mut self, strategy: Strategy) -> Self {
+            self.strategy = strategy;
+            self
+        }
+    }
+
+    impl<E> Clone for Config<E>
+    where
+        E: Expression,
+    {
+        #[inline]
+        fn clone(&self) -> Self {
+            *self
+        }
+    }
+
+    impl<E> Copy for Config<E> where E: Expression {}
+
+    /// Rewrite Step
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub struct Rewrite<E> {
+        /// Rewritten expression
+        pub expr: E,
+
+        /// Identifier of the applied rule
+        pub rule: RuleId,
+
+        /// Position of the rewritten subexpression
+        pub position: Position,
+    }
+
+    /// Returns the positions of the subexpressions of `expr` in the order given by `strategy`.
+    pub fn positions<E>(expr: &ExprRef<E>, strategy: Strategy) -> Vec<Position>
+    where
+        E: Expression,
+    {
+        fn visit<E>(
+            expr: &ExprRef<E>,
+            path: &mut Position,
+            strategy: Strategy,
+            out: &mut Vec<Position>,
+        ) where
+            E: Expression,
+        {
+            if strategy == Strategy::Outermost {
+                out.push(path.clone());
+            }
+            if let ExprRef::Group(group) = expr {
+                for (i, e) in group.iter().enumerate() {
+                    path.push(i);
+                    visit(&e.cases(), path, strategy, out);
+                    path.pop();
+                }
+            }
+            if strategy == Strategy::Innermost {
+                out.push(path.clone());
+            }
+        }
+        let mut out = Vec::new();
+        visit(expr, &mut Vec::new(), strategy, &mut out);
+        out
+    }
+
+    /// Returns a copy of the subexpression of `expr` at `position`.
+    pub fn subexpression_at<E>(expr: &ExprRef<E>, position: &[usize]) -> Option<E>
+    where
+        E: Expression,
+        E::Atom: Clone,
+        E::Group: FromIterator<E>,
+    {
+        match position.split_first() {
+            None => Some(expr.to_owned()),
+            Some((index, rest)) => match expr {
+                ExprRef::Group(group) => subexpression_at(&group.iter().nth(*index)?.cases(), rest),
+                _ => None,
+            },
+        }
+    }
+
+    /// Rebuilds `expr` with the subexpression at `position` replaced by the output of `f`.
+    ///
+    /// Returns `None` if `position` is not a valid position of `expr` or if `f` returns `None`.
+    pub fn replace_at<E, F>(expr: &ExprRef<E>, position: &[usize], f: F) -> Option<E>
+    where
+        E: Expression,
+        E::Atom: Clone,
+        E::Group: FromIterator<E>,
+        F: FnOnce(&ExprRef<E>) -> Option<E>,
+    {
+        match position.split_first() {
+            None => f(expr),
+            Some((index, rest)) => match expr {
+                ExprRef::Group(group) => {
+                    let mut f = Some(f);
+                    let mut replaced = false;
+                    let mut children = Vec::new();
+                    for (i, e) in group.iter().enumerate() {
+                        if i == *index {
+                            children.push(replace_at(&e.cases(), rest, f.take()?)?);
+                            replaced = true;
+                        } else {
+                            children.push(e.cases().to_owned());
+                        }
+                    }
+                    replaced.then(|| E::from_group(children.into_iter().collect()))
+                }
+                _ => None,
+            },
+        }
+    }
+
+    /// Applies `rule` at the root of `expr` using the given `reading`.
+    pub fn apply_at_root<E, R>(expr: &ExprRef<E>, rule: &R, config: &Config<E>) -> Option<E>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        let (top, bot) = rule.ref_pair();
+        match config.reading {
+            Reading::Term => {
+                if top.len() != Some(1) || bot.len() != Some(1) {
+                    return None;
+                }
+                let pattern = top.iter().next()?;
+                let template = bot.iter().next()?;
+                let mut bindings = Vec::new();
+                if !substitution::match_ref(
+                    &pattern.cases(),
+                    expr,
+                    &mut bindings,
+                    config.is_variable,
+                ) || !rule.allows(&bindings)
+                {
+                    return None;
+                }
+                let substitution = substitution::Structure::<E>::from_iter(bindings);
+                Some(substitution.apply_ref(&template.cases().to_owned()))
+            }
+            Reading::Multiset => {
+                let group = match expr {
+                    ExprRef::Group(group) => group,
+                    _ => return None,
+                };
+                let patterns = top.iter().map(move |e| e.cases()).collect::<Vec<_>>();
+                let targets = group.iter().map(move |e| e.cases()).collect::<Vec<_>>();
+                let mut found = None;
+                substitution::match_multiset(
+                    &patterns,
+                    &targets,
+                    &mut Vec::new(),
+                    config.is_variable,
+                    |bindings, chosen| {
+                        if !rule.allows(bindings) {
+                            return false;
+                        }
+                        found = Some((
+                            bindings
+                                .iter()
+                                .map(move |t| t.as_ref().to_owned())
+                                .collect::<substitution::Structure<E>>(),
+                            chosen.to_vec(),
+                        ));
+                        true
+                    },
+                );
+                let (substitution, mut chosen) = found?;
+                chosen.sort_unstable();
+                let children = targets
+                    .iter()
+                    .enumerate()
+                    .filter(move |(i, _)| chosen.binary_search(i).is_err())
+                    .map(move |(_, e)| e.to_owned())
+                    .chain(substitution.apply_group_ref(&bot))
+                    .collect();
+                Some(E::from_group(children))
+            }
+        }
+    }
+
+    /// Rewrites `expr` once using the first position, in the order given by the strategy, at
+    /// which some rule of `rules` applies. Rules are tried in increasing order of their
+    /// identifiers.
+    pub fn rewrite_once<E, P>(expr: &E, rules: &P, config: &Config<E>) -> Option<Rewrite<E>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+    {
+        let cases = expr.cases();
+        positions(&cases, config.strategy)
+            .into_iter()
+            .find_map(move |position| {
+                let subexpression = subexpression_at(&cases, &position)?;
+                let state = match config.reading {
+                    Reading::Term => vec![subexpression],
+                    Reading::Multiset => match subexpression.cases() {
+                        ExprRef::Group(group) => {
+                            group.iter().map(move |e| e.cases().to_owned()).collect()
+                        }
+                        _ => return None,
+                    },
+                };
+                let mut found = None;
+                rules.for_each_candidate(&state, |id, rule| {
+                    match replace_at(&cases, &position, |e| apply_at_root(e, rule, config)) {
+                        Some(expr) => {
+                            found = Some(Rewrite {
+                                expr,
+                                rule: id,
+                                position: position.clone(),
+                            });
+                            true
+                        }
+                        _ => false,
+                    }
+                });
+                found
+            })
+    }
+
+    /// Normalization Result
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub struct Normalization<E> {
+        /// Last expression reached
+        pub expr: E,
+
+        /// Number of rewrite steps performed
+        pub steps: usize,
+    }
+
+    /// Rewrites `expr` with [`rewrite_once`] until no rule applies, consuming one step of
+    /// `fuel` for every rewrite.
+    ///
+    /// Returns the last expression reached as a partial result if the fuel runs out first.
+    pub fn normalize<E, P>(
+        expr: E,
+        rules: &P,
+        config: &Config<E>,
+        fuel: &mut Fuel,
+    ) -> Result<Normalization<E>, Exhausted<Normalization<E>>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+    {
+        let mut expr = expr;
+        let mut steps = 0;
+        while let Some(rewrite) = rewrite_once(&expr, rules, config) {
+            if let Err(reason) = fuel.step() {
+                return Err(Exhausted::new(reason, Normalization { expr, steps }));
+            }
+            expr = rewrite.expr;
+            steps += 1;
+        }
+        Ok(Normalization { expr, steps })
+    }
+
+    /// Returns every rewrite of `expr` by `rule` at any position, in the order given by the
+    /// strategy.
+    pub fn rewrite_all_positions<E, R>(expr: &E, rule: &R, config: &Config<E>) -> Vec<(Position, E)>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        let cases = expr.cases();
+        positions(&cases, config.strategy)
+            .into_iter()
+            .filter_map(move |position| {
+                let rewritten = replace_at(&cases, &position, |e| apply_at_root(e, rule, config))?;
+                Some((position, rewritten))
+            })
+            .collect()
+    }
+}
+
+/// Completion Module
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod completion {
+    use {
+        super::*,
+        crate::{
+            fuel::{Exhaustion, Fuel},
+            rewrite::{self, Position},
+            rule::{RuleId, RuleSet},
+        },
+        alloc::{format, vec::Vec},
+        core::fmt,
+    };
+
+    /// Critical Pair
+    ///
+    /// An overlap between two rules read as term rewrite rules: the [`peak`](Self::peak)
+    /// rewrites in one step both to [`left`](Self::left), using the first rule at the root, and
+    /// to [`right`](Self::right), using the second rule at [`position`](Self::position).
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub struct CriticalPair<E> {
+        /// Common ancestor of both sides
+        pub peak: E,
+
+        /// Result of rewriting the peak with the first rule
+        pub left: E,
+
+        /// Result of rewriting the peak with the second rule
+        pub right: E,
+
+        /// Position of the overlap in the top of the first rule
+        pub position: Position,
+    }
+
+    impl<E> CriticalPair<E>
+    where
+        E: Expression,
+    {
+        /// Returns `true` if both sides of the critical pair are equal.
+        #[inline]
+        pub fn is_trivial(&self) -> bool
+        where
+            E::Atom: PartialEq,
+        {
+            expr::eq_ref::<E>(&self.left.cases(), &self.right.cases())
+        }
+
+        /// Converts the critical pair into the rule rewriting its left side into its right
+        /// side.
+        #[inline]
+        pub fn into_rule<R>(self) -> R
+        where
+            E::Group: Container<E>,
+            R: Rule<E>,
+        {
+            R::new(
+                Some(self.left).into_iter().collect(),
+                Some(self.right).into_iter().collect(),
+            )
+        }
+    }
+
+    /// Returns the single top and bottom elements of a rule, if it can be read as a term
+    /// rewrite rule.
+    pub(crate) fn as_term_rule<E, R>(rule: &R) -> Option<(E, E)>
+    where
+        E: Expression,
+        E::Atom: Clone,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        let (top, bot) = rule.ref_pair();
+        if top.len() != Some(1) || bot.len() != Some(1) {
+            return None;
+        }
+        Some((
+            top.iter().next()?.cases().to_owned(),
+            bot.iter().next()?.cases().to_owned(),
+        ))
+    }
+
+    /// Computes the critical pairs between `first` and `second` read as term rewrite rules.
+    ///
+    /// The variables of `second` are renamed apart using `rename` before unifying its top with
+    /// every non-variable subexpression of the top of `first`. Rules whose top or bottom do not
+    /// have exactly one element have no critical pairs.
+    pub fn critical_pairs<E, R1, R2, F, G>(
+        first: &R1,
+        second: &R2,
+        mut is_variable: F,
+        mut rename: G,
+    ) -> Vec<CriticalPair<E>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        R1: Rule<E>,
+        R2: Rule<E>,
+        F: FnMut(&E::Atom) -> bool,
+        G: FnMut(&E::Atom) -> E::Atom,
+    {
+        let mut pairs = Vec::new();
+        let ((l1, r1), (l2, r2)) = match (as_term_rule(first), as_term_rule(second)) {
+            (Some(first), Some(second)) => (first, second),
+            _ => return pairs,
+        };
+        let mut renaming = |atom: &E::Atom| {
+            if is_variable(atom) {
+                E::from_atom(rename(atom))
+            } else {
+                E::from_atom(atom.clone())
+            }
+        };
+        let l2 = l2.substitute_ref(&mut renaming);
+        let r2 = r2.substitute_ref(&mut renaming);
+        let l1_cases = l1.cases();
+        for position in rewrite::positions(&l1_cases, rewrite::Strategy::Outermost) {
+            let subexpression = match rewrite::subexpression_at(&l1_cases, &position) {
+                Some(subexpression) => subexpression,
+                _ => continue,
+            };
+            if matches!(subexpression.cases(), ExprRef::Atom(atom) if is_variable(atom)) {
+                continue;
+            }
+            let unifier = match substitution::unify(&subexpression, &l2, &mut is_variable) {
+                Some(unifier) => unifier,
+                _ => continue,
+            };
+            let peak = unifier.apply_ref(&l1);
+            let contractum = unifier.apply_ref(&r2);
+            if let Some(right) =
+                rewrite::replace_at(&peak.cases(), &position, move |_| Some(contractum))
+            {
+                pairs.push(CriticalPair {
+                    left: unifier.apply_ref(&r1),
+                    right,
+                    peak,
+                    position,
+                });
+            }
+        }
+        pairs
+    }
+
+    /// Unjoinable Critical Pair
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub struct Unjoinable<E> {
+        /// Identifier of the rule applied at the root of the peak
+        pub first: RuleId,
+
+        /// Identifier of the rule applied inside the peak
+        pub second: RuleId,
+
+        /// Critical pair which could not be joined
+        pub pair: CriticalPair<E>,
+
+        /// Normal form reached from the left side
+        pub left: E,
+
+        /// Normal form reached from the right side
+        pub right: E,
+
+        /// Set to `true` if both normalizations converged within the budget
+        pub converged: bool,
+    }
+
+    /// Returns the critical pairs of every ordered pair of rules in `rules`, including the
+    /// overlaps of each rule with itself, tagged with the identifiers of the two rules.
+    pub fn all_critical_pairs<E, R, G>(
+        rules: &RuleSet<E, R>,
+        is_variable: fn(&E::Atom) -> bool,
+        mut rename: G,
+    ) -> Vec<(RuleId, RuleId, CriticalPair<E>)>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        G: FnMut(&E::Atom) -> E::Atom,
+    {
+        let mut pairs = Vec::new();
+        for first in rules {
+            for second in rules {
+                pairs.extend(
+                    critical_pairs(&first.rule, &second.rule, is_variable, &mut rename)
+                        .into_iter()
+                        .filter(|pair| !(first.id == second.id && pair.position.is_empty()))
+                        .map(|pair| (first.id, second.id, pair)),
+                );
+            }
+        }
+        pairs
+    }
+
+    /// Checks that every non-trivial critical pair of `rules` is joinable, that is, that both
+    /// of its sides normalize to the same expression using at most `budget` steps each.
+    ///
+    /// Rules are read as term rewrite rules, whatever the [`Reading`](rewrite::Reading) of
+    /// `config`. Returns the critical pairs which could not be joined on failure.
+    pub fn is_locally_confluent<E, R, G>(
+        rules: &RuleSet<E, R>,
+        config: &rewrite::Config<E>,
+        rename: G,
+        budget: usize,
+    ) -> Result<(), Vec<Unjoinable<E>>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        G: FnMut(&E::Atom) -> E::Atom,
+    {
+        let config = config.reading(rewrite::Reading::Term);
+        let unjoinable = all_critical_pairs(rules, config.is_variable, rename)
+            .into_iter()
+            .filter(|(_, _, pair)| !pair.is_trivial())
+            .filter_map(|(first, second, pair)| {
+                let normalize = |expr: &E| {
+                    let mut fuel = Fuel::unlimited().with_steps(budget);
+                    match rewrite::normalize(expr.clone(), rules, &config, &mut fuel) {
+                        Ok(normalization) => (normalization.expr, true),
+                        Err(exhausted) => (exhausted.partial.expr, false),
+                    }
+                };
+                let (left, left_converged) = normalize(&pair.left);
+                let (right, right_converged) = normalize(&pair.right);
+                if expr::eq_ref::<E>(&left.cases(), &right.cases()) {
+                    return None;
+                }
+                Some(Unjoinable {
+                    first,
+                    second,
+                    pair,
+                    converged: left_converged && right_converged,
+                    left,
+                    right,
+                })
+            })
+            .collect::<Vec<_>>();
+        if unjoinable.is_empty() {
+            Ok(())
+        } else {
+            Err(unjoinable)
+        }
+    }
+
+    /// Completion Failure Reason
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub enum CompletionError<E> {
+        /// An equation between two distinct normal forms could not be oriented by the ordering.
+        Unorientable(E, E),
+
+        /// The fuel ran out before every equation was processed.
+        Exhausted(Exhaustion),
+    }
+
+    impl<E> fmt::Display for CompletionError<E> {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::Unorientable(..) => f.write_str("equation could not be oriented"),
+                Self::Exhausted(reason) => write!(f, "completion stopped: {}", reason),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl<E> std::error::Error for CompletionError<E> where E: fmt::Debug {}
+
+    /// Incomplete Completion
+    #[derive(Clone, Debug)]
+    pub struct Incomplete<E>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+    {
+        /// Reason for the failure
+        pub error: CompletionError<E>,
+
+        /// Rules oriented before the failure
+        pub rules: RuleSet<E>,
+
+        /// Equations which were not processed, including the one which caused the failure
+        pub equations: Vec<(E, E)>,
+    }
+
+    /// Runs a Knuth–Bendix-style completion procedure over `rules`, read as equations between
+    /// their single top and bottom elements.
+    ///
+    /// Equations are normalized with the current rules, oriented with `ordering`, and every new
+    /// rule contributes its critical pairs as new equations, while the rules whose top it
+    /// rewrites are turned back into equations. Processing an equation and every rewrite step
+    /// of the normalizations consume one step of `fuel`, and every oriented rule consumes one
+    /// node. On success, returns a rule set which is
+    /// terminating, if `ordering` is a reduction ordering, and locally confluent.
+    ///
+    /// Only normal forms are oriented: a normalization cut off by `fuel` fails with
+    /// [`Exhausted`](CompletionError::Exhausted), and an equation between normal forms which
+    /// `ordering` cannot orient fails with [`Unorientable`](CompletionError::Unorientable)
+    /// holding the normal forms. In both cases the equation being processed is put back into
+    /// the unprocessed [`equations`](Incomplete::equations).
+    pub fn complete<E, R, O, G>(
+        rules: &RuleSet<E, R>,
+        ordering: &O,
+        is_variable: fn(&E::Atom) -> bool,
+        mut rename: G,
+        fuel: &mut Fuel,
+    ) -> Result<RuleSet<E>, Incomplete<E>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        O: ordering::TermOrdering<E>,
+        G: FnMut(&E::Atom) -> E::Atom,
+    {
+        let config = rewrite::Config::new(is_variable);
+        let mut equations = rules
+            .iter()
+            .filter_map(move |entry| as_term_rule(&entry.rule))
+            .collect::<Vec<_>>();
+        let mut system = RuleSet::<E>::new();
+        let mut oriented = 0;
+        while let Some((original_lhs, original_rhs)) = equations.pop() {
+            let normalized = fuel.step().and_then(|_| {
+                let lhs = rewrite::normalize(original_lhs.clone(), &system, &config, fuel)
+                    .map_err(|exhausted| exhausted.reason)?;
+                let rhs = rewrite::normalize(original_rhs.clone(), &system, &config, fuel)
+                    .map_err(|exhausted| exhausted.reason)?;
+                Ok((lhs.expr, rhs.expr))
+            });
+            let (lhs, rhs) = match normalized {
+                Ok(pair) => pair,
+                Err(reason) => {
+                    equations.push((original_lhs, original_rhs));
+                    return Err(Incomplete {
+                        error: CompletionError::Exhausted(reason),
+                        rules: system,
+                        equations,
+                    });
+                }
+            };
+            if expr::eq_ref::<E>(&lhs.cases(), &rhs.cases()) {
+                continue;
+            }
+            let (lhs, rhs) = if ordering.greater(&lhs, &rhs) {
+                (lhs, rhs)
+            } else if ordering.greater(&rhs, &lhs) {
+                (rhs, lhs)
+            } else {
+                equations.push((original_lhs, original_rhs));
+                return Err(Incomplete {
+                    error: CompletionError::Unorientable(lhs, rhs),
+                    rules: system,
+                    equations,
+                });
+            };
+            if let Err(reason) = fuel.node() {
+                equations.push((lhs, rhs));
+                return Err(Incomplete {
+                    error: CompletionError::Exhausted(reason),
+                    rules: system,
+                    equations,
+                });
+            }
+            let rule = rule::Structure::<E>::new(
+                Some(lhs).into_iter().collect(),
+                Some(rhs).into_iter().collect(),
+            );
+            let single = RuleSet::<E>::from_iter(Some(("", rule)));
+            let mut collapsed = Vec::new();
+            for entry in &system {
+                if let Some((l, r)) = as_term_rule(&entry.rule) {
+                    if rewrite::rewrite_once(&l, &single, &config).is_some() {
+                        collapsed.push(entry.id);
+                        equations.push((l, r));
+                    }
+                }
+            }
+            for id in collapsed {
+                system.remove(id);
+            }
+            let rule = match single.into_iter().next() {
+                Some(entry) => entry.rule,
+                _ => continue,
+            };
+            oriented += 1;
+            let id = system.insert(format!("completion-{}", oriented), rule);
+            if let Some(new) = system.rule(id) {
+                for entry in &system {
+                    for pair in critical_pairs(new, &entry.rule, is_variable, &mut rename)
+                        .into_iter()
+                        .chain(critical_pairs(&entry.rule, new, is_variable, &mut rename))
+                    {
+                        if !pair.is_trivial() {
+                            equations.push((pair.left, pair.right));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(system)
+    }
+
+    #[cfg(all(test, feature = "simple"))]
+    mod tests {
+        use {
+            super::*,
+            crate::{expr::SimpleExpr, ordering::Lpo, rule::Structure},
+            alloc::{string::String, vec},
+        };
+
+        type E = SimpleExpr;
+
+        fn is_variable(atom: &String) -> bool {
+            atom.starts_with('?')
+        }
+
+        fn rename(atom: &String) -> String {
+            format!("{}'", atom)
+        }
+
+        fn precedence(atom: &String) -> usize {
+            match atom.as_str() {
+                "a" => 3,
+                "f" => 2,
+                "c" => 1,
+                _ => 0,
+            }
+        }
+
+        fn atom(atom: &str) -> E {
+            E::atom(atom)
+        }
+
+        fn group<const N: usize>(items: [E; N]) -> E {
+            E::group(IntoIterator::into_iter(items))
+        }
+
+        fn rule(top: E, bot: E) -> Structure<E> {
+            Structure::new(vec![top], vec![bot])
+        }
+
+        fn normalize(expr: E, rules: &RuleSet<E>) -> E {
+            let config = rewrite::Config::new(is_variable);
+            rewrite::normalize(expr, rules, &config, &mut Fuel::unlimited())
+                .unwrap()
+                .expr
+        }
+
+        fn overlapping() -> RuleSet<E> {
+            let mut rules = RuleSet::new();
+            rules.insert(
+                "cancel",
+                rule(
+                    group([atom("f"), group([atom("g"), atom("?x")])]),
+                    atom("?x"),
+                ),
+            );
+            rules.insert("constant", rule(group([atom("g"), atom("a")]), atom("b")));
+            rules
+        }
+
+        #[test]
+        fn critical_pair_of_overlapping_rules() {
+            let rules = overlapping();
+            let pairs = all_critical_pairs(&rules, is_variable, rename);
+            assert_eq!(pairs.len(), 1);
+            let (first, second, pair) = &pairs[0];
+            assert_eq!((*first, *second), (0, 1));
+            assert_eq!(pair.peak, group([atom("f"), group([atom("g"), atom("a")])]));
+            assert_eq!(pair.left, atom("a"));
+            assert_eq!(pair.right, group([atom("f"), atom("b")]));
+            assert!(!pair.is_trivial());
+        }
+
+        #[test]
+        fn critical_pairs_need_term_rules() {
+            let rule = Structure::<E>::new(vec![atom("a"), atom("b")], vec![atom("c")]);
+            assert!(critical_pairs(&rule, &rule, is_variable, rename).is_empty());
+        }
+
+        #[test]
+        fn local_confluence() {
+            let mut rules = overlapping();
+            let config = rewrite::Config::new(is_variable);
+            let unjoinable = is_locally_confluent(&rules, &config, rename, 16).unwrap_err();
+            assert_eq!(unjoinable.len(), 1);
+            assert_eq!(unjoinable[0].left, atom("a"));
+            assert_eq!(unjoinable[0].right, group([atom("f"), atom("b")]));
+            assert!(unjoinable[0].converged);
+            rules.insert("join", rule(group([atom("f"), atom("b")]), atom("a")));
+            assert_eq!(is_locally_confluent(&rules, &config, rename, 16), Ok(()));
+        }
+
+        #[test]
+        fn completion_orients_and_collapses() {
+            let mut rules = RuleSet::<E>::new();
+            rules.insert("shrink", rule(atom("a"), atom("c")));
+            rules.insert("apply", rule(group([atom("f"), atom("a")]), atom("b")));
+            let ordering = Lpo::new(is_variable, precedence);
+            let completed = complete(
+                &rules,
+                &ordering,
+                is_variable,
+                rename,
+                &mut Fuel::unlimited(),
+            )
+            .unwrap();
+            assert_eq!(completed.len(), 2);
+            assert_eq!(
+                normalize(group([atom("f"), atom("a")]), &completed),
+                atom("b")
+            );
+            assert_eq!(
+                normalize(group([atom("f"), atom("c")]), &completed),
+                atom("b")
+            );
+            let config = rewrite::Config::new(is_variable);
+            assert_eq!(
+                is_locally_confluent(&completed, &config, rename, 16),
+                Ok(())
+            );
+        }
+
+        #[test]
+        fn completion_rejects_unorientable_equations() {
+            let mut rules = RuleSet::<E>::new();
+            rules.insert(
+                "commute",
+                rule(
+                    group([atom("f"), atom("?x"), atom("?y")]),
+                    group([atom("f"), atom("?y"), atom("?x")]),
+                ),
+            );
+            let ordering = Lpo::new(is_variable, precedence);
+            let incomplete = complete(
+                &rules,
+                &ordering,
+                is_variable,
+                rename,
+                &mut Fuel::unlimited(),
+            )
+            .unwrap_err();
+            assert!(matches!(
+                incomplete.error,
+                CompletionError::Unorientable(..)
+            ));
+            assert!(incomplete.rules.is_empty());
+            assert_eq!(incomplete.equations.len(), 1);
+        }
+
+        #[test]
+        fn completion_stops_when_out_of_fuel() {
+            let mut rules = RuleSet::<E>::new();
+            rules.insert("shrink", rule(atom("a"), atom("c")));
+            rules.insert("apply", rule(group([atom("f"), atom("a")]), atom("b")));
+            let ordering = Lpo::new(is_variable, precedence);
+            let mut fuel = Fuel::unlimited().with_steps(1);
+            let incomplete =
+                complete(&rules, &ordering, is_variable, rename, &mut fuel).unwrap_err();
+            assert_eq!(
+                incomplete.error,
+                CompletionError::Exhausted(Exhaustion::Steps)
+            );
+            assert_eq!(incomplete.rules.len(), 1);
+            assert_eq!(incomplete.equations.len(), 1);
+        }
+    }
+}
+
+/// Term Ordering Module
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod ordering {
+    use {super::*, alloc::vec::Vec, core::cmp::Ordering};
+
+    /// Term Ordering Trait
+    ///
+    /// A strict ordering over expressions used to orient equations into rewrite rules. To
+    /// guarantee termination of the oriented rules, the ordering should be a reduction
+    /// ordering, that is, well-founded and closed under substitutions and contexts.
+    pub trait TermOrdering<E>
+    where
+        E: Expression,
+    {
+        /// Returns `true` if `lhs` is strictly greater than `rhs`.
+        fn greater(&self, lhs: &E, rhs: &E) -> bool;
+    }
+
+    impl<E, F> TermOrdering<E> for F
+    where
+        E: Expression,
+        F: Fn(&E, &E) -> bool,
+    {
+        #[inline]
+        fn greater(&self, lhs: &E, rhs: &E) -> bool {
+            self(lhs, rhs)
+        }
+    }
+
+    /// Head Symbol of an Expression
+    ///
+    /// A group whose first element is an atom is read as the application of that atom to the
+    /// remaining elements, any other group is read as an anonymous tuple, and an atom is read
+    /// as a constant.
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum Symbol<A> {
+        /// Constant Atom
+        Atom(A),
+
+        /// Application of an atom to the given number of arguments
+        Apply(A, usize),
+
+        /// Tuple with the given number of elements
+        Tuple(usize),
+    }
+
+    impl<A> Symbol<A> {
+        /// Returns the atom naming the symbol, if it has one.
+        #[inline]
+        pub fn atom(&self) -> Option<&A> {
+            match self {
+                Self::Atom(atom) | Self::Apply(atom, _) => Some(atom),
+                _ => None,
+            }
+        }
+
+        /// Returns the number of arguments of the symbol.
+        #[inline]
+        pub fn arity(&self) -> usize {
+            match self {
+                Self::Atom(_) => 0,
+                Self::Apply(_, arity) | Self::Tuple(arity) => *arity,
+            }
+        }
+    }
+
+    /// Splits `expr` into its head [`Symbol`] and its arguments.
+    pub fn decompose<E>(expr: &E) -> (Symbol<E::Atom>, Vec<E>)
+    where
+        E: Expression,
+        E::Atom: Clone,
+        E::Group: FromIterator<E>,
+    {
+        match expr.cases() {
+            ExprRef::Atom(atom) => (Symbol::Atom(atom.clone()), Vec::new()),
+            ExprRef::Group(group) => {
+                let mut elements = group.iter().map(move |e| e.cases().to_owned());
+                let first = match elements.next() {
+                    Some(first) => first,
+                    _ => return (Symbol::Tuple(0), Vec::new()),
+                };
+                match first.atom_ref() {
+                    Some(head) => {
+                        let head = head.clone();
+                        let args = elements.collect::<Vec<_>>();
+                        (Symbol::Apply(head, args.len()), args)
+                    }
+                    _ => {
+                        let args = Some(first).into_iter().chain(elements).collect::<Vec<_>>();
+                        (Symbol::Tuple(args.len()), args)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the number of atoms and groups of `expr`.
+    pub fn size<E>(expr: &ExprRef<E>) -> usize
+    where
+        E: Expression,
+    {
+        match expr {
+            ExprRef::Atom(_) => 1,
+            ExprRef::Group(group) => 1 + group.iter().map(move |e| size(&e.cases())).sum::<usize>(),
+        }
+    }
+
+    /// Returns the nesting depth of `expr`, where atoms have depth zero.
+    pub fn depth<E>(expr: &ExprRef<E>) -> usize
+    where
+        E: Expression,
+    {
+        match expr {
+            ExprRef::Atom(_) => 0,
+            ExprRef::Group(group) => {
+                1 + group
+                    .iter()
+                    .map(move |e| depth(&e.cases()))
+                    .max()
+                    .unwrap_or(0)
+            }
+        }
+    }
+
+    /// Appends the variables of `expr`, with repetitions, to `vars`.
+    pub fn variables<E, F>(expr: &ExprRef<E>, vars: &mut Vec<E::Atom>, is_variable: &F)
+    where
+        E: Expression,
+        E::Atom: Clone,
+        F: Fn(&E::Atom) -> bool,
+    {
+        match expr {
+            ExprRef::Atom(atom) if is_variable(*atom) => vars.push((*atom).clone()),
+            ExprRef::Atom(_) => {}
+            ExprRef::Group(group) => {
+                for e in group.iter() {
+                    variables(&e.cases(), vars, is_variable);
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if every variable of `rhs` occurs in `lhs` at least as many times.
+    pub fn variables_contained<E, F>(lhs: &E, rhs: &E, is_variable: &F) -> bool
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        F: Fn(&E::Atom) -> bool,
+    {
+        let mut lhs_vars = Vec::new();
+        let mut rhs_vars = Vec::new();
+        variables(&lhs.cases(), &mut lhs_vars, is_variable);
+        variables(&rhs.cases(), &mut rhs_vars, is_variable);
+        for var in rhs_vars {
+            match lhs_vars.iter().position(move |v| *v == var) {
+                Some(index) => {
+                    lhs_vars.swap_remove(index);
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// Size Ordering
+    ///
+    /// Orders expressions by their [`size`], requiring the variables of the smaller side to
+    /// occur in the larger one so that the ordering is stable under substitutions.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct Size<F> {
+        /// Variable predicate
+        pub is_variable: F,
+    }
+
+    impl<E, F> TermOrdering<E> for Size<F>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        F: Fn(&E::Atom) -> bool,
+    {
+        #[inline]
+        fn greater(&self, lhs: &E, rhs: &E) -> bool {
+            size(&lhs.cases()) > size(&rhs.cases())
+                && variables_contained(lhs, rhs, &self.is_variable)
+        }
+    }
+
+    /// Compares two symbols using the atom `rank` function, returning `None` if the symbols
+    /// are distinct but have the same rank.
+    #[inline]
+    fn compare_symbols<A, P>(lhs: &Symbol<A>, rhs: &Symbol<A>, rank: &P) -> Option<Ordering>
+    where
+        A: PartialEq,
+        P: Fn(&A) -> usize,
+    {
+        if lhs == rhs {
+            return Some(Ordering::Equal);
+        }
+        let key = move |s: &Symbol<A>| (s.atom().map(rank), s.arity());
+        match key(lhs).cmp(&key(rhs)) {
+            Ordering::Equal => None,
+            ordering => Some(ordering),
+        }
+    }
+
+    /// Returns `true` if the variable expression `var` occurs in `expr` and is distinct from it.
+    #[inline]
+    fn properly_contains<E>(expr: &E, var: &E::Atom) -> bool
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        match expr.cases() {
+            ExprRef::Atom(_) => false,
+            cases => substitution::occurs::<E>(var, &cases),
+        }
+    }
+
+    /// Lexicographic Path Ordering
+    ///
+    /// Symbols are compared with the `precedence` rank function, where a greater rank means a
+    /// greater symbol. Tuples are smaller than every named symbol.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct Lpo<F, P> {
+        /// Variable predicate
+        pub is_variable: F,
+
+        /// Symbol precedence
+        pub precedence: P,
+    }
+
+    impl<F, P> Lpo<F, P> {
+        /// Builds a new [`Lpo`] ordering.
+        #[inline]
+        pub fn new(is_variable: F, precedence: P) -> Self {
+            Self {
+                is_variable,
+                precedence,
+            }
+        }
+    }
+
+    impl<E, F, P> TermOrdering<E> for Lpo<F, P>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E>,
+        F: Fn(&E::Atom) -> bool,
+        P: Fn(&E::Atom) -> usize,
+    {
+        fn greater(&self, lhs: &E, rhs: &E) -> bool {
+            if let ExprRef::Atom(atom) = rhs.cases() {
+                if (self.is_variable)(atom) {
+                    return properly_contains(lhs, atom);
+                }
+            }
+            if matches!(lhs.cases(), ExprRef::Atom(atom) if (self.is_variable)(atom)) {
+                return false;
+            }
+            let (f, lhs_args) = decompose(lhs);
+            if lhs_args
+                .iter()
+                .any(|arg| expr::eq_ref::<E>(&arg.cases(), &rhs.cases()) || self.greater(arg, rhs))
+            {
+                return true;
+            }
+            let (g, rhs_args) = decompose(rhs);
+            match compare_symbols(&f, &g, &self.precedence) {
+                Some(Ordering::Greater) => rhs_args.iter().all(|arg| self.greater(lhs, arg)),
+                Some(Ordering::Equal) => {
+                    rhs_args.iter().all(|arg| self.greater(lhs, arg))
+                        && lhs_args
+                            .iter()
+                            .zip(&rhs_args)
+                            .find(|(l, r)| !expr::eq_ref::<E>(&l.cases(), &r.cases()))
+                            .map_or(false, |(l, r)| self.greater(l, r))
+                }
+                _ => false,
+            }
+        }
+    }
+
+    /// Knuth–Bendix Ordering
+    ///
+    /// Every symbol has a weight given by `weight` and every variable has weight
+    /// `variable_weight`. Expressions are compared by total weight first, then by the
+    /// `precedence` of their head symbols, and finally lexicographically on their arguments.
+    /// The special case for unary symbols of weight zero is not supported, so `weight` should
+    /// be positive.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct Kbo<F, P, W> {
+        /// Variable predicate
+        pub is_variable: F,
+
+        /// Symbol precedence
+        pub precedence: P,
+
+        /// Symbol weights
+        pub weight: W,
+
+        /// Weight of every variable
+        pub variable_weight: usize,
+    }
+
+    impl<F, P, W> Kbo<F, P, W> {
+        /// Builds a new [`Kbo`] ordering with a variable weight of one.
+        #[inline]
+        pub fn new(is_variable: F, precedence: P, weight: W) -> Self {
+            Self {
+                is_variable,
+                precedence,
+                weight,
+                variable_weight: 1,
+            }
+        }
+    }
+
+    impl<F, P, W> Kbo<F, P, W> {
+        /// Computes the total weight of `expr`.
+        pub fn total_weight<E>(&self, expr: &ExprRef<E>) -> usize
+        where
+            E: Expression,
+            F: Fn(&E::Atom) -> bool,
+            W: Fn(&E::Atom) -> usize,
+        {
+            match expr {
+                ExprRef::Atom(atom) if (self.is_variable)(atom) => self.variable_weight,
+                ExprRef::Atom(atom) => (self.weight)(atom),
+                ExprRef::Group(group) => {
+                    let mut iter = group.iter();
+                    match iter.next() {
+                        Some(first) => {
+                            let first = first.cases();
+                            let head = match &first {
+                                ExprRef::Atom(atom) if !(self.is_variable)(atom) => {
+                                    (self.weight)(atom)
+                                }
+                                _ => 1 + self.total_weight(&first),
+                            };
+                            head + iter
+                                .map(move |e| self.total_weight(&e.cases()))
+                                .sum::<usize>()
+                        }
+                        _ => 1,
+                    }
+                }
+            }
+        }
+    }
+
+    impl<E, F, P, W> TermOrdering<E> for Kbo<F, P, W>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E>,
+        F: Fn(&E::Atom) -> bool,
+        P: Fn(&E::Atom) -> usize,
+        W: Fn(&E::Atom) -> usize,
+    {
+        fn greater(&self, lhs: &E, rhs: &E) -> bool {
+            if !variables_contained(lhs, rhs, &self.is_variable) {
+                return false;
+            }
+            let lhs_weight = self.total_weight(&lhs.cases());
+            let rhs_weight = self.total_weight(&rhs.cases());
+            if lhs_weight != rhs_weight {
+                return lhs_weight > rhs_weight;
+            }
+            if let ExprRef::Atom(atom) = rhs.cases() {
+                if (self.is_variable)(atom) {
+                    return properly_contains(lhs, atom);
+                }
+            }
+            let (f, lhs_args) = decompose(lhs);
+            let (g, rhs_args) = decompose(rhs);
+            match compare_symbols(&f, &g, &self.precedence) {
+                Some(Ordering::Greater) => true,
+                Some(Ordering::Equal) => lhs_args
+                    .iter()
+                    .zip(&rhs_args)
+                    .find(|(l, r)| !expr::eq_ref::<E>(&l.cases(), &r.cases()))
+                    .map_or(false, |(l, r)| self.greater(l, r)),
+                _ => false,
+            }
+        }
+    }
+}
+
+/// Congruence Closure Module
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod congruence {
+    use {
+        super::*,
+        crate::rule::{RuleId, RuleSet},
+        alloc::{vec, vec::Vec},
+        core::mem,
+    };
+
+    /// Term Identifier
+    pub type TermId = usize;
+
+    /// Term Node
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    enum Node<A> {
+        /// Atomic Term
+        Atom(A),
+
+        /// Group Term with the identifiers of its elements
+        Group(Vec<TermId>),
+    }
+
+    /// Reason for an Equality
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum Reason {
+        /// The equality was asserted as the equation with the given index.
+        Equation(usize),
+
+        /// The two group terms are equal because their elements are pairwise equal.
+        Congruence(TermId, TermId),
+    }
+
+    /// Congruence Closure
+    ///
+    /// Maintains the smallest congruence containing a set of ground equations, that is, the
+    /// smallest equivalence relation over terms which contains the equations and relates two
+    /// groups whenever their elements are pairwise related.
+    #[derive(Clone, Debug)]
+    pub struct CongruenceClosure<A> {
+        /// Interned terms
+        nodes: Vec<Node<A>>,
+
+        /// Union-find parent links
+        parent: Vec<TermId>,
+
+        /// Union-find class sizes
+        size: Vec<usize>,
+
+        /// Group terms which have an element in the class, indexed by representative
+        uses: Vec<Vec<TermId>>,
+
+        /// Proof forest edges
+        proof: Vec<Option<(TermId, Reason)>>,
+
+        /// Number of asserted equations
+        equations: usize,
+    }
+
+    impl<A> CongruenceClosure<A>
+    where
+        A: Clone + PartialEq,
+    {
+        /// Builds a new empty [`CongruenceClosure`].
+        #[inline]
+        pub fn new() -> Self {
+            Self {
+                nodes: Vec::new(),
+                parent: Vec::new(),
+                size: Vec::new(),
+                uses: Vec::new(),
+                proof: Vec::new(),
+                equations: 0,
+            }
+        }
+
+        /// Returns the number of interned terms.
+        #[inline]
+        pub fn len(&self) -> usize {
+            self.nodes.len()
+        }
+
+        /// Returns `true` if no terms were interned.
+        #[inline]
+        pub fn is_empty(&self) -> bool {
+            self.nodes.is_empty()
+        }
+
+        /// Returns the number of asserted equations.
+        #[inline]
+        pub fn equation_count(&self) -> usize {
+            self.equations
+        }
+
+        /// Returns the representative of the class of `term`.
+        #[inline]
+        pub fn find(&self, mut term: TermId) -> TermId {
+            while self.parent[term] != term {
+                term = self.parent[term];
+            }
+            term
+        }
+
+        /// Returns the identifier of `expr` if it was already interned.
+        pub fn lookup<E>(&self, expr: &ExprRef<E>) -> Option<TermId>
+        where
+            E: Expression<Atom = A>,
+        {
+            let node = match expr {
+                ExprRef::Atom(atom) => Node::Atom((*atom).clone()),
+                ExprRef::Group(group) => Node::Group(
+                    group
+                        .iter()
+                        .map(move |e| self.lookup(&e.cases()))
+                        .collect::<Option<_>>()?,
+                ),
+            };
+            self.nodes.iter().position(move |n| *n == node)
+        }
+
+        /// Interns `expr` and returns its identifier.
+        pub fn add<E>(&mut self, expr: &ExprRef<E>) -> TermId
+        where
+            E: Expression<Atom = A>,
+        {
+            let node = match expr {
+                ExprRef::Atom(atom) => Node::Atom((*atom).clone()),
+                ExprRef::Group(group) => {
+                    Node::Group(group.iter().map(|e| self.add(&e.cases())).collect())
+                }
+            };
+            if let Some(id) = self.nodes.iter().position(|n| *n == node) {
+                return id;
+            }
+            let id = self.nodes.len();
+            if let Node::Group(elements) = &node {
+                for element in elements {
+                    let root = self.find(*element);
+                    if !self.uses[root].contains(&id) {
+                        self.uses[root].push(id);
+                    }
+                }
+            }
+            self.nodes.push(node);
+            self.parent.push(id);
+            self.size.push(1);
+            self.uses.push(Vec::new());
+            self.proof.push(None);
+            let congruent = (0..id).find(|other| self.is_congruent(id, *other));
+            if let Some(other) = congruent {
+                self.propagate(vec![(id, other, Reason::Congruence(id, other))]);
+            }
+            id
+        }
+
+        /// Returns `true` if the two terms are groups whose elements are pairwise equal.
+        fn is_congruent(&self, lhs: TermId, rhs: TermId) -> bool {
+            match (&self.nodes[lhs], &self.nodes[rhs]) {
+                (Node::Group(l), Node::Group(r)) => {
+                    l.len() == r.len()
+                        && l.iter().zip(r).all(|(l, r)| self.find(*l) == self.find(*r))
+                }
+                _ => false,
+            }
+        }
+
+        /// Makes `term` the root of its proof tree by reversing the edges along its path.
+        fn reroot(&mut self, term: TermId) {
+            let mut current = term;
+            let mut previous = None;
+            while let Some(next) = mem::replace(&mut self.proof[current], previous) {
+                let (target, reason) = next;
+                previous = Some((current, reason));
+                current = target;
+            }
+        }
+
+        /// Merges the classes of the pending pairs and of the pairs they make congruent.
+        fn propagate(&mut self, mut pending: Vec<(TermId, TermId, Reason)>) {
+            while let Some((lhs, rhs, reason)) = pending.pop() {
+                let (mut l, mut r) = (self.find(lhs), self.find(rhs));
+                if l == r {
+                    continue;
+                }
+                self.reroot(lhs);
+                self.proof[lhs] = Some((rhs, reason));
+                if self.size[l] > self.size[r] {
+                    mem::swap(&mut l, &mut r);
+                }
+                self.parent[l] = r;
+                self.size[r] += self.size[l];
+                let moved = mem::take(&mut self.uses[l]);
+                for u in &moved {
+                    for v in &self.uses[r] {
+                        if self.find(*u) != self.find(*v) && self.is_congruent(*u, *v) {
+                            pending.push((*u, *v, Reason::Congruence(*u, *v)));
+                        }
+                    }
+                }
+                for u in moved {
+                    if !self.uses[r].contains(&u) {
+                        self.uses[r].push(u);
+                    }
+                }
+            }
+        }
+
+        /// Asserts the ground equation `lhs = rhs` and returns its index.
+        pub fn assert_equal<E>(&mut self, lhs: &E, rhs: &E) -> usize
+        where
+            E: Expression<Atom = A>,
+        {
+            let l = self.add(&lhs.cases());
+            let r = self.add(&rhs.cases());
+            let index = self.equations;
+            self.equations += 1;
+            self.propagate(vec![(l, r, Reason::Equation(index))]);
+            index
+        }
+
+        /// Asserts the equation between the single top and bottom elements of `rule`, returning
+        /// its index, or `None` if the rule does not have singleton sides.
+        pub fn assert_rule<E, R>(&mut self, rule: &R) -> Option<usize>
+        where
+            E: Expression<Atom = A>,
+            E::Group: Container<E>,
+            R: Rule<E>,
+        {
+            let (lhs, rhs) = completion::as_term_rule(rule)?;
+            Some(self.assert_equal(&lhs, &rhs))
+        }
+
+        /// Asserts the equations of the rules of `rules` with singleton sides, returning the
+        /// pairs `(rule, equation)` of the asserted rules.
+        pub fn assert_rule_set<E, R>(&mut self, rules: &RuleSet<E, R>) -> Vec<(RuleId, usize)>
+        where
+            E: Expression<Atom = A>,
+            E::Group: Container<E>,
+            R: Rule<E>,
+        {
+            rules
+                .iter()
+                .filter_map(|entry| Some((entry.id, self.assert_rule(&entry.rule)?)))
+                .collect()
+        }
+
+        /// Returns `true` if `lhs` and `rhs` are equal in the congruence closure.
+        #[inline]
+        pub fn are_equal<E>(&mut self, lhs: &E, rhs: &E) -> bool
+        where
+            E: Expression<Atom = A>,
+        {
+            let l = self.add(&lhs.cases());
+            let r = self.add(&rhs.cases());
+            self.find(l) == self.find(r)
+        }
+
+        /// Returns the indices, in increasing order, of the equations which imply that `lhs`
+        /// and `rhs` are equal, or `None` if they are not equal.
+        pub fn explain<E>(&mut self, lhs: &E, rhs: &E) -> Option<Vec<usize>>
+        where
+            E: Expression<Atom = A>,
+        {
+            let l = self.add(&lhs.cases());
+            let r = self.add(&rhs.cases());
+            let mut equations = Vec::new();
+            self.explain_terms(l, r, &mut equations)?;
+            equations.sort_unstable();
+            equations.dedup();
+            Some(equations)
+        }
+
+        /// Collects the equations along the proof path between `lhs` and `rhs`.
+        fn explain_terms(
+            &self,
+            lhs: TermId,
+            rhs: TermId,
+            equations: &mut Vec<usize>,
+        ) -> Option<()> {
+            if self.find(lhs) != self.find(rhs) {
+                return None;
+            }
+            let mut pending = vec![(lhs, rhs)];
+            while let Some((lhs, rhs)) = pending.pop() {
+                let lhs_path = self.proof_path(lhs);
+                let rhs_path = self.proof_path(rhs);
+                let common = lhs_path
+                    .iter()
+                    .find(|(t, _)| rhs_path.iter().any(|(u, _)| u == t))
+                    .map(|(t, _)| *t)?;
+                for path in [lhs_path, rhs_path] {
+                    for (_, reason) in path.into_iter().take_while(|(t, _)| *t != common) {
+                        match reason {
+                            Some(Reason::Equation(index)) => equations.push(index),
+                            Some(Reason::Congruence(u, v)) => {
+                                if let (Node::Group(l), Node::Group(r)) =
+                                    (&self.nodes[u], &self.nodes[v])
+                                {
+                                    pending.extend(l.iter().copied().zip(r.iter().copied()));
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            Some(())
+        }
+
+        /// Returns the terms from `term` to the root of its proof tree, paired with the reason
+        /// of the edge leaving each term.
+        fn proof_path(&self, term: TermId) -> Vec<(TermId, Option<Reason>)> {
+            let mut path = Vec::new();
+            let mut current = term;
+            loop {
+                match self.proof[current] {
+                    Some((next, reason)) => {
+                        path.push((current, Some(reason)));
+                        current = next;
+                    }
+                    _ => {
+                        path.push((current, None));
+                        return path;
+                    }
+                }
+            }
+        }
+    }
+
+    impl<A> Default for CongruenceClosure<A>
+    where
+        A: Clone + PartialEq,
+    {
+        #[inline]
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+}
+
+/// Equality Saturation Module
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod saturation {
+    use {
+        super::*,
+        crate::rule::RuleSet,
+        alloc::{vec, vec::Vec},
+    };
+
+    /// E-Class Identifier
+    pub type ClassId = usize;
+
+    /// E-Node
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub enum ENode<A> {
+        /// Atomic Node
+        Atom(A),
+
+        /// Group Node with the e-classes of its elements
+        Group(Vec<ClassId>),
+    }
+
+    /// Pattern Variable Bindings
+    pub type Bindings<A> = Vec<(A, ClassId)>;
+
+    /// Saturation Limits
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct Limits {
+        /// Maximum number of iterations
+        pub iterations: usize,
+
+        /// Maximum number of e-nodes
+        pub nodes: usize,
+    }
+
+    impl Default for Limits {
+        #[inline]
+        fn default() -> Self {
+            Self {
+                iterations: 32,
+                nodes: 10_000,
+            }
+        }
+    }
+
+    /// Saturation Stop Reason
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum StopReason {
+        /// No rule application changed the e-graph.
+        Saturated,
+
+        /// The iteration limit was reached.
+        IterationLimit,
+
+        /// The node limit was reached.
+        NodeLimit,
+    }
+
+    /// Saturation Report
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct Report {
+        /// Number of iterations performed
+        pub iterations: usize,
+
+        /// Reason the saturation stopped
+        pub stop: StopReason,
+    }
+
+    /// E-Graph
+    ///
+    /// Stores a set of expressions together with an equivalence relation over them which is
+    /// closed under congruence. Rules are read as term rewrite rules, with a single top and
+    /// bottom element, and applying a rule merges the classes of both sides instead of
+    /// replacing one by the other. Rule [guards](Rule::allows) and premises are not consulted.
+    #[derive(Clone, Debug)]
+    pub struct EGraph<A> {
+        /// E-nodes paired with their e-class
+        nodes: Vec<(ENode<A>, ClassId)>,
+
+        /// Union-find parent links
+        parent: Vec<ClassId>,
+    }
+
+    impl<A> EGraph<A>
+    where
+        A: Clone + PartialEq,
+    {
+        /// Builds a new empty [`EGraph`].
+        #[inline]
+        pub fn new() -> Self {
+            Self {
+                nodes: Vec::new(),
+                parent: Vec::new(),
+            }
+        }
+
+        /// Returns the number of e-nodes.
+        #[inline]
+        pub fn node_count(&self) -> usize {
+            self.nodes.len()
+        }
+
+        /// Returns the number of e-classes.
+        #[inline]
+        pub fn class_count(&self) -> usize {
+            (0..self.parent.len())
+                .filter(move |c| self.parent[*c] == *c)
+                .count()
+        }
+
+        /// Returns the canonical representative of `class`.
+        #[inline]
+        pub fn find(&self, mut class: ClassId) -> ClassId {
+            while self.parent[class] != class {
+                class = self.parent[class];
+            }
+            class
+        }
+
+        /// Returns an iterator over the canonical e-classes.
+        #[inline]
+        pub fn classes(&self) -> impl '_ + Iterator<Item = ClassId> {
+            (0..self.parent.len()).filter(move |c| self.parent[*c] == *c)
+        }
+
+        /// Returns an iterator over the e-nodes of `class`.
+        #[inline]
+        pub fn nodes(&self, class: ClassId) -> impl '_ + Iterator<Item = &ENode<A>> {
+            let class = self.find(class);
+            self.nodes
+                .iter()
+                .filter(move |(_, c)| self.find(*c) == class)
+                .map(move |(n, _)| n)
+        }
+
+        /// Rewrites the children of `node` to their canonical classes.
+        #[inline]
+        fn canonicalize(&self, node: ENode<A>) -> ENode<A> {
+            match node {
+                ENode::Group(children) => {
+                    ENode::Group(children.into_iter().map(|c| self.find(c)).collect())
+                }
+                node => node,
+            }
+        }
+
+        /// Adds an e-node and returns its e-class.
+        pub fn add_node(&mut self, node: ENode<A>) -> ClassId {
+            let node = self.canonicalize(node);
+            if let Some((_, class)) = self.nodes.iter().find(|(n, _)| *n == node) {
+                return self.find(*class);
+            }
+            let class = self.parent.len();
+            self.parent.push(class);
+            self.nodes.push((node, class));
+            class
+        }
+
+        /// Adds `expr` and returns its e-class.
+        pub fn add<E>(&mut self, expr: &ExprRef<E>) -> ClassId
+        where
+            E: Expression<Atom = A>,
+        {
+            let node = match expr {
+                ExprRef::Atom(atom) => ENode::Atom((*atom).clone()),
+                ExprRef::Group(group) => {
+                    ENode::Group(group.iter().map(|e| self.add(&e.cases())).collect())
+                }
+            };
+            self.add_node(node)
+        }
+
+        /// Returns the e-class of `expr` if it is represented in the e-graph.
+        pub fn lookup<E>(&self, expr: &ExprRef<E>) -> Option<ClassId>
+        where
+            E: Expression<Atom = A>,
+        {
+            let node = match expr {
+                ExprRef::Atom(atom) => ENode::Atom((*atom).clone()),
+                ExprRef::Group(group) => ENode::Group(
+                    group
+                        .iter()
+                        .map(|e| self.lookup(&e.cases()))
+                        .collect::<Option<_>>()?,
+                ),
+            };
+            let node = self.canonicalize(node);
+            self.nodes
+                .iter()
+                .find(|(n, _)| self.canonicalize(n.clone()) == node)
+                .map(|(_, c)| self.find(*c))
+        }
+
+        /// Merges two e-classes, returning `true` if they were distinct.
+        ///
+        /// Call [`rebuild`](Self::rebuild) afterwards to restore the congruence invariant.
+        #[inline]
+        pub fn union(&mut self, lhs: ClassId, rhs: ClassId) -> bool {
+            let (lhs, rhs) = (self.find(lhs), self.find(rhs));
+            if lhs == rhs {
+                return false;
+            }
+            let (root, child) = if lhs < rhs { (lhs, rhs) } else { (rhs, lhs) };
+            self.parent[child] = root;
+            true
+        }
+
+        /// Restores the congruence invariant by merging the e-classes of e-nodes which became
+        /// equal, and removes duplicate e-nodes.
+        pub fn rebuild(&mut self) {
+            loop {
+                let mut merged = false;
+                let nodes = core::mem::take(&mut self.nodes);
+                let mut canonical = Vec::<(ENode<A>, ClassId)>::with_capacity(nodes.len());
+                for (node, class) in nodes {
+                    let node = self.canonicalize(node);
+                    let class = self.find(class);
+                    match canonical.iter().find(|(n, _)| *n == node) {
+                        Some((_, other)) => {
+                            let other = *other;
+                            merged |= self.union(class, other);
+                        }
+                        _ => canonical.push((node, class)),
+                    }
+                }
+                self.nodes = canonical;
+                if !merged {
+                    break;
+                }
+            }
+        }
+
+        /// Returns `true` if `lhs` and `rhs` are in the same e-class, adding them to the
+        /// e-graph if necessary.
+        #[inline]
+        pub fn are_equal<E>(&mut self, lhs: &E, rhs: &E) -> bool
+        where
+            E: Expression<Atom = A>,
+        {
+            let l = self.add(&lhs.cases());
+            let r = self.add(&rhs.cases());
+            self.rebuild();
+            self.find(l) == self.find(r)
+        }
+
+        /// Returns every way of extending `bindings` so that `pattern` matches some expression
+        /// represented by `class`.
+        pub fn ematch<E, F>(
+            &self,
+            pattern: &ExprRef<E>,
+            class: ClassId,
+            bindings: Bindings<A>,
+            is_variable: &mut F,
+        ) -> Vec<Bindings<A>>
+        where
+            E: Expression<Atom = A>,
+            F: FnMut(&A) -> bool,
+        {
+            let class = self.find(class);
+            match pattern {
+                ExprRef::Atom(atom) if is_variable(*atom) => {
+                    match bindings.iter().find(|(v, _)| v == *atom) {
+                        Some((_, bound)) if self.find(*bound) == class => vec![bindings],
+                        Some(_) => Vec::new(),
+                        _ => {
+                            let mut bindings = bindings;
+                            bindings.push(((*atom).clone(), class));
+                            vec![bindings]
+                        }
+                    }
+                }
+                ExprRef::Atom(atom) => {
+                    if self
+                        .nodes(class)
+                        .any(move |n| matches!(n, ENode::Atom(a) if a == *atom))
+                    {
+                        vec![bindings]
+                    } else {
+                        Vec::new()
+                    }
+                }
+                ExprRef::Group(group) => {
+                    let mut results = Vec::new();
+                    for node in self.nodes(class) {
+                        let children = match node {
+                            ENode::Group(children) if Some(children.len()) == group.len() => {
+                                children
+                            }
+                            _ => continue,
+                        };
+                        let mut partial = vec![bindings.clone()];
+                        for (p, c) in group.iter().zip(children) {
+                            partial = partial
+                                .into_iter()
+                                .flat_map(|b| self.ematch(&p.cases(), *c, b, is_variable))
+                                .collect();
+                        }
+                        results.extend(partial);
+                    }
+                    results
+                }
+            }
+        }
+
+        /// Adds the instantiation of `template` with `bindings` and returns its e-class.
+        pub fn instantiate<E, F>(
+            &mut self,
+            template: &ExprRef<E>,
+            bindings: &[(A, ClassId)],
+            is_variable: &mut F,
+        ) -> ClassId
+        where
+            E: Expression<Atom = A>,
+            F: FnMut(&A) -> bool,
+        {
+            match template {
+                ExprRef::Atom(atom) if is_variable(*atom) => {
+                    match bindings.iter().find(|(v, _)| v == *atom) {
+                        Some((_, class)) => self.find(*class),
+                        _ => self.add_node(ENode::Atom((*atom).clone())),
+                    }
+                }
+                ExprRef::Atom(atom) => self.add_node(ENode::Atom((*atom).clone())),
+                ExprRef::Group(group) => {
+                    let children = group
+                        .iter()
+                        .map(|e| self.instantiate(&e.cases(), bindings, is_variable))
+                        .collect();
+                    self.add_node(ENode::Group(children))
+                }
+            }
+        }
+
+        /// Applies the rules of `rules` until saturation or until one of the `limits` is
+        /// reached.
+        pub fn saturate<E, R, F>(
+            &mut self,
+            rules: &RuleSet<E, R>,
+            mut is_variable: F,
+            limits: Limits,
+        ) -> Report
+        where
+            E: Expression<Atom = A>,
+            E::Group: Container<E>,
+            R: Rule<E>,
+            F: FnMut(&A) -> bool,
+        {
+            let rules = rules
+                .iter()
+                .filter_map(move |entry| completion::as_term_rule(&entry.rule))
+                .collect::<Vec<_>>();
+            for iteration in 0..limits.iterations {
+                let mut matches = Vec::new();
+                for (index, (top, _)) in rules.iter().enumerate() {
+                    for class in self.classes() {
+                        for bindings in
+                            self.ematch(&top.cases(), class, Vec::new(), &mut is_variable)
+                        {
+                            matches.push((index, class, bindings));
+                        }
+                    }
+                }
+                let mut changed = false;
+                for (index, class, bindings) in matches {
+                    let rhs =
+                        self.instantiate(&rules[index].1.cases(), &bindings, &mut is_variable);
+                    changed |= self.union(class, rhs);
+                    if self.nodes.len() > limits.nodes {
+                        self.rebuild();
+                        return Report {
+                            iterations: iteration + 1,
+                            stop: StopReason::NodeLimit,
+                        };
+                    }
+                }
+                self.rebuild();
+                if !changed {
+                    return Report {
+                        iterations: iteration + 1,
+                        stop: StopReason::Saturated,
+                    };
+                }
+            }
+            Report {
+                iterations: limits.iterations,
+                stop: StopReason::IterationLimit,
+            }
+        }
+    }
+
+    impl<A> Default for EGraph<A>
+    where
+        A: Clone + PartialEq,
+    {
+        #[inline]
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// Extraction Cost Function Trait
+    pub trait CostFunction<A> {
+        /// Returns the cost of `node` itself, not including the cost of its children.
+        fn cost(&self, node: &ENode<A>) -> u64;
+    }
+
+    impl<A, F> CostFunction<A> for F
+    where
+        F: Fn(&ENode<A>) -> u64,
+    {
+        #[inline]
+        fn cost(&self, node: &ENode<A>) -> u64 {
+            self(node)
+        }
+    }
+
+    /// Expression Size Cost Function
+    ///
+    /// Every atom and every group costs one.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct AstSize;
+
+    impl<A> CostFunction<A> for AstSize {
+        #[inline]
+        fn cost(&self, node: &ENode<A>) -> u64 {
+            let _ = node;
+            1
+        }
+    }
+
+    impl<A> EGraph<A>
+    where
+        A: Clone + PartialEq,
+    {
+        /// Computes the minimum total cost of the expressions represented by every e-class,
+        /// indexed by class identifier, along with the index of the e-node achieving it.
+        ///
+        /// Classes which only represent infinite expressions have no cost.
+        pub fn costs<C>(&self, cost: &C) -> Vec<Option<(u64, usize)>>
+        where
+            C: CostFunction<A>,
+        {
+            let mut best = vec![None::<(u64, usize)>; self.parent.len()];
+            loop {
+                let mut changed = false;
+                for (index, (node, class)) in self.nodes.iter().enumerate() {
+                    let total = match node {
+                        ENode::Atom(_) => Some(cost.cost(node)),
+                        ENode::Group(children) => {
+                            children.iter().try_fold(cost.cost(node), |acc, c| {
+                                Some(acc.saturating_add(best[self.find(*c)]?.0))
+                            })
+                        }
+                    };
+                    let class = self.find(*class);
+                    if let Some(total) = total {
+                        if best[class].map_or(true, |(b, _)| total < b) {
+                            best[class] = Some((total, index));
+                            changed = true;
+                        }
+                    }
+                }
+                if !changed {
+                    return best;
+                }
+            }
+        }
+
+        /// Extracts the expression of minimum total cost represented by `class`, returning it
+        /// with its cost.
+        pub fn extract<E, C>(&self, class: ClassId, cost: &C) -> Option<(u64, E)>
+        where
+            E: Expression<Atom = A>,
+            E::Group: FromIterator<E>,
+            C: CostFunction<A>,
+        {
+            let best = self.costs(cost);
+            let class = self.find(class);
+            let (total, _) = best[class]?;
+            Some((total, self.build(class, &best)?))
+        }
+
+        /// Builds the expression of `class` from the best e-nodes.
+        fn build<E>(&self, class: ClassId, best: &[Option<(u64, usize)>]) -> Option<E>
+        where
+            E: Expression<Atom = A>,
+            E::Group: FromIterator<E>,
+        {
+            let (_, index) = best[self.find(class)]?;
+            match &self.nodes[index].0 {
+                ENode::Atom(atom) => Some(E::from_atom(atom.clone())),
+                ENode::Group(children) => Some(E::from_group(
+                    children
+                        .iter()
+                        .map(|c| self.build(*c, best))
+                        .collect::<Option<Vec<_>>>()?
+                        .into_iter()
+                        .collect(),
+                )),
+            }
+        }
+
+        /// Extracts the expression of minimum total cost which is equal to `expr`.
+        #[inline]
+        pub fn extract_expr<E, C>(&mut self, expr: &E, cost: &C) -> Option<(u64, E)>
+        where
+            E: Expression<Atom = A>,
+            E::Group: FromIterator<E>,
+            C: CostFunction<A>,
+        {
+            let class = self.add(&expr.cases());
+            self.rebuild();
+            self.extract(class, cost)
+        }
+    }
+}
+
+/// Deduction Engine Module
+///
+/// The state of the engine is a multiset of expressions. A rule applies to the state when its
+/// top matches distinct elements of the state, which are then consumed and replaced by the
+/// instantiation of the bottom of the rule.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod engine {
+    use {
+        super::*,
+        crate::{
+            fuel::{Exhausted, Exhaustion, Fuel},
+            rule::{RuleId, RuleProvider, RuleSet},
+        },
+        alloc::{boxed::Box, collections::BTreeSet, string::String, vec::Vec},
+        core::{cmp::Ordering, fmt, hash::Hash, mem},
+    };
+
+    /// Engine Configuration
+    pub struct Config<E>
+    where
+        E: Expression,
+    {
+        /// Variable predicate for rule atoms
+        pub is_variable: fn(&E::Atom) -> bool,
+
+        /// Deterministic mode flag
+        ///
+        /// When set, matches are enumerated in increasing order of rule identifier and of
+        /// consumed indices whatever the candidate order of the rule provider, so that runs
+        /// are exactly reproducible. Randomized routines should then be given a portable
+        /// seeded generator such as [`SplitMix64`](util::SplitMix64).
+        pub deterministic: bool,
+    }
+
+    impl<E> Config<E>
+    where
+        E: Expression,
+    {
+        /// Builds a new [`Config`].
+        #[inline]
+        pub fn new(is_variable: fn(&E::Atom) -> bool) -> Self {
+            Self {
+                is_variable,
+                deterministic: false,
+            }
+        }
+
+        /// Returns a copy of the configuration with the given deterministic mode flag.
+        #[inline]
+        pub fn deterministic(mut self, deterministic: bool) -> Self {
+            self.deterministic = deterministic;
+            self
+        }
+    }
+
+    impl<E> Clone for Config<E>
+    where
+        E: Expression,
+    {
+        #[inline]
+        fn clone(&self) -> Self {
+            *self
+        }
+    }
+
+    impl<E> Copy for Config<E> where E: Expression {}
+
+    /// Rule Match
+    #[derive(Debug)]
+    pub struct Match<E>
+    where
+        E: Expression,
+    {
+        /// Identifier of the matched rule
+        pub rule: RuleId,
+
+        /// Bindings of the variables of the rule
+        pub bindings: substitution::Structure<E>,
+
+        /// Indices of the consumed elements of the state in increasing order
+        pub consumed: Vec<usize>,
+
+        /// Instantiated bottom of the rule
+        pub produced: Vec<E>,
+
+        /// Weight of the matched rule
+        pub weight: Option<f64>,
+    }
+
+    /// Calls `f` on every match of the rules of `rules` against `state` until `f` returns
+    /// `true`. Returns `true` if `f` stopped the enumeration.
+    ///
+    /// Matches are enumerated in the candidate order of the provider and, for each rule, in the
+    /// order in which [`match_multiset`](substitution::match_multiset) finds them, unless the
+    /// configuration is [deterministic](Config::deterministic). Side conditions and premises are
+    /// checked before calling `f`.
+    pub fn for_each_match<E, P, F>(state: &[E], rules: &P, config: &Config<E>, mut f: F) -> bool
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        F: FnMut(Match<E>) -> bool,
+    {
+        if config.deterministic {
+            let mut matches = Vec::new();
+            for_each_match(state, rules, &config.deterministic(false), |m| {
+                matches.push(m);
+                false
+            });
+            return for_each_sorted(matches, f);
+        }
+        let targets = state.iter().map(move |e| e.cases()).collect::<Vec<_>>();
+        rules.for_each_candidate(state, |id, rule| {
+            for_each_rule_match(state, &targets, id, rule, config, |_, _| true, &mut f)
+        })
+    }
+
+    /// Calls `f` on every match of the rules of `rules` against `state` which involves at
+    /// least one of the elements of `state` at index `delta` or later, until `f` returns
+    /// `true`. Returns `true` if `f` stopped the enumeration.
+    ///
+    /// Every such match is enumerated exactly once, by requiring the first pattern matched to a
+    /// new element to be preceded only by patterns matched to old elements. Since a new element
+    /// can also satisfy a premise, the matches of rules with premises whose top only uses old
+    /// elements are enumerated as well when their premises did not hold among the old elements.
+    /// Rules with an empty top and no premises only match when `delta` is zero. See
+    /// [`for_each_match`] for more details.
+    pub fn for_each_new_match<E, P, F>(
+        state: &[E],
+        delta: usize,
+        rules: &P,
+        config: &Config<E>,
+        mut f: F,
+    ) -> bool
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        F: FnMut(Match<E>) -> bool,
+    {
+        if config.deterministic {
+            let mut matches = Vec::new();
+            for_each_new_match(state, delta, rules, &config.deterministic(false), |m| {
+                matches.push(m);
+                false
+            });
+            return for_each_sorted(matches, f);
+        }
+        let targets = state.iter().map(move |e| e.cases()).collect::<Vec<_>>();
+        rules.for_each_candidate(state, |id, rule| {
+            let len = rule.top().iter().count();
+            (0..len).any(|pivot| {
+                let allowed = move |pattern: usize, target: usize| match pattern.cmp(&pivot) {
+                    Ordering::Less => target < delta,
+                    Ordering::Equal => target >= delta,
+                    _ => true,
+                };
+                for_each_rule_match(state, &targets, id, rule, config, allowed, &mut f)
+            }) || (retries_old(rule, len, delta)
+                && for_each_rule_match(
+                    state,
+                    &targets,
+                    id,
+                    rule,
+                    config,
+                    move |_, target| target < delta,
+                    &mut |m| !is_old_match(rule, &m, state, delta) && f(m),
+                ))
+        })
+    }
+
+    /// Returns `true` if some matches of `rule`, whose top has `len` patterns, only using the
+    /// elements of the state before `delta` can be new, that is, if there was no previous
+    /// round and the top is empty, or if a new element can satisfy a premise.
+    #[inline]
+    fn retries_old<E, R>(rule: &R, len: usize, delta: usize) -> bool
+    where
+        E: Expression,
+        R: Rule<E>,
+    {
+        if delta == 0 {
+            len == 0
+        } else {
+            !rule.premises().is_empty()
+        }
+    }
+
+    /// Returns `true` if the match `m` of `rule`, whose top only uses the elements of `state`
+    /// before `delta`, was already enumerated before the elements at `delta` were added.
+    #[inline]
+    fn is_old_match<E, R>(rule: &R, m: &Match<E>, state: &[E], delta: usize) -> bool
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        delta > 0 && premises_hold(rule, &m.bindings, &state[..delta])
+    }
+
+    /// Returns `true` if every [premise](Rule::premises) of `rule`, instantiated with
+    /// `substitution`, is an element of `state`.
+    #[inline]
+    fn premises_hold<E, R>(rule: &R, substitution: &substitution::Structure<E>, state: &[E]) -> bool
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        rule.premises_hold(|premise| {
+            let premise = substitution.apply_ref(premise);
+            state
+                .iter()
+                .any(|e| expr::eq_ref::<E>(&premise.cases(), &e.cases()))
+        })
+    }
+
+    /// Calls `f` on `matches` in increasing order of rule identifier and of consumed indices,
+    /// keeping the enumeration order otherwise, until `f` returns `true`. Returns `true` if `f`
+    /// stopped the enumeration.
+    #[inline]
+    fn for_each_sorted<E, F>(mut matches: Vec<Match<E>>, f: F) -> bool
+    where
+        E: Expression,
+        F: FnMut(Match<E>) -> bool,
+    {
+        matches.sort_by(|l, r| (l.rule, &l.consumed).cmp(&(r.rule, &r.consumed)));
+        matches.into_iter().any(f)
+    }
+
+    /// Calls `f` on every match of `rule` against `state` allowed by `allowed`, until `f`
+    /// returns `true`. Returns `true` if `f` stopped the enumeration.
+    fn for_each_rule_match<E, R, H, F>(
+        state: &[E],
+        targets: &[ExprRef<E>],
+        id: RuleId,
+        rule: &R,
+        config: &Config<E>,
+        allowed: H,
+        f: &mut F,
+    ) -> bool
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        H: FnMut(usize, usize) -> bool,
+        F: FnMut(Match<E>) -> bool,
+    {
+        let (top, bot) = rule.ref_pair();
+        let patterns = top.iter().map(move |e| e.cases()).collect::<Vec<_>>();
+        substitution::match_multiset_by(
+            &patterns,
+            targets,
+            &mut Vec::new(),
+            config.is_variable,
+            allowed,
+            |bindings, chosen| {
+                if !rule.allows(bindings) {
+                    return false;
+                }
+                let substitution = bindings
+                    .iter()
+                    .map(move |t| t.as_ref().to_owned())
+                    .collect::<substitution::Structure<E>>();
+                if !premises_hold(rule, &substitution, state)
+                    || !absences_hold(rule, &substitution, state, config.is_variable)
+                {
+                    return false;
+                }
+                let mut consumed = chosen.to_vec();
+                consumed.sort_unstable();
+                log_trace!("rule {} matched consuming {:?}", id, consumed);
+                let produced = substitution.apply_group_ref(&bot).into_iter().collect();
+                f(Match {
+                    rule: id,
+                    bindings: substitution,
+                    consumed,
+                    produced,
+                    weight: rule.weight(),
+                })
+            },
+        )
+    }
+
+    /// Returns `true` if no [absence pattern](Rule::absences) of `rule`, instantiated with
+    /// `substitution`, matches an element of `state`.
+    pub fn absences_hold<E, R, F>(
+        rule: &R,
+        substitution: &substitution::Structure<E>,
+        state: &[E],
+        mut is_variable: F,
+    ) -> bool
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        F: FnMut(&E::Atom) -> bool,
+    {
+        rule.absences().iter().all(|pattern| {
+            let pattern = substitution.apply_ref(pattern);
+            !state
+                .iter()
+                .any(|e| substitution::match_expr(&pattern, e, &mut Vec::new(), &mut is_variable))
+        })
+    }
+
+    /// Returns every match of the rules of `rules` against `state`.
+    ///
+    /// See [`for_each_match`] for more details.
+    #[inline]
+    pub fn matches<E, P>(state: &[E], rules: &P, config: &Config<E>) -> Vec<Match<E>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+    {
+        let mut matches = Vec::new();
+        for_each_match(state, rules, config, |m| {
+            matches.push(m);
+            false
+        });
+        matches
+    }
+
+    /// Returns the first match of the rules of `rules` against `state`.
+    ///
+    /// See [`for_each_match`] for more details.
+    #[inline]
+    pub fn first_match<E, P>(state: &[E], rules: &P, config: &Config<E>) -> Option<Match<E>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+    {
+        let mut first = None;
+        for_each_match(state, rules, config, |m| {
+            first = Some(m);
+            true
+        });
+        first
+    }
+
+    /// Random Match Sampling
+    #[cfg(feature = "rand")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "rand")))]
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum Sampling {
+        /// Every match is equally likely.
+        Uniform,
+
+        /// Matches are chosen with probability proportional to the [weight](Rule::weight) of
+        /// their rule, where rules without a weight have weight `1`.
+        Weighted,
+    }
+
+    /// Samples a match of the rules of `rules` against `state` using `sampling`.
+    ///
+    /// The matches are enumerated once with [`for_each_match`], keeping a single candidate by
+    /// weighted reservoir sampling.
+    #[cfg(feature = "rand")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "rand")))]
+    pub fn sample_match<E, P, G>(
+        state: &[E],
+        rules: &P,
+        config: &Config<E>,
+        sampling: Sampling,
+        rng: &mut G,
+    ) -> Option<Match<E>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        G: rand::Rng + ?Sized,
+    {
+        let mut total = 0.0;
+        let mut chosen = None;
+        for_each_match(state, rules, config, |m| {
+            let weight = match sampling {
+                Sampling::Uniform => 1.0,
+                Sampling::Weighted => m.weight.unwrap_or(1.0).max(0.0),
+            };
+            total += weight;
+            if total > 0.0 && rng.gen::<f64>() * total < weight {
+                chosen = Some(m);
+            }
+            false
+        });
+        chosen
+    }
+
+    /// Applies the match `m` to `state` in place, removing the consumed elements and appending
+    /// the produced ones. The match must have been computed against `state`.
+    #[inline]
+    pub fn apply_match<E>(state: &mut Vec<E>, m: Match<E>)
+    where
+        E: Expression,
+    {
+        for index in m.consumed.into_iter().rev() {
+            state.remove(index);
+        }
+        state.extend(m.produced);
+    }
+
+    /// Returns the state obtained by applying the match `m` to `state`.
+    #[inline]
+    pub fn successor<E>(state: &[E], m: &Match<E>) -> Vec<E>
+    where
+        E: Expression,
+        E::Atom: Clone,
+        E::Group: FromIterator<E>,
+    {
+        state
+            .iter()
+            .enumerate()
+            .filter(move |(i, _)| m.consumed.binary_search(i).is_err())
+            .map(move |(_, e)| E::clone(e))
+            .chain(m.produced.iter().map(E::clone))
+            .collect()
+    }
+
+    /// Engine Observer Trait
+    ///
+    /// Receives the events of the [`Engine`] and of the searches of the [`search`] module.
+    /// Every callback does nothing by default, and the unit type is the observer which ignores
+    /// every event.
+    pub trait Observer<E>
+    where
+        E: Expression,
+    {
+        /// Called after a rule application, with the step recording it. Searches call it for
+        /// every transition they keep.
+        #[inline]
+        fn on_rule_applied(&mut self, step: &proof::Step<E>) {
+            let _ = step;
+        }
+
+        /// Called before the successors of `state` are computed.
+        #[inline]
+        fn on_state_expanded(&mut self, state: &[E]) {
+            let _ = state;
+        }
+
+        /// Called when a search abandons the branch of the given `depth`.
+        #[inline]
+        fn on_backtrack(&mut self, depth: usize) {
+            let _ = depth;
+        }
+
+        /// Called when a search finds a derivation of a goal.
+        #[inline]
+        fn on_goal_reached(&mut self, derivation: &proof::Derivation<E>) {
+            let _ = derivation;
+        }
+
+        /// Called for every match of the rule `rule` found, whether or not it is then applied.
+        #[inline]
+        fn on_match(&mut self, rule: RuleId) {
+            let _ = rule;
+        }
+
+        /// Called when the fuel runs out or the computation is cancelled.
+        #[inline]
+        fn on_exhausted(&mut self, reason: Exhaustion) {
+            let _ = reason;
+        }
+    }
+
+    impl<E> Observer<E> for () where E: Expression {}
+
+    impl<E, O> Observer<E> for &mut O
+    where
+        E: Expression,
+        O: Observer<E> + ?Sized,
+    {
+        #[inline]
+        fn on_rule_applied(&mut self, step: &proof::Step<E>) {
+            (**self).on_rule_applied(step)
+        }
+
+        #[inline]
+        fn on_state_expanded(&mut self, state: &[E]) {
+            (**self).on_state_expanded(state)
+        }
+
+        #[inline]
+        fn on_backtrack(&mut self, depth: usize) {
+            (**self).on_backtrack(depth)
+        }
+
+        #[inline]
+        fn on_goal_reached(&mut self, derivation: &proof::Derivation<E>) {
+            (**self).on_goal_reached(derivation)
+        }
+
+        #[inline]
+        fn on_match(&mut self, rule: RuleId) {
+            (**self).on_match(rule)
+        }
+
+        #[inline]
+        fn on_exhausted(&mut self, reason: Exhaustion) {
+            (**self).on_exhausted(reason)
+        }
+    }
+
+    /// Dependencies of a State Element
+    ///
+    /// The initial premises and the rules a state element transitively depends on, through
+    /// the elements consumed by the steps which produced it.
+    #[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct Dependencies {
+        /// Indices of the initial premises in the state where tracking started
+        pub premises: BTreeSet<usize>,
+
+        /// Identifiers of the applied rules
+        pub rules: BTreeSet<RuleId>,
+    }
+
+    impl Dependencies {
+        /// Builds the [`Dependencies`] of the initial premise at `index`, which only depends
+        /// on itself.
+        #[inline]
+        pub fn premise(index: usize) -> Self {
+            let mut dependencies = Self::default();
+            dependencies.premises.insert(index);
+            dependencies
+        }
+
+        /// Adds the dependencies of `other` to `self`.
+        #[inline]
+        pub fn extend(&mut self, other: &Self) {
+            self.premises.extend(other.premises.iter().copied());
+            self.rules.extend(other.rules.iter().copied());
+        }
+    }
+
+    /// Dependency Tracker
+    #[derive(Debug, Default)]
+    struct Tracker {
+        /// Dependencies of the elements of the state
+        current: Vec<Dependencies>,
+
+        /// Dependencies of the elements consumed by every applied step
+        consumed: Vec<Vec<Dependencies>>,
+    }
+
+    /// Invariant Violation Policy
+    ///
+    /// Policies are ordered by severity.
+    #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+    pub enum Policy {
+        /// Records the violation and keeps the step.
+        Report,
+
+        /// Records the violation, keeps the step and stops the engine.
+        Abort,
+
+        /// Records the violation and reverts the step.
+        Reject,
+    }
+
+    /// Invariant Violation
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct Violation {
+        /// Index of the violated invariant
+        pub invariant: usize,
+
+        /// Length of the derivation once the violating step was applied
+        pub step: usize,
+
+        /// Policy of the violated invariant
+        pub policy: Policy,
+    }
+
+    impl fmt::Display for Violation {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "step {} violated invariant {} with the {:?} policy",
+                self.step, self.invariant, self.policy
+            )
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for Violation {}
+
+    /// State Invariant
+    struct Invariant<E> {
+        /// Name of the invariant
+        name: String,
+
+        /// Violation policy
+        policy: Policy,
+
+        /// Invariant predicate
+        check: Box<dyn Fn(&[E]) -> bool + Send + Sync>,
+    }
+
+    /// Assumption Scope
+    struct Scope<E>
+    where
+        E: Expression,
+    {
+        /// Assumed expression
+        assumption: E,
+
+        /// State before the assumption
+        state: Vec<E>,
+
+        /// Length of the derivation before the assumption
+        steps: usize,
+
+        /// Dependencies of the state before the assumption, if tracking is enabled
+        dependencies: Option<Vec<Dependencies>>,
+    }
+
+    /// Deduction Engine
+    pub struct Engine<E, P, O = ()>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+    {
+        /// Rule provider
+        rules: P,
+
+        /// Current state
+        state: Vec<E>,
+
+        /// Engine configuration
+        config: Config<E>,
+
+        /// Derivation of the current state from the initial state
+        derivation: proof::Derivation<E>,
+
+        /// Engine observer
+        observer: O,
+
+        /// Dependency tracker, if tracking is enabled
+        tracker: Option<Tracker>,
+
+        /// Open assumption scopes, innermost last
+        scopes: Vec<Scope<E>>,
+
+        /// Invariants checked after every step
+        invariants: Vec<Invariant<E>>,
+
+        /// Recorded invariant violations
+        violations: Vec<Violation>,
+
+        /// Abortion flag
+        aborted: bool,
+
+        /// Recorded trace
+        trace: Option<proof::Trace<E>>,
+
+        /// Trace streamer, if streaming is enabled
+        streamer: Option<Streamer<E>>,
+    }
+
+    /// Trace Streamer
+    struct Streamer<E>
+    where
+        E: Expression,
+    {
+        /// Sink of the encoded records
+        sink: Box<dyn proof::TraceSink + Send + Sync>,
+
+        /// Step delta encoder
+        encode: fn(&proof::Step<E>) -> Vec<u8>,
+    }
+
+    impl<E> Streamer<E>
+    where
+        E: Expression,
+    {
+        /// Streams the delta of `step`.
+        #[inline]
+        fn step(&mut self, step: &proof::Step<E>) {
+            self.sink.accept(&(self.encode)(step));
+        }
+
+        /// Streams the retraction of the last delta.
+        #[inline]
+        fn retract(&mut self) {
+            self.sink.accept(&binary::encode_retraction());
+        }
+    }
+
+    impl<E, P> Engine<E, P>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+    {
+        /// Builds a new [`Engine`] with the given rules and initial state.
+        #[inline]
+        pub fn new(rules: P, state: Vec<E>, config: Config<E>) -> Self {
+            Self {
+                derivation: proof::Derivation::new(&state),
+                rules,
+                state,
+                config,
+                observer: (),
+                tracker: None,
+                scopes: Vec::new(),
+                invariants: Vec::new(),
+                violations: Vec::new(),
+                aborted: false,
+                trace: None,
+                streamer: None,
+            }
+        }
+    }
+
+    impl<E, P, O> Engine<E, P, O>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        O: Observer<E>,
+    {
+        /// Replaces the observer of the engine by `observer`.
+        #[inline]
+        pub fn with_observer<T>(self, observer: T) -> Engine<E, P, T>
+        where
+            T: Observer<E>,
+        {
+            Engine {
+                rules: self.rules,
+                state: self.state,
+                config: self.config,
+                derivation: self.derivation,
+                observer,
+                tracker: self.tracker,
+                scopes: self.scopes,
+                invariants: self.invariants,
+                violations: self.violations,
+                aborted: self.aborted,
+                trace: self.trace,
+                streamer: self.streamer,
+            }
+        }
+
+        /// Registers an invariant predicate over the state, checked after every step with the
+        /// given violation `policy`, and returns its index.
+        #[inline]
+        pub fn add_invariant<N, F>(&mut self, name: N, policy: Policy, check: F) -> usize
+        where
+            N: Into<String>,
+            F: 'static + Fn(&[E]) -> bool + Send + Sync,
+        {
+            self.invariants.push(Invariant {
+                name: name.into(),
+                policy,
+                check: Box::new(check),
+            });
+            self.invariants.len() - 1
+        }
+
+        /// Returns the name of the invariant at `index`.
+        #[inline]
+        pub fn invariant_name(&self, index: usize) -> Option<&str> {
+            self.invariants
+                .get(index)
+                .map(move |invariant| invariant.name.as_str())
+        }
+
+        /// Returns the recorded invariant violations, in order of occurrence.
+        #[inline]
+        pub fn violations(&self) -> &[Violation] {
+            &self.violations
+        }
+
+        /// Returns `true` if an invariant with the [`Abort`](Policy::Abort) policy was
+        /// violated.
+        #[inline]
+        pub fn is_aborted(&self) -> bool {
+            self.aborted
+        }
+
+        /// Clears the abortion flag, for instance after reverting the step which set it.
+        #[inline]
+        pub fn clear_abort(&mut self) {
+            self.aborted = false;
+        }
+
+        /// Enables dependency tracking, starting from the current state whose elements become
+        /// the initial premises.
+        #[inline]
+        pub fn track_dependencies(mut self) -> Self {
+            self.tracker = Some(Tracker {
+                current: (0..self.state.len()).map(Dependencies::premise).collect(),
+                consumed: Vec::new(),
+            });
+            self
+        }
+
+        /// Returns the dependencies of the elements of the current state in order, if
+        /// tracking is enabled.
+        #[inline]
+        pub fn dependencies(&self) -> Option<&[Dependencies]> {
+            self.tracker
+                .as_ref()
+                .map(move |tracker| tracker.current.as_slice())
+        }
+
+        /// Returns the dependencies of the first element of the current state equal to
+        /// `expr`, if tracking is enabled and there is such an element.
+        #[inline]
+        pub fn dependencies_of(&self, expr: &E) -> Option<&Dependencies> {
+            let index = self.state.iter().position(move |e| E::eq(e, expr))?;
+            self.dependencies()?.get(index)
+        }
+
+        /// Enables trace recording, starting from the current state, storing the state after
+        /// every `interval` steps as a checkpoint. An `interval` of zero disables checkpoints.
+        #[inline]
+        pub fn record_trace(mut self, interval: usize) -> Self {
+            self.trace = Some(proof::Trace::with_checkpoints(
+                self.state.iter().map(E::clone).collect(),
+                interval,
+            ));
+            self
+        }
+
+        /// Returns the recorded trace, if trace recording is enabled.
+        #[inline]
+        pub fn trace(&self) -> Option<&proof::Trace<E>> {
+            self.trace.as_ref()
+        }
+
+        /// Removes the recorded trace, disabling trace recording.
+        #[inline]
+        pub fn take_trace(&mut self) -> Option<proof::Trace<E>> {
+            self.trace.take()
+        }
+
+        /// Enables trace streaming: the [binary encoding](binary::encode_step_delta) of the
+        /// delta of every applied step is sent to `sink` as soon as the step is applied, and a
+        /// [retraction](binary::encode_retraction) record is sent for every reverted step.
+        #[inline]
+        pub fn stream_trace<S>(mut self, sink: S) -> Self
+        where
+            E::Atom: binary::AtomCodec,
+            S: 'static + proof::TraceSink + Send + Sync,
+        {
+            self.streamer = Some(Streamer {
+                sink: Box::new(sink),
+                encode: binary::encode_step_delta::<E>,
+            });
+            self
+        }
+
+        /// Returns the observer of the engine.
+        #[inline]
+        pub fn observer(&self) -> &O {
+            &self.observer
+        }
+
+        /// Returns a mutable reference to the observer of the engine.
+        #[inline]
+        pub fn observer_mut(&mut self) -> &mut O {
+            &mut self.observer
+        }
+
+        /// Returns the rules of the engine.
+        #[inline]
+        pub fn rules(&self) -> &P {
+            &self.rules
+        }
+
+        /// Returns the current state of the engine.
+        #[inline]
+        pub fn state(&self) -> &[E] {
+            &self.state
+        }
+
+        /// Returns the configuration of the engine.
+        #[inline]
+        pub fn config(&self) -> &Config<E> {
+            &self.config
+        }
+
+        /// Returns the number of steps applied since the engine was built.
+        #[inline]
+        pub fn steps(&self) -> usize {
+            self.derivation.len()
+        }
+
+        /// Returns the derivation of the current state from the initial state.
+        #[inline]
+        pub fn derivation(&self) -> &proof::Derivation<E> {
+            &self.derivation
+        }
+
+        /// Consumes the engine, returning its current state.
+        #[inline]
+        pub fn into_state(self) -> Vec<E> {
+            self.state
+        }
+
+        /// Consumes the engine, returning its current state and its derivation.
+        #[inline]
+        pub fn into_parts(self) -> (Vec<E>, proof::Derivation<E>) {
+            (self.state, self.derivation)
+        }
+
+        /// Returns every match of the rules against the current state.
+        #[inline]
+        pub fn matches(&self) -> Vec<Match<E>> {
+            matches(&self.state, &self.rules, &self.config)
+        }
+
+        /// Returns the first match of the rules against the current state.
+        #[inline]
+        pub fn first_match(&self) -> Option<Match<E>> {
+            first_match(&self.state, &self.rules, &self.config)
+        }
+
+        /// Applies the match `m`, which must have been computed against the current state,
+        /// and records it in the derivation.
+        ///
+        /// Invariant violations are handled as in [`try_apply`](Self::try_apply).
+        #[inline]
+        pub fn apply(&mut self, m: Match<E>) {
+            let _ = self.try_apply(m);
+        }
+
+        /// Applies the match `m` like [`apply`](Self::apply) and checks the invariants.
+        ///
+        /// Every violation is recorded. Returns the most severe violation if its policy is
+        /// [`Reject`](Policy::Reject), in which case the step is reverted, or
+        /// [`Abort`](Policy::Abort), in which case the engine stops.
+        pub fn try_apply(&mut self, m: Match<E>) -> Result<(), Violation> {
+            let indices = if self.invariants.is_empty() {
+                Vec::new()
+            } else {
+                m.consumed.clone()
+            };
+            if let Some(tracker) = &mut self.tracker {
+                let mut removed = Vec::with_capacity(m.consumed.len());
+                for index in m.consumed.iter().rev() {
+                    removed.push(tracker.current.remove(*index));
+                }
+                removed.reverse();
+                let mut dependencies = Dependencies::default();
+                dependencies.rules.insert(m.rule);
+                for consumed in &removed {
+                    dependencies.extend(consumed);
+                }
+                tracker
+                    .current
+                    .extend((0..m.produced.len()).map(|_| dependencies.clone()));
+                tracker.consumed.push(removed);
+            }
+            let mut consumed = Vec::with_capacity(m.consumed.len());
+            for index in m.consumed.into_iter().rev() {
+                consumed.push(self.state.remove(index));
+            }
+            consumed.reverse();
+            self.state.extend(m.produced.iter().map(E::clone));
+            self.derivation.push(proof::Step {
+                rule: m.rule,
+                bindings: m.bindings,
+                consumed,
+                produced: m.produced,
+                digest: expr::multiset_digest_of(&self.state),
+            });
+            if let Some(step) = self.derivation.steps().last() {
+                if let Some(trace) = &mut self.trace {
+                    trace.push(proof::Delta::of(step));
+                }
+                if let Some(streamer) = &mut self.streamer {
+                    streamer.step(step);
+                }
+                log_trace!(
+                    "applied rule {} at step {}",
+                    step.rule,
+                    self.derivation.len()
+                );
+                self.observer.on_rule_applied(step);
+            }
+            self.check_invariants(&indices)
+        }
+
+        /// Checks the invariants after the step which consumed the elements at `consumed`.
+        fn check_invariants(&mut self, consumed: &[usize]) -> Result<(), Violation> {
+            let step = self.derivation.len();
+            let mut worst = None::<Violation>;
+            for (index, invariant) in self.invariants.iter().enumerate() {
+                if (invariant.check)(&self.state) {
+                    continue;
+                }
+                log_debug!("invariant {} violated at step {}", invariant.name, step);
+                let violation = Violation {
+                    invariant: index,
+                    step,
+                    policy: invariant.policy,
+                };
+                self.violations.push(violation);
+                if worst.map_or(true, |worst| worst.policy < violation.policy) {
+                    worst = Some(violation);
+                }
+            }
+            match worst {
+                Some(violation) if violation.policy == Policy::Reject => {
+                    self.revert(consumed);
+                    Err(violation)
+                }
+                Some(violation) if violation.policy == Policy::Abort => {
+                    self.aborted = true;
+                    Err(violation)
+                }
+                _ => Ok(()),
+            }
+        }
+
+        /// Reverts the last applied step, given the indices of the elements it consumed in
+        /// increasing order, and removes it from the derivation. Steps applied before the
+        /// innermost open assumption scope cannot be reverted.
+        pub(crate) fn revert(&mut self, consumed: &[usize]) -> Option<proof::Step<E>> {
+            let depth = self.derivation.len();
+            if matches!(self.scopes.last(), Some(scope) if depth <= scope.steps) {
+                return None;
+            }
+            let step = self.derivation.pop()?;
+            if let Some(trace) = &mut self.trace {
+                trace.pop();
+            }
+            if let Some(streamer) = &mut self.streamer {
+                streamer.retract();
+            }
+            let len = self.state.len() - step.produced.len();
+            self.state.truncate(len);
+            for (index, e) in consumed.iter().zip(&step.consumed) {
+                self.state.insert(*index, E::clone(e));
+            }
+            if let Some(tracker) = &mut self.tracker {
+                tracker.current.truncate(len);
+                let removed = tracker.consumed.pop().unwrap_or_default();
+                for (index, dependencies) in consumed.iter().zip(removed) {
+                    tracker.current.insert(*index, dependencies);
+                }
+            }
+            log_trace!("reverted rule {} at step {}", step.rule, depth);
+            self.observer.on_backtrack(depth);
+            Some(step)
+        }
+
+        /// Opens a new assumption scope, pushing `assumption` into the state, and returns the
+        /// number of open scopes. The consequences derived from now on are hypothetical until
+        /// the scope is [discharged](Self::discharge).
+        pub fn assume(&mut self, assumption: E) -> usize {
+            self.scopes.push(Scope {
+                state: self.state.iter().map(E::clone).collect(),
+                steps: self.derivation.len(),
+                dependencies: self
+                    .tracker
+                    .as_ref()
+                    .map(move |tracker| tracker.current.clone()),
+                assumption: E::clone(&assumption),
+            });
+            self.state.push(assumption);
+            if let Some(tracker) = &mut self.tracker {
+                tracker.current.push(Dependencies::default());
+            }
+            log_trace!("opened assumption scope {}", self.scopes.len());
+            self.scopes.len()
+        }
+
+        /// Returns the number of open assumption scopes.
+        #[inline]
+        pub fn scopes(&self) -> usize {
+            self.scopes.len()
+        }
+
+        /// Discharges the innermost assumption scope, returning the conditional rule it
+        /// proves, or `None` if no scope is open.
+        ///
+        /// The top of the rule is the assumption together with the elements consumed from the
+        /// state before the assumption, and its bottom is made of the elements of the current
+        /// state which were not in the state before the assumption. The state and the
+        /// derivation are rolled back to what they were before the assumption.
+        pub fn discharge(&mut self) -> Option<rule::Structure<E>> {
+            let scope = self.scopes.pop()?;
+            let mut produced = mem::take(&mut self.state);
+            let mut top = alloc::vec![scope.assumption];
+            for e in &scope.state {
+                match produced.iter().position(|p| E::eq(p, e)) {
+                    Some(index) => {
+                        produced.remove(index);
+                    }
+                    _ => top.push(E::clone(e)),
+                }
+            }
+            self.state = scope.state;
+            while self.derivation.len() > scope.steps {
+                self.derivation.pop();
+                if let Some(tracker) = &mut self.tracker {
+                    tracker.consumed.pop();
+                }
+                if let Some(trace) = &mut self.trace {
+                    trace.pop();
+                }
+                if let Some(streamer) = &mut self.streamer {
+                    streamer.retract();
+                }
+            }
+            if let (Some(tracker), Some(dependencies)) = (&mut self.tracker, scope.dependencies) {
+                tracker.current = dependencies;
+            }
+            log_trace!("discharged assumption scope {}", self.scopes.len() + 1);
+            Some(rule::Structure::new(
+                top.into_iter().collect(),
+                produced.into_iter().collect(),
+            ))
+        }
+
+        /// Applies the first match against the current state, returning the identifier of the
+        /// applied rule. Returns `None` if the engine is aborted or if the step violates an
+        /// invariant with the [`Reject`](Policy::Reject) or [`Abort`](Policy::Abort) policy.
+        #[inline]
+        pub fn step(&mut self) -> Option<RuleId> {
+            if self.aborted {
+                return None;
+            }
+            let m = self.first_match()?;
+            let rule = m.rule;
+            self.observer.on_match(rule);
+            self.try_apply(m).ok()?;
+            Some(rule)
+        }
+
+        /// Applies a match against the current state sampled with `sampling`, returning the
+        /// identifier of the applied rule.
+        ///
+        /// See [`sample_match`] for more details.
+        #[cfg(feature = "rand")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "rand")))]
+        #[inline]
+        pub fn step_random<G>(&mut self, sampling: Sampling, rng: &mut G) -> Option<RuleId>
+        where
+            G: rand::Rng + ?Sized,
+        {
+            if self.aborted {
+                return None;
+            }
+            let m = sample_match(&self.state, &self.rules, &self.config, sampling, rng)?;
+            let rule = m.rule;
+            self.observer.on_match(rule);
+            self.try_apply(m).ok()?;
+            Some(rule)
+        }
+
+        /// Applies rules until none applies, consuming one step of `fuel` for every
+        /// application. Returns the number of applied steps, as a partial result if the fuel
+        /// runs out first. Stops early if a step violates an invariant with the
+        /// [`Reject`](Policy::Reject) or [`Abort`](Policy::Abort) policy.
+        #[inline]
+        pub fn run(&mut self, fuel: &mut Fuel) -> Result<usize, Exhausted<usize>> {
+            let mut steps = 0;
+            while let Some(m) = self.first_match().filter(|_| !self.aborted) {
+                self.observer.on_match(m.rule);
+                if let Err(reason) = fuel.step() {
+                    log_debug!("engine stopped after {} steps: {:?}", steps, reason);
+                    self.observer.on_exhausted(reason);
+                    return Err(Exhausted::new(reason, steps));
+                }
+                match self.try_apply(m) {
+                    Ok(()) => steps += 1,
+                    Err(violation) => {
+                        if violation.policy == Policy::Abort {
+                            steps += 1;
+                        }
+                        log_debug!("engine stopped after {} steps: {:?}", steps, violation);
+                        return Ok(steps);
+                    }
+                }
+            }
+            log_debug!("engine reached a normal form after {} steps", steps);
+            Ok(steps)
+        }
+    }
+
+    /// Saturation Result
+    #[derive(Debug)]
+    pub struct Saturation<E> {
+        /// Saturated state
+        pub state: Vec<E>,
+
+        /// Pairs `(rule, count)` of the number of firings of each rule in increasing order of
+        /// rule identifier
+        pub fired: Vec<(RuleId, usize)>,
+
+        /// Number of rounds performed
+        pub rounds: usize,
+    }
+
+    /// Computes the closure of `state` under the rules of `rules`.
+    ///
+    /// Saturation reads rules as monotone inferences: the top of a rule is matched against
+    /// the state without consuming it and the elements of the instantiated bottom which are not
+    /// yet in the state are added to it. A rule is said to fire when it adds a new fact.
+    ///
+    /// Saturation uses semi-naive evaluation: after the first round, every round only tries
+    /// the matches which involve at least one fact added by the previous round, either in
+    /// their top or in their premises, see [`for_each_new_match`].
+    ///
+    /// Every firing consumes one step of `fuel` and every new fact one node. If the fuel runs
+    /// out, returns the facts derived so far as a partial result.
+    #[inline]
+    pub fn saturate<E, P>(
+        state: Vec<E>,
+        rules: &P,
+        config: &Config<E>,
+        fuel: &mut Fuel,
+    ) -> Result<Saturation<E>, Exhausted<Saturation<E>>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+    {
+        saturate_by(state, fuel, move |state, delta, f| {
+            for_each_new_match(state, delta, rules, config, f)
+        })
+    }
+
+    /// Saturates `state` using `new_matches` to enumerate the matches of every round which
+    /// involve an element at index `delta` or later, see [`saturate`].
+    pub(crate) fn saturate_by<E, M>(
+        mut state: Vec<E>,
+        fuel: &mut Fuel,
+        mut new_matches: M,
+    ) -> Result<Saturation<E>, Exhausted<Saturation<E>>>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+        M: FnMut(&[E], usize, &mut dyn FnMut(Match<E>) -> bool) -> bool,
+    {
+        let contains = |state: &[E], e: &E| {
+            state
+                .iter()
+                .any(|s| expr::eq_ref::<E>(&s.cases(), &e.cases()))
+        };
+        let mut fired = Vec::<(RuleId, usize)>::new();
+        let (mut rounds, mut delta) = (0, 0);
+        let stop = loop {
+            let mut new = Vec::<E>::new();
+            let mut stop = fuel.check().err();
+            new_matches(&state, delta, &mut |m| {
+                if stop.is_some() {
+                    return true;
+                }
+                let mut added = false;
+                for e in m.produced {
+                    if !contains(&state, &e) && !contains(&new, &e) {
+                        if !added {
+                            if let Err(reason) = fuel.step() {
+                                stop = Some(reason);
+                                break;
+                            }
+                            added = true;
+                        }
+                        if let Err(reason) = fuel.node() {
+                            stop = Some(reason);
+                            break;
+                        }
+                        new.push(e);
+                    }
+                }
+                if added {
+                    match fired.binary_search_by_key(&m.rule, |(id, _)| *id) {
+                        Ok(index) => fired[index].1 += 1,
+                        Err(index) => fired.insert(index, (m.rule, 1)),
+                    }
+                }
+                stop.is_some()
+            });
+            rounds += 1;
+            log_trace!("saturation round {} derived {} facts", rounds, new.len());
+            let saturated = new.is_empty();
+            delta = state.len();
+            state.extend(new);
+            if stop.is_some() || saturated {
+                break stop;
+            }
+        };
+        log_debug!(
+            "saturation stopped after {} rounds with {} facts: {:?}",
+            rounds,
+            state.len(),
+            stop
+        );
+        let saturation = Saturation {
+            state,
+            fired,
+            rounds,
+        };
+        match stop {
+            Some(reason) => Err(Exhausted::new(reason, saturation)),
+            _ => Ok(saturation),
+        }
+    }
+
+    /// Rule Subset Provider
+    ///
+    /// Provides the rules of a [`RuleSet`] with the given identifiers.
+    struct Subset<'r, E, R>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        /// Underlying rule set
+        rules: &'r RuleSet<E, R>,
+
+        /// Provided identifiers
+        ids: &'r [RuleId],
+    }
+
+    impl<'r, E, R> RuleProvider<E> for Subset<'r, E, R>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        type Rule = R;
+
+        #[inline]
+        fn for_each_candidate<F>(&self, state: &[E], mut f: F) -> bool
+        where
+            F: FnMut(RuleId, &Self::Rule) -> bool,
+        {
+            let _ = state;
+            self.ids.iter().any(move |id| match self.rules.rule(*id) {
+                Some(rule) => f(*id, rule),
+                _ => false,
+            })
+        }
+
+        #[inline]
+        fn get_rule(&self, id: RuleId) -> Option<&Self::Rule> {
+            self.ids
+                .contains(&id)
+                .then(|| self.rules.rule(id))
+                .flatten()
+        }
+    }
+
+    /// Computes the closure of `state` under `rules` by [saturating](saturate) the
+    /// [strata](rule::stratify) of the rules in order, so that the absence patterns of a rule
+    /// are only checked once the rules which could invalidate them have been saturated.
+    ///
+    /// Returns the unstratifiable rules if the rules cannot be stratified. The `fuel` is shared
+    /// by all the strata.
+    pub fn saturate_stratified<E, R>(
+        mut state: Vec<E>,
+        rules: &RuleSet<E, R>,
+        config: &Config<E>,
+        fuel: &mut Fuel,
+    ) -> Result<Result<Saturation<E>, Exhausted<Saturation<E>>>, Vec<RuleId>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        let strata = rule::stratify(rules, config.is_variable)?;
+        let mut fired = Vec::<(RuleId, usize)>::new();
+        let mut rounds = 0;
+        let mut stop = None;
+        for ids in &strata {
+            let saturation = match saturate(state, &Subset { rules, ids }, config, fuel) {
+                Ok(saturation) => saturation,
+                Err(exhausted) => {
+                    stop = Some(exhausted.reason);
+                    exhausted.partial
+                }
+            };
+            state = saturation.state;
+            rounds += saturation.rounds;
+            for (id, count) in saturation.fired {
+                match fired.binary_search_by_key(&id, |(id, _)| *id) {
+                    Ok(index) => fired[index].1 += count,
+                    Err(index) => fired.insert(index, (id, count)),
+                }
+            }
+            if stop.is_some() {
+                break;
+            }
+        }
+        let saturation = Saturation {
+            state,
+            fired,
+            rounds,
+        };
+        Ok(match stop {
+            Some(reason) => Err(Exhausted::new(reason, saturation)),
+            _ => Ok(saturation),
+        })
+    }
+    /// Parallel Engine Algorithms
+    #[cfg(feature = "parallel")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "parallel")))]
+    pub mod parallel {
+        use {
+            super::*,
+            rayon::iter::{IntoParallelIterator, ParallelIterator},
+        };
+
+        /// Returns the matches of the rules of `rules` against `state` which involve at least
+        /// one of the elements of `state` at index `delta` or later, see [`for_each_new_match`].
+        ///
+        /// The matches are computed in parallel, splitting the work by rule and, for every
+        /// rule, by the pattern matched to the first new element. They are returned in the
+        /// order of `rules`, and sorted like [`for_each_match`] in deterministic mode.
+        pub fn new_matches<E, R>(
+            state: &[E],
+            delta: usize,
+            rules: &RuleSet<E, R>,
+            config: &Config<E>,
+        ) -> Vec<Match<E>>
+        where
+            E: Expression + Send + Sync,
+            E::Atom: Clone + PartialEq + Send + Sync,
+            E::Group: Container<E>,
+            for<'e> ExprRef<'e, E>: Sync,
+            R: Rule<E> + Sync,
+        {
+            let mut tasks = Vec::new();
+            for entry in rules.iter() {
+                let len = entry.rule.top().iter().count();
+                tasks.extend((0..len).map(|pivot| (entry.id, &entry.rule, Some(pivot))));
+                if retries_old(&entry.rule, len, delta) {
+                    tasks.push((entry.id, &entry.rule, None));
+                }
+            }
+            let targets = state.iter().map(move |e| e.cases()).collect::<Vec<_>>();
+            let targets = &targets;
+            let matches = tasks
+                .into_par_iter()
+                .map(move |(id, rule, pivot)| {
+                    let allowed = move |pattern: usize, target: usize| match pivot {
+                        Some(pivot) => match pattern.cmp(&pivot) {
+                            Ordering::Less => target < delta,
+                            Ordering::Equal => target >= delta,
+                            _ => true,
+                        },
+                        _ => target < delta,
+                    };
+                    let mut matches = Vec::new();
+                    for_each_rule_match(state, targets, id, rule, config, allowed, &mut |m| {
+                        if pivot.is_some() || !is_old_match(rule, &m, state, delta) {
+                            matches.push(m);
+                        }
+                        false
+                    });
+                    matches
+                })
+                .collect::<Vec<_>>();
+            let mut matches = matches.into_iter().flatten().collect::<Vec<_>>();
+            if config.deterministic {
+                matches.sort_by(|l, r| (l.rule, &l.consumed).cmp(&(r.rule, &r.consumed)));
+            }
+            matches
+        }
+
+        /// Computes the closure of `state` under the rules of `rules` like [`saturate`],
+        /// computing the matches of every round in parallel with [`new_matches`].
+        #[inline]
+        pub fn saturate<E, R>(
+            state: Vec<E>,
+            rules: &RuleSet<E, R>,
+            config: &Config<E>,
+            fuel: &mut Fuel,
+        ) -> Result<Saturation<E>, Exhausted<Saturation<E>>>
+        where
+            E: Expression + Send + Sync,
+            E::Atom: Clone + PartialEq + Send + Sync,
+            E::Group: Container<E>,
+            for<'e> ExprRef<'e, E>: Sync,
+            R: Rule<E> + Sync,
+        {
+            saturate_by(state, fuel, move |state, delta, f| {
+                new_matches(state, delta, rules, config).into_iter().any(f)
+            })
+        }
+    }
+
+    #[cfg(all(test, feature = "simple"))]
+    mod tests {
+        use {
+            super::*,
+            crate::{
+                expr::SimpleExpr,
+                rule::{Conditional, Negated, Structure},
+            },
+            alloc::vec,
+        };
+
+        type E = SimpleExpr;
+
+        fn is_variable(atom: &String) -> bool {
+            atom.starts_with('?')
+        }
+
+        fn atom(atom: &str) -> E {
+            E::atom(atom)
+        }
+
+        #[test]
+        fn saturate_retries_premises_derived_later() {
+            let mut rules = RuleSet::<E, Conditional<E>>::new();
+            rules.insert(
+                "premise",
+                Conditional::new(Structure::new(vec![atom("a")], vec![atom("p")]), vec![]),
+            );
+            rules.insert(
+                "conditional",
+                Conditional::new(
+                    Structure::new(vec![atom("a")], vec![atom("b")]),
+                    vec![atom("p")],
+                ),
+            );
+            let config = Config::new(is_variable);
+            let saturation =
+                saturate(vec![atom("a")], &rules, &config, &mut Fuel::unlimited()).unwrap();
+            assert_eq!(saturation.state, vec![atom("a"), atom("p"), atom("b")]);
+        }
+
+        fn chain() -> RuleSet<E> {
+            let mut rules = RuleSet::new();
+            rules.insert("first", Structure::new(vec![atom("a")], vec![atom("b")]));
+            rules.insert("second", Structure::new(vec![atom("b")], vec![atom("c")]));
+            rules
+        }
+
+        #[test]
+        fn run_reaches_normal_form() {
+            let mut engine = Engine::new(chain(), vec![atom("a")], Config::new(is_variable));
+            assert_eq!(engine.run(&mut Fuel::unlimited()), Ok(2));
+            assert_eq!(engine.state(), &[atom("c")]);
+            assert_eq!(engine.derivation().len(), 2);
+        }
+
+        #[test]
+        fn run_returns_partial_result_when_out_of_fuel() {
+            let mut engine = Engine::new(chain(), vec![atom("a")], Config::new(is_variable));
+            assert_eq!(
+                engine.run(&mut Fuel::unlimited().with_steps(1)),
+                Err(Exhausted::new(Exhaustion::Steps, 1))
+            );
+            assert_eq!(engine.state(), &[atom("b")]);
+            assert_eq!(engine.derivation().len(), 1);
+        }
+
+        #[test]
+        fn try_apply_reverts_rejected_step() {
+            let mut engine = Engine::new(chain(), vec![atom("b")], Config::new(is_variable));
+            let invariant =
+                engine.add_invariant("no c", Policy::Reject, |state| !state.contains(&atom("c")));
+            let m = engine.first_match().unwrap();
+            let violation = Violation {
+                invariant,
+                step: 1,
+                policy: Policy::Reject,
+            };
+            assert_eq!(engine.try_apply(m), Err(violation));
+            assert_eq!(engine.state(), &[atom("b")]);
+            assert_eq!(engine.derivation().len(), 0);
+            assert_eq!(engine.violations(), &[violation]);
+        }
+
+        #[test]
+        fn discharge_proves_assumption_consequences() {
+            let mut engine = Engine::new(chain(), vec![atom("x")], Config::new(is_variable));
+            assert_eq!(engine.discharge(), None);
+            assert_eq!(engine.assume(atom("a")), 1);
+            assert_eq!(engine.run(&mut Fuel::unlimited()), Ok(2));
+            assert_eq!(engine.state(), &[atom("x"), atom("c")]);
+            assert_eq!(
+                engine.discharge(),
+                Some(Structure::new(vec![atom("a")], vec![atom("c")]))
+            );
+            assert_eq!(engine.scopes(), 0);
+            assert_eq!(engine.state(), &[atom("x")]);
+            assert_eq!(engine.derivation().len(), 0);
+        }
+
+        #[test]
+        fn saturate_stratified_checks_absences_after_lower_strata() {
+            let mut rules = RuleSet::<E, Negated<E>>::new();
+            rules.insert(
+                "negated",
+                Negated::new(
+                    Structure::new(vec![atom("a")], vec![atom("c")]),
+                    vec![atom("b")],
+                ),
+            );
+            let positive = rules.insert(
+                "positive",
+                Negated::new(Structure::new(vec![atom("a")], vec![atom("b")]), vec![]),
+            );
+            let config = Config::new(is_variable);
+            let saturation =
+                saturate_stratified(vec![atom("a")], &rules, &config, &mut Fuel::unlimited())
+                    .unwrap()
+                    .unwrap();
+            assert_eq!(saturation.state, vec![atom("a"), atom("b")]);
+            assert_eq!(saturation.fired, vec![(positive, 1)]);
+        }
+
+        #[test]
+        fn saturate_stratified_rejects_negative_cycles() {
+            let mut rules = RuleSet::<E, Negated<E>>::new();
+            let id = rules.insert(
+                "cycle",
+                Negated::new(
+                    Structure::new(vec![atom("a")], vec![atom("b")]),
+                    vec![atom("b")],
+                ),
+            );
+            let config = Config::new(is_variable);
+            assert_eq!(
+                saturate_stratified(vec![atom("a")], &rules, &config, &mut Fuel::unlimited()).err(),
+                Some(vec![id])
+            );
+        }
+    }
+}
+
+/// Proof Module
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod proof {
+    use {
+        super::*,
+        crate::{
+            engine::{self, Config},
+            rule::{RuleId, RuleSet, Scoring},
+        },
+        alloc::{
+            collections::{BTreeMap, BTreeSet},
+            format,
+            vec::Vec,
+        },
+        core::{fmt, hash::Hash},
+    };
+
+    /// Derivation Step
+    #[derive(Debug)]
+    pub struct Step<E>
+    where
+        E: Expression,
+    {
+        /// Identifier of the applied rule
+        pub rule: RuleId,
+
+        /// Bindings of the variables of the rule
+        pub bindings: substitution::Structure<E>,
+
+        /// Elements consumed from the state
+        pub consumed: Vec<E>,
+
+        /// Elements produced into the state
+        pub produced: Vec<E>,
+
+        /// [Digest](expr::multiset_digest_of) of the resulting state
+        pub digest: u64,
+    }
+
+    impl<E> Clone for Step<E>
+    where
+        E: Expression,
+        E::Atom: Clone,
+        E::Group: FromIterator<E>,
+    {
+        #[inline]
+        fn clone(&self) -> Self {
+            Self {
+                rule: self.rule,
+                bindings: self
+                    .bindings
+                    .terms
+                    .iter()
+                    .map(move |t| substitution::Term::new(t.var.clone(), E::clone(&t.expr)))
+                    .collect(),
+                consumed: self.consumed.iter().map(E::clone).collect(),
+                produced: self.produced.iter().map(E::clone).collect(),
+                digest: self.digest,
+            }
+        }
+    }
+
+    /// Derivation
+    ///
+    /// A sequence of rule applications leading from an initial state to a final state, built by
+    /// the [`Engine`](engine::Engine).
+    #[derive(Debug)]
+    pub struct Derivation<E>
+    where
+        E: Expression,
+    {
+        /// [Digest](expr::multiset_digest_of) of the initial state
+        pub initial: u64,
+
+        /// Derivation steps
+        steps: Vec<Step<E>>,
+    }
+
+    impl<E> Derivation<E>
+    where
+        E: Expression,
+    {
+        /// Builds an empty [`Derivation`] starting from `initial`.
+        #[inline]
+        pub fn new(initial: &[E]) -> Self
+        where
+            E::Atom: Hash,
+        {
+            Self::from_digest(expr::multiset_digest_of(initial))
+        }
+
+        /// Builds an empty [`Derivation`] starting from the state with the given digest.
+        #[inline]
+        pub fn from_digest(initial: u64) -> Self {
+            Self {
+                initial,
+                steps: Vec::new(),
+            }
+        }
+
+        /// Returns the number of steps of the derivation.
+        #[inline]
+        pub fn len(&self) -> usize {
+            self.steps.len()
+        }
+
+        /// Returns `true` if the derivation has no steps.
+        #[inline]
+        pub fn is_empty(&self) -> bool {
+            self.steps.is_empty()
+        }
+
+        /// Returns the steps of the derivation.
+        #[inline]
+        pub fn steps(&self) -> &[Step<E>] {
+            &self.steps
+        }
+
+        /// Appends a step to the derivation.
+        #[inline]
+        pub fn push(&mut self, step: Step<E>) {
+            self.steps.push(step);
+        }
+
+        /// Removes the last step of the derivation.
+        #[inline]
+        pub fn pop(&mut self) -> Option<Step<E>> {
+            self.steps.pop()
+        }
+
+        /// Returns an iterator over the identifiers of the applied rules.
+        #[inline]
+        pub fn rules(&self) -> impl '_ + Iterator<Item = RuleId> {
+            self.steps.iter().map(move |step| step.rule)
+        }
+
+        /// Returns the digest of the final state of the derivation.
+        #[inline]
+        pub fn final_digest(&self) -> u64 {
+            self.steps
+                .last()
+                .map_or(self.initial, move |step| step.digest)
+        }
+
+        /// Returns an iterator over the states reached by the steps of the derivation from
+        /// `initial`, checking that `initial` has the initial digest of the derivation and that
+        /// every step only consumes elements of the state and records the digest of the state
+        /// it leads to, as [`check_with`] does. The rules of the steps are not checked. The
+        /// iterator stops after the first error.
+        #[inline]
+        pub fn replay(&self, initial: &[E]) -> Replay<'_, E>
+        where
+            E::Atom: Clone + Hash + PartialEq,
+            E::Group: FromIterator<E>,
+        {
+            Replay {
+                steps: self.steps.iter(),
+                index: 0,
+                state: initial.iter().map(E::clone).collect(),
+                pending: check_initial(self, initial).err(),
+                done: false,
+            }
+        }
+    }
+
+    /// Derivation Replay Iterator
+    ///
+    /// Built by [`Derivation::replay`].
+    pub struct Replay<'d, E>
+    where
+        E: Expression,
+    {
+        /// Remaining steps
+        steps: core::slice::Iter<'d, Step<E>>,
+
+        /// Index of the next step
+        index: usize,
+
+        /// Current state
+        state: Vec<E>,
+
+        /// Error to report before the first step
+        pending: Option<ProofError>,
+
+        /// Termination flag, set after an error
+        done: bool,
+    }
+
+    impl<'d, E> Replay<'d, E>
+    where
+        E: Expression,
+    {
+        /// Returns the current state.
+        #[inline]
+        pub fn state(&self) -> &[E] {
+            &self.state
+        }
+
+        /// Returns the index of the next step.
+        #[inline]
+        pub fn index(&self) -> usize {
+            self.index
+        }
+
+        /// Consumes the iterator, returning the current state.
+        #[inline]
+        pub fn into_state(self) -> Vec<E> {
+            self.state
+        }
+    }
+
+    impl<'d, E> Iterator for Replay<'d, E>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: FromIterator<E>,
+    {
+        type Item = Result<Vec<E>, ProofError>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            if let Some(error) = self.pending.take() {
+                self.done = true;
+                return Some(Err(error));
+            }
+            if self.done {
+                return None;
+            }
+            let step = self.steps.next()?;
+            let index = self.index;
+            self.index += 1;
+            match advance(&mut self.state, step, index) {
+                Ok(()) => Some(Ok(self.state.iter().map(E::clone).collect())),
+                Err(error) => {
+                    self.done = true;
+                    Some(Err(error))
+                }
+            }
+        }
+
+        #[inline]
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            match (self.pending.is_some(), self.done) {
+                (true, _) => (1, Some(1)),
+                (_, true) => (0, Some(0)),
+                _ => (self.steps.len(), Some(self.steps.len())),
+            }
+        }
+    }
+
+    impl<'d, E> core::iter::FusedIterator for Replay<'d, E>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: FromIterator<E>,
+    {
+    }
+
+    impl<E> Clone for Derivation<E>
+    where
+        E: Expression,
+        E::Atom: Clone,
+        E::Group: FromIterator<E>,
+    {
+        #[inline]
+        fn clone(&self) -> Self {
+            Self {
+                initial: self.initial,
+                steps: self.steps.clone(),
+            }
+        }
+    }
+
+    impl<E> IntoIterator for Derivation<E>
+    where
+        E: Expression,
+    {
+        type Item = Step<E>;
+        type IntoIter = alloc::vec::IntoIter<Step<E>>;
+
+        #[inline]
+        fn into_iter(self) -> Self::IntoIter {
+            self.steps.into_iter()
+        }
+    }
+
+    /// Trace Delta
+    ///
+    /// The change of state made by one step of a [`Trace`].
+    #[derive(Debug)]
+    pub struct Delta<E>
+    where
+        E: Expression,
+    {
+        /// Identifier of the applied rule
+        pub rule: RuleId,
+
+        /// Elements consumed from the state
+        pub consumed: Vec<E>,
+
+        /// Elements produced into the state
+        pub produced: Vec<E>,
+    }
+
+    impl<E> Delta<E>
+    where
+        E: Expression,
+    {
+        /// Builds the [`Delta`] of `step`.
+        #[inline]
+        pub fn of(step: &Step<E>) -> Self
+        where
+            E::Atom: Clone,
+            E::Group: FromIterator<E>,
+        {
+            Self {
+                rule: step.rule,
+                consumed: step.consumed.iter().map(E::clone).collect(),
+                produced: step.produced.iter().map(E::clone).collect(),
+            }
+        }
+
+        /// Applies the delta to `state`, returning `false` if it consumes an element which is
+        /// not in the state, in which case `state` is left partially updated.
+        #[inline]
+        pub fn apply(&self, state: &mut Vec<E>) -> bool
+        where
+            E::Atom: Clone + PartialEq,
+            E::Group: FromIterator<E>,
+        {
+            if !self.consumed.iter().all(|e| take(state, e).is_some()) {
+                return false;
+            }
+            state.extend(self.produced.iter().map(E::clone));
+            true
+        }
+    }
+
+    impl<E> Clone for Delta<E>
+    where
+        E: Expression,
+        E::Atom: Clone,
+        E::Group: FromIterator<E>,
+    {
+        #[inline]
+        fn clone(&self) -> Self {
+            Self {
+                rule: self.rule,
+                consumed: self.consumed.iter().map(E::clone).collect(),
+                produced: self.produced.iter().map(E::clone).collect(),
+            }
+        }
+    }
+
+    /// Step-Indexed Trace
+    ///
+    /// The initial state of a run together with the [`Delta`] of every step, from which the
+    /// state at any step is reconstructed. With a checkpoint interval `k`, the state after
+    /// every `k` steps is also stored so that a reconstruction applies fewer than `k` deltas.
+    #[derive(Debug)]
+    pub struct Trace<E>
+    where
+        E: Expression,
+    {
+        /// Initial state
+        initial: Vec<E>,
+
+        /// Step deltas
+        deltas: Vec<Delta<E>>,
+
+        /// Checkpoint interval, zero if there are no checkpoints
+        interval: usize,
+
+        /// States after every `interval` steps
+        checkpoints: Vec<Vec<E>>,
+    }
+
+    impl<E> Trace<E>
+    where
+        E: Expression,
+    {
+        /// Builds an empty [`Trace`] starting from `initial`, without checkpoints.
+        #[inline]
+        pub fn new(initial: Vec<E>) -> Self {
+            Self::with_checkpoints(initial, 0)
+        }
+
+        /// Builds an empty [`Trace`] starting from `initial` which stores the state after every
+        /// `interval` steps. An `interval` of zero disables checkpoints.
+        #[inline]
+        pub fn with_checkpoints(initial: Vec<E>, interval: usize) -> Self {
+            Self {
+                initial,
+                deltas: Vec::new(),
+                interval,
+                checkpoints: Vec::new(),
+            }
+        }
+
+        /// Builds the [`Trace`] of `derivation` starting from `initial`, with the given
+        /// checkpoint `interval`. Returns `None` if a step consumes an element which is not in
+        /// the state.
+        pub fn from_derivation(
+            initial: Vec<E>,
+            derivation: &Derivation<E>,
+            interval: usize,
+        ) -> Option<Self>
+        where
+            E::Atom: Clone + PartialEq,
+            E::Group: FromIterator<E>,
+        {
+            let mut trace = Self::with_checkpoints(initial, interval);
+            for step in derivation.steps() {
+                if !trace.push(Delta::of(step)) {
+                    return None;
+                }
+            }
+            Some(trace)
+        }
+
+        /// Returns the number of steps of the trace.
+        #[inline]
+        pub fn len(&self) -> usize {
+            self.deltas.len()
+        }
+
+        /// Returns `true` if the trace has no steps.
+        #[inline]
+        pub fn is_empty(&self) -> bool {
+            self.deltas.is_empty()
+        }
+
+        /// Returns the initial state of the trace.
+        #[inline]
+        pub fn initial(&self) -> &[E] {
+            &self.initial
+        }
+
+        /// Returns the deltas of the steps of the trace.
+        #[inline]
+        pub fn deltas(&self) -> &[Delta<E>] {
+            &self.deltas
+        }
+
+        /// Returns the checkpoint interval of the trace, zero if there are no checkpoints.
+        #[inline]
+        pub fn interval(&self) -> usize {
+            self.interval
+        }
+
+        /// Appends the delta of a step to the trace, returning `false` and leaving the trace
+        /// unchanged if the delta consumes an element which is not in the final state. The
+        /// delta is only checked when it completes a checkpoint.
+        pub fn push(&mut self, delta: Delta<E>) -> bool
+        where
+            E::Atom: Clone + PartialEq,
+            E::Group: FromIterator<E>,
+        {
+            if self.interval != 0 && (self.deltas.len() + 1) % self.interval == 0 {
+                let mut state = match self.state_at(self.deltas.len()) {
+                    Some(state) => state,
+                    _ => return false,
+                };
+                if !delta.apply(&mut state) {
+                    return false;
+                }
+                self.checkpoints.push(state);
+            }
+            self.deltas.push(delta);
+            true
+        }
+
+        /// Removes the delta of the last step of the trace.
+        #[inline]
+        pub fn pop(&mut self) -> Option<Delta<E>> {
+            let delta = self.deltas.pop()?;
+            if self.interval != 0 {
+                self.checkpoints.truncate(self.deltas.len() / self.interval);
+            }
+            Some(delta)
+        }
+
+        /// Reconstructs the state after `step` steps, starting from the closest checkpoint.
+        /// Returns `None` if `step` is greater than the length of the trace or if a delta
+        /// consumes an element which is not in the state.
+        pub fn state_at(&self, step: usize) -> Option<Vec<E>>
+        where
+            E::Atom: Clone + PartialEq,
+            E::Group: FromIterator<E>,
+        {
+            if step > self.deltas.len() {
+                return None;
+            }
+            let (start, base) = match step.checked_div(self.interval).unwrap_or(0) {
+                0 => (0, &self.initial),
+                index => (index * self.interval, &self.checkpoints[index - 1]),
+            };
+            let mut state = base.iter().map(E::clone).collect();
+            for delta in &self.deltas[start..step] {
+                if !delta.apply(&mut state) {
+                    return None;
+                }
+            }
+            Some(state)
+        }
+
+        /// Reconstructs the final state of the trace.
+        #[inline]
+        pub fn final_state(&self) -> Option<Vec<E>>
+        where
+            E::Atom: Clone + PartialEq,
+            E::Group: FromIterator<E>,
+        {
+            self.state_at(self.deltas.len())
+        }
+    }
+
+    impl<E> Clone for Trace<E>
+    where
+        E: Expression,
+        E::Atom: Clone,
+        E::Group: FromIterator<E>,
+    {
+        #[inline]
+        fn clone(&self) -> Self {
+            let copy = |state: &Vec<E>| state.iter().map(E::clone).collect();
+            Self {
+                initial: copy(&self.initial),
+                deltas: self.deltas.clone(),
+                interval: self.interval,
+                checkpoints: self.checkpoints.iter().map(copy).collect(),
+            }
+        }
+    }
+
+    /// Trace Sink Trait
+    ///
+    /// Receives encoded trace records one at a time, see
+    /// [`Engine::stream_trace`](engine::Engine::stream_trace).
+    pub trait TraceSink {
+        /// Accepts the next encoded trace record.
+        fn accept(&mut self, record: &[u8]);
+    }
+
+    impl TraceSink for Vec<Vec<u8>> {
+        #[inline]
+        fn accept(&mut self, record: &[u8]) {
+            self.push(record.to_vec());
+        }
+    }
+
+    impl<S> TraceSink for &mut S
+    where
+        S: TraceSink + ?Sized,
+    {
+        #[inline]
+        fn accept(&mut self, record: &[u8]) {
+            (**self).accept(record)
+        }
+    }
+
+    /// Removes the first element of `state` equal to `expr`, returning its index.
+    #[inline]
+    fn take<E>(state: &mut Vec<E>, expr: &E) -> Option<usize>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        let index = state.iter().position(move |e| E::eq(e, expr))?;
+        state.remove(index);
+        Some(index)
+    }
+
+    /// Rebuilds a derivation from `initial` with the given steps, recomputing their digests.
+    /// Returns the derivation and its final state.
+    fn rebuild<E>(initial: &[E], steps: Vec<Step<E>>) -> Result<(Derivation<E>, Vec<E>), ProofError>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: FromIterator<E>,
+    {
+        let mut derivation = Derivation::new(initial);
+        let mut state = initial.iter().map(E::clone).collect::<Vec<_>>();
+        for (index, mut step) in steps.into_iter().enumerate() {
+            for e in &step.consumed {
+                take(&mut state, e).ok_or(ProofError::new(index, ProofErrorKind::NotInState))?;
+            }
+            state.extend(step.produced.iter().map(E::clone));
+            step.digest = expr::multiset_digest_of(&state);
+            derivation.push(step);
+        }
+        Ok((derivation, state))
+    }
+
+    /// Returns, for every step of `steps` applied from `initial`, whether it is needed to
+    /// produce an element consumed by a needed step or an element of `goal`.
+    fn live_steps<E>(steps: &[Step<E>], initial: &[E], goal: &[E]) -> Result<Vec<bool>, ProofError>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E>,
+    {
+        let mut state = initial
+            .iter()
+            .map(move |e| (E::clone(e), None))
+            .collect::<Vec<_>>();
+        let mut uses = Vec::with_capacity(steps.len());
+        for (index, step) in steps.iter().enumerate() {
+            let mut producers = Vec::new();
+            for e in &step.consumed {
+                let position = state
+                    .iter()
+                    .position(|(s, _)| E::eq(s, e))
+                    .ok_or(ProofError::new(index, ProofErrorKind::NotInState))?;
+                producers.push(state.remove(position).1);
+            }
+            uses.push(producers);
+            state.extend(
+                step.produced
+                    .iter()
+                    .map(move |e| (E::clone(e), Some(index))),
+            );
+        }
+        let mut live = alloc::vec![false; steps.len()];
+        for e in goal {
+            let position = state
+                .iter()
+                .position(|(s, _)| E::eq(s, e))
+                .ok_or(ProofError::new(steps.len(), ProofErrorKind::GoalNotReached))?;
+            if let (_, Some(producer)) = state.remove(position) {
+                live[producer] = true;
+            }
+        }
+        for index in (0..steps.len()).rev() {
+            if live[index] {
+                for producer in uses[index].iter().flatten() {
+                    live[*producer] = true;
+                }
+            }
+        }
+        Ok(live)
+    }
+
+    /// Returns the elements consumed from the state before `steps` and the elements added to
+    /// it by `steps`, applied in order, and whether some step consumes an element produced by
+    /// an earlier one.
+    fn combine<'s, E, I>(steps: I) -> (Vec<E>, Vec<E>, bool)
+    where
+        E: 's + Expression,
+        I: IntoIterator<Item = &'s Step<E>>,
+        E::Atom: Clone + PartialEq,
+        E::Group: FromIterator<E>,
+    {
+        let mut consumed = Vec::new();
+        let mut produced = Vec::new();
+        let mut linked = false;
+        for step in steps {
+            for e in &step.consumed {
+                match take(&mut produced, e) {
+                    Some(_) => linked = true,
+                    _ => consumed.push(E::clone(e)),
+                }
+            }
+            produced.extend(step.produced.iter().map(E::clone));
+        }
+        (consumed, produced, linked)
+    }
+
+    /// Returns a single step of a rule of `rules` accepted by `accept`, which consumes exactly
+    /// `consumed` and produces `produced`, if there is one. The digest of the step is left
+    /// to the caller.
+    fn single_step<E, R, F>(
+        consumed: Vec<E>,
+        produced: &[E],
+        rules: &RuleSet<E, R>,
+        config: &Config<E>,
+        accept: F,
+    ) -> Option<Step<E>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        F: Fn(RuleId) -> bool,
+    {
+        let mut found = None;
+        engine::for_each_match(&consumed, rules, config, |m| {
+            if accept(m.rule)
+                && m.consumed.len() == consumed.len()
+                && multiset_eq(&m.produced, produced)
+            {
+                found = Some((m.rule, m.bindings, m.produced));
+                return true;
+            }
+            false
+        });
+        let (rule, bindings, produced) = found?;
+        Some(Step {
+            rule,
+            bindings,
+            consumed,
+            produced,
+            digest: 0,
+        })
+    }
+
+    /// Returns a single step of a rule of `rules` with the combined effect of `first`
+    /// followed by `second`, if `second` consumes an element produced by `first`.
+    #[inline]
+    fn shortcut<E, R>(
+        first: &Step<E>,
+        second: &Step<E>,
+        rules: &RuleSet<E, R>,
+        config: &Config<E>,
+    ) -> Option<Step<E>>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        let (consumed, produced, linked) = combine([first, second].iter().copied());
+        if !linked {
+            return None;
+        }
+        single_step(consumed, &produced, rules, config, |_| true)
+    }
+
+    /// Minimizes `derivation`, applied from `initial` and reaching a state which contains
+    /// `goal`, returning a shorter derivation [checked](check_with) against `rules` with the
+    /// variables of `config`.
+    ///
+    /// The steps whose produced elements are never consumed by a later needed step nor part
+    /// of `goal` are removed, and then consecutive linked steps are repeatedly replaced by a
+    /// single step of a rule of `rules` with their combined effect, found with `config`.
+    pub fn minimize<E, R>(
+        derivation: &Derivation<E>,
+        initial: &[E],
+        goal: &[E],
+        rules: &RuleSet<E, R>,
+        config: &Config<E>,
+    ) -> Result<Derivation<E>, ProofError>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        let live = live_steps(&derivation.steps, initial, goal)?;
+        let mut steps = derivation
+            .steps
+            .iter()
+            .zip(live)
+            .filter(move |(_, live)| *live)
+            .map(move |(step, _)| step.clone())
+            .collect::<Vec<_>>();
+        let mut index = 0;
+        while index + 1 < steps.len() {
+            match shortcut(&steps[index], &steps[index + 1], rules, config) {
+                Some(step) => {
+                    steps[index] = step;
+                    steps.remove(index + 1);
+                }
+                _ => index += 1,
+            }
+        }
+        let (minimized, mut rest) = rebuild(initial, steps)?;
+        check_with(&minimized, rules, initial, config.is_variable)?;
+        for e in goal {
+            take(&mut rest, e).ok_or(ProofError::new(
+                minimized.len(),
+                ProofErrorKind::GoalNotReached,
+            ))?;
+        }
+        log_debug!(
+            "minimized derivation from {} to {} steps",
+            derivation.len(),
+            minimized.len()
+        );
+        Ok(minimized)
+    }
+
+    /// Lemma
+    ///
+    /// Derived rule factoring a step sequence which is repeated across derivations.
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub struct Lemma {
+        /// Identifier of the derived rule
+        pub rule: RuleId,
+
+        /// Rules of the factored step sequence, in order
+        pub chain: Vec<RuleId>,
+
+        /// Number of step sequences replaced by a step of the lemma
+        pub uses: usize,
+    }
+
+    /// Counts the occurrences of every rule sequence with length between `2` and `max_len` in
+    /// `derivations`, without overlaps inside the same derivation.
+    pub fn repeated_chains<E>(
+        derivations: &[Derivation<E>],
+        max_len: usize,
+    ) -> BTreeMap<Vec<RuleId>, usize>
+    where
+        E: Expression,
+    {
+        let mut counts = BTreeMap::<Vec<RuleId>, usize>::new();
+        for derivation in derivations {
+            let rules = derivation.rules().collect::<Vec<_>>();
+            for len in 2..=max_len.min(rules.len()) {
+                let mut ends = BTreeMap::<&[RuleId], usize>::new();
+                for start in 0..=rules.len() - len {
+                    let chain = &rules[start..start + len];
+                    let end = ends.entry(chain).or_insert(0);
+                    if start >= *end {
+                        *end = start + len;
+                        *counts.entry(chain.to_vec()).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+        counts
+    }
+
+    /// Replaces the occurrences of `chain` in `steps` by a single step of `lemma`, returning
+    /// the number of replaced occurrences. Occurrences whose combined effect is not a step of
+    /// `lemma` are kept.
+    fn factor<E, R>(
+        steps: &mut Vec<Step<E>>,
+        chain: &[RuleId],
+        lemma: RuleId,
+        rules: &RuleSet<E, R>,
+        config: &Config<E>,
+    ) -> usize
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        let mut uses = 0;
+        let mut index = 0;
+        while index + chain.len() <= steps.len() {
+            let window = &steps[index..index + chain.len()];
+            if !window.iter().map(move |s| s.rule).eq(chain.iter().copied()) {
+                index += 1;
+                continue;
+            }
+            let (consumed, produced, _) = combine(window);
+            match single_step(consumed, &produced, rules, config, move |id| id == lemma) {
+                Some(mut step) => {
+                    step.digest = window[chain.len() - 1].digest;
+                    steps[index] = step;
+                    steps.drain(index + 1..index + chain.len());
+                    uses += 1;
+                    index += 1;
+                }
+                _ => index += 1,
+            }
+        }
+        uses
+    }
+
+    /// Extracts lemmas from `derivations`: the rule sequences with length at most `max_len`
+    /// occurring at least `min_count` times are [derived](RuleSet::derive) into new rules of
+    /// `rules`, most steps saved first, and their occurrences in `derivations` are replaced by
+    /// single steps of the new rules, found with `config`. The weights of the rules of a
+    /// sequence are combined into the score of its lemma using `scoring`.
+    ///
+    /// Derived rules which cannot replace at least `min_count` occurrences are removed again.
+    /// The final states of the rewritten derivations are unchanged.
+    pub fn extract_lemmas<E, R>(
+        derivations: &mut [Derivation<E>],
+        rules: &mut RuleSet<E, R>,
+        config: &Config<E>,
+        max_len: usize,
+        min_count: usize,
+        scoring: Scoring,
+    ) -> Vec<Lemma>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        let min_count = min_count.max(2);
+        let mut lemmas = Vec::new();
+        let mut rejected = BTreeSet::new();
+        loop {
+            let best = repeated_chains(derivations, max_len)
+                .into_iter()
+                .filter(|(chain, count)| *count >= min_count && !rejected.contains(chain))
+                .max_by_key(move |(chain, count)| ((chain.len() - 1) * count, chain.len()));
+            let chain = match best {
+                Some((chain, _)) => chain,
+                _ => break,
+            };
+            let lemma = match rules.derive(format!("lemma-{}", lemmas.len()), &chain, scoring) {
+                Ok(id) => id,
+                _ => {
+                    rejected.insert(chain);
+                    continue;
+                }
+            };
+            let mut rewritten = derivations
+                .iter()
+                .map(|derivation| derivation.steps.clone())
+                .collect::<Vec<_>>();
+            let uses = rewritten
+                .iter_mut()
+                .map(|steps| factor(steps, &chain, lemma, rules, config))
+                .sum::<usize>();
+            if uses < min_count {
+                rules.remove(lemma);
+                rejected.insert(chain);
+                continue;
+            }
+            for (derivation, steps) in derivations.iter_mut().zip(rewritten) {
+                derivation.steps = steps;
+            }
+            log_debug!("extracted lemma {} used {} times", lemma, uses);
+            lemmas.push(Lemma {
+                rule: lemma,
+                chain,
+                uses,
+            });
+        }
+        lemmas
+    }
+
+    /// Explanation
+    ///
+    /// Tree explaining why a fact holds after a [`Derivation`].
+    #[derive(Debug)]
+    pub struct Explanation<'d, E>
+    where
+        E: Expression,
+    {
+        /// Explained fact
+        pub fact: &'d E,
+
+        /// Step which produced the fact, or `None` if the fact was given
+        pub cause: Option<Cause<'d, E>>,
+    }
+
+    /// Explanation Cause
+    ///
+    /// Rule application which produced an explained fact.
+    #[derive(Debug)]
+    pub struct Cause<'d, E>
+    where
+        E: Expression,
+    {
+        /// Index of the step in the derivation
+        pub step: usize,
+
+        /// Identifier of the applied rule
+        pub rule: RuleId,
+
+        /// Bindings of the variables of the rule
+        pub bindings: &'d substitution::Structure<E>,
+
+        /// Explanations of the facts consumed by the step
+        pub premises: Vec<Explanation<'d, E>>,
+    }
+
+    impl<'d, E> Explanation<'d, E>
+    where
+        E: Expression,
+    {
+        /// Returns `true` if the fact was given rather than derived.
+        #[inline]
+        pub fn is_given(&self) -> bool {
+            self.cause.is_none()
+        }
+
+        /// Returns the depth of the explanation tree, which is `0` for given facts.
+        #[inline]
+        pub fn depth(&self) -> usize {
+            self.cause.as_ref().map_or(0, move |cause| {
+                1 + cause.premises.iter().map(Self::depth).max().unwrap_or(0)
+            })
+        }
+
+        /// Returns the indices of the steps used by the explanation.
+        #[inline]
+        pub fn steps(&self) -> BTreeSet<usize> {
+            let mut steps = BTreeSet::new();
+            self.collect_steps(&mut steps);
+            steps
+        }
+
+        fn collect_steps(&self, steps: &mut BTreeSet<usize>) {
+            if let Some(cause) = &self.cause {
+                steps.insert(cause.step);
+                for premise in &cause.premises {
+                    premise.collect_steps(steps);
+                }
+            }
+        }
+
+        /// Returns the given facts the explanation rests on.
+        #[inline]
+        pub fn givens(&self) -> Vec<&'d E> {
+            match &self.cause {
+                Some(cause) => cause.premises.iter().flat_map(Self::givens).collect(),
+                _ => alloc::vec![self.fact],
+            }
+        }
+    }
+
+    /// Explains the fact produced by the element `index` of step `step`.
+    fn cause_of<'d, E>(
+        steps: &'d [Step<E>],
+        claimed: &mut [Vec<bool>],
+        step: usize,
+        index: usize,
+    ) -> Explanation<'d, E>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        claimed[step][index] = true;
+        let premises = steps[step]
+            .consumed
+            .iter()
+            .map(|fact| explain_before(steps, claimed, fact, step))
+            .collect();
+        Explanation {
+            fact: &steps[step].produced[index],
+            cause: Some(Cause {
+                step,
+                rule: steps[step].rule,
+                bindings: &steps[step].bindings,
+                premises,
+            }),
+        }
+    }
+
+    /// Explains `fact` as consumed by a step after the first `before` steps.
+    fn explain_before<'d, E>(
+        steps: &'d [Step<E>],
+        claimed: &mut [Vec<bool>],
+        fact: &'d E,
+        before: usize,
+    ) -> Explanation<'d, E>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        match producer(steps, claimed, fact, before) {
+            Some((step, index)) => cause_of(steps, claimed, step, index),
+            _ => Explanation { fact, cause: None },
+        }
+    }
+
+    /// Finds the latest element of the first `before` steps equal to `fact` which does not
+    /// already explain another fact.
+    #[inline]
+    fn producer<E>(
+        steps: &[Step<E>],
+        claimed: &[Vec<bool>],
+        fact: &E,
+        before: usize,
+    ) -> Option<(usize, usize)>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        (0..before).rev().find_map(move |step| {
+            steps[step]
+                .produced
+                .iter()
+                .zip(&claimed[step])
+                .position(move |(e, claimed)| !claimed && E::eq(e, fact))
+                .map(move |index| (step, index))
+        })
+    }
+
+    /// Explains why `conclusion` holds after `derivation`, tracing the latest step producing
+    /// it back through the steps producing the facts it consumed, down to given facts.
+    ///
+    /// Returns `None` if no step of `derivation` produces `conclusion`.
+    pub fn explain<'d, E>(
+        derivation: &'d Derivation<E>,
+        conclusion: &E,
+    ) -> Option<Explanation<'d, E>>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        let steps = &derivation.steps;
+        let mut claimed = steps
+            .iter()
+            .map(move |step| alloc::vec![false; step.produced.len()])
+            .collect::<Vec<_>>();
+        let (step, index) = producer(steps, &claimed, conclusion, steps.len())?;
+        Some(cause_of(steps, &mut claimed, step, index))
+    }
+
+    /// Derivation Record Format
+    #[cfg(feature = "serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+    pub mod record {
+        use {
+            super::*,
+            crate::rule::database::ExprRecord,
+            serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer},
+        };
+
+        /// Current Derivation Format Version
+        pub const FORMAT_VERSION: u32 = 1;
+
+        /// Rule Reference
+        ///
+        /// Refers to a rule by identifier and, optionally, by [content hash](Rule::content_hash)
+        /// so that the rule can be found again if the rule set is renumbered.
+        #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+        pub struct RuleReference {
+            /// Rule Identifier
+            pub id: RuleId,
+
+            /// Rule Content Hash
+            #[serde(default)]
+            pub hash: Option<u64>,
+        }
+
+        /// Step Record Type
+        #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+        pub struct StepRecord<A> {
+            /// Applied rule
+            pub rule: RuleReference,
+
+            /// Bindings of the variables of the rule
+            #[serde(default)]
+            pub bindings: Vec<(A, ExprRecord<A>)>,
+
+            /// Elements consumed from the state
+            #[serde(default)]
+            pub consumed: Vec<ExprRecord<A>>,
+
+            /// Elements produced into the state
+            #[serde(default)]
+            pub produced: Vec<ExprRecord<A>>,
+
+            /// Digest of the resulting state
+            pub digest: u64,
+        }
+
+        impl<A> StepRecord<A> {
+            /// Builds a new [`StepRecord`] referring to the rule of `step` by identifier and by
+            /// `hash`.
+            #[inline]
+            pub(crate) fn from_step<E>(step: &Step<E>, hash: Option<u64>) -> Self
+            where
+                E: Expression<Atom = A>,
+                A: Clone,
+            {
+                Self {
+                    rule: RuleReference {
+                        id: step.rule,
+                        hash,
+                    },
+                    bindings: step
+                        .bindings
+                        .terms
+                        .iter()
+                        .map(move |t| (t.var.clone(), ExprRecord::from_expr(&t.expr)))
+                        .collect(),
+                    consumed: step.consumed.iter().map(ExprRecord::from_expr).collect(),
+                    produced: step.produced.iter().map(ExprRecord::from_expr).collect(),
+                    digest: step.digest,
+                }
+            }
+
+            /// Converts the record back into a [`Step`] applying the rule `rule`.
+            #[inline]
+            pub(crate) fn into_step<E>(self, rule: RuleId) -> Step<E>
+            where
+                E: Expression<Atom = A>,
+                E::Group: FromIterator<E>,
+            {
+                Step {
+                    rule,
+                    bindings: self
+                        .bindings
+                        .into_iter()
+                        .map(move |(var, expr)| substitution::Term::new(var, expr.into_expr()))
+                        .collect(),
+                    consumed: self
+                        .consumed
+                        .into_iter()
+                        .map(ExprRecord::into_expr)
+                        .collect(),
+                    produced: self
+                        .produced
+                        .into_iter()
+                        .map(ExprRecord::into_expr)
+                        .collect(),
+                    digest: self.digest,
+                }
+            }
+        }
+
+        /// Derivation Record Type
+        ///
+        /// Serialization data model for a [`Derivation`]. The [`version`](Self::version) field
+        /// records the format the derivation was written with so that older derivations can be
+        /// upgraded with [`migrate`](Self::migrate) before they are checked. Records written
+        /// before the field existed are read as version `0`.
+        #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+        pub struct DerivationRecord<A> {
+            /// Format Version
+            #[serde(default)]
+            pub version: u32,
+
+            /// Digest of the initial state
+            pub initial: u64,
+
+            /// Step Records
+            #[serde(default)]
+            pub steps: Vec<StepRecord<A>>,
+        }
+
+        /// Resolution Error Type
+        #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+        pub struct ResolveError {
+            /// Index of the step whose rule could not be found
+            pub step: usize,
+
+            /// Unresolved rule reference
+            pub rule: RuleReference,
+        }
+
+        impl fmt::Display for ResolveError {
+            #[inline]
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(
+                    f,
+                    "rule {} of step {} could not be resolved",
+                    self.rule.id, self.step
+                )
+            }
+        }
+
+        #[cfg(feature = "std")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+        impl std::error::Error for ResolveError {}
+
+        /// Migration Error Type
+        #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+        pub enum MigrationError {
+            /// The format version is newer than [`FORMAT_VERSION`].
+            UnsupportedVersion(u32),
+
+            /// A migration did not advance the format version.
+            StalledMigration(u32),
+        }
+
+        impl fmt::Display for MigrationError {
+            #[inline]
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self {
+                    Self::UnsupportedVersion(version) => {
+                        write!(f, "unsupported derivation format version ({})", version)
+                    }
+                    Self::StalledMigration(version) => write!(
+                        f,
+                        "derivation migration did not advance the version ({})",
+                        version
+                    ),
+                }
+            }
+        }
+
+        #[cfg(feature = "std")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+        impl std::error::Error for MigrationError {}
+
+        /// Applies the built-in migration for the version of `record`.
+        ///
+        /// Version `0` records only lack the version field, so they are upgraded unchanged.
+        pub fn migrate_builtin<A>(
+            mut record: DerivationRecord<A>,
+        ) -> Result<DerivationRecord<A>, MigrationError> {
+            match record.version {
+                0 => {
+                    record.version = 1;
+                    Ok(record)
+                }
+                version => Err(MigrationError::UnsupportedVersion(version)),
+            }
+        }
+
+        impl<A> DerivationRecord<A> {
+            /// Builds a new [`DerivationRecord`] referring to rules by identifier only.
+            #[inline]
+            pub fn from_derivation<E>(derivation: &Derivation<E>) -> Self
+            where
+                E: Expression<Atom = A>,
+                A: Clone,
+            {
+                Self::from_derivation_with(derivation, move |_| None)
+            }
+
+            /// Builds a new [`DerivationRecord`] referring to rules by identifier and by the
+            /// content hash of the corresponding rule of `rules`.
+            #[inline]
+            pub fn from_derivation_in<E, R>(
+                derivation: &Derivation<E>,
+                rules: &RuleSet<E, R>,
+            ) -> Self
+            where
+                E: Expression<Atom = A>,
+                E::Group: Container<E>,
+                R: Rule<E>,
+                A: Clone + Hash,
+            {
+                Self::from_derivation_with(derivation, move |id| {
+                    rules.rule(id).map(move |rule| rule.content_hash())
+                })
+            }
+
+            /// Builds a new [`DerivationRecord`] using `hash` to compute the content hash of
+            /// every referenced rule.
+            pub fn from_derivation_with<E, F>(derivation: &Derivation<E>, mut hash: F) -> Self
+            where
+                E: Expression<Atom = A>,
+                A: Clone,
+                F: FnMut(RuleId) -> Option<u64>,
+            {
+                Self {
+                    version: FORMAT_VERSION,
+                    initial: derivation.initial,
+                    steps: derivation
+                        .steps()
+                        .iter()
+                        .map(|step| StepRecord::from_step(step, hash(step.rule)))
+                        .collect(),
+                }
+            }
+
+            /// Upgrades the record to [`FORMAT_VERSION`] by repeatedly calling `hook` on older
+            /// versions. Each call must return a record with a strictly larger version.
+            pub fn migrate<F>(mut self, mut hook: F) -> Result<Self, MigrationError>
+            where
+                F: FnMut(Self) -> Result<Self, MigrationError>,
+            {
+                while self.version < FORMAT_VERSION {
+                    let version = self.version;
+                    self = hook(self)?;
+                    if self.version <= version {
+                        return Err(MigrationError::StalledMigration(version));
+                    }
+                }
+                if self.version > FORMAT_VERSION {
+                    Err(MigrationError::UnsupportedVersion(self.version))
+                } else {
+                    Ok(self)
+                }
+            }
+
+            /// Upgrades the record to [`FORMAT_VERSION`] using the built-in migrations.
+            #[inline]
+            pub fn upgrade(self) -> Result<Self, MigrationError> {
+                self.migrate(migrate_builtin)
+            }
+
+            /// Converts the record back into a [`Derivation`], trusting the rule identifiers.
+            #[inline]
+            pub fn into_derivation<E>(self) -> Derivation<E>
+            where
+                E: Expression<Atom = A>,
+                E::Group: FromIterator<E>,
+            {
+                let mut derivation = Derivation::from_digest(self.initial);
+                for step in self.steps {
+                    let rule = step.rule.id;
+                    derivation.push(step.into_step(rule));
+                }
+                derivation
+            }
+
+            /// Converts the record back into a [`Derivation`] against `rules`. Rule references
+            /// whose identifier does not match their content hash are resolved by finding the
+            /// rule with that content hash.
+            pub fn resolve<E, R>(self, rules: &RuleSet<E, R>) -> Result<Derivation<E>, ResolveError>
+            where
+                E: Expression<Atom = A>,
+                E::Group: Container<E>,
+                R: Rule<E>,
+                A: Hash,
+            {
+                self.resolve_with(move |_, reference| match reference.hash {
+                    Some(hash) => match rules.rule(reference.id) {
+                        Some(rule) if rule.content_hash() == hash => Some(reference.id),
+                        _ => rules
+                            .iter()
+                            .find(move |entry| entry.rule.content_hash() == hash)
+                            .map(move |entry| entry.id),
+                    },
+                    _ => rules.rule(reference.id).map(move |_| reference.id),
+                })
+            }
+
+            /// Converts the record back into a [`Derivation`] using `resolve` to find the
+            /// identifier of the rule of every step.
+            pub fn resolve_with<E, F>(self, mut resolve: F) -> Result<Derivation<E>, ResolveError>
+            where
+                E: Expression<Atom = A>,
+                E::Group: FromIterator<E>,
+                F: FnMut(usize, &RuleReference) -> Option<RuleId>,
+            {
+                let mut derivation = Derivation::from_digest(self.initial);
+                for (index, step) in self.steps.into_iter().enumerate() {
+                    let rule = resolve(index, &step.rule).ok_or(ResolveError {
+                        step: index,
+                        rule: step.rule,
+                    })?;
+                    derivation.push(step.into_step(rule));
+                }
+                Ok(derivation)
+            }
+        }
+
+        impl<E> Serialize for Derivation<E>
+        where
+            E: Expression,
+            E::Atom: Clone + Serialize,
+        {
+            #[inline]
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                DerivationRecord::from_derivation(self).serialize(serializer)
+            }
+        }
+
+        impl<'de, E> Deserialize<'de> for Derivation<E>
+        where
+            E: Expression,
+            E::Atom: Deserialize<'de>,
+            E::Group: FromIterator<E>,
+        {
+            #[inline]
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                Ok(DerivationRecord::deserialize(deserializer)?
+                    .upgrade()
+                    .map_err(D::Error::custom)?
+                    .into_derivation())
+            }
+        }
+    }
+
+    /// Proof Error Kind
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum ProofErrorKind {
+        /// The digest of the initial state does not match the derivation.
+        InitialDigest,
+
+        /// The rule of the step is not in the rule set.
+        UnknownRule(RuleId),
+
+        /// The bindings of the step bind an atom which is not a variable.
+        InvalidBinding,
+
+        /// The side conditions of the rule reject the bindings of the step.
+        Disallowed,
+
+        /// A premise of the rule does not hold in the state before the step.
+        PremiseMissing,
+
+        /// An absence pattern of the rule matches the state before the step.
+        AbsenceViolated,
+
+        /// The consumed elements are not the instantiated top of the rule.
+        ConsumedMismatch,
+
+        /// The consumed elements are not contained in the state before the step.
+        NotInState,
+
+        /// The produced elements are not the instantiated bottom of the rule.
+        ProducedMismatch,
+
+        /// The digest of the state after the step does not match the derivation.
+        DigestMismatch,
+
+        /// The final state does not contain the goal, reported at the length of the
+        /// derivation.
+        GoalNotReached,
+    }
+
+    impl fmt::Display for ProofErrorKind {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::InitialDigest => f.write_str("initial state does not match the derivation"),
+                Self::UnknownRule(id) => write!(f, "rule {} is not in the rule set", id),
+                Self::InvalidBinding => {
+                    f.write_str("bindings bind an atom which is not a variable")
+                }
+                Self::Disallowed => f.write_str("side conditions reject the bindings"),
+                Self::PremiseMissing => f.write_str("premise does not hold"),
+                Self::AbsenceViolated => f.write_str("absence pattern matches the state"),
+                Self::ConsumedMismatch => f.write_str("consumed elements are not the rule top"),
+                Self::NotInState => f.write_str("consumed elements are not in the state"),
+                Self::ProducedMismatch => f.write_str("produced elements are not the rule bottom"),
+                Self::DigestMismatch => f.write_str("state digest does not match the derivation"),
+                Self::GoalNotReached => f.write_str("final state does not contain the goal"),
+            }
+        }
+    }
+
+    /// Proof Error
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct ProofError {
+        /// Index of the failing step
+        pub step: usize,
+
+        /// Error kind
+        pub kind: ProofErrorKind,
+    }
+
+    impl fmt::Display for ProofError {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "step {}: {}", self.step, self.kind)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for ProofError {}
+
+    impl ProofError {
+        /// Builds a new [`ProofError`].
+        #[inline]
+        pub fn new(step: usize, kind: ProofErrorKind) -> Self {
+            Self { step, kind }
+        }
+    }
+
+    /// Returns `true` if `lhs` and `rhs` are equal as multisets.
+    #[inline]
+    fn multiset_eq<E>(lhs: &[E], rhs: &[E]) -> bool
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        util::multiset_eq_by(
+            lhs.iter().map(move |e| e.cases()),
+            rhs.iter().map(move |e| e.cases()).collect(),
+            move |l, r| expr::eq_ref::<E>(l, r),
+        )
+    }
+
+    /// Checks every step of `derivation` against `rules` starting from `initial`, where any
+    /// atom may be bound by a substitution. Absence patterns only match elements equal to
+    /// their instantiation.
+    ///
+    /// See [`check_with`] for more details.
+    #[inline]
+    pub fn check<E, R>(
+        derivation: &Derivation<E>,
+        rules: &RuleSet<E, R>,
+        initial: &[E],
+    ) -> Result<(), ProofError>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        check_inner(derivation, rules, initial, move |_| true, move |_| false)
+    }
+
+    /// Checks every step of `derivation` against `rules` starting from `initial`, independently
+    /// of the engine which built it.
+    ///
+    /// For every step, the checker verifies that the rule exists, that its bindings only bind
+    /// atoms satisfying `is_variable`, that its side conditions, premises and absence patterns
+    /// hold, that the consumed and produced elements are the instantiated top and bottom of
+    /// the rule, that the consumed elements are contained in the state and that the digest of
+    /// the resulting state is correct.
+    #[inline]
+    pub fn check_with<E, R, F>(
+        derivation: &Derivation<E>,
+        rules: &RuleSet<E, R>,
+        initial: &[E],
+        is_variable: F,
+    ) -> Result<(), ProofError>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        F: Fn(&E::Atom) -> bool,
+    {
+        check_inner(derivation, rules, initial, &is_variable, &is_variable)
+    }
+
+    /// Checks `derivation` where `can_bind` selects the atoms which may be bound by the
+    /// substitutions and `is_variable` the variables of the absence patterns.
+    fn check_inner<E, R, B, F>(
+        derivation: &Derivation<E>,
+        rules: &RuleSet<E, R>,
+        initial: &[E],
+        mut can_bind: B,
+        mut is_variable: F,
+    ) -> Result<(), ProofError>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        B: FnMut(&E::Atom) -> bool,
+        F: FnMut(&E::Atom) -> bool,
+    {
+        check_initial(derivation, initial)?;
+        let mut state = initial.iter().map(E::clone).collect::<Vec<_>>();
+        for (index, step) in derivation.steps().iter().enumerate() {
+            let error = move |kind| Err(ProofError::new(index, kind));
+            let rule = match rules.rule(step.rule) {
+                Some(rule) => rule,
+                _ => return error(ProofErrorKind::UnknownRule(step.rule)),
+            };
+            if !step.bindings.terms.iter().all(|t| can_bind(&t.var)) {
+                return error(ProofErrorKind::InvalidBinding);
+            }
+            if !rule.allows(&step.bindings.terms) {
+                return error(ProofErrorKind::Disallowed);
+            }
+            let premises_hold = rule.premises_hold(|premise| {
+                let premise = step.bindings.apply_ref(premise);
+                state
+                    .iter()
+                    .any(|e| expr::eq_ref::<E>(&premise.cases(), &e.cases()))
+            });
+            if !premises_hold {
+                return error(ProofErrorKind::PremiseMissing);
+            }
+            if !engine::absences_hold(rule, &step.bindings, &state, &mut is_variable) {
+                return error(ProofErrorKind::AbsenceViolated);
+            }
+            let (top, bot) = rule.ref_pair();
+            let top = step
+                .bindings
+                .apply_group_ref(&top)
+                .into_iter()
+                .collect::<Vec<_>>();
+            if !multiset_eq(&top, &step.consumed) {
+                return error(ProofErrorKind::ConsumedMismatch);
+            }
+            let bot = step
+                .bindings
+                .apply_group_ref(&bot)
+                .into_iter()
+                .collect::<Vec<_>>();
+            if !multiset_eq(&bot, &step.produced) {
+                return error(ProofErrorKind::ProducedMismatch);
+            }
+            advance(&mut state, step, index)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that `initial` has the initial digest of `derivation`.
+    #[inline]
+    fn check_initial<E>(derivation: &Derivation<E>, initial: &[E]) -> Result<(), ProofError>
+    where
+        E: Expression,
+        E::Atom: Hash,
+    {
+        if expr::multiset_digest_of(initial) == derivation.initial {
+            Ok(())
+        } else {
+            Err(ProofError::new(0, ProofErrorKind::InitialDigest))
+        }
+    }
+
+    /// Applies `step`, at position `index` in its derivation, to `state`, checking that the
+    /// consumed elements are contained in the state and that the digest of the resulting
+    /// state is correct.
+    fn advance<E>(state: &mut Vec<E>, step: &Step<E>, index: usize) -> Result<(), ProofError>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: FromIterator<E>,
+    {
+        for e in &step.consumed {
+            take(state, e).ok_or(ProofError::new(index, ProofErrorKind::NotInState))?;
+        }
+        state.extend(step.produced.iter().map(E::clone));
+        if expr::multiset_digest_of(state) != step.digest {
+            return Err(ProofError::new(index, ProofErrorKind::DigestMismatch));
+        }
+        Ok(())
+    }
+
+    /// Textual Witness Format
+    ///
+    /// A line-oriented rendering of a derivation for review and version control. A witness
+    /// starts with the line `witness 1`, followed by one `given <expr>` line per element of the
+    /// initial state and one `step <name> <bindings> -> <produced>` line per step, where
+    /// `<name>` is the name of the applied rule, `<bindings>` is a group of `(variable expr)`
+    /// pairs and `<produced>` is the group of the produced elements. Expressions are written
+    /// as in the [plain-text rule format](rule::text). Empty lines and lines starting with `#`
+    /// are ignored.
+    pub mod witness {
+        use {
+            super::*,
+            crate::rule::text::{self, ParseErrorKind},
+            core::fmt::{Display, Write},
+        };
+
+        /// Witness Format Version
+        pub const VERSION: u32 = 1;
+
+        /// Witness Error Kind
+        #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+        pub enum WitnessErrorKind {
+            /// The witness does not start with the `witness` header.
+            MissingHeader,
+
+            /// The witness version is not [`VERSION`].
+            UnsupportedVersion,
+
+            /// The line is neither a `given` nor a `step` line.
+            UnknownDirective,
+
+            /// A `given` line follows a `step` line.
+            GivenAfterStep,
+
+            /// The step has no rule name.
+            MissingName,
+
+            /// No rule of the rule set has the name of the step.
+            UnknownRule,
+
+            /// The bindings of the step are not a group of `(variable expr)` pairs.
+            InvalidBindings,
+
+            /// The bindings of the step are not followed by `->`.
+            MissingArrow,
+
+            /// The produced elements of the step are not a group.
+            InvalidProduced,
+
+            /// An expression could not be parsed.
+            Syntax(ParseErrorKind),
+
+            /// The step is not a valid step from the current state.
+            Proof(ProofErrorKind),
+        }
+
+        impl fmt::Display for WitnessErrorKind {
+            #[inline]
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self {
+                    Self::MissingHeader => f.write_str("missing `witness` header"),
+                    Self::UnsupportedVersion => f.write_str("unsupported witness version"),
+                    Self::UnknownDirective => f.write_str("expected a `given` or `step` line"),
+                    Self::GivenAfterStep => f.write_str("`given` line after a `step` line"),
+                    Self::MissingName => f.write_str("step has no rule name"),
+                    Self::UnknownRule => f.write_str("step names an unknown rule"),
+                    Self::InvalidBindings => f.write_str("bindings are not variable pairs"),
+                    Self::MissingArrow => f.write_str("bindings are not followed by `->`"),
+                    Self::InvalidProduced => f.write_str("produced elements are not a group"),
+                    Self::Syntax(kind) => fmt::Display::fmt(kind, f),
+                    Self::Proof(kind) => fmt::Display::fmt(kind, f),
+                }
+            }
+        }
+
+        /// Witness Error
+        #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+        pub struct WitnessError {
+            /// Line of the error (starting at `1`)
+            pub line: usize,
+
+            /// Error kind
+            pub kind: WitnessErrorKind,
+        }
+
+        impl fmt::Display for WitnessError {
+            #[inline]
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "line {}: {}", self.line, self.kind)
+            }
+        }
+
+        #[cfg(feature = "std")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+        impl std::error::Error for WitnessError {}
+
+        impl WitnessError {
+            /// Builds a new [`WitnessError`].
+            #[inline]
+            pub fn new(line: usize, kind: WitnessErrorKind) -> Self {
+                Self { line, kind }
+            }
+        }
+
+        /// Returns `true` if `name` can be written as the rule name of a step.
+        #[inline]
+        fn is_valid_name(name: &str) -> bool {
+            !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        }
+
+        /// Writes the witness of `derivation` from `initial` using `atom` to write atoms and the
+        /// names of the rules of `rules`.
+        ///
+        /// Fails if the rule of a step is not in `rules` or if its name contains whitespace or
+        /// parentheses.
+        pub fn write_with<E, R, W, F>(
+            derivation: &Derivation<E>,
+            initial: &[E],
+            rules: &RuleSet<E, R>,
+            out: &mut W,
+            mut atom: F,
+        ) -> fmt::Result
+        where
+            E: Expression,
+            E::Group: Container<E>,
+            R: Rule<E>,
+            W: Write,
+            F: FnMut(&E::Atom, &mut W) -> fmt::Result,
+        {
+            writeln!(out, "witness {}", VERSION)?;
+            for e in initial {
+                out.write_str("given ")?;
+                text::write_expr_with::<E, W, F>(&e.cases(), out, &mut atom)?;
+                out.write_char('\n')?;
+            }
+            for step in derivation.steps() {
+                let name = &rules.get(step.rule).ok_or(fmt::Error)?.name;
+                if !is_valid_name(name) {
+                    return Err(fmt::Error);
+                }
+                write!(out, "step {} (", name)?;
+                for (i, term) in step.bindings.terms.iter().enumerate() {
+                    out.write_str(if i == 0 { "(" } else { " (" })?;
+                    atom(&term.var, out)?;
+                    out.write_char(' ')?;
+                    text::write_expr_with::<E, W, F>(&term.expr.cases(), out, &mut atom)?;
+                    out.write_char(')')?;
+                }
+                out.write_str(") -> (")?;
+                for (i, e) in step.produced.iter().enumerate() {
+                    if i > 0 {
+                        out.write_char(' ')?;
+                    }
+                    text::write_expr_with::<E, W, F>(&e.cases(), out, &mut atom)?;
+                }
+                out.write_str(")\n")?;
+            }
+            Ok(())
+        }
+
+        /// Writes the witness of `derivation` from `initial` with the names of the rules of
+        /// `rules`.
+        ///
+        /// See [`write_with`] for more details.
+        #[inline]
+        pub fn write<E, R, W>(
+            derivation: &Derivation<E>,
+            initial: &[E],
+            rules: &RuleSet<E, R>,
+            out: &mut W,
+        ) -> fmt::Result
+        where
+            E: Expression,
+            E::Atom: Display,
+            E::Group: Container<E>,
+            R: Rule<E>,
+            W: Write,
+        {
+            write_with(derivation, initial, rules, out, move |a, out| {
+                write!(out, "{}", a)
+            })
+        }
+
+        /// Returns the byte offset just past the group at the start of `source`.
+        fn group_end(source: &str) -> Option<usize> {
+            if !source.starts_with('(') {
+                return None;
+            }
+            let mut depth = 0usize;
+            for (offset, c) in source.char_indices() {
+                match c {
+                    '(' => depth += 1,
+                    ')' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(offset + 1);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            None
+        }
+
+        /// Parses the bindings of a step.
+        fn parse_bindings<E, F>(
+            source: &str,
+            atom: &mut F,
+        ) -> Result<substitution::Structure<E>, WitnessErrorKind>
+        where
+            E: Expression,
+            E::Group: Container<E>,
+            F: FnMut(&str) -> E::Atom,
+        {
+            let bindings = text::parse_expr_with::<E, _>(source, &mut *atom)
+                .map_err(move |error| WitnessErrorKind::Syntax(error.kind))?;
+            bindings
+                .group()
+                .ok_or(WitnessErrorKind::InvalidBindings)?
+                .into_iter()
+                .map(move |pair| {
+                    let mut pair = pair
+                        .group()
+                        .ok_or(WitnessErrorKind::InvalidBindings)?
+                        .into_iter();
+                    match (pair.next().and_then(E::atom), pair.next(), pair.next()) {
+                        (Some(var), Some(expr), None) => Ok(substitution::Term::new(var, expr)),
+                        _ => Err(WitnessErrorKind::InvalidBindings),
+                    }
+                })
+                .collect()
+        }
+
+        /// Parses a step line, without its `step` keyword, and applies it to `state`.
+        fn parse_step<E, R, F>(
+            line: &str,
+            rules: &RuleSet<E, R>,
+            state: &mut Vec<E>,
+            atom: &mut F,
+        ) -> Result<Step<E>, WitnessErrorKind>
+        where
+            E: Expression,
+            E::Atom: Clone + Hash + PartialEq,
+            E::Group: Container<E>,
+            R: Rule<E>,
+            F: FnMut(&str) -> E::Atom,
+        {
+            let line = line.trim_start();
+            let name_end = line.find(|c: char| c.is_whitespace()).unwrap_or(line.len());
+            let name = &line[..name_end];
+            if !is_valid_name(name) {
+                return Err(WitnessErrorKind::MissingName);
+            }
+            let entry = rules
+                .iter()
+                .find(move |entry| entry.name == name)
+                .ok_or(WitnessErrorKind::UnknownRule)?;
+            let rest = line[name_end..].trim_start();
+            let end = group_end(rest).ok_or(WitnessErrorKind::InvalidBindings)?;
+            let bindings = parse_bindings::<E, F>(&rest[..end], atom)?;
+            let rest = rest[end..].trim_start();
+            if !rest.starts_with("->") {
+                return Err(WitnessErrorKind::MissingArrow);
+            }
+            let produced = text::parse_expr_with::<E, _>(&rest[2..], &mut *atom)
+                .map_err(move |error| WitnessErrorKind::Syntax(error.kind))?
+                .group()
+                .ok_or(WitnessErrorKind::InvalidProduced)?
+                .into_iter()
+                .collect::<Vec<_>>();
+            let consumed = bindings
+                .apply_group_ref(&entry.rule.top())
+                .into_iter()
+                .collect::<Vec<_>>();
+            let bot = bindings
+                .apply_group_ref(&entry.rule.bot())
+                .into_iter()
+                .collect::<Vec<_>>();
+            if !multiset_eq(&bot, &produced) {
+                return Err(WitnessErrorKind::Proof(ProofErrorKind::ProducedMismatch));
+            }
+            for e in &consumed {
+                take(state, e).ok_or(WitnessErrorKind::Proof(ProofErrorKind::NotInState))?;
+            }
+            state.extend(produced.iter().map(E::clone));
+            Ok(Step {
+                rule: entry.id,
+                bindings,
+                consumed,
+                produced,
+                digest: expr::multiset_digest_of(state),
+            })
+        }
+
+        /// Parses a witness against `rules` using `atom` to build atoms from their text,
+        /// returning the initial state and the derivation.
+        ///
+        /// The consumed elements of every step are the top of its rule instantiated by its
+        /// bindings and the digests are recomputed, so that the parsed derivation replays from
+        /// the initial state.
+        pub fn parse_with<E, R, F>(
+            source: &str,
+            rules: &RuleSet<E, R>,
+            mut atom: F,
+        ) -> Result<(Vec<E>, Derivation<E>), WitnessError>
+        where
+            E: Expression,
+            E::Atom: Clone + Hash + PartialEq,
+            E::Group: Container<E>,
+            R: Rule<E>,
+            F: FnMut(&str) -> E::Atom,
+        {
+            let mut lines = source
+                .lines()
+                .enumerate()
+                .map(move |(index, line)| (index + 1, line.trim()))
+                .filter(move |(_, line)| !line.is_empty() && !line.starts_with('#'));
+            match lines.next() {
+                Some((line, header)) => match header.strip_prefix("witness") {
+                    Some(version) if version.starts_with(char::is_whitespace) => {
+                        if version.trim().parse::<u32>() != Ok(VERSION) {
+                            return Err(WitnessError::new(
+                                line,
+                                WitnessErrorKind::UnsupportedVersion,
+                            ));
+                        }
+                    }
+                    _ => return Err(WitnessError::new(line, WitnessErrorKind::MissingHeader)),
+                },
+                _ => return Err(WitnessError::new(1, WitnessErrorKind::MissingHeader)),
+            }
+            let mut initial = Vec::new();
+            let mut state = Vec::new();
+            let mut derivation = None;
+            for (line, content) in lines {
+                let error = move |kind| WitnessError::new(line, kind);
+                if let Some(given) = content.strip_prefix("given ") {
+                    if derivation.is_some() {
+                        return Err(error(WitnessErrorKind::GivenAfterStep));
+                    }
+                    let e = text::parse_expr_with::<E, _>(given, &mut atom)
+                        .map_err(move |err| error(WitnessErrorKind::Syntax(err.kind)))?;
+                    state.push(E::clone(&e));
+                    initial.push(e);
+                } else if let Some(step) = content.strip_prefix("step ") {
+                    let step = parse_step(step, rules, &mut state, &mut atom).map_err(error)?;
+                    derivation
+                        .get_or_insert_with(|| Derivation::new(&initial))
+                        .push(step);
+                } else {
+                    return Err(error(WitnessErrorKind::UnknownDirective));
+                }
+            }
+            let derivation = derivation.unwrap_or_else(|| Derivation::new(&initial));
+            Ok((initial, derivation))
+        }
+
+        /// Parses a witness against `rules`.
+        ///
+        /// See [`parse_with`] for more details.
+        #[inline]
+        pub fn parse<E, R>(
+            source: &str,
+            rules: &RuleSet<E, R>,
+        ) -> Result<(Vec<E>, Derivation<E>), WitnessError>
+        where
+            E: Expression,
+            E::Atom: Clone + Hash + PartialEq,
+            E::Group: Container<E>,
+            R: Rule<E>,
+            for<'a> E::Atom: From<&'a str>,
+        {
+            parse_with(source, rules, move |s| E::Atom::from(s))
+        }
+    }
+
+    #[cfg(all(test, feature = "simple"))]
+    mod tests {
+        use {
+            super::*,
+            crate::{engine::Engine, expr::SimpleExpr, fuel::Fuel, rule::Structure},
+            alloc::{string::String, vec},
+        };
+
+        type E = SimpleExpr;
+
+        fn is_variable(atom: &String) -> bool {
+            atom.starts_with('?')
+        }
+
+        fn atom(atom: &str) -> E {
+            E::atom(atom)
+        }
+
+        fn group<const N: usize>(items: [E; N]) -> E {
+            E::group(IntoIterator::into_iter(items))
+        }
+
+        fn apply(head: &str, arg: &str) -> E {
+            group([atom(head), atom(arg)])
+        }
+
+        fn rules() -> RuleSet<E> {
+            let mut rules = RuleSet::new();
+            rules.insert(
+                "fuse",
+                Structure::new(
+                    vec![apply("f", "?x"), apply("g", "?x")],
+                    vec![apply("h", "?x")],
+                ),
+            );
+            rules.insert(
+                "grow",
+                Structure::new(vec![apply("h", "?y")], vec![apply("k", "?y"), atom("c")]),
+            );
+            rules
+        }
+
+        fn initial() -> Vec<E> {
+            vec![apply("f", "a"), atom("b"), apply("g", "a")]
+        }
+
+        /// Returns the derivation built by running the engine from the initial state.
+        fn derivation() -> Derivation<E> {
+            let mut engine = Engine::new(rules(), initial(), Config::new(is_variable));
+            assert_eq!(engine.run(&mut Fuel::unlimited()), Ok(2));
+            engine.into_parts().1
+        }
+
+        /// Returns a copy of `derivation` whose step at `index` is modified by `f`.
+        fn tampered<F>(derivation: &Derivation<E>, index: usize, f: F) -> Derivation<E>
+        where
+            F: FnOnce(&mut Step<E>),
+        {
+            let mut tampered = Derivation::from_digest(derivation.initial);
+            let mut f = Some(f);
+            for (i, step) in derivation.steps().iter().enumerate() {
+                let mut step = step.clone();
+                if i == index {
+                    if let Some(f) = f.take() {
+                        f(&mut step);
+                    }
+                }
+                tampered.push(step);
+            }
+            tampered
+        }
+
+        fn check(derivation: &Derivation<E>) -> Result<(), ProofError> {
+            check_with(derivation, &rules(), &initial(), is_variable)
+        }
+
+        fn error(step: usize, kind: ProofErrorKind) -> Result<(), ProofError> {
+            Err(ProofError::new(step, kind))
+        }
+
+        #[test]
+        fn accepts_engine_derivation() {
+            let derivation = derivation();
+            assert_eq!(check(&derivation), Ok(()));
+            assert_eq!(super::check(&derivation, &rules(), &initial()), Ok(()));
+        }
+
+        #[test]
+        fn rejects_wrong_initial_state() {
+            let derivation = derivation();
+            assert_eq!(
+                check_with(&derivation, &rules(), &[atom("b")], is_variable),
+                error(0, ProofErrorKind::InitialDigest)
+            );
+        }
+
+        #[test]
+        fn rejects_wrong_consumed_elements() {
+            let derivation = tampered(&derivation(), 0, |step| {
+                step.consumed = vec![apply("f", "a"), atom("b")];
+            });
+            assert_eq!(
+                check(&derivation),
+                error(0, ProofErrorKind::ConsumedMismatch)
+            );
+        }
+
+        #[test]
+        fn rejects_elements_missing_from_the_state() {
+            let derivation = tampered(&derivation(), 0, |step| {
+                step.bindings.terms[0].expr = atom("b");
+                step.consumed = vec![apply("f", "b"), apply("g", "b")];
+                step.produced = vec![apply("h", "b")];
+            });
+            assert_eq!(check(&derivation), error(0, ProofErrorKind::NotInState));
+        }
+
+        #[test]
+        fn rejects_wrong_produced_elements() {
+            let derivation = tampered(&derivation(), 1, |step| {
+                step.produced = vec![apply("k", "a")];
+            });
+            assert_eq!(
+                check(&derivation),
+                error(1, ProofErrorKind::ProducedMismatch)
+            );
+        }
+
+        #[test]
+        fn rejects_wrong_bindings() {
+            let derivation = tampered(&derivation(), 0, |step| {
+                step.bindings.terms[0].expr = atom("b");
+            });
+            assert_eq!(
+                check(&derivation),
+                error(0, ProofErrorKind::ConsumedMismatch)
+            );
+            let derivation = tampered(&derivation, 0, |step| {
+                step.bindings.terms[0].var = "a".into();
+            });
+            assert_eq!(check(&derivation), error(0, ProofErrorKind::InvalidBinding));
+        }
+
+        #[test]
+        fn rejects_unknown_rules() {
+            let derivation = tampered(&derivation(), 1, |step| step.rule = 99);
+            assert_eq!(
+                check(&derivation),
+                error(1, ProofErrorKind::UnknownRule(99))
+            );
+        }
+
+        #[test]
+        fn rejects_wrong_digests() {
+            let derivation = tampered(&derivation(), 1, |step| step.digest ^= 1);
+            assert_eq!(check(&derivation), error(1, ProofErrorKind::DigestMismatch));
+        }
+    }
+}
+
+/// Proof Search Module
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod search {
+    use {
+        super::*,
+        crate::{
+            engine::{self, Config, Match, Observer},
+            fuel::{Exhausted, Fuel},
+            proof::{Derivation, Step},
+            rule::{RuleProvider, RuleSet},
+            util::MultisetDigest,
+        },
+        alloc::{
+            collections::{BTreeMap, BTreeSet, BinaryHeap, VecDeque},
+            vec::Vec,
+        },
+        core::{cmp::Ordering, hash::Hash, mem},
+    };
+
+    /// Search Goal Trait
+    pub trait Goal<E>
+    where
+        E: Expression,
+    {
+        /// Returns `true` if `state` satisfies the goal.
+        fn is_reached(&self, state: &[E]) -> bool;
+    }
+
+    impl<E, F> Goal<E> for F
+    where
+        E: Expression,
+        F: Fn(&[E]) -> bool,
+    {
+        #[inline]
+        fn is_reached(&self, state: &[E]) -> bool {
+            self(state)
+        }
+    }
+
+    /// Exact State Goal
+    ///
+    /// Satisfied by the states which are equal to the target state as multisets.
+    #[derive(Clone, Copy, Debug)]
+    pub struct Exact<'s, E>(pub &'s [E]);
+
+    impl<'s, E> Goal<E> for Exact<'s, E>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        #[inline]
+        fn is_reached(&self, state: &[E]) -> bool {
+            util::multiset_eq_by(
+                self.0.iter().map(move |e| e.cases()),
+                state.iter().map(move |e| e.cases()).collect(),
+                move |l, r| expr::eq_ref::<E>(l, r),
+            )
+        }
+    }
+
+    /// Visited State Set
+    ///
+    /// Stores states up to permutation of their elements, indexed by their
+    /// [commutative digest](expr::multiset_digest_of). Exact sets also store the states to
+    /// resolve digest collisions, while hashed sets only store the digests, saving memory at
+    /// the cost of treating colliding states as visited.
+    #[derive(Debug)]
+    pub struct Visited<E> {
+        /// Stored states indexed by digest
+        states: BTreeMap<u64, Vec<Vec<E>>>,
+
+        /// Exactness flag
+        exact: bool,
+
+        /// Number of stored states
+        len: usize,
+    }
+
+    impl<E> Visited<E>
+    where
+        E: Expression,
+        E::Atom: Hash + PartialEq,
+    {
+        /// Builds a new exact [`Visited`] set.
+        #[inline]
+        pub fn new() -> Self {
+            Self {
+                states: BTreeMap::new(),
+                exact: true,
+                len: 0,
+            }
+        }
+
+        /// Builds a new hashed [`Visited`] set.
+        #[inline]
+        pub fn hashed() -> Self {
+            Self {
+                exact: false,
+                ..Self::new()
+            }
+        }
+
+        /// Returns `true` if the set stores the states and not only their digests.
+        #[inline]
+        pub fn is_exact(&self) -> bool {
+            self.exact
+        }
+
+        /// Returns the number of visited states.
+        #[inline]
+        pub fn len(&self) -> usize {
+            self.len
+        }
+
+        /// Returns `true` if no state was visited.
+        #[inline]
+        pub fn is_empty(&self) -> bool {
+            self.len == 0
+        }
+
+        /// Returns `true` if `state` was visited.
+        #[inline]
+        pub fn contains(&self, state: &[E]) -> bool {
+            self.states
+                .get(&expr::multiset_digest_of(state))
+                .map_or(false, |states| {
+                    !self.exact || states.iter().any(|s| Exact(s).is_reached(state))
+                })
+        }
+
+        /// Marks `state` as visited, returning `true` if it was not visited before.
+        pub fn insert(&mut self, state: &[E]) -> bool
+        where
+            E::Atom: Clone,
+            E::Group: FromIterator<E>,
+        {
+            let states = self
+                .states
+                .entry(expr::multiset_digest_of(state))
+                .or_default();
+            if self.exact {
+                if states.iter().any(|s| Exact(s).is_reached(state)) {
+                    return false;
+                }
+                states.push(state.iter().map(E::clone).collect());
+            } else if states.is_empty() {
+                states.push(Vec::new());
+            } else {
+                return false;
+            }
+            self.len += 1;
+            true
+        }
+
+        /// Removes every visited state.
+        #[inline]
+        pub fn clear(&mut self) {
+            self.states.clear();
+            self.len = 0;
+        }
+    }
+
+    impl<E> Default for Visited<E>
+    where
+        E: Expression,
+        E::Atom: Hash + PartialEq,
+    {
+        #[inline]
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// Search Filter Trait
+    ///
+    /// Decides, inside the frontier loop of a search, which newly generated states are kept.
+    pub trait Filter<E>
+    where
+        E: Expression,
+    {
+        /// Returns `true` if `state` should be kept in the search.
+        fn keep(&mut self, state: &[E]) -> bool;
+    }
+
+    impl<E, F> Filter<E> for F
+    where
+        E: Expression,
+        F: FnMut(&[E]) -> bool,
+    {
+        #[inline]
+        fn keep(&mut self, state: &[E]) -> bool {
+            self(state)
+        }
+    }
+
+    impl<E, A, B> Filter<E> for (A, B)
+    where
+        E: Expression,
+        A: Filter<E>,
+        B: Filter<E>,
+    {
+        #[inline]
+        fn keep(&mut self, state: &[E]) -> bool {
+            self.0.keep(state) && self.1.keep(state)
+        }
+    }
+
+    /// Trivial Filter
+    ///
+    /// Keeps every state.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct KeepAll;
+
+    impl<E> Filter<E> for KeepAll
+    where
+        E: Expression,
+    {
+        #[inline]
+        fn keep(&mut self, state: &[E]) -> bool {
+            let _ = state;
+            true
+        }
+    }
+
+    /// Abstraction Filter
+    ///
+    /// Maps every state to an abstract key and only keeps the first state with a given key.
+    #[derive(Clone, Debug)]
+    pub struct Abstraction<F, K> {
+        /// Abstraction function
+        key: F,
+
+        /// Keys of the kept states
+        seen: BTreeSet<K>,
+    }
+
+    impl<F, K> Abstraction<F, K>
+    where
+        K: Ord,
+    {
+        /// Builds a new [`Abstraction`] filter from an abstraction function.
+        #[inline]
+        pub fn new(key: F) -> Self {
+            Self {
+                key,
+                seen: BTreeSet::new(),
+            }
+        }
+
+        /// Returns the number of distinct keys seen by the filter.
+        #[inline]
+        pub fn len(&self) -> usize {
+            self.seen.len()
+        }
+
+        /// Returns `true` if the filter has not seen any key.
+        #[inline]
+        pub fn is_empty(&self) -> bool {
+            self.seen.is_empty()
+        }
+    }
+
+    impl<E, F, K> Filter<E> for Abstraction<F, K>
+    where
+        E: Expression,
+        F: FnMut(&[E]) -> K,
+        K: Ord,
+    {
+        #[inline]
+        fn keep(&mut self, state: &[E]) -> bool {
+            self.seen.insert((self.key)(state))
+        }
+    }
+
+    /// Calls `f` on every successor of `state` together with the step leading to it and the
+    /// weight of its rule.
+    ///
+    /// This is the transition relation shared by every search of this module.
+    pub fn expand<E, P, F>(state: &[E], rules: &P, config: &Config<E>, mut f: F)
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        F: FnMut(Vec<E>, Step<E>, Option<f64>),
+    {
+        engine::for_each_match(state, rules, config, |m| {
+            let next = engine::successor(state, &m);
+            let step = Step {
+                rule: m.rule,
+                bindings: m.bindings,
+                consumed: m.consumed.iter().map(|i| E::clone(&state[*i])).collect(),
+                produced: m.produced,
+                digest: expr::multiset_digest_of(&next),
+            };
+            f(next, step, m.weight);
+            false
+        });
+    }
+
+    /// Successor of a state with the step leading to it and the weight of its rule
+    type Successor<E> = (Vec<E>, Step<E>, Option<f64>);
+
+    /// Returns the successors of `state`, see [`expand`].
+    #[inline]
+    fn successors<E, P>(state: &[E], rules: &P, config: &Config<E>) -> Vec<Successor<E>>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+    {
+        let mut successors = Vec::new();
+        expand(state, rules, config, |next, step, weight| {
+            successors.push((next, step, weight))
+        });
+        successors
+    }
+
+    /// Search Tree Node
+    struct Node<E>
+    where
+        E: Expression,
+    {
+        /// Parent node and the step leading from it
+        parent: Option<(usize, Step<E>)>,
+    }
+
+    /// Search Tree
+    ///
+    /// Stores the nodes reached by a search so that the derivation of any node can be rebuilt.
+    struct Tree<E>
+    where
+        E: Expression,
+    {
+        /// Nodes in order of discovery
+        nodes: Vec<Node<E>>,
+    }
+
+    impl<E> Tree<E>
+    where
+        E: Expression,
+    {
+        /// Builds a new tree with a single root.
+        #[inline]
+        fn new() -> Self {
+            Self {
+                nodes: alloc::vec![Node { parent: None }],
+            }
+        }
+
+        /// Adds a child of `parent` reached by `step`, returning its index.
+        #[inline]
+        fn push(&mut self, parent: usize, step: Step<E>) -> usize {
+            self.nodes.push(Node {
+                parent: Some((parent, step)),
+            });
+            self.nodes.len() - 1
+        }
+
+        /// Takes the steps leading from the root to the node `index`.
+        fn take_path(&mut self, mut index: usize) -> Vec<Step<E>> {
+            let mut steps = Vec::new();
+            while let Some((parent, step)) = self.nodes[index].parent.take() {
+                steps.push(step);
+                index = parent;
+            }
+            steps.reverse();
+            steps
+        }
+
+        /// Returns the derivation of the node `index` from the root.
+        fn derivation(&self, initial: u64, mut index: usize) -> Derivation<E> {
+            let mut steps = Vec::new();
+            while let Some((parent, step)) = &self.nodes[index].parent {
+                steps.push(step.clone());
+                index = *parent;
+            }
+            let mut derivation = Derivation::from_digest(initial);
+            for step in steps.into_iter().rev() {
+                derivation.push(step);
+            }
+            derivation
+        }
+
+        /// Consumes the tree, returning the derivation of the node `index` from the root.
+        #[inline]
+        fn into_derivation(mut self, initial: u64, index: usize) -> Derivation<E> {
+            let mut derivation = Derivation::from_digest(initial);
+            for step in self.take_path(index) {
+                derivation.push(step);
+            }
+            derivation
+        }
+    }
+
+    /// Explores the rule applications from `initial` in breadth-first order, returning a
+    /// shortest derivation of a state satisfying `goal`.
+    ///
+    /// States equal up to permutation are only expanded once, see [`Visited`]. The search does
+    /// not terminate if the goal is unreachable, the state space is infinite and the `fuel`
+    /// is unlimited. See [`drive`] for the fuel consumption and the partial result, and for
+    /// the events reported to `observer`.
+    #[inline]
+    pub fn bfs<E, P, G, O>(
+        config: &Config<E>,
+        rules: &P,
+        initial: Vec<E>,
+        goal: G,
+        fuel: &mut Fuel,
+        observer: O,
+    ) -> Result<Option<Derivation<E>>, Exhausted<Derivation<E>>>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        G: Goal<E>,
+        O: Observer<E>,
+    {
+        bfs_with(config, rules, initial, goal, KeepAll, fuel, observer)
+    }
+
+    /// Runs a [breadth-first search](bfs) which drops the generated states rejected by
+    /// `filter`.
+    #[inline]
+    pub fn bfs_with<E, P, G, F, O>(
+        config: &Config<E>,
+        rules: &P,
+        initial: Vec<E>,
+        goal: G,
+        filter: F,
+        fuel: &mut Fuel,
+        observer: O,
+    ) -> Result<Option<Derivation<E>>, Exhausted<Derivation<E>>>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        G: Goal<E>,
+        F: Filter<E>,
+        O: Observer<E>,
+    {
+        drive(
+            config,
+            rules,
+            initial,
+            goal,
+            UnitCost,
+            filter,
+            VecDeque::new(),
+            fuel,
+            observer,
+        )
+        .map(move |found| found.map(move |(derivation, _)| derivation))
+        .map_err(move |exhausted| exhausted.map(move |(derivation, _)| derivation))
+    }
+
+    /// Collects the matches against `state` in reverse order of discovery so that popping them
+    /// visits them in order.
+    #[inline]
+    fn frame<E, P>(state: &[E], rules: &P, config: &Config<E>) -> Vec<Match<E>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+    {
+        let mut matches = engine::matches(state, rules, config);
+        matches.reverse();
+        matches
+    }
+
+    /// Applies the match `m` to `state` in place, updating the digest accumulator `digest`,
+    /// and returns the step recording it.
+    fn apply_in_place<E>(state: &mut Vec<E>, digest: &mut MultisetDigest, m: Match<E>) -> Step<E>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash,
+        E::Group: FromIterator<E>,
+    {
+        let mut consumed = Vec::with_capacity(m.consumed.len());
+        for index in m.consumed.iter().rev() {
+            let e = state.remove(*index);
+            digest.remove(expr::digest(&e));
+            consumed.push(e);
+        }
+        consumed.reverse();
+        for e in &m.produced {
+            digest.insert(expr::digest(e));
+            state.push(E::clone(e));
+        }
+        Step {
+            rule: m.rule,
+            bindings: m.bindings,
+            consumed,
+            produced: m.produced,
+            digest: digest.finish(),
+        }
+    }
+
+    /// Reverts `step`, which consumed the elements at `indices` in increasing order, applied to
+    /// `state` in place by [`apply_in_place`].
+    fn revert_in_place<E>(
+        state: &mut Vec<E>,
+        digest: &mut MultisetDigest,
+        step: Step<E>,
+        indices: &[usize],
+    ) where
+        E: Expression,
+        E::Atom: Hash,
+    {
+        for e in state.drain(state.len() - step.produced.len()..) {
+            digest.remove(expr::digest(&e));
+        }
+        for (index, e) in indices.iter().zip(step.consumed) {
+            digest.insert(expr::digest(&e));
+            state.insert(*index, e);
+        }
+    }
+
+    /// Runs a depth-limited search, returning the derivation found, if any, and whether some
+    /// state was cut off by the depth limit.
+    fn dfs_inner<E, P, G, O>(
+        config: &Config<E>,
+        rules: &P,
+        initial: &[E],
+        goal: &G,
+        limit: usize,
+        fuel: &mut Fuel,
+        observer: &mut O,
+    ) -> Result<(Option<Derivation<E>>, bool), Exhausted<Derivation<E>>>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        G: Goal<E>,
+        O: Observer<E>,
+    {
+        let digest = expr::multiset_digest_of(initial);
+        let into_derivation = move |path: Vec<Step<E>>| {
+            let mut derivation = Derivation::from_digest(digest);
+            for step in path {
+                derivation.push(step);
+            }
+            derivation
+        };
+        if goal.is_reached(initial) {
+            let derivation = Derivation::from_digest(digest);
+            observer.on_goal_reached(&derivation);
+            return Ok((Some(derivation), false));
+        }
+        if limit == 0 {
+            return Ok((None, true));
+        }
+        let mut cutoff = false;
+        let mut path = Vec::new();
+        let mut consumed = Vec::<Vec<usize>>::new();
+        let mut state = initial.iter().map(E::clone).collect::<Vec<_>>();
+        let mut state_digest = initial.iter().map(expr::digest).collect::<MultisetDigest>();
+        if let Err(reason) = fuel.step() {
+            observer.on_exhausted(reason);
+            return Err(Exhausted::new(reason, into_derivation(path)));
+        }
+        observer.on_state_expanded(initial);
+        let mut stack = alloc::vec![frame(initial, rules, config)];
+        while let Some(frame_matches) = stack.last_mut() {
+            match frame_matches.pop() {
+                Some(m) => {
+                    observer.on_match(m.rule);
+                    if let Err(reason) = fuel.node() {
+                        observer.on_exhausted(reason);
+                        return Err(Exhausted::new(reason, into_derivation(path)));
+                    }
+                    consumed.push(m.consumed.clone());
+                    let step = apply_in_place(&mut state, &mut state_digest, m);
+                    observer.on_rule_applied(&step);
+                    path.push(step);
+                    if goal.is_reached(&state) {
+                        let derivation = into_derivation(path);
+                        observer.on_goal_reached(&derivation);
+                        return Ok((Some(derivation), cutoff));
+                    }
+                    if path.len() < limit {
+                        if let Err(reason) = fuel.step() {
+                            observer.on_exhausted(reason);
+                            return Err(Exhausted::new(reason, into_derivation(path)));
+                        }
+                        observer.on_state_expanded(&state);
+                        stack.push(frame(&state, rules, config));
+                        continue;
+                    }
+                    cutoff = true;
+                }
+                _ => {
+                    stack.pop();
+                }
+            }
+            if let (Some(step), Some(indices)) = (path.pop(), consumed.pop()) {
+                observer.on_backtrack(path.len() + 1);
+                revert_in_place(&mut state, &mut state_digest, step, &indices);
+            }
+        }
+        Ok((None, cutoff))
+    }
+
+    /// Explores the rule applications from `initial` in depth-first order up to `limit` steps,
+    /// returning the first derivation found of a state satisfying `goal`.
+    ///
+    /// The search applies and reverts the steps along the current path on a single state,
+    /// updating its [digest](util::MultisetDigest) incrementally, and only stores the matches
+    /// against the states of the path, so backtracking copies no state and the memory used by
+    /// the search grows linearly with `limit`. Every expanded state consumes one step of `fuel`
+    /// and every generated state one node. If the fuel runs out, returns the
+    /// derivation of the current path as a partial result. The depth reported to `observer`
+    /// when backtracking is the length of the abandoned path.
+    #[inline]
+    pub fn dfs<E, P, G, O>(
+        config: &Config<E>,
+        rules: &P,
+        initial: &[E],
+        goal: G,
+        limit: usize,
+        fuel: &mut Fuel,
+        mut observer: O,
+    ) -> Result<Option<Derivation<E>>, Exhausted<Derivation<E>>>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        G: Goal<E>,
+        O: Observer<E>,
+    {
+        dfs_inner(config, rules, initial, &goal, limit, fuel, &mut observer)
+            .map(move |(found, _)| found)
+    }
+
+    /// Runs [`dfs`] with increasing depth limits up to `max_depth`, returning a shortest
+    /// derivation of a state satisfying `goal`.
+    ///
+    /// The search stops early if the whole state space was explored within the current limit.
+    /// The `fuel` is shared by all the iterations.
+    pub fn iddfs<E, P, G, O>(
+        config: &Config<E>,
+        rules: &P,
+        initial: &[E],
+        goal: G,
+        max_depth: usize,
+        fuel: &mut Fuel,
+        mut observer: O,
+    ) -> Result<Option<Derivation<E>>, Exhausted<Derivation<E>>>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        G: Goal<E>,
+        O: Observer<E>,
+    {
+        for limit in 0..=max_depth {
+            match dfs_inner(config, rules, initial, &goal, limit, fuel, &mut observer)? {
+                (Some(derivation), _) => return Ok(Some(derivation)),
+                (_, false) => return Ok(None),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Searches for a counterexample to `property`, a state reachable from `initial` in at
+    /// most `bound` steps which does not satisfy it, returning a shortest derivation of such a
+    /// state.
+    ///
+    /// Runs [`iddfs`] with the negation of `property` as goal, so `initial` itself is checked
+    /// first. Returns `Ok(None)` if every state reachable within `bound` steps satisfies the
+    /// property.
+    #[inline]
+    pub fn find_counterexample<E, P, Q, O>(
+        config: &Config<E>,
+        rules: &P,
+        initial: &[E],
+        property: Q,
+        bound: usize,
+        fuel: &mut Fuel,
+        observer: O,
+    ) -> Result<Option<Derivation<E>>, Exhausted<Derivation<E>>>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        Q: Goal<E>,
+        O: Observer<E>,
+    {
+        iddfs(
+            config,
+            rules,
+            initial,
+            move |state: &[E]| !property.is_reached(state),
+            bound,
+            fuel,
+            observer,
+        )
+    }
+
+    /// Search Cost Trait
+    pub trait Cost<E>
+    where
+        E: Expression,
+    {
+        /// Returns the non-negative cost of `step`, where `weight` is the weight of its rule.
+        fn cost(&self, step: &Step<E>, weight: Option<f64>) -> f64;
+
+        /// Returns an estimate of the cost of reaching the goal from `state`.
+        ///
+        /// The estimate must never exceed the actual cost for [`astar`] to return a
+        /// minimum-cost derivation.
+        #[inline]
+        fn heuristic(&self, state: &[E]) -> f64 {
+            let _ = state;
+            0.0
+        }
+    }
+
+    /// Unit Cost
+    ///
+    /// Every step costs `1` and the heuristic is zero.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct UnitCost;
+
+    impl<E> Cost<E> for UnitCost
+    where
+        E: Expression,
+    {
+        #[inline]
+        fn cost(&self, step: &Step<E>, weight: Option<f64>) -> f64 {
+            let _ = (step, weight);
+            1.0
+        }
+    }
+
+    /// Weight Cost
+    ///
+    /// Every step costs the [weight](Rule::weight) of its rule, or `1` if the rule has no
+    /// weight, and the heuristic is zero.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct WeightCost;
+
+    impl<E> Cost<E> for WeightCost
+    where
+        E: Expression,
+    {
+        #[inline]
+        fn cost(&self, step: &Step<E>, weight: Option<f64>) -> f64 {
+            let _ = step;
+            weight.unwrap_or(1.0)
+        }
+    }
+
+    /// Best-First Frontier Entry
+    #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+    struct Scored<T> {
+        /// Estimated total cost
+        priority: f64,
+
+        /// Insertion order used to break ties
+        order: usize,
+
+        /// Entry value
+        value: T,
+    }
+
+    impl<T> PartialEq for Scored<T> {
+        #[inline]
+        fn eq(&self, other: &Self) -> bool {
+            self.cmp(other) == Ordering::Equal
+        }
+    }
+
+    impl<T> Eq for Scored<T> {}
+
+    impl<T> PartialOrd for Scored<T> {
+        #[inline]
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl<T> Ord for Scored<T> {
+        /// Orders entries so that the largest entry has the lowest priority and was inserted
+        /// first.
+        #[inline]
+        fn cmp(&self, other: &Self) -> Ordering {
+            other
+                .priority
+                .total_cmp(&self.priority)
+                .then_with(|| other.order.cmp(&self.order))
+        }
+    }
+
+    /// Search Frontier Trait
+    ///
+    /// Stores the entries waiting to be expanded by a search and decides the order in which
+    /// they are expanded.
+    pub trait Frontier<T> {
+        /// Adds `item` to the frontier with the given `priority`, where lower priorities are
+        /// expanded first by frontiers which support them.
+        fn push(&mut self, item: T, priority: f64);
+
+        /// Removes the next item to expand from the frontier.
+        fn pop(&mut self) -> Option<T>;
+
+        /// Returns the number of items in the frontier.
+        fn len(&self) -> usize;
+
+        /// Returns `true` if the frontier is empty.
+        #[inline]
+        fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+    }
+
+    /// First-in first-out frontier ignoring priorities
+    impl<T> Frontier<T> for VecDeque<T> {
+        #[inline]
+        fn push(&mut self, item: T, priority: f64) {
+            let _ = priority;
+            self.push_back(item);
+        }
+
+        #[inline]
+        fn pop(&mut self) -> Option<T> {
+            self.pop_front()
+        }
+
+        #[inline]
+        fn len(&self) -> usize {
+            VecDeque::len(self)
+        }
+    }
+
+    /// Last-in first-out frontier ignoring priorities
+    impl<T> Frontier<T> for Vec<T> {
+        #[inline]
+        fn push(&mut self, item: T, priority: f64) {
+            let _ = priority;
+            Vec::push(self, item);
+        }
+
+        #[inline]
+        fn pop(&mut self) -> Option<T> {
+            Vec::pop(self)
+        }
+
+        #[inline]
+        fn len(&self) -> usize {
+            Vec::len(self)
+        }
+    }
+
+    /// Priority Queue Frontier
+    ///
+    /// Expands items in order of increasing priority, breaking ties in insertion order.
+    #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+    pub struct PriorityQueue<T> {
+        /// Underlying heap
+        heap: BinaryHeap<Scored<T>>,
+
+        /// Number of items inserted so far
+        order: usize,
+    }
+
+    impl<T> PriorityQueue<T> {
+        /// Builds a new empty [`PriorityQueue`].
+        #[inline]
+        pub fn new() -> Self {
+            Self {
+                heap: BinaryHeap::new(),
+                order: 0,
+            }
+        }
+    }
+
+    impl<T> Default for PriorityQueue<T> {
+        #[inline]
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<T> Frontier<T> for PriorityQueue<T> {
+        #[inline]
+        fn push(&mut self, item: T, priority: f64) {
+            self.heap.push(Scored {
+                priority,
+                order: self.order,
+                value: item,
+            });
+            self.order += 1;
+        }
+
+        #[inline]
+        fn pop(&mut self) -> Option<T> {
+            self.heap.pop().map(move |scored| scored.value)
+        }
+
+        #[inline]
+        fn len(&self) -> usize {
+            self.heap.len()
+        }
+    }
+
+    /// Frontier Entry
+    #[derive(Debug)]
+    pub struct Entry<E> {
+        /// State of the entry
+        pub state: Vec<E>,
+
+        /// Cost of the derivation of the state
+        pub cost: f64,
+
+        /// Node of the state in the search tree
+        node: usize,
+    }
+
+    /// Search State
+    ///
+    /// Holds the state of a [`drive`]n search as explicit data: the search tree, the set of
+    /// expanded states and the frontier. A search whose fuel ran out keeps its state and can
+    /// be resumed by calling [`run`](Self::run) again. With the `serde` feature, the state can
+    /// be serialized to checkpoint a search and resume it later or elsewhere, see
+    /// [`record::SearchRecord`].
+    pub struct Search<E, Q>
+    where
+        E: Expression,
+    {
+        /// Digest of the initial state
+        digest: u64,
+
+        /// Search tree
+        tree: Tree<E>,
+
+        /// Expanded states
+        expanded: Visited<E>,
+
+        /// Frontier
+        frontier: Q,
+
+        /// Entries popped from the frontier but not expanded when the fuel ran out, the next
+        /// one last
+        pending: Vec<Entry<E>>,
+
+        /// Node and cost of the last expanded state
+        last: (usize, f64),
+    }
+
+    impl<E, Q> Search<E, Q>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        Q: Frontier<Entry<E>>,
+    {
+        /// Builds a new search from `initial` using `frontier`, which should be empty.
+        #[inline]
+        pub fn new(initial: Vec<E>, mut frontier: Q) -> Self {
+            let digest = expr::multiset_digest_of(&initial);
+            frontier.push(
+                Entry {
+                    cost: 0.0,
+                    node: 0,
+                    state: initial,
+                },
+                0.0,
+            );
+            Self {
+                digest,
+                tree: Tree::new(),
+                expanded: Visited::new(),
+                frontier,
+                pending: Vec::new(),
+                last: (0, 0.0),
+            }
+        }
+
+        /// Returns the frontier of the search.
+        #[inline]
+        pub fn frontier(&self) -> &Q {
+            &self.frontier
+        }
+
+        /// Returns the set of expanded states.
+        #[inline]
+        pub fn expanded(&self) -> &Visited<E> {
+            &self.expanded
+        }
+
+        /// Returns the number of nodes of the search tree.
+        #[inline]
+        pub fn nodes(&self) -> usize {
+            self.tree.nodes.len()
+        }
+
+        /// Returns the derivation of the state of `entry`, which must belong to this search.
+        #[inline]
+        pub fn derivation(&self, entry: &Entry<E>) -> Derivation<E> {
+            self.tree.derivation(self.digest, entry.node)
+        }
+
+        /// Returns the derivation of the last expanded state and its cost.
+        #[inline]
+        pub fn last(&self) -> (Derivation<E>, f64) {
+            let (node, cost) = self.last;
+            (self.tree.derivation(self.digest, node), cost)
+        }
+
+        /// Expands the entries of the frontier until a state satisfying `goal` is popped,
+        /// returning its derivation and cost. See [`drive`] for more details.
+        ///
+        /// If the fuel runs out, the search keeps the entries which were not expanded yet, as
+        /// well as the entry being expanded, and can be resumed with new fuel.
+        #[allow(clippy::too_many_arguments)]
+        pub fn run<P, G, C, F, O>(
+            &mut self,
+            config: &Config<E>,
+            rules: &P,
+            goal: G,
+            cost: C,
+            mut filter: F,
+            fuel: &mut Fuel,
+            mut observer: O,
+        ) -> Result<Option<(Derivation<E>, f64)>, Exhausted<(Derivation<E>, f64)>>
+        where
+            P: RuleProvider<E>,
+            G: Goal<E>,
+            C: Cost<E>,
+            F: Filter<E>,
+            O: Observer<E>,
+        {
+            self.run_by(goal, cost, filter, 1, fuel, observer, move |entries| {
+                entries
+                    .iter()
+                    .map(move |entry| successors(&entry.state, rules, config))
+                    .collect()
+            })
+        }
+
+        /// Expands the entries of the frontier by batches of at most `batch` entries, computing
+        /// the successors of every batch with `successors`, until a state satisfying `goal` is
+        /// popped.
+        ///
+        /// The successors are processed in the order of the batch, skipping the states expanded
+        /// earlier in the batch, so the search does not depend on how `successors` computes them.
+        #[allow(clippy::too_many_arguments)]
+        fn run_by<G, C, F, O, S>(
+            &mut self,
+            goal: G,
+            cost: C,
+            mut filter: F,
+            batch: usize,
+            fuel: &mut Fuel,
+            mut observer: O,
+            mut successors: S,
+        ) -> Result<Option<(Derivation<E>, f64)>, Exhausted<(Derivation<E>, f64)>>
+        where
+            G: Goal<E>,
+            C: Cost<E>,
+            F: Filter<E>,
+            O: Observer<E>,
+            S: FnMut(&[Entry<E>]) -> Vec<Vec<Successor<E>>>,
+        {
+            loop {
+                let mut entries = Vec::new();
+                let mut popped = Visited::new();
+                let mut stop = None;
+                while entries.len() < batch.max(1) {
+                    let entry = match self.pending.pop().or_else(|| self.frontier.pop()) {
+                        Some(entry) => entry,
+                        _ => break,
+                    };
+                    if goal.is_reached(&entry.state) {
+                        let derivation = self.derivation(&entry);
+                        self.pending.extend(entries.into_iter().rev());
+                        observer.on_goal_reached(&derivation);
+                        return Ok(Some((derivation, entry.cost)));
+                    }
+                    if self.expanded.contains(&entry.state) || popped.contains(&entry.state) {
+                        continue;
+                    }
+                    if let Err(reason) = fuel.step() {
+                        self.pending.push(entry);
+                        stop = Some(reason);
+                        break;
+                    }
+                    popped.insert(&entry.state);
+                    entries.push(entry);
+                }
+                if entries.is_empty() && stop.is_none() {
+                    return Ok(None);
+                }
+                let batch_successors = successors(&entries);
+                let mut entries = entries.into_iter().zip(batch_successors);
+                while let Some((entry, successors)) = entries.next() {
+                    observer.on_state_expanded(&entry.state);
+                    for (next, step, weight) in successors {
+                        observer.on_match(step.rule);
+                        if self.expanded.contains(&next) || !filter.keep(&next) {
+                            continue;
+                        }
+                        if let Err(reason) = fuel.node() {
+                            let rest = entries.map(move |(entry, _)| entry).collect::<Vec<_>>();
+                            self.pending.extend(rest.into_iter().rev());
+                            self.pending.push(entry);
+                            observer.on_exhausted(reason);
+                            return Err(Exhausted::new(reason, self.last()));
+                        }
+                        observer.on_rule_applied(&step);
+                        let spent = entry.cost + cost.cost(&step, weight);
+                        let priority = spent + cost.heuristic(&next);
+                        let node = self.tree.push(entry.node, step);
+                        self.frontier.push(
+                            Entry {
+                                state: next,
+                                cost: spent,
+                                node,
+                            },
+                            priority,
+                        );
+                    }
+                    self.expanded.insert(&entry.state);
+                    self.last = (entry.node, entry.cost);
+                }
+                if let Some(reason) = stop {
+                    observer.on_exhausted(reason);
+                    return Err(Exhausted::new(reason, self.last()));
+                }
+            }
+        }
+    }
+
+    /// Generic Search Driver
+    ///
+    /// Expands the entries of `frontier` in the order it decides until a state satisfying
+    /// `goal` is expanded, returning its derivation and cost. Entries are pushed with their
+    /// cost plus the heuristic of `cost` as priority, states rejected by `filter` are dropped
+    /// and states equal up to permutation are only expanded once, see [`Visited`].
+    ///
+    /// Using a [`VecDeque`] gives a breadth-first search, a [`Vec`] a depth-first search and a
+    /// [`PriorityQueue`] a best-first search.
+    ///
+    /// Every expanded state consumes one step of `fuel` and every state pushed to the frontier
+    /// one node. If the fuel runs out, returns the derivation of the last expanded state and
+    /// its cost as a partial result. Use a [`Search`] directly to resume the search afterwards.
+    #[allow(clippy::too_many_arguments)]
+    #[inline]
+    pub fn drive<E, P, G, C, F, Q, O>(
+        config: &Config<E>,
+        rules: &P,
+        initial: Vec<E>,
+        goal: G,
+        cost: C,
+        mut filter: F,
+        frontier: Q,
+        fuel: &mut Fuel,
+        observer: O,
+    ) -> Result<Option<(Derivation<E>, f64)>, Exhausted<(Derivation<E>, f64)>>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        G: Goal<E>,
+        C: Cost<E>,
+        F: Filter<E>,
+        Q: Frontier<Entry<E>>,
+        O: Observer<E>,
+    {
+        filter.keep(&initial);
+        Search::new(initial, frontier).run(config, rules, goal, cost, filter, fuel, observer)
+    }
+
+    /// Explores the rule applications from `initial` in order of increasing estimated total
+    /// cost, returning a minimum-cost derivation of a state satisfying `goal` and its cost.
+    ///
+    /// States equal up to permutation are only expanded once, see [`Visited`], which preserves
+    /// optimality when the heuristic is consistent. See [`drive`] for the fuel consumption and
+    /// the partial result, and for the events reported to `observer`.
+    #[inline]
+    pub fn astar<E, P, G, C, O>(
+        config: &Config<E>,
+        rules: &P,
+        initial: Vec<E>,
+        goal: G,
+        cost: C,
+        fuel: &mut Fuel,
+        observer: O,
+    ) -> Result<Option<(Derivation<E>, f64)>, Exhausted<(Derivation<E>, f64)>>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        G: Goal<E>,
+        C: Cost<E>,
+        O: Observer<E>,
+    {
+        astar_with(config, rules, initial, goal, cost, KeepAll, fuel, observer)
+    }
+
+    /// Runs an [A* search](astar) which drops the generated states rejected by `filter`.
+    #[allow(clippy::too_many_arguments)]
+    #[inline]
+    pub fn astar_with<E, P, G, C, F, O>(
+        config: &Config<E>,
+        rules: &P,
+        initial: Vec<E>,
+        goal: G,
+        cost: C,
+        filter: F,
+        fuel: &mut Fuel,
+        observer: O,
+    ) -> Result<Option<(Derivation<E>, f64)>, Exhausted<(Derivation<E>, f64)>>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        G: Goal<E>,
+        C: Cost<E>,
+        F: Filter<E>,
+        O: Observer<E>,
+    {
+        drive(
+            config,
+            rules,
+            initial,
+            goal,
+            cost,
+            filter,
+            PriorityQueue::new(),
+            fuel,
+            observer,
+        )
+    }
+    /// Calls `f` on every predecessor of `state` together with the forward step leading from
+    /// it to `state`.
+    ///
+    /// Predecessors are found by matching the bottom of every rule against `state` and
+    /// replacing the matched elements by the instantiated top. Variables of the top of a rule
+    /// which do not occur in its bottom are left uninstantiated.
+    #[inline]
+    pub fn expand_backward<E, R, F>(state: &[E], rules: &RuleSet<E, R>, config: &Config<E>, f: F)
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        F: FnMut(Vec<E>, Step<E>),
+    {
+        expand_backward_inner(state, rules, config, true, f)
+    }
+
+    /// Calls `f` on every predecessor of `state` as in [`expand_backward`], checking the
+    /// premises and absence patterns of the rules against the predecessor only if `complete`
+    /// is set, that is if `state` is a whole state rather than a subgoal.
+    fn expand_backward_inner<E, R, F>(
+        state: &[E],
+        rules: &RuleSet<E, R>,
+        config: &Config<E>,
+        complete: bool,
+        mut f: F,
+    ) where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        F: FnMut(Vec<E>, Step<E>),
+    {
+        let targets = state.iter().map(move |e| e.cases()).collect::<Vec<_>>();
+        let digest = expr::multiset_digest_of(state);
+        for entry in rules.iter() {
+            let rule = &entry.rule;
+            let (top, bot) = rule.ref_pair();
+            let patterns = bot.iter().map(move |e| e.cases()).collect::<Vec<_>>();
+            substitution::match_multiset(
+                &patterns,
+                &targets,
+                &mut Vec::new(),
+                config.is_variable,
+                |bindings, chosen| {
+                    if !rule.allows(bindings) {
+                        return false;
+                    }
+                    let substitution = bindings
+                        .iter()
+                        .map(move |t| t.as_ref().to_owned())
+                        .collect::<substitution::Structure<E>>();
+                    let mut chosen = chosen.to_vec();
+                    chosen.sort_unstable();
+                    let consumed = substitution
+                        .apply_group_ref(&top)
+                        .into_iter()
+                        .collect::<Vec<_>>();
+                    let previous = state
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, _)| chosen.binary_search(i).is_err())
+                        .map(|(_, e)| E::clone(e))
+                        .chain(consumed.iter().map(E::clone))
+                        .collect::<Vec<_>>();
+                    let context_holds = !complete
+                        || rule.premises_hold(|premise| {
+                            let premise = substitution.apply_ref(premise);
+                            previous
+                                .iter()
+                                .any(|e| expr::eq_ref::<E>(&premise.cases(), &e.cases()))
+                        }) && engine::absences_hold(
+                            rule,
+                            &substitution,
+                            &previous,
+                            config.is_variable,
+                        );
+                    if context_holds {
+                        let produced = chosen.iter().map(|i| E::clone(&state[*i])).collect();
+                        f(
+                            previous,
+                            Step {
+                                rule: entry.id,
+                                bindings: substitution,
+                                consumed,
+                                produced,
+                                digest,
+                            },
+                        );
+                    }
+                    false
+                },
+            );
+        }
+    }
+
+    /// Bidirectional Search Side
+    struct Side<E>
+    where
+        E: Expression,
+    {
+        /// Search tree of the side
+        tree: Tree<E>,
+
+        /// States of the nodes of the tree
+        states: Vec<Vec<E>>,
+
+        /// Nodes of the tree indexed by the digest of their state
+        seen: BTreeMap<u64, Vec<usize>>,
+
+        /// Nodes of the current layer
+        frontier: Vec<usize>,
+
+        /// Number of expanded layers
+        depth: usize,
+    }
+
+    impl<E> Side<E>
+    where
+        E: Expression,
+        E::Atom: Hash + PartialEq,
+    {
+        /// Builds a new side rooted at `root`.
+        #[inline]
+        fn new(root: Vec<E>) -> Self {
+            let mut seen = BTreeMap::new();
+            seen.insert(expr::multiset_digest_of(&root), alloc::vec![0]);
+            Self {
+                tree: Tree::new(),
+                states: alloc::vec![root],
+                seen,
+                frontier: alloc::vec![0],
+                depth: 0,
+            }
+        }
+
+        /// Returns the node whose state is equal to `state` as a multiset, if any.
+        #[inline]
+        fn find(&self, state: &[E]) -> Option<usize> {
+            self.seen
+                .get(&expr::multiset_digest_of(state))?
+                .iter()
+                .copied()
+                .find(|node| Exact(&self.states[*node]).is_reached(state))
+        }
+
+        /// Adds a child of `parent` reached by `step`, returning its index.
+        #[inline]
+        fn push(&mut self, parent: usize, state: Vec<E>, step: Step<E>) -> usize {
+            let child = self.tree.push(parent, step);
+            self.seen
+                .entry(expr::multiset_digest_of(&state))
+                .or_default()
+                .push(child);
+            self.states.push(state);
+            child
+        }
+    }
+
+    /// Explores the rule applications forward from `initial` and backward from `goal` in
+    /// alternating breadth-first layers until the two searches meet, returning a derivation of
+    /// `goal` from `initial`. At most `max_depth` layers are expanded in total.
+    ///
+    /// Every expanded state consumes one step of `fuel` and every new state one node. If the
+    /// fuel runs out, returns the derivation of the last state reached by the forward search
+    /// as a partial result. See [`expand_backward`] for the backward transitions, which are
+    /// also reported to `observer`.
+    pub fn bidirectional<E, R, O>(
+        config: &Config<E>,
+        rules: &RuleSet<E, R>,
+        initial: Vec<E>,
+        goal: Vec<E>,
+        max_depth: usize,
+        fuel: &mut Fuel,
+        mut observer: O,
+    ) -> Result<Option<Derivation<E>>, Exhausted<Derivation<E>>>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        O: Observer<E>,
+    {
+        let digest = expr::multiset_digest_of(&initial);
+        let mut forward = Side::new(initial);
+        let mut backward = Side::new(goal);
+        let mut meeting = backward.find(&forward.states[0]).map(|b| (0, b));
+        let mut stop = None;
+        while meeting.is_none() && stop.is_none() && forward.depth + backward.depth < max_depth {
+            let expand_forward = !forward.frontier.is_empty()
+                && (backward.frontier.is_empty()
+                    || forward.frontier.len() <= backward.frontier.len());
+            let mut frontier = Vec::new();
+            if expand_forward {
+                for node in mem::take(&mut forward.frontier) {
+                    if let Err(reason) = fuel.step() {
+                        stop = Some(reason);
+                        break;
+                    }
+                    let state = forward.states[node]
+                        .iter()
+                        .map(E::clone)
+                        .collect::<Vec<_>>();
+                    observer.on_state_expanded(&state);
+                    expand(&state, rules, config, |next, step, _| {
+                        observer.on_match(step.rule);
+                        if meeting.is_some() || stop.is_some() || forward.find(&next).is_some() {
+                            return;
+                        }
+                        if let Err(reason) = fuel.node() {
+                            stop = Some(reason);
+                            return;
+                        }
+                        observer.on_rule_applied(&step);
+                        let met = backward.find(&next);
+                        let child = forward.push(node, next, step);
+                        frontier.push(child);
+                        meeting = met.map(|b| (child, b));
+                    });
+                    if meeting.is_some() || stop.is_some() {
+                        break;
+                    }
+                }
+                forward.frontier = frontier;
+                forward.depth += 1;
+            } else if !backward.frontier.is_empty() {
+                for node in mem::take(&mut backward.frontier) {
+                    if let Err(reason) = fuel.step() {
+                        stop = Some(reason);
+                        break;
+                    }
+                    let state = backward.states[node]
+                        .iter()
+                        .map(E::clone)
+                        .collect::<Vec<_>>();
+                    observer.on_state_expanded(&state);
+                    expand_backward(&state, rules, config, |previous, step| {
+                        observer.on_match(step.rule);
+                        if meeting.is_some() || stop.is_some() || backward.find(&previous).is_some()
+                        {
+                            return;
+                        }
+                        if let Err(reason) = fuel.node() {
+                            stop = Some(reason);
+                            return;
+                        }
+                        observer.on_rule_applied(&step);
+                        let met = forward.find(&previous);
+                        let child = backward.push(node, previous, step);
+                        frontier.push(child);
+                        meeting = met.map(|f| (f, child));
+                    });
+                    if meeting.is_some() || stop.is_some() {
+                        break;
+                    }
+                }
+                backward.frontier = frontier;
+                backward.depth += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+        let (f, b) = match (meeting, stop) {
+            (Some(meeting), _) => meeting,
+            (_, Some(reason)) => {
+                let last = forward.states.len() - 1;
+                observer.on_exhausted(reason);
+                return Err(Exhausted::new(
+                    reason,
+                    forward.tree.into_derivation(digest, last),
+                ));
+            }
+            _ => return Ok(None),
+        };
+        let mut derivation = Derivation::from_digest(digest);
+        for step in forward.tree.take_path(f) {
+            derivation.push(step);
+        }
+        for step in backward.tree.take_path(b).into_iter().rev() {
+            derivation.push(step);
+        }
+        observer.on_goal_reached(&derivation);
+        Ok(Some(derivation))
+    }
+    /// Returns `true` if `lhs` is contained in `rhs` as a multiset.
+    #[inline]
+    fn is_submultiset<E>(lhs: &[E], rhs: &[E]) -> bool
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        let mut used = alloc::vec![false; rhs.len()];
+        lhs.iter().all(|l| {
+            let found = rhs
+                .iter()
+                .zip(used.iter_mut())
+                .find(|(r, used)| !**used && expr::eq_ref::<E>(&l.cases(), &r.cases()));
+            match found {
+                Some((_, used)) => {
+                    *used = true;
+                    true
+                }
+                _ => false,
+            }
+        })
+    }
+
+    /// Replays the steps of `path`, in backward order, forward from `initial` and returns the
+    /// resulting derivation if every step applies and the derivation is accepted by
+    /// [`proof::check_with`] and reaches `goal`.
+    fn replay_forward<E, R>(
+        config: &Config<E>,
+        rules: &RuleSet<E, R>,
+        initial: &[E],
+        goal: &[E],
+        path: &[Step<E>],
+    ) -> Option<Derivation<E>>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        let mut state = initial.iter().map(E::clone).collect::<Vec<_>>();
+        let mut derivation = Derivation::new(initial);
+        for step in path.iter().rev() {
+            let mut step = step.clone();
+            for consumed in &step.consumed {
+                let position = state
+                    .iter()
+                    .position(|e| expr::eq_ref::<E>(&e.cases(), &consumed.cases()))?;
+                state.remove(position);
+            }
+            state.extend(step.produced.iter().map(E::clone));
+            step.digest = expr::multiset_digest_of(&state);
+            derivation.push(step);
+        }
+        if !is_submultiset(goal, &state) {
+            return None;
+        }
+        proof::check_with(&derivation, rules, initial, config.is_variable).ok()?;
+        Some(derivation)
+    }
+
+    /// Returns the derivation of the goal from the deepest subgoal `subgoal` of the branch
+    /// made of the steps of `path`, in backward order.
+    #[inline]
+    fn branch<E>(subgoal: &[E], path: Vec<Step<E>>) -> Derivation<E>
+    where
+        E: Expression,
+        E::Atom: Hash,
+    {
+        let mut derivation = Derivation::new(subgoal);
+        for step in path.into_iter().rev() {
+            derivation.push(step);
+        }
+        derivation
+    }
+
+    /// Runs a depth-limited regression from `goal`, returning the first derivation found, if
+    /// any, and whether some subgoal was cut off by the depth limit.
+    ///
+    /// A branch whose subgoal is contained in `initial` is only accepted if its steps replay
+    /// forward from `initial` into a [checked](proof::check_with) derivation, and is otherwise
+    /// regressed further.
+    fn regress<E, R, O>(
+        config: &Config<E>,
+        rules: &RuleSet<E, R>,
+        initial: &[E],
+        goal: &[E],
+        limit: usize,
+        fuel: &mut Fuel,
+        observer: &mut O,
+    ) -> Result<(Option<Derivation<E>>, bool), Exhausted<Derivation<E>>>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        O: Observer<E>,
+    {
+        let predecessors = |state: &[E]| {
+            let mut predecessors = Vec::new();
+            expand_backward_inner(state, rules, config, false, |previous, step| {
+                predecessors.push((previous, step))
+            });
+            predecessors.reverse();
+            predecessors
+        };
+        if is_submultiset(goal, initial) {
+            return Ok((Some(Derivation::new(initial)), false));
+        }
+        if limit == 0 {
+            return Ok((None, true));
+        }
+        let mut cutoff = false;
+        let mut path = Vec::<Step<E>>::new();
+        let mut subgoals = alloc::vec![goal.iter().map(E::clone).collect::<Vec<_>>()];
+        if let Err(reason) = fuel.step() {
+            observer.on_exhausted(reason);
+            return Err(Exhausted::new(reason, branch(goal, path)));
+        }
+        observer.on_state_expanded(goal);
+        let mut stack = alloc::vec![predecessors(goal)];
+        while let Some(frame) = stack.last_mut() {
+            match frame.pop() {
+                Some((subgoal, step)) => {
+                    observer.on_match(step.rule);
+                    if subgoals.iter().any(|s| Exact(s).is_reached(&subgoal)) {
+                        continue;
+                    }
+                    if let Err(reason) = fuel.node() {
+                        observer.on_exhausted(reason);
+                        let deepest = subgoals.pop().unwrap_or_default();
+                        return Err(Exhausted::new(reason, branch(&deepest, path)));
+                    }
+                    observer.on_rule_applied(&step);
+                    path.push(step);
+                    if is_submultiset(&subgoal, initial) {
+                        if let Some(derivation) =
+                            replay_forward(config, rules, initial, goal, &path)
+                        {
+                            return Ok((Some(derivation), cutoff));
+                        }
+                    }
+                    if path.len() < limit {
+                        if let Err(reason) = fuel.step() {
+                            observer.on_exhausted(reason);
+                            return Err(Exhausted::new(reason, branch(&subgoal, path)));
+                        }
+                        observer.on_state_expanded(&subgoal);
+                        stack.push(predecessors(&subgoal));
+                        subgoals.push(subgoal);
+                    } else {
+                        cutoff = true;
+                        observer.on_backtrack(path.len());
+                        path.pop();
+                    }
+                }
+                _ => {
+                    stack.pop();
+                    subgoals.pop();
+                    if !path.is_empty() {
+                        observer.on_backtrack(path.len());
+                    }
+                    path.pop();
+                }
+            }
+        }
+        Ok((None, cutoff))
+    }
+
+    /// Proves `goal` from `initial` by working backward from the goal, returning a derivation
+    /// of a state containing `goal` as a multiset.
+    ///
+    /// Rules are selected by matching their bottoms against the current subgoals, which are
+    /// then replaced by the instantiated tops of the rules, until the subgoals are contained
+    /// in `initial`. The search uses iterative deepening up to `max_depth` steps and skips
+    /// subgoals already visited along the current branch. See [`expand_backward`] for more
+    /// details.
+    ///
+    /// Premises and absence patterns are not checked against the subgoals, which are only
+    /// parts of states. Instead, every branch reaching a subgoal contained in `initial` is
+    /// replayed forward from `initial` and validated with [`proof::check_with`], and the
+    /// search goes on if the validation fails, for instance because a variable of the top of
+    /// a rule was left uninstantiated.
+    ///
+    /// Every expanded subgoal consumes one step of `fuel` and every generated subgoal one node.
+    /// If the fuel runs out, returns the derivation of `goal` from the deepest subgoal of the
+    /// current branch as a partial result. The fuel is shared by all the iterations. The states
+    /// reported to `observer` are the subgoals and the steps are the backward steps.
+    pub fn backward<E, R, O>(
+        config: &Config<E>,
+        rules: &RuleSet<E, R>,
+        initial: &[E],
+        goal: &[E],
+        max_depth: usize,
+        fuel: &mut Fuel,
+        mut observer: O,
+    ) -> Result<Option<Derivation<E>>, Exhausted<Derivation<E>>>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        O: Observer<E>,
+    {
+        for limit in 0..=max_depth {
+            match regress(config, rules, initial, goal, limit, fuel, &mut observer)? {
+                (Some(derivation), _) => {
+                    observer.on_goal_reached(&derivation);
+                    return Ok(Some(derivation));
+                }
+                (_, false) => return Ok(None),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+    /// Parallel Search Algorithms
+    #[cfg(feature = "parallel")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "parallel")))]
+    pub mod parallel {
+        use {
+            super::*,
+            rayon::iter::{IntoParallelRefIterator, ParallelIterator},
+        };
+
+        impl<E, Q> Search<E, Q>
+        where
+            E: Expression + Send + Sync,
+            E::Atom: Clone + Hash + PartialEq + Send + Sync,
+            E::Group: Container<E>,
+            Q: Frontier<Entry<E>>,
+        {
+            /// Runs the search like [`run`](Search::run), popping up to `batch` entries from the
+            /// frontier at a time and computing their successors in parallel.
+            ///
+            /// See [`drive`] for more details.
+            #[allow(clippy::too_many_arguments)]
+            #[inline]
+            pub fn run_parallel<P, G, C, F, O>(
+                &mut self,
+                config: &Config<E>,
+                rules: &P,
+                goal: G,
+                cost: C,
+                filter: F,
+                batch: usize,
+                fuel: &mut Fuel,
+                observer: O,
+            ) -> Result<Option<(Derivation<E>, f64)>, Exhausted<(Derivation<E>, f64)>>
+            where
+                P: RuleProvider<E> + Sync,
+                G: Goal<E>,
+                C: Cost<E>,
+                F: Filter<E>,
+                O: Observer<E>,
+            {
+                self.run_by(goal, cost, filter, batch, fuel, observer, move |entries| {
+                    entries
+                        .par_iter()
+                        .map(move |entry| successors(&entry.state, rules, config))
+                        .collect()
+                })
+            }
+        }
+
+        /// Parallel Search Driver
+        ///
+        /// Runs the search of [`drive`](super::drive), popping up to `batch` entries from
+        /// `frontier` at a time and computing their successors in parallel. Only the successors
+        /// are computed in parallel: `filter`, `cost` and `observer` see them in the order of
+        /// the batch, which keeps the search deterministic.
+        ///
+        /// With a [`VecDeque`] and an unbounded batch, every batch is a layer of a breadth-first
+        /// search. With a [`PriorityQueue`], the derivation found is only guaranteed to have
+        /// minimum cost when `batch` is one. Every expanded state consumes one step of `fuel`
+        /// and every state pushed to the frontier one node. Use a [`Search`] and
+        /// [`Search::run_parallel`] directly to resume the search afterwards.
+        #[allow(clippy::too_many_arguments)]
+        #[inline]
+        pub fn drive<E, P, G, C, F, Q, O>(
+            config: &Config<E>,
+            rules: &P,
+            initial: Vec<E>,
+            goal: G,
+            cost: C,
+            mut filter: F,
+            frontier: Q,
+            batch: usize,
+            fuel: &mut Fuel,
+            observer: O,
+        ) -> Result<Option<(Derivation<E>, f64)>, Exhausted<(Derivation<E>, f64)>>
+        where
+            E: Expression + Send + Sync,
+            E::Atom: Clone + Hash + PartialEq + Send + Sync,
+            E::Group: Container<E>,
+            P: RuleProvider<E> + Sync,
+            G: Goal<E>,
+            C: Cost<E>,
+            F: Filter<E>,
+            Q: Frontier<Entry<E>>,
+            O: Observer<E>,
+        {
+            filter.keep(&initial);
+            Search::new(initial, frontier)
+                .run_parallel(config, rules, goal, cost, filter, batch, fuel, observer)
+        }
+
+        /// Runs a [breadth-first search](super::bfs) expanding every layer in parallel.
+        ///
+        /// See [`drive`] for more details.
+        #[inline]
+        pub fn bfs<E, P, G, O>(
+            config: &Config<E>,
+            rules: &P,
+            initial: Vec<E>,
+            goal: G,
+            fuel: &mut Fuel,
+            observer: O,
+        ) -> Result<Option<Derivation<E>>, Exhausted<Derivation<E>>>
+        where
+            E: Expression + Send + Sync,
+            E::Atom: Clone + Hash + PartialEq + Send + Sync,
+            E::Group: Container<E>,
+            P: RuleProvider<E> + Sync,
+            G: Goal<E>,
+            O: Observer<E>,
+        {
+            drive(
+                config,
+                rules,
+                initial,
+                goal,
+                UnitCost,
+                KeepAll,
+                VecDeque::new(),
+                usize::MAX,
+                fuel,
+                observer,
+            )
+            .map(move |found| found.map(move |(derivation, _)| derivation))
+            .map_err(move |exhausted| exhausted.map(move |(derivation, _)| derivation))
+        }
+
+        /// Runs a best-first search expanding `batch` entries at a time in parallel, returning
+        /// a derivation of a state satisfying `goal` and its cost.
+        ///
+        /// See [`drive`] for more details.
+        #[inline]
+        #[allow(clippy::too_many_arguments)]
+        pub fn astar<E, P, G, C, O>(
+            config: &Config<E>,
+            rules: &P,
+            initial: Vec<E>,
+            goal: G,
+            cost: C,
+            batch: usize,
+            fuel: &mut Fuel,
+            observer: O,
+        ) -> Result<Option<(Derivation<E>, f64)>, Exhausted<(Derivation<E>, f64)>>
+        where
+            E: Expression + Send + Sync,
+            E::Atom: Clone + Hash + PartialEq + Send + Sync,
+            E::Group: Container<E>,
+            P: RuleProvider<E> + Sync,
+            G: Goal<E>,
+            C: Cost<E>,
+            O: Observer<E>,
+        {
+            drive(
+                config,
+                rules,
+                initial,
+                goal,
+                cost,
+                KeepAll,
+                PriorityQueue::new(),
+                batch,
+                fuel,
+                observer,
+            )
+        }
+    }
+
+    /// Search Checkpoints
+    #[cfg(feature = "serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+    pub mod record {
+        use {
+            super::*,
+            crate::{proof::record::StepRecord, rule::database::ExprRecord},
+            serde::{Deserialize, Deserializer, Serialize, Serializer},
+        };
+
+        /// Converts the expression records `records` into expressions.
+        #[inline]
+        fn into_exprs<E>(records: Vec<ExprRecord<E::Atom>>) -> Vec<E>
+        where
+            E: Expression,
+            E::Group: FromIterator<E>,
+        {
+            records.into_iter().map(ExprRecord::into_expr).collect()
+        }
+
+        /// Frontier Entry Record Type
+        #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+        pub struct EntryRecord<A> {
+            /// State of the entry
+            #[serde(default)]
+            pub state: Vec<ExprRecord<A>>,
+
+            /// Cost of the derivation of the state
+            pub cost: f64,
+
+            /// Node of the state in the search tree
+            pub node: usize,
+        }
+
+        impl<A> EntryRecord<A> {
+            /// Builds a new [`EntryRecord`] from `entry`.
+            #[inline]
+            pub fn from_entry<E>(entry: &Entry<E>) -> Self
+            where
+                E: Expression<Atom = A>,
+                A: Clone,
+            {
+                Self {
+                    state: entry.state.iter().map(ExprRecord::from_expr).collect(),
+                    cost: entry.cost,
+                    node: entry.node,
+                }
+            }
+
+            /// Converts the record back into an [`Entry`].
+            #[inline]
+            pub fn into_entry<E>(self) -> Entry<E>
+            where
+                E: Expression<Atom = A>,
+                E::Group: FromIterator<E>,
+            {
+                Entry {
+                    state: into_exprs(self.state),
+                    cost: self.cost,
+                    node: self.node,
+                }
+            }
+        }
+
+        impl<E> Serialize for Entry<E>
+        where
+            E: Expression,
+            E::Atom: Clone + Serialize,
+        {
+            #[inline]
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                EntryRecord::from_entry(self).serialize(serializer)
+            }
+        }
+
+        impl<'de, E> Deserialize<'de> for Entry<E>
+        where
+            E: Expression,
+            E::Atom: Deserialize<'de>,
+            E::Group: FromIterator<E>,
+        {
+            #[inline]
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                Ok(EntryRecord::deserialize(deserializer)?.into_entry())
+            }
+        }
+
+        /// Visited Set Record Type
+        #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+        pub struct VisitedRecord<A> {
+            /// Exactness flag
+            pub exact: bool,
+
+            /// Stored states indexed by digest
+            #[serde(default)]
+            pub states: Vec<(u64, Vec<Vec<ExprRecord<A>>>)>,
+        }
+
+        impl<A> VisitedRecord<A> {
+            /// Builds a new [`VisitedRecord`] from `visited`.
+            #[inline]
+            pub fn from_visited<E>(visited: &Visited<E>) -> Self
+            where
+                E: Expression<Atom = A>,
+                A: Clone,
+            {
+                Self {
+                    exact: visited.exact,
+                    states: visited
+                        .states
+                        .iter()
+                        .map(move |(digest, states)| {
+                            (
+                                *digest,
+                                states
+                                    .iter()
+                                    .map(move |s| s.iter().map(ExprRecord::from_expr).collect())
+                                    .collect(),
+                            )
+                        })
+                        .collect(),
+                }
+            }
+
+            /// Converts the record back into a [`Visited`] set.
+            #[inline]
+            pub fn into_visited<E>(self) -> Visited<E>
+            where
+                E: Expression<Atom = A>,
+                E::Group: FromIterator<E>,
+            {
+                let states = self
+                    .states
+                    .into_iter()
+                    .map(move |(digest, states)| {
+                        (digest, states.into_iter().map(into_exprs).collect())
+                    })
+                    .collect::<BTreeMap<_, Vec<Vec<E>>>>();
+                Visited {
+                    len: states.values().map(Vec::len).sum(),
+                    states,
+                    exact: self.exact,
+                }
+            }
+        }
+
+        impl<E> Serialize for Visited<E>
+        where
+            E: Expression,
+            E::Atom: Clone + Serialize,
+        {
+            #[inline]
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                VisitedRecord::from_visited(self).serialize(serializer)
+            }
+        }
+
+        impl<'de, E> Deserialize<'de> for Visited<E>
+        where
+            E: Expression,
+            E::Atom: Deserialize<'de>,
+            E::Group: FromIterator<E>,
+        {
+            #[inline]
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                Ok(VisitedRecord::deserialize(deserializer)?.into_visited())
+            }
+        }
+
+        /// Search Record Type
+        ///
+        /// Serialization data model for a [`Search`] checkpoint. The frontier is stored as is,
+        /// its entries being serialized as [`EntryRecord`]s. Rules are referred to by
+        /// identifier, so a search should be resumed with the rules it was started with.
+        #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+        pub struct SearchRecord<A, Q> {
+            /// Digest of the initial state
+            pub digest: u64,
+
+            /// Nodes of the search tree with their parent and the step leading from it
+            #[serde(default)]
+            pub nodes: Vec<Option<(usize, StepRecord<A>)>>,
+
+            /// Expanded states
+            pub expanded: VisitedRecord<A>,
+
+            /// Frontier
+            pub frontier: Q,
+
+            /// Entries popped from the frontier but not expanded, the next one last
+            #[serde(default)]
+            pub pending: Vec<EntryRecord<A>>,
+
+            /// Node and cost of the last expanded state
+            pub last: (usize, f64),
+        }
+
+        impl<'s, A, Q> SearchRecord<A, &'s Q> {
+            /// Builds a new [`SearchRecord`] from `search`, borrowing its frontier.
+            #[inline]
+            pub fn from_search<E>(search: &'s Search<E, Q>) -> Self
+            where
+                E: Expression<Atom = A>,
+                A: Clone,
+            {
+                Self {
+                    digest: search.digest,
+                    nodes: search
+                        .tree
+                        .nodes
+                        .iter()
+                        .map(move |node| {
+                            node.parent.as_ref().map(move |(parent, step)| {
+                                (*parent, StepRecord::from_step(step, None))
+                            })
+                        })
+                        .collect(),
+                    expanded: VisitedRecord::from_visited(&search.expanded),
+                    frontier: &search.frontier,
+                    pending: search.pending.iter().map(EntryRecord::from_entry).collect(),
+                    last: search.last,
+                }
+            }
+        }
+
+        impl<A, Q> SearchRecord<A, Q> {
+            /// Converts the record back into a [`Search`], trusting the rule identifiers.
+            #[inline]
+            pub fn into_search<E>(self) -> Search<E, Q>
+            where
+                E: Expression<Atom = A>,
+                E::Group: FromIterator<E>,
+            {
+                Search {
+                    digest: self.digest,
+                    tree: Tree {
+                        nodes: self
+                            .nodes
+                            .into_iter()
+                            .map(move |node| Node {
+                                parent: node.map(move |(parent, step)| {
+                                    let rule = step.rule.id;
+                                    (parent, step.into_step(rule))
+                                }),
+                            })
+                            .collect(),
+                    },
+                    expanded: self.expanded.into_visited(),
+                    frontier: self.frontier,
+                    pending: self
+                        .pending
+                        .into_iter()
+                        .map(EntryRecord::into_entry)
+                        .collect(),
+                    last: self.last,
+                }
+            }
+        }
+
+        impl<E, Q> Serialize for Search<E, Q>
+        where
+            E: Expression,
+            E::Atom: Clone + Serialize,
+            Q: Serialize,
+        {
+            #[inline]
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                SearchRecord::from_search(self).serialize(serializer)
+            }
+        }
+
+        impl<'de, E, Q> Deserialize<'de> for Search<E, Q>
+        where
+            E: Expression,
+            E::Atom: Deserialize<'de>,
+            E::Group: FromIterator<E>,
+            Q: Deserialize<'de>,
+        {
+            #[inline]
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                Ok(SearchRecord::deserialize(deserializer)?.into_search())
+            }
+        }
+    }
+
+    /// Monte Carlo Tree Search
+    #[cfg(feature = "rand")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "rand")))]
+    pub mod mcts {
+        use {super::*, rand::Rng};
+
+        /// Search Transition
+        ///
+        /// A successor state together with the step leading to it and the weight of its rule.
+        pub type Transition<E> = (Vec<E>, Step<E>, Option<f64>);
+
+        /// Rollout Policy Trait
+        pub trait Rollout<E>
+        where
+            E: Expression,
+        {
+            /// Chooses the index of the next transition among the non-empty `transitions` of
+            /// `state`.
+            fn choose<G>(
+                &mut self,
+                state: &[E],
+                transitions: &[Transition<E>],
+                rng: &mut G,
+            ) -> usize
+            where
+                G: Rng + ?Sized;
+        }
+
+        /// Uniform Rollout Policy
+        #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+        pub struct Uniform;
+
+        impl<E> Rollout<E> for Uniform
+        where
+            E: Expression,
+        {
+            #[inline]
+            fn choose<G>(
+                &mut self,
+                state: &[E],
+                transitions: &[Transition<E>],
+                rng: &mut G,
+            ) -> usize
+            where
+                G: Rng + ?Sized,
+            {
+                let _ = state;
+                sample_index(transitions.len(), rng)
+            }
+        }
+
+        /// Weighted Rollout Policy
+        ///
+        /// Chooses transitions with probability proportional to the [weight](Rule::weight) of
+        /// their rule, where rules without a weight have weight `1`.
+        #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+        pub struct Weighted;
+
+        impl<E> Rollout<E> for Weighted
+        where
+            E: Expression,
+        {
+            #[inline]
+            fn choose<G>(
+                &mut self,
+                state: &[E],
+                transitions: &[Transition<E>],
+                rng: &mut G,
+            ) -> usize
+            where
+                G: Rng + ?Sized,
+            {
+                let _ = state;
+                sample_weighted(transitions.iter().map(move |t| t.2), rng)
+            }
+        }
+
+        /// Samples an index below `len` uniformly. The index is sampled as a `u64` so that the
+        /// result does not depend on the width of `usize`.
+        #[inline]
+        pub(crate) fn sample_index<G>(len: usize, rng: &mut G) -> usize
+        where
+            G: Rng + ?Sized,
+        {
+            rng.gen_range(0..len as u64) as usize
+        }
+
+        /// Samples an index with probability proportional to its weight, where missing weights
+        /// count as `1`. Falls back to a uniform choice if every weight is zero.
+        pub(crate) fn sample_weighted<I, G>(weights: I, rng: &mut G) -> usize
+        where
+            I: Clone + ExactSizeIterator<Item = Option<f64>>,
+            G: Rng + ?Sized,
+        {
+            let weight = move |w: Option<f64>| w.unwrap_or(1.0).max(0.0);
+            let total = weights.clone().map(weight).sum::<f64>();
+            if total <= 0.0 {
+                return sample_index(weights.len(), rng);
+            }
+            let mut target = rng.gen::<f64>() * total;
+            let last = weights.len() - 1;
+            for (index, w) in weights.enumerate() {
+                target -= weight(w);
+                if target < 0.0 {
+                    return index;
+                }
+            }
+            last
+        }
+
+        /// Monte Carlo Tree Search Configuration
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        pub struct Config {
+            /// Exploration constant of the UCT selection rule
+            pub exploration: f64,
+
+            /// Number of search iterations
+            pub iterations: usize,
+
+            /// Maximum number of steps of a rollout
+            pub rollout_depth: usize,
+        }
+
+        impl Default for Config {
+            #[inline]
+            fn default() -> Self {
+                Self {
+                    exploration: core::f64::consts::SQRT_2,
+                    iterations: 1_000,
+                    rollout_depth: 32,
+                }
+            }
+        }
+
+        /// Search Tree Node
+        struct Node<E>
+        where
+            E: Expression,
+        {
+            /// State of the node
+            state: Vec<E>,
+
+            /// Parent node and the step leading from it
+            parent: Option<(usize, Step<E>)>,
+
+            /// Child nodes
+            children: Vec<usize>,
+
+            /// Transitions which have not been expanded yet, computed on the first visit
+            untried: Option<Vec<(Vec<E>, Step<E>)>>,
+
+            /// Number of visits
+            visits: f64,
+
+            /// Total reward
+            value: f64,
+        }
+
+        impl<E> Node<E>
+        where
+            E: Expression,
+        {
+            #[inline]
+            fn new(state: Vec<E>, parent: Option<(usize, Step<E>)>) -> Self {
+                Self {
+                    state,
+                    parent,
+                    children: Vec::new(),
+                    untried: None,
+                    visits: 0.0,
+                    value: 0.0,
+                }
+            }
+        }
+
+        /// Runs a Monte Carlo tree search from `initial`, returning the shortest derivation of a
+        /// state satisfying `goal` found within the configured number of iterations.
+        ///
+        /// Every iteration selects a node with the UCT rule, expands one of its transitions and
+        /// plays a random rollout chosen by `rollout` from the new node, rewarding the nodes
+        /// along the path if the rollout reaches the goal. The transitions are the same as for
+        /// the other searches of this module.
+        ///
+        /// Every iteration consumes one step of `fuel` and every new node of the search tree
+        /// one node. If the fuel runs out, returns the shortest derivation found so far as a
+        /// partial result. Every new shortest derivation is reported to `observer`, as well as
+        /// the expansions of the nodes of the search tree, but not the rollouts.
+        #[allow(clippy::too_many_arguments)]
+        pub fn mcts<E, P, Q, O, G, B>(
+            config: &engine::Config<E>,
+            rules: &P,
+            initial: Vec<E>,
+            goal: Q,
+            search: Config,
+            mut rollout: O,
+            rng: &mut G,
+            fuel: &mut Fuel,
+            mut observer: B,
+        ) -> Result<Option<Derivation<E>>, Exhausted<Option<Derivation<E>>>>
+        where
+            E: Expression,
+            E::Atom: Clone + Hash + PartialEq,
+            E::Group: Container<E>,
+            P: RuleProvider<E>,
+            Q: Goal<E>,
+            O: Rollout<E>,
+            G: Rng + ?Sized,
+            B: Observer<E>,
+        {
+            let digest = expr::multiset_digest_of(&initial);
+            if goal.is_reached(&initial) {
+                let derivation = Derivation::from_digest(digest);
+                observer.on_goal_reached(&derivation);
+                return Ok(Some(derivation));
+            }
+            let mut nodes = alloc::vec![Node::new(initial, None)];
+            let mut best: Option<Derivation<E>> = None;
+            for _ in 0..search.iterations {
+                if let Err(reason) = fuel.step() {
+                    observer.on_exhausted(reason);
+                    return Err(Exhausted::new(reason, best));
+                }
+                let mut index = 0;
+                loop {
+                    let node = &mut nodes[index];
+                    if node.untried.is_none() {
+                        let mut untried = Vec::new();
+                        if !goal.is_reached(&node.state) {
+                            observer.on_state_expanded(&node.state);
+                            expand(&node.state, rules, config, |next, step, _| {
+                                observer.on_match(step.rule);
+                                untried.push((next, step))
+                            });
+                        }
+                        untried.reverse();
+                        node.untried = Some(untried);
+                    }
+                    let node = &nodes[index];
+                    if node.untried.as_ref().map_or(false, Vec::is_empty)
+                        && !node.children.is_empty()
+                    {
+                        let ln = libm::log(node.visits);
+                        index = *node
+                            .children
+                            .iter()
+                            .max_by(|l, r| {
+                                let uct = |child: &Node<E>| {
+                                    if child.visits == 0.0 {
+                                        f64::INFINITY
+                                    } else {
+                                        child.value / child.visits
+                                            + search.exploration * libm::sqrt(ln / child.visits)
+                                    }
+                                };
+                                uct(&nodes[**l]).total_cmp(&uct(&nodes[**r]))
+                            })
+                            .unwrap_or(&index);
+                    } else {
+                        break;
+                    }
+                }
+                if let Some((state, step)) = nodes[index].untried.as_mut().and_then(Vec::pop) {
+                    if let Err(reason) = fuel.node() {
+                        observer.on_exhausted(reason);
+                        return Err(Exhausted::new(reason, best));
+                    }
+                    observer.on_rule_applied(&step);
+                    nodes.push(Node::new(state, Some((index, step))));
+                    let child = nodes.len() - 1;
+                    nodes[index].children.push(child);
+                    index = child;
+                }
+                let mut rollout_steps = Vec::new();
+                let mut state = nodes[index].state.iter().map(E::clone).collect::<Vec<_>>();
+                let mut reached = goal.is_reached(&state);
+                while !reached && rollout_steps.len() < search.rollout_depth {
+                    let mut transitions = Vec::new();
+                    expand(&state, rules, config, |next, step, weight| {
+                        transitions.push((next, step, weight))
+                    });
+                    if transitions.is_empty() {
+                        break;
+                    }
+                    let choice = rollout.choose(&state, &transitions, rng);
+                    let (next, step, _) = transitions.swap_remove(choice);
+                    rollout_steps.push(step);
+                    state = next;
+                    reached = goal.is_reached(&state);
+                }
+                let reward = if reached { 1.0 } else { 0.0 };
+                if reached {
+                    let mut path = Vec::new();
+                    let mut current = index;
+                    while let Some((parent, step)) = &nodes[current].parent {
+                        path.push(step.clone());
+                        current = *parent;
+                    }
+                    path.reverse();
+                    path.extend(rollout_steps);
+                    if best.as_ref().map_or(true, |b| path.len() < b.len()) {
+                        let mut derivation = Derivation::from_digest(digest);
+                        for step in path {
+                            derivation.push(step);
+                        }
+                        observer.on_goal_reached(&derivation);
+                        best = Some(derivation);
+                    }
+                }
+                let mut current = Some(index);
+                while let Some(i) = current {
+                    nodes[i].visits += 1.0;
+                    nodes[i].value += reward;
+                    current = nodes[i].parent.as_ref().map(move |(p, _)| *p);
+                }
+            }
+            Ok(best)
+        }
+    }
+}
+
+/// Fuel Module
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod fuel {
+    use {
+        alloc::sync::Arc,
+        core::{
+            fmt,
+            sync::atomic::{AtomicBool, Ordering},
+        },
+    };
+
+    #[cfg(feature = "std")]
+    use std::time::{Duration, Instant};
+
+    /// Cancellation Token
+    ///
+    /// A cloneable handle to a shared flag which can be set from another thread, or from a
+    /// callback, to ask the computations holding a clone of the token to stop. The flag is
+    /// checked every time [`Fuel`] is consumed, so cancellation is cooperative.
+    #[derive(Clone, Debug, Default)]
+    pub struct CancelToken(Arc<AtomicBool>);
+
+    impl CancelToken {
+        /// Builds a new [`CancelToken`] which is not cancelled.
+        #[inline]
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Requests the cancellation of the computations holding a clone of this token.
+        #[inline]
+        pub fn cancel(&self) {
+            self.0.store(true, Ordering::Relaxed)
+        }
+
+        /// Returns `true` if the cancellation was requested.
+        #[inline]
+        pub fn is_cancelled(&self) -> bool {
+            self.0.load(Ordering::Relaxed)
+        }
+
+        /// Clears the cancellation request so that the token can be reused.
+        #[inline]
+        pub fn reset(&self) {
+            self.0.store(false, Ordering::Relaxed)
+        }
+    }
+
+    /// Exhaustion Reason
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum Exhaustion {
+        /// The step budget ran out.
+        Steps,
+
+        /// The node budget ran out.
+        Nodes,
+
+        /// The deadline passed.
+        #[cfg(feature = "std")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+        Time,
+
+        /// The computation was cancelled through a [`CancelToken`].
+        Cancelled,
+    }
+
+    impl fmt::Display for Exhaustion {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(match self {
+                Self::Steps => "step budget ran out",
+                Self::Nodes => "node budget ran out",
+                #[cfg(feature = "std")]
+                Self::Time => "deadline passed",
+                Self::Cancelled => "computation was cancelled",
+            })
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for Exhaustion {}
+
+    /// Exhausted Outcome
+    ///
+    /// Returned by the long-running routines of this crate when their [`Fuel`] runs out or
+    /// when they are cancelled, together with the partial result computed so far.
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub struct Exhausted<P> {
+        /// Reason the fuel ran out
+        pub reason: Exhaustion,
+
+        /// Partial result
+        pub partial: P,
+    }
+
+    impl<P> fmt::Display for Exhausted<P> {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            fmt::Display::fmt(&self.reason, f)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl<P> std::error::Error for Exhausted<P> where P: fmt::Debug {}
+
+    impl<P> Exhausted<P> {
+        /// Builds a new [`Exhausted`] outcome.
+        #[inline]
+        pub fn new(reason: Exhaustion, partial: P) -> Self {
+            Self { reason, partial }
+        }
+
+        /// Maps the partial result with `f`.
+        #[inline]
+        pub fn map<Q, F>(self, f: F) -> Exhausted<Q>
+        where
+            F: FnOnce(P) -> Q,
+        {
+            Exhausted::new(self.reason, f(self.partial))
+        }
+    }
+
+    /// Computation Fuel
+    ///
+    /// Bounds the number of steps and nodes a computation may use, and optionally the time
+    /// it may take. What counts as a step or a node is documented by every routine accepting
+    /// fuel, typically a rule application or an expansion for steps and a new fact or a
+    /// generated state for nodes. The same fuel can be passed to several routines in turn to
+    /// bound them together.
+    #[derive(Clone, Debug, Default)]
+    pub struct Fuel {
+        /// Step budget
+        steps: Option<usize>,
+
+        /// Node budget
+        nodes: Option<usize>,
+
+        /// Deadline
+        #[cfg(feature = "std")]
+        deadline: Option<Instant>,
+
+        /// Cancellation token
+        cancel: Option<CancelToken>,
+
+        /// Steps consumed so far
+        spent_steps: usize,
+
+        /// Nodes consumed so far
+        spent_nodes: usize,
+    }
+
+    impl Fuel {
+        /// Builds a new unlimited [`Fuel`].
+        #[inline]
+        pub fn unlimited() -> Self {
+            Self::default()
+        }
+
+        /// Limits the number of steps to `steps`.
+        #[inline]
+        pub fn with_steps(mut self, steps: usize) -> Self {
+            self.steps = Some(steps);
+            self
+        }
+
+        /// Limits the number of nodes to `nodes`.
+        #[inline]
+        pub fn with_nodes(mut self, nodes: usize) -> Self {
+            self.nodes = Some(nodes);
+            self
+        }
+
+        /// Sets the deadline to `deadline`.
+        #[cfg(feature = "std")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+        #[inline]
+        pub fn with_deadline(mut self, deadline: Instant) -> Self {
+            self.deadline = Some(deadline);
+            self
+        }
+
+        /// Sets the deadline to `timeout` from now.
+        #[cfg(feature = "std")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+        #[inline]
+        pub fn with_timeout(self, timeout: Duration) -> Self {
+            self.with_deadline(Instant::now() + timeout)
+        }
+
+        /// Makes the fuel run out once `token` is cancelled.
+        #[inline]
+        pub fn with_cancel(mut self, token: CancelToken) -> Self {
+            self.cancel = Some(token);
+            self
+        }
+
+        /// Returns `true` if the cancellation token of the fuel, if any, was cancelled.
+        #[inline]
+        pub fn is_cancelled(&self) -> bool {
+            self.cancel
+                .as_ref()
+                .map_or(false, CancelToken::is_cancelled)
+        }
+
+        /// Returns the number of steps consumed so far.
+        #[inline]
+        pub fn spent_steps(&self) -> usize {
+            self.spent_steps
+        }
+
+        /// Returns the number of nodes consumed so far.
+        #[inline]
+        pub fn spent_nodes(&self) -> usize {
+            self.spent_nodes
+        }
+
+        /// Returns the number of steps left, if the steps are limited.
+        #[inline]
+        pub fn remaining_steps(&self) -> Option<usize> {
+            self.steps.map(|s| s.saturating_sub(self.spent_steps))
+        }
+
+        /// Returns the number of nodes left, if the nodes are limited.
+        #[inline]
+        pub fn remaining_nodes(&self) -> Option<usize> {
+            self.nodes.map(|n| n.saturating_sub(self.spent_nodes))
+        }
+
+        /// Checks that the computation was not cancelled and that the deadline, if any, has
+        /// not passed.
+        #[inline]
+        pub fn check(&self) -> Result<(), Exhaustion> {
+            if self.is_cancelled() {
+                return Err(Exhaustion::Cancelled);
+            }
+            #[cfg(feature = "std")]
+            if matches!(self.deadline, Some(deadline) if Instant::now() >= deadline) {
+                return Err(Exhaustion::Time);
+            }
+            Ok(())
+        }
+
+        /// Consumes one step, returning the reason the fuel ran out if it cannot.
+        #[inline]
+        pub fn step(&mut self) -> Result<(), Exhaustion> {
+            if let Err(reason) = self.check() {
+                log_debug!(
+                    "fuel exhausted after {} steps: {:?}",
+                    self.spent_steps,
+                    reason
+                );
+                return Err(reason);
+            }
+            if self.remaining_steps() == Some(0) {
+                log_debug!("fuel exhausted after {} steps", self.spent_steps);
+                return Err(Exhaustion::Steps);
+            }
+            self.spent_steps += 1;
+            Ok(())
+        }
+
+        /// Consumes one node, returning the reason the fuel ran out if it cannot.
+        #[inline]
+        pub fn node(&mut self) -> Result<(), Exhaustion> {
+            if self.remaining_nodes() == Some(0) {
+                log_debug!("fuel exhausted after {} nodes", self.spent_nodes);
+                return Err(Exhaustion::Nodes);
+            }
+            self.spent_nodes += 1;
+            Ok(())
+        }
+    }
+}
+
+/// Metrics Module
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod metrics {
+    use crate::{
+        engine::Observer,
+        fuel::Exhaustion,
+        proof::{Derivation, Step},
+        rule::RuleId,
+        Expression,
+    };
+
+    /// Counter of the states expanded by the searches
+    pub const STATES_EXPANDED: &str = "states_expanded";
+
+    /// Counter of the matches found by the engine and the searches
+    pub const MATCHES_TRIED: &str = "matches_tried";
+
+    /// Counter of the rules applied by the engine and of the transitions kept by the searches
+    pub const RULES_APPLIED: &str = "rules_applied";
+
+    /// Counter of the branches abandoned by the searches
+    pub const BACKTRACKS: &str = "backtracks";
+
+    /// Counter of the goals reached by the searches
+    pub const GOALS_REACHED: &str = "goals_reached";
+
+    /// Counter of the computations whose fuel ran out, including cancellations
+    pub const EXHAUSTIONS: &str = "exhaustions";
+
+    /// Counter of the cancelled computations
+    pub const CANCELLATIONS: &str = "cancellations";
+
+    /// Gauge of the length of the last derivation of a goal
+    pub const DERIVATION_LENGTH: &str = "derivation_length";
+
+    /// Metrics Sink Trait
+    ///
+    /// Receives counters and gauges identified by name, such as the constants of this module,
+    /// so that any metrics library can be plugged in. The unit type is the sink which ignores
+    /// every metric.
+    pub trait Metrics {
+        /// Increments the counter `name` by `value`.
+        fn increment(&mut self, name: &'static str, value: u64);
+
+        /// Sets the gauge `name` to `value`.
+        fn gauge(&mut self, name: &'static str, value: f64);
+    }
+
+    impl Metrics for () {
+        #[inline]
+        fn increment(&mut self, name: &'static str, value: u64) {
+            let _ = (name, value);
+        }
+
+        #[inline]
+        fn gauge(&mut self, name: &'static str, value: f64) {
+            let _ = (name, value);
+        }
+    }
+
+    impl<M> Metrics for &mut M
+    where
+        M: Metrics + ?Sized,
+    {
+        #[inline]
+        fn increment(&mut self, name: &'static str, value: u64) {
+            (**self).increment(name, value)
+        }
+
+        #[inline]
+        fn gauge(&mut self, name: &'static str, value: f64) {
+            (**self).gauge(name, value)
+        }
+    }
+
+    /// Measuring Observer
+    ///
+    /// An [`Observer`] which reports the events of the engine and of the searches into the
+    /// wrapped [`Metrics`] sink, using the counters and gauges of this module.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct Measured<M>(pub M);
+
+    impl<E, M> Observer<E> for Measured<M>
+    where
+        E: Expression,
+        M: Metrics,
+    {
+        #[inline]
+        fn on_rule_applied(&mut self, step: &Step<E>) {
+            let _ = step;
+            self.0.increment(RULES_APPLIED, 1)
+        }
+
+        #[inline]
+        fn on_state_expanded(&mut self, state: &[E]) {
+            let _ = state;
+            self.0.increment(STATES_EXPANDED, 1)
+        }
+
+        #[inline]
+        fn on_backtrack(&mut self, depth: usize) {
+            let _ = depth;
+            self.0.increment(BACKTRACKS, 1)
+        }
+
+        #[inline]
+        fn on_goal_reached(&mut self, derivation: &Derivation<E>) {
+            self.0.increment(GOALS_REACHED, 1);
+            self.0.gauge(DERIVATION_LENGTH, derivation.len() as f64)
+        }
+
+        #[inline]
+        fn on_match(&mut self, rule: RuleId) {
+            let _ = rule;
+            self.0.increment(MATCHES_TRIED, 1)
+        }
+
+        #[inline]
+        fn on_exhausted(&mut self, reason: Exhaustion) {
+            self.0.increment(EXHAUSTIONS, 1);
+            if reason == Exhaustion::Cancelled {
+                self.0.increment(CANCELLATIONS, 1)
+            }
+        }
+    }
+}
+
+/// Persistent State Module
+///
+/// States which share their structure with the states they were derived from, so that
+/// backtracking searches can snapshot and roll back a state without copying it.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod persistent {
+    use {
+        super::*,
+        crate::engine::Match,
+        alloc::{sync::Arc, vec::Vec},
+        core::{cmp::Ordering, fmt, hash::Hash, iter::FromIterator},
+    };
+
+    /// Tree Node
+    struct Node<E> {
+        /// Element stored at this node
+        value: Arc<E>,
+
+        /// Elements before this node
+        left: Tree<E>,
+
+        /// Elements after this node
+        right: Tree<E>,
+
+        /// Number of elements of the subtree
+        len: usize,
+
+        /// Height of the subtree
+        height: u8,
+    }
+
+    /// Shared Balanced Tree
+    type Tree<E> = Option<Arc<Node<E>>>;
+
+    #[inline]
+    fn len<E>(tree: &Tree<E>) -> usize {
+        tree.as_ref().map_or(0, move |node| node.len)
+    }
+
+    #[inline]
+    fn height<E>(tree: &Tree<E>) -> u8 {
+        tree.as_ref().map_or(0, move |node| node.height)
+    }
+
+    /// Builds the tree with `left`, `value` and `right` in order.
+    #[inline]
+    fn node<E>(left: Tree<E>, value: Arc<E>, right: Tree<E>) -> Tree<E> {
+        Some(Arc::new(Node {
+            len: len(&left) + len(&right) + 1,
+            height: height(&left).max(height(&right)) + 1,
+            value,
+            left,
+            right,
+        }))
+    }
+
+    /// Builds a balanced tree with `left`, `value` and `right` in order, where the heights of
+    /// `left` and `right` differ by at most two.
+    fn balance<E>(left: Tree<E>, value: Arc<E>, right: Tree<E>) -> Tree<E> {
+        let (hl, hr) = (height(&left), height(&right));
+        if hl > hr + 1 {
+            if let Some(l) = left.as_deref() {
+                if height(&l.left) >= height(&l.right) {
+                    return node(
+                        l.left.clone(),
+                        l.value.clone(),
+                        node(l.right.clone(), value, right),
+                    );
+                } else if let Some(lr) = l.right.as_deref() {
+                    return node(
+                        node(l.left.clone(), l.value.clone(), lr.left.clone()),
+                        lr.value.clone(),
+                        node(lr.right.clone(), value, right),
+                    );
+                }
+            }
+        } else if hr > hl + 1 {
+            if let Some(r) = right.as_deref() {
+                if height(&r.right) >= height(&r.left) {
+                    return node(
+                        node(left, value, r.left.clone()),
+                        r.value.clone(),
+                        r.right.clone(),
+                    );
+                } else if let Some(rl) = r.left.as_deref() {
+                    return node(
+                        node(left, value, rl.left.clone()),
+                        rl.value.clone(),
+                        node(rl.right.clone(), r.value.clone(), r.right.clone()),
+                    );
+                }
+            }
+        }
+        node(left, value, right)
+    }
+
+    /// Builds a balanced tree with the elements of `values` in order.
+    fn build<E>(values: &[Arc<E>]) -> Tree<E> {
+        if values.is_empty() {
+            return None;
+        }
+        let mid = values.len() / 2;
+        node(
+            build(&values[..mid]),
+            values[mid].clone(),
+            build(&values[mid + 1..]),
+        )
+    }
+
+    /// Returns the tree obtained by inserting `value` at position `index` in `tree`.
+    fn insert<E>(tree: &Tree<E>, index: usize, value: Arc<E>) -> Tree<E> {
+        match tree.as_deref() {
+            Some(n) => {
+                let left = len(&n.left);
+                if index <= left {
+                    balance(
+                        insert(&n.left, index, value),
+                        n.value.clone(),
+                        n.right.clone(),
+                    )
+                } else {
+                    balance(
+                        n.left.clone(),
+                        n.value.clone(),
+                        insert(&n.right, index - left - 1, value),
+                    )
+                }
+            }
+            _ => node(None, value, None),
+        }
+    }
+
+    /// Returns the tree obtained by removing the element at position `index` of `tree`, and
+    /// the removed element. The index must be in bounds.
+    fn remove<E>(tree: &Tree<E>, index: usize) -> (Tree<E>, Option<Arc<E>>) {
+        match tree.as_deref() {
+            Some(n) => {
+                let left = len(&n.left);
+                match index.cmp(&left) {
+                    Ordering::Less => {
+                        let (rest, value) = remove(&n.left, index);
+                        (balance(rest, n.value.clone(), n.right.clone()), value)
+                    }
+                    Ordering::Greater => {
+                        let (rest, value) = remove(&n.right, index - left - 1);
+                        (balance(n.left.clone(), n.value.clone(), rest), value)
+                    }
+                    _ => {
+                        let tree = match remove(&n.right, 0) {
+                            (rest, Some(first)) => balance(n.left.clone(), first, rest),
+                            _ => n.left.clone(),
+                        };
+                        (tree, Some(n.value.clone()))
+                    }
+                }
+            }
+            _ => (None, None),
+        }
+    }
+
+    /// Persistent Multiset
+    ///
+    /// A sequence of elements stored in a balanced tree whose nodes are shared between the
+    /// clones of the multiset. Cloning takes constant time, and [`insert`](Self::insert) and
+    /// [`remove`](Self::remove) take logarithmic time, copying only the nodes on the path to
+    /// the modified position and leaving the clones untouched. This makes clones cheap
+    /// snapshots for backtracking searches, which can roll back a state by returning to a
+    /// snapshot instead of copying whole vectors of expressions at every branch.
+    ///
+    /// Elements keep their positions like in a [`Vec`], so the indices of a [`Match`]
+    /// computed against [`to_vec`](Self::to_vec) refer to the same elements.
+    pub struct Multiset<E> {
+        /// Root of the tree
+        root: Tree<E>,
+    }
+
+    impl<E> Multiset<E> {
+        /// Builds a new empty [`Multiset`].
+        #[inline]
+        pub fn new() -> Self {
+            Self { root: None }
+        }
+
+        /// Returns the number of elements of the multiset.
+        #[inline]
+        pub fn len(&self) -> usize {
+            len(&self.root)
+        }
+
+        /// Returns `true` if the multiset has no elements.
+        #[inline]
+        pub fn is_empty(&self) -> bool {
+            self.root.is_none()
+        }
+
+        /// Returns `true` if `self` and `other` share their whole structure, in which case
+        /// they are equal.
+        #[inline]
+        pub fn ptr_eq(&self, other: &Self) -> bool {
+            match (&self.root, &other.root) {
+                (Some(l), Some(r)) => Arc::ptr_eq(l, r),
+                (None, None) => true,
+                _ => false,
+            }
+        }
+
+        /// Returns the element at position `index`, if it exists.
+        pub fn get(&self, mut index: usize) -> Option<&E> {
+            let mut tree = &self.root;
+            while let Some(n) = tree.as_deref() {
+                let left = len(&n.left);
+                match index.cmp(&left) {
+                    Ordering::Less => tree = &n.left,
+                    Ordering::Greater => {
+                        index -= left + 1;
+                        tree = &n.right;
+                    }
+                    _ => return Some(&*n.value),
+                }
+            }
+            None
+        }
+
+        /// Returns an iterator over the elements of the multiset in order.
+        #[inline]
+        pub fn iter(&self) -> Iter<E> {
+            let mut iter = Iter {
+                stack: Vec::new(),
+                remaining: self.len(),
+            };
+            iter.descend(&self.root);
+            iter
+        }
+    }
+
+    impl<E> Multiset<E> {
+        /// Inserts `value` at position `index`, shifting the following elements.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `index` is greater than the length of the multiset.
+        #[inline]
+        pub fn insert(&mut self, index: usize, value: E) {
+            assert!(index <= self.len(), "insertion index out of bounds");
+            self.root = insert(&self.root, index, Arc::new(value));
+        }
+
+        /// Appends `value` to the multiset.
+        #[inline]
+        pub fn push(&mut self, value: E) {
+            self.root = insert(&self.root, self.len(), Arc::new(value));
+        }
+
+        /// Removes the element at position `index`, shifting the following elements. Returns
+        /// `false` if the index is out of bounds.
+        #[inline]
+        pub fn remove(&mut self, index: usize) -> bool {
+            if index >= self.len() {
+                return false;
+            }
+            self.root = remove(&self.root, index).0;
+            true
+        }
+    }
+
+    impl<E> Multiset<E>
+    where
+        E: Expression,
+    {
+        /// Builds a new [`Multiset`] with copies of the elements of `state` in order.
+        #[inline]
+        pub fn from_state(state: &[E]) -> Self
+        where
+            E::Atom: Clone,
+            E::Group: FromIterator<E>,
+        {
+            state.iter().map(E::clone).collect()
+        }
+
+        /// Returns copies of the elements of the multiset in order.
+        #[inline]
+        pub fn to_vec(&self) -> Vec<E>
+        where
+            E::Atom: Clone,
+            E::Group: FromIterator<E>,
+        {
+            self.iter().map(E::clone).collect()
+        }
+
+        /// Applies the match `m` in place, removing the consumed elements and appending the
+        /// produced ones, like [`apply_match`](engine::apply_match). The match must have been
+        /// computed against the elements of the multiset in order.
+        #[inline]
+        pub fn apply_match(&mut self, m: Match<E>) {
+            for index in m.consumed.into_iter().rev() {
+                self.remove(index);
+            }
+            self.extend(m.produced);
+        }
+
+        /// Returns the multiset obtained by applying the match `m`, sharing the structure of
+        /// `self`.
+        #[inline]
+        pub fn successor(&self, m: &Match<E>) -> Self
+        where
+            E::Atom: Clone,
+            E::Group: FromIterator<E>,
+        {
+            let mut next = self.clone();
+            for index in m.consumed.iter().rev() {
+                next.remove(*index);
+            }
+            next.extend(m.produced.iter().map(E::clone));
+            next
+        }
+
+        /// Computes the digest of the multiset, which is equal to the
+        /// [`multiset_digest_of`](expr::multiset_digest_of) its elements.
+        #[inline]
+        pub fn digest(&self) -> u64
+        where
+            E::Atom: Hash,
+        {
+            util::multiset_digest(self.iter().map(expr::digest::<E>))
+        }
+    }
+
+    impl<E> Clone for Multiset<E> {
+        #[inline]
+        fn clone(&self) -> Self {
+            Self {
+                root: self.root.clone(),
+            }
+        }
+    }
+
+    impl<E> Default for Multiset<E> {
+        #[inline]
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<E> fmt::Debug for Multiset<E>
+    where
+        E: fmt::Debug,
+    {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_list().entries(self.iter()).finish()
+        }
+    }
+
+    impl<E> PartialEq for Multiset<E>
+    where
+        E: PartialEq,
+    {
+        #[inline]
+        fn eq(&self, other: &Self) -> bool {
+            self.ptr_eq(other) || (self.len() == other.len() && self.iter().eq(other.iter()))
+        }
+    }
+
+    impl<E> Eq for Multiset<E> where E: Eq {}
+
+    impl<E> Extend<E> for Multiset<E> {
+        #[inline]
+        fn extend<I>(&mut self, iter: I)
+        where
+            I: IntoIterator<Item = E>,
+        {
+            for value in iter {
+                self.push(value);
+            }
+        }
+    }
+
+    impl<E> FromIterator<E> for Multiset<E> {
+        #[inline]
+        fn from_iter<I>(iter: I) -> Self
+        where
+            I: IntoIterator<Item = E>,
+        {
+            Self {
+                root: build(&iter.into_iter().map(Arc::new).collect::<Vec<_>>()),
+            }
+        }
+    }
+
+    impl<E> From<Vec<E>> for Multiset<E> {
+        #[inline]
+        fn from(values: Vec<E>) -> Self {
+            values.into_iter().collect()
+        }
+    }
+
+    impl<'m, E> IntoIterator for &'m Multiset<E> {
+        type Item = &'m E;
+        type IntoIter = Iter<'m, E>;
+
+        #[inline]
+        fn into_iter(self) -> Self::IntoIter {
+            self.iter()
+        }
+    }
+
+    /// Persistent Multiset Iterator
+    #[derive(Clone)]
+    pub struct Iter<'m, E> {
+        /// Nodes whose element and right subtree are left to visit
+        stack: Vec<&'m Node<E>>,
+
+        /// Number of elements left to visit
+        remaining: usize,
+    }
+
+    impl<'m, E> Iter<'m, E> {
+        /// Pushes the leftmost path of `tree` onto the stack.
+        #[inline]
+        fn descend(&mut self, mut tree: &'m Tree<E>) {
+            while let Some(n) = tree.as_deref() {
+                self.stack.push(n);
+                tree = &n.left;
+            }
+        }
+    }
+
+    impl<'m, E> Iterator for Iter<'m, E> {
+        type Item = &'m E;
+
+        #[inline]
+        fn next(&mut self) -> Option<Self::Item> {
+            let n = self.stack.pop()?;
+            self.descend(&n.right);
+            self.remaining -= 1;
+            Some(&*n.value)
+        }
+
+        #[inline]
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            (self.remaining, Some(self.remaining))
+        }
+    }
+
+    impl<'m, E> ExactSizeIterator for Iter<'m, E> {}
+}
+
+/// Interactive Session Module
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod session {
+    use {
+        super::*,
+        crate::{
+            engine::{Config, Engine, Match, Observer, Policy, Violation},
+            fuel::Fuel,
+            proof::{Derivation, Step},
+            rule::{RuleId, RuleProvider},
+        },
+        alloc::vec::Vec,
+        core::hash::Hash,
+    };
+
+    /// Proof Obligation
+    #[derive(Debug)]
+    pub struct Obligation<E>
+    where
+        E: Expression,
+    {
+        /// Expression which must be reached
+        pub expr: E,
+
+        /// Premises which may be assumed while proving the obligation
+        pub premises: Vec<E>,
+    }
+
+    impl<E> Obligation<E>
+    where
+        E: Expression,
+    {
+        /// Builds a new [`Obligation`] without premises.
+        #[inline]
+        pub fn new(expr: E) -> Self {
+            Self {
+                expr,
+                premises: Vec::new(),
+            }
+        }
+
+        /// Returns `true` if the obligation is reached by `state`, that is, if `state` or its
+        /// premises contain its expression.
+        #[inline]
+        pub fn is_reached(&self, state: &[E]) -> bool
+        where
+            E::Atom: PartialEq,
+        {
+            self.premises
+                .iter()
+                .chain(state)
+                .any(|e| E::eq(e, &self.expr))
+        }
+    }
+
+    impl<E> Clone for Obligation<E>
+    where
+        E: Expression,
+        E::Atom: Clone,
+        E::Group: FromIterator<E>,
+    {
+        #[inline]
+        fn clone(&self) -> Self {
+            Self {
+                expr: E::clone(&self.expr),
+                premises: self.premises.iter().map(E::clone).collect(),
+            }
+        }
+    }
+
+    /// Interactive Session
+    ///
+    /// Wraps an [`Engine`] with a history of the applied steps, so that interactive tools can
+    /// undo and redo rule applications. Applying a new step clears the steps which can be
+    /// redone.
+    ///
+    /// A session also manages a list of open [obligations](Obligation), one of which is
+    /// focused. Obligations are closed by [`close_reached`](Self::close_reached) once the
+    /// state or their premises contain their expression. The [fuel](Self::fuel) of a session
+    /// bounds the iterations of the repeating tactics run against it.
+    pub struct Session<E, P, O = ()>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+    {
+        /// Wrapped engine
+        engine: Engine<E, P, O>,
+
+        /// Indices of the elements consumed by every applied step, in order of application
+        applied: Vec<Vec<usize>>,
+
+        /// Undone steps and the indices of the elements they consumed, last undone last
+        undone: Vec<(Step<E>, Vec<usize>)>,
+
+        /// Open obligations
+        goals: Vec<Obligation<E>>,
+
+        /// Index of the focused obligation
+        focus: usize,
+
+        /// Fuel charged by the repeating tactics
+        fuel: Fuel,
+    }
+
+    impl<E, P> Session<E, P>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+    {
+        /// Builds a new [`Session`] with the given rules and initial state.
+        #[inline]
+        pub fn new(rules: P, state: Vec<E>, config: Config<E>) -> Self {
+            Self::from_engine(Engine::new(rules, state, config))
+        }
+    }
+
+    impl<E, P, O> Session<E, P, O>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        O: Observer<E>,
+    {
+        /// Builds a new [`Session`] starting from the current state of `engine`. The steps
+        /// applied before by the engine cannot be undone.
+        #[inline]
+        pub fn from_engine(engine: Engine<E, P, O>) -> Self {
+            Self {
+                engine,
+                applied: Vec::new(),
+                undone: Vec::new(),
+                goals: Vec::new(),
+                focus: 0,
+                fuel: Fuel::unlimited(),
+            }
+        }
+
+        /// Sets the fuel charged by the repeating tactics run against the session.
+        #[inline]
+        pub fn with_fuel(mut self, fuel: Fuel) -> Self {
+            self.fuel = fuel;
+            self
+        }
+
+        /// Returns the fuel of the session.
+        #[inline]
+        pub fn fuel(&self) -> &Fuel {
+            &self.fuel
+        }
+
+        /// Returns a mutable reference to the fuel of the session.
+        #[inline]
+        pub fn fuel_mut(&mut self) -> &mut Fuel {
+            &mut self.fuel
+        }
+
+        /// Returns the wrapped engine.
+        #[inline]
+        pub fn engine(&self) -> &Engine<E, P, O> {
+            &self.engine
+        }
+
+        /// Consumes the session, returning the wrapped engine.
+        #[inline]
+        pub fn into_engine(self) -> Engine<E, P, O> {
+            self.engine
+        }
+
+        /// Returns the current state of the session.
+        #[inline]
+        pub fn state(&self) -> &[E] {
+            self.engine.state()
+        }
+
+        /// Returns every match of the rules against the current state.
+        #[inline]
+        pub fn matches(&self) -> Vec<Match<E>> {
+            self.engine.matches()
+        }
+
+        /// Returns every match of the rule `rule` against the current state.
+        #[inline]
+        pub fn matches_of(&self, rule: RuleId) -> Vec<Match<E>> {
+            let mut matches = self.matches();
+            matches.retain(move |m| m.rule == rule);
+            matches
+        }
+
+        /// Applies the match `m`, which must have been computed against the current state,
+        /// and clears the steps which can be redone.
+        ///
+        /// Returns the invariant violation reported by [`Engine::try_apply`], if any. The step
+        /// is only kept in the history if it was not rejected.
+        #[inline]
+        pub fn apply(&mut self, m: Match<E>) -> Result<(), Violation> {
+            self.undone.clear();
+            let consumed = m.consumed.clone();
+            let result = self.engine.try_apply(m);
+            if !matches!(result, Err(violation) if violation.policy == Policy::Reject) {
+                self.applied.push(consumed);
+            }
+            result
+        }
+
+        /// Applies the first match of the rule `rule` against the current state. Returns
+        /// `Ok(false)` if the rule does not match.
+        #[inline]
+        pub fn apply_rule(&mut self, rule: RuleId) -> Result<bool, Violation> {
+            match self.matches_of(rule).into_iter().next() {
+                Some(m) => self.apply(m).map(move |_| true),
+                _ => Ok(false),
+            }
+        }
+
+        /// Returns `true` if some step can be undone.
+        #[inline]
+        pub fn can_undo(&self) -> bool {
+            !self.applied.is_empty()
+        }
+
+        /// Returns `true` if some step can be redone.
+        #[inline]
+        pub fn can_redo(&self) -> bool {
+            !self.undone.is_empty()
+        }
+
+        /// Undoes the last applied step, returning it.
+        pub fn undo(&mut self) -> Option<&Step<E>> {
+            let consumed = self.applied.pop()?;
+            match self.engine.revert(&consumed) {
+                Some(step) => {
+                    self.undone.push((step, consumed));
+                    self.undone.last().map(move |(step, _)| step)
+                }
+                _ => {
+                    self.applied.push(consumed);
+                    None
+                }
+            }
+        }
+
+        /// Clears the abortion flag of the wrapped engine, see [`Engine::clear_abort`].
+        #[inline]
+        pub fn clear_abort(&mut self) {
+            self.engine.clear_abort();
+        }
+
+        /// Undoes the steps applied after the first `len` steps of the [history](Self::history).
+        #[inline]
+        pub fn rewind(&mut self, len: usize) {
+            while self.applied.len() > len && self.undo().is_some() {}
+        }
+
+        /// Redoes the last undone step, returning the identifier of its rule.
+        pub fn redo(&mut self) -> Option<RuleId> {
+            let (step, consumed) = self.undone.pop()?;
+            let rule = step.rule;
+            let result = self.engine.try_apply(Match {
+                rule,
+                bindings: step.bindings,
+                consumed: consumed.clone(),
+                produced: step.produced,
+                weight: None,
+            });
+            if matches!(result, Err(violation) if violation.policy == Policy::Reject) {
+                return None;
+            }
+            self.applied.push(consumed);
+            Some(rule)
+        }
+
+        /// Returns the steps applied during the session, in order of application.
+        #[inline]
+        pub fn history(&self) -> &[Step<E>] {
+            let steps = self.engine.derivation().steps();
+            &steps[steps.len() - self.applied.len()..]
+        }
+
+        /// Returns the steps which can be redone, the next one to redo first.
+        #[inline]
+        pub fn redoable(&self) -> impl '_ + Iterator<Item = &Step<E>> {
+            self.undone.iter().rev().map(move |(step, _)| step)
+        }
+
+        /// Returns the derivation of the current state from the initial state of the engine.
+        #[inline]
+        pub fn derivation(&self) -> &Derivation<E> {
+            self.engine.derivation()
+        }
+
+        /// Returns a copy of the derivation of the current state, which stays valid after
+        /// the session goes on.
+        #[inline]
+        pub fn export(&self) -> Derivation<E> {
+            self.derivation().clone()
+        }
+
+        /// Opens a new obligation for `expr`, returning its index.
+        #[inline]
+        pub fn add_goal(&mut self, expr: E) -> usize {
+            self.goals.push(Obligation::new(expr));
+            self.goals.len() - 1
+        }
+
+        /// Returns the open obligations.
+        #[inline]
+        pub fn goals(&self) -> &[Obligation<E>] {
+            &self.goals
+        }
+
+        /// Returns `true` if every obligation is closed.
+        #[inline]
+        pub fn is_proved(&self) -> bool {
+            self.goals.is_empty()
+        }
+
+        /// Focuses the obligation at `index`. Returns `false` if there is no such obligation.
+        #[inline]
+        pub fn focus(&mut self, index: usize) -> bool {
+            if index < self.goals.len() {
+                self.focus = index;
+                true
+            } else {
+                false
+            }
+        }
+
+        /// Returns the focused obligation and its index.
+        #[inline]
+        pub fn focused(&self) -> Option<(usize, &Obligation<E>)> {
+            self.goals.get(self.focus).map(|goal| (self.focus, goal))
+        }
+
+        /// Splits the obligation at `index`, whose expression must have [ratio
+        /// shape](rule::Structure), into one obligation for every element of its bottom, in
+        /// place. The elements of its top become premises of the new obligations. Returns the
+        /// number of new obligations.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `index` is out of bounds.
+        pub fn split(&mut self, index: usize) -> Result<usize, rule::ShapeError> {
+            let expr = Expression::into(E::clone(&self.goals[index].expr));
+            let (top, bot) = <rule::Structure<E> as TryFrom<Expr<E>>>::try_from(expr)?.pair();
+            let goal = self.goals.remove(index);
+            let premises = goal.premises.into_iter().chain(top).collect::<Vec<_>>();
+            let subgoals = bot
+                .into_iter()
+                .map(|expr| Obligation {
+                    expr,
+                    premises: premises.iter().map(E::clone).collect(),
+                })
+                .collect::<Vec<_>>();
+            let count = subgoals.len();
+            self.goals.splice(index..index, subgoals);
+            if self.focus > index {
+                self.focus = self.focus + count - 1;
+            } else if self.focus >= self.goals.len() {
+                self.focus = 0;
+            }
+            Ok(count)
+        }
+
+        /// Closes the obligations reached by the current state, returning them. The focus
+        /// stays on the same obligation if it is still open, and moves to the next one
+        /// otherwise.
+        pub fn close_reached(&mut self) -> Vec<Obligation<E>> {
+            let state = self.engine.state();
+            let focus = self.focus;
+            let mut closed = Vec::new();
+            let mut open = Vec::with_capacity(self.goals.len());
+            for (index, goal) in self.goals.drain(..).enumerate() {
+                if goal.is_reached(state) {
+                    if index < focus {
+                        self.focus -= 1;
+                    }
+                    closed.push(goal);
+                } else {
+                    open.push(goal);
+                }
+            }
+            self.goals = open;
+            if self.focus >= self.goals.len() {
+                self.focus = 0;
+            }
+            closed
+        }
+    }
+}
+
+/// Tactic Module
+///
+/// Programmable proof automation on top of the rule applications of a [`Session`].
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod tactic {
+    use {
+        super::*,
+        crate::{
+            engine::{Match, Observer, Policy, Violation},
+            fuel::{Exhaustion, Fuel},
+            rule::{RuleId, RuleProvider},
+            session::Session,
+        },
+        alloc::vec::Vec,
+        core::{fmt, hash::Hash},
+    };
+
+    /// Tactic Error
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum TacticError {
+        /// The rule did not match the current state.
+        NoMatch(RuleId),
+
+        /// The tactic ran out of steps.
+        Exhausted(Exhaustion),
+
+        /// A step violated an invariant with the [`Reject`](Policy::Reject) or
+        /// [`Abort`](Policy::Abort) policy.
+        Violated(Violation),
+
+        /// The tactic failed for another reason, for instance a choice between no tactics.
+        Failed,
+    }
+
+    impl fmt::Display for TacticError {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::NoMatch(id) => write!(f, "rule {} does not match the current state", id),
+                Self::Exhausted(reason) => fmt::Display::fmt(reason, f),
+                Self::Violated(violation) => fmt::Display::fmt(violation, f),
+                Self::Failed => f.write_str("tactic failed"),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for TacticError {}
+
+    /// Turns the invariant violation of a step applied to `session` into an error, undoing
+    /// the step if it was kept and clearing the abortion it caused.
+    #[inline]
+    fn violated<E, P, O>(session: &mut Session<E, P, O>, violation: Violation) -> TacticError
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        O: Observer<E>,
+    {
+        if violation.policy != Policy::Reject
+            && session.undo().is_some()
+            && violation.policy == Policy::Abort
+        {
+            session.clear_abort();
+        }
+        TacticError::Violated(violation)
+    }
+
+    /// Tactic Result
+    ///
+    /// The number of steps applied by a successful tactic.
+    pub type TacticResult = Result<usize, TacticError>;
+
+    /// Tactic Trait
+    ///
+    /// A tactic runs against a [`Session`], applying rules to its current state. A failing
+    /// tactic leaves the session as it found it, so that tactics can be combined with
+    /// [`then`](Self::then), [`or_else`](Self::or_else), [`repeat`](Self::repeat) and
+    /// [`try_`](Self::try_).
+    pub trait Tactic<E, P, O = ()>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        O: Observer<E>,
+    {
+        /// Runs the tactic against `session`, returning the number of applied steps.
+        fn run(&self, session: &mut Session<E, P, O>) -> TacticResult;
+
+        /// Runs `self` and then `next`, failing if one of them fails.
+        #[inline]
+        fn then<T>(self, next: T) -> Then<Self, T>
+        where
+            Self: Sized,
+            T: Tactic<E, P, O>,
+        {
+            Then(self, next)
+        }
+
+        /// Runs `self`, or `other` if `self` fails.
+        #[inline]
+        fn or_else<T>(self, other: T) -> OrElse<Self, T>
+        where
+            Self: Sized,
+            T: Tactic<E, P, O>,
+        {
+            OrElse(self, other)
+        }
+
+        /// Runs `self` until it fails, applies no step or the [fuel](Session::fuel) of the
+        /// session runs out, charging one step per iteration. Never fails.
+        #[inline]
+        fn repeat(self) -> Repeat<Self>
+        where
+            Self: Sized,
+        {
+            Repeat(self)
+        }
+
+        /// Runs `self`, succeeding without applying any step if it fails.
+        #[inline]
+        fn try_(self) -> Try<Self>
+        where
+            Self: Sized,
+        {
+            Try(self)
+        }
+    }
+
+    impl<E, P, O, F> Tactic<E, P, O> for F
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        O: Observer<E>,
+        F: Fn(&mut Session<E, P, O>) -> TacticResult,
+    {
+        #[inline]
+        fn run(&self, session: &mut Session<E, P, O>) -> TacticResult {
+            self(session)
+        }
+    }
+
+    /// Runs `f` against `session`, rewinding the steps it applied if it fails.
+    #[inline]
+    fn atomically<E, P, O, F>(session: &mut Session<E, P, O>, f: F) -> TacticResult
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        O: Observer<E>,
+        F: FnOnce(&mut Session<E, P, O>) -> TacticResult,
+    {
+        let mark = session.history().len();
+        let result = f(session);
+        if result.is_err() {
+            session.rewind(mark);
+        }
+        result
+    }
+
+    /// Apply Tactic
+    ///
+    /// Applies the first match of a rule.
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct Apply(pub RuleId);
+
+    impl<E, P, O> Tactic<E, P, O> for Apply
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        O: Observer<E>,
+    {
+        #[inline]
+        fn run(&self, session: &mut Session<E, P, O>) -> TacticResult {
+            match session.apply_rule(self.0) {
+                Ok(true) => Ok(1),
+                Ok(false) => Err(TacticError::NoMatch(self.0)),
+                Err(violation) => Err(violated(session, violation)),
+            }
+        }
+    }
+
+    /// Compose Tactic
+    ///
+    /// Applies the first match of every rule in order, which has the effect of the
+    /// [composition](rule::compose) of the rules. Fails without applying any step if one of
+    /// the rules does not match.
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub struct Compose(pub Vec<RuleId>);
+
+    impl<E, P, O> Tactic<E, P, O> for Compose
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        O: Observer<E>,
+    {
+        #[inline]
+        fn run(&self, session: &mut Session<E, P, O>) -> TacticResult {
+            atomically(session, |session| {
+                self.0.iter().map(|rule| Apply(*rule).run(session)).sum()
+            })
+        }
+    }
+
+    /// Substitute Tactic
+    ///
+    /// Applies the first match of a rule whose bindings agree with the given bindings, so
+    /// that the variables of the rule can be instantiated by hand.
+    #[derive(Debug)]
+    pub struct Substitute<E>
+    where
+        E: Expression,
+    {
+        /// Rule to apply
+        pub rule: RuleId,
+
+        /// Required bindings of the variables of the rule
+        pub bindings: substitution::Structure<E>,
+    }
+
+    impl<E> Substitute<E>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        /// Returns `true` if the bindings of `m` agree with the required bindings.
+        #[inline]
+        pub fn agrees(&self, m: &Match<E>) -> bool {
+            self.bindings.terms.iter().all(|required| {
+                m.bindings
+                    .terms
+                    .iter()
+                    .any(|t| t.var == required.var && E::eq(&t.expr, &required.expr))
+            })
+        }
+    }
+
+    impl<E, P, O> Tactic<E, P, O> for Substitute<E>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        O: Observer<E>,
+    {
+        #[inline]
+        fn run(&self, session: &mut Session<E, P, O>) -> TacticResult {
+            match session
+                .matches_of(self.rule)
+                .into_iter()
+                .find(|m| self.agrees(m))
+            {
+                Some(m) => match session.apply(m) {
+                    Ok(()) => Ok(1),
+                    Err(violation) => Err(violated(session, violation)),
+                },
+                _ => Err(TacticError::NoMatch(self.rule)),
+            }
+        }
+    }
+
+    /// Normalize Tactic
+    ///
+    /// Applies the first match against the current state until no rule matches. Fails
+    /// without applying any step if more than `limit` steps would be needed.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct Normalize {
+        /// Maximum number of steps
+        pub limit: Option<usize>,
+    }
+
+    impl<E, P, O> Tactic<E, P, O> for Normalize
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        O: Observer<E>,
+    {
+        fn run(&self, session: &mut Session<E, P, O>) -> TacticResult {
+            let mut fuel = match self.limit {
+                Some(limit) => Fuel::unlimited().with_steps(limit),
+                _ => Fuel::unlimited(),
+            };
+            atomically(session, |session| {
+                let mut steps = 0;
+                while let Some(m) = session.matches().into_iter().next() {
+                    fuel.step().map_err(TacticError::Exhausted)?;
+                    session.apply(m).map_err(TacticError::Violated)?;
+                    steps += 1;
+                }
+                Ok(steps)
+            })
+        }
+    }
+
+    /// Sequence Combinator
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct Then<A, B>(pub A, pub B);
+
+    impl<E, P, O, A, B> Tactic<E, P, O> for Then<A, B>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        O: Observer<E>,
+        A: Tactic<E, P, O>,
+        B: Tactic<E, P, O>,
+    {
+        #[inline]
+        fn run(&self, session: &mut Session<E, P, O>) -> TacticResult {
+            atomically(session, |session| {
+                Ok(self.0.run(session)? + self.1.run(session)?)
+            })
+        }
+    }
+
+    /// Alternative Combinator
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct OrElse<A, B>(pub A, pub B);
+
+    impl<E, P, O, A, B> Tactic<E, P, O> for OrElse<A, B>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        O: Observer<E>,
+        A: Tactic<E, P, O>,
+        B: Tactic<E, P, O>,
+    {
+        #[inline]
+        fn run(&self, session: &mut Session<E, P, O>) -> TacticResult {
+            self.0.run(session).or_else(|_| self.1.run(session))
+        }
+    }
+
+    /// Repetition Combinator
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct Repeat<T>(pub T);
+
+    impl<E, P, O, T> Tactic<E, P, O> for Repeat<T>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        O: Observer<E>,
+        T: Tactic<E, P, O>,
+    {
+        #[inline]
+        fn run(&self, session: &mut Session<E, P, O>) -> TacticResult {
+            let mut steps = 0;
+            while session.fuel_mut().step().is_ok() {
+                match self.0.run(session) {
+                    Ok(applied) if applied > 0 => steps += applied,
+                    _ => break,
+                }
+            }
+            Ok(steps)
+        }
+    }
+
+    /// Recovery Combinator
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct Try<T>(pub T);
+
+    impl<E, P, O, T> Tactic<E, P, O> for Try<T>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+        O: Observer<E>,
+        T: Tactic<E, P, O>,
+    {
+        #[inline]
+        fn run(&self, session: &mut Session<E, P, O>) -> TacticResult {
+            Ok(self.0.run(session).unwrap_or(0))
+        }
+    }
+
+    /// Tactic Scripts
+    #[cfg(feature = "serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+    pub mod script {
+        use {
+            super::*,
+            crate::rule::database::ExprRecord,
+            alloc::boxed::Box,
+            serde::{Deserialize, Serialize},
+        };
+
+        /// Tactic Script
+        ///
+        /// A [`Tactic`] represented as data, so that proof strategies can be stored and shared
+        /// without compiling them. Expressions are stored as [`ExprRecord`]s over atoms of
+        /// type `A`.
+        #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+        pub enum TacticScript<A> {
+            /// Runs the [`Apply`] tactic.
+            Apply(RuleId),
+
+            /// Runs the [`Compose`] tactic.
+            Compose(Vec<RuleId>),
+
+            /// Runs the [`Substitute`] tactic.
+            Substitute {
+                /// Rule to apply
+                rule: RuleId,
+
+                /// Required bindings of the variables of the rule
+                bindings: Vec<(A, ExprRecord<A>)>,
+            },
+
+            /// Runs the [`Normalize`] tactic.
+            Normalize {
+                /// Maximum number of steps
+                limit: Option<usize>,
+            },
+
+            /// Runs every script in order, failing if one of them fails.
+            Then(Vec<TacticScript<A>>),
+
+            /// Runs the first script which succeeds, failing if all of them fail.
+            OrElse(Vec<TacticScript<A>>),
+
+            /// Runs the script until it fails, applies no step or the fuel of the session runs
+            /// out.
+            Repeat(Box<TacticScript<A>>),
+
+            /// Runs the script, succeeding without applying any step if it fails.
+            Try(Box<TacticScript<A>>),
+        }
+
+        /// Script Error
+        #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+        pub struct ScriptError {
+            /// Position of the failing primitive in the script, as the indices of the nested
+            /// scripts leading to it
+            pub path: Vec<usize>,
+
+            /// Error of the failing primitive
+            pub error: TacticError,
+        }
+
+        impl fmt::Display for ScriptError {
+            #[inline]
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{} at script path {:?}", self.error, self.path)
+            }
+        }
+
+        #[cfg(feature = "std")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+        impl std::error::Error for ScriptError {}
+
+        impl ScriptError {
+            /// Builds a new [`ScriptError`] for a failing primitive at the root of a script.
+            #[inline]
+            pub fn new(error: TacticError) -> Self {
+                Self {
+                    path: Vec::new(),
+                    error,
+                }
+            }
+
+            /// Prepends `index` to the path of the error.
+            #[inline]
+            fn within(mut self, index: usize) -> Self {
+                self.path.insert(0, index);
+                self
+            }
+        }
+
+        impl<A> TacticScript<A> {
+            /// Runs the script against `session`, returning the number of applied steps, or
+            /// the position of the primitive which made the script fail. A failing script
+            /// leaves the session as it found it.
+            pub fn run<E, P, O>(&self, session: &mut Session<E, P, O>) -> Result<usize, ScriptError>
+            where
+                E: Expression<Atom = A>,
+                E::Atom: Clone + Hash + PartialEq,
+                E::Group: Container<E>,
+                P: RuleProvider<E>,
+                O: Observer<E>,
+            {
+                let primitive = move |result: TacticResult| result.map_err(ScriptError::new);
+                match self {
+                    Self::Apply(rule) => primitive(Apply(*rule).run(session)),
+                    Self::Compose(rules) => primitive(Compose(rules.clone()).run(session)),
+                    Self::Substitute { rule, bindings } => primitive(
+                        Substitute {
+                            rule: *rule,
+                            bindings: bindings
+                                .iter()
+                                .map(move |(var, expr)| {
+                                    substitution::Term::new(var.clone(), expr.clone().into_expr())
+                                })
+                                .collect(),
+                        }
+                        .run(session),
+                    ),
+                    Self::Normalize { limit } => {
+                        primitive(Normalize { limit: *limit }.run(session))
+                    }
+                    Self::Then(scripts) => {
+                        let mark = session.history().len();
+                        let mut steps = 0;
+                        for (index, script) in scripts.iter().enumerate() {
+                            match script.run(session) {
+                                Ok(applied) => steps += applied,
+                                Err(error) => {
+                                    session.rewind(mark);
+                                    return Err(error.within(index));
+                                }
+                            }
+                        }
+                        Ok(steps)
+                    }
+                    Self::OrElse(scripts) => {
+                        let mut last = ScriptError::new(TacticError::Failed);
+                        for (index, script) in scripts.iter().enumerate() {
+                            match script.run(session) {
+                                Ok(applied) => return Ok(applied),
+                                Err(error) => last = error.within(index),
+                            }
+                        }
+                        Err(last)
+                    }
+                    Self::Repeat(script) => {
+                        let mut steps = 0;
+                        while session.fuel_mut().step().is_ok() {
+                            match script.run(session) {
+                                Ok(applied) if applied > 0 => steps += applied,
+                                _ => break,
+                            }
+                        }
+                        Ok(steps)
+                    }
+                    Self::Try(script) => Ok(script.run(session).unwrap_or(0)),
+                }
+            }
+        }
+
+        impl<E, P, O> Tactic<E, P, O> for TacticScript<E::Atom>
+        where
+            E: Expression,
+            E::Atom: Clone + Hash + PartialEq,
+            E::Group: Container<E>,
+            P: RuleProvider<E>,
+            O: Observer<E>,
+        {
+            #[inline]
+            fn run(&self, session: &mut Session<E, P, O>) -> TacticResult {
+                TacticScript::run(self, session).map_err(move |error| error.error)
+            }
+        }
+    }
+}
+
+/// Linear Resource Module
+///
+/// A checked runtime discipline for the multiset semantics of rule application, where every
+/// state element is a resource with an explicit multiplicity.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod linear {
+    use {
+        super::*,
+        crate::{
+            engine::{self, Config, Match},
+            rule::{RuleId, RuleProvider},
+        },
+        alloc::vec::Vec,
+        core::{fmt, hash::Hash},
+    };
+
+    /// Resource Multiset
+    ///
+    /// Stores every distinct element once, together with its multiplicity.
+    #[derive(Debug)]
+    pub struct Resources<E>
+    where
+        E: Expression,
+    {
+        /// Distinct elements and their positive multiplicities
+        entries: Vec<(E, usize)>,
+    }
+
+    impl<E> Resources<E>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        /// Builds a new empty [`Resources`] multiset.
+        #[inline]
+        pub fn new() -> Self {
+            Self {
+                entries: Vec::new(),
+            }
+        }
+
+        /// Builds a new [`Resources`] multiset counting the elements of `state`.
+        #[inline]
+        pub fn from_state(state: Vec<E>) -> Self {
+            let mut resources = Self::new();
+            for e in state {
+                resources.add(e, 1);
+            }
+            resources
+        }
+
+        /// Returns the distinct elements and their multiplicities.
+        #[inline]
+        pub fn entries(&self) -> &[(E, usize)] {
+            &self.entries
+        }
+
+        /// Returns the number of resources, counted with multiplicity.
+        #[inline]
+        pub fn total(&self) -> usize {
+            self.entries.iter().map(move |(_, count)| count).sum()
+        }
+
+        /// Returns `true` if there are no resources.
+        #[inline]
+        pub fn is_empty(&self) -> bool {
+            self.entries.is_empty()
+        }
+
+        /// Returns the index of the entry of `expr`, if it is a resource.
+        #[inline]
+        fn position(&self, expr: &E) -> Option<usize> {
+            self.entries.iter().position(move |(e, _)| E::eq(e, expr))
+        }
+
+        /// Returns the multiplicity of `expr`.
+        #[inline]
+        pub fn count(&self, expr: &E) -> usize {
+            self.position(expr)
+                .map_or(0, move |index| self.entries[index].1)
+        }
+
+        /// Adds `count` copies of `expr`.
+        #[inline]
+        pub fn add(&mut self, expr: E, count: usize) {
+            if count == 0 {
+                return;
+            }
+            match self.position(&expr) {
+                Some(index) => self.entries[index].1 += count,
+                _ => self.entries.push((expr, count)),
+            }
+        }
+
+        /// Removes the resources of `items` atomically: either every item is available with
+        /// its multiplicity and all of them are removed, or nothing is removed.
+        pub fn consume(&mut self, items: &[(E, usize)]) -> Result<(), LinearError> {
+            let mut required = Vec::<(usize, usize)>::new();
+            for (expr, count) in items {
+                let index = self.position(expr).ok_or(LinearError::Missing)?;
+                match required.iter_mut().find(move |(i, _)| *i == index) {
+                    Some((_, total)) => *total += count,
+                    _ => required.push((index, *count)),
+                }
+            }
+            for (index, count) in &required {
+                let available = self.entries[*index].1;
+                if available < *count {
+                    return Err(LinearError::Insufficient {
+                        required: *count,
+                        available,
+                    });
+                }
+            }
+            for (index, count) in required {
+                self.entries[index].1 -= count;
+            }
+            self.entries.retain(move |(_, count)| *count > 0);
+            Ok(())
+        }
+
+        /// Returns the resources as a state, repeating every element with its multiplicity.
+        #[inline]
+        pub fn to_state(&self) -> Vec<E>
+        where
+            E::Atom: Clone,
+            E::Group: FromIterator<E>,
+        {
+            self.entries
+                .iter()
+                .flat_map(move |(e, count)| (0..*count).map(move |_| E::clone(e)))
+                .collect()
+        }
+
+        /// Returns `true` if `self` and `other` hold the same resources with the same
+        /// multiplicities.
+        #[inline]
+        pub fn same_as(&self, other: &Self) -> bool {
+            self.entries.len() == other.entries.len()
+                && self
+                    .entries
+                    .iter()
+                    .all(move |(e, count)| other.count(e) == *count)
+        }
+    }
+
+    impl<E> Clone for Resources<E>
+    where
+        E: Expression,
+        E::Atom: Clone,
+        E::Group: FromIterator<E>,
+    {
+        #[inline]
+        fn clone(&self) -> Self {
+            Self {
+                entries: self
+                    .entries
+                    .iter()
+                    .map(move |(e, count)| (E::clone(e), *count))
+                    .collect(),
+            }
+        }
+    }
+
+    impl<E> Default for Resources<E>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        #[inline]
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// Linear Error
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum LinearError {
+        /// A consumed element is not a resource.
+        Missing,
+
+        /// A consumed element is not available with the required multiplicity.
+        Insufficient {
+            /// Required multiplicity
+            required: usize,
+
+            /// Available multiplicity
+            available: usize,
+        },
+
+        /// The ledger does not account for the current resources.
+        Unbalanced,
+    }
+
+    impl fmt::Display for LinearError {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::Missing => f.write_str("consumed element is not a resource"),
+                Self::Insufficient {
+                    required,
+                    available,
+                } => write!(
+                    f,
+                    "consumed element is required {} times but only {} are available",
+                    required, available
+                ),
+                Self::Unbalanced => f.write_str("ledger does not account for the resources"),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for LinearError {}
+
+    /// Ledger Entry
+    ///
+    /// The resources consumed and produced by a rule application.
+    #[derive(Debug)]
+    pub struct LedgerEntry<E>
+    where
+        E: Expression,
+    {
+        /// Identifier of the applied rule
+        pub rule: RuleId,
+
+        /// Consumed resources and their multiplicities
+        pub consumed: Vec<(E, usize)>,
+
+        /// Produced resources and their multiplicities
+        pub produced: Vec<(E, usize)>,
+    }
+
+    /// Groups equal elements of `exprs` with their multiplicities.
+    #[inline]
+    fn counted<E>(exprs: Vec<E>) -> Vec<(E, usize)>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        Resources::from_state(exprs).entries
+    }
+
+    /// Linear Engine
+    ///
+    /// Runs rules over [`Resources`], decrementing the multiplicities of the consumed elements
+    /// atomically at every rule application and recording every application in a resource
+    /// ledger, which can be [audited](Self::audit) against the initial resources.
+    pub struct LinearEngine<E, P>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+    {
+        /// Rule provider
+        rules: P,
+
+        /// Engine configuration
+        config: Config<E>,
+
+        /// Initial resources
+        initial: Resources<E>,
+
+        /// Current resources
+        resources: Resources<E>,
+
+        /// Resource ledger
+        ledger: Vec<LedgerEntry<E>>,
+    }
+
+    impl<E, P> LinearEngine<E, P>
+    where
+        E: Expression,
+        E::Atom: Clone + Hash + PartialEq,
+        E::Group: Container<E>,
+        P: RuleProvider<E>,
+    {
+        /// Builds a new [`LinearEngine`] with the given rules and initial resources.
+        #[inline]
+        pub fn new(rules: P, resources: Resources<E>, config: Config<E>) -> Self {
+            Self {
+                rules,
+                config,
+                initial: resources.clone(),
+                resources,
+                ledger: Vec::new(),
+            }
+        }
+
+        /// Returns the current resources.
+        #[inline]
+        pub fn resources(&self) -> &Resources<E> {
+            &self.resources
+        }
+
+        /// Returns the resource ledger, in order of application.
+        #[inline]
+        pub fn ledger(&self) -> &[LedgerEntry<E>] {
+            &self.ledger
+        }
+
+        /// Returns every match of the rules against the [state](Resources::to_state) of the
+        /// current resources.
+        #[inline]
+        pub fn matches(&self) -> Vec<Match<E>> {
+            engine::matches(&self.resources.to_state(), &self.rules, &self.config)
+        }
+
+        /// Applies the match `m`, which must have been computed against the state of the
+        /// current resources. Fails without changing the resources if the consumed elements are
+        /// not available.
+        pub fn apply(&mut self, m: Match<E>) -> Result<(), LinearError> {
+            let state = self.resources.to_state();
+            let consumed = m
+                .consumed
+                .iter()
+                .map(|index| state.get(*index).map(E::clone).ok_or(LinearError::Missing))
+                .collect::<Result<Vec<_>, _>>()?;
+            let consumed = counted(consumed);
+            self.resources.consume(&consumed)?;
+            let produced = counted(m.produced);
+            for (e, count) in &produced {
+                self.resources.add(E::clone(e), *count);
+            }
+            log_trace!("linear rule {} applied", m.rule);
+            self.ledger.push(LedgerEntry {
+                rule: m.rule,
+                consumed,
+                produced,
+            });
+            Ok(())
+        }
+
+        /// Applies the first match against the current resources, returning the identifier of
+        /// the applied rule.
+        #[inline]
+        pub fn step(&mut self) -> Option<RuleId> {
+            let state = self.resources.to_state();
+            let m = engine::first_match(&state, &self.rules, &self.config)?;
+            let rule = m.rule;
+            self.apply(m).ok()?;
+            Some(rule)
+        }
+
+        /// Checks that replaying the ledger from the initial resources consumes only available
+        /// resources and leads to the current resources.
+        pub fn audit(&self) -> Result<(), LinearError> {
+            let mut resources = self.initial.clone();
+            for entry in &self.ledger {
+                resources.consume(&entry.consumed)?;
+                for (e, count) in &entry.produced {
+                    resources.add(E::clone(e), *count);
+                }
+            }
+            if resources.same_as(&self.resources) {
+                Ok(())
+            } else {
+                Err(LinearError::Unbalanced)
+            }
+        }
+    }
+}
+
+/// Constraint Handling Rules Module
+///
+/// A CHR-like execution model on top of rule matching, with simplification and propagation
+/// rules fired by priority.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod chr {
+    use {
+        super::*,
+        crate::{
+            engine::{self, Config},
+            fuel::{Exhausted, Fuel},
+            rule::{RuleId, RuleSet},
+        },
+        alloc::{
+            collections::{BTreeMap, BTreeSet},
+            string::String,
+            vec::Vec,
+        },
+    };
+
+    /// Rule Kind
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum Kind {
+        /// Consumes the matched elements and replaces them by the bottom of the rule.
+        Simplification,
+
+        /// Keeps the matched elements and adds the bottom of the rule. A propagation rule
+        /// fires at most once for every combination of matched elements.
+        Propagation,
+    }
+
+    /// CHR Program
+    ///
+    /// A [`RuleSet`] where every rule has a [`Kind`] and a priority. Among the applicable
+    /// rules, the rules with the lowest priority value fire first.
+    #[derive(Debug)]
+    pub struct Program<E, R = rule::Structure<E>>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        /// Rules of the program
+        rules: RuleSet<E, R>,
+
+        /// Kind and priority of every rule
+        info: BTreeMap<RuleId, (Kind, i64)>,
+    }
+
+    impl<E, R> Program<E, R>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        /// Builds a new empty [`Program`].
+        #[inline]
+        pub fn new() -> Self {
+            Self {
+                rules: RuleSet::new(),
+                info: BTreeMap::new(),
+            }
+        }
+
+        /// Inserts a new rule of the given `kind` and `priority`, returning its identifier.
+        #[inline]
+        pub fn insert<N>(&mut self, name: N, rule: R, kind: Kind, priority: i64) -> RuleId
+        where
+            N: Into<String>,
+        {
+            let id = self.rules.insert(name, rule);
+            self.info.insert(id, (kind, priority));
+            id
+        }
+
+        /// Inserts a new simplification rule, returning its identifier.
+        #[inline]
+        pub fn simplification<N>(&mut self, name: N, rule: R, priority: i64) -> RuleId
+        where
+            N: Into<String>,
+        {
+            self.insert(name, rule, Kind::Simplification, priority)
+        }
+
+        /// Inserts a new propagation rule, returning its identifier.
+        #[inline]
+        pub fn propagation<N>(&mut self, name: N, rule: R, priority: i64) -> RuleId
+        where
+            N: Into<String>,
+        {
+            self.insert(name, rule, Kind::Propagation, priority)
+        }
+
+        /// Returns the rules of the program.
+        #[inline]
+        pub fn rules(&self) -> &RuleSet<E, R> {
+            &self.rules
+        }
+
+        /// Returns the kind and the priority of the rule `id`.
+        #[inline]
+        pub fn info(&self, id: RuleId) -> Option<(Kind, i64)> {
+            self.info.get(&id).copied()
+        }
+    }
+
+    impl<E, R> Default for Program<E, R>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        #[inline]
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// Execution Result
+    #[derive(Debug)]
+    pub struct Execution<E> {
+        /// Final constraint store
+        pub state: Vec<E>,
+
+        /// Number of rule firings
+        pub fired: usize,
+
+        /// Propagation history, as pairs of a propagation rule and the identifiers of the
+        /// constraints it fired on
+        pub history: BTreeSet<(RuleId, Vec<u64>)>,
+    }
+
+    /// Runs `program` on the constraint store `state` until no rule can fire, consuming one
+    /// step of `fuel` for every firing.
+    ///
+    /// Every constraint of the store is given an identifier, so that the propagation history
+    /// can tell apart equal constraints. At every firing, the applicable match with the lowest
+    /// priority value fires, the earliest match in the [enumeration
+    /// order](engine::for_each_match) winning ties. Returns the current store as a partial
+    /// result if the fuel runs out first.
+    pub fn run<E, R>(
+        program: &Program<E, R>,
+        state: Vec<E>,
+        config: &Config<E>,
+        fuel: &mut Fuel,
+    ) -> Result<Execution<E>, Exhausted<Execution<E>>>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        let mut ids = (0..state.len() as u64).collect::<Vec<_>>();
+        let mut next = ids.len() as u64;
+        let mut execution = Execution {
+            state,
+            fired: 0,
+            history: BTreeSet::new(),
+        };
+        loop {
+            let mut chosen = None;
+            engine::for_each_match(&execution.state, &program.rules, config, |m| {
+                let (kind, priority) = match program.info(m.rule) {
+                    Some(info) => info,
+                    _ => return false,
+                };
+                if chosen
+                    .as_ref()
+                    .map_or(false, |(_, _, best)| *best <= priority)
+                {
+                    return false;
+                }
+                if kind == Kind::Propagation {
+                    let key = (m.rule, m.consumed.iter().map(|i| ids[*i]).collect());
+                    if execution.history.contains(&key) {
+                        return false;
+                    }
+                }
+                chosen = Some((m, kind, priority));
+                false
+            });
+            let (m, kind, _) = match chosen {
+                Some(chosen) => chosen,
+                _ => return Ok(execution),
+            };
+            if let Err(reason) = fuel.step() {
+                return Err(Exhausted::new(reason, execution));
+            }
+            log_trace!("chr rule {} fired", m.rule);
+            match kind {
+                Kind::Simplification => {
+                    for index in m.consumed.iter().rev() {
+                        ids.remove(*index);
+                    }
+                    engine::apply_match(&mut execution.state, m);
+                }
+                Kind::Propagation => {
+                    execution
+                        .history
+                        .insert((m.rule, m.consumed.iter().map(|i| ids[*i]).collect()));
+                    execution.state.extend(m.produced);
+                }
+            }
+            while ids.len() < execution.state.len() {
+                ids.push(next);
+                next += 1;
+            }
+            execution.fired += 1;
+        }
+    }
+}
+
+/// Petri Net Module
+///
+/// Reads a ground [`RuleSet`] as a Petri net whose places are the distinct elements of the
+/// rules and whose transitions are the rules, giving decision procedures for the ground
+/// fragment over markings, the exponent vectors of states.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod petri {
+    use {
+        super::*,
+        crate::rule::{RuleId, RuleSet},
+        alloc::{
+            collections::{BTreeSet, VecDeque},
+            vec,
+            vec::Vec,
+        },
+        core::fmt,
+    };
+
+    /// Marking
+    ///
+    /// The number of tokens in every place of a net, that is, the exponent vector of a state.
+    pub type Marking = Vec<usize>;
+
+    /// Petri Net Error
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum PetriError {
+        /// The rule is not ground.
+        NonGround(RuleId),
+
+        /// The invariants of the net overflow 64-bit integers.
+        Overflow,
+    }
+
+    impl fmt::Display for PetriError {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::NonGround(id) => write!(f, "rule {} is not ground", id),
+                Self::Overflow => f.write_str("net invariants overflow 64-bit integers"),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for PetriError {}
+
+    /// Transition
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub struct Transition {
+        /// Identifier of the rule of the transition
+        pub rule: RuleId,
+
+        /// Tokens consumed from every place
+        pub pre: Marking,
+
+        /// Tokens produced into every place
+        pub post: Marking,
+    }
+
+    impl Transition {
+        /// Returns `true` if the transition is enabled in `marking`.
+        #[inline]
+        pub fn is_enabled(&self, marking: &[usize]) -> bool {
+            self.pre.iter().zip(marking).all(move |(pre, m)| pre <= m)
+        }
+
+        /// Returns the marking reached by firing the transition in `marking`, if it is
+        /// enabled.
+        #[inline]
+        pub fn fire(&self, marking: &[usize]) -> Option<Marking> {
+            if !self.is_enabled(marking) {
+                return None;
+            }
+            Some(
+                marking
+                    .iter()
+                    .zip(&self.pre)
+                    .zip(&self.post)
+                    .map(move |((m, pre), post)| m - pre + post)
+                    .collect(),
+            )
+        }
+    }
+
+    /// Petri Net
+    ///
+    /// Premises, absences and side conditions of the rules are not represented in the net.
+    #[derive(Debug)]
+    pub struct PetriNet<E> {
+        /// Places of the net
+        places: Vec<E>,
+
+        /// Transitions of the net
+        transitions: Vec<Transition>,
+    }
+
+    impl<E> PetriNet<E>
+    where
+        E: Expression,
+        E::Atom: Clone + PartialEq,
+        E::Group: Container<E>,
+    {
+        /// Builds the [`PetriNet`] of the ground rules of `rules`.
+        pub fn from_rules<R, F>(
+            rules: &RuleSet<E, R>,
+            mut is_variable: F,
+        ) -> Result<Self, PetriError>
+        where
+            R: Rule<E>,
+            F: FnMut(&E::Atom) -> bool,
+        {
+            let mut places = Vec::<E>::new();
+            let mut arcs = Vec::new();
+            for entry in rules.iter() {
+                let (top, bot) = (entry.rule.top(), entry.rule.bot());
+                if !top
+                    .iter()
+                    .chain(bot.iter())
+                    .all(|e| is_ground(&e.cases(), &mut is_variable))
+                {
+                    return Err(PetriError::NonGround(entry.id));
+                }
+                let mut index = |e: ExprRef<E>| match places
+                    .iter()
+                    .position(|p| expr::eq_ref(&p.cases(), &e))
+                {
+                    Some(index) => index,
+                    _ => {
+                        places.push(e.to_owned());
+                        places.len() - 1
+                    }
+                };
+                let pre = top.iter().map(|e| index(e.cases())).collect::<Vec<_>>();
+                let post = bot.iter().map(|e| index(e.cases())).collect::<Vec<_>>();
+                arcs.push((entry.id, pre, post));
+            }
+            let count = |indices: &[usize]| {
+                let mut marking = vec![0; places.len()];
+                for index in indices {
+                    marking[*index] += 1;
+                }
+                marking
+            };
+            let transitions = arcs
+                .iter()
+                .map(|(rule, pre, post)| Transition {
+                    rule: *rule,
+                    pre: count(pre),
+                    post: count(post),
+                })
+                .collect();
+            Ok(Self {
+                places,
+                transitions,
+            })
+        }
+
+        /// Returns the places of the net.
+        #[inline]
+        pub fn places(&self) -> &[E] {
+            &self.places
+        }
+
+        /// Returns the transitions of the net.
+        #[inline]
+        pub fn transitions(&self) -> &[Transition] {
+            &self.transitions
+        }
+
+        /// Returns the marking of `state` and the elements of `state` which are not places.
+        pub fn marking<'s>(&self, state: &'s [E]) -> (Marking, Vec<&'s E>) {
+            let mut marking = vec![0; self.places.len()];
+            let mut inert = Vec::new();
+            for e in state {
+                match self.places.iter().position(move |p| E::eq(p, e)) {
+                    Some(index) => marking[index] += 1,
+                    _ => inert.push(e),
+                }
+            }
+            (marking, inert)
+        }
+
+        /// Returns the incidence matrix of the net, with one row for every place and one
+        /// column for every transition.
+        pub fn incidence(&self) -> Vec<Vec<i64>> {
+            (0..self.places.len())
+                .map(|place| {
+                    self.transitions
+                        .iter()
+                        .map(move |t| t.post[place] as i64 - t.pre[place] as i64)
+                        .collect()
+                })
+                .collect()
+        }
+
+        /// Returns a basis of the P-invariants of the net, the integer weightings of the places
+        /// whose weighted token count is preserved by every transition.
+        ///
+        /// Returns [`PetriError::Overflow`] if the elimination overflows 64-bit integers.
+        #[inline]
+        pub fn p_invariants(&self) -> Result<Vec<Vec<i64>>, PetriError> {
+            let incidence = self.incidence();
+            let transposed = (0..self.transitions.len())
+                .map(|t| incidence.iter().map(move |row| row[t]).collect())
+                .collect();
+            null_space(transposed, self.places.len())
+        }
+
+        /// Returns a basis of the T-invariants of the net, the integer firing counts of the
+        /// transitions which leave every marking unchanged.
+        ///
+        /// Returns [`PetriError::Overflow`] if the elimination overflows 64-bit integers.
+        #[inline]
+        pub fn t_invariants(&self) -> Result<Vec<Vec<i64>>, PetriError> {
+            null_space(self.incidence(), self.transitions.len())
+        }
+
+        /// Decides whether `target` is reachable from `initial` in at most `bound` firings.
+        ///
+        /// Returns `Some(false)` if a [P-invariant](Self::p_invariants) separates the two
+        /// states or if every marking reachable from `initial` was explored, `Some(true)` if
+        /// `target` was reached, and `None` if the bound was hit first. The elements of the
+        /// states which are not places are never changed by the net, so they must be equal.
+        /// The P-invariants are skipped if computing them or their weights overflows.
+        pub fn is_reachable(&self, initial: &[E], target: &[E], bound: usize) -> Option<bool> {
+            let (start, mut inert) = self.marking(initial);
+            let (goal, other) = self.marking(target);
+            if inert.len() != other.len() {
+                return Some(false);
+            }
+            for e in other {
+                match inert.iter().position(move |i| E::eq(i, e)) {
+                    Some(index) => {
+                        inert.swap_remove(index);
+                    }
+                    _ => return Some(false),
+                }
+            }
+            let weight = |y: &[i64], m: &[usize]| {
+                y.iter().zip(m).try_fold(0i64, move |w, (y, m)| {
+                    y.checked_mul(*m as i64).and_then(move |t| w.checked_add(t))
+                })
+            };
+            let separated = self.p_invariants().map_or(false, |invariants| {
+                invariants
+                    .iter()
+                    .any(|y| match (weight(y, &start), weight(y, &goal)) {
+                        (Some(lhs), Some(rhs)) => lhs != rhs,
+                        _ => false,
+                    })
+            });
+            if separated {
+                return Some(false);
+            }
+            let mut visited = BTreeSet::new();
+            let mut frontier = VecDeque::new();
+            visited.insert(start.clone());
+            frontier.push_back((start, 0));
+            let mut cutoff = false;
+            while let Some((marking, depth)) = frontier.pop_front() {
+                if marking == goal {
+                    return Some(true);
+                }
+                if depth == bound {
+                    cutoff = true;
+                    continue;
+                }
+                for transition in &self.transitions {
+                    if let Some(next) = transition.fire(&marking) {
+                        if visited.insert(next.clone()) {
+                            frontier.push_back((next, depth + 1));
+                        }
+                    }
+                }
+            }
+            if cutoff {
+                None
+            } else {
+                Some(false)
+            }
+        }
+    }
+
+    /// Returns `true` if no atom of `expr` satisfies `is_variable`.
+    fn is_ground<E, F>(expr: &ExprRef<E>, is_variable: &mut F) -> bool
+    where
+        E: Expression,
+        F: FnMut(&E::Atom) -> bool,
+    {
+        match expr {
+            ExprRef::Atom(atom) => !is_variable(atom),
+            ExprRef::Group(group) => group
+                .iter()
+                .all(move |e| is_ground(&e.cases(), is_variable)),
+        }
+    }
+
+    /// Returns the greatest common divisor of `a` and `b`.
+    #[inline]
+    fn gcd(mut a: i64, mut b: i64) -> Result<i64, PetriError> {
+        while b != 0 {
+            let r = a.checked_rem(b).ok_or(PetriError::Overflow)?;
+            a = b;
+            b = r;
+        }
+        a.checked_abs().ok_or(PetriError::Overflow)
+    }
+
+    /// Divides the entries of `row` by their greatest common divisor.
+    #[inline]
+    fn reduce(row: &mut [i64]) -> Result<(), PetriError> {
+        let divisor = row.iter().try_fold(0, move |g, x| gcd(g, *x))?;
+        if divisor > 1 {
+            row.iter_mut().for_each(move |x| *x /= divisor);
+        }
+        Ok(())
+    }
+
+    /// Returns an integer basis of the null space of `matrix`, whose rows have `columns`
+    /// entries, computed by fraction-free Gaussian elimination.
+    ///
+    /// Every row is kept reduced by the greatest common divisor of its entries, and
+    /// [`PetriError::Overflow`] is returned if an entry still overflows.
+    fn null_space(mut matrix: Vec<Vec<i64>>, columns: usize) -> Result<Vec<Vec<i64>>, PetriError> {
+        for row in &mut matrix {
+            reduce(row)?;
+        }
+        let mut pivots = Vec::new();
+        let mut row = 0;
+        for column in 0..columns {
+            let pivot = match (row..matrix.len()).find(|r| matrix[*r][column] != 0) {
+                Some(pivot) => pivot,
+                _ => continue,
+            };
+            matrix.swap(row, pivot);
+            for other in 0..matrix.len() {
+                if other == row || matrix[other][column] == 0 {
+                    continue;
+                }
+                let (p, f) = (matrix[row][column], matrix[other][column]);
+                for c in 0..columns {
+                    matrix[other][c] = matrix[other][c]
+                        .checked_mul(p)
+                        .zip(matrix[row][c].checked_mul(f))
+                        .and_then(move |(l, r)| l.checked_sub(r))
+                        .ok_or(PetriError::Overflow)?;
+                }
+                reduce(&mut matrix[other])?;
+            }
+            pivots.push(column);
+            row += 1;
+        }
+        (0..columns)
+            .filter(|column| !pivots.contains(column))
+            .map(|free| {
+                let mut scale = 1i64;
+                for (r, c) in pivots.iter().enumerate() {
+                    let p = matrix[r][*c];
+                    scale = (scale / gcd(scale, p)?)
+                        .checked_mul(p.checked_abs().ok_or(PetriError::Overflow)?)
+                        .ok_or(PetriError::Overflow)?;
+                }
+                let mut vector = vec![0; columns];
+                vector[free] = scale;
+                for (r, c) in pivots.iter().enumerate() {
+                    vector[*c] = matrix[r][free]
+                        .checked_mul(scale / matrix[r][*c])
+                        .and_then(i64::checked_neg)
+                        .ok_or(PetriError::Overflow)?;
+                }
+                reduce(&mut vector)?;
+                Ok(vector)
+            })
+            .collect()
+    }
+}
+
+/// Expression Shape Schemas
+///
+/// Expected expression shapes described as data and checked against any [`Expression`].
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod shape {
+    use {
+        super::*,
+        alloc::{boxed::Box, vec, vec::Vec},
+        core::fmt,
+    };
+
+    /// Shape Schema
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub enum Schema<A> {
+        /// Any expression
+        Any,
+
+        /// Any atom
+        Atom,
+
+        /// The given atom
+        Exact(A),
+
+        /// Any group
+        Group,
+
+        /// Group with one element for each schema, matching it
+        GroupOf(Vec<Schema<A>>),
+
+        /// Group starting with the given atom, followed by one element for each schema
+        Tagged(A, Vec<Schema<A>>),
+
+        /// Group whose elements all match the schema
+        Repeat(Box<Schema<A>>),
+    }
+
+    impl<A> Schema<A> {
+        /// Builds the schema of groups of `n` elements matching `schema`.
+        #[inline]
+        pub fn group_of_n(n: usize, schema: Self) -> Self
+        where
+            A: Clone,
+        {
+            Self::GroupOf(vec![schema; n])
+        }
+
+        /// Builds the schema of groups of groups.
+        #[inline]
+        pub fn group_of_groups() -> Self {
+            Self::repeat(Self::Group)
+        }
+
+        /// Builds the schema of groups whose elements all match `schema`.
+        #[inline]
+        pub fn repeat(schema: Self) -> Self {
+            Self::Repeat(Box::new(schema))
+        }
+
+        /// Builds the schema of the [`Rule`](rule::Rule) shape, a pair of groups.
+        #[inline]
+        pub fn ratio() -> Self {
+            Self::GroupOf(vec![Self::Group, Self::Group])
+        }
+
+        /// Checks `expr` against the schema.
+        #[inline]
+        pub fn check<E>(&self, expr: &E) -> Result<(), SchemaError>
+        where
+            E: Expression<Atom = A>,
+            A: PartialEq,
+        {
+            self.check_ref(&expr.cases())
+        }
+
+        /// Checks the expression reference `expr` against the schema.
+        pub fn check_ref<E>(&self, expr: &ExprRef<E>) -> Result<(), SchemaError>
+        where
+            E: Expression<Atom = A>,
+            A: PartialEq,
+        {
+            match (self, expr) {
+                (Self::Any, _)
+                | (Self::Atom, ExprRef::Atom(_))
+                | (Self::Group, ExprRef::Group(_)) => Ok(()),
+                (Self::Exact(expected), ExprRef::Atom(atom)) if expected == *atom => Ok(()),
+                (Self::Exact(_), ExprRef::Atom(_)) => Err(SchemaError::new(Mismatch::WrongAtom)),
+                (Self::Atom, _) | (Self::Exact(_), _) => {
+                    Err(SchemaError::new(Mismatch::ExpectedAtom))
+                }
+                (_, ExprRef::Atom(_)) => Err(SchemaError::new(Mismatch::ExpectedGroup)),
+                (Self::GroupOf(schemas), ExprRef::Group(group)) => {
+                    check_items::<E, _>(schemas, group.iter(), 0)
+                }
+                (Self::Tagged(tag, schemas), ExprRef::Group(group)) => {
+                    let mut iter = group.iter();
+                    let tagged = iter.next().map_or(false, move |item| match item.cases() {
+                        ExprRef::Atom(atom) => tag == atom,
+                        _ => false,
+                    });
+                    if !tagged {
+                        return Err(SchemaError::new(Mismatch::MissingTag));
+                    }
+                    check_items::<E, _>(schemas, iter, 1)
+                }
+                (Self::Repeat(schema), ExprRef::Group(group)) => {
+                    for (index, item) in group.iter().enumerate() {
+                        schema
+                            .check_ref::<E>(&item.cases())
+                            .map_err(move |error| error.within(index))?;
+                    }
+                    Ok(())
+                }
+            }
+        }
+
+        /// Returns `true` if `expr` matches the schema.
+        #[inline]
+        pub fn is_match<E>(&self, expr: &E) -> bool
+        where
+            E: Expression<Atom = A>,
+            A: PartialEq,
+        {
+            self.check(expr).is_ok()
+        }
+    }
+
+    /// Checks the `items` of a group, starting at position `offset`, against `schemas`.
+    fn check_items<'e, E, I>(
+        schemas: &[Schema<E::Atom>],
+        items: I,
+        offset: usize,
+    ) -> Result<(), SchemaError>
+    where
+        E: 'e + Expression,
+        E::Atom: PartialEq,
+        I: Iterator<Item = GroupRefItem<'e, E>>,
+    {
+        let mut found = 0;
+        for item in items {
+            if let Some(schema) = schemas.get(found) {
+                schema
+                    .check_ref::<E>(&item.cases())
+                    .map_err(move |error| error.within(offset + found))?;
+            }
+            found += 1;
+        }
+        if found == schemas.len() {
+            Ok(())
+        } else {
+            Err(SchemaError::new(Mismatch::Length {
+                expected: offset + schemas.len(),
+                found: offset + found,
+            }))
+        }
+    }
+
+    /// Schema Mismatch
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum Mismatch {
+        /// An atom was expected.
+        ExpectedAtom,
+
+        /// A group was expected.
+        ExpectedGroup,
+
+        /// The atom is not the expected one.
+        WrongAtom,
+
+        /// The group does not start with the expected tag.
+        MissingTag,
+
+        /// The group does not have the expected number of elements.
+        Length {
+            /// Expected number of elements
+            expected: usize,
+
+            /// Number of elements found
+            found: usize,
+        },
+    }
+
+    impl fmt::Display for Mismatch {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::ExpectedAtom => f.write_str("expected an atom"),
+                Self::ExpectedGroup => f.write_str("expected a group"),
+                Self::WrongAtom => f.write_str("unexpected atom"),
+                Self::MissingTag => f.write_str("group does not start with the expected tag"),
+                Self::Length { expected, found } => {
+                    write!(f, "expected {} elements, found {}", expected, found)
+                }
+            }
+        }
+    }
+
+    /// Schema Error
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub struct SchemaError {
+        /// Position of the offending sub-expression, as the indices of the nested groups leading
+        /// to it
+        pub path: Vec<usize>,
+
+        /// Mismatch at the offending sub-expression
+        pub mismatch: Mismatch,
+    }
+
+    impl fmt::Display for SchemaError {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{} at path {:?}", self.mismatch, self.path)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for SchemaError {}
+
+    impl SchemaError {
+        /// Builds a new [`SchemaError`] for a mismatch at the root of an expression.
+        #[inline]
+        pub fn new(mismatch: Mismatch) -> Self {
+            Self {
+                path: Vec::new(),
+                mismatch,
+            }
+        }
+
+        /// Prepends `index` to the path of the error.
+        #[inline]
+        pub fn within(mut self, index: usize) -> Self {
+            self.path.insert(0, index);
+            self
+        }
+    }
+}
+
+/// Pretty-Printing Module
+///
+/// Configurable s-expression layout for expressions, rules and states. Groups which fit in
+/// the maximum width are written on one line, and the others are broken after their first
+/// element with the remaining elements indented on their own lines.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod fmt {
+    use {
+        super::*,
+        crate::rule::RuleSet,
+        alloc::string::String,
+        core::fmt::{self as core_fmt, Display, Write},
+    };
+
+    /// Writes `atom` with its [`Display`] implementation.
+    #[inline]
+    pub fn display<A>(atom: &A, out: &mut dyn Write) -> core_fmt::Result
+    where
+        A: Display,
+    {
+        write!(out, "{}", atom)
+    }
+
+    /// Writes `count` spaces.
+    #[inline]
+    fn spaces(out: &mut dyn Write, count: usize) -> core_fmt::Result {
+        for _ in 0..count {
+            out.write_char(' ')?;
+        }
+        Ok(())
+    }
+
+    /// Pretty-Printer
+    #[derive(Clone, Copy, Debug)]
+    pub struct Printer<F> {
+        /// Indentation of the broken elements of a group
+        pub indent: usize,
+
+        /// Maximum line width
+        pub max_width: usize,
+
+        /// Atom Formatter
+        pub atom: F,
+    }
+
+    impl<F> Printer<F> {
+        /// Builds a new [`Printer`] with an indentation of `2` and a maximum width of `80`,
+        /// using `atom` to write atoms.
+        #[inline]
+        pub fn new(atom: F) -> Self {
+            Self {
+                indent: 2,
+                max_width: 80,
+                atom,
+            }
+        }
+
+        /// Sets the indentation of the broken elements of a group.
+        #[inline]
+        pub fn with_indent(mut self, indent: usize) -> Self {
+            self.indent = indent;
+            self
+        }
+
+        /// Sets the maximum line width.
+        #[inline]
+        pub fn with_max_width(mut self, max_width: usize) -> Self {
+            self.max_width = max_width;
+            self
+        }
+
+        /// Writes `expr` on a single line.
+        fn flat<E>(&mut self, expr: &ExprRef<E>, out: &mut dyn Write) -> core_fmt::Result
+        where
+            E: Expression,
+            F: FnMut(&E::Atom, &mut dyn Write) -> core_fmt::Result,
+        {
+            match expr {
+                ExprRef::Atom(atom) => (self.atom)(atom, out),
+                ExprRef::Group(group) => self.flat_group::<E>(group, out),
+            }
+        }
+
+        /// Writes `group` on a single line.
+        fn flat_group<E>(&mut self, group: &GroupRef<E>, out: &mut dyn Write) -> core_fmt::Result
+        where
+            E: Expression,
+            F: FnMut(&E::Atom, &mut dyn Write) -> core_fmt::Result,
+        {
+            out.write_char('(')?;
+            for (i, item) in group.iter().enumerate() {
+                if i > 0 {
+                    out.write_char(' ')?;
+                }
+                self.flat::<E>(&item.cases(), out)?;
+            }
+            out.write_char(')')
+        }
+
+        /// Writes `expr` starting at `column`.
+        fn layout<E>(
+            &mut self,
+            expr: &ExprRef<E>,
+            column: usize,
+            out: &mut dyn Write,
+        ) -> core_fmt::Result
+        where
+            E: Expression,
+            F: FnMut(&E::Atom, &mut dyn Write) -> core_fmt::Result,
+        {
+            match expr {
+                ExprRef::Atom(atom) => (self.atom)(atom, out),
+                ExprRef::Group(group) => self.layout_group::<E>(group, column, out),
+            }
+        }
+
+        /// Writes `group` starting at `column`.
+        fn layout_group<E>(
+            &mut self,
+            group: &GroupRef<E>,
+            column: usize,
+            out: &mut dyn Write,
+        ) -> core_fmt::Result
+        where
+            E: Expression,
+            F: FnMut(&E::Atom, &mut dyn Write) -> core_fmt::Result,
+        {
+            let mut flat = String::new();
+            self.flat_group::<E>(group, &mut flat)?;
+            if column + flat.len() <= self.max_width {
+                return out.write_str(&flat);
+            }
+            out.write_char('(')?;
+            for (i, item) in group.iter().enumerate() {
+                let item_column = if i == 0 {
+                    column + 1
+                } else {
+                    out.write_char('\n')?;
+                    spaces(out, column + self.indent)?;
+                    column + self.indent
+                };
+                self.layout::<E>(&item.cases(), item_column, out)?;
+            }
+            out.write_char(')')
+        }
+
+        /// Writes the rule with sides `top` and `bot` starting at `column`.
+        fn layout_ratio<E>(
+            &mut self,
+            top: &GroupRef<E>,
+            bot: &GroupRef<E>,
+            column: usize,
+            out: &mut dyn Write,
+        ) -> core_fmt::Result
+        where
+            E: Expression,
+            F: FnMut(&E::Atom, &mut dyn Write) -> core_fmt::Result,
+        {
+            let mut flat = String::new();
+            flat.push('(');
+            self.flat_group::<E>(top, &mut flat)?;
+            flat.push(' ');
+            self.flat_group::<E>(bot, &mut flat)?;
+            flat.push(')');
+            if column + flat.len() <= self.max_width {
+                return out.write_str(&flat);
+            }
+            out.write_char('(')?;
+            self.layout_group::<E>(top, column + 1, out)?;
+            out.write_char('\n')?;
+            spaces(out, column + 1)?;
+            self.layout_group::<E>(bot, column + 1, out)?;
+            out.write_char(')')
+        }
+
+        /// Writes `expr` into `out`.
+        #[inline]
+        pub fn expr<E, W>(&mut self, expr: &E, out: &mut W) -> core_fmt::Result
+        where
+            E: Expression,
+            F: FnMut(&E::Atom, &mut dyn Write) -> core_fmt::Result,
+            W: Write,
+        {
+            self.layout::<E>(&expr.cases(), 0, out)
+        }
+
+        /// Writes `group` into `out`.
+        #[inline]
+        pub fn group<E, W>(&mut self, group: &GroupRef<E>, out: &mut W) -> core_fmt::Result
+        where
+            E: Expression,
+            F: FnMut(&E::Atom, &mut dyn Write) -> core_fmt::Result,
+            W: Write,
+        {
+            self.layout_group::<E>(group, 0, out)
+        }
+
+        /// Writes `rule` as the s-expression `((top items) (bot items))` into `out`.
+        #[inline]
+        pub fn ratio<E, R, W>(&mut self, rule: &R, out: &mut W) -> core_fmt::Result
+        where
+            E: Expression,
+            E::Group: Container<E>,
+            F: FnMut(&E::Atom, &mut dyn Write) -> core_fmt::Result,
+            R: Rule<E>,
+            W: Write,
+        {
+            self.layout_ratio::<E>(&rule.top(), &rule.bot(), 0, out)
+        }
+
+        /// Writes every rule of `rules` on its own lines as `name: ((top items) (bot items))`
+        /// into `out`.
+        pub fn rules<E, R, W>(&mut self, rules: &RuleSet<E, R>, out: &mut W) -> core_fmt::Result
+        where
+            E: Expression,
+            E::Group: Container<E>,
+            F: FnMut(&E::Atom, &mut dyn Write) -> core_fmt::Result,
+            R: Rule<E>,
+            W: Write,
+        {
+            for entry in rules {
+                write!(out, "{}: ", entry.name)?;
+                let column = entry.name.chars().count() + 2;
+                self.layout_ratio::<E>(&entry.rule.top(), &entry.rule.bot(), column, out)?;
+                out.write_char('\n')?;
+            }
+            Ok(())
+        }
+
+        /// Writes `state` as a group of its elements into `out`.
+        pub fn state<E, W>(&mut self, state: &[E], out: &mut W) -> core_fmt::Result
+        where
+            E: Expression,
+            F: FnMut(&E::Atom, &mut dyn Write) -> core_fmt::Result,
+            W: Write,
+        {
+            let mut flat = String::new();
+            flat.push('(');
+            for (i, e) in state.iter().enumerate() {
+                if i > 0 {
+                    flat.push(' ');
+                }
+                self.flat::<E>(&e.cases(), &mut flat)?;
+            }
+            flat.push(')');
+            if flat.len() <= self.max_width {
+                return out.write_str(&flat);
+            }
+            out.write_char('(')?;
+            for (i, e) in state.iter().enumerate() {
+                let column = if i == 0 {
+                    1
+                } else {
+                    out.write_char('\n')?;
+                    spaces(out, self.indent)?;
+                    self.indent
+                };
+                self.layout::<E>(&e.cases(), column, out)?;
+            }
+            out.write_char(')')
+        }
+
+        /// Returns `expr` pretty-printed as a string.
+        #[inline]
+        pub fn expr_to_string<E>(&mut self, expr: &E) -> String
+        where
+            E: Expression,
+            F: FnMut(&E::Atom, &mut dyn Write) -> core_fmt::Result,
+        {
+            let mut out = String::new();
+            let _ = self.expr(expr, &mut out);
+            out
+        }
+    }
+
+    /// Writes a space into `out` unless `first` is set, which is then cleared.
+    #[inline]
+    fn separate(first: &mut bool, out: &mut dyn Write) -> core_fmt::Result {
+        if core::mem::replace(first, false) {
+            Ok(())
+        } else {
+            out.write_char(' ')
+        }
+    }
+
+    /// Writes the shape of a run of `atoms` atoms and resets the run.
+    #[inline]
+    fn atom_run(atoms: &mut usize, first: &mut bool, out: &mut dyn Write) -> core_fmt::Result {
+        match core::mem::take(atoms) {
+            0 => Ok(()),
+            1 => {
+                separate(first, out)?;
+                out.write_char('_')
+            }
+            count => {
+                separate(first, out)?;
+                write!(out, "_*{}", count)
+            }
+        }
+    }
+
+    /// Writes the shape of the elements of `group` with atoms elided.
+    fn group_shape<E>(group: &GroupRef<E>, out: &mut dyn Write) -> core_fmt::Result
+    where
+        E: Expression,
+    {
+        out.write_char('(')?;
+        let mut atoms = 0;
+        let mut first = true;
+        for item in group.iter() {
+            match item.cases() {
+                ExprRef::Atom(_) => atoms += 1,
+                ExprRef::Group(group) => {
+                    atom_run(&mut atoms, &mut first, out)?;
+                    separate(&mut first, out)?;
+                    group_shape::<E>(&group, out)?;
+                }
+            }
+        }
+        atom_run(&mut atoms, &mut first, out)?;
+        out.write_char(')')
+    }
+
+    /// Writes the shape of `expr` into `out`: atoms are written as `_`, runs of `k` atoms
+    /// inside a group as `_*k` and groups are parenthesized, so that the arity and the nesting
+    /// of every group stay visible on inputs which are too large to print in full.
+    #[inline]
+    pub fn format_shape<E, W>(expr: &E, out: &mut W) -> core_fmt::Result
+    where
+        E: Expression,
+        W: Write,
+    {
+        match expr.cases() {
+            ExprRef::Atom(_) => out.write_char('_'),
+            ExprRef::Group(group) => group_shape::<E>(&group, out),
+        }
+    }
+
+    /// Writes the shape of `rule` into `out` as `(top bot)`, following [`format_shape`].
+    #[inline]
+    pub fn format_ratio_shape<E, R, W>(rule: &R, out: &mut W) -> core_fmt::Result
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        W: Write,
+    {
+        out.write_char('(')?;
+        group_shape::<E>(&rule.top(), out)?;
+        out.write_char(' ')?;
+        group_shape::<E>(&rule.bot(), out)?;
+        out.write_char(')')
+    }
+
+    /// Returns the shape of `expr` as a string, following [`format_shape`].
+    #[inline]
+    pub fn shape_to_string<E>(expr: &E) -> String
+    where
+        E: Expression,
+    {
+        let mut out = String::new();
+        let _ = format_shape(expr, &mut out);
+        out
+    }
+}
+
+/// Canonical Binary Encoding Module
+///
+/// A stable, versioned and canonical binary encoding of expressions, rules and derivations,
+/// suitable for content hashing. Every document starts with the [`MAGIC`] bytes, the
+/// [`VERSION`] of the format and a [`Kind`] byte. Lengths and counts are unsigned LEB128
+/// integers in their shortest form and digests are little-endian 64-bit integers.
+///
+/// An atom is the byte `0` followed by the length and the bytes of its [`AtomCodec`]
+/// encoding, and a group is the byte `1` followed by the number of its elements and their
+/// encodings. Decoding rejects expressions nested deeper than [`MAX_DEPTH`] groups. The sides of a rule and the elements consumed and produced by a step are
+/// multisets, written as a count followed by the encodings of their elements in increasing
+/// byte order, and the bindings of a step are written in increasing order of their variables.
+/// Decoding rejects every non-canonical input, so that equal objects have equal encodings.
+///
+/// A trace record is either the byte `0` followed by the rule identifier and the consumed and
+/// produced multisets of a [`Delta`], or the byte `1` for the retraction of the last delta.
+///
+/// The state digests of a derivation are written as they were computed by
+/// [`expr::digest`], which is not canonical, so a derivation decoded in another build
+/// should be [checked](crate::proof::check) before its digests are trusted.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod binary {
+    use {
+        super::*,
+        crate::{
+            proof::{Delta, Derivation, Step},
+            rule::RuleId,
+        },
+        alloc::{string::String, vec::Vec},
+        core::fmt,
+    };
+
+    /// Magic Bytes
+    pub const MAGIC: [u8; 3] = *b"RDC";
+
+    /// Format Version
+    pub const VERSION: u8 = 1;
+
+    /// Maximum Nesting Depth of a Decoded Expression
+    pub const MAX_DEPTH: usize = 256;
+
+    /// Atom Codec Trait
+    pub trait AtomCodec: Sized {
+        /// Appends the bytes of `self` to `out`.
+        fn encode_atom(&self, out: &mut Vec<u8>);
+
+        /// Decodes an atom from its bytes.
+        fn decode_atom(bytes: &[u8]) -> Option<Self>;
+    }
+
+    impl AtomCodec for String {
+        #[inline]
+        fn encode_atom(&self, out: &mut Vec<u8>) {
+            out.extend_from_slice(self.as_bytes());
+        }
+
+        #[inline]
+        fn decode_atom(bytes: &[u8]) -> Option<Self> {
+            core::str::from_utf8(bytes).ok().map(Into::into)
+        }
+    }
+
+    impl AtomCodec for Vec<u8> {
+        #[inline]
+        fn encode_atom(&self, out: &mut Vec<u8>) {
+            out.extend_from_slice(self);
+        }
+
+        #[inline]
+        fn decode_atom(bytes: &[u8]) -> Option<Self> {
+            Some(bytes.to_vec())
+        }
+    }
+
+    impl AtomCodec for u64 {
+        #[inline]
+        fn encode_atom(&self, out: &mut Vec<u8>) {
+            out.extend_from_slice(&self.to_le_bytes());
+        }
+
+        #[inline]
+        fn decode_atom(bytes: &[u8]) -> Option<Self> {
+            Some(Self::from_le_bytes(bytes.try_into().ok()?))
+        }
+    }
+
+    /// Document Kind
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum Kind {
+        /// Expression
+        Expr = 0,
+
+        /// Rule
+        Rule = 1,
+
+        /// Derivation
+        Derivation = 2,
+
+        /// Trace Record
+        TraceRecord = 3,
+    }
+
+    /// Trace Record
+    #[derive(Debug)]
+    pub enum TraceRecord<E>
+    where
+        E: Expression,
+    {
+        /// Delta of an applied step
+        Delta(Delta<E>),
+
+        /// Retraction of the last delta
+        Retraction,
+    }
+
+    /// Decoding Error
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum DecodeError {
+        /// The input does not start with the [`MAGIC`] bytes.
+        BadMagic,
+
+        /// The format version is not [`VERSION`].
+        UnsupportedVersion(u8),
+
+        /// The document is not of the expected kind.
+        WrongKind(u8),
+
+        /// The input ended in the middle of the document.
+        UnexpectedEnd,
+
+        /// An expression starts with an unknown tag.
+        InvalidTag(u8),
+
+        /// The bytes of an atom are not a valid atom.
+        InvalidAtom,
+
+        /// An integer does not fit or is not in its shortest form.
+        InvalidInteger,
+
+        /// An expression is nested deeper than [`MAX_DEPTH`] groups.
+        TooDeep,
+
+        /// A multiset or the bindings of a step are not in canonical order.
+        NonCanonical,
+
+        /// The input continues after the end of the document.
+        TrailingBytes,
+    }
+
+    impl fmt::Display for DecodeError {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::BadMagic => f.write_str("input is not a canonical binary document"),
+                Self::UnsupportedVersion(version) => {
+                    write!(f, "unsupported format version {}", version)
+                }
+                Self::WrongKind(kind) => write!(f, "unexpected document kind {}", kind),
+                Self::UnexpectedEnd => f.write_str("unexpected end of input"),
+                Self::InvalidTag(tag) => write!(f, "invalid expression tag {}", tag),
+                Self::InvalidAtom => f.write_str("invalid atom"),
+                Self::InvalidInteger => f.write_str("invalid integer"),
+                Self::TooDeep => write!(f, "expression nested deeper than {} groups", MAX_DEPTH),
+                Self::NonCanonical => f.write_str("input is not in canonical order"),
+                Self::TrailingBytes => f.write_str("trailing bytes after the document"),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for DecodeError {}
+
+    /// Appends `n` as an unsigned LEB128 integer.
+    #[inline]
+    fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+        while n >= 0x80 {
+            out.push((n as u8) | 0x80);
+            n >>= 7;
+        }
+        out.push(n as u8);
+    }
+
+    /// Appends the document header of the given `kind`.
+    #[inline]
+    fn write_header(out: &mut Vec<u8>, kind: Kind) {
+        out.extend_from_slice(&MAGIC);
+        out.push(VERSION);
+        out.push(kind as u8);
+    }
+
+    /// Appends the encoding of `atom`.
+    #[inline]
+    fn write_atom<A>(out: &mut Vec<u8>, atom: &A)
+    where
+        A: AtomCodec,
+    {
+        out.push(0);
+        let mut bytes = Vec::new();
+        atom.encode_atom(&mut bytes);
+        write_varint(out, bytes.len() as u64);
+        out.extend_from_slice(&bytes);
+    }
+
+    /// Appends the encoding of `expr`.
+    fn write_expr<E>(out: &mut Vec<u8>, expr: &ExprRef<E>)
+    where
+        E: Expression,
+        E::Atom: AtomCodec,
+    {
+        match expr {
+            ExprRef::Atom(atom) => write_atom(out, *atom),
+            ExprRef::Group(group) => {
+                out.push(1);
+                write_varint(out, group.iter().count() as u64);
+                for item in group.iter() {
+                    write_expr::<E>(out, &item.cases());
+                }
+            }
+        }
+    }
+
+    /// Appends the canonical encoding of a multiset from the encodings of its elements.
+    #[inline]
+    fn write_multiset(out: &mut Vec<u8>, mut encodings: Vec<Vec<u8>>) {
+        encodings.sort();
+        write_varint(out, encodings.len() as u64);
+        for encoding in encodings {
+            out.extend_from_slice(&encoding);
+        }
+    }
+
+    /// Returns the encoding of `expr` without header.
+    #[inline]
+    fn expr_bytes<E>(expr: &ExprRef<E>) -> Vec<u8>
+    where
+        E: Expression,
+        E::Atom: AtomCodec,
+    {
+        let mut out = Vec::new();
+        write_expr::<E>(&mut out, expr);
+        out
+    }
+
+    /// Appends the canonical encoding of `group` read as a multiset.
+    #[inline]
+    fn write_group_multiset<E>(out: &mut Vec<u8>, group: &GroupRef<E>)
+    where
+        E: Expression,
+        E::Atom: AtomCodec,
+    {
+        write_multiset(
+            out,
+            group
+                .iter()
+                .map(move |item| expr_bytes::<E>(&item.cases()))
+                .collect(),
+        )
+    }
+
+    /// Appends the canonical encoding of `exprs` read as a multiset.
+    #[inline]
+    fn write_exprs_multiset<E>(out: &mut Vec<u8>, exprs: &[E])
+    where
+        E: Expression,
+        E::Atom: AtomCodec,
+    {
+        write_multiset(
+            out,
+            exprs
+                .iter()
+                .map(move |e| expr_bytes::<E>(&e.cases()))
+                .collect(),
+        )
+    }
+
+    /// Encodes `expr`.
+    pub fn encode_expr<E>(expr: &E) -> Vec<u8>
+    where
+        E: Expression,
+        E::Atom: AtomCodec,
+    {
+        let mut out = Vec::new();
+        write_header(&mut out, Kind::Expr);
+        write_expr::<E>(&mut out, &expr.cases());
+        out
+    }
+
+    /// Encodes `rule`, reading its top and bottom as multisets.
+    pub fn encode_rule<E, R>(rule: &R) -> Vec<u8>
+    where
+        E: Expression,
+        E::Atom: AtomCodec,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        let mut out = Vec::new();
+        write_header(&mut out, Kind::Rule);
+        write_group_multiset::<E>(&mut out, &rule.top());
+        write_group_multiset::<E>(&mut out, &rule.bot());
+        out
+    }
+
+    /// Encodes `derivation`.
+    pub fn encode_derivation<E>(derivation: &Derivation<E>) -> Vec<u8>
+    where
+        E: Expression,
+        E::Atom: AtomCodec,
+    {
+        let mut out = Vec::new();
+        write_header(&mut out, Kind::Derivation);
+        out.extend_from_slice(&derivation.initial.to_le_bytes());
+        write_varint(&mut out, derivation.len() as u64);
+        for step in derivation.steps() {
+            write_varint(&mut out, step.rule as u64);
+            let mut bindings = step
+                .bindings
+                .terms
+                .iter()
+                .map(move |t| {
+                    let mut var = Vec::new();
+                    write_atom(&mut var, &t.var);
+                    (var, expr_bytes::<E>(&t.expr.cases()))
+                })
+                .collect::<Vec<_>>();
+            bindings.sort();
+            write_varint(&mut out, bindings.len() as u64);
+            for (var, expr) in bindings {
+                out.extend_from_slice(&var);
+                out.extend_from_slice(&expr);
+            }
+            write_exprs_multiset(&mut out, &step.consumed);
+            write_exprs_multiset(&mut out, &step.produced);
+            out.extend_from_slice(&step.digest.to_le_bytes());
+        }
+        out
+    }
+
+    /// Appends the encoding of the delta of a step.
+    #[inline]
+    fn write_delta<E>(out: &mut Vec<u8>, rule: RuleId, consumed: &[E], produced: &[E])
+    where
+        E: Expression,
+        E::Atom: AtomCodec,
+    {
+        write_header(out, Kind::TraceRecord);
+        out.push(0);
+        write_varint(out, rule as u64);
+        write_exprs_multiset(out, consumed);
+        write_exprs_multiset(out, produced);
+    }
+
+    /// Encodes the trace record of `delta`.
+    #[inline]
+    pub fn encode_delta<E>(delta: &Delta<E>) -> Vec<u8>
+    where
+        E: Expression,
+        E::Atom: AtomCodec,
+    {
+        let mut out = Vec::new();
+        write_delta(&mut out, delta.rule, &delta.consumed, &delta.produced);
+        out
+    }
+
+    /// Encodes the trace record of the delta of `step`.
+    #[inline]
+    pub fn encode_step_delta<E>(step: &Step<E>) -> Vec<u8>
+    where
+        E: Expression,
+        E::Atom: AtomCodec,
+    {
+        let mut out = Vec::new();
+        write_delta(&mut out, step.rule, &step.consumed, &step.produced);
+        out
+    }
+
+    /// Encodes the trace record of a retraction.
+    #[inline]
+    pub fn encode_retraction() -> Vec<u8> {
+        let mut out = Vec::new();
+        write_header(&mut out, Kind::TraceRecord);
+        out.push(1);
+        out
+    }
+
+    /// Decoding Cursor
+    struct Reader<'b> {
+        /// Input Bytes
+        bytes: &'b [u8],
+
+        /// Offset into the input
+        position: usize,
+    }
+
+    impl<'b> Reader<'b> {
+        #[inline]
+        fn new(bytes: &'b [u8]) -> Self {
+            Self { bytes, position: 0 }
+        }
+
+        #[inline]
+        fn take(&mut self, len: usize) -> Result<&'b [u8], DecodeError> {
+            let end = self
+                .position
+                .checked_add(len)
+                .filter(|end| *end <= self.bytes.len())
+                .ok_or(DecodeError::UnexpectedEnd)?;
+            let bytes = &self.bytes[self.position..end];
+            self.position = end;
+            Ok(bytes)
+        }
+
+        #[inline]
+        fn byte(&mut self) -> Result<u8, DecodeError> {
+            Ok(self.take(1)?[0])
+        }
+
+        #[inline]
+        fn u64(&mut self) -> Result<u64, DecodeError> {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(self.take(8)?);
+            Ok(u64::from_le_bytes(bytes))
+        }
+
+        fn varint(&mut self) -> Result<u64, DecodeError> {
+            let mut n = 0u64;
+            for shift in (0..64).step_by(7) {
+                let byte = self.byte()?;
+                let bits = u64::from(byte & 0x7F);
+                if shift == 63 && bits > 1 {
+                    return Err(DecodeError::InvalidInteger);
+                }
+                n |= bits << shift;
+                if byte & 0x80 == 0 {
+                    if byte == 0 && shift > 0 {
+                        return Err(DecodeError::InvalidInteger);
+                    }
+                    return Ok(n);
+                }
+            }
+            Err(DecodeError::InvalidInteger)
+        }
+
+        #[inline]
+        fn usize(&mut self) -> Result<usize, DecodeError> {
+            self.varint()?
+                .try_into()
+                .map_err(move |_| DecodeError::InvalidInteger)
+        }
+
+        fn header(&mut self, kind: Kind) -> Result<(), DecodeError> {
+            if self
+                .take(MAGIC.len())
+                .map_err(move |_| DecodeError::BadMagic)?
+                != MAGIC
+            {
+                return Err(DecodeError::BadMagic);
+            }
+            match self.byte()? {
+                VERSION => {}
+                version => return Err(DecodeError::UnsupportedVersion(version)),
+            }
+            match self.byte()? {
+                k if k == kind as u8 => Ok(()),
+                k => Err(DecodeError::WrongKind(k)),
+            }
+        }
+
+        fn atom<A>(&mut self) -> Result<A, DecodeError>
+        where
+            A: AtomCodec,
+        {
+            let len = self.usize()?;
+            A::decode_atom(self.take(len)?).ok_or(DecodeError::InvalidAtom)
+        }
+
+        #[inline]
+        fn expr<E>(&mut self) -> Result<E, DecodeError>
+        where
+            E: Expression,
+            E::Atom: AtomCodec,
+            E::Group: FromIterator<E>,
+        {
+            self.nested_expr(MAX_DEPTH)
+        }
+
+        /// Decodes an expression with at most `depth` levels of groups.
+        fn nested_expr<E>(&mut self, depth: usize) -> Result<E, DecodeError>
+        where
+            E: Expression,
+            E::Atom: AtomCodec,
+            E::Group: FromIterator<E>,
+        {
+            match self.byte()? {
+                0 => Ok(E::from_atom(self.atom()?)),
+                1 => {
+                    let depth = depth.checked_sub(1).ok_or(DecodeError::TooDeep)?;
+                    let len = self.usize()?;
+                    (0..len)
+                        .map(|_| self.nested_expr(depth))
+                        .collect::<Result<_, _>>()
+                        .map(E::from_group)
+                }
+                tag => Err(DecodeError::InvalidTag(tag)),
+            }
+        }
+
+        /// Decodes a multiset of expressions, checking that it is in canonical order.
+        fn multiset<E, C>(&mut self) -> Result<C, DecodeError>
+        where
+            E: Expression,
+            E::Atom: AtomCodec,
+            E::Group: FromIterator<E>,
+            C: FromIterator<E>,
+        {
+            let len = self.usize()?;
+            let mut previous: Option<&[u8]> = None;
+            (0..len)
+                .map(|_| {
+                    let start = self.position;
+                    let expr = self.expr()?;
+                    let bytes = &self.bytes[start..self.position];
+                    if previous.map_or(false, move |p| p > bytes) {
+                        return Err(DecodeError::NonCanonical);
+                    }
+                    previous = Some(bytes);
+                    Ok(expr)
+                })
+                .collect()
+        }
+
+        fn finish<T>(&self, value: T) -> Result<T, DecodeError> {
+            if self.position == self.bytes.len() {
+                Ok(value)
+            } else {
+                Err(DecodeError::TrailingBytes)
+            }
+        }
+    }
+
+    /// Decodes an expression.
+    pub fn decode_expr<E>(bytes: &[u8]) -> Result<E, DecodeError>
+    where
+        E: Expression,
+        E::Atom: AtomCodec,
+        E::Group: FromIterator<E>,
+    {
+        let mut reader = Reader::new(bytes);
+        reader.header(Kind::Expr)?;
+        let expr = reader.expr()?;
+        reader.finish(expr)
+    }
+
+    /// Decodes a rule.
+    pub fn decode_rule<E>(bytes: &[u8]) -> Result<rule::Structure<E>, DecodeError>
+    where
+        E: Expression,
+        E::Atom: AtomCodec,
+        E::Group: FromIterator<E>,
+    {
+        let mut reader = Reader::new(bytes);
+        reader.header(Kind::Rule)?;
+        let top = reader.multiset::<E, _>()?;
+        let bot = reader.multiset::<E, _>()?;
+        reader.finish(rule::Structure::new(top, bot))
+    }
+
+    /// Decodes a derivation.
+    pub fn decode_derivation<E>(bytes: &[u8]) -> Result<Derivation<E>, DecodeError>
+    where
+        E: Expression,
+        E::Atom: AtomCodec,
+        E::Group: FromIterator<E>,
+    {
+        let mut reader = Reader::new(bytes);
+        reader.header(Kind::Derivation)?;
+        let mut derivation = Derivation::from_digest(reader.u64()?);
+        for _ in 0..reader.usize()? {
+            let rule = reader.usize()? as RuleId;
+            let mut bindings = Vec::new();
+            let mut previous: Option<(usize, usize)> = None;
+            for _ in 0..reader.usize()? {
+                let start = reader.position;
+                match reader.byte()? {
+                    0 => {}
+                    tag => return Err(DecodeError::InvalidTag(tag)),
+                }
+                let var = reader.atom()?;
+                let expr = reader.expr::<E>()?;
+                if let Some((s, e)) = previous {
+                    if reader.bytes[s..e] > reader.bytes[start..reader.position] {
+                        return Err(DecodeError::NonCanonical);
+                    }
+                }
+                previous = Some((start, reader.position));
+                bindings.push(substitution::Term::new(var, expr));
+            }
+            let consumed = reader.multiset::<E, _>()?;
+            let produced = reader.multiset::<E, _>()?;
+            derivation.push(Step {
+                rule,
+                bindings: bindings.into_iter().collect(),
+                consumed,
+                produced,
+                digest: reader.u64()?,
+            });
+        }
+        reader.finish(derivation)
+    }
+
+    /// Decodes a trace record.
+    pub fn decode_trace_record<E>(bytes: &[u8]) -> Result<TraceRecord<E>, DecodeError>
+    where
+        E: Expression,
+        E::Atom: AtomCodec,
+        E::Group: FromIterator<E>,
+    {
+        let mut reader = Reader::new(bytes);
+        reader.header(Kind::TraceRecord)?;
+        let record = match reader.byte()? {
+            0 => TraceRecord::Delta(Delta {
+                rule: reader.usize()? as RuleId,
+                consumed: reader.multiset::<E, _>()?,
+                produced: reader.multiset::<E, _>()?,
+            }),
+            1 => TraceRecord::Retraction,
+            tag => return Err(DecodeError::InvalidTag(tag)),
+        };
+        reader.finish(record)
+    }
+
+    #[cfg(all(test, feature = "simple"))]
+    mod tests {
+        use {super::*, crate::expr::SimpleExpr, alloc::vec};
+
+        type E = SimpleExpr;
+
+        fn atom(atom: &str) -> E {
+            E::atom(atom)
+        }
+
+        fn group<const N: usize>(items: [E; N]) -> E {
+            E::group(IntoIterator::into_iter(items))
+        }
+
+        fn header(kind: Kind) -> Vec<u8> {
+            let mut out = Vec::new();
+            write_header(&mut out, kind);
+            out
+        }
+
+        fn nested(depth: usize) -> E {
+            (0..depth).fold(group([]), move |e, _| group([e]))
+        }
+
+        fn derivation() -> Derivation<E> {
+            let mut derivation = Derivation::from_digest(7);
+            derivation.push(Step {
+                rule: 3,
+                bindings: vec![
+                    substitution::Term::new("?y".into(), atom("b")),
+                    substitution::Term::new("?x".into(), group([atom("f"), atom("a")])),
+                ]
+                .into_iter()
+                .collect(),
+                consumed: vec![atom("b"), group([atom("f"), atom("a")]), atom("a")],
+                produced: vec![atom("c")],
+                digest: 42,
+            });
+            derivation
+        }
+
+        #[test]
+        fn expression_round_trip() {
+            let expr = group([atom("f"), atom("a"), group([atom("g")]), group([])]);
+            let bytes = encode_expr(&expr);
+            assert_eq!(bytes[..5], *b"RDC\x01\x00");
+            assert_eq!(decode_expr::<E>(&bytes), Ok(expr));
+            assert_eq!(decode_expr::<E>(&encode_expr(&atom(""))), Ok(atom("")));
+        }
+
+        #[test]
+        fn rule_round_trip() {
+            let rule = rule::Structure::<E>::new(
+                vec![atom("b"), group([atom("f"), atom("?x")]), atom("a")],
+                vec![atom("c")],
+            );
+            let bytes = encode_rule(&rule);
+            let permuted = rule::Structure::<E>::new(
+                vec![atom("a"), atom("b"), group([atom("f"), atom("?x")])],
+                vec![atom("c")],
+            );
+            assert_eq!(encode_rule(&permuted), bytes);
+            let decoded = decode_rule::<E>(&bytes).unwrap();
+            assert_eq!(decoded, permuted);
+            assert_eq!(encode_rule(&decoded), bytes);
+        }
+
+        #[test]
+        fn derivation_round_trip() {
+            let bytes = encode_derivation(&derivation());
+            let decoded = decode_derivation::<E>(&bytes).unwrap();
+            assert_eq!(decoded.initial, 7);
+            assert_eq!(decoded.len(), 1);
+            let step = &decoded.steps()[0];
+            assert_eq!((step.rule, step.digest), (3, 42));
+            assert_eq!(step.bindings.terms[0].var, "?x");
+            assert_eq!(step.bindings.terms[1].var, "?y");
+            assert_eq!(
+                step.consumed,
+                vec![atom("a"), atom("b"), group([atom("f"), atom("a")])]
+            );
+            assert_eq!(step.produced, vec![atom("c")]);
+            assert_eq!(encode_derivation(&decoded), bytes);
+        }
+
+        #[test]
+        fn trace_record_round_trip() {
+            let delta = Delta::of(&derivation().steps()[0]);
+            match decode_trace_record::<E>(&encode_delta(&delta)).unwrap() {
+                TraceRecord::Delta(decoded) => {
+                    assert_eq!(decoded.rule, 3);
+                    assert_eq!(encode_delta(&decoded), encode_delta(&delta));
+                }
+                record => panic!("unexpected record {:?}", record),
+            }
+            assert!(matches!(
+                decode_trace_record::<E>(&encode_retraction()),
+                Ok(TraceRecord::Retraction)
+            ));
+        }
+
+        #[test]
+        fn rejects_wrong_header() {
+            let bytes = encode_expr(&atom("a"));
+            assert_eq!(decode_expr::<E>(&bytes[..2]), Err(DecodeError::BadMagic));
+            assert_eq!(decode_rule::<E>(&bytes), Err(DecodeError::WrongKind(0)));
+            let mut future = bytes.clone();
+            future[3] = VERSION + 1;
+            assert_eq!(
+                decode_expr::<E>(&future),
+                Err(DecodeError::UnsupportedVersion(VERSION + 1))
+            );
+        }
+
+        #[test]
+        fn rejects_unexpected_end() {
+            let bytes = encode_derivation(&derivation());
+            for len in 5..bytes.len() {
+                assert_eq!(
+                    decode_derivation::<E>(&bytes[..len]).unwrap_err(),
+                    DecodeError::UnexpectedEnd,
+                    "truncated to {} bytes",
+                    len
+                );
+            }
+        }
+
+        #[test]
+        fn rejects_deep_nesting() {
+            let bytes = encode_expr(&nested(MAX_DEPTH - 1));
+            assert_eq!(decode_expr::<E>(&bytes), Ok(nested(MAX_DEPTH - 1)));
+            let bytes = encode_expr(&nested(MAX_DEPTH));
+            assert_eq!(decode_expr::<E>(&bytes), Err(DecodeError::TooDeep));
+        }
+
+        #[test]
+        fn rejects_invalid_tags() {
+            let mut bytes = header(Kind::Expr);
+            bytes.push(2);
+            assert_eq!(decode_expr::<E>(&bytes), Err(DecodeError::InvalidTag(2)));
+            let mut bytes = header(Kind::TraceRecord);
+            bytes.push(2);
+            assert_eq!(
+                decode_trace_record::<E>(&bytes).unwrap_err(),
+                DecodeError::InvalidTag(2)
+            );
+            let mut bytes = header(Kind::Derivation);
+            bytes.extend_from_slice(&0u64.to_le_bytes());
+            bytes.extend_from_slice(&[1, 0, 1, 1, 0]);
+            assert_eq!(
+                decode_derivation::<E>(&bytes).unwrap_err(),
+                DecodeError::InvalidTag(1)
+            );
+        }
+
+        #[test]
+        fn rejects_non_canonical_order() {
+            let mut bytes = header(Kind::Rule);
+            bytes.extend_from_slice(&[2, 0, 1, b'b', 0, 1, b'a', 0]);
+            assert_eq!(decode_rule::<E>(&bytes), Err(DecodeError::NonCanonical));
+            let mut bytes = header(Kind::Derivation);
+            bytes.extend_from_slice(&0u64.to_le_bytes());
+            bytes.extend_from_slice(&[1, 0, 2, 0, 1, b'y', 0, 1, b'a', 0, 1, b'x', 0, 1, b'a']);
+            assert_eq!(
+                decode_derivation::<E>(&bytes).unwrap_err(),
+                DecodeError::NonCanonical
+            );
+        }
+
+        #[test]
+        fn rejects_trailing_bytes() {
+            let mut bytes = encode_expr(&atom("a"));
+            bytes.push(0);
+            assert_eq!(decode_expr::<E>(&bytes), Err(DecodeError::TrailingBytes));
+            let mut bytes = encode_retraction();
+            bytes.push(0);
+            assert_eq!(
+                decode_trace_record::<E>(&bytes).unwrap_err(),
+                DecodeError::TrailingBytes
+            );
+        }
+
+        #[test]
+        fn rejects_non_minimal_integers() {
+            let mut bytes = header(Kind::Expr);
+            bytes.extend_from_slice(&[0, 0x81, 0x00, b'a']);
+            assert_eq!(decode_expr::<E>(&bytes), Err(DecodeError::InvalidInteger));
+            let mut bytes = header(Kind::Expr);
+            bytes.extend_from_slice(&[1, 0x80, 0x00]);
+            assert_eq!(decode_expr::<E>(&bytes), Err(DecodeError::InvalidInteger));
+            let mut bytes = header(Kind::Expr);
+            bytes.extend_from_slice(&[
+                1, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x02,
+            ]);
+            assert_eq!(decode_expr::<E>(&bytes), Err(DecodeError::InvalidInteger));
+        }
+    }
+}
+
+/// Rule Pack Module
+///
+/// A binary rule set format which can be read in place from a byte slice, for rule tables
+/// stored in read-only memory. A pack starts with the [`MAGIC`] bytes, the [`VERSION`] of the
+/// format and the number of atoms, nodes and rules and the length of the string table, followed
+/// by the atom, node and rule tables and the string table. All integers are little-endian
+/// `u32`s and every record has a fixed width:
+///
+/// - an atom is the offset and the length of its UTF-8 text in the string table,
+/// - a node is either `0` and the index of an atom or `1` and the index of the first node and
+///   the number of nodes of a group, whose nodes are contiguous and come after the node itself,
+/// - a rule is the index of the atom of its name followed by the index of the first node and
+///   the number of nodes of its top and of its bottom.
+///
+/// [`RulePack::parse`] validates the whole pack without allocating, after which every access
+/// is infallible.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod pack {
+    use {
+        super::*,
+        crate::rule::RuleSet,
+        alloc::{
+            collections::BTreeMap,
+            string::{String, ToString},
+            vec::Vec,
+        },
+        core::{
+            fmt::{self, Display},
+            str,
+        },
+    };
+
+    /// Magic Bytes
+    pub const MAGIC: [u8; 4] = *b"RDPK";
+
+    /// Format Version
+    pub const VERSION: u32 = 1;
+
+    /// Header Width
+    const HEADER: usize = 24;
+
+    /// Atom Record Width
+    const ATOM: usize = 8;
+
+    /// Node Record Width
+    const NODE: usize = 12;
+
+    /// Rule Record Width
+    const RULE: usize = 20;
+
+    /// Rule Pack Error
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum PackError {
+        /// The input does not start with the [`MAGIC`] bytes.
+        BadMagic,
+
+        /// The format version is not [`VERSION`].
+        UnsupportedVersion(u32),
+
+        /// The input is shorter than its tables.
+        Truncated,
+
+        /// An atom is out of the string table or is not valid UTF-8.
+        InvalidAtom(u32),
+
+        /// A node has an unknown tag or refers to missing or earlier nodes.
+        InvalidNode(u32),
+
+        /// A rule refers to a missing atom or to missing nodes.
+        InvalidRule(u32),
+
+        /// The rule set has too many elements to be packed.
+        TooLarge,
+    }
+
+    impl fmt::Display for PackError {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::BadMagic => f.write_str("input is not a rule pack"),
+                Self::UnsupportedVersion(version) => {
+                    write!(f, "unsupported rule pack version {}", version)
+                }
+                Self::Truncated => f.write_str("rule pack is truncated"),
+                Self::InvalidAtom(index) => write!(f, "invalid atom {}", index),
+                Self::InvalidNode(index) => write!(f, "invalid node {}", index),
+                Self::InvalidRule(index) => write!(f, "invalid rule {}", index),
+                Self::TooLarge => f.write_str("rule set is too large to be packed"),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for PackError {}
+
+    /// Reads the `index`-th `u32` of `bytes`.
+    #[inline]
+    fn read_u32(bytes: &[u8], index: usize) -> u32 {
+        let mut word = [0; 4];
+        word.copy_from_slice(&bytes[4 * index..4 * index + 4]);
+        u32::from_le_bytes(word)
+    }
+
+    /// Returns `true` if the `len` records starting at `start` are within `count` records.
+    #[inline]
+    fn in_bounds(start: u32, len: u32, count: u32) -> bool {
+        start
+            .checked_add(len)
+            .map_or(false, move |end| end <= count)
+    }
+
+    /// Rule Pack
+    #[derive(Clone, Copy, Debug)]
+    pub struct RulePack<'b> {
+        /// Atom Table
+        atoms: &'b [u8],
+
+        /// Node Table
+        nodes: &'b [u8],
+
+        /// Rule Table
+        rules: &'b [u8],
+
+        /// String Table
+        strings: &'b [u8],
+    }
+
+    impl<'b> RulePack<'b> {
+        /// Parses and validates a rule pack.
+        pub fn parse(bytes: &'b [u8]) -> Result<Self, PackError> {
+            if bytes.len() < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+                return Err(PackError::BadMagic);
+            }
+            if bytes.len() < HEADER {
+                return Err(PackError::Truncated);
+            }
+            match read_u32(bytes, 1) {
+                VERSION => {}
+                version => return Err(PackError::UnsupportedVersion(version)),
+            }
+            let mut rest = &bytes[HEADER..];
+            let mut table = |index, width: usize| -> Result<&'b [u8], PackError> {
+                let len = (read_u32(bytes, index) as usize)
+                    .checked_mul(width)
+                    .filter(|len| *len <= rest.len())
+                    .ok_or(PackError::Truncated)?;
+                let (table, tail) = rest.split_at(len);
+                rest = tail;
+                Ok(table)
+            };
+            let pack = Self {
+                atoms: table(2, ATOM)?,
+                nodes: table(3, NODE)?,
+                rules: table(4, RULE)?,
+                strings: table(5, 1)?,
+            };
+            pack.validate()?;
+            Ok(pack)
+        }
+
+        /// Checks that every record of the pack is valid.
+        fn validate(&self) -> Result<(), PackError> {
+            let atoms = self.atom_count();
+            let nodes = (self.nodes.len() / NODE) as u32;
+            for index in 0..atoms {
+                let offset = read_u32(self.atoms, 2 * index as usize);
+                let len = read_u32(self.atoms, 2 * index as usize + 1);
+                if !in_bounds(offset, len, self.strings.len() as u32)
+                    || str::from_utf8(&self.strings[offset as usize..(offset + len) as usize])
+                        .is_err()
+                {
+                    return Err(PackError::InvalidAtom(index));
+                }
+            }
+            for index in 0..nodes {
+                let (tag, first, second) = self.node(index);
+                let valid = match tag {
+                    0 => first < atoms,
+                    1 => first > index && in_bounds(first, second, nodes),
+                    _ => false,
+                };
+                if !valid {
+                    return Err(PackError::InvalidNode(index));
+                }
+            }
+            for index in 0..self.len() as u32 {
+                let record = &self.rules[RULE * index as usize..];
+                if read_u32(record, 0) >= atoms
+                    || !in_bounds(read_u32(record, 1), read_u32(record, 2), nodes)
+                    || !in_bounds(read_u32(record, 3), read_u32(record, 4), nodes)
+                {
+                    return Err(PackError::InvalidRule(index));
+                }
+            }
+            Ok(())
+        }
+
+        #[inline]
+        fn atom_count(&self) -> u32 {
+            (self.atoms.len() / ATOM) as u32
+        }
+
+        /// Returns the text of the atom `index`.
+        #[inline]
+        fn atom(&self, index: u32) -> &'b str {
+            let offset = read_u32(self.atoms, 2 * index as usize) as usize;
+            let len = read_u32(self.atoms, 2 * index as usize + 1) as usize;
+            str::from_utf8(&self.strings[offset..offset + len]).unwrap_or_default()
+        }
+
+        /// Returns the fields of the node `index`.
+        #[inline]
+        fn node(&self, index: u32) -> (u32, u32, u32) {
+            let record = &self.nodes[NODE * index as usize..];
+            (
+                read_u32(record, 0),
+                read_u32(record, 1),
+                read_u32(record, 2),
+            )
+        }
+
+        /// Returns the number of rules of the pack.
+        #[inline]
+        pub fn len(&self) -> usize {
+            self.rules.len() / RULE
+        }
+
+        /// Returns `true` if the pack has no rules.
+        #[inline]
+        pub fn is_empty(&self) -> bool {
+            self.rules.is_empty()
+        }
+
+        /// Returns the rule with the given `index`.
+        #[inline]
+        pub fn get(&self, index: usize) -> Option<PackedRule<'b>> {
+            if index >= self.len() {
+                return None;
+            }
+            let record = &self.rules[RULE * index..];
+            Some(PackedRule {
+                name: self.atom(read_u32(record, 0)),
+                top: PackedGroup {
+                    pack: *self,
+                    start: read_u32(record, 1),
+                    len: read_u32(record, 2),
+                },
+                bot: PackedGroup {
+                    pack: *self,
+                    start: read_u32(record, 3),
+                    len: read_u32(record, 4),
+                },
+            })
+        }
+
+        /// Returns an iterator over the rules of the pack.
+        #[inline]
+        pub fn iter(&self) -> impl 'b + Iterator<Item = PackedRule<'b>> {
+            let pack = *self;
+            (0..self.len()).filter_map(move |index| pack.get(index))
+        }
+
+        /// Loads the rules of the pack into a [`RuleSet`].
+        #[inline]
+        pub fn load<E, R>(&self) -> RuleSet<E, R>
+        where
+            E: Expression,
+            E::Group: Container<E>,
+            for<'a> E::Atom: From<&'a str>,
+            R: Rule<E>,
+        {
+            let mut rules = RuleSet::new();
+            for rule in self.iter() {
+                rules.insert(rule.name, rule.to_rule());
+            }
+            rules
+        }
+    }
+
+    /// Packed Rule
+    #[derive(Clone, Copy, Debug)]
+    pub struct PackedRule<'b> {
+        /// Rule Name
+        pub name: &'b str,
+
+        /// Top of the rule
+        pub top: PackedGroup<'b>,
+
+        /// Bottom of the rule
+        pub bot: PackedGroup<'b>,
+    }
+
+    impl<'b> PackedRule<'b> {
+        /// Builds the rule.
+        #[inline]
+        pub fn to_rule<E, R>(&self) -> R
+        where
+            E: Expression,
+            E::Group: Container<E>,
+            for<'a> E::Atom: From<&'a str>,
+            R: Rule<E>,
+        {
+            R::new(self.top.to_group::<E>(), self.bot.to_group::<E>())
+        }
+    }
+
+    /// Packed Group
+    #[derive(Clone, Copy, Debug)]
+    pub struct PackedGroup<'b> {
+        /// Rule Pack
+        pack: RulePack<'b>,
+
+        /// Index of the first node
+        start: u32,
+
+        /// Number of nodes
+        len: u32,
+    }
+
+    impl<'b> PackedGroup<'b> {
+        /// Returns the number of elements of the group.
+        #[inline]
+        pub fn len(&self) -> usize {
+            self.len as usize
+        }
+
+        /// Returns `true` if the group has no elements.
+        #[inline]
+        pub fn is_empty(&self) -> bool {
+            self.len == 0
+        }
+
+        /// Returns the element of the group with the given `index`.
+        #[inline]
+        pub fn get(&self, index: usize) -> Option<PackedExpr<'b>> {
+            if index >= self.len() {
+                return None;
+            }
+            Some(match self.pack.node(self.start + index as u32) {
+                (0, atom, _) => PackedExpr::Atom(self.pack.atom(atom)),
+                (_, start, len) => PackedExpr::Group(Self {
+                    pack: self.pack,
+                    start,
+                    len,
+                }),
+            })
+        }
+
+        /// Returns an iterator over the elements of the group.
+        #[inline]
+        pub fn iter(&self) -> impl 'b + Iterator<Item = PackedExpr<'b>> {
+            let group = *self;
+            (0..self.len()).filter_map(move |index| group.get(index))
+        }
+
+        /// Builds the group.
+        #[inline]
+        pub fn to_group<E>(&self) -> E::Group
+        where
+            E: Expression,
+            E::Group: FromIterator<E>,
+            for<'a> E::Atom: From<&'a str>,
+        {
+            self.iter().map(move |e| e.to_expr()).collect()
+        }
+    }
+
+    /// Packed Expression
+    #[derive(Clone, Copy, Debug)]
+    pub enum PackedExpr<'b> {
+        /// Atomic expression
+        Atom(&'b str),
+
+        /// Grouped expression
+        Group(PackedGroup<'b>),
+    }
+
+    impl<'b> PackedExpr<'b> {
+        /// Builds the expression.
+        #[inline]
+        pub fn to_expr<E>(&self) -> E
+        where
+            E: Expression,
+            E::Group: FromIterator<E>,
+            for<'a> E::Atom: From<&'a str>,
+        {
+            match self {
+                Self::Atom(atom) => E::from_atom((*atom).into()),
+                Self::Group(group) => E::from_group(group.to_group::<E>()),
+            }
+        }
+    }
+
+    /// Rule Pack Writer
+    #[derive(Default)]
+    struct Writer {
+        /// Atom indices by text
+        indices: BTreeMap<String, u32>,
+
+        /// Atom records
+        atoms: Vec<[u32; 2]>,
+
+        /// Node records
+        nodes: Vec<[u32; 3]>,
+
+        /// Rule records
+        rules: Vec<[u32; 5]>,
+
+        /// String table
+        strings: String,
+    }
+
+    impl Writer {
+        /// Returns the index of the atom with the given `text`, adding it if it is new.
+        fn intern(&mut self, text: String) -> Result<u32, PackError> {
+            if let Some(index) = self.indices.get(&text) {
+                return Ok(*index);
+            }
+            let index = self
+                .atoms
+                .len()
+                .try_into()
+                .map_err(|_| PackError::TooLarge)?;
+            let offset = self
+                .strings
+                .len()
+                .try_into()
+                .map_err(|_| PackError::TooLarge)?;
+            let len = text.len().try_into().map_err(|_| PackError::TooLarge)?;
+            self.strings.push_str(&text);
+            self.atoms.push([offset, len]);
+            self.indices.insert(text, index);
+            Ok(index)
+        }
+
+        /// Writes the elements of `group` as contiguous nodes, followed by the nodes of their
+        /// subgroups, returning the index of the first node and the number of nodes.
+        fn group<E>(&mut self, group: &GroupRef<E>) -> Result<(u32, u32), PackError>
+        where
+            E: Expression,
+            E::Atom: Display,
+        {
+            let items = group.iter().collect::<Vec<_>>();
+            let start = self.nodes.len();
+            self.nodes.resize(start + items.len(), [0; 3]);
+            for (i, item) in items.iter().enumerate() {
+                self.nodes[start + i] = match item.cases() {
+                    ExprRef::Atom(atom) => [0, self.intern(atom.to_string())?, 0],
+                    ExprRef::Group(group) => {
+                        let (first, len) = self.group::<E>(&group)?;
+                        [1, first, len]
+                    }
+                };
+            }
+            Ok((
+                start.try_into().map_err(|_| PackError::TooLarge)?,
+                items.len().try_into().map_err(|_| PackError::TooLarge)?,
+            ))
+        }
+
+        /// Returns the bytes of the pack.
+        fn finish(self) -> Result<Vec<u8>, PackError> {
+            let mut out = Vec::with_capacity(
+                HEADER
+                    + ATOM * self.atoms.len()
+                    + NODE * self.nodes.len()
+                    + RULE * self.rules.len()
+                    + self.strings.len(),
+            );
+            out.extend_from_slice(&MAGIC);
+            for word in [
+                VERSION as usize,
+                self.atoms.len(),
+                self.nodes.len(),
+                self.rules.len(),
+                self.strings.len(),
+            ] {
+                let word: u32 = word.try_into().map_err(|_| PackError::TooLarge)?;
+                out.extend_from_slice(&word.to_le_bytes());
+            }
+            let words = self
+                .atoms
+                .iter()
+                .flatten()
+                .chain(self.nodes.iter().flatten())
+                .chain(self.rules.iter().flatten());
+            for word in words {
+                out.extend_from_slice(&word.to_le_bytes());
+            }
+            out.extend_from_slice(self.strings.as_bytes());
+            Ok(out)
+        }
+    }
+
+    /// Writes `rules` as a rule pack.
+    pub fn write<E, R>(rules: &RuleSet<E, R>) -> Result<Vec<u8>, PackError>
+    where
+        E: Expression,
+        E::Atom: Display,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        let mut writer = Writer::default();
+        for entry in rules {
+            let name = writer.intern(entry.name.clone())?;
+            let (top_start, top_len) = writer.group::<E>(&entry.rule.top())?;
+            let (bot_start, bot_len) = writer.group::<E>(&entry.rule.bot())?;
+            writer
+                .rules
+                .push([name, top_start, top_len, bot_start, bot_len]);
+        }
+        writer.finish()
+    }
+}
+
+/// E-Graph Interop Module
+///
+/// Conversions between the expressions and rules of this crate and the [`RecExpr`] and
+/// [`Rewrite`] types of the `egg` crate. A group with more than one element whose first element
+/// is an atom which is not a variable becomes a node labelled by that atom with the remaining
+/// elements as children, every other group becomes a [`GROUP`] node with all of its elements as
+/// children and every atom becomes a leaf. Variables become pattern variables, prefixed by `?`
+/// unless they already are.
+#[cfg(all(feature = "alloc", feature = "egg"))]
+#[cfg_attr(docsrs, doc(cfg(all(feature = "alloc", feature = "egg"))))]
+pub mod egg {
+    use {
+        super::*,
+        crate::rule::RuleSet,
+        ::egg::{Analysis, ENodeOrVar, Id, Pattern, RecExpr, Rewrite, SymbolLang, Var},
+        alloc::{
+            format,
+            string::{String, ToString},
+            vec::Vec,
+        },
+        core::fmt::{self, Display},
+    };
+
+    /// Label of the nodes which stand for headless groups
+    pub const GROUP: &str = "()";
+
+    /// E-Graph Conversion Error
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    pub enum EggError {
+        /// The rule does not have exactly one top and one bottom element.
+        NotTermRule,
+
+        /// The variable is not a valid pattern variable.
+        InvalidVariable(String),
+
+        /// The rewrite has no pattern on one of its sides.
+        MissingPattern,
+
+        /// The rewrite was rejected by `egg`.
+        Rewrite(String),
+    }
+
+    impl fmt::Display for EggError {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::NotTermRule => f.write_str("rule is not a one-to-one term rule"),
+                Self::InvalidVariable(name) => write!(f, "invalid pattern variable `{}`", name),
+                Self::MissingPattern => f.write_str("rewrite has no pattern"),
+                Self::Rewrite(error) => write!(f, "invalid rewrite: {}", error),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for EggError {}
+
+    /// Returns the label of the node for the group with the given `items` and the items which
+    /// become its children.
+    fn head<'i, 'e, E>(
+        items: &'i [GroupRefItem<'e, E>],
+        is_variable: &mut dyn FnMut(&E::Atom) -> bool,
+    ) -> (String, &'i [GroupRefItem<'e, E>])
+    where
+        E: Expression,
+        E::Atom: Display,
+    {
+        if items.len() > 1 {
+            if let ExprRef::Atom(atom) = items[0].cases() {
+                if !is_variable(atom) {
+                    return (atom.to_string(), &items[1..]);
+                }
+            }
+        }
+        (GROUP.into(), items)
+    }
+
+    /// Adds the nodes of `expr` to `ast`, returning the identifier of its root.
+    fn add_expr<E>(expr: &ExprRef<E>, ast: &mut RecExpr<SymbolLang>) -> Id
+    where
+        E: Expression,
+        E::Atom: Display,
+    {
+        match expr {
+            ExprRef::Atom(atom) => ast.add(SymbolLang::leaf(atom.to_string())),
+            ExprRef::Group(group) => {
+                let items = group.iter().collect::<Vec<_>>();
+                let (label, rest) = head::<E>(&items, &mut |_| false);
+                let children = rest
+                    .iter()
+                    .map(|item| add_expr::<E>(&item.cases(), ast))
+                    .collect();
+                ast.add(SymbolLang::new(label, children))
+            }
+        }
+    }
+
+    /// Converts the atom `var` into a pattern variable.
+    fn variable<A>(var: &A) -> Result<Var, EggError>
+    where
+        A: Display,
+    {
+        let name = var.to_string();
+        let name = if name.starts_with('?') {
+            name
+        } else {
+            format!("?{}", name)
+        };
+        name.parse()
+            .map_err(move |_| EggError::InvalidVariable(name))
+    }
+
+    /// Adds the nodes of the pattern `expr` to `ast`, returning the identifier of its root.
+    fn add_pattern<E>(
+        expr: &ExprRef<E>,
+        ast: &mut RecExpr<ENodeOrVar<SymbolLang>>,
+        is_variable: &mut dyn FnMut(&E::Atom) -> bool,
+    ) -> Result<Id, EggError>
+    where
+        E: Expression,
+        E::Atom: Display,
+    {
+        match expr {
+            ExprRef::Atom(atom) if is_variable(atom) => {
+                Ok(ast.add(ENodeOrVar::Var(variable(*atom)?)))
+            }
+            ExprRef::Atom(atom) => {
+                Ok(ast.add(ENodeOrVar::ENode(SymbolLang::leaf(atom.to_string()))))
+            }
+            ExprRef::Group(group) => {
+                let items = group.iter().collect::<Vec<_>>();
+                let (label, rest) = head::<E>(&items, is_variable);
+                let children = rest
+                    .iter()
+                    .map(|item| add_pattern::<E>(&item.cases(), ast, is_variable))
+                    .collect::<Result<_, _>>()?;
+                Ok(ast.add(ENodeOrVar::ENode(SymbolLang::new(label, children))))
+            }
+        }
+    }
+
+    /// Builds the expression rooted at `id` in `ast`, where `parts` returns the label and the
+    /// children of a node.
+    fn from_ast<E, L>(ast: &[L], id: Id, parts: &dyn Fn(&L) -> (String, &[Id])) -> E
+    where
+        E: Expression,
+        E::Group: FromIterator<E>,
+        for<'a> E::Atom: From<&'a str>,
+    {
+        let (label, children) = parts(&ast[usize::from(id)]);
+        if label == GROUP {
+            E::from_group(
+                children
+                    .iter()
+                    .map(move |child| from_ast(ast, *child, parts))
+                    .collect(),
+            )
+        } else if children.is_empty() {
+            E::from_atom(label.as_str().into())
+        } else {
+            E::from_group(
+                core::iter::once(E::from_atom(label.as_str().into()))
+                    .chain(
+                        children
+                            .iter()
+                            .map(move |child| from_ast(ast, *child, parts)),
+                    )
+                    .collect(),
+            )
+        }
+    }
+
+    /// Returns the identifier of the root of the nodes of a [`RecExpr`], which is the last one.
+    #[inline]
+    fn root<L>(nodes: &[L]) -> Option<Id> {
+        Some(Id::from(nodes.len().checked_sub(1)?))
+    }
+
+    /// Returns the label and the children of a node.
+    #[inline]
+    fn symbol_parts(node: &SymbolLang) -> (String, &[Id]) {
+        (node.op.as_str().into(), node.children.as_slice())
+    }
+
+    /// Returns the label and the children of a pattern node.
+    #[inline]
+    fn pattern_parts(node: &ENodeOrVar<SymbolLang>) -> (String, &[Id]) {
+        match node {
+            ENodeOrVar::ENode(node) => symbol_parts(node),
+            ENodeOrVar::Var(var) => (var.to_string(), &[]),
+        }
+    }
+
+    /// Builds the group with the single expression of the pattern `ast` as its element.
+    #[inline]
+    fn pattern_group<E>(ast: Option<&RecExpr<ENodeOrVar<SymbolLang>>>) -> Result<E::Group, EggError>
+    where
+        E: Expression,
+        E::Group: FromIterator<E>,
+        for<'a> E::Atom: From<&'a str>,
+    {
+        let nodes = ast.ok_or(EggError::MissingPattern)?.as_ref();
+        let root = root(nodes).ok_or(EggError::MissingPattern)?;
+        Ok(core::iter::once(from_ast(nodes, root, &pattern_parts)).collect())
+    }
+
+    /// Converts `expr` into a [`RecExpr`].
+    #[inline]
+    pub fn expr_to_rec<E>(expr: &E) -> RecExpr<SymbolLang>
+    where
+        E: Expression,
+        E::Atom: Display,
+    {
+        let mut ast = RecExpr::default();
+        add_expr::<E>(&expr.cases(), &mut ast);
+        ast
+    }
+
+    /// Converts a [`RecExpr`] into an expression, returning `None` if it is empty.
+    #[inline]
+    pub fn expr_from_rec<E>(rec: &RecExpr<SymbolLang>) -> Option<E>
+    where
+        E: Expression,
+        E::Group: FromIterator<E>,
+        for<'a> E::Atom: From<&'a str>,
+    {
+        let nodes = rec.as_ref();
+        Some(from_ast(nodes, root(nodes)?, &symbol_parts))
+    }
+
+    /// Converts `expr` into a [`Pattern`], treating the atoms which satisfy `is_variable` as
+    /// variables.
+    #[inline]
+    pub fn pattern<E, F>(expr: &E, mut is_variable: F) -> Result<Pattern<SymbolLang>, EggError>
+    where
+        E: Expression,
+        E::Atom: Display,
+        F: FnMut(&E::Atom) -> bool,
+    {
+        let mut ast = RecExpr::default();
+        add_pattern::<E>(&expr.cases(), &mut ast, &mut is_variable)?;
+        Ok(Pattern::new(ast))
+    }
+
+    /// Converts a [`Pattern`] into an expression, turning its variables into atoms.
+    #[inline]
+    pub fn pattern_to_expr<E>(pattern: &Pattern<SymbolLang>) -> Option<E>
+    where
+        E: Expression,
+        E::Group: FromIterator<E>,
+        for<'a> E::Atom: From<&'a str>,
+    {
+        let nodes = pattern.ast.as_ref();
+        Some(from_ast(nodes, root(nodes)?, &pattern_parts))
+    }
+
+    /// Converts `rule` into a [`Rewrite`] named `name`, if it has exactly one top and one
+    /// bottom element, treating the atoms which satisfy `is_variable` as variables.
+    pub fn rule_to_rewrite<E, R, N, F>(
+        name: &str,
+        rule: &R,
+        mut is_variable: F,
+    ) -> Result<Rewrite<SymbolLang, N>, EggError>
+    where
+        E: Expression,
+        E::Atom: Display,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        N: Analysis<SymbolLang>,
+        F: FnMut(&E::Atom) -> bool,
+    {
+        let top = rule.top();
+        let bot = rule.bot();
+        let mut top_iter = top.iter();
+        let mut bot_iter = bot.iter();
+        match (
+            top_iter.next(),
+            top_iter.next(),
+            bot_iter.next(),
+            bot_iter.next(),
+        ) {
+            (Some(lhs), None, Some(rhs), None) => {
+                let mut searcher = RecExpr::default();
+                add_pattern::<E>(&lhs.cases(), &mut searcher, &mut is_variable)?;
+                let mut applier = RecExpr::default();
+                add_pattern::<E>(&rhs.cases(), &mut applier, &mut is_variable)?;
+                Rewrite::new(name, Pattern::new(searcher), Pattern::new(applier))
+                    .map_err(EggError::Rewrite)
+            }
+            _ => Err(EggError::NotTermRule),
+        }
+    }
+
+    /// Converts every rule of `rules` into a [`Rewrite`] named after its entry.
+    pub fn rules_to_rewrites<E, R, N, F>(
+        rules: &RuleSet<E, R>,
+        mut is_variable: F,
+    ) -> Result<Vec<Rewrite<SymbolLang, N>>, EggError>
+    where
+        E: Expression,
+        E::Atom: Display,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        N: Analysis<SymbolLang>,
+        F: FnMut(&E::Atom) -> bool,
+    {
+        rules
+            .iter()
+            .map(|entry| rule_to_rewrite(&entry.name, &entry.rule, &mut is_variable))
+            .collect()
+    }
+
+    /// Converts a [`Rewrite`] into a rule with its searcher on top and its applier on the
+    /// bottom, turning its variables into atoms.
+    pub fn rule_from_rewrite<E, R, N>(rewrite: &Rewrite<SymbolLang, N>) -> Result<R, EggError>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        for<'a> E::Atom: From<&'a str>,
+        R: Rule<E>,
+        N: Analysis<SymbolLang>,
+    {
+        Ok(R::new(
+            pattern_group::<E>(rewrite.searcher.get_pattern_ast())?,
+            pattern_group::<E>(rewrite.applier.get_pattern_ast())?,
+        ))
+    }
+
+    /// Converts `rewrites` into a rule set, naming every rule after its rewrite.
+    pub fn rules_from_rewrites<'r, E, R, N, I>(rewrites: I) -> Result<RuleSet<E, R>, EggError>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        for<'a> E::Atom: From<&'a str>,
+        R: Rule<E>,
+        N: 'r + Analysis<SymbolLang>,
+        I: IntoIterator<Item = &'r Rewrite<SymbolLang, N>>,
+    {
+        let mut rules = RuleSet::new();
+        for rewrite in rewrites {
+            rules.insert(rewrite.name.as_str(), rule_from_rewrite(rewrite)?);
+        }
+        Ok(rules)
+    }
+}
+
+/// Graphviz Export Module
+///
+/// Writes rule dependency graphs and derivations in the DOT language, with nodes labelled by
+/// [pretty-printed](crate::fmt::Printer) expressions.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod graphviz {
+    use {
+        super::*,
+        crate::{
+            fmt::Printer,
+            proof::Derivation,
+            rule::{DependencyGraph, RuleId, RuleSet},
+        },
+        alloc::{string::String, vec::Vec},
+        core::fmt::{self, Write},
+    };
+
+    /// Writes `text` as a quoted DOT string.
+    fn quoted<W>(text: &str, out: &mut W) -> fmt::Result
+    where
+        W: Write,
+    {
+        out.write_char('"')?;
+        for c in text.chars() {
+            match c {
+                '"' => out.write_str("\\\"")?,
+                '\\' => out.write_str("\\\\")?,
+                '\n' => out.write_str("\\n")?,
+                _ => out.write_char(c)?,
+            }
+        }
+        out.write_char('"')
+    }
+
+    /// Returns the label of the rule `id` of `rules`, which is its name or its identifier if
+    /// it is not in the set.
+    fn rule_label<E, R>(rules: &RuleSet<E, R>, id: RuleId) -> String
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        match rules.get(id) {
+            Some(entry) => entry.name.clone(),
+            _ => alloc::format!("#{}", id),
+        }
+    }
+
+    /// Writes the dependency `graph` of `rules` as a DOT digraph, with one node per rule
+    /// labelled by its name and its pretty-printed rule.
+    pub fn to_dot<E, R, F, W>(
+        rules: &RuleSet<E, R>,
+        graph: &DependencyGraph,
+        printer: &mut Printer<F>,
+        out: &mut W,
+    ) -> fmt::Result
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        F: FnMut(&E::Atom, &mut dyn Write) -> fmt::Result,
+        W: Write,
+    {
+        out.write_str("digraph rules {\n")?;
+        for entry in rules {
+            let mut label = entry.name.clone();
+            label.push('\n');
+            printer.ratio(&entry.rule, &mut label)?;
+            write!(out, "    r{} [shape=box, label=", entry.id)?;
+            quoted(&label, out)?;
+            out.write_str("];\n")?;
+        }
+        for (source, target) in graph.edges() {
+            writeln!(out, "    r{} -> r{};", source, target)?;
+        }
+        out.write_str("}\n")
+    }
+
+    /// Writes `derivation` as a DOT digraph, with one node per fact labelled by the
+    /// pretty-printed fact and one edge from every consumed fact to every fact produced by the
+    /// same step, labelled by the name of the rule in `rules`.
+    ///
+    /// Consumed facts which no earlier step produced are the given facts of the derivation.
+    pub fn derivation_to_dot<E, R, F, W>(
+        derivation: &Derivation<E>,
+        rules: &RuleSet<E, R>,
+        printer: &mut Printer<F>,
+        out: &mut W,
+    ) -> fmt::Result
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        F: FnMut(&E::Atom, &mut dyn Write) -> fmt::Result,
+        W: Write,
+    {
+        out.write_str("digraph derivation {\n")?;
+        let mut nodes = 0usize;
+        let mut node = |expr: &E,
+                        given: bool,
+                        out: &mut W,
+                        printer: &mut Printer<F>|
+         -> Result<usize, fmt::Error> {
+            let mut label = String::new();
+            printer.expr(expr, &mut label)?;
+            write!(out, "    f{} [label=", nodes)?;
+            quoted(&label, out)?;
+            out.write_str(if given { ", shape=box];\n" } else { "];\n" })?;
+            nodes += 1;
+            Ok(nodes - 1)
+        };
+        let mut live = Vec::<(usize, &E)>::new();
+        for step in derivation.steps() {
+            let mut sources = Vec::with_capacity(step.consumed.len());
+            for e in &step.consumed {
+                match live.iter().rposition(move |(_, l)| E::eq(l, e)) {
+                    Some(index) => sources.push(live.remove(index).0),
+                    _ => sources.push(node(e, true, out, printer)?),
+                }
+            }
+            let label = rule_label(rules, step.rule);
+            for e in &step.produced {
+                let target = node(e, false, out, printer)?;
+                for source in &sources {
+                    write!(out, "    f{} -> f{} [label=", source, target)?;
+                    quoted(&label, out)?;
+                    out.write_str("];\n")?;
+                }
+                live.push((target, e));
+            }
+        }
+        out.write_str("}\n")
+    }
+}
+
+/// JSON Conversion Module
+///
+/// Converts expressions, rules, rule sets and derivations to and from [`Value`]s with a fixed
+/// schema, independently of any `serde` implementation of the expression type:
+///
+/// - an atom is a string and a group is an array of expressions,
+/// - a rule is the array `[top, bot]` of its two groups,
+/// - a rule set is an array of `{"id": number, "name": string, "rule": rule}` objects,
+/// - a derivation is an object `{"initial": number, "steps": [step, ...]}` where every step is
+///   an object `{"rule": number, "bindings": [[string, expr], ...], "consumed": [expr, ...],
+///   "produced": [expr, ...], "digest": number}`.
+#[cfg(feature = "json")]
+#[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+pub mod json {
+    use {
+        super::*,
+        crate::{
+            proof::{Derivation, Step},
+            rule::{RuleId, RuleSet},
+        },
+        alloc::{
+            string::{String, ToString},
+            vec::Vec,
+        },
+        core::fmt::{self, Display},
+        serde_json::{Map, Value},
+    };
+
+    /// JSON Conversion Error
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum JsonError {
+        /// The value is neither a string nor an array.
+        NotExpression,
+
+        /// A required object field is missing.
+        MissingField(&'static str),
+
+        /// An object field does not have the expected type.
+        InvalidField(&'static str),
+
+        /// The value does not have the rule shape.
+        Shape(rule::ShapeError),
+    }
+
+    impl fmt::Display for JsonError {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::NotExpression => f.write_str("value is neither a string nor an array"),
+                Self::MissingField(field) => write!(f, "missing field `{}`", field),
+                Self::InvalidField(field) => write!(f, "invalid field `{}`", field),
+                Self::Shape(error) => fmt::Display::fmt(error, f),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for JsonError {}
+
+    /// Converts an expression reference into a [`Value`].
+    pub fn expr_ref_to_value<E>(expr: &ExprRef<E>) -> Value
+    where
+        E: Expression,
+        E::Atom: Display,
+    {
+        match expr {
+            ExprRef::Atom(atom) => Value::String(atom.to_string()),
+            ExprRef::Group(group) => group_to_value::<E>(group),
+        }
+    }
+
+    /// Converts a group into a [`Value`].
+    #[inline]
+    pub fn group_to_value<E>(group: &GroupRef<E>) -> Value
+    where
+        E: Expression,
+        E::Atom: Display,
+    {
+        Value::Array(
+            group
+                .iter()
+                .map(move |item| expr_ref_to_value::<E>(&item.cases()))
+                .collect(),
+        )
+    }
+
+    /// Converts an expression into a [`Value`].
+    #[inline]
+    pub fn expr_to_value<E>(expr: &E) -> Value
+    where
+        E: Expression,
+        E::Atom: Display,
+    {
+        expr_ref_to_value::<E>(&expr.cases())
+    }
+
+    /// Converts a [`Value`] into an expression.
+    pub fn expr_from_value<E>(value: &Value) -> Result<E, JsonError>
+    where
+        E: Expression,
+        E::Group: FromIterator<E>,
+        for<'a> E::Atom: From<&'a str>,
+    {
+        match value {
+            Value::String(atom) => Ok(E::from_atom(atom.as_str().into())),
+            Value::Array(items) => Ok(E::from_group(group_from_values(items)?)),
+            _ => Err(JsonError::NotExpression),
+        }
+    }
+
+    /// Converts an array of [`Value`]s into a group.
+    #[inline]
+    fn group_from_values<E>(items: &[Value]) -> Result<E::Group, JsonError>
+    where
+        E: Expression,
+        E::Group: FromIterator<E>,
+        for<'a> E::Atom: From<&'a str>,
+    {
+        items.iter().map(expr_from_value).collect()
+    }
+
+    /// Converts an array field of `object` into a vector of expressions.
+    #[inline]
+    fn exprs_field<E>(object: &Map<String, Value>, field: &'static str) -> Result<Vec<E>, JsonError>
+    where
+        E: Expression,
+        E::Group: FromIterator<E>,
+        for<'a> E::Atom: From<&'a str>,
+    {
+        array_field(object, field)?
+            .iter()
+            .map(expr_from_value)
+            .collect()
+    }
+
+    /// Returns the array field `field` of `object`.
+    #[inline]
+    fn array_field<'v>(
+        object: &'v Map<String, Value>,
+        field: &'static str,
+    ) -> Result<&'v Vec<Value>, JsonError> {
+        object
+            .get(field)
+            .ok_or(JsonError::MissingField(field))?
+            .as_array()
+            .ok_or(JsonError::InvalidField(field))
+    }
+
+    /// Returns the unsigned integer field `field` of `object`.
+    #[inline]
+    fn u64_field(object: &Map<String, Value>, field: &'static str) -> Result<u64, JsonError> {
+        object
+            .get(field)
+            .ok_or(JsonError::MissingField(field))?
+            .as_u64()
+            .ok_or(JsonError::InvalidField(field))
+    }
+
+    /// Converts `rule` into the [`Value`] `[top, bot]`.
+    #[inline]
+    pub fn rule_to_value<E, R>(rule: &R) -> Value
+    where
+        E: Expression,
+        E::Atom: Display,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        Value::Array(alloc::vec![
+            group_to_value::<E>(&rule.top()),
+            group_to_value::<E>(&rule.bot()),
+        ])
+    }
+
+    /// Converts the [`Value`] `[top, bot]` into a rule.
+    pub fn rule_from_value<E, R>(value: &Value) -> Result<R, JsonError>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        for<'a> E::Atom: From<&'a str>,
+        R: Rule<E>,
+    {
+        let structure =
+            rule::Structure::<E>::try_from(Expression::into(expr_from_value::<E>(value)?))
+                .map_err(JsonError::Shape)?;
+        let (top, bot) = structure.pair();
+        Ok(R::new(top, bot))
+    }
+
+    /// Converts `rules` into an array of `{"id", "name", "rule"}` objects.
+    pub fn rules_to_value<E, R>(rules: &RuleSet<E, R>) -> Value
+    where
+        E: Expression,
+        E::Atom: Display,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        Value::Array(
+            rules
+                .iter()
+                .map(move |entry| {
+                    let mut object = Map::new();
+                    object.insert("id".into(), Value::from(entry.id as u64));
+                    object.insert("name".into(), Value::String(entry.name.clone()));
+                    object.insert("rule".into(), rule_to_value(&entry.rule));
+                    Value::Object(object)
+                })
+                .collect(),
+        )
+    }
+
+    /// Converts an array of `{"name", "rule"}` objects into a rule set, inserting the rules
+    /// in order. The `id` fields are ignored since rule sets assign their own identifiers.
+    pub fn rules_from_value<E, R>(value: &Value) -> Result<RuleSet<E, R>, JsonError>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        for<'a> E::Atom: From<&'a str>,
+        R: Rule<E>,
+    {
+        let mut rules = RuleSet::new();
+        for entry in value.as_array().ok_or(JsonError::InvalidField("rules"))? {
+            let entry = entry.as_object().ok_or(JsonError::InvalidField("rules"))?;
+            let name = entry
+                .get("name")
+                .ok_or(JsonError::MissingField("name"))?
+                .as_str()
+                .ok_or(JsonError::InvalidField("name"))?;
+            let rule = rule_from_value(entry.get("rule").ok_or(JsonError::MissingField("rule"))?)?;
+            rules.insert(name, rule);
+        }
+        Ok(rules)
+    }
+
+    /// Converts `step` into a step object.
+    fn step_to_value<E>(step: &Step<E>) -> Value
+    where
+        E: Expression,
+        E::Atom: Display,
+    {
+        let mut object = Map::new();
+        object.insert("rule".into(), Value::from(step.rule as u64));
+        object.insert(
+            "bindings".into(),
+            Value::Array(
+                step.bindings
+                    .terms
+                    .iter()
+                    .map(move |t| {
+                        Value::Array(alloc::vec![
+                            Value::String(t.var.to_string()),
+                            expr_to_value(&t.expr),
+                        ])
+                    })
+                    .collect(),
+            ),
+        );
+        object.insert(
+            "consumed".into(),
+            Value::Array(step.consumed.iter().map(expr_to_value).collect()),
+        );
+        object.insert(
+            "produced".into(),
+            Value::Array(step.produced.iter().map(expr_to_value).collect()),
+        );
+        object.insert("digest".into(), Value::from(step.digest));
+        Value::Object(object)
+    }
+
+    /// Converts a step object into a step.
+    fn step_from_value<E>(value: &Value) -> Result<Step<E>, JsonError>
+    where
+        E: Expression,
+        E::Group: FromIterator<E>,
+        for<'a> E::Atom: From<&'a str>,
+    {
+        let object = value.as_object().ok_or(JsonError::InvalidField("steps"))?;
+        let bindings = array_field(object, "bindings")?
+            .iter()
+            .map(move |binding| match binding.as_array().map(Vec::as_slice) {
+                Some([Value::String(var), expr]) => Ok(substitution::Term::new(
+                    var.as_str().into(),
+                    expr_from_value(expr)?,
+                )),
+                _ => Err(JsonError::InvalidField("bindings")),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Step {
+            rule: u64_field(object, "rule")? as RuleId,
+            bindings,
+            consumed: exprs_field(object, "consumed")?,
+            produced: exprs_field(object, "produced")?,
+            digest: u64_field(object, "digest")?,
+        })
+    }
+
+    /// Converts `derivation` into a derivation object.
+    pub fn derivation_to_value<E>(derivation: &Derivation<E>) -> Value
+    where
+        E: Expression,
+        E::Atom: Display,
+    {
+        let mut object = Map::new();
+        object.insert("initial".into(), Value::from(derivation.initial));
+        object.insert(
+            "steps".into(),
+            Value::Array(derivation.steps().iter().map(step_to_value).collect()),
+        );
+        Value::Object(object)
+    }
+
+    /// Converts a derivation object into a derivation.
+    pub fn derivation_from_value<E>(value: &Value) -> Result<Derivation<E>, JsonError>
+    where
+        E: Expression,
+        E::Group: FromIterator<E>,
+        for<'a> E::Atom: From<&'a str>,
+    {
+        let object = value
+            .as_object()
+            .ok_or(JsonError::InvalidField("derivation"))?;
+        let mut derivation = Derivation::from_digest(u64_field(object, "initial")?);
+        for step in array_field(object, "steps")? {
+            derivation.push(step_from_value(step)?);
+        }
+        Ok(derivation)
     }
+}
 
-    /// Tries to generate a substitution from two grouped expressions.
-    // FIXME: find a way to accept `&E::Group` or `GroupRef<E>`
+/// WebAssembly Bindings Module
+///
+/// Exposes composition, substitution, rule application and breadth-first search to JavaScript
+/// through `wasm-bindgen`, over the [`json`] encoding of [`SimpleExpr`](expr::SimpleExpr)s with
+/// string atoms. Atoms starting with `?` are variables. Every function takes and returns JSON
+/// text, and throws the message of its error as a string.
+#[cfg(feature = "wasm")]
+#[cfg_attr(docsrs, doc(cfg(feature = "wasm")))]
+pub mod wasm {
+    use {
+        super::*,
+        crate::{
+            engine::{self, Config},
+            fuel::Fuel,
+            search,
+        },
+        alloc::{
+            string::{String, ToString},
+            vec::Vec,
+        },
+        core::fmt::Display,
+        serde_json::{Map, Value},
+        wasm_bindgen::prelude::*,
+    };
+
+    /// Expression Type of the Bindings
+    type E = expr::SimpleExpr;
+
+    /// Rule Type of the Bindings
+    type R = rule::Structure<E>;
+
+    /// Returns `true` if `atom` is a variable, i.e. if it starts with `?`.
+    #[allow(clippy::ptr_arg)]
     #[inline]
-    pub fn generate_from_groups<E, S, F>(
-        lhs: &GroupRef<E>,
-        rhs: &GroupRef<E>,
-        mut can_substitute: F,
-    ) -> Option<Directed<S>>
+    pub fn is_variable(atom: &String) -> bool {
+        atom.starts_with('?')
+    }
+
+    /// Converts `error` into a JavaScript string.
+    #[inline]
+    fn error<T>(error: T) -> JsValue
+    where
+        T: Display,
+    {
+        JsValue::from_str(&error.to_string())
+    }
+
+    /// Parses the JSON `text`.
+    #[inline]
+    fn parse(text: &str) -> Result<Value, JsValue> {
+        serde_json::from_str(text).map_err(error)
+    }
+
+    /// Returns the items of the JSON array `value`, with `what` naming the expected array.
+    #[inline]
+    fn items<'v>(value: &'v Value, what: &str) -> Result<&'v [Value], JsValue> {
+        value
+            .as_array()
+            .map(Vec::as_slice)
+            .ok_or_else(move || error(format_args!("expected an array of {}", what)))
+    }
+
+    /// Converts an array of expressions into a state.
+    #[inline]
+    fn state_from_value(value: &Value) -> Result<Vec<E>, JsValue> {
+        items(value, "expressions")?
+            .iter()
+            .map(move |e| json::expr_from_value(e).map_err(error))
+            .collect()
+    }
+
+    /// Converts `state` into an array of expressions.
+    #[inline]
+    fn state_to_value(state: &[E]) -> Value {
+        Value::Array(state.iter().map(json::expr_to_value).collect())
+    }
+
+    /// Composes the array of rules `rules` in order, see [`rule::compose`].
+    #[wasm_bindgen]
+    pub fn compose(rules: &str) -> Result<String, JsValue> {
+        let rules = items(&parse(rules)?, "rules")?
+            .iter()
+            .map(move |r| json::rule_from_value::<E, R>(r).map_err(error))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(json::rule_to_value(&rule::compose::<E, R, _>(rules)).to_string())
+    }
+
+    /// Substitutes the array of `[var, expr]` pairs `bindings` into `expr`.
+    #[wasm_bindgen]
+    pub fn substitute(expr: &str, bindings: &str) -> Result<String, JsValue> {
+        let expr = json::expr_from_value::<E>(&parse(expr)?).map_err(error)?;
+        let bindings = items(&parse(bindings)?, "bindings")?
+            .iter()
+            .map(move |pair| match pair.as_array().map(Vec::as_slice) {
+                Some([Value::String(var), e]) => {
+                    Ok((var.clone(), json::expr_from_value::<E>(e).map_err(error)?))
+                }
+                _ => Err(error("expected a `[var, expr]` pair")),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let result = substitution::from_iter_ref(
+            bindings.iter().map(move |(var, e)| (var, Clone::clone(e))),
+            &expr,
+        );
+        Ok(json::expr_to_value(&result).to_string())
+    }
+
+    /// Applies the rule set `rules` to the array of expressions `state`, returning an array of
+    /// `{"rule": number, "state": [expr, ...]}` objects, one for every match.
+    #[wasm_bindgen]
+    pub fn apply(rules: &str, state: &str) -> Result<String, JsValue> {
+        let rules = json::rules_from_value::<E, R>(&parse(rules)?).map_err(error)?;
+        let state = state_from_value(&parse(state)?)?;
+        let successors = engine::matches(&state, &rules, &Config::new(is_variable))
+            .iter()
+            .map(|m| {
+                let mut object = Map::new();
+                object.insert("rule".into(), Value::from(m.rule as u64));
+                object.insert(
+                    "state".into(),
+                    state_to_value(&engine::successor(&state, m)),
+                );
+                Value::Object(object)
+            })
+            .collect();
+        Ok(Value::Array(successors).to_string())
+    }
+
+    /// Searches the rule set `rules` in breadth-first order for a shortest derivation from the
+    /// array of expressions `initial` to a state equal to the array of expressions `goal` as a
+    /// multiset, spending at most `steps` steps of fuel, see [`search::bfs`]. Returns the
+    /// derivation object, or `null` if the goal is unreachable, and throws if the fuel runs out.
+    #[wasm_bindgen]
+    pub fn bfs(rules: &str, initial: &str, goal: &str, steps: usize) -> Result<String, JsValue> {
+        let rules = json::rules_from_value::<E, R>(&parse(rules)?).map_err(error)?;
+        let initial = state_from_value(&parse(initial)?)?;
+        let goal = state_from_value(&parse(goal)?)?;
+        let mut fuel = Fuel::unlimited().with_steps(steps);
+        match search::bfs(
+            &Config::new(is_variable),
+            &rules,
+            initial,
+            search::Exact(&goal),
+            &mut fuel,
+            (),
+        ) {
+            Ok(Some(derivation)) => Ok(json::derivation_to_value(&derivation).to_string()),
+            Ok(None) => Ok(Value::Null.to_string()),
+            Err(exhausted) => Err(error(exhausted.reason)),
+        }
+    }
+}
+
+/// S-Expression Value Interop Module
+///
+/// Conversions between the expressions and rules of this crate and [`Value`]s of the `lexpr`
+/// crate. Atoms become symbols, groups become proper lists and rules become lists of their top
+/// and bottom lists. Symbols, strings and numbers are read back as atoms and vectors are read
+/// back as groups.
+#[cfg(all(feature = "alloc", feature = "lexpr"))]
+#[cfg_attr(docsrs, doc(cfg(all(feature = "alloc", feature = "lexpr"))))]
+pub mod lexpr {
+    use {
+        super::*,
+        crate::rule::RuleSet,
+        ::lexpr::Value,
+        alloc::{
+            string::{String, ToString},
+            vec::Vec,
+        },
+        core::fmt::{self, Display},
+    };
+
+    /// S-Expression Conversion Error
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum LexprError {
+        /// The value is neither an atom nor a list.
+        NotExpression,
+
+        /// The list is not a proper list.
+        ImproperList,
+
+        /// A rule set entry is not a list of a name and a rule.
+        InvalidEntry,
+
+        /// The value does not have the rule shape.
+        Shape(rule::ShapeError),
+    }
+
+    impl fmt::Display for LexprError {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::NotExpression => f.write_str("value is neither an atom nor a list"),
+                Self::ImproperList => f.write_str("value is not a proper list"),
+                Self::InvalidEntry => f.write_str("rule set entry is not a name and a rule"),
+                Self::Shape(error) => fmt::Display::fmt(error, f),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for LexprError {}
+
+    /// Returns the elements of `value` if it is a proper list or a vector.
+    #[inline]
+    fn elements(value: &Value) -> Result<Vec<&Value>, LexprError> {
+        match value {
+            Value::Vector(items) => Ok(items.iter().collect()),
+            _ if value.is_list() => Ok(value.list_iter().into_iter().flatten().collect()),
+            Value::Cons(_) => Err(LexprError::ImproperList),
+            _ => Err(LexprError::NotExpression),
+        }
+    }
+
+    /// Converts an expression reference into a [`Value`].
+    pub fn expr_ref_to_value<E>(expr: &ExprRef<E>) -> Value
     where
         E: Expression,
-        E::Atom: Clone + PartialEq,
-        E::Group: FromIterator<E>,
-        S: FromIterator<Term<E>> + IntoIterator<Item = Term<E>>,
-        F: FnMut(&E::Atom) -> bool,
+        E::Atom: Display,
     {
-        generate_from_groups_inner(lhs, rhs, &mut can_substitute)
+        match expr {
+            ExprRef::Atom(atom) => Value::symbol(atom.to_string()),
+            ExprRef::Group(group) => group_to_value::<E>(group),
+        }
     }
 
-    /// Tries to generate a substitution from two expressions.
-    // FIXME: find a way to accept `&E` or `ExprRef<E>`
+    /// Converts a group into a list [`Value`].
     #[inline]
-    pub fn generate<E, S, F>(lhs: &E, rhs: &E, mut can_substitute: F) -> Option<Directed<S>>
+    pub fn group_to_value<E>(group: &GroupRef<E>) -> Value
     where
         E: Expression,
-        E::Atom: Clone + PartialEq,
-        E::Group: FromIterator<E>,
-        S: FromIterator<Term<E>> + IntoIterator<Item = Term<E>>,
-        F: FnMut(&E::Atom) -> bool,
+        E::Atom: Display,
     {
-        generate_inner(lhs.cases(), rhs.cases(), &mut can_substitute)
+        Value::list(
+            group
+                .iter()
+                .map(move |item| expr_ref_to_value::<E>(&item.cases()))
+                .collect::<Vec<_>>(),
+        )
     }
 
-    fn generate_from_atoms_inner<E, S, F>(
-        lhs: &E::Atom,
-        rhs: &E::Atom,
-        can_substitute: &mut F,
-    ) -> Option<Directed<S>>
+    /// Converts an expression into a [`Value`].
+    #[inline]
+    pub fn expr_to_value<E>(expr: &E) -> Value
+    where
+        E: Expression,
+        E::Atom: Display,
+    {
+        expr_ref_to_value::<E>(&expr.cases())
+    }
+
+    /// Converts a [`Value`] into an expression.
+    pub fn expr_from_value<E>(value: &Value) -> Result<E, LexprError>
     where
         E: Expression,
-        E::Atom: Clone + PartialEq,
         E::Group: FromIterator<E>,
-        S: FromIterator<Term<E>> + IntoIterator<Item = Term<E>>,
-        F: FnMut(&E::Atom) -> bool,
+        for<'a> E::Atom: From<&'a str>,
     {
-        if lhs == rhs {
-            Some(Directed::Forward(S::from_iter(None)))
-        } else if can_substitute(lhs) {
-            Some(Directed::Forward(
-                Term::new(lhs.clone(), E::from_atom(rhs.clone())).unit(),
-            ))
-        } else if can_substitute(rhs) {
-            Some(Directed::Backward(
-                Term::new(rhs.clone(), E::from_atom(lhs.clone())).unit(),
-            ))
-        } else {
-            None
+        match value {
+            Value::Symbol(atom) | Value::String(atom) => Ok(E::from_atom(atom.as_ref().into())),
+            Value::Number(number) => Ok(E::from_atom(number.to_string().as_str().into())),
+            _ => Ok(E::from_group(group_from_value(value)?)),
         }
     }
 
-    fn generate_from_groups_inner<E, S, F>(
-        lhs: &GroupRef<E>,
-        rhs: &GroupRef<E>,
-        can_substitute: &mut F,
-    ) -> Option<Directed<S>>
+    /// Converts a list [`Value`] into a group.
+    #[inline]
+    pub fn group_from_value<E>(value: &Value) -> Result<E::Group, LexprError>
     where
         E: Expression,
-        E::Atom: Clone + PartialEq,
         E::Group: FromIterator<E>,
-        S: FromIterator<Term<E>> + IntoIterator<Item = Term<E>>,
-        F: FnMut(&E::Atom) -> bool,
+        for<'a> E::Atom: From<&'a str>,
     {
-        // FIXME: use `S as FromIterator` to construct the final substitution
-        // instead of relying on an intermediate `Vec`
-        let mut is_flipped = false;
-        let mut substitution = Vec::<Term<_>>::new();
-        let mut lhs_iter = lhs.iter();
-        let mut rhs_iter = rhs.iter();
-        for (lhs, rhs) in lhs_iter.by_ref().zip(rhs_iter.by_ref()) {
-            match generate_inner::<_, S, _>(lhs.cases(), rhs.cases(), can_substitute) {
-                Some(generated) => {
-                    let (is_forward, generated) = generated.unpack();
-                    if !is_forward {
-                        if is_flipped
-                            || !substitution
-                                .iter_mut()
-                                .all(|t| t.flip_in_place() && can_substitute(&t.var))
-                        {
-                            return None;
-                        }
-                        is_flipped = true;
-                        substitution.extend(generated);
-                    } else if is_flipped {
-                        for term in generated {
-                            let flipped = term.flip()?;
-                            if !can_substitute(&flipped.var) {
-                                return None;
-                            }
-                            substitution.push(flipped);
+        elements(value)?.into_iter().map(expr_from_value).collect()
+    }
+
+    /// Converts `rule` into the list [`Value`] of its top and bottom lists.
+    #[inline]
+    pub fn rule_to_value<E, R>(rule: &R) -> Value
+    where
+        E: Expression,
+        E::Atom: Display,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        Value::list(alloc::vec![
+            group_to_value::<E>(&rule.top()),
+            group_to_value::<E>(&rule.bot()),
+        ])
+    }
+
+    /// Converts a [`Value`] into a rule.
+    pub fn rule_from_value<E, R>(value: &Value) -> Result<R, LexprError>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        for<'a> E::Atom: From<&'a str>,
+        R: Rule<E>,
+    {
+        match elements(value)?.as_slice() {
+            [top, bot] => Ok(R::new(
+                group_from_value::<E>(top).map_err(move |_| {
+                    LexprError::Shape(rule::ShapeError::MissingTopGroup(rule::Offending::atom(0)))
+                })?,
+                group_from_value::<E>(bot).map_err(move |_| {
+                    LexprError::Shape(rule::ShapeError::MissingBotGroup(rule::Offending::atom(1)))
+                })?,
+            )),
+            items => Err(LexprError::Shape(rule::ShapeError::BadGroupShape(
+                items.len(),
+            ))),
+        }
+    }
+
+    /// Converts `rules` into a list of `(name rule)` entries.
+    pub fn rules_to_value<E, R>(rules: &RuleSet<E, R>) -> Value
+    where
+        E: Expression,
+        E::Atom: Display,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        Value::list(
+            rules
+                .iter()
+                .map(move |entry| {
+                    Value::list(alloc::vec![
+                        Value::symbol(entry.name.as_str()),
+                        rule_to_value(&entry.rule),
+                    ])
+                })
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Converts a list of `(name rule)` entries into a rule set.
+    pub fn rules_from_value<E, R>(value: &Value) -> Result<RuleSet<E, R>, LexprError>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        for<'a> E::Atom: From<&'a str>,
+        R: Rule<E>,
+    {
+        let mut rules = RuleSet::new();
+        for entry in elements(value)? {
+            match elements(entry)?.as_slice() {
+                [name, rule] => {
+                    let name: String = match name {
+                        Value::Symbol(name) | Value::String(name) => name.as_ref().into(),
+                        _ => return Err(LexprError::InvalidEntry),
+                    };
+                    rules.insert(name, rule_from_value(rule)?);
+                }
+                _ => return Err(LexprError::InvalidEntry),
+            }
+        }
+        Ok(rules)
+    }
+
+    impl<E> From<&rule::Structure<E>> for Value
+    where
+        E: Expression,
+        E::Atom: Display,
+        E::Group: Container<E>,
+    {
+        #[inline]
+        fn from(rule: &rule::Structure<E>) -> Self {
+            rule_to_value(rule)
+        }
+    }
+
+    impl<E> TryFrom<&Value> for rule::Structure<E>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        for<'a> E::Atom: From<&'a str>,
+    {
+        type Error = LexprError;
+
+        #[inline]
+        fn try_from(value: &Value) -> Result<Self, Self::Error> {
+            rule_from_value(value)
+        }
+    }
+
+    impl<E, R> From<&RuleSet<E, R>> for Value
+    where
+        E: Expression,
+        E::Atom: Display,
+        E::Group: Container<E>,
+        R: Rule<E>,
+    {
+        #[inline]
+        fn from(rules: &RuleSet<E, R>) -> Self {
+            rules_to_value(rules)
+        }
+    }
+
+    impl<E, R> TryFrom<&Value> for RuleSet<E, R>
+    where
+        E: Expression,
+        E::Group: Container<E>,
+        for<'a> E::Atom: From<&'a str>,
+        R: Rule<E>,
+    {
+        type Error = LexprError;
+
+        #[inline]
+        fn try_from(value: &Value) -> Result<Self, Self::Error> {
+            rules_from_value(value)
+        }
+    }
+
+    #[cfg(feature = "simple")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "simple")))]
+    impl<A> From<&expr::SimpleExpr<A>> for Value
+    where
+        A: Display,
+    {
+        #[inline]
+        fn from(expr: &expr::SimpleExpr<A>) -> Self {
+            expr_to_value(expr)
+        }
+    }
+
+    #[cfg(feature = "simple")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "simple")))]
+    impl<A> TryFrom<&Value> for expr::SimpleExpr<A>
+    where
+        for<'a> A: From<&'a str>,
+    {
+        type Error = LexprError;
+
+        #[inline]
+        fn try_from(value: &Value) -> Result<Self, Self::Error> {
+            expr_from_value(value)
+        }
+    }
+}
+
+/// LaTeX Rendering Module
+///
+/// Renders rules as inference figures and derivations as proof trees in the format of the
+/// `bussproofs` package.
+#[cfg(feature = "latex")]
+#[cfg_attr(docsrs, doc(cfg(feature = "latex")))]
+pub mod latex {
+    use {
+        super::*,
+        crate::{
+            proof::{self, Derivation, Explanation},
+            rule::RuleId,
+        },
+        core::fmt::{self, Display, Write},
+    };
+
+    /// Writes the text of `atom` as upright math, escaping the LaTeX special characters.
+    pub fn escaped<A>(atom: &A, out: &mut dyn Write) -> fmt::Result
+    where
+        A: Display,
+    {
+        struct Escape<'w>(&'w mut dyn Write);
+
+        impl Write for Escape<'_> {
+            #[inline]
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                for c in s.chars() {
+                    match c {
+                        '\\' => self.0.write_str("\\backslash{}")?,
+                        '{' | '}' | '$' | '&' | '#' | '_' | '%' => {
+                            self.0.write_char('\\')?;
+                            self.0.write_char(c)?;
                         }
-                    } else {
-                        substitution.extend(generated);
+                        '^' => self.0.write_str("\\hat{}")?,
+                        '~' => self.0.write_str("\\sim{}")?,
+                        _ => self.0.write_char(c)?,
                     }
                 }
-                _ => return None,
+                Ok(())
             }
         }
-        if lhs_iter.next().is_some() || rhs_iter.next().is_some() {
-            None
-        } else {
-            // FIXME: we can probably safely remove the `is_identity` checks
-            Some(Directed::pack(
-                !is_flipped,
-                substitution
-                    .into_iter()
-                    .filter(move |s| !s.is_identity())
-                    .collect(),
-            ))
-        }
+
+        out.write_str("\\mathrm{")?;
+        write!(Escape(out), "{}", atom)?;
+        out.write_char('}')
     }
 
-    fn generate_inner<E, S, F>(
-        lhs: ExprRef<'_, E>,
-        rhs: ExprRef<'_, E>,
-        can_substitute: &mut F,
-    ) -> Option<Directed<S>>
+    /// Writes `expr` in math mode, with groups in parentheses.
+    pub fn expr<E, F>(expr: &ExprRef<E>, out: &mut dyn Write, atom: &mut F) -> fmt::Result
     where
         E: Expression,
-        E::Atom: Clone + PartialEq,
-        E::Group: FromIterator<E>,
-        S: FromIterator<Term<E>> + IntoIterator<Item = Term<E>>,
-        F: FnMut(&E::Atom) -> bool,
+        F: FnMut(&E::Atom, &mut dyn Write) -> fmt::Result,
     {
-        match (&lhs, &rhs) {
-            (ExprRef::Atom(lhs_atom), ExprRef::Atom(rhs_atom)) => {
-                generate_from_atoms(*lhs_atom, *rhs_atom, can_substitute)
+        match expr {
+            ExprRef::Atom(a) => atom(a, out),
+            ExprRef::Group(group) => {
+                out.write_char('(')?;
+                for (i, item) in group.iter().enumerate() {
+                    if i > 0 {
+                        out.write_str("\\;")?;
+                    }
+                    self::expr::<E, F>(&item.cases(), out, atom)?;
+                }
+                out.write_char(')')
             }
-            (ExprRef::Atom(lhs), _) if can_substitute(lhs) => Some(Directed::Forward(
-                Term::new((*lhs).clone(), rhs.to_owned()).unit(),
-            )),
-            (_, ExprRef::Atom(rhs)) if can_substitute(rhs) => Some(Directed::Backward(
-                Term::new((*rhs).clone(), lhs.to_owned()).unit(),
-            )),
-            (ExprRef::Group(lhs), ExprRef::Group(rhs)) => {
-                generate_from_groups_inner(lhs, rhs, can_substitute)
+        }
+    }
+
+    /// Writes the elements of `group` in math mode separated by `\quad`, or `\cdot` if the
+    /// group is empty.
+    fn side<E, F>(group: &GroupRef<E>, out: &mut dyn Write, atom: &mut F) -> fmt::Result
+    where
+        E: Expression,
+        F: FnMut(&E::Atom, &mut dyn Write) -> fmt::Result,
+    {
+        let mut empty = true;
+        for (i, item) in group.iter().enumerate() {
+            if i > 0 {
+                out.write_str(" \\quad ")?;
             }
-            _ => None,
+            expr::<E, F>(&item.cases(), out, atom)?;
+            empty = false;
         }
+        if empty {
+            out.write_str("\\cdot")?;
+        }
+        Ok(())
     }
 
-    /// Generalizes a set of expressions to a single expression and substitutions.
-    #[inline]
-    pub fn generalize<E, F, G, S, C>(
-        exprs: &[E],
-        mut can_substitute: F,
-        mut atom_generator: G,
-    ) -> (E, C)
+    /// Writes `rule` as the inference figure `\frac{top}{bot}` in math mode.
+    pub fn ratio<E, R, F>(rule: &R, out: &mut dyn Write, mut atom: F) -> fmt::Result
     where
         E: Expression,
-        E::Atom: Clone + PartialEq,
-        E::Group: FromIterator<E> + IntoIterator<Item = E>,
-        F: FnMut(&E::Atom) -> bool,
-        G: FnMut() -> E::Atom,
-        S: Substitution<E>,
-        C: FromIterator<S>,
+        E::Group: Container<E>,
+        R: Rule<E>,
+        F: FnMut(&E::Atom, &mut dyn Write) -> fmt::Result,
     {
-        generalize_inner(exprs, &mut can_substitute, &mut atom_generator)
+        out.write_str("\\frac{")?;
+        side::<E, F>(&rule.top(), out, &mut atom)?;
+        out.write_str("}{")?;
+        side::<E, F>(&rule.bot(), out, &mut atom)?;
+        out.write_char('}')
     }
 
-    #[inline]
-    fn generalize_to_atom<E, S, C>(target: &E::Atom, exprs: &[E]) -> C
+    /// Writes the `bussproofs` commands of `explanation`, labelling the inferences with
+    /// `name`.
+    fn tree<E, F, N>(
+        explanation: &Explanation<E>,
+        out: &mut dyn Write,
+        atom: &mut F,
+        name: &mut N,
+    ) -> fmt::Result
     where
         E: Expression,
-        E::Atom: Clone + PartialEq,
-        E::Group: FromIterator<E> + IntoIterator<Item = E>,
-        S: Substitution<E>,
-        C: FromIterator<S>,
+        F: FnMut(&E::Atom, &mut dyn Write) -> fmt::Result,
+        N: FnMut(RuleId, &mut dyn Write) -> fmt::Result,
     {
-        exprs
-            .iter()
-            .map(move |expr| match expr.atom_ref() {
-                Some(atom) if target == atom => S::empty(),
-                _ => S::unit(target.clone(), (*expr).to_owned()),
-            })
-            .collect()
+        let inference = match &explanation.cause {
+            Some(cause) => {
+                for premise in &cause.premises {
+                    tree(premise, out, atom, name)?;
+                }
+                if cause.premises.is_empty() {
+                    out.write_str("\\AxiomC{}\n")?;
+                }
+                out.write_str("\\RightLabel{\\scriptsize ")?;
+                name(cause.rule, out)?;
+                out.write_str("}\n")?;
+                match cause.premises.len() {
+                    0 | 1 => "UnaryInfC",
+                    2 => "BinaryInfC",
+                    3 => "TrinaryInfC",
+                    4 => "QuaternaryInfC",
+                    5 => "QuinaryInfC",
+                    _ => return Err(fmt::Error),
+                }
+            }
+            _ => "AxiomC",
+        };
+        write!(out, "\\{}{{$", inference)?;
+        expr::<E, F>(&explanation.fact.cases(), out, atom)?;
+        out.write_str("$}\n")
     }
 
-    fn transpose_substitutions<E, S>(
-        expr_count: usize,
-        mut substitutions: Vec<Vec<S>>,
-    ) -> impl Iterator<Item = S>
+    /// Writes `explanation` as a `prooftree` environment, labelling the inferences with
+    /// `name`.
+    ///
+    /// Steps with more than five premises are not supported by `bussproofs` and make the
+    /// rendering fail.
+    pub fn explanation<E, F, N>(
+        explanation: &Explanation<E>,
+        out: &mut dyn Write,
+        mut atom: F,
+        mut name: N,
+    ) -> fmt::Result
     where
         E: Expression,
-        E::Atom: PartialEq,
-        E::Group: FromIterator<E> + IntoIterator<Item = E>,
-        S: Substitution<E>,
+        F: FnMut(&E::Atom, &mut dyn Write) -> fmt::Result,
+        N: FnMut(RuleId, &mut dyn Write) -> fmt::Result,
     {
-        let mut result = Vec::with_capacity(expr_count);
-        for _ in 0..expr_count {
-            result.push(S::from(
-                substitutions
-                    .iter_mut()
-                    .map(move |s| {
-                        let mut s = s.pop().unwrap().structure();
-                        s.dedup(); // FIXME: is this correct?
-                        s
-                    })
-                    .flatten()
-                    .collect(),
-            ));
-        }
-        result.into_iter().rev()
+        out.write_str("\\begin{prooftree}\n")?;
+        tree(explanation, out, &mut atom, &mut name)?;
+        out.write_str("\\end{prooftree}\n")
     }
 
-    fn generalize_inner<E, F, G, S, C>(
-        exprs: &[E],
-        can_substitute: &mut F,
-        atom_generator: &mut G,
-    ) -> (E, C)
+    /// Writes the proof tree of `conclusion` in `derivation`, as [explained](proof::explain),
+    /// as a `prooftree` environment, labelling the inferences with `name`. A conclusion which
+    /// no step produces is written as an axiom.
+    pub fn derivation<E, F, N>(
+        derivation: &Derivation<E>,
+        conclusion: &E,
+        out: &mut dyn Write,
+        mut atom: F,
+        name: N,
+    ) -> fmt::Result
     where
         E: Expression,
-        E::Atom: Clone + PartialEq,
-        E::Group: FromIterator<E> + IntoIterator<Item = E>,
-        F: FnMut(&E::Atom) -> bool,
-        G: FnMut() -> E::Atom,
-        S: Substitution<E>,
-        C: FromIterator<S>,
+        E::Atom: PartialEq,
+        F: FnMut(&E::Atom, &mut dyn Write) -> fmt::Result,
+        N: FnMut(RuleId, &mut dyn Write) -> fmt::Result,
     {
-        match exprs.len() {
-            0 => (E::empty(), C::from_iter(None)),
-            1 => (exprs[0].to_owned(), C::from_iter(Some(S::empty()))),
-            expr_count => {
-                match exprs.iter().find_map(move |e| e.atom()) {
-                    Some(atom) if can_substitute(atom) => {
-                        return (E::from_atom(atom.clone()), generalize_to_atom(atom, exprs));
-                    }
-                    None => {
-                        if let Some(group_size) = (&exprs[0]).unwrap_group().len() {
-                            let groups = exprs.iter().map(move |e| e.unwrap_group());
-                            if groups.clone().all(move |g| g.len() == Some(group_size)) {
-                                let (expr_base, inner_substitutions) = (0..group_size)
-                                    .into_iter()
-                                    .map(move |i| {
-                                        // FIXME: remove the call to `.to_owned` if possible
-                                        generalize_inner::<_, _, _, _, Vec<S>>(
-                                            &groups
-                                                .clone()
-                                                .map(move |g| g.get(i).unwrap().to_owned())
-                                                .collect::<Vec<_>>(),
-                                            can_substitute,
-                                            atom_generator,
-                                        )
-                                    })
-                                    .unzip::<_, _, Vec<_>, Vec<_>>();
-                                return (
-                                    E::from_group(expr_base.into_iter().collect()),
-                                    transpose_substitutions(expr_count, inner_substitutions)
-                                        .collect(),
-                                );
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-                let target = atom_generator();
-                let substitutions = generalize_to_atom(&target, exprs);
-                (E::from_atom(target), substitutions)
+        match proof::explain(derivation, conclusion) {
+            Some(tree) => explanation(&tree, out, atom, name),
+            _ => {
+                out.write_str("\\begin{prooftree}\n\\AxiomC{$")?;
+                expr::<E, F>(&conclusion.cases(), out, &mut atom)?;
+                out.write_str("$}\n\\end{prooftree}\n")
             }
         }
     }
 }
 
-/// Composition Module
-#[cfg(feature = "composition")]
-#[cfg_attr(docsrs, doc(cfg(feature = "composition")))]
-pub mod composition {
+/// Graph Interop Module
+///
+/// Exports of the rule dependency graph, the fact graph of a derivation and the incidence
+/// structure of the rule hypergraph as [`DiGraph`]s, so that the algorithms of the `petgraph`
+/// crate can run on them.
+#[cfg(all(feature = "alloc", feature = "petgraph"))]
+#[cfg_attr(docsrs, doc(cfg(all(feature = "alloc", feature = "petgraph"))))]
+pub mod petgraph {
     use {
         super::*,
+        crate::{
+            proof::Derivation,
+            rule::{DependencyGraph, Hypergraph, RuleId},
+        },
+        ::petgraph::graph::{DiGraph, NodeIndex},
         alloc::vec::Vec,
-        core::{iter, slice},
     };
 
-    /// Evaluation Shape
-    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-    pub enum EvaluationShape<R, C> {
-        /// Rule Shape
-        Rule(R),
-
-        /// Composition Shape
-        Composition(C),
+    /// Converts the dependency `graph` into a [`DiGraph`] whose nodes are weighted by rule
+    /// identifiers, in the order of [`DependencyGraph::nodes`].
+    pub fn dependency_graph(graph: &DependencyGraph) -> DiGraph<RuleId, ()> {
+        let mut result = DiGraph::with_capacity(graph.len(), 0);
+        for id in graph.nodes() {
+            result.add_node(*id);
+        }
+        for (source, target) in graph.edges() {
+            if let (Ok(s), Ok(t)) = (
+                graph.nodes().binary_search(&source),
+                graph.nodes().binary_search(&target),
+            ) {
+                result.add_edge(NodeIndex::new(s), NodeIndex::new(t), ());
+            }
+        }
+        result
     }
 
-    /// Stored Rule Reference
-    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-    pub enum StoredRuleRef<'e, R, K> {
-        /// Rule
-        Rule(&'e R),
+    /// Derivation Fact
+    #[derive(Debug)]
+    pub struct Fact<'d, E> {
+        /// Fact Expression
+        pub expr: &'d E,
 
-        /// Key
-        Key(&'e K),
+        /// Index of the step which produced the fact or `None` if it is a given fact
+        pub step: Option<usize>,
     }
 
-    impl<'e, R, K> StoredRuleRef<'e, R, K> {
-        /// Returns an owned [`StoredRule`] value.
+    impl<'d, E> Clone for Fact<'d, E> {
         #[inline]
-        pub fn to_owned(&self) -> StoredRule<R, K>
-        where
-            R: Clone,
-            K: Clone,
-        {
-            match self {
-                Self::Rule(rule) => StoredRule::Rule(Clone::clone(rule)),
-                Self::Key(key) => StoredRule::Key(Clone::clone(key)),
+        fn clone(&self) -> Self {
+            *self
+        }
+    }
+
+    impl<'d, E> Copy for Fact<'d, E> {}
+
+    /// Converts `derivation` into a [`DiGraph`] with one node per fact and one edge, weighted
+    /// by the index of the step, from every consumed fact to every fact produced by the same
+    /// step.
+    ///
+    /// Consumed facts which no earlier step produced are the given facts of the derivation.
+    pub fn derivation_graph<E>(derivation: &Derivation<E>) -> DiGraph<Fact<'_, E>, usize>
+    where
+        E: Expression,
+        E::Atom: PartialEq,
+    {
+        let mut result = DiGraph::new();
+        let mut live = Vec::<(NodeIndex, &E)>::new();
+        for (index, step) in derivation.steps().iter().enumerate() {
+            let sources = step
+                .consumed
+                .iter()
+                .map(|e| match live.iter().rposition(move |(_, l)| E::eq(l, e)) {
+                    Some(i) => live.remove(i).0,
+                    _ => result.add_node(Fact {
+                        expr: e,
+                        step: None,
+                    }),
+                })
+                .collect::<Vec<_>>();
+            for e in &step.produced {
+                let target = result.add_node(Fact {
+                    expr: e,
+                    step: Some(index),
+                });
+                for source in &sources {
+                    result.add_edge(*source, target, index);
+                }
+                live.push((target, e));
             }
         }
+        result
     }
 
-    impl<'e, R, K> From<&'e StoredRule<R, K>> for StoredRuleRef<'e, R, K> {
-        #[inline]
-        fn from(stored: &'e StoredRule<R, K>) -> Self {
-            stored.as_ref()
+    /// Hypergraph Incidence Node
+    #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+    pub enum Incidence {
+        /// Index of an item of the hypergraph
+        Item(usize),
+
+        /// Identifier of a rule of the hypergraph
+        Rule(RuleId),
+    }
+
+    /// Converts `hypergraph` into its bipartite incidence [`DiGraph`], with an edge from every
+    /// item consumed by a rule to the rule and from the rule to every item it produces,
+    /// weighted by multiplicity.
+    ///
+    /// The node with index `i` is the item with index `i`, and the rules follow the items in
+    /// the order of [`Hypergraph::edges`].
+    pub fn incidence_graph<E>(hypergraph: &Hypergraph<E>) -> DiGraph<Incidence, usize>
+    where
+        E: Expression,
+    {
+        let mut result = DiGraph::new();
+        for i in 0..hypergraph.items().len() {
+            result.add_node(Incidence::Item(i));
+        }
+        for edge in hypergraph.edges() {
+            let rule = result.add_node(Incidence::Rule(edge.rule));
+            for (item, count) in &edge.sources {
+                result.add_edge(NodeIndex::new(*item), rule, *count);
+            }
+            for (item, count) in &edge.targets {
+                result.add_edge(rule, NodeIndex::new(*item), *count);
+            }
         }
+        result
     }
+}
 
-    /// Stored Rule
+/// Rational Number Interop Module
+///
+/// Conversions between [`Ratio<u64>`] and rules whose top and bottom are the prime factors of
+/// the numerator and the denominator, with multiplicity. Under these conversions
+/// [composition](rule::compose) of rules corresponds to multiplication of rational numbers.
+/// Zero is represented by the single factor `0` on top.
+#[cfg(all(feature = "alloc", feature = "num-rational"))]
+#[cfg_attr(docsrs, doc(cfg(all(feature = "alloc", feature = "num-rational"))))]
+pub mod rational {
+    use {super::*, alloc::vec::Vec, core::fmt, num_rational::Ratio};
+
+    /// Rational Conversion Error
     #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-    pub enum StoredRule<R, K> {
-        /// Rule
-        Rule(R),
+    pub enum RationalError {
+        /// An element of the rule is not an atom.
+        NotAtom,
 
-        /// Key
-        Key(K),
+        /// The bottom of the rule multiplies to zero.
+        ZeroDenominator,
+
+        /// A product does not fit in a `u64`.
+        Overflow,
     }
 
-    impl<R, K> StoredRule<R, K> {
-        /// Returns the [`&StoredRule`](StoredRule) as a [`StoredRuleRef`].
+    impl fmt::Display for RationalError {
         #[inline]
-        pub fn as_ref(&self) -> StoredRuleRef<'_, R, K> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
             match self {
-                Self::Rule(rule) => StoredRuleRef::Rule(rule),
-                Self::Key(key) => StoredRuleRef::Key(key),
+                Self::NotAtom => f.write_str("rule element is not an atom"),
+                Self::ZeroDenominator => f.write_str("rule has a zero denominator"),
+                Self::Overflow => f.write_str("rule product overflows"),
             }
         }
     }
 
-    impl<E, R, K> From<StoredRule<R, K>> for Expr<E>
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for RationalError {}
+
+    /// Returns the prime factors of `n` in increasing order, with multiplicity.
+    ///
+    /// The factors of `1` are empty and the factors of `0` are `[0]`.
+    pub fn prime_factors(mut n: u64) -> Vec<u64> {
+        let mut factors = Vec::new();
+        if n == 0 {
+            factors.push(0);
+            return factors;
+        }
+        while n % 2 == 0 {
+            factors.push(2);
+            n /= 2;
+        }
+        let mut p = 3;
+        while p <= n / p {
+            while n % p == 0 {
+                factors.push(p);
+                n /= p;
+            }
+            p += 2;
+        }
+        if n > 1 {
+            factors.push(n);
+        }
+        factors
+    }
+
+    /// Converts `ratio` into the rule of the prime factors of its numerator over the prime
+    /// factors of its denominator.
+    #[inline]
+    pub fn to_rule<E, R>(ratio: &Ratio<u64>) -> R
     where
         E: Expression,
+        E::Atom: From<u64>,
         E::Group: Container<E>,
         R: Rule<E>,
-        K: Into<E::Atom>,
     {
-        #[inline]
-        fn from(rule: StoredRule<R, K>) -> Self {
-            match rule {
-                StoredRule::Rule(rule) => rule.into().into(),
-                StoredRule::Key(key) => Self::Atom(key.into()),
-            }
-        }
+        let side = move |n| {
+            prime_factors(n)
+                .into_iter()
+                .map(move |p| E::from_atom(p.into()))
+                .collect()
+        };
+        R::new(side(*ratio.numer()), side(*ratio.denom()))
+    }
+
+    /// Returns the product of the atoms of `group`.
+    #[inline]
+    fn product<E>(group: &GroupRef<E>) -> Result<u64, RationalError>
+    where
+        E: Expression,
+        E::Atom: Clone + Into<u64>,
+    {
+        group
+            .iter()
+            .try_fold(1u64, move |n, item| match item.cases() {
+                ExprRef::Atom(atom) => n
+                    .checked_mul(atom.clone().into())
+                    .ok_or(RationalError::Overflow),
+                _ => Err(RationalError::NotAtom),
+            })
     }
 
-    impl<E, R, K> TryFrom<Expr<E>> for StoredRule<R, K>
+    /// Converts `rule` into the ratio of the product of its top over the product of its bottom,
+    /// in lowest terms.
+    #[inline]
+    pub fn from_rule<E, R>(rule: &R) -> Result<Ratio<u64>, RationalError>
     where
         E: Expression,
+        E::Atom: Clone + Into<u64>,
         E::Group: Container<E>,
         R: Rule<E>,
-        K: TryFrom<E::Atom>,
     {
-        type Error = ();
-
-        #[inline]
-        fn try_from(expr: Expr<E>) -> Result<Self, Self::Error> {
-            if expr.is_atom() {
-                match expr.unwrap_atom().try_into() {
-                    Ok(key) => Ok(Self::Key(key)),
-                    _ => Err(()),
-                }
-            } else {
-                match R::try_from(E::from_expr(expr)) {
-                    Ok(rule) => Ok(Self::Rule(rule)),
-                    _ => Err(()),
-                }
-            }
+        let numer = product::<E>(&rule.top())?;
+        match product::<E>(&rule.bot())? {
+            0 => Err(RationalError::ZeroDenominator),
+            denom => Ok(Ratio::new(numer, denom)),
         }
     }
+}
 
-    /// Composition Term Reference Type
-    #[derive(Debug, Eq, PartialEq)]
-    pub struct TermRef<'e, R, S, K> {
-        /// [`StoredRule`] Reference
-        pub rule: StoredRuleRef<'e, R, K>,
-
-        /// [`Substitution`] Reference
-        pub subst: &'e S,
-    }
+/// Fixed-Capacity Module
+///
+/// Buffers with a capacity bound and the [`ArrayRatio`], whose composition runs over
+/// fixed-capacity arrays without allocating and reports overflows as [`CapacityError`]s.
+pub mod fixed {
+    use {
+        super::*,
+        core::{fmt, iter::Flatten},
+    };
 
-    impl<'e, R, S, K> TermRef<'e, R, S, K> {
-        /// Builds a new composition term reference.
-        #[inline]
-        pub fn new(rule: StoredRuleRef<'e, R, K>, subst: &'e S) -> Self {
-            Self { rule, subst }
-        }
+    #[cfg(feature = "alloc")]
+    use alloc::vec::Vec;
 
-        /// Returns an owned [`Term`].
-        #[inline]
-        pub fn to_owned(&self) -> Term<R, S, K>
-        where
-            R: Clone,
-            S: Clone,
-            K: Clone,
-        {
-            Term::new(self.rule.to_owned(), self.subst.clone())
-        }
+    /// Capacity Error
+    ///
+    /// A buffer has no room left for another element.
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct CapacityError {
+        /// Capacity of the buffer
+        pub capacity: usize,
     }
 
-    impl<'e, R, S, K> From<&'e Term<R, S, K>> for TermRef<'e, R, S, K> {
+    impl fmt::Display for CapacityError {
         #[inline]
-        fn from(term: &'e Term<R, S, K>) -> Self {
-            term.as_ref()
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "capacity of {} elements exceeded", self.capacity)
         }
     }
 
-    /// Composition Term Type
-    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-    pub struct Term<R, S, K> {
-        /// [`StoredRule`] Object
-        pub rule: StoredRule<R, K>,
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for CapacityError {}
 
-        /// [`Substitution`] Object
-        pub subst: S,
-    }
+    /// Capacity-Limited Buffer Trait
+    ///
+    /// A buffer read as a multiset, so that removing an element may reorder the others.
+    pub trait Buffer<T>: Sized {
+        /// Builds an empty buffer.
+        fn empty() -> Self;
 
-    impl<R, S, K> Term<R, S, K> {
-        /// Builds a new composition term.
-        #[inline]
-        pub fn new(rule: StoredRule<R, K>, subst: S) -> Self {
-            Self { rule, subst }
-        }
+        /// Returns the number of elements of the buffer.
+        fn len(&self) -> usize;
 
-        /// Returns the [`&Term`](Term) as a [`TermRef`].
+        /// Returns `true` if the buffer has no elements.
         #[inline]
-        pub fn as_ref(&self) -> TermRef<'_, R, S, K> {
-            TermRef::new(self.rule.as_ref(), &self.subst)
+        fn is_empty(&self) -> bool {
+            self.len() == 0
         }
 
+        /// Returns the maximum number of elements of the buffer.
+        fn capacity(&self) -> usize;
+
+        /// Returns the element at `index`.
+        fn get(&self, index: usize) -> Option<&T>;
+
+        /// Appends `item` to the buffer, failing if the buffer is full.
+        fn try_push(&mut self, item: T) -> Result<(), CapacityError>;
+
+        /// Removes the element at `index`, replacing it by the last element.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `index` is out of bounds.
+        fn swap_remove(&mut self, index: usize) -> T;
+
+        /// Appends the elements of `iter` to the buffer, failing at the first element which does
+        /// not fit.
         #[inline]
-        fn iter<E>(self) -> impl Iterator<Item = E>
+        fn try_extend<I>(&mut self, iter: I) -> Result<(), CapacityError>
         where
-            E: Expression,
-            E::Group: Container<E>,
-            R: Rule<E>,
-            S: Substitution<E>,
-            K: Into<E::Atom>,
+            I: IntoIterator<Item = T>,
         {
-            util::two_item_iter(E::from_expr(Into::into(self.rule)), self.subst.into())
+            iter.into_iter()
+                .try_for_each(move |item| self.try_push(item))
         }
     }
 
-    impl<E, R, S, K> TryFrom<(E, E)> for Term<R, S, K> {
-        type Error = ();
-
+    #[cfg(feature = "alloc")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+    impl<T> Buffer<T> for Vec<T> {
         #[inline]
-        fn try_from((rule, subst): (E, E)) -> Result<Self, Self::Error> {
-            // TODO: Ok(Self::new(eval.try_into()?, subst.try_into()?))
-            let _ = (rule, subst);
-            todo!()
+        fn empty() -> Self {
+            Vec::new()
         }
-    }
-
-    /// Composition Trait
-    pub trait Composition<E, R, S, K = <E as Expression>::Atom, V = Vec<Term<R, S, K>>>:
-        super::Structure<E, Structure<E, R, S, K, V>>
-    where
-        E: Expression,
-        E::Group: Container<E>,
-        R: Rule<E>,
-        S: Substitution<E>,
-        K: Into<E::Atom>,
-        V: Container<Term<R, S, K>>,
-    {
-        /// Iterator over elements by reference.
-        type Iter<'e>: Iterator<Item = TermRef<'e, R, S, K>>
-        where
-            R: 'e,
-            S: 'e,
-            K: 'e;
 
-        /// Returns an iterator over the elements by reference.
-        fn iter(&self) -> Self::Iter<'_>;
+        #[inline]
+        fn len(&self) -> usize {
+            Vec::len(self)
+        }
 
-        /// Builds an empty `Composition`.
         #[inline]
-        fn empty() -> Self
-        where
-            Self: Sized,
-        {
-            Self::from(Structure::from_iter(None))
+        fn capacity(&self) -> usize {
+            usize::MAX
         }
 
-        /// Checks if the `Composition` is empty.
         #[inline]
-        fn is_empty(&self) -> bool {
-            self.iter().next().is_none()
+        fn get(&self, index: usize) -> Option<&T> {
+            self.as_slice().get(index)
         }
 
-        /// Evaluates the composition.
         #[inline]
-        fn flat_resolve<F>(self, resolver: F) -> Result<R, E::Atom>
-        where
-            Self: Sized,
-            F: FnMut(&K) -> Option<R>,
-        {
-            let _ = resolver;
-            todo!()
+        fn try_push(&mut self, item: T) -> Result<(), CapacityError> {
+            self.push(item);
+            Ok(())
         }
 
-        /// Evaluates the composition.
         #[inline]
-        fn resolve<F>(self, resolver: F) -> Result<R, E::Atom>
-        where
-            Self: Sized,
-            F: FnMut(&K) -> Option<EvaluationShape<R, Self>>,
-        {
-            let _ = resolver;
-            todo!()
+        fn swap_remove(&mut self, index: usize) -> T {
+            Vec::swap_remove(self, index)
         }
     }
 
-    /// Composition Structure Type
-    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-    pub struct Structure<E, R, S, K = <E as Expression>::Atom, V = Vec<Term<R, S, K>>>
-    where
-        E: Expression,
-        E::Group: Container<E>,
-        R: Rule<E>,
-        S: Substitution<E>,
-        V: Container<Term<R, S, K>>,
-    {
-        /// Composition terms
-        pub terms: V,
+    /// Array Buffer
+    ///
+    /// A [`Buffer`] of at most `N` elements stored inline.
+    #[derive(Clone, Debug)]
+    pub struct ArrayBuffer<T, const N: usize> {
+        /// Slots, the first `len` of which are occupied
+        items: [Option<T>; N],
 
-        /// Phantom Marker
-        __: PhantomData<(E, R, S, K)>,
+        /// Number of elements
+        len: usize,
     }
 
-    impl<E, R, S, K, V> Structure<E, R, S, K, V>
-    where
-        E: Expression,
-        E::Group: Container<E>,
-        R: Rule<E>,
-        S: Substitution<E>,
-        V: Container<Term<R, S, K>>,
-    {
-        /// Builds a new [`Structure`] from a [`Term`] container.
+    impl<T, const N: usize> ArrayBuffer<T, N> {
+        /// Builds an empty [`ArrayBuffer`].
         #[inline]
-        pub fn new(terms: V) -> Self {
+        pub fn new() -> Self {
             Self {
-                terms,
-                __: PhantomData,
+                items: [(); N].map(|_| None),
+                len: 0,
             }
         }
-    }
 
-    impl<E, R, S, K, V> Default for Structure<E, R, S, K, V>
-    where
-        E: Expression,
-        E::Group: Container<E>,
-        R: Rule<E>,
-        S: Substitution<E>,
-        V: Container<Term<R, S, K>>,
-    {
+        /// Builds an [`ArrayBuffer`] from the elements of `iter`, failing if there are more
+        /// than `N` of them.
         #[inline]
-        fn default() -> Self {
-            Self::new(V::from_iter(None))
+        pub fn try_from_iter<I>(iter: I) -> Result<Self, CapacityError>
+        where
+            I: IntoIterator<Item = T>,
+        {
+            let mut buffer = Self::new();
+            buffer.try_extend(iter)?;
+            Ok(buffer)
         }
-    }
-
-    impl<E, R, S, K, V> IntoIterator for Structure<E, R, S, K, V>
-    where
-        E: Expression,
-        E::Group: Container<E>,
-        R: Rule<E>,
-        S: Substitution<E>,
-        V: Container<Term<R, S, K>>,
-    {
-        type Item = Term<R, S, K>;
-
-        type IntoIter = <V as IntoIterator>::IntoIter;
 
+        /// Returns an iterator over the elements of the buffer.
         #[inline]
-        fn into_iter(self) -> Self::IntoIter {
-            self.terms.into_iter()
+        pub fn iter(&self) -> impl '_ + Iterator<Item = &T> {
+            self.items[..self.len].iter().flatten()
         }
-    }
 
-    impl<E, R, S, K, V> FromIterator<Term<R, S, K>> for Structure<E, R, S, K, V>
-    where
-        E: Expression,
-        E::Group: Container<E>,
-        R: Rule<E>,
-        S: Substitution<E>,
-        V: Container<Term<R, S, K>>,
-    {
+        /// Removes the last element of the buffer.
         #[inline]
-        fn from_iter<I>(iter: I) -> Self
-        where
-            I: IntoIterator<Item = Term<R, S, K>>,
-        {
-            Self::new(V::from_iter(iter))
+        pub fn pop(&mut self) -> Option<T> {
+            self.len = self.len.checked_sub(1)?;
+            self.items[self.len].take()
         }
     }
 
-    impl<E, R, S, K, V> From<Structure<E, R, S, K, V>> for Expr<E>
-    where
-        E: Expression,
-        E::Group: Container<E>,
-        R: Rule<E>,
-        S: Substitution<E>,
-        K: Into<E::Atom>,
-        V: Container<Term<R, S, K>>,
-    {
+    impl<T, const N: usize> Default for ArrayBuffer<T, N> {
         #[inline]
-        fn from(structure: Structure<E, R, S, K, V>) -> Self {
-            Self::Group(structure.into_iter().map(Term::iter).flatten().collect())
+        fn default() -> Self {
+            Self::new()
         }
     }
 
-    /// Composition Shape Error Type
-    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-    pub enum ShapeError {}
-
-    /// Checks if an atom matches the `Composition` pattern.
-    #[inline]
-    pub fn matches_atom<E>(atom: &E::Atom) -> Result<(), ShapeError>
-    where
-        E: Expression,
-    {
-        // FIXME: implement
-        let _ = atom;
-        todo!()
-    }
-
-    /// Checks if a group matches the `Composition` pattern.
-    #[inline]
-    pub fn matches_group<E>(group: &GroupRef<E>) -> Result<(), ShapeError>
-    where
-        E: Expression,
-    {
-        // FIXME: implement
-        let _ = group;
-        todo!()
-    }
+    impl<T, const N: usize> Buffer<T> for ArrayBuffer<T, N> {
+        #[inline]
+        fn empty() -> Self {
+            Self::new()
+        }
 
-    /// Checks if an expression matches the `Composition` pattern.
-    #[inline]
-    pub fn matches<E>(expr: &ExprRef<E>) -> Result<(), ShapeError>
-    where
-        E: Expression,
-    {
-        match expr {
-            ExprRef::Atom(atom) => matches_atom::<E>(atom),
-            ExprRef::Group(group) => matches_group::<E>(group),
+        #[inline]
+        fn len(&self) -> usize {
+            self.len
         }
-    }
 
-    impl<E, R, S, K, V> Matcher<E> for Structure<E, R, S, K, V>
-    where
-        E: Expression,
-        E::Group: Container<E>,
-        R: Rule<E>,
-        S: Substitution<E>,
-        V: Container<Term<R, S, K>>,
-    {
-        type Error = ShapeError;
+        #[inline]
+        fn capacity(&self) -> usize {
+            N
+        }
 
         #[inline]
-        fn matches_atom(atom: &E::Atom) -> Result<(), Self::Error> {
-            matches_atom::<E>(atom)
+        fn get(&self, index: usize) -> Option<&T> {
+            self.items[..self.len].get(index)?.as_ref()
         }
 
         #[inline]
-        fn matches_group(group: GroupRef<E>) -> Result<(), Self::Error> {
-            matches_group::<E>(&group)
+        fn try_push(&mut self, item: T) -> Result<(), CapacityError> {
+            match self.items.get_mut(self.len) {
+                Some(slot) => {
+                    *slot = Some(item);
+                    self.len += 1;
+                    Ok(())
+                }
+                _ => Err(CapacityError { capacity: N }),
+            }
+        }
+
+        #[inline]
+        fn swap_remove(&mut self, index: usize) -> T {
+            assert!(index < self.len, "index out of bounds");
+            self.items.swap(index, self.len - 1);
+            self.pop().expect("the buffer is not empty")
         }
     }
 
-    impl<E, R, S, K, V> TryFrom<Expr<E>> for Structure<E, R, S, K, V>
-    where
-        E: Expression,
-        E::Group: Container<E>,
-        R: Rule<E>,
-        S: Substitution<E>,
-        V: Container<Term<R, S, K>>,
-    {
-        type Error = <Structure<E, R, S, K, V> as Matcher<E>>::Error;
+    impl<T, const N: usize> IntoIterator for ArrayBuffer<T, N> {
+        type Item = T;
+        type IntoIter = Flatten<core::array::IntoIter<Option<T>, N>>;
 
         #[inline]
-        fn try_from(expr: Expr<E>) -> Result<Self, Self::Error> {
-            // FIXME: implement
-            let _ = expr;
-            todo!()
+        fn into_iter(self) -> Self::IntoIter {
+            IntoIterator::into_iter(self.items).flatten()
         }
     }
 
-    impl<E, R, S, K, V> Shape<E> for Structure<E, R, S, K, V>
+    /// Computes the symmetric difference of two multisets stored in [`Buffer`]s in place,
+    /// returning the unmatched elements of `left` and of `right`.
+    pub fn symmetric_difference_by<T, B, F>(mut left: B, mut right: B, mut eq: F) -> (B, B)
     where
-        E: Expression,
-        E::Group: Container<E>,
-        R: Rule<E>,
-        S: Substitution<E>,
-        K: Into<E::Atom>,
-        V: Container<Term<R, S, K>>,
+        B: Buffer<T>,
+        F: FnMut(&T, &T) -> bool,
     {
+        let mut index = 0;
+        while let Some(l) = left.get(index) {
+            match (0..right.len()).find(|i| right.get(*i).map_or(false, |r| eq(l, r))) {
+                Some(matched) => {
+                    left.swap_remove(index);
+                    right.swap_remove(matched);
+                }
+                _ => index += 1,
+            }
+        }
+        (left, right)
     }
 
-    /// Structure Vector Reference Iterator
-    #[derive(Clone)]
-    pub struct StructureVecIter<'s, R, S, K>(slice::Iter<'s, Term<R, S, K>>);
+    /// Composes the `(top, bot)` pairs `top` and `bot` using the ratio monoid multiplication
+    /// algorithm, failing if a side of the composite does not fit into its buffer.
+    ///
+    /// [`rule::pair_compose_by`] runs this algorithm over [`Vec`]s.
+    pub fn pair_compose_by<T, B, F>(
+        top: (B, B),
+        bot: (B, B),
+        eq: F,
+    ) -> Result<(B, B), CapacityError>
+    where
+        B: Buffer<T> + IntoIterator<Item = T>,
+        F: FnMut(&T, &T) -> bool,
+    {
+        let (top_top, top_bot) = top;
+        let (bot_top, bot_bot) = bot;
+        let (mut lower, mut upper) = symmetric_difference_by(top_bot, bot_top, eq);
+        upper.try_extend(top_top)?;
+        lower.try_extend(bot_bot)?;
+        Ok((upper, lower))
+    }
 
-    impl<R, S, K> AsRef<[Term<R, S, K>]> for StructureVecIter<'_, R, S, K> {
+    /// Fixed-Capacity Ratio
+    ///
+    /// A ratio whose top and bottom are [`ArrayBuffer`]s of at most `N` elements.
+    #[derive(Clone, Debug)]
+    pub struct ArrayRatio<T, const N: usize> {
+        /// Top of the ratio
+        top: ArrayBuffer<T, N>,
+
+        /// Bottom of the ratio
+        bot: ArrayBuffer<T, N>,
+    }
+
+    impl<T, const N: usize> ArrayRatio<T, N> {
+        /// Builds the empty [`ArrayRatio`], the unit of composition.
         #[inline]
-        fn as_ref(&self) -> &[Term<R, S, K>] {
-            self.0.as_ref()
+        pub fn new() -> Self {
+            Self {
+                top: ArrayBuffer::new(),
+                bot: ArrayBuffer::new(),
+            }
         }
-    }
 
-    impl<'s, R, S, K> DoubleEndedIterator for StructureVecIter<'s, R, S, K> {
+        /// Builds an [`ArrayRatio`] from its `top` and `bot`, failing if either has more than
+        /// `N` elements.
         #[inline]
-        fn next_back(&mut self) -> Option<TermRef<'s, R, S, K>> {
-            self.0.next_back().map(Term::as_ref)
+        pub fn try_new<I, J>(top: I, bot: J) -> Result<Self, CapacityError>
+        where
+            I: IntoIterator<Item = T>,
+            J: IntoIterator<Item = T>,
+        {
+            Ok(Self {
+                top: ArrayBuffer::try_from_iter(top)?,
+                bot: ArrayBuffer::try_from_iter(bot)?,
+            })
         }
-    }
 
-    impl<R, S, K> ExactSizeIterator for StructureVecIter<'_, R, S, K> {}
+        /// Builds an [`ArrayRatio`] from a rule, failing if either side has more than `N`
+        /// elements.
+        #[cfg(feature = "alloc")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+        #[inline]
+        pub fn try_from_rule<R>(rule: R) -> Result<Self, CapacityError>
+        where
+            T: Expression,
+            T::Group: Container<T>,
+            R: Rule<T>,
+        {
+            let structure = rule.structure();
+            Self::try_new(structure.top, structure.bot)
+        }
 
-    impl<R, S, K> iter::FusedIterator for StructureVecIter<'_, R, S, K> {}
+        /// Returns the top of the ratio.
+        #[inline]
+        pub fn top(&self) -> &ArrayBuffer<T, N> {
+            &self.top
+        }
 
-    impl<'s, R, S, K> Iterator for StructureVecIter<'s, R, S, K> {
-        type Item = TermRef<'s, R, S, K>;
+        /// Returns the bottom of the ratio.
+        #[inline]
+        pub fn bot(&self) -> &ArrayBuffer<T, N> {
+            &self.bot
+        }
 
+        /// Returns the top and bottom of the ratio.
         #[inline]
-        fn next(&mut self) -> Option<Self::Item> {
-            self.0.next().map(Term::as_ref)
+        pub fn into_parts(self) -> (ArrayBuffer<T, N>, ArrayBuffer<T, N>) {
+            (self.top, self.bot)
         }
 
+        /// Converts the ratio into a rule.
+        #[cfg(feature = "alloc")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
         #[inline]
-        fn size_hint(&self) -> (usize, Option<usize>) {
-            self.0.size_hint()
+        pub fn into_rule<R>(self) -> R
+        where
+            T: Expression,
+            T::Group: Container<T>,
+            R: Rule<T>,
+        {
+            R::new(
+                self.top.into_iter().collect(),
+                self.bot.into_iter().collect(),
+            )
         }
 
+        /// Composes `self` with `other` using the ratio monoid multiplication algorithm,
+        /// failing if a side of the composite has more than `N` elements.
         #[inline]
-        fn count(self) -> usize {
-            self.0.count()
+        pub fn try_compose_by<F>(self, other: Self, eq: F) -> Result<Self, CapacityError>
+        where
+            F: FnMut(&T, &T) -> bool,
+        {
+            let (top, bot) = pair_compose_by(self.into_parts(), other.into_parts(), eq)?;
+            Ok(Self { top, bot })
         }
-    }
 
-    impl<E, R, S, K> Composition<E, R, S, K> for Structure<E, R, S, K>
-    where
-        E: Expression,
-        E::Group: Container<E>,
-        R: Rule<E>,
-        S: Substitution<E>,
-        K: Into<E::Atom>,
-    {
-        type Iter<'e>
+        /// Composes `self` with `other` using [`PartialEq`] to compare elements, see
+        /// [`try_compose_by`](Self::try_compose_by).
+        #[inline]
+        pub fn try_compose(self, other: Self) -> Result<Self, CapacityError>
         where
-            R: 'e,
-            S: 'e,
-            K: 'e,
-        = StructureVecIter<'e, R, S, K>;
+            T: PartialEq,
+        {
+            self.try_compose_by(other, PartialEq::eq)
+        }
 
+        /// Folds the ratios of `ratios` using [`try_compose_by`](Self::try_compose_by).
         #[inline]
-        fn iter(&self) -> Self::Iter<'_> {
-            StructureVecIter(self.terms.iter())
+        pub fn try_compose_all_by<I, F>(ratios: I, mut eq: F) -> Result<Self, CapacityError>
+        where
+            I: IntoIterator<Item = Self>,
+            F: FnMut(&T, &T) -> bool,
+        {
+            ratios.into_iter().try_fold(Self::new(), move |acc, ratio| {
+                acc.try_compose_by(ratio, &mut eq)
+            })
+        }
+    }
+
+    impl<T, const N: usize> Default for ArrayRatio<T, N> {
+        #[inline]
+        fn default() -> Self {
+            Self::new()
         }
     }
 }
 
-/// Stored Objects Module
-pub mod stored {
-    use super::*;
+/// Crate Errors
+///
+/// A single error type wrapping the errors of every module, so that applications can handle
+/// the failures of this crate uniformly.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod error {
+    use {
+        super::*,
+        crate::{
+            completion::CompletionError,
+            engine::Violation,
+            fuel::{Exhausted, Exhaustion},
+            linear::LinearError,
+            petri::PetriError,
+            proof::ProofError,
+            rule::text::ParseError,
+            shape::SchemaError,
+            tactic::TacticError,
+        },
+        alloc::string::String,
+        core::fmt::{self, Display},
+    };
 
-    /// Resolvable Trait
-    pub trait Resolvable<K> {
-        /// Resolved Part Type
-        type Part;
+    /// Error Kind
+    ///
+    /// Stable classification of the variants of [`Error`].
+    #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+    pub enum ErrorKind {
+        /// An expression does not have the expected shape.
+        Shape,
 
-        /// Resolved Output Type
-        type Output;
+        /// A text could not be parsed.
+        Parse,
 
-        /// Key Container Type
-        type UnresolvedKeys: IntoIterator<Item = K>;
+        /// A serialized rule set or derivation could not be loaded.
+        Format,
 
-        /// Resolves all keys in the given object and returns the output or the unresolved keys.
-        fn resolve<F>(self, resolver: F) -> Result<Self::Output, Self::UnresolvedKeys>
-        where
-            F: FnMut(&K) -> Option<Self::Part>;
+        /// A derivation is not a valid proof.
+        Proof,
+
+        /// A computation ran out of fuel or was cancelled.
+        Exhausted,
+
+        /// A state invariant was violated.
+        Invariant,
+
+        /// A tactic failed.
+        Tactic,
+
+        /// A resource discipline was broken.
+        Resource,
+
+        /// A rule set is outside the fragment supported by an analysis.
+        Unsupported,
+
+        /// A completion procedure failed.
+        Completion,
     }
 
-    /// Stored Object
-    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-    pub enum Stored<K, V> {
-        /// Key
-        Key(K),
+    /// Crate Error
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    #[non_exhaustive]
+    pub enum Error {
+        /// Rule shape error
+        Shape(rule::ShapeError),
 
-        /// Value
-        Value(V),
+        /// Substitution shape error
+        SubstitutionShape(substitution::ShapeError),
+
+        /// AEP shape error
+        #[cfg(feature = "aep")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "aep")))]
+        AepShape(aep::ShapeError),
+
+        /// Schema error
+        Schema(SchemaError),
+
+        /// Plain-text parse error
+        Parse(ParseError),
+
+        /// Rule parse error
+        #[cfg(feature = "parse")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "parse")))]
+        RatioParse(rule::text::RatioParseError),
+
+        /// Rule database error
+        #[cfg(feature = "serde")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+        Database(rule::database::DatabaseError),
+
+        /// JSON conversion error
+        #[cfg(feature = "json")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+        Json(json::JsonError),
+
+        /// Canonical binary decoding error
+        Binary(binary::DecodeError),
+
+        /// Rule pack error
+        Pack(pack::PackError),
+
+        /// E-graph conversion error
+        #[cfg(feature = "egg")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "egg")))]
+        Egg(crate::egg::EggError),
+
+        /// S-expression value conversion error
+        #[cfg(feature = "lexpr")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "lexpr")))]
+        Lexpr(crate::lexpr::LexprError),
+
+        /// Rational number conversion error
+        #[cfg(feature = "num-rational")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "num-rational")))]
+        Rational(rational::RationalError),
+
+        /// Derivation record resolution error
+        #[cfg(feature = "serde")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+        Resolve(proof::record::ResolveError),
+
+        /// Derivation record migration error
+        #[cfg(feature = "serde")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+        Migration(proof::record::MigrationError),
+
+        /// Proof checking error
+        Proof(ProofError),
+
+        /// Witness parsing error
+        Witness(proof::witness::WitnessError),
+
+        /// Fuel exhaustion
+        Exhausted(Exhaustion),
+
+        /// Invariant violation
+        Violated(Violation),
+
+        /// Tactic error
+        Tactic(TacticError),
+
+        /// Tactic script error
+        #[cfg(feature = "serde")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+        Script(tactic::script::ScriptError),
+
+        /// Linear resource error
+        Linear(LinearError),
+
+        /// Petri net error
+        Petri(PetriError),
+
+        /// Fixed-capacity buffer overflow
+        Capacity(fixed::CapacityError),
+
+        /// The equation between the printed sides could not be oriented during completion.
+        Unorientable(String, String),
     }
 
-    impl<K, V> Stored<K, V> {
-        /// Returns the [`&Stored`](Stored) as a [`StoredRef`].
+    impl Error {
+        /// Returns the kind of the error.
         #[inline]
-        pub fn as_ref(&self) -> StoredRef<'_, K, V> {
+        pub fn kind(&self) -> ErrorKind {
             match self {
-                Self::Key(key) => StoredRef::Key(key),
-                Self::Value(value) => StoredRef::Value(value),
+                Self::Shape(_) | Self::SubstitutionShape(_) | Self::Schema(_) => ErrorKind::Shape,
+                #[cfg(feature = "aep")]
+                Self::AepShape(_) => ErrorKind::Shape,
+                Self::Parse(_) => ErrorKind::Parse,
+                #[cfg(feature = "parse")]
+                Self::RatioParse(_) => ErrorKind::Parse,
+                #[cfg(feature = "serde")]
+                Self::Database(_) | Self::Resolve(_) | Self::Migration(_) => ErrorKind::Format,
+                #[cfg(feature = "json")]
+                Self::Json(_) => ErrorKind::Format,
+                Self::Binary(_) | Self::Pack(_) => ErrorKind::Format,
+                #[cfg(feature = "egg")]
+                Self::Egg(_) => ErrorKind::Unsupported,
+                #[cfg(feature = "lexpr")]
+                Self::Lexpr(_) => ErrorKind::Format,
+                #[cfg(feature = "num-rational")]
+                Self::Rational(_) => ErrorKind::Shape,
+                Self::Proof(_) => ErrorKind::Proof,
+                Self::Witness(_) => ErrorKind::Parse,
+                Self::Exhausted(_) => ErrorKind::Exhausted,
+                Self::Violated(_) => ErrorKind::Invariant,
+                Self::Tactic(_) => ErrorKind::Tactic,
+                #[cfg(feature = "serde")]
+                Self::Script(_) => ErrorKind::Tactic,
+                Self::Linear(_) | Self::Capacity(_) => ErrorKind::Resource,
+                Self::Petri(_) => ErrorKind::Unsupported,
+                Self::Unorientable(..) => ErrorKind::Completion,
             }
         }
     }
 
-    impl<K, V> Resolvable<K> for Stored<K, V> {
-        type Part = V;
-
-        type Output = V;
-
-        type UnresolvedKeys = Result<K, Infallible>;
+    impl fmt::Display for Error {
+        #[inline]
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::Shape(error) => fmt::Display::fmt(error, f),
+                Self::SubstitutionShape(error) => fmt::Display::fmt(error, f),
+                #[cfg(feature = "aep")]
+                Self::AepShape(error) => fmt::Display::fmt(error, f),
+                Self::Schema(error) => fmt::Display::fmt(error, f),
+                Self::Parse(error) => fmt::Display::fmt(error, f),
+                #[cfg(feature = "parse")]
+                Self::RatioParse(error) => fmt::Display::fmt(error, f),
+                #[cfg(feature = "serde")]
+                Self::Database(error) => fmt::Display::fmt(error, f),
+                #[cfg(feature = "json")]
+                Self::Json(error) => fmt::Display::fmt(error, f),
+                Self::Binary(error) => fmt::Display::fmt(error, f),
+                Self::Pack(error) => fmt::Display::fmt(error, f),
+                #[cfg(feature = "egg")]
+                Self::Egg(error) => fmt::Display::fmt(error, f),
+                #[cfg(feature = "lexpr")]
+                Self::Lexpr(error) => fmt::Display::fmt(error, f),
+                #[cfg(feature = "num-rational")]
+                Self::Rational(error) => fmt::Display::fmt(error, f),
+                #[cfg(feature = "serde")]
+                Self::Resolve(error) => fmt::Display::fmt(error, f),
+                #[cfg(feature = "serde")]
+                Self::Migration(error) => fmt::Display::fmt(error, f),
+                Self::Proof(error) => fmt::Display::fmt(error, f),
+                Self::Witness(error) => fmt::Display::fmt(error, f),
+                Self::Exhausted(reason) => fmt::Display::fmt(reason, f),
+                Self::Violated(violation) => fmt::Display::fmt(violation, f),
+                Self::Tactic(error) => fmt::Display::fmt(error, f),
+                #[cfg(feature = "serde")]
+                Self::Script(error) => fmt::Display::fmt(error, f),
+                Self::Linear(error) => fmt::Display::fmt(error, f),
+                Self::Petri(error) => fmt::Display::fmt(error, f),
+                Self::Capacity(error) => fmt::Display::fmt(error, f),
+                Self::Unorientable(left, right) => {
+                    write!(f, "equation `{} = {}` could not be oriented", left, right)
+                }
+            }
+        }
+    }
 
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    impl std::error::Error for Error {
         #[inline]
-        fn resolve<F>(self, mut resolver: F) -> Result<Self::Output, Self::UnresolvedKeys>
-        where
-            F: FnMut(&K) -> Option<Self::Part>,
-        {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
             match self {
-                Self::Key(key) => resolver(&key).ok_or(Ok(key)),
-                Self::Value(value) => Ok(value),
+                Self::Shape(error) => Some(error),
+                Self::SubstitutionShape(error) => Some(error),
+                #[cfg(feature = "aep")]
+                Self::AepShape(error) => Some(error),
+                Self::Schema(error) => Some(error),
+                Self::Parse(error) => Some(error),
+                #[cfg(feature = "parse")]
+                Self::RatioParse(error) => Some(error),
+                #[cfg(feature = "serde")]
+                Self::Database(error) => Some(error),
+                #[cfg(feature = "json")]
+                Self::Json(error) => Some(error),
+                Self::Binary(error) => Some(error),
+                Self::Pack(error) => Some(error),
+                #[cfg(feature = "egg")]
+                Self::Egg(error) => Some(error),
+                #[cfg(feature = "lexpr")]
+                Self::Lexpr(error) => Some(error),
+                #[cfg(feature = "num-rational")]
+                Self::Rational(error) => Some(error),
+                #[cfg(feature = "serde")]
+                Self::Resolve(error) => Some(error),
+                #[cfg(feature = "serde")]
+                Self::Migration(error) => Some(error),
+                Self::Proof(error) => Some(error),
+                Self::Witness(error) => Some(error),
+                Self::Exhausted(error) => Some(error),
+                Self::Violated(error) => Some(error),
+                Self::Tactic(error) => Some(error),
+                #[cfg(feature = "serde")]
+                Self::Script(error) => Some(error),
+                Self::Linear(error) => Some(error),
+                Self::Petri(error) => Some(error),
+                Self::Capacity(error) => Some(error),
+                Self::Unorientable(..) => None,
             }
         }
     }
 
-    /// Stored Reference
-    pub type StoredRef<'r, K, V> = Stored<&'r K, &'r V>;
+    macro_rules! impl_from {
+        ($($(#[$meta:meta])* $variant:ident($source:ty),)*) => {
+            $(
+                $(#[$meta])*
+                impl From<$source> for Error {
+                    #[inline]
+                    fn from(error: $source) -> Self {
+                        Self::$variant(error)
+                    }
+                }
+            )*
+        };
+    }
 
-    impl<'r, K, V> StoredRef<'r, K, V> {
-        /// Returns an owned [`Stored`] value.
+    impl_from! {
+        Shape(rule::ShapeError),
+        SubstitutionShape(substitution::ShapeError),
+        #[cfg(feature = "aep")]
+        AepShape(aep::ShapeError),
+        Schema(SchemaError),
+        Parse(ParseError),
+        #[cfg(feature = "parse")]
+        RatioParse(rule::text::RatioParseError),
+        #[cfg(feature = "serde")]
+        Database(rule::database::DatabaseError),
+        #[cfg(feature = "json")]
+        Json(json::JsonError),
+        Binary(binary::DecodeError),
+        Pack(pack::PackError),
+        #[cfg(feature = "egg")]
+        Egg(crate::egg::EggError),
+        #[cfg(feature = "lexpr")]
+        Lexpr(crate::lexpr::LexprError),
+        #[cfg(feature = "num-rational")]
+        Rational(rational::RationalError),
+        #[cfg(feature = "serde")]
+        Resolve(proof::record::ResolveError),
+        #[cfg(feature = "serde")]
+        Migration(proof::record::MigrationError),
+        Proof(ProofError),
+        Witness(proof::witness::WitnessError),
+        Exhausted(Exhaustion),
+        Violated(Violation),
+        Tactic(TacticError),
+        #[cfg(feature = "serde")]
+        Script(tactic::script::ScriptError),
+        Linear(LinearError),
+        Petri(PetriError),
+        Capacity(fixed::CapacityError),
+    }
+
+    impl<P> From<Exhausted<P>> for Error {
         #[inline]
-        pub fn to_owned(&self) -> Stored<K, V>
-        where
-            K: Clone,
-            V: Clone,
-        {
-            match self {
-                Self::Key(key) => Stored::Key(Clone::clone(key)),
-                Self::Value(value) => Stored::Value(Clone::clone(value)),
+        fn from(exhausted: Exhausted<P>) -> Self {
+            Self::Exhausted(exhausted.reason)
+        }
+    }
+
+    impl<E> From<CompletionError<E>> for Error
+    where
+        E: Expression,
+        E::Atom: Display,
+    {
+        #[inline]
+        fn from(error: CompletionError<E>) -> Self {
+            match error {
+                CompletionError::Unorientable(left, right) => {
+                    let mut printer = crate::fmt::Printer::new(crate::fmt::display::<E::Atom>)
+                        .with_max_width(usize::MAX);
+                    Self::Unorientable(
+                        printer.expr_to_string(&left),
+                        printer.expr_to_string(&right),
+                    )
+                }
+                CompletionError::Exhausted(reason) => Self::Exhausted(reason),
             }
         }
     }
+}
+
+/// Utilities
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub mod util {
+    use {
+        alloc::vec::Vec,
+        bitvec::vec::BitVec,
+        core::{
+            convert::Infallible,
+            hash::Hasher,
+            iter::{from_fn, FromIterator, FusedIterator},
+            marker::PhantomData,
+        },
+    };
+
+    /// An Infallible Phantom Data Object
+    // FIXME: implement derive traits correctly
+    #[derive(Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+    pub struct Nothing<T: ?Sized>(pub Infallible, PhantomData<T>);
+
+    impl<T: ?Sized> Clone for Nothing<T> {
+        #[inline]
+        fn clone(&self) -> Self {
+            Self(self.0, self.1)
+        }
+    }
+
+    impl<T: ?Sized> Copy for Nothing<T> {}
+
+    /// Fowler–Noll–Vo (FNV-1a) Hasher
+    ///
+    /// Unlike the hashers of the standard library this hasher has no random state, so digests
+    /// computed with it are the same across runs. Integers are hashed as their little-endian
+    /// bytes, and `usize` and `isize` as 64-bit integers, so that the digests do not depend on
+    /// the platform either, as long as the hashed values are fed in the same way.
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct Fnv1a(u64);
+
+    impl Fnv1a {
+        /// FNV-1a Offset Basis
+        pub const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+        /// FNV-1a Prime
+        pub const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        /// Builds a new [`Fnv1a`] hasher.
+        #[inline]
+        pub fn new() -> Self {
+            Self(Self::OFFSET_BASIS)
+        }
+    }
 
-    impl<'r, K, V> From<&'r Stored<K, V>> for StoredRef<'r, K, V> {
+    impl Default for Fnv1a {
         #[inline]
-        fn from(stored: &'r Stored<K, V>) -> Self {
-            stored.as_ref()
+        fn default() -> Self {
+            Self::new()
         }
     }
 
-    /// Stored Shape Type
-    #[derive(Debug, Eq, Hash, PartialEq)]
-    pub enum StoredShape<E, S, K = <E as Expression>::Atom>
-    where
-        E: Expression,
-    {
-        /// Key
-        Key(K),
+    impl Hasher for Fnv1a {
+        #[inline]
+        fn finish(&self) -> u64 {
+            self.0
+        }
 
-        /// Expression Shape
-        Shape(S),
+        #[inline]
+        fn write(&mut self, bytes: &[u8]) {
+            for byte in bytes {
+                self.0 ^= u64::from(*byte);
+                self.0 = self.0.wrapping_mul(Self::PRIME);
+            }
+        }
 
-        #[doc(hidden)]
-        __(util::Nothing<E>),
-    }
+        #[inline]
+        fn write_u16(&mut self, i: u16) {
+            self.write(&i.to_le_bytes())
+        }
 
-    impl<E, S, K> StoredShape<E, S, K>
-    where
-        E: Expression,
-    {
-        /// Checks if the `StoredShape` is a key.
-        #[must_use]
         #[inline]
-        pub fn is_key(&self) -> bool {
-            matches!(self, Self::Key(_))
+        fn write_u32(&mut self, i: u32) {
+            self.write(&i.to_le_bytes())
         }
 
-        /// Checks if the `StoredShape` is a shaped expression.
-        #[must_use]
         #[inline]
-        pub fn is_shape(&self) -> bool {
-            matches!(self, Self::Shape(_))
+        fn write_u64(&mut self, i: u64) {
+            self.write(&i.to_le_bytes())
         }
 
-        /// Converts from an `StoredShape<E, S, K>` to an `Option<K>`.
-        #[must_use]
         #[inline]
-        pub fn key(self) -> Option<K> {
-            match self {
-                Self::Key(key) => Some(key),
-                _ => None,
-            }
+        fn write_u128(&mut self, i: u128) {
+            self.write(&i.to_le_bytes())
         }
 
-        /// Converts from an `&StoredShape<E, S, K>` to an `Option<&K>`.
-        #[must_use]
         #[inline]
-        pub fn key_ref(&self) -> Option<&K> {
-            match self {
-                Self::Key(key) => Some(key),
-                _ => None,
-            }
+        fn write_usize(&mut self, i: usize) {
+            self.write_u64(i as u64)
         }
 
-        /// Converts from a `StoredShape<E, S, K>` to an `Option<S>`.
-        #[must_use]
         #[inline]
-        pub fn shape(self) -> Option<S> {
-            match self {
-                Self::Shape(shape) => Some(shape),
-                _ => None,
-            }
+        fn write_i16(&mut self, i: i16) {
+            self.write_u16(i as u16)
         }
 
-        /// Converts from a `&StoredShape<E, S, K>` to an `Option<&S>`.
-        #[must_use]
         #[inline]
-        pub fn shape_ref(&self) -> Option<&S> {
-            match self {
-                Self::Shape(shape) => Some(shape),
-                _ => None,
-            }
+        fn write_i32(&mut self, i: i32) {
+            self.write_u32(i as u32)
         }
 
-        /// Returns the contained `K` key, consuming the `self` value.
-        ///
-        /// # Panics
-        ///
-        /// Panics if the contained value was a `Shape`.
         #[inline]
-        #[track_caller]
-        pub fn unwrap_key(self) -> K {
-            match self {
-                Self::Key(key) => key,
-                _ => panic!(),
-            }
+        fn write_i64(&mut self, i: i64) {
+            self.write_u64(i as u64)
         }
 
-        /// Returns the contained `Shape`, consuming the `self` value.
-        ///
-        /// # Panics
-        ///
-        /// Panics if the contained value was a `K`.
         #[inline]
-        #[track_caller]
-        pub fn unwrap_shape(self) -> S {
-            match self {
-                Self::Shape(shape) => shape,
-                _ => panic!(),
-            }
+        fn write_i128(&mut self, i: i128) {
+            self.write_u128(i as u128)
         }
-    }
 
-    impl<E, S, K> Clone for StoredShape<E, S, K>
-    where
-        E: Expression,
-        S: Clone,
-        K: Clone,
-    {
         #[inline]
-        fn clone(&self) -> Self {
-            match self {
-                Self::Key(key) => Self::Key(key.clone()),
-                Self::Shape(shape) => Self::Shape(shape.clone()),
-            }
+        fn write_isize(&mut self, i: isize) {
+            self.write_u64(i as u64)
         }
     }
 
-    impl<E, S, K> From<StoredShape<E, S, K>> for Expr<E>
+    /// Combines a sequence of digests into a single digest.
+    #[inline]
+    pub fn sequence_digest<I>(digests: I) -> u64
     where
-        E: Expression,
-        S: Into<Expr<E>>,
-        K: Into<E::Atom>,
+        I: IntoIterator<Item = u64>,
     {
-        #[inline]
-        fn from(expr: StoredShape<E, S, K>) -> Self {
-            match expr {
-                StoredShape::Key(key) => Self::Atom(key.into()),
-                StoredShape::Shape(shape) => shape.into(),
-            }
+        let mut state = Fnv1a::new();
+        let mut count = 0u64;
+        for digest in digests {
+            state.write_u64(digest);
+            count += 1;
         }
+        state.write_u64(count);
+        state.finish()
     }
 
-    /// [`StoredShape`] Matcher Error
-    pub enum StoredShapeMatcherError<E, S, K = <E as Expression>::Atom>
+    /// Combines a multiset of digests into a single digest which does not depend on the order of
+    /// the digests, see [`MultisetDigest`].
+    #[inline]
+    pub fn multiset_digest<I>(digests: I) -> u64
     where
-        E: Expression,
-        S: Matcher<E>,
-        K: TryFrom<E::Atom>,
+        I: IntoIterator<Item = u64>,
     {
-        /// Base Shape Error
-        Base(<S as Matcher<E>>::Error),
-
-        /// Invalid Key Error
-        InvalidKey(K::Error),
+        digests.into_iter().collect::<MultisetDigest>().finish()
     }
 
-    impl<E, S, K> TryFrom<Expr<E>> for StoredShape<E, S, K>
-    where
-        E: Expression,
-        S: Shape<E>,
-        K: TryFrom<E::Atom>,
-    {
-        type Error = StoredShapeMatcherError<E, S, K>;
+    /// Incremental Multiset Digest
+    ///
+    /// Accumulates the digests of the elements of a multiset in any order, as the wrapping sum
+    /// of their mixed digests and their count, so that the digest of a multiset can be updated
+    /// in constant time when an element is inserted or removed.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct MultisetDigest {
+        /// Wrapping sum of the mixed digests
+        sum: u64,
+
+        /// Number of digests
+        count: u64,
+    }
 
+    impl MultisetDigest {
+        /// Builds the digest accumulator of the empty multiset.
         #[inline]
-        fn try_from(expr: Expr<E>) -> Result<Self, Self::Error> {
-            match expr {
-                Expr::Atom(key) => key
-                    .try_into()
-                    .map(StoredShape::Key)
-                    .map_err(StoredShapeMatcherError::InvalidKey),
-                _ => expr
-                    .try_into()
-                    .map(StoredShape::Shape)
-                    .map_err(StoredShapeMatcherError::Base),
-            }
+        pub fn new() -> Self {
+            Self::default()
         }
-    }
 
-    impl<E, S> Matcher<E> for StoredShape<E, S>
-    where
-        E: Expression,
-        S: Matcher<E>,
-    {
-        type Error = StoredShapeMatcherError<E, S>;
+        /// Mixes the bits of `digest` with the SplitMix64 finalizer.
+        #[inline]
+        fn mix(digest: u64) -> u64 {
+            let mut z = digest.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        }
 
+        /// Adds an element with the given digest.
         #[inline]
-        fn matches_atom(atom: &E::Atom) -> Result<(), Self::Error> {
-            let _ = atom;
-            Ok(())
+        pub fn insert(&mut self, digest: u64) {
+            self.sum = self.sum.wrapping_add(Self::mix(digest));
+            self.count = self.count.wrapping_add(1);
         }
 
+        /// Removes an element with the given digest, which must have been inserted.
         #[inline]
-        fn matches_group(group: GroupRef<E>) -> Result<(), Self::Error> {
-            S::matches_group(group).map_err(StoredShapeMatcherError::Base)
+        pub fn remove(&mut self, digest: u64) {
+            self.sum = self.sum.wrapping_sub(Self::mix(digest));
+            self.count = self.count.wrapping_sub(1);
         }
-    }
 
-    impl<E, S> Shape<E> for StoredShape<E, S>
-    where
-        E: Expression,
-        S: Shape<E>,
-    {
+        /// Returns the digest of the multiset.
+        #[inline]
+        pub fn finish(&self) -> u64 {
+            sequence_digest([self.sum, self.count].iter().copied())
+        }
     }
 
-    impl<E, S, K> Resolvable<K> for StoredShape<E, S, K>
-    where
-        E: Expression,
-    {
-        type Part = S;
-
-        type Output = S;
-
-        type UnresolvedKeys = Result<K, Infallible>;
-
+    impl Extend<u64> for MultisetDigest {
         #[inline]
-        fn resolve<F>(self, mut resolver: F) -> Result<Self::Output, Self::UnresolvedKeys>
+        fn extend<I>(&mut self, iter: I)
         where
-            F: FnMut(&K) -> Option<Self::Part>,
+            I: IntoIterator<Item = u64>,
         {
-            match self {
-                Self::Key(key) => resolver(&key).ok_or(Ok(key)),
-                Self::Shape(shape) => Ok(shape),
+            for digest in iter {
+                self.insert(digest);
             }
         }
     }
-}
-
-/// Utilities
-pub mod util {
-    use {
-        alloc::vec::Vec,
-        bitvec::vec::BitVec,
-        core::{
-            convert::Infallible,
-            iter::{from_fn, FromIterator, FusedIterator},
-            marker::PhantomData,
-        },
-    };
 
-    /// An Infallible Phantom Data Object
-    // FIXME: implement derive traits correctly
-    #[derive(Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-    pub struct Nothing<T: ?Sized>(pub Infallible, PhantomData<T>);
-
-    impl<T: ?Sized> Clone for Nothing<T> {
+    impl FromIterator<u64> for MultisetDigest {
         #[inline]
-        fn clone(&self) -> Self {
-            Self(self.0, self.1)
+        fn from_iter<I>(iter: I) -> Self
+        where
+            I: IntoIterator<Item = u64>,
+        {
+            let mut accumulator = Self::new();
+            accumulator.extend(iter);
+            accumulator
         }
     }
 
-    impl<T: ?Sized> Copy for Nothing<T> {}
-
     /// Builds a zeroed [`BitVec`] of the specified length.
     #[inline]
     pub fn zeroed_bit_vector(length: usize) -> BitVec {
@@ -3589,6 +26069,127 @@ pub mod util {
         multiset_symmetric_difference_by(left, right, PartialEq::eq)
     }
 
+    /// Checks if two multisets are equal.
+    pub fn multiset_eq_by<L, RItem, F>(left: L, right: Vec<RItem>, mut eq: F) -> bool
+    where
+        L: IntoIterator,
+        F: FnMut(&L::Item, &RItem) -> bool,
+    {
+        let mut matches = zeroed_bit_vector(right.len());
+        let mut count = 0;
+        for l in left {
+            if set_first_new_match_by(&l, &right, &mut matches, &mut eq) {
+                return false;
+            }
+            count += 1;
+        }
+        count == right.len()
+    }
+
+    /// Returns `true` if `text` matches the glob `pattern`, where `*` matches any sequence of
+    /// characters and `?` matches any single character.
+    pub fn glob_match(pattern: &str, text: &str) -> bool {
+        let pattern = pattern.chars().collect::<Vec<_>>();
+        let text = text.chars().collect::<Vec<_>>();
+        let (mut p, mut t) = (0, 0);
+        let mut backtrack = None;
+        while t < text.len() {
+            match pattern.get(p) {
+                Some('*') => {
+                    backtrack = Some((p, t));
+                    p += 1;
+                }
+                Some(c) if *c == '?' || *c == text[t] => {
+                    p += 1;
+                    t += 1;
+                }
+                _ => match backtrack {
+                    Some((star, matched)) => {
+                        p = star + 1;
+                        t = matched + 1;
+                        backtrack = Some((star, matched + 1));
+                    }
+                    _ => return false,
+                },
+            }
+        }
+        pattern[p..].iter().all(move |c| *c == '*')
+    }
+
+    /// Checks if two multisets are equal.
+    #[inline]
+    pub fn multiset_eq<L, RItem>(left: L, right: Vec<RItem>) -> bool
+    where
+        L: IntoIterator,
+        L::Item: PartialEq<RItem>,
+    {
+        multiset_eq_by(left, right, PartialEq::eq)
+    }
+
+    /// SplitMix64 Random Number Generator
+    ///
+    /// A small seeded generator whose output only depends on its seed, on every platform,
+    /// which makes randomized searches reproducible. It is not suitable for cryptography.
+    #[cfg(feature = "rand")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "rand")))]
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct SplitMix64(u64);
+
+    #[cfg(feature = "rand")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "rand")))]
+    impl SplitMix64 {
+        /// Builds a new [`SplitMix64`] generator from `seed`.
+        #[inline]
+        pub fn new(seed: u64) -> Self {
+            Self(seed)
+        }
+    }
+
+    #[cfg(feature = "rand")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "rand")))]
+    impl rand::RngCore for SplitMix64 {
+        #[inline]
+        fn next_u32(&mut self) -> u32 {
+            (self.next_u64() >> 32) as u32
+        }
+
+        #[inline]
+        fn next_u64(&mut self) -> u64 {
+            self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = self.0;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        }
+
+        #[inline]
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            rand::rand_core::impls::fill_bytes_via_next(self, dest)
+        }
+
+        #[inline]
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "rand")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "rand")))]
+    impl rand::SeedableRng for SplitMix64 {
+        type Seed = [u8; 8];
+
+        #[inline]
+        fn from_seed(seed: Self::Seed) -> Self {
+            Self(u64::from_le_bytes(seed))
+        }
+
+        #[inline]
+        fn seed_from_u64(state: u64) -> Self {
+            Self(state)
+        }
+    }
+
     /// Parallel Computation Utilities
     #[cfg(feature = "parallel")]
     #[cfg_attr(docsrs, doc(cfg(feature = "parallel")))]